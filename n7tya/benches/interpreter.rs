@@ -0,0 +1,57 @@
+//! 処理系の criterion ベンチマーク (cargo bench)
+//!
+//! benches/programs/*.n7t を段階ごとに計測する。
+//! 同じプログラムを n7tya bench でも計測できる。
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use n7tya::interpreter::Interpreter;
+use n7tya::lexer::Lexer;
+use n7tya::parser::Parser;
+
+const PROGRAMS: &[(&str, &str)] = &[
+    ("loop", include_str!("programs/loop.n7t")),
+    ("strings", include_str!("programs/strings.n7t")),
+    ("longstr", include_str!("programs/longstr.n7t")),
+    ("dict", include_str!("programs/dict.n7t")),
+    ("dictstr", include_str!("programs/dictstr.n7t")),
+    ("jsx", include_str!("programs/jsx.n7t")),
+];
+
+/// 字句解析・構文解析用に全プログラムをつなげたソース
+fn combined_source() -> String {
+    PROGRAMS
+        .iter()
+        .map(|(_, source)| *source)
+        .collect::<Vec<_>>()
+        .join("\n")
+        .repeat(20)
+}
+
+fn bench_lexer(c: &mut Criterion) {
+    let source = combined_source();
+    c.bench_function("lex", |b| {
+        b.iter(|| Lexer::new(black_box(&source)).tokenize())
+    });
+}
+
+fn bench_parser(c: &mut Criterion) {
+    let tokens = Lexer::new(&combined_source()).tokenize();
+    c.bench_function("parse", |b| {
+        b.iter(|| Parser::new(black_box(tokens.clone())).parse().unwrap())
+    });
+}
+
+fn bench_programs(c: &mut Criterion) {
+    let mut group = c.benchmark_group("run");
+    group.sample_size(10);
+    for (name, source) in PROGRAMS {
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        group.bench_function(*name, |b| {
+            b.iter(|| Interpreter::new().run(&program).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_lexer, bench_parser, bench_programs);
+criterion_main!(benches);
@@ -0,0 +1,284 @@
+//! API ドキュメントの生成 (n7tya doc)
+//!
+//! 関数・クラス・コンポーネントの本体の先頭に書いた文字列リテラル (docstring) と
+//! 宣言のシグネチャを集め、Markdown か HTML にする。
+
+use crate::ast::*;
+use crate::jsx_render::{escape_html, generate_html_page};
+
+/// 出力形式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DocFormat {
+    Markdown,
+    Html,
+}
+
+impl DocFormat {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "md" | "markdown" => Ok(DocFormat::Markdown),
+            "html" => Ok(DocFormat::Html),
+            _ => Err(format!(
+                "Unknown doc format: {} (expected md or html)",
+                name
+            )),
+        }
+    }
+
+    /// 出力ファイル名
+    pub fn file_name(self) -> &'static str {
+        match self {
+            DocFormat::Markdown => "index.md",
+            DocFormat::Html => "index.html",
+        }
+    }
+}
+
+/// ドキュメントの1項目 (members はクラスのメソッドやコンポーネントの state など)
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocEntry {
+    pub signature: String,
+    pub doc: Option<String>,
+    pub members: Vec<DocEntry>,
+}
+
+impl DocEntry {
+    fn new(signature: String, doc: Option<&String>) -> Self {
+        Self {
+            signature,
+            doc: doc.cloned(),
+            members: Vec::new(),
+        }
+    }
+}
+
+/// ファイルのトップレベルの宣言を書いた順に集める
+pub fn module_entries(program: &Program) -> Vec<DocEntry> {
+    program.items.iter().filter_map(item_entry).collect()
+}
+
+fn item_entry(item: &Item) -> Option<DocEntry> {
+    let entry = match item {
+        Item::FunctionDef(f) => DocEntry::new(
+            fn_signature("def", &f.name, &f.params, &f.return_type),
+            f.doc.as_ref(),
+        ),
+        Item::ClassDef(c) => {
+            let mut signature = format!("class {}", c.name);
+            if let Some(parent) = &c.parent {
+                signature.push_str(&format!(" {}", parent));
+            }
+            if !c.interfaces.is_empty() {
+                signature.push_str(&format!(" implements {}", c.interfaces.join(", ")));
+            }
+            let mut entry = DocEntry::new(signature, c.doc.as_ref());
+            for member in &c.body {
+                let member = match member {
+                    ClassBodyItem::Field(f) => {
+                        DocEntry::new(format!("{}: {}", f.name, f.type_annotation), None)
+                    }
+                    ClassBodyItem::Method(m) => DocEntry::new(
+                        fn_signature("def", &m.name, &m.params, &m.return_type),
+                        m.doc.as_ref(),
+                    ),
+                    ClassBodyItem::StaticMethod(m) => DocEntry::new(
+                        fn_signature("static def", &m.name, &m.params, &m.return_type),
+                        m.doc.as_ref(),
+                    ),
+                    ClassBodyItem::Const(c) => DocEntry::new(
+                        match &c.type_annotation {
+                            Some(ty) => format!("const {}: {}", c.name, ty),
+                            None => format!("const {}", c.name),
+                        },
+                        None,
+                    ),
+                    ClassBodyItem::Trivia(_) => continue,
+                };
+                entry.members.push(member);
+            }
+            entry
+        }
+        Item::StructDef(s) => {
+            let mut entry = DocEntry::new(format!("struct {}", s.name), None);
+            for f in &s.fields {
+                entry.members.push(DocEntry::new(
+                    format!("{}: {}", f.name, f.type_annotation),
+                    None,
+                ));
+            }
+            entry
+        }
+        Item::InterfaceDef(i) => {
+            let mut entry = DocEntry::new(format!("interface {}", i.name), None);
+            for m in &i.methods {
+                entry.members.push(DocEntry::new(
+                    fn_signature("def", &m.name, &m.params, &m.return_type),
+                    None,
+                ));
+            }
+            entry
+        }
+        Item::ComponentDef(c) => {
+            let keyword = if c.island {
+                "island component"
+            } else {
+                "component"
+            };
+            let mut entry = DocEntry::new(format!("{} {}", keyword, c.name), c.doc.as_ref());
+            for member in &c.body {
+                let member = match member {
+                    ComponentBodyItem::State(s) => {
+                        let signature = match s.persist {
+                            Some(persist) => format!("state {} {}", persist.name(), s.name),
+                            None => format!("state {}", s.name),
+                        };
+                        DocEntry::new(signature, None)
+                    }
+                    ComponentBodyItem::Method(m) => DocEntry::new(
+                        fn_signature("def", &m.name, &m.params, &m.return_type),
+                        m.doc.as_ref(),
+                    ),
+                    ComponentBodyItem::Render(_) | ComponentBodyItem::Trivia(_) => continue,
+                };
+                entry.members.push(member);
+            }
+            entry
+        }
+        Item::ServerDef(s) => {
+            let mut entry = DocEntry::new(format!("server {}", s.name), None);
+            for item in &s.body {
+                let ServerBodyItem::Route(route) = item else {
+                    continue;
+                };
+                let mut signature = format!("{} \"{}\"", route.method.to_uppercase(), route.path);
+                if let Some(param) = &route.body_param {
+                    signature.push_str(&format!(" ({})", param_text(param)));
+                }
+                if let Some(ty) = &route.return_type {
+                    signature.push_str(&format!(" -> {}", ty));
+                }
+                entry.members.push(DocEntry::new(signature, None));
+            }
+            entry
+        }
+        Item::Import(_) | Item::Statement(_) => return None,
+    };
+    Some(entry)
+}
+
+/// def name a: Int, b -> Str (ソースコードと同じ書き方)
+fn fn_signature(keyword: &str, name: &str, params: &[Param], return_type: &Option<Type>) -> String {
+    let mut signature = format!("{} {}", keyword, name);
+    if !params.is_empty() {
+        let params: Vec<String> = params.iter().map(param_text).collect();
+        signature.push_str(&format!(" {}", params.join(", ")));
+    }
+    if let Some(ty) = return_type {
+        signature.push_str(&format!(" -> {}", ty));
+    }
+    signature
+}
+
+fn param_text(param: &Param) -> String {
+    match &param.type_annotation {
+        Some(ty) => format!("{}: {}", param.name, ty),
+        None => param.name.clone(),
+    }
+}
+
+/// モジュール (ファイルのパスとその項目) ごとに見出しを付けて1つの文書にする
+pub fn render(title: &str, modules: &[(String, Vec<DocEntry>)], format: DocFormat) -> String {
+    match format {
+        DocFormat::Markdown => render_markdown(title, modules),
+        DocFormat::Html => render_html(title, modules),
+    }
+}
+
+fn render_markdown(title: &str, modules: &[(String, Vec<DocEntry>)]) -> String {
+    let mut out = format!("# {}\n", title);
+    for (path, entries) in modules {
+        out.push_str(&format!("\n## {}\n", path));
+        for entry in entries {
+            out.push_str(&format!("\n### `{}`\n", entry.signature));
+            if let Some(doc) = &entry.doc {
+                out.push_str(&format!("\n{}\n", doc));
+            }
+            if !entry.members.is_empty() {
+                out.push('\n');
+            }
+            for member in &entry.members {
+                out.push_str(&format!("- `{}`", member.signature));
+                if let Some(doc) = &member.doc {
+                    // 2行目以降はリストの項目の続きとして字下げする
+                    out.push_str(&format!(": {}", doc.replace('\n', "\n  ")));
+                }
+                out.push('\n');
+            }
+        }
+    }
+    out
+}
+
+fn render_html(title: &str, modules: &[(String, Vec<DocEntry>)]) -> String {
+    let mut body = format!("<h1>{}</h1>\n", escape_html(title));
+    for (path, entries) in modules {
+        body.push_str(&format!("<h2>{}</h2>\n", escape_html(path)));
+        for entry in entries {
+            body.push_str(&format!(
+                "<section>\n<h3><code>{}</code></h3>\n",
+                escape_html(&entry.signature)
+            ));
+            if let Some(doc) = &entry.doc {
+                body.push_str(&html_paragraphs(doc));
+            }
+            if !entry.members.is_empty() {
+                body.push_str("<ul>\n");
+                for member in &entry.members {
+                    body.push_str(&format!(
+                        "<li><code>{}</code>",
+                        escape_html(&member.signature)
+                    ));
+                    if let Some(doc) = &member.doc {
+                        body.push_str(&html_paragraphs(doc));
+                    }
+                    body.push_str("</li>\n");
+                }
+                body.push_str("</ul>\n");
+            }
+            body.push_str("</section>\n");
+        }
+    }
+    generate_html_page(title, &body)
+}
+
+/// 空行で区切った段落を <p> にする
+fn html_paragraphs(doc: &str) -> String {
+    doc.split("\n\n")
+        .map(|p| format!("<p>{}</p>\n", escape_html(p.trim())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_render_markdown() {
+        let source = "def add a: Int, b: Int -> Int\n\t\"2つの数を足す\"\n\treturn a + b\nclass Counter\n\t`\n\t数を数える。\n\n\t詳しい説明。\n\t`\n\tcount: Int = 0\n\tdef increment\n\t\t\"1つ増やす\"\n\t\treturn 1\n";
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let modules = vec![("src/main.n7t".to_string(), module_entries(&program))];
+        assert_eq!(
+            render("app", &modules, DocFormat::Markdown),
+            "# app\n\n## src/main.n7t\n\n\
+             ### `def add a: Int, b: Int -> Int`\n\n2つの数を足す\n\n\
+             ### `class Counter`\n\n数を数える。\n\n詳しい説明。\n\n\
+             - `count: Int`\n- `def increment`: 1つ増やす\n"
+        );
+        let html = render("app", &modules, DocFormat::Html);
+        assert!(html.contains(
+            "<h3><code>class Counter</code></h3>\n<p>数を数える。</p>\n<p>詳しい説明。</p>"
+        ));
+    }
+}
@@ -0,0 +1,398 @@
+//! 静的ファイル (public/) の配信と fingerprint
+//!
+//! `n7tya build` は public/ のファイルを内容のハッシュを付けた名前で dist/ にコピーし
+//! (public/css/app.css → dist/css/app.1a2b3c4d.css)、元のパスとの対応を dist/assets.json に書く。
+//! CSS / HTML の中の参照はコピーするときに、render_page の HTML は返すときに書き換える。
+//! 名前が内容で変わるので、fingerprint 付きのファイルはブラウザにずっとキャッシュさせてよい。
+//! build する前は public/ のファイルをそのまま配信する。
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 静的ファイルを置くディレクトリ
+pub const PUBLIC_DIR: &str = "public";
+/// build の出力先
+pub const DIST_DIR: &str = "dist";
+/// 元のパスと fingerprint 付きのパスの対応表 (dist の中)
+pub const MANIFEST: &str = "assets.json";
+
+/// 配信する静的ファイル
+#[derive(Debug, Clone)]
+pub struct Assets {
+    root: PathBuf,                      // 配信するファイルのディレクトリ (dist か public)
+    manifest: BTreeMap<String, String>, // "/css/app.css" -> "/css/app.1a2b3c4d.css" (build 前は空)
+}
+
+/// 配信するファイルの中身
+#[derive(Debug, Clone, PartialEq)]
+pub struct StaticFile {
+    pub body: Vec<u8>,
+    pub content_type: &'static str,
+    pub immutable: bool, // fingerprint 付きの名前なのでキャッシュし続けてよい
+}
+
+impl Assets {
+    /// プロジェクトの dist/assets.json か public/ を読む (どちらもなければ None)
+    pub fn load(project_dir: &Path) -> Result<Option<Self>, String> {
+        let dist = project_dir.join(DIST_DIR);
+        let manifest_path = dist.join(MANIFEST);
+        if manifest_path.exists() {
+            let text = fs::read_to_string(&manifest_path)
+                .map_err(|e| format!("Failed to read {}: {}", manifest_path.display(), e))?;
+            let manifest = serde_json::from_str(&text)
+                .map_err(|e| format!("Invalid {}: {}", manifest_path.display(), e))?;
+            return Ok(Some(Self {
+                root: dist,
+                manifest,
+            }));
+        }
+        let public = project_dir.join(PUBLIC_DIR);
+        Ok(public.is_dir().then(|| Self {
+            root: public,
+            manifest: BTreeMap::new(),
+        }))
+    }
+
+    /// GET されたパスのファイル (静的ファイルでなければ None)
+    ///
+    /// build 後は対応表にあるパスだけを配信し、元のパスで来たら fingerprint 付きのファイルを返す。
+    pub fn file(&self, path: &str) -> Option<StaticFile> {
+        let path = path.split(['?', '#']).next().unwrap_or(path);
+        let (file, immutable) = if self.manifest.is_empty() {
+            // ".." でディレクトリの外を読ませない
+            if !path.starts_with('/') || path.split('/').any(|s| s == "..") {
+                return None;
+            }
+            (path, false)
+        } else if let Some(fingerprinted) = self.manifest.get(path) {
+            (fingerprinted.as_str(), false)
+        } else if self.manifest.values().any(|v| v == path) {
+            (path, true)
+        } else {
+            return None;
+        };
+        let full = self.root.join(file.trim_start_matches('/'));
+        if !full.is_file() {
+            return None;
+        }
+        Some(StaticFile {
+            body: fs::read(&full).ok()?,
+            content_type: content_type(file),
+            immutable,
+        })
+    }
+
+    /// render_page の HTML の中の参照を fingerprint 付きのパスにする
+    pub fn rewrite_html(&self, html: &str) -> String {
+        if self.manifest.is_empty() {
+            return html.to_string();
+        }
+        rewrite_refs(html, "/", &self.manifest)
+    }
+}
+
+/// public のファイルを fingerprint 付きで dist にコピーし、対応表を dist/assets.json に書く
+///
+/// 画像などを先にコピーし、それらを参照する CSS、最後に HTML の順に書き換えてから
+/// ハッシュを取る (参照先が変われば CSS の名前も変わる)。HTML はページの URL なので名前を変えない。
+/// 前回の対応表にあって今回書かなかったファイル (古い fingerprint の CSS など) は消す。
+pub fn build(public: &Path, dist: &Path) -> Result<BTreeMap<String, String>, String> {
+    let previous: BTreeMap<String, String> = fs::read_to_string(dist.join(MANIFEST))
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default();
+    let mut files = Vec::new();
+    collect_files(public, public, &mut files)?;
+    let stage = |path: &String| match extension(path) {
+        "css" => 1,
+        "html" | "htm" => 2,
+        _ => 0,
+    };
+    files.sort_by_key(|path| (stage(path), path.clone()));
+
+    let mut manifest = BTreeMap::new();
+    for path in files {
+        let source = public.join(path.trim_start_matches('/'));
+        let mut body =
+            fs::read(&source).map_err(|e| format!("Failed to read {}: {}", source.display(), e))?;
+        let target = match stage(&path) {
+            0 => fingerprinted_path(&path, &body),
+            rewritten => {
+                let base = &path[..path.rfind('/').unwrap_or(0) + 1];
+                body = rewrite_refs(&String::from_utf8_lossy(&body), base, &manifest).into_bytes();
+                if rewritten == 1 {
+                    fingerprinted_path(&path, &body)
+                } else {
+                    path.clone()
+                }
+            }
+        };
+        let out = dist.join(target.trim_start_matches('/'));
+        if let Some(dir) = out.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        fs::write(&out, &body).map_err(|e| format!("Failed to write {}: {}", out.display(), e))?;
+        manifest.insert(path, target);
+    }
+    for target in previous.values() {
+        if !manifest.values().any(|t| t == target) {
+            remove_output(dist, target);
+        }
+    }
+
+    let json = serde_json::to_string_pretty(&manifest).unwrap_or_default();
+    let manifest_path = dist.join(MANIFEST);
+    fs::write(&manifest_path, json + "\n")
+        .map_err(|e| format!("Failed to write {}: {}", manifest_path.display(), e))?;
+    Ok(manifest)
+}
+
+/// build が dist に書いたファイル (sitemap.xml など) を、名前を変えずに dist/assets.json に加える
+pub fn register(dist: &Path, paths: &[String]) -> Result<(), String> {
+    let manifest_path = dist.join(MANIFEST);
+    let mut manifest: BTreeMap<String, String> = match fs::read_to_string(&manifest_path) {
+        Ok(text) => serde_json::from_str(&text)
+            .map_err(|e| format!("Invalid {}: {}", manifest_path.display(), e))?,
+        Err(_) => BTreeMap::new(),
+    };
+    for path in paths {
+        manifest.insert(path.clone(), path.clone());
+    }
+    let json = serde_json::to_string_pretty(&manifest).unwrap_or_default();
+    fs::write(&manifest_path, json + "\n")
+        .map_err(|e| format!("Failed to write {}: {}", manifest_path.display(), e))
+}
+
+/// dist の中の出力を消し、空になったディレクトリも dist まで順に消す
+///
+/// マニフェストに `../` やシンボリックリンクで dist の外を指すパスがあっても、外のファイルは消さない
+pub fn remove_output(dist: &Path, file: &str) {
+    let (Ok(dist), Ok(path)) = (
+        dist.canonicalize(),
+        dist.join(file.trim_start_matches('/')).canonicalize(),
+    ) else {
+        return;
+    };
+    if !path.starts_with(&dist) || fs::remove_file(&path).is_err() {
+        return;
+    }
+    let mut dir = path.parent();
+    while let Some(current) = dir.filter(|d| d.starts_with(&dist) && *d != dist) {
+        // 空でなければ remove_dir は失敗する
+        if fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+}
+
+/// ディレクトリの下のファイルを "/css/app.css" の形で集める
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<String>) -> Result<(), String> {
+    let entries =
+        fs::read_dir(dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+    for entry in entries {
+        let path = entry
+            .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?
+            .path();
+        if path.is_dir() {
+            collect_files(root, &path, files)?;
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            let parts: Vec<String> = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            files.push(format!("/{}", parts.join("/")));
+        }
+    }
+    Ok(())
+}
+
+/// /css/app.css -> /css/app.1a2b3c4d.css
+pub fn fingerprinted_path(path: &str, content: &[u8]) -> String {
+    let hash = fingerprint(content);
+    let name_start = path.rfind('/').map_or(0, |i| i + 1);
+    match path[name_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let dot = name_start + dot;
+            format!("{}.{}{}", &path[..dot], hash, &path[dot..])
+        }
+        _ => format!("{}.{}", path, hash),
+    }
+}
+
+/// 内容の 32 ビットのハッシュ (FNV-1a, 16 進 8 桁)
+///
+/// 実行環境によらず同じ内容なら同じ名前にするため、乱数で初期化される std のハッシュは使わない。
+pub(crate) fn fingerprint(content: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in content {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("{:08x}", (hash ^ (hash >> 32)) as u32)
+}
+
+/// HTML の属性値 (="...") と CSS の url(...) のうち、対応表にあるパスを書き換える
+///
+/// base は相対パスを解決するディレクトリ ("/css/")。書き換えた参照は "/" から始まるパスになる。
+fn rewrite_refs(text: &str, base: &str, manifest: &BTreeMap<String, String>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some((start, close)) = next_ref(rest) {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest
+            .find(|c: char| c == close || (close == ')' && c.is_whitespace()) || c == '\n')
+            .unwrap_or(rest.len());
+        let reference = &rest[..end];
+        let split = reference.find(['?', '#']).unwrap_or(reference.len());
+        let (path, suffix) = reference.split_at(split);
+        match resolve(base, path).and_then(|p| manifest.get(&p)) {
+            Some(target) => {
+                out.push_str(target);
+                out.push_str(suffix);
+            }
+            None => out.push_str(reference),
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// 次の参照の開始位置と、参照を閉じる文字
+fn next_ref(text: &str) -> Option<(usize, char)> {
+    let attribute = [("=\"", '"'), ("='", '\'')]
+        .iter()
+        .filter_map(|(pattern, close)| text.find(pattern).map(|i| (i + 2, *close)))
+        .min();
+    let url = text.find("url(").map(|i| {
+        let start = i + 4;
+        match text[start..].chars().next() {
+            Some(quote @ ('"' | '\'')) => (start + 1, quote),
+            _ => (start, ')'),
+        }
+    });
+    match (attribute, url) {
+        (Some(a), Some(u)) => Some(a.min(u)),
+        (a, u) => a.or(u),
+    }
+}
+
+/// 参照を "/" から始まるパスにする (外部の URL や data: は None)
+fn resolve(base: &str, reference: &str) -> Option<String> {
+    if reference.is_empty() || reference.starts_with("//") || reference.contains(':') {
+        return None;
+    }
+    let joined = if reference.starts_with('/') {
+        reference.to_string()
+    } else {
+        format!("{}{}", base, reference)
+    };
+    let mut parts: Vec<&str> = Vec::new();
+    for part in joined.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            part => parts.push(part),
+        }
+    }
+    Some(format!("/{}", parts.join("/")))
+}
+
+fn extension(path: &str) -> &str {
+    let name = &path[path.rfind('/').map_or(0, |i| i + 1)..];
+    name.rsplit_once('.').map_or("", |(_, ext)| ext)
+}
+
+fn content_type(path: &str) -> &'static str {
+    match extension(path).to_ascii_lowercase().as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_rewrites_references() {
+        let dir = std::env::temp_dir().join(format!("n7tya-assets-{}", std::process::id()));
+        let public = dir.join("public");
+        fs::create_dir_all(public.join("css")).unwrap();
+        fs::write(public.join("logo.png"), b"png").unwrap();
+        fs::write(
+            public.join("css/app.css"),
+            "body { background: url(../logo.png); }",
+        )
+        .unwrap();
+        fs::write(public.join("index.html"), "<link href=\"/css/app.css\">").unwrap();
+
+        let manifest = build(&public, &dir.join("dist")).unwrap();
+        let logo = fingerprinted_path("/logo.png", b"png");
+        let css = &manifest["/css/app.css"];
+        assert_eq!(manifest["/logo.png"], logo);
+        assert_eq!(manifest["/index.html"], "/index.html");
+        assert_eq!(
+            fs::read_to_string(dir.join("dist").join(&css[1..])).unwrap(),
+            format!("body {{ background: url({}); }}", logo)
+        );
+
+        let assets = Assets::load(&dir).unwrap().unwrap();
+        assert_eq!(
+            assets.rewrite_html("<link rel=\"stylesheet\" href=\"/css/app.css?v=1\">"),
+            format!("<link rel=\"stylesheet\" href=\"{}?v=1\">", css)
+        );
+        assert!(assets.file(css).unwrap().immutable);
+        assert!(!assets.file("/css/app.css").unwrap().immutable);
+        assert_eq!(assets.file("/../n7tya.toml"), None);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_build_removes_stale_outputs() {
+        let dir = std::env::temp_dir().join(format!("n7tya-assets-stale-{}", std::process::id()));
+        let public = dir.join("public");
+        let dist = dir.join("dist");
+        fs::create_dir_all(public.join("css")).unwrap();
+        fs::create_dir_all(public.join("img")).unwrap();
+        fs::write(public.join("css/app.css"), "a {}").unwrap();
+        fs::write(public.join("img/logo.png"), b"png").unwrap();
+        let first = build(&public, &dist).unwrap();
+        fs::create_dir_all(dist.join("about")).unwrap();
+        fs::write(dist.join("about/index.html"), "page").unwrap();
+
+        fs::write(public.join("css/app.css"), "b {}").unwrap();
+        fs::remove_dir_all(public.join("img")).unwrap();
+        let second = build(&public, &dist).unwrap();
+        assert!(!dist.join(&first["/css/app.css"][1..]).exists());
+        assert!(dist.join(&second["/css/app.css"][1..]).exists());
+        // 空になったディレクトリも消し、build が書いていないファイルは残す
+        assert!(!dist.join("img").exists());
+        assert!(dist.join("about/index.html").exists());
+
+        // dist の外を指すマニフェストの項目は消さない
+        fs::write(dir.join("keep.txt"), "keep").unwrap();
+        remove_output(&dist, "/../keep.txt");
+        assert!(dir.join("keep.txt").exists());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
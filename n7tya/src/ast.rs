@@ -12,6 +12,8 @@ pub struct Program {
 pub enum Item {
     FunctionDef(FunctionDef),
     ClassDef(ClassDef),
+    StructDef(StructDef),
+    InterfaceDef(InterfaceDef),
     ComponentDef(ComponentDef),
     ServerDef(ServerDef),
     Import(ImportStmt),
@@ -24,6 +26,7 @@ pub struct ImportStmt {
     pub module: String,
     pub names: Vec<String>,    // from X import A, B, C
     pub alias: Option<String>, // import X as Y
+    pub lazy: bool,            // lazy import X (最初に使うときに実行する)
 }
 
 /// 関数定義
@@ -34,6 +37,8 @@ pub struct FunctionDef {
     pub return_type: Option<Type>,
     pub body: Vec<Statement>,
     pub is_async: bool,
+    pub doc: Option<String>, // 本体の先頭の文字列リテラル (docstring)
+    pub line: usize,         // def を書いた行 (ソースマップに使う。ラムダなどソースにないものは 0)
 }
 
 /// パラメータ
@@ -55,6 +60,34 @@ pub enum Type {
     Set(Box<Type>),
     Fn(Vec<Type>, Box<Type>), // Fn[Params] -> RetType
     Custom(String),
+    None,                 // none (Int | none のように合併型の中で使う)
+    Optional(Box<Type>),  // Int?
+    Union(Vec<Type>),     // Int | Str
+    Json(Box<Type>),     // Json<User> (ルートの戻り値を JSON にして返す)
+}
+
+impl std::fmt::Display for Type {
+    /// ソースコードでの書き方で表示する (エラーメッセージ用)
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Type::Int => write!(f, "Int"),
+            Type::Float => write!(f, "Float"),
+            Type::Bool => write!(f, "Bool"),
+            Type::Str => write!(f, "Str"),
+            Type::List(inner) => write!(f, "List<{}>", inner),
+            Type::Dict(key, value) => write!(f, "Dict<{}, {}>", key, value),
+            Type::Set(inner) => write!(f, "Set<{}>", inner),
+            Type::Fn(..) => write!(f, "Fn"),
+            Type::Custom(name) => write!(f, "{}", name),
+            Type::None => write!(f, "none"),
+            Type::Optional(inner) => write!(f, "{}?", inner),
+            Type::Union(members) => {
+                let names: Vec<String> = members.iter().map(|m| m.to_string()).collect();
+                write!(f, "{}", names.join(" | "))
+            }
+            Type::Json(inner) => write!(f, "Json<{}>", inner),
+        }
+    }
 }
 
 /// 文
@@ -68,13 +101,34 @@ pub enum Statement {
     For(ForStmt),
     While(WhileStmt),
     Match(MatchStmt),
-    Break,
-    Continue,
+    // break / continue (ラベルがあればそのループを抜ける・次に進む)
+    Break(Option<String>),
+    Continue(Option<String>),
+    // 関数の中で定義する関数 (外側の変数を参照で捕まえるクロージャ)
+    FunctionDef(FunctionDef),
+    // nonlocal a, b: 外側の関数の変数を書き換えると宣言する
+    Nonlocal(Vec<String>),
+    // copy a, b: 関数の本体の先頭に書き、def を実行したときの値を複製して捕まえる
+    Copy(Vec<String>),
     // コンポーネント用
     State(StateDecl),
     Render(RenderBlock),
     // 代入
     Assignment(AssignmentStmt),
+    // コメント・空行 (実行時は無視される)
+    Trivia(Trivia),
+}
+
+/// コメント・空行などのトリビア
+///
+/// 実行には影響しないが、AST から別のコードを書き出すとき (island の JS 生成) に
+/// コメントを失わないようASTに保持する
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trivia {
+    /// `#` 以降のコメント本文。trailing は直前の行の末尾にあったことを示す
+    Comment { text: String, trailing: bool },
+    /// 連続する空行の数
+    BlankLines(usize),
 }
 
 /// 変数宣言 (let, 変更可能)
@@ -103,11 +157,31 @@ pub struct AssignmentStmt {
 pub struct StateDecl {
     pub name: String,
     pub value: Expression,
+    pub persist: Option<Persist>, // state local count = 0
+}
+
+/// state を描画をまたいで保存する先
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Persist {
+    Memory,  // サーバーのプロセス内 (全員で共有)
+    Local,   // ブラウザの localStorage (island のみ)
+    Session, // サーバーのセッション (Cookie で訪問者ごと)
+}
+
+impl Persist {
+    pub fn name(self) -> &'static str {
+        match self {
+            Persist::Memory => "memory",
+            Persist::Local => "local",
+            Persist::Session => "session",
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct RenderBlock {
     pub body: Vec<Statement>,
+    pub line: usize, // render を書いた行
 }
 
 /// If文
@@ -116,6 +190,7 @@ pub struct IfStmt {
     pub condition: Expression,
     pub then_block: Vec<Statement>,
     pub else_block: Option<Vec<Statement>>,
+    pub elif: bool, // else_block を elif で書いた (else の中に if を1つ書いたブロックと区別する)
 }
 
 /// For文
@@ -124,6 +199,7 @@ pub struct ForStmt {
     pub target: String,
     pub iterator: Expression,
     pub body: Vec<Statement>,
+    pub label: Option<String>, // outer: for ... の outer
 }
 
 /// While文
@@ -131,6 +207,7 @@ pub struct ForStmt {
 pub struct WhileStmt {
     pub condition: Expression,
     pub body: Vec<Statement>,
+    pub label: Option<String>,
 }
 
 /// Match文 (パターンマッチ)
@@ -149,9 +226,15 @@ pub struct MatchCase {
 #[derive(Debug, Clone)]
 pub enum Pattern {
     Literal(Literal),
-    Identifier(String), // 変数にバインド
-    Wildcard,           // _
-    Range(i64, i64),    // 1..10
+    Identifier(String),           // 変数にバインド
+    Wildcard,                     // _
+    Range(i64, i64),              // 1..10
+    List(Vec<Pattern>),           // [first, *rest]
+    Rest(String),                 // *rest (リストパターン内でのみ有効、_ なら捨てる)
+    Dict(Vec<(String, Pattern)>), // {"type": t} (余分なキーは無視)
+    Variant(String, Box<Pattern>), // Ok(v) / Err(e) / Some(x)
+    Constructor(String, Vec<Pattern>), // Point(x, 0) (構造体・クラスのフィールドを定義順に照合)
+    Fields(String, Vec<(String, Pattern)>), // User {name: n} (名前で照合、ほかのフィールドは無視)
 }
 
 /// 式
@@ -166,7 +249,9 @@ pub enum Expression {
     Index(Box<IndexExpr>),
     Lambda(Box<LambdaExpr>),
     Await(Box<Expression>),
+    Try(Box<Expression>), // expr? (Err / none なら関数から即座に返す)
     JsxElement(Box<JsxElement>),
+    StructLiteral(Box<StructLiteral>),
 }
 
 #[derive(Debug, Clone)]
@@ -194,6 +279,29 @@ pub enum BinaryOp {
     In, // x in list
 }
 
+impl std::fmt::Display for BinaryOp {
+    /// ソースコードでの書き方で表示する (エラーメッセージ用)
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let symbol = match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Gt => ">",
+            BinaryOp::Le => "<=",
+            BinaryOp::Ge => ">=",
+            BinaryOp::And => "and",
+            BinaryOp::Or => "or",
+            BinaryOp::In => "in",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct UnaryExpr {
     pub op: UnaryOp,
@@ -210,6 +318,7 @@ pub enum UnaryOp {
 pub struct CallExpr {
     pub func: Expression,
     pub args: Vec<Expression>,
+    pub line: usize, // 呼び出した位置 (スタックトレースに使う)
 }
 
 #[derive(Debug, Clone)]
@@ -231,11 +340,20 @@ pub struct LambdaExpr {
     pub body: Expression,
 }
 
+/// 構造体リテラル: Point { x: 1, y: 2 }
+#[derive(Debug, Clone)]
+pub struct StructLiteral {
+    pub name: String,
+    pub fields: Vec<(String, Expression)>,
+}
+
 /// リテラル
 #[derive(Debug, Clone)]
 pub enum Literal {
     Int(i64),
     Float(f64),
+    Duration(std::time::Duration), // 5s / 200ms
+    Size(u64),                     // 10mb (バイト数)
     Str(String),
     Bool(bool),
     List(Vec<Expression>),
@@ -249,26 +367,65 @@ pub enum Literal {
 #[derive(Debug, Clone)]
 pub struct ClassDef {
     pub name: String,
-    pub parent: Option<String>, // 継承
+    pub parent: Option<String>,  // 継承
+    pub interfaces: Vec<String>, // implements で宣言したインターフェース
     pub body: Vec<ClassBodyItem>,
+    pub doc: Option<String>,
 }
 
 #[derive(Debug, Clone)]
 pub enum ClassBodyItem {
     Field(FieldDef),
     Method(FunctionDef),
+    StaticMethod(FunctionDef), // static def (ClassName.method() で呼ぶ)
+    Const(ConstDecl),          // クラス定数 (ClassName.NAME で参照する)
+    Trivia(Trivia),
+}
+
+/// 構造体定義 (型付きフィールドのみを持つ軽量なデータ型)
+#[derive(Debug, Clone)]
+pub struct StructDef {
+    pub name: String,
+    pub fields: Vec<FieldDef>,
+}
+
+/// インターフェース定義 (実装するクラスが持つべきメソッドのシグネチャ)
+#[derive(Debug, Clone)]
+pub struct InterfaceDef {
+    pub name: String,
+    pub methods: Vec<MethodSignature>,
+}
+
+/// 本体を持たないメソッド宣言: def area -> Int
+#[derive(Debug, Clone)]
+pub struct MethodSignature {
+    pub name: String,
+    pub params: Vec<Param>,
+    pub return_type: Option<Type>,
 }
 
 #[derive(Debug, Clone)]
 pub struct FieldDef {
     pub name: String,
     pub type_annotation: Type,
+    pub default: Option<Expression>, // クラスのフィールド初期値 (name: Int = 0)
+    pub checks: Vec<FieldCheck>,     // 型のあとの @min(0) などの検証
+}
+
+/// フィールドの値の検証 (`age: Int @min(0)`、引数はリテラルだけ)
+#[derive(Debug, Clone)]
+pub struct FieldCheck {
+    pub name: String,
+    pub args: Vec<Literal>,
 }
 
 #[derive(Debug, Clone)]
 pub struct ComponentDef {
     pub name: String,
     pub body: Vec<ComponentBodyItem>,
+    pub island: bool, // island component: ブラウザでも動かす (クライアント JS を生成する)
+    pub doc: Option<String>,
+    pub line: usize, // component を書いた行
 }
 
 #[derive(Debug, Clone)]
@@ -276,6 +433,7 @@ pub enum ComponentBodyItem {
     State(StateDecl),
     Method(FunctionDef),
     Render(RenderBlock),
+    Trivia(Trivia),
 }
 
 #[derive(Debug, Clone)]
@@ -287,13 +445,17 @@ pub struct ServerDef {
 #[derive(Debug, Clone)]
 pub enum ServerBodyItem {
     Route(RouteDef),
+    Trivia(Trivia),
 }
 
 #[derive(Debug, Clone)]
 pub struct RouteDef {
     pub path: String,
     pub method: String,
+    pub body_param: Option<Param>, // post "/users" (user: User) のリクエストボディ
+    pub return_type: Option<Type>, // -> Json<User>
     pub body: Vec<Statement>,
+    pub line: usize, // メソッドとパスを書いた行
 }
 
 #[derive(Debug, Clone)]
@@ -0,0 +1,179 @@
+//! ベンチマーク (n7tya bench)
+//!
+//! ディレクトリ内の .n7t を字句解析から実行まで繰り返し計測する。
+//! 結果は JSON に保存でき、保存した結果と比べて遅くなったものを報告する。
+
+use crate::interpreter::Interpreter;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+/// これ以上遅くなったら退行とみなす割合 (%) の既定値
+pub const DEFAULT_THRESHOLD: f64 = 10.0;
+
+/// 1プログラムの計測結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchResult {
+    pub name: String,
+    pub median_ns: u64,
+}
+
+/// ベースラインとの比較
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub name: String,
+    pub current_ns: u64,
+    pub baseline_ns: Option<u64>, // ベースラインにないプログラムは None
+}
+
+impl Comparison {
+    /// ベースラインからの増減 (%)
+    pub fn change(&self) -> Option<f64> {
+        let baseline = self.baseline_ns.filter(|&ns| ns > 0)?;
+        Some((self.current_ns as f64 - baseline as f64) / baseline as f64 * 100.0)
+    }
+}
+
+/// dir 内の .n7t をファイル名順に、それぞれ runs 回実行した中央値を返す
+pub fn run_dir(dir: &Path, runs: usize) -> Result<Vec<BenchResult>, String> {
+    let entries = fs::read_dir(dir)
+        .map_err(|e| format!("Failed to read directory '{}': {}", dir.display(), e))?;
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|e| e == "n7t"))
+        .collect();
+    paths.sort();
+
+    let mut results = Vec::new();
+    for path in paths {
+        let source = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read file '{}': {}", path.display(), e))?;
+        let name = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default()
+            .to_string();
+        let median_ns = measure(&source, runs).map_err(|e| format!("{}: {}", name, e))?;
+        results.push(BenchResult { name, median_ns });
+    }
+    Ok(results)
+}
+
+/// 1回の暖機のあと runs 回実行し、中央値 (ナノ秒) を返す
+fn measure(source: &str, runs: usize) -> Result<u64, String> {
+    run_source(source)?;
+    let mut times = Vec::with_capacity(runs);
+    for _ in 0..runs.max(1) {
+        let start = Instant::now();
+        run_source(source)?;
+        times.push(start.elapsed().as_nanos() as u64);
+    }
+    times.sort_unstable();
+    Ok(times[times.len() / 2])
+}
+
+/// 字句解析・構文解析・実行をひと通り行う
+pub fn run_source(source: &str) -> Result<(), String> {
+    let tokens = Lexer::new(source).tokenize();
+    let program = Parser::new(tokens)
+        .parse()
+        .map_err(|e| format!("Parse error: {}", e))?;
+    Interpreter::new().run(&program).map(|_| ())
+}
+
+pub fn to_json(results: &[BenchResult]) -> String {
+    let benchmarks: serde_json::Map<String, serde_json::Value> = results
+        .iter()
+        .map(|r| (r.name.clone(), json!({ "median_ns": r.median_ns })))
+        .collect();
+    let mut text = serde_json::to_string_pretty(&json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "benchmarks": benchmarks,
+    }))
+    .unwrap_or_default();
+    text.push('\n');
+    text
+}
+
+pub fn from_json(text: &str) -> Result<Vec<BenchResult>, String> {
+    let value: serde_json::Value =
+        serde_json::from_str(text).map_err(|e| format!("Invalid benchmark file: {}", e))?;
+    let benchmarks = value["benchmarks"]
+        .as_object()
+        .ok_or("Invalid benchmark file: missing 'benchmarks'")?;
+    benchmarks
+        .iter()
+        .map(|(name, entry)| {
+            let median_ns = entry["median_ns"].as_u64().ok_or_else(|| {
+                format!("Invalid benchmark file: missing 'median_ns' for '{}'", name)
+            })?;
+            Ok(BenchResult {
+                name: name.clone(),
+                median_ns,
+            })
+        })
+        .collect()
+}
+
+/// 今回の結果をベースラインと突き合わせる (順序は今回の結果のまま)
+pub fn compare(baseline: &[BenchResult], current: &[BenchResult]) -> Vec<Comparison> {
+    current
+        .iter()
+        .map(|r| Comparison {
+            name: r.name.clone(),
+            current_ns: r.median_ns,
+            baseline_ns: baseline
+                .iter()
+                .find(|b| b.name == r.name)
+                .map(|b| b.median_ns),
+        })
+        .collect()
+}
+
+/// ナノ秒を ms 表記にする
+pub fn format_ns(ns: u64) -> String {
+    format!("{:.2} ms", ns as f64 / 1_000_000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_round_trip_and_compare() {
+        let baseline = vec![
+            BenchResult {
+                name: "loop".to_string(),
+                median_ns: 1000,
+            },
+            BenchResult {
+                name: "strings".to_string(),
+                median_ns: 2000,
+            },
+        ];
+        assert_eq!(from_json(&to_json(&baseline)).unwrap(), baseline);
+
+        let current = vec![
+            BenchResult {
+                name: "loop".to_string(),
+                median_ns: 1200,
+            },
+            BenchResult {
+                name: "strings".to_string(),
+                median_ns: 1900,
+            },
+            BenchResult {
+                name: "jsx".to_string(),
+                median_ns: 500,
+            },
+        ];
+        let comparisons = compare(&baseline, &current);
+        assert_eq!(comparisons[0].change(), Some(20.0));
+        assert_eq!(comparisons[1].change(), Some(-5.0));
+        assert_eq!(comparisons[2].baseline_ns, None);
+        assert!(from_json("{}").is_err());
+    }
+}
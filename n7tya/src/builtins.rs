@@ -3,12 +3,93 @@
 //! 標準で利用可能な組み込み関数群
 
 use crate::interpreter::Value;
+use crate::iter::{Iter, Range};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fs;
 use std::io::{self, Write};
 use std::rc::Rc;
 
+/// import できる標準ライブラリのモジュールとその関数
+///
+/// 関数は "fs.read_file" のような名前の組み込み関数として呼ばれる
+pub const STDLIB_MODULES: &[(&str, &[&str])] = &[
+    (
+        "fs",
+        &[
+            "read_file",
+            "try_read_file",
+            "write_file",
+            "exists",
+            "remove",
+            "read_dir",
+        ],
+    ),
+    ("json", &["parse", "try_parse", "stringify", "get", "set"]),
+    ("csv", &["read", "write", "parse", "stringify"]),
+    ("yaml", &["parse", "try_parse", "stringify"]),
+    ("toml", &["parse", "try_parse", "stringify"]),
+    ("text", &["diff"]),
+    ("http", &["get", "post", "put", "patch", "delete", "download"]),
+    ("url", &["parse", "encode", "decode", "build_query"]),
+    ("base64", &["encode", "decode"]),
+    ("sqlite", &["open", "execute", "query", "close"]),
+    ("env", &["get"]),
+    ("process", &["run"]),
+    ("os", &["env", "set_env", "args", "cwd", "platform", "exit"]),
+    ("flash", &["set", "take"]), // flash, health, config, cache の実行はインタプリタ
+    ("health", &["check"]),
+    ("config", &["get"]),
+    ("cache", &["get_or", "get", "set", "delete", "clear"]),
+    ("circuit", &["call", "status", "reset"]),
+    ("events", &["on", "off", "emit"]),
+    (
+        "time",
+        &[
+            "now",
+            "monotonic",
+            "sleep",
+            "seconds",
+            "minutes",
+            "hours",
+            "days",
+            "weeks",
+            "format",
+            "parse",
+            "date",
+            "parts",
+        ],
+    ),
+    (
+        "stats",
+        &["mean", "median", "mode", "stdev", "percentile", "histogram"],
+    ),
+    (
+        "re",
+        &["match", "search", "find_all", "replace", "split", "escape"],
+    ),
+    (
+        "linalg",
+        &[
+            "identity",
+            "zeros",
+            "dot",
+            "cross",
+            "norm",
+            "add",
+            "sub",
+            "scale",
+            "transpose",
+            "matmul",
+            "det",
+            "inverse",
+        ],
+    ),
+];
+
+/// redirect(path) の値の印 (ルートがこれを返すと 303 See Other で path に移る)
+pub const REDIRECT_PREFIX: &str = "__redirect_";
+
 /// 組み込み関数の実行
 pub fn call_builtin(name: &str, args: Vec<Value>) -> Result<Value, String> {
     match name {
@@ -18,48 +99,100 @@ pub fn call_builtin(name: &str, args: Vec<Value>) -> Result<Value, String> {
         "range" => builtin_range(args),
         "input" => builtin_input(args),
         "str" => builtin_str(args),
+        "redirect" => builtin_redirect(args),
         "int" => builtin_int(args),
         "float" => builtin_float(args),
+        "decimal" => crate::decimal::builtin(args),
+        "diff" => crate::diff::diff(args),
+        "patch" => crate::diff::patch(args),
+        "assert_eq" => crate::diff::assert_eq(args),
+        "hex" | "bin" | "oct" => builtin_radix(name, args),
+        "bit_length" | "popcount" => builtin_bits(name, args),
+        "format" => match args.split_first() {
+            Some((Value::Str(template), args)) => {
+                Ok(Value::Str(format_string(template, args)?.into()))
+            }
+            _ => Err("format() expects a format string and values".to_string()),
+        },
         "type" => builtin_type(args),
         "abs" => builtin_abs(args),
         "min" => builtin_min(args),
         "max" => builtin_max(args),
         "sum" => builtin_sum(args),
-        "sorted" => builtin_sorted(args),
         "reversed" => builtin_reversed(args),
         "enumerate" => builtin_enumerate(args),
-        "zip" => builtin_zip(args),
+        "zip" | "zip_longest" => builtin_zip(name, args),
         "filter" => builtin_filter(args),
         "map" => builtin_map(args),
+        // 並行実行 (引数がリストリテラルの場合はインタプリタが並行に評価する)
+        // Result / Option
+        "Ok" => wrap_builtin("Ok", args, Value::Ok),
+        "Err" => wrap_builtin("Err", args, Value::Err),
+        "Some" => wrap_builtin("Some", args, Value::Some),
         // fs モジュール
         "fs.read_file" => builtin_fs_read_file(args),
+        "fs.try_read_file" => Ok(into_result(builtin_fs_read_file(args))),
         "fs.write_file" => builtin_fs_write_file(args),
         "fs.exists" => builtin_fs_exists(args),
         "fs.remove" => builtin_fs_remove(args),
         "fs.read_dir" => builtin_fs_read_dir(args),
         // json モジュール
         "json.parse" => builtin_json_parse(args),
+        "json.try_parse" => Ok(into_result(builtin_json_parse(args))),
         "json.stringify" => builtin_json_stringify(args),
+        "json.get" => crate::jsonpath::get(args),
+        "json.set" => crate::jsonpath::set(args),
         // http モジュール
-        "http.get" => builtin_http_get(args),
-        "http.post" => builtin_http_post(args),
+        _ if crate::http::is_request(name) => crate::http::call(name, args),
+        // url モジュール
+        _ if name.starts_with("url.") => crate::url::call(&name["url.".len()..], args),
         // base64 モジュール
         "base64.encode" => builtin_base64_encode(args),
         "base64.decode" => builtin_base64_decode(args),
         // sqlite モジュール
-        "sqlite.open" => builtin_sqlite_open(args),
-        "sqlite.execute" => builtin_sqlite_execute(args),
-        "sqlite.query" => builtin_sqlite_query(args),
-        "sqlite.close" => builtin_sqlite_close(args),
-        _ if name.starts_with("__class_") => {
+        _ if name.starts_with("sqlite.") => crate::sqlite::call(&name["sqlite.".len()..], args),
+        // env / process モジュール
+        "env.get" => builtin_env_get(args),
+        "process.run" => builtin_process_run(args),
+        // time モジュール
+        _ if name.starts_with("time.") => crate::datetime::call(&name["time.".len()..], args),
+        // stats モジュール
+        _ if name.starts_with("stats.") => crate::stats::call(&name["stats.".len()..], args),
+        // re モジュール
+        _ if name.starts_with("re.") => crate::re::call(&name["re.".len()..], args),
+        // linalg モジュール
+        _ if name.starts_with("linalg.") => crate::linalg::call(&name["linalg.".len()..], args),
+        // csv モジュール
+        _ if name.starts_with("csv.") => crate::csv::call(&name["csv.".len()..], args),
+        "yaml.try_parse" => Ok(into_result(crate::yaml::call("parse", args))),
+        _ if name.starts_with("yaml.") => crate::yaml::call(&name["yaml.".len()..], args),
+        "toml.try_parse" => Ok(into_result(crate::toml::call("parse", args))),
+        _ if name.starts_with("toml.") => crate::toml::call(&name["toml.".len()..], args),
+        "text.diff" => crate::diff::text_diff(args),
+        _ => match name.strip_prefix("__class_") {
             // クラスコンストラクタ
-            let class_name = name.strip_prefix("__class_").unwrap();
-            Ok(Value::Class(
+            Some(class_name) => Ok(Value::Class(
                 class_name.to_string(),
                 Rc::new(RefCell::new(HashMap::new())),
-            ))
-        }
-        _ => Err(format!("Unknown builtin function: {}", name)),
+            )),
+            None => Err(format!("Unknown builtin function: {}", name)),
+        },
+    }
+}
+
+/// Ok(v) / Err(e) / Some(v) を作る
+fn wrap_builtin(name: &str, args: Vec<Value>, wrap: fn(Rc<Value>) -> Value) -> Result<Value, String> {
+    match <[Value; 1]>::try_from(args) {
+        Ok([value]) => Ok(wrap(Rc::new(value))),
+        Err(_) => Err(format!("{}() takes exactly 1 argument", name)),
+    }
+}
+
+/// 組み込み関数の失敗を実行時エラーにせず Err の値として返す (fs.try_read_file など)
+fn into_result(result: Result<Value, String>) -> Value {
+    match result {
+        Ok(value) => Value::Ok(Rc::new(value)),
+        Err(message) => Value::Err(Rc::new(Value::Str(message.into()))),
     }
 }
 
@@ -82,38 +215,22 @@ fn builtin_len(args: Vec<Value>) -> Result<Value, String> {
         Some(Value::Str(s)) => Ok(Value::Int(s.len() as i64)),
         Some(Value::Dict(d)) => Ok(Value::Int(d.borrow().len() as i64)),
         Some(Value::Set(s)) => Ok(Value::Int(s.borrow().len() as i64)),
-        _ => Err("len() expects list, string, dict, or set".to_string()),
+        Some(Value::Range(range)) => i64::try_from(range.len())
+            .map(Value::Int)
+            .map_err(|_| "Integer overflow".to_string()),
+        _ => Err("len() expects list, string, dict, set or range".to_string()),
     }
 }
 
+/// range は回すときに値を1つずつ作る (step が 0 なら何も返さない)
 fn builtin_range(args: Vec<Value>) -> Result<Value, String> {
-    match args.as_slice() {
-        [Value::Int(n)] => {
-            let list: Vec<Value> = (0..*n).map(Value::Int).collect();
-            Ok(Value::List(Rc::new(RefCell::new(list))))
-        }
-        [Value::Int(start), Value::Int(end)] => {
-            let list: Vec<Value> = (*start..*end).map(Value::Int).collect();
-            Ok(Value::List(Rc::new(RefCell::new(list))))
-        }
-        [Value::Int(start), Value::Int(end), Value::Int(step)] => {
-            let mut list = Vec::new();
-            let mut i = *start;
-            if *step > 0 {
-                while i < *end {
-                    list.push(Value::Int(i));
-                    i += step;
-                }
-            } else if *step < 0 {
-                while i > *end {
-                    list.push(Value::Int(i));
-                    i += step;
-                }
-            }
-            Ok(Value::List(Rc::new(RefCell::new(list))))
-        }
-        _ => Err("range() expects 1-3 integer arguments".to_string()),
-    }
+    let (start, end, step) = match args.as_slice() {
+        [Value::Int(n)] => (0, *n, 1),
+        [Value::Int(start), Value::Int(end)] => (*start, *end, 1),
+        [Value::Int(start), Value::Int(end), Value::Int(step)] => (*start, *end, *step),
+        _ => return Err("range() expects 1-3 integer arguments".to_string()),
+    };
+    Ok(Value::Range(Range { start, end, step }))
 }
 
 fn builtin_input(args: Vec<Value>) -> Result<Value, String> {
@@ -127,20 +244,41 @@ fn builtin_input(args: Vec<Value>) -> Result<Value, String> {
         .read_line(&mut input)
         .map_err(|e| format!("Failed to read input: {}", e))?;
 
-    Ok(Value::Str(input.trim_end().to_string()))
+    Ok(Value::Str(input.trim_end().into()))
+}
+
+fn builtin_redirect(args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [Value::Str(path)] if !path.contains(['\r', '\n']) => {
+            Ok(Value::BuiltinFn(format!("{}{}", REDIRECT_PREFIX, path)))
+        }
+        [Value::Str(_)] => Err("redirect() path must not contain line breaks".to_string()),
+        _ => Err("redirect() expects a path string".to_string()),
+    }
 }
 
 fn builtin_str(args: Vec<Value>) -> Result<Value, String> {
     match args.first() {
-        Some(v) => Ok(Value::Str(v.display())),
+        Some(v) => Ok(Value::Str(v.display().into())),
         None => Err("str() requires an argument".to_string()),
     }
 }
 
 fn builtin_int(args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [Value::Str(s), Value::Int(base)] => return parse_int_radix(s, *base),
+        [_, _] => return Err("int() with a base requires a string and an integer base".to_string()),
+        _ => {}
+    }
     match args.first() {
         Some(Value::Int(n)) => Ok(Value::Int(*n)),
         Some(Value::Float(f)) => Ok(Value::Int(*f as i64)),
+        Some(Value::Decimal(d)) => d
+            .trunc()
+            .to_string()
+            .parse::<i64>()
+            .map(Value::Int)
+            .map_err(|_| format!("Cannot convert {} to int", d)),
         Some(Value::Str(s)) => s
             .parse::<i64>()
             .map(Value::Int)
@@ -150,10 +288,336 @@ fn builtin_int(args: Vec<Value>) -> Result<Value, String> {
     }
 }
 
+/// int(s, base): base 進数の文字列を整数にする (base が 0 なら 0x / 0o / 0b の接頭辞で決める)
+fn parse_int_radix(s: &str, base: i64) -> Result<Value, String> {
+    if base != 0 && !(2..=36).contains(&base) {
+        return Err("int() base must be 0 or between 2 and 36".to_string());
+    }
+    let error = || format!("Cannot convert '{}' to int with base {}", s, base);
+    let text = s.trim();
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    // 基数と同じ接頭辞は付いていてもよい
+    let head = digits.get(..2).unwrap_or("").to_ascii_lowercase();
+    let prefixed = [("0x", 16), ("0o", 8), ("0b", 2)]
+        .into_iter()
+        .find(|(prefix, _)| head == *prefix);
+    let (radix, digits) = match (base, prefixed) {
+        (0, Some((_, radix))) => (radix, &digits[2..]),
+        (0, None) => (10, digits),
+        (base, Some((_, radix))) if base == radix => (radix, &digits[2..]),
+        (base, _) => (base, digits),
+    };
+    // 符号は取り除いてあるので、残りに符号があれば誤り
+    if digits.starts_with(['+', '-']) {
+        return Err(error());
+    }
+    let magnitude = u64::from_str_radix(digits, radix as u32).map_err(|_| error())?;
+    let value = if negative {
+        0i64.checked_sub_unsigned(magnitude)
+    } else {
+        i64::try_from(magnitude).ok()
+    };
+    value.map(Value::Int).ok_or_else(error)
+}
+
+/// hex(n) / bin(n) / oct(n): 0x / 0b / 0o を付けた n 進数の文字列 (負の数は先頭に -)
+fn builtin_radix(name: &str, args: Vec<Value>) -> Result<Value, String> {
+    let [Value::Int(n)] = args.as_slice() else {
+        return Err(format!("{}() requires an integer argument", name));
+    };
+    let sign = if *n < 0 { "-" } else { "" };
+    let magnitude = n.unsigned_abs();
+    let text = match name {
+        "hex" => format!("{}0x{:x}", sign, magnitude),
+        "bin" => format!("{}0b{:b}", sign, magnitude),
+        _ => format!("{}0o{:o}", sign, magnitude),
+    };
+    Ok(Value::Str(text.into()))
+}
+
+/// bit_length(n): n の絶対値を表すのに必要なビット数、popcount(n): 絶対値の 1 のビットの数
+fn builtin_bits(name: &str, args: Vec<Value>) -> Result<Value, String> {
+    let [Value::Int(n)] = args.as_slice() else {
+        return Err(format!("{}() requires an integer argument", name));
+    };
+    let magnitude = n.unsigned_abs();
+    let bits = match name {
+        "bit_length" => u64::BITS - magnitude.leading_zeros(),
+        _ => magnitude.count_ones(),
+    };
+    Ok(Value::Int(bits as i64))
+}
+
+/// format("{} has {:>8.2} points", name, points) / "...".format(...)
+///
+/// {} は順に、{0} は番号の引数を、{name} は引数の辞書の name を埋める。{{ と }} は { と }。
+/// : のあとは [[埋める文字]揃え][+][0][幅][,][.精度][型] (揃えは < > ^、型は d f e % x X o b s)。
+/// 精度は数値なら小数点以下の桁数、文字列なら最大の文字数。数値は右、それ以外は左に揃える。
+pub fn format_string(template: &str, args: &[Value]) -> Result<String, String> {
+    let mut out = String::new();
+    let mut next = 0;
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '}' => return Err("format(): single '}' in format string".to_string()),
+            '{' => {
+                let mut field = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => field.push(c),
+                        None => return Err("format(): unmatched '{' in format string".to_string()),
+                    }
+                }
+                let (name, spec) = field.split_once(':').unwrap_or((&field, ""));
+                let value = if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+                    let index = if name.is_empty() {
+                        next += 1;
+                        next - 1
+                    } else {
+                        name.parse()
+                            .map_err(|_| format!("format(): invalid field '{{{}}}'", field))?
+                    };
+                    args.get(index).cloned().ok_or_else(|| {
+                        format!(
+                            "format(): no argument for field {} (got {})",
+                            index,
+                            args.len()
+                        )
+                    })?
+                } else {
+                    named_field(args, name)
+                        .ok_or_else(|| format!("format(): no argument for field '{}'", name))?
+                };
+                out.push_str(&format_value(&value, &FormatSpec::parse(spec)?)?);
+            }
+            c => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+/// {name} は引数の辞書 (構造体・クラスならフィールド) から同じ名前の値を探す
+fn named_field(args: &[Value], name: &str) -> Option<Value> {
+    args.iter().find_map(|arg| match arg {
+        Value::Dict(entries) => entries.borrow().get(name).cloned(),
+        Value::Class(_, fields) => fields.borrow().get(name).cloned(),
+        Value::Struct(_, fields) => fields
+            .borrow()
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.clone()),
+        _ => None,
+    })
+}
+
+/// {:...} の : のあと
+#[derive(Default)]
+struct FormatSpec {
+    fill: Option<char>,
+    align: Option<char>,
+    sign: bool,
+    zero: bool,
+    width: usize,
+    comma: bool,
+    precision: Option<usize>,
+    kind: Option<char>,
+}
+
+impl FormatSpec {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let invalid = || format!("format(): invalid format spec '{}'", spec);
+        let mut result = FormatSpec::default();
+        let chars: Vec<char> = spec.chars().collect();
+        let mut i = 0;
+        let is_align = |c: Option<&char>| matches!(c, Some('<' | '>' | '^'));
+        if is_align(chars.get(1)) {
+            result.fill = Some(chars[0]);
+            result.align = Some(chars[1]);
+            i = 2;
+        } else if is_align(chars.first()) {
+            result.align = Some(chars[0]);
+            i = 1;
+        }
+        if chars.get(i) == Some(&'+') {
+            result.sign = true;
+            i += 1;
+        }
+        if chars.get(i) == Some(&'0') {
+            result.zero = true;
+            i += 1;
+        }
+        let digits = |i: &mut usize| {
+            let start = *i;
+            while chars.get(*i).is_some_and(char::is_ascii_digit) {
+                *i += 1;
+            }
+            chars[start..*i].iter().collect::<String>()
+        };
+        let width = digits(&mut i);
+        if !width.is_empty() {
+            result.width = width.parse().map_err(|_| invalid())?;
+        }
+        if chars.get(i) == Some(&',') {
+            result.comma = true;
+            i += 1;
+        }
+        if chars.get(i) == Some(&'.') {
+            i += 1;
+            let precision = digits(&mut i);
+            result.precision = Some(precision.parse().map_err(|_| invalid())?);
+        }
+        match &chars[i..] {
+            [] => {}
+            [kind @ ('d' | 'f' | 'e' | '%' | 'x' | 'X' | 'o' | 'b' | 's')] => {
+                result.kind = Some(*kind)
+            }
+            _ => return Err(invalid()),
+        }
+        Ok(result)
+    }
+}
+
+fn format_value(value: &Value, spec: &FormatSpec) -> Result<String, String> {
+    let number = match value {
+        Value::Int(n) => Some(*n as f64),
+        Value::Float(f) => Some(*f),
+        Value::Decimal(d) => Some(crate::decimal::to_float(*d)),
+        _ => None,
+    };
+    let type_error = |kind: char, expected: &str| {
+        Err(format!(
+            "format(): '{}' format requires {}, got {}",
+            kind,
+            expected,
+            value.type_name()
+        ))
+    };
+    let body = match (spec.kind, value) {
+        // Decimal は Float を通さずに丸める
+        (Some('f') | None, Value::Decimal(d)) if spec.precision.is_some() => {
+            crate::decimal::format_fixed(*d, spec.precision.unwrap_or(0))
+        }
+        (Some(kind @ ('x' | 'X' | 'o' | 'b' | 'd')), Value::Int(n)) => {
+            let magnitude = n.unsigned_abs();
+            let digits = match kind {
+                'x' => format!("{:x}", magnitude),
+                'X' => format!("{:X}", magnitude),
+                'o' => format!("{:o}", magnitude),
+                'b' => format!("{:b}", magnitude),
+                _ => magnitude.to_string(),
+            };
+            if *n < 0 {
+                format!("-{}", digits)
+            } else {
+                digits
+            }
+        }
+        (Some(kind @ ('x' | 'X' | 'o' | 'b' | 'd')), _) => return type_error(kind, "an integer"),
+        (Some(kind @ ('f' | 'e' | '%')), _) => {
+            let Some(f) = number else {
+                return type_error(kind, "a number");
+            };
+            let precision = spec.precision.unwrap_or(6);
+            match kind {
+                'f' => format!("{:.*}", precision, f),
+                'e' => {
+                    // 指数は符号と2桁以上で書く (1.5e3 → 1.5e+03)
+                    let text = format!("{:.*e}", precision, f);
+                    let (mantissa, exponent) = text.split_once('e').unwrap_or((&text, "0"));
+                    let (sign, digits) = match exponent.strip_prefix('-') {
+                        Some(digits) => ('-', digits),
+                        None => ('+', exponent),
+                    };
+                    format!("{}e{}{:0>2}", mantissa, sign, digits)
+                }
+                _ => format!("{:.*}%", precision, f * 100.0),
+            }
+        }
+        // 精度を付けた数値は小数点以下の桁数を揃える
+        (None, _) if number.is_some() && spec.precision.is_some() => {
+            format!("{:.*}", spec.precision.unwrap_or(0), number.unwrap_or(0.0))
+        }
+        _ => {
+            let text = value.display();
+            match spec.precision {
+                Some(max) => text.chars().take(max).collect(),
+                None => text,
+            }
+        }
+    };
+    let is_number = number.is_some() && spec.kind != Some('s');
+    let mut body = if is_number && spec.comma {
+        group_thousands(&body)
+    } else {
+        body
+    };
+    if is_number && spec.sign && !body.starts_with('-') {
+        body.insert(0, '+');
+    }
+
+    let len = body.chars().count();
+    if len >= spec.width {
+        return Ok(body);
+    }
+    let padding = spec.width - len;
+    // 0 埋めは符号のあとに入れる
+    if is_number && spec.zero && spec.align.is_none() {
+        let sign_len = usize::from(body.starts_with(['+', '-']));
+        body.insert_str(sign_len, &"0".repeat(padding));
+        return Ok(body);
+    }
+    let fill = spec.fill.unwrap_or(' ').to_string();
+    let align = spec.align.unwrap_or(if is_number { '>' } else { '<' });
+    let (left, right) = match align {
+        '>' => (padding, 0),
+        '^' => (padding / 2, padding - padding / 2),
+        _ => (0, padding),
+    };
+    Ok(format!(
+        "{}{}{}",
+        fill.repeat(left),
+        body,
+        fill.repeat(right)
+    ))
+}
+
+/// 整数部分を 3 桁ごとにカンマで区切る ("-1234567.5" → "-1,234,567.5")
+fn group_thousands(number: &str) -> String {
+    let (sign, rest) = match number.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", number),
+    };
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    let (digits, tail) = rest.split_at(end);
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    format!("{}{}{}", sign, grouped, tail)
+}
+
 fn builtin_float(args: Vec<Value>) -> Result<Value, String> {
     match args.first() {
         Some(Value::Int(n)) => Ok(Value::Float(*n as f64)),
         Some(Value::Float(f)) => Ok(Value::Float(*f)),
+        Some(Value::Decimal(d)) => Ok(Value::Float(crate::decimal::to_float(*d))),
         Some(Value::Str(s)) => s
             .parse::<f64>()
             .map(Value::Float)
@@ -163,28 +627,17 @@ fn builtin_float(args: Vec<Value>) -> Result<Value, String> {
 }
 
 fn builtin_type(args: Vec<Value>) -> Result<Value, String> {
-    let type_name = match args.first() {
-        Some(Value::Int(_)) => "Int",
-        Some(Value::Float(_)) => "Float",
-        Some(Value::Str(_)) => "Str",
-        Some(Value::Bool(_)) => "Bool",
-        Some(Value::List(_)) => "List",
-        Some(Value::Dict(_)) => "Dict",
-        Some(Value::Set(_)) => "Set",
-        Some(Value::None) => "None",
-        Some(Value::Fn(_, _)) => "Fn",
-        Some(Value::BuiltinFn(_)) => "BuiltinFn",
-        Some(Value::Class(name, _)) => return Ok(Value::Str(name.clone())),
-        Some(Value::Return(_)) => "Return",
-        None => return Err("type() requires an argument".to_string()),
-    };
-    Ok(Value::Str(type_name.to_string()))
+    match args.first() {
+        Some(value) => Ok(Value::Str(value.type_name().into())),
+        None => Err("type() requires an argument".to_string()),
+    }
 }
 
 fn builtin_abs(args: Vec<Value>) -> Result<Value, String> {
     match args.first() {
         Some(Value::Int(n)) => Ok(Value::Int(n.abs())),
         Some(Value::Float(f)) => Ok(Value::Float(f.abs())),
+        Some(Value::Decimal(d)) => Ok(Value::Decimal(d.abs())),
         _ => Err("abs() requires a numeric argument".to_string()),
     }
 }
@@ -271,10 +724,17 @@ fn builtin_sum(args: Vec<Value>) -> Result<Value, String> {
     match args.first() {
         Some(Value::List(list)) => {
             let list = list.borrow();
+            if list.iter().any(|item| matches!(item, Value::Decimal(_))) {
+                return crate::decimal::sum(&list);
+            }
             let mut sum = 0i64;
             for item in list.iter() {
                 match item {
-                    Value::Int(n) => sum += n,
+                    Value::Int(n) => {
+                        sum = sum
+                            .checked_add(*n)
+                            .ok_or_else(|| "Integer overflow".to_string())?
+                    }
                     _ => return Err("sum() requires list of integers".to_string()),
                 }
             }
@@ -284,25 +744,6 @@ fn builtin_sum(args: Vec<Value>) -> Result<Value, String> {
     }
 }
 
-fn builtin_sorted(args: Vec<Value>) -> Result<Value, String> {
-    match args.first() {
-        Some(Value::List(list)) => {
-            let list = list.borrow();
-            let mut ints: Vec<i64> = Vec::new();
-            for item in list.iter() {
-                match item {
-                    Value::Int(n) => ints.push(*n),
-                    _ => return Err("sorted() requires list of integers".to_string()),
-                }
-            }
-            ints.sort();
-            let result: Vec<Value> = ints.into_iter().map(Value::Int).collect();
-            Ok(Value::List(Rc::new(RefCell::new(result))))
-        }
-        _ => Err("sorted() expects a list argument".to_string()),
-    }
-}
-
 fn builtin_reversed(args: Vec<Value>) -> Result<Value, String> {
     match args.first() {
         Some(Value::List(list)) => {
@@ -312,57 +753,59 @@ fn builtin_reversed(args: Vec<Value>) -> Result<Value, String> {
         }
         Some(Value::Str(s)) => {
             let reversed: String = s.chars().rev().collect();
-            Ok(Value::Str(reversed))
+            Ok(Value::Str(reversed.into()))
         }
         _ => Err("reversed() expects a list or string argument".to_string()),
     }
 }
 
+/// enumerate(x) / enumerate(x, start) はインデックスと要素の組を返すイテレータを返す
 fn builtin_enumerate(args: Vec<Value>) -> Result<Value, String> {
-    match args.first() {
-        Some(Value::List(list)) => {
-            let list = list.borrow();
-            let result: Vec<Value> = list
-                .iter()
-                .enumerate()
-                .map(|(i, v)| {
-                    Value::List(Rc::new(RefCell::new(vec![Value::Int(i as i64), v.clone()])))
-                })
-                .collect();
-            Ok(Value::List(Rc::new(RefCell::new(result))))
+    let start = match args.get(1) {
+        None => 0,
+        Some(Value::Int(start)) if args.len() == 2 => *start,
+        Some(_) => {
+            return Err("enumerate() expects a collection and an optional start index".to_string())
         }
-        _ => Err("enumerate() expects a list argument".to_string()),
-    }
+    };
+    args.first()
+        .and_then(|value| Iter::enumerate(value, start))
+        .ok_or_else(|| "enumerate() expects a list, dict, string, set or iterator".to_string())
 }
 
-fn builtin_zip(args: Vec<Value>) -> Result<Value, String> {
-    if args.len() != 2 {
-        return Err("zip() expects exactly 2 arguments".to_string());
-    }
+/// zip(a, b, ...) は一番短いもの、zip_longest(a, b, ...) は一番長いものに合わせて
+/// (足りない要素は none) 要素の組を返すイテレータを返す
+fn builtin_zip(name: &str, args: Vec<Value>) -> Result<Value, String> {
+    Iter::zip(&args, name == "zip_longest").ok_or_else(|| {
+        format!(
+            "{}() expects lists, dicts, strings, sets or iterators",
+            name
+        )
+    })
+}
 
-    match (&args[0], &args[1]) {
-        (Value::List(list1), Value::List(list2)) => {
-            let list1 = list1.borrow();
-            let list2 = list2.borrow();
-            let result: Vec<Value> = list1
-                .iter()
-                .zip(list2.iter())
-                .map(|(a, b)| Value::List(Rc::new(RefCell::new(vec![a.clone(), b.clone()]))))
-                .collect();
-            Ok(Value::List(Rc::new(RefCell::new(result))))
+/// filter(f, x) は f が真を返した要素だけを取り出すイテレータを返す
+fn builtin_filter(args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [function @ (Value::Fn(..) | Value::BuiltinFn(_)), iterable] => {
+            Iter::filter(function.clone(), iterable)
+                .ok_or_else(|| format!("filter() cannot iterate over {}", iterable.display()))
+        }
+        _ => {
+            Err("filter() expects a function and a list, dict, string, set or iterator".to_string())
         }
-        _ => Err("zip() expects two list arguments".to_string()),
     }
 }
 
-fn builtin_filter(_args: Vec<Value>) -> Result<Value, String> {
-    // filter() は高階関数なので、Interpreter 側で実装する必要がある
-    Err("filter() is not yet implemented as a builtin".to_string())
-}
-
-fn builtin_map(_args: Vec<Value>) -> Result<Value, String> {
-    // map() は高階関数なので、Interpreter 側で実装する必要がある
-    Err("map() is not yet implemented as a builtin".to_string())
+/// map(f, x) は要素を取り出すときに f を呼ぶイテレータを返す
+fn builtin_map(args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [function @ (Value::Fn(..) | Value::BuiltinFn(_)), iterable] => {
+            Iter::map(function.clone(), iterable)
+                .ok_or_else(|| format!("map() cannot iterate over {}", iterable.display()))
+        }
+        _ => Err("map() expects a function and a list, dict, string, set or iterator".to_string()),
+    }
 }
 
 // ============================================================
@@ -374,8 +817,8 @@ fn builtin_fs_read_file(args: Vec<Value>) -> Result<Value, String> {
         return Err("fs.read_file() takes exactly 1 argument".to_string());
     }
     if let Value::Str(path) = &args[0] {
-        match fs::read_to_string(path) {
-            Ok(content) => Ok(Value::Str(content)),
+        match fs::read_to_string(&**path) {
+            Ok(content) => Ok(Value::Str(content.into())),
             Err(e) => Err(format!("Failed to read file '{}': {}", path, e)),
         }
     } else {
@@ -388,7 +831,7 @@ fn builtin_fs_write_file(args: Vec<Value>) -> Result<Value, String> {
         return Err("fs.write_file() takes exactly 2 arguments".to_string());
     }
     if let (Value::Str(path), Value::Str(content)) = (&args[0], &args[1]) {
-        match fs::write(path, content) {
+        match fs::write(&**path, &**content) {
             Ok(_) => Ok(Value::None),
             Err(e) => Err(format!("Failed to write file '{}': {}", path, e)),
         }
@@ -402,7 +845,7 @@ fn builtin_fs_exists(args: Vec<Value>) -> Result<Value, String> {
         return Err("fs.exists() takes exactly 1 argument".to_string());
     }
     if let Value::Str(path) = &args[0] {
-        Ok(Value::Bool(std::path::Path::new(path).exists()))
+        Ok(Value::Bool(std::path::Path::new(&**path).exists()))
     } else {
         Err("fs.exists() expects a string path".to_string())
     }
@@ -413,11 +856,11 @@ fn builtin_fs_remove(args: Vec<Value>) -> Result<Value, String> {
         return Err("fs.remove() takes exactly 1 argument".to_string());
     }
     if let Value::Str(path) = &args[0] {
-        let path_obj = std::path::Path::new(path);
+        let path_obj = std::path::Path::new(&**path);
         let result = if path_obj.is_dir() {
-            fs::remove_dir_all(path)
+            fs::remove_dir_all(path_obj)
         } else {
-            fs::remove_file(path)
+            fs::remove_file(path_obj)
         };
         match result {
             Ok(_) => Ok(Value::None),
@@ -433,12 +876,12 @@ fn builtin_fs_read_dir(args: Vec<Value>) -> Result<Value, String> {
         return Err("fs.read_dir() takes exactly 1 argument".to_string());
     }
     if let Value::Str(path) = &args[0] {
-        match fs::read_dir(path) {
+        match fs::read_dir(&**path) {
             Ok(entries) => {
                 let names: Vec<Value> = entries
                     .filter_map(|e| e.ok())
                     .filter_map(|e| e.file_name().into_string().ok())
-                    .map(Value::Str)
+                    .map(|name| Value::Str(name.into()))
                     .collect();
                 Ok(Value::List(Rc::new(RefCell::new(names))))
             }
@@ -453,7 +896,7 @@ fn builtin_fs_read_dir(args: Vec<Value>) -> Result<Value, String> {
 // json モジュール - JSON操作
 // ============================================================
 
-fn json_to_value(json: serde_json::Value) -> Value {
+pub(crate) fn json_to_value(json: serde_json::Value) -> Value {
     match json {
         serde_json::Value::Null => Value::None,
         serde_json::Value::Bool(b) => Value::Bool(b),
@@ -466,7 +909,7 @@ fn json_to_value(json: serde_json::Value) -> Value {
                 Value::None
             }
         }
-        serde_json::Value::String(s) => Value::Str(s),
+        serde_json::Value::String(s) => Value::Str(s.into()),
         serde_json::Value::Array(arr) => {
             let values: Vec<Value> = arr.into_iter().map(json_to_value).collect();
             Value::List(Rc::new(RefCell::new(values)))
@@ -481,7 +924,13 @@ fn json_to_value(json: serde_json::Value) -> Value {
     }
 }
 
-fn value_to_json(value: &Value) -> serde_json::Value {
+pub(crate) fn value_to_json(value: &Value) -> serde_json::Value {
+    to_json(value, false)
+}
+
+/// decimal_numbers が true なら Decimal を (精度を落として) JSON の数値にする
+fn to_json(value: &Value, decimal_numbers: bool) -> serde_json::Value {
+    let value_to_json = |value: &Value| to_json(value, decimal_numbers);
     match value {
         Value::None => serde_json::Value::Null,
         Value::Bool(b) => serde_json::Value::Bool(*b),
@@ -491,11 +940,19 @@ fn value_to_json(value: &Value) -> serde_json::Value {
                 .map(serde_json::Value::Number)
                 .unwrap_or(serde_json::Value::Null)
         }
-        Value::Str(s) => serde_json::Value::String(s.clone()),
+        // JSON では秒数とバイト数にする
+        Value::Duration(d) => value_to_json(&Value::Float(d.as_secs_f64())),
+        Value::Size(bytes) => serde_json::Value::Number((*bytes).into()),
+        Value::Decimal(d) if decimal_numbers => {
+            value_to_json(&Value::Float(crate::decimal::to_float(*d)))
+        }
+        Value::Decimal(d) => serde_json::Value::String(d.to_string()),
+        Value::Str(s) => serde_json::Value::String(s.to_string()),
         Value::List(list) => {
             let arr: Vec<serde_json::Value> = list.borrow().iter().map(value_to_json).collect();
             serde_json::Value::Array(arr)
         }
+        Value::Range(range) => range.items().iter().map(value_to_json).collect(),
         Value::Dict(dict) => {
             let obj: serde_json::Map<String, serde_json::Value> = dict
                 .borrow()
@@ -504,6 +961,14 @@ fn value_to_json(value: &Value) -> serde_json::Value {
                 .collect();
             serde_json::Value::Object(obj)
         }
+        Value::Struct(_, fields) => {
+            let obj: serde_json::Map<String, serde_json::Value> = fields
+                .borrow()
+                .iter()
+                .map(|(k, v)| (k.clone(), value_to_json(v)))
+                .collect();
+            serde_json::Value::Object(obj)
+        }
         _ => serde_json::Value::Null,
     }
 }
@@ -522,35 +987,48 @@ fn builtin_json_parse(args: Vec<Value>) -> Result<Value, String> {
     }
 }
 
-fn builtin_json_stringify(args: Vec<Value>) -> Result<Value, String> {
-    if args.len() != 1 {
-        return Err("json.stringify() takes exactly 1 argument".to_string());
-    }
-    let json = value_to_json(&args[0]);
-    match serde_json::to_string(&json) {
-        Ok(s) => Ok(Value::Str(s)),
-        Err(e) => Err(format!("JSON stringify error: {}", e)),
+/// json.stringify(value) / json.stringify(value, {"decimal": "number"})
+/// 値の中にイテレータがあるか (回し切らないと JSON にできない)
+fn has_iterator(value: &Value) -> bool {
+    match value {
+        Value::Iter(_) => true,
+        Value::List(items) | Value::Set(items) => items.borrow().iter().any(has_iterator),
+        Value::Dict(dict) => dict.borrow().values().any(has_iterator),
+        Value::Struct(_, fields) => fields.borrow().iter().any(|(_, v)| has_iterator(v)),
+        _ => false,
     }
 }
 
-// ============================================================
-// http モジュール - HTTPクライアント
-// ============================================================
-
-fn builtin_http_get(args: Vec<Value>) -> Result<Value, String> {
-    if args.len() != 1 {
-        return Err("http.get() takes exactly 1 argument".to_string());
-    }
-    if let Value::Str(url) = &args[0] {
-        match ureq::get(url).call() {
-            Ok(response) => {
-                let body = response.into_string().unwrap_or_default();
-                Ok(Value::Str(body))
+fn builtin_json_stringify(args: Vec<Value>) -> Result<Value, String> {
+    let decimal_numbers = match args.as_slice() {
+        [_] => false,
+        [_, Value::Dict(options)] => match options.borrow().get("decimal") {
+            None => false,
+            Some(Value::Str(mode)) if &**mode == "string" => false,
+            Some(Value::Str(mode)) if &**mode == "number" => true,
+            Some(other) => {
+                return Err(format!(
+                    "json.stringify(): option 'decimal' must be \"string\" or \"number\", got {}",
+                    other.display()
+                ))
             }
-            Err(e) => Err(format!("HTTP GET error: {}", e)),
+        },
+        _ => {
+            return Err(
+                "json.stringify() expects a value and an optional dict of options".to_string(),
+            )
         }
-    } else {
-        Err("http.get() expects a URL string".to_string())
+    };
+    if has_iterator(&args[0]) {
+        return Err(
+            "json.stringify() cannot serialize an iterator; convert it with list() first"
+                .to_string(),
+        );
+    }
+    let json = to_json(&args[0], decimal_numbers);
+    match serde_json::to_string(&json) {
+        Ok(s) => Ok(Value::Str(s.into())),
+        Err(e) => Err(format!("JSON stringify error: {}", e)),
     }
 }
 
@@ -564,7 +1042,7 @@ fn builtin_base64_encode(args: Vec<Value>) -> Result<Value, String> {
         return Err("base64.encode() takes exactly 1 argument".to_string());
     }
     match &args[0] {
-        Value::Str(s) => Ok(Value::Str(BASE64.encode(s))),
+        Value::Str(s) => Ok(Value::Str(BASE64.encode(s.as_bytes()).into())),
         _ => Err("base64.encode() expects a string".to_string()),
     }
 }
@@ -574,9 +1052,9 @@ fn builtin_base64_decode(args: Vec<Value>) -> Result<Value, String> {
         return Err("base64.decode() takes exactly 1 argument".to_string());
     }
     match &args[0] {
-        Value::Str(s) => match BASE64.decode(s) {
+        Value::Str(s) => match BASE64.decode(s.as_bytes()) {
             Ok(bytes) => match String::from_utf8(bytes) {
-                Ok(s) => Ok(Value::Str(s)),
+                Ok(s) => Ok(Value::Str(s.into())),
                 Err(_) => Err("base64 decoded result is not valid UTF-8".to_string()),
             },
             Err(e) => Err(format!("base64 decode error: {}", e)),
@@ -586,162 +1064,49 @@ fn builtin_base64_decode(args: Vec<Value>) -> Result<Value, String> {
 }
 
 // ============================================================
-// sqlite モジュール
+// env / process モジュール - 環境変数とサブプロセス
 // ============================================================
-use rusqlite::{Connection, params_from_iter};
-use std::sync::atomic::{AtomicI64, Ordering};
-
-// SQLite接続を管理するスレッドローカルストレージ
-thread_local! {
-    static SQLITE_CONNECTIONS: RefCell<HashMap<i64, Connection>> = RefCell::new(HashMap::new());
-}
-
-static NEXT_CONN_ID: AtomicI64 = AtomicI64::new(1);
-
-fn builtin_sqlite_open(args: Vec<Value>) -> Result<Value, String> {
-    if args.len() != 1 {
-        return Err("sqlite.open() takes exactly 1 argument".to_string());
-    }
-    if let Value::Str(path) = &args[0] {
-        match Connection::open(path) {
-            Ok(conn) => {
-                let id = NEXT_CONN_ID.fetch_add(1, Ordering::SeqCst);
-                SQLITE_CONNECTIONS.with(|conns| {
-                    conns.borrow_mut().insert(id, conn);
-                });
-                Ok(Value::Int(id))
-            }
-            Err(e) => Err(format!("SQLite open error: {}", e)),
-        }
-    } else {
-        Err("sqlite.open() expects a path string".to_string())
-    }
-}
-
-fn builtin_sqlite_execute(args: Vec<Value>) -> Result<Value, String> {
-    if args.len() < 2 {
-        return Err("sqlite.execute() takes at least 2 arguments (conn_id, sql)".to_string());
-    }
-    match (&args[0], &args[1]) {
-        (Value::Int(id), Value::Str(sql)) => {
-            let params: Vec<Box<dyn rusqlite::ToSql>> = args.iter().skip(2).map(|v| {
-                let p: Box<dyn rusqlite::ToSql> = match v {
-                    Value::Int(n) => Box::new(*n),
-                    Value::Float(f) => Box::new(*f),
-                    Value::Str(s) => Box::new(s.clone()),
-                    Value::Bool(b) => Box::new(*b),
-                    Value::None => Box::new(rusqlite::types::Null),
-                    _ => Box::new(v.display()), // Fallback to string repr
-                };
-                p
-            }).collect();
-
-            SQLITE_CONNECTIONS.with(|conns| {
-                if let Some(conn) = conns.borrow().get(id) {
-                    match conn.execute(sql, params_from_iter(params.iter())) {
-                        Ok(affected) => Ok(Value::Int(affected as i64)),
-                        Err(e) => Err(format!("SQLite execute error: {}", e)),
-                    }
-                } else {
-                    Err("Invalid SQLite connection ID".to_string())
-                }
-            })
-        }
-        _ => Err("sqlite.execute() expects (id: Int, sql: Str)".to_string()),
-    }
-}
 
-fn builtin_sqlite_query(args: Vec<Value>) -> Result<Value, String> {
-    if args.len() < 2 {
-        return Err("sqlite.query() takes at least 2 arguments (conn_id, sql)".to_string());
-    }
-    match (&args[0], &args[1]) {
-        (Value::Int(id), Value::Str(sql)) => {
-            let params_vals: Vec<Box<dyn rusqlite::ToSql>> = args.iter().skip(2).map(|v| {
-                let p: Box<dyn rusqlite::ToSql> = match v {
-                    Value::Int(n) => Box::new(*n),
-                    Value::Float(f) => Box::new(*f),
-                    Value::Str(s) => Box::new(s.clone()),
-                    Value::Bool(b) => Box::new(*b),
-                    Value::None => Box::new(rusqlite::types::Null),
-                    _ => Box::new(v.display()),
-                };
-                p
-            }).collect();
-
-            SQLITE_CONNECTIONS.with(|conns| {
-                if let Some(conn) = conns.borrow().get(id) {
-                    let mut stmt = conn.prepare(sql).map_err(|e| e.to_string())?;
-                    let col_names: Vec<String> = stmt.column_names().into_iter().map(|s| s.to_string()).collect();
-                    
-                    let rows = stmt.query_map(params_from_iter(params_vals.iter()), |row| {
-                        let mut dict = HashMap::new();
-                        for (i, col_name) in col_names.iter().enumerate() {
-                            let val = match row.get_ref(i)? {
-                                rusqlite::types::ValueRef::Null => Value::None,
-                                rusqlite::types::ValueRef::Integer(n) => Value::Int(n),
-                                rusqlite::types::ValueRef::Real(f) => Value::Float(f),
-                                rusqlite::types::ValueRef::Text(t) => Value::Str(String::from_utf8_lossy(t).to_string()),
-                                rusqlite::types::ValueRef::Blob(b) => Value::Str(BASE64.encode(b)), // Blob as Base64
-                            };
-                            dict.insert(col_name.clone(), val);
-                        }
-                        Ok(Value::Dict(Rc::new(RefCell::new(dict))))
-                    }).map_err(|e| e.to_string())?;
-
-                    let result_list: Vec<Value> = rows.filter_map(Result::ok).collect();
-                    Ok(Value::List(Rc::new(RefCell::new(result_list))))
-                } else {
-                    Err("Invalid SQLite connection ID".to_string())
-                }
-            })
-        }
-        _ => Err("sqlite.query() expects (id: Int, sql: Str)".to_string()),
-    }
+/// env.get(name) / env.get(name, default): 環境変数 (ない場合は none か default)
+fn builtin_env_get(args: Vec<Value>) -> Result<Value, String> {
+    let (name, default) = match args.as_slice() {
+        [Value::Str(name)] => (name, Value::None),
+        [Value::Str(name), default] => (name, default.clone()),
+        _ => return Err("env.get() expects (name: Str, default?)".to_string()),
+    };
+    Ok(std::env::var(&**name).map_or(default, |value| Value::Str(value.into())))
 }
 
-fn builtin_sqlite_close(args: Vec<Value>) -> Result<Value, String> {
-    if args.len() != 1 {
-        return Err("sqlite.close() takes exactly 1 argument".to_string());
-    }
-    if let Value::Int(id) = &args[0] {
-        SQLITE_CONNECTIONS.with(|conns| {
-            if conns.borrow_mut().remove(id).is_some() {
-                Ok(Value::None)
-            } else {
-                Err("Invalid SQLite connection ID".to_string())
-            }
+/// process.run(command) / process.run(command, args): 終わるまで待ち、status・stdout・stderr を返す
+fn builtin_process_run(args: Vec<Value>) -> Result<Value, String> {
+    let (command, arguments) = match args.as_slice() {
+        [Value::Str(command)] => (command, Vec::new()),
+        [Value::Str(command), Value::List(list)] => (command, list.borrow().clone()),
+        _ => return Err("process.run() expects (command: Str, args?: List<Str>)".to_string()),
+    };
+    let arguments = arguments
+        .iter()
+        .map(|arg| match arg {
+            Value::Str(s) => Ok(s.to_string()),
+            _ => Err("process.run() expects a list of strings as arguments".to_string()),
         })
-    } else {
-        Err("sqlite.close() expects an integer ID".to_string())
-    }
-}
-
-fn builtin_http_post(args: Vec<Value>) -> Result<Value, String> {
-    if args.len() < 2 {
-        return Err("http.post() takes at least 2 arguments (url, body)".to_string());
-    }
-    if let (Value::Str(url), body) = (&args[0], &args[1]) {
-        let body_str = match body {
-            Value::Str(s) => s.clone(),
-            _ => {
-                // 自動的にJSONにシリアライズ
-                let json = value_to_json(body);
-                serde_json::to_string(&json).unwrap_or_default()
-            }
-        };
-        
-        match ureq::post(url)
-            .set("Content-Type", "application/json")
-            .send_string(&body_str)
-        {
-            Ok(response) => {
-                let body = response.into_string().unwrap_or_default();
-                Ok(Value::Str(body))
-            }
-            Err(e) => Err(format!("HTTP POST error: {}", e)),
-        }
-    } else {
-        Err("http.post() expects (url: Str, body)".to_string())
-    }
+        .collect::<Result<Vec<_>, _>>()?;
+    let output = std::process::Command::new(&**command)
+        .args(&arguments)
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", command, e))?;
+    let mut result = HashMap::new();
+    result.insert(
+        "status".to_string(),
+        Value::Int(output.status.code().unwrap_or(-1) as i64),
+    );
+    result.insert(
+        "stdout".to_string(),
+        Value::Str(String::from_utf8_lossy(&output.stdout).into_owned().into()),
+    );
+    result.insert(
+        "stderr".to_string(),
+        Value::Str(String::from_utf8_lossy(&output.stderr).into_owned().into()),
+    );
+    Ok(Value::Dict(Rc::new(RefCell::new(result))))
 }
@@ -0,0 +1,224 @@
+//! cache モジュール (メモリ上のキャッシュ、有効期限つき)
+//!
+//! `cache.get_or("rates", 60, fetch_rates)` は保存した値がまだ有効ならそれを返し、
+//! なければ関数を呼んで結果を 60 秒間保存する。ルートの中で遅い API の呼び出しなどを減らすのに使う。
+//! 保存できる数 (n7tya.toml の [cache] max_entries) を超えたら、最も長く使っていない値から消す。
+//! 値はプロセスの中だけに保存し、同じ値をそのまま返す (リストを書き換えると保存した値も変わる)。
+
+use crate::interpreter::{Interpreter, Value};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// [cache] max_entries を書いていないときに保存できる数
+pub const DEFAULT_MAX_ENTRIES: usize = 1000;
+
+/// キーごとの値と有効期限 (LRU)
+#[derive(Debug)]
+pub struct Cache {
+    entries: HashMap<String, Entry>,
+    max_entries: usize,
+    clock: u64, // 使うたびに増やし、最後に使った時を Entry::used に記録する
+}
+
+#[derive(Debug)]
+struct Entry {
+    value: Value,
+    expires: Option<Instant>,
+    used: u64,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_ENTRIES)
+    }
+}
+
+impl Cache {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            max_entries,
+            clock: 0,
+        }
+    }
+
+    /// 保存できる数を変える (超えている分は使っていない順に消す)
+    pub fn set_max_entries(&mut self, max_entries: usize) {
+        self.max_entries = max_entries;
+        while self.entries.len() > max_entries {
+            self.evict();
+        }
+    }
+
+    /// 有効な値 (期限が切れていれば消して None)
+    pub fn get(&mut self, key: &str) -> Option<Value> {
+        let entry = self.entries.get_mut(key)?;
+        if entry
+            .expires
+            .is_some_and(|expires| Instant::now() >= expires)
+        {
+            self.entries.remove(key);
+            return None;
+        }
+        self.clock += 1;
+        entry.used = self.clock;
+        Some(entry.value.clone())
+    }
+
+    /// ttl が None なら期限なし
+    pub fn set(&mut self, key: String, value: Value, ttl: Option<Duration>) {
+        if self.max_entries == 0 {
+            return;
+        }
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.max_entries {
+            self.evict();
+        }
+        self.clock += 1;
+        let entry = Entry {
+            value,
+            expires: ttl.map(|ttl| Instant::now() + ttl),
+            used: self.clock,
+        };
+        self.entries.insert(key, entry);
+    }
+
+    pub fn remove(&mut self, key: &str) -> bool {
+        self.entries.remove(key).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// 期限切れの値があればそれを、なければ最も長く使っていない値を消す
+    fn evict(&mut self) {
+        let now = Instant::now();
+        let victim = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| (entry.expires.is_none_or(|e| e > now), entry.used))
+            .map(|(key, _)| key.clone());
+        if let Some(key) = victim {
+            self.entries.remove(&key);
+        }
+    }
+}
+
+/// cache.get_or(key, ttl, fn) / cache.get(key) / cache.set(key, value, ttl) / cache.delete(key) / cache.clear()
+pub fn call(
+    interpreter: &mut Interpreter,
+    function: &str,
+    args: Vec<Value>,
+) -> Result<Value, String> {
+    match (function, args.as_slice()) {
+        ("get_or", [key, ttl, compute @ (Value::Fn(..) | Value::BuiltinFn(_))]) => {
+            let (key, ttl) = (cache_key(key), parse_ttl(ttl)?);
+            if let Some(value) = interpreter.cache().get(&key) {
+                return Ok(value);
+            }
+            // 関数がエラーになったときは何も保存しない
+            let value = interpreter.call_function(compute.clone(), Vec::new())?;
+            interpreter.cache().set(key, value.clone(), ttl);
+            Ok(value)
+        }
+        ("get_or", _) => Err(
+            "cache.get_or() expects a key, a ttl in seconds (or none) and a function".to_string(),
+        ),
+        ("get", [key]) => Ok(interpreter
+            .cache()
+            .get(&cache_key(key))
+            .unwrap_or(Value::None)),
+        ("set", [key, value]) => {
+            interpreter.cache().set(cache_key(key), value.clone(), None);
+            Ok(Value::None)
+        }
+        ("set", [key, value, ttl]) => {
+            let ttl = parse_ttl(ttl)?;
+            interpreter.cache().set(cache_key(key), value.clone(), ttl);
+            Ok(Value::None)
+        }
+        ("delete", [key]) => Ok(Value::Bool(interpreter.cache().remove(&cache_key(key)))),
+        ("clear", []) => {
+            interpreter.cache().clear();
+            Ok(Value::None)
+        }
+        ("get" | "set" | "delete" | "clear", _) => Err(format!(
+            "cache.{}() got the wrong number of arguments",
+            function
+        )),
+        _ => Err(format!("Module 'cache' has no member '{}'", function)),
+    }
+}
+
+/// 文字列以外のキーは表示した文字列にする (["user", 1] など)
+fn cache_key(key: &Value) -> String {
+    match key {
+        Value::Str(s) => s.to_string(),
+        other => other.display(),
+    }
+}
+
+/// 有効期限 (秒数か 60s のような Duration、none なら期限なし)
+fn parse_ttl(ttl: &Value) -> Result<Option<Duration>, String> {
+    let seconds = match ttl {
+        Value::None => return Ok(None),
+        _ => crate::units::seconds(ttl).unwrap_or(f64::NAN),
+    };
+    if !(seconds.is_finite() && seconds > 0.0) {
+        return Err(format!(
+            "cache ttl must be a positive number of seconds or none, got {}",
+            ttl.display()
+        ));
+    }
+    Ok(Some(Duration::from_secs_f64(seconds)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn run(interpreter: &mut Interpreter, source: &str) -> Result<String, String> {
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        interpreter.run(&program).map(|value| value.display())
+    }
+
+    #[test]
+    fn test_cache_get_or() {
+        let mut interpreter = Interpreter::new();
+        let source = "let calls = []\ndef fetch\n    calls.append(1)\n    return len(calls) * 10\n[cache.get_or(\"rates\", 60, fetch), cache.get_or(\"rates\", 60, fetch), cache.get_or([\"user\", 1], none, fetch), len(calls)]\n";
+        assert_eq!(run(&mut interpreter, source).unwrap(), "[10, 10, 20, 2]");
+        // 別の実行からも同じキャッシュを使う (サーバーのリクエストをまたいで残る)
+        let source = "cache.set(\"a\", 1, 0.05)\n[cache.get(\"rates\"), cache.get(\"a\"), cache.delete(\"rates\"), cache.get(\"rates\")]\n";
+        assert_eq!(
+            run(&mut interpreter, source).unwrap(),
+            "[10, 1, true, none]"
+        );
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(run(&mut interpreter, "cache.get(\"a\")\n").unwrap(), "none");
+
+        // エラーになった関数の結果は保存しない
+        let source = "def broken\n    return 1 / 0\ncache.get_or(\"x\", 60, broken)\n";
+        assert_eq!(
+            run(&mut interpreter, source).unwrap_err(),
+            "Division by zero"
+        );
+        assert_eq!(run(&mut interpreter, "cache.get(\"x\")\n").unwrap(), "none");
+        assert!(run(&mut interpreter, "cache.set(\"x\", 1, -1)\n").is_err());
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let mut cache = Cache::new(2);
+        cache.set("a".to_string(), Value::Int(1), None);
+        cache.set("b".to_string(), Value::Int(2), None);
+        cache.get("a");
+        cache.set("c".to_string(), Value::Int(3), None);
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("a").is_some() && cache.get("c").is_some());
+        cache.set_max_entries(1);
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("c").is_some());
+    }
+}
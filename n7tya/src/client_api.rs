@@ -0,0 +1,142 @@
+//! island からサーバーのルートを呼ぶスタブ
+//!
+//! island のメソッドで `api.get_users()` と書くと、サーバーのルート表から生成した
+//! fetch の呼び出しになる。名前はメソッドとパスから作る
+//! (GET "/users" → get_users, POST "/users/new" → post_users_new, GET "/" → get_index)。
+//! ボディを受け取るルート (`post "/users" (user: User)`) は引数を1つ取り、JSON にして送る。
+//! 結果はレスポンスが JSON (`-> Json<T>` のルート) ならその値、それ以外は文字列になる。
+//! スタブの行はソースマップでルートを書いた .n7t の行に対応させる。
+
+use crate::ast::{Param, ServerBodyItem, ServerDef, Type};
+
+/// スタブを作るルート
+#[derive(Debug, Clone)]
+pub struct ApiRoute {
+    pub name: String,
+    pub method: String, // 大文字 (GET, POST ...)
+    pub path: String,
+    pub body: Option<Param>,
+    pub returns: Option<Type>,
+    pub file: String, // ルートを書いた .n7t ファイル (ソースマップに使う)
+    pub line: usize,
+}
+
+impl ApiRoute {
+    /// 呼び出しに渡す引数の数 (ボディがあれば1つ)
+    pub fn arity(&self) -> usize {
+        usize::from(self.body.is_some())
+    }
+}
+
+/// サーバーのルートをスタブ名で列挙する (servers は定義したファイルのパスとの組)
+///
+/// "/files/*" のようなパスが決まらないルートは呼び出し先にならないので除く。
+/// 同じ名前になるルートは先に書いたものを使う。
+pub fn api_routes(servers: &[(&str, &ServerDef)]) -> Vec<ApiRoute> {
+    let mut routes: Vec<ApiRoute> = Vec::new();
+    for (file, server) in servers {
+        for item in &server.body {
+            let ServerBodyItem::Route(route) = item else {
+                continue;
+            };
+            if route.path.contains('*') {
+                continue;
+            }
+            let name = stub_name(&route.method, &route.path);
+            if routes.iter().any(|r| r.name == name) {
+                continue;
+            }
+            routes.push(ApiRoute {
+                name,
+                method: route.method.to_ascii_uppercase(),
+                path: route.path.clone(),
+                body: route.body_param.clone(),
+                returns: route.return_type.clone(),
+                file: file.to_string(),
+                line: route.line,
+            });
+        }
+    }
+    routes
+}
+
+/// get "/users/active" -> get_users_active (英数字以外は _ にする)
+pub fn stub_name(method: &str, path: &str) -> String {
+    let segments: Vec<String> = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            s.chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect()
+        })
+        .collect();
+    let path = if segments.is_empty() {
+        "index".to_string()
+    } else {
+        segments.join("_")
+    };
+    format!("{}_{}", method.to_ascii_lowercase(), path)
+}
+
+/// スタブから呼ぶ fetch (island のクライアントコードの中に置き、この後に `api` の各スタブを並べる)
+pub const CLIENT_FETCH: &str = r#"  const $fetch = async (method, path, body) => {
+    const init = { method, headers: {} };
+    const token = document.querySelector('meta[name="csrf-token"]');
+    if (token) init.headers["X-CSRF-Token"] = token.content;
+    if (body !== undefined) {
+      init.headers["Content-Type"] = "application/json";
+      init.body = JSON.stringify(body);
+    }
+    const response = await fetch(path, init);
+    if (!response.ok) throw new Error(method + " " + path + " failed with status " + response.status);
+    const type = response.headers.get("Content-Type") || "";
+    return type.startsWith("application/json") ? response.json() : response.text();
+  };
+"#;
+
+/// `const api = { ... }` の中の1つのスタブ (シグネチャのコメントと定義の2行)
+pub fn client_stub(route: &ApiRoute) -> String {
+    let method = serde_json::Value::from(route.method.as_str());
+    let path = serde_json::Value::from(route.path.as_str());
+    let mut out = format!("    // {}\n", signature(route));
+    if route.body.is_some() {
+        out.push_str(&format!(
+            "    {}: (body) => $fetch({}, {}, body),\n",
+            route.name, method, path
+        ));
+    } else {
+        out.push_str(&format!(
+            "    {}: () => $fetch({}, {}),\n",
+            route.name, method, path
+        ));
+    }
+    out
+}
+
+/// POST /users (user: User) -> Json<User> (生成した JS のコメントに残す)
+fn signature(route: &ApiRoute) -> String {
+    let mut text = format!("{} {}", route.method, route.path);
+    if let Some(param) = &route.body {
+        match &param.type_annotation {
+            Some(ty) => text.push_str(&format!(" ({}: {})", param.name, ty)),
+            None => text.push_str(&format!(" ({})", param.name)),
+        }
+    }
+    if let Some(ty) = &route.returns {
+        text.push_str(&format!(" -> {}", ty));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stub_name() {
+        assert_eq!(stub_name("get", "/users"), "get_users");
+        assert_eq!(stub_name("post", "/users/new-item"), "post_users_new_item");
+        assert_eq!(stub_name("get", "/"), "get_index");
+    }
+}
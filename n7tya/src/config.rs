@@ -0,0 +1,142 @@
+//! アプリケーションの設定 (n7tya.toml か `n7tya run --config` のファイルの [server] と [config])
+//!
+//! [config] の値はプログラムから `config.get("api_url")` で読める。
+//! サーバーの実行中に SIGHUP を送ると、設定ファイルを読み直して [server] と [config] を
+//! 入れ替える (プロセスは再起動しない)。読み直しは次のリクエストを受け付けたときに行い、
+//! ファイルに誤りがあれば前の設定のまま動き続ける。[telemetry] も読み直す (crate::telemetry)。
+
+use crate::interpreter::{Interpreter, ServerOptions, Value};
+use crate::telemetry::TelemetryOptions;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+/// 設定ファイルから読んだ設定
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    pub server: ServerOptions,
+    /// [server.名前] のサーバーごとの設定
+    pub servers: HashMap<String, ServerOptions>,
+    pub values: HashMap<String, Value>,
+    /// [telemetry] (書いていなければトレースを送らない)
+    pub telemetry: Option<TelemetryOptions>,
+    /// [cache] max_entries (cache モジュールに保存できる数)
+    pub cache_entries: usize,
+}
+
+impl AppConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Self::from_toml(&content)
+    }
+
+    pub fn from_toml(content: &str) -> Result<Self, String> {
+        let server = ServerOptions::from_toml(content)?;
+        let servers = ServerOptions::sections_from_toml(content)?;
+        let telemetry = TelemetryOptions::from_toml(content)?;
+        let table: toml::Table = content
+            .parse()
+            .map_err(|e| format!("Invalid n7tya.toml: {}", e))?;
+        let values = match table.get("config") {
+            Some(toml::Value::Table(config)) => config
+                .iter()
+                .map(|(key, value)| (key.clone(), toml_to_value(value)))
+                .collect(),
+            Some(_) => return Err("[config] must be a table".to_string()),
+            None => HashMap::new(),
+        };
+        let cache_entries = match table.get("cache").map(|cache| cache.get("max_entries")) {
+            None | Some(None) => crate::cache::DEFAULT_MAX_ENTRIES,
+            Some(Some(value)) => value
+                .as_integer()
+                .and_then(|n| usize::try_from(n).ok())
+                .ok_or_else(|| {
+                    format!(
+                        "cache.max_entries must be a non-negative integer, got {}",
+                        value
+                    )
+                })?,
+        };
+        Ok(Self {
+            server,
+            servers,
+            values,
+            telemetry,
+            cache_entries,
+        })
+    }
+}
+
+/// config.get(key) / config.get(key, default)
+pub fn call(
+    interpreter: &mut Interpreter,
+    function: &str,
+    args: Vec<Value>,
+) -> Result<Value, String> {
+    match (function, args.as_slice()) {
+        ("get", [Value::Str(key)]) => Ok(interpreter.config_value(key).unwrap_or(Value::None)),
+        ("get", [Value::Str(key), default]) => Ok(interpreter
+            .config_value(key)
+            .unwrap_or_else(|| default.clone())),
+        ("get", _) => Err("config.get() expects a key and an optional default".to_string()),
+        _ => Err(format!("Module 'config' has no member '{}'", function)),
+    }
+}
+
+/// SIGHUP を受け取ると true になるフラグ (SIGHUP がない環境では変わらない)
+pub fn reload_signal() -> Arc<AtomicBool> {
+    let flag = Arc::new(AtomicBool::new(false));
+    #[cfg(unix)]
+    if let Err(e) = signal_hook::flag::register(signal_hook::consts::SIGHUP, flag.clone()) {
+        println!("Failed to listen for SIGHUP: {}", e);
+    }
+    flag
+}
+
+fn toml_to_value(value: &toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::Str(s.as_str().into()),
+        toml::Value::Integer(n) => Value::Int(*n),
+        toml::Value::Float(f) => Value::Float(*f),
+        toml::Value::Boolean(b) => Value::Bool(*b),
+        toml::Value::Datetime(d) => Value::Str(d.to_string().into()),
+        toml::Value::Array(items) => Value::List(Rc::new(RefCell::new(
+            items.iter().map(toml_to_value).collect(),
+        ))),
+        toml::Value::Table(table) => Value::Dict(Rc::new(RefCell::new(
+            table
+                .iter()
+                .map(|(key, value)| (key.clone(), toml_to_value(value)))
+                .collect(),
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_config_get() {
+        let config = AppConfig::from_toml(
+            "[server]\ncsrf = false\n[config]\napi_url = \"https://api.example.com\"\nlimits = { per_minute = 60 }\n",
+        )
+        .unwrap();
+        assert!(!config.server.csrf);
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_config(config);
+        let source = "[config.get(\"api_url\"), config.get(\"limits\")[\"per_minute\"], config.get(\"missing\", 3), config.get(\"missing\")]\n";
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        assert_eq!(
+            interpreter.run(&program).unwrap().display(),
+            "[https://api.example.com, 60, 3, none]"
+        );
+    }
+}
@@ -0,0 +1,248 @@
+//! トップレベルの const の初期値をパース時に評価する
+//!
+//! 初期値がリテラル・演算・それより前のグローバル定数・結果が環境によらない組み込み関数
+//! (len, str, min など) だけでできていれば、評価した値のリテラルに置き換える。
+//! 実行時には計算しなくてよく、`const TIMEOUT = 60 / 0` のような誤りは実行する前にわかる。
+//! クラス定数 (クラス本体の const) も同じように評価する。
+//! 関数の中の const や、関数呼び出しなどを含む初期値はそのまま実行時に評価する。
+
+use crate::ast::*;
+use crate::interpreter::{Interpreter, Value};
+use std::collections::HashSet;
+
+/// 引数が同じなら結果も同じ組み込み関数
+const PURE_BUILTINS: &[&str] = &[
+    "len",
+    "str",
+    "int",
+    "float",
+    "abs",
+    "min",
+    "max",
+    "sum",
+    "hex",
+    "bin",
+    "oct",
+    "bit_length",
+    "popcount",
+    "format",
+];
+
+/// 評価できる const の初期値をリテラルに置き換える (評価がエラーになった定数があればエラー)
+pub fn fold_constants(program: &mut Program) -> Result<(), String> {
+    // if や for の中も含めてトップレベルで定義している名前
+    let shadowed = crate::resolve::resolve(program)
+        .globals()
+        .map(|symbol| symbol.name.clone())
+        .collect();
+    let mut folder = Folder {
+        interpreter: None,
+        constants: HashSet::new(),
+        shadowed,
+    };
+    for item in &mut program.items {
+        match item {
+            Item::Statement(Statement::Const(decl)) => folder.fold(decl)?,
+            Item::ClassDef(class) => {
+                for item in &mut class.body {
+                    if let ClassBodyItem::Const(decl) = item {
+                        folder.fold_class_constant(&class.name, decl)?;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+struct Folder {
+    interpreter: Option<Interpreter>, // 評価したグローバル定数を定義してある (最初の評価で作る)
+    constants: HashSet<String>,       // 評価したグローバル定数
+    shadowed: HashSet<String>,        // プログラムが定義している名前 (組み込み関数を隠しうる)
+}
+
+impl Folder {
+    /// グローバル定数を評価して置き換え、あとの定数の初期値から使えるようにする
+    fn fold(&mut self, decl: &mut ConstDecl) -> Result<(), String> {
+        let Some(value) = self.evaluate(&decl.name.clone(), decl)? else {
+            return Ok(());
+        };
+        let interpreter = self.interpreter.get_or_insert_with(Interpreter::new);
+        interpreter.define_constant(&decl.name, value)?;
+        self.constants.insert(decl.name.clone());
+        Ok(())
+    }
+
+    /// クラス定数はクラスの中の名前なので、ほかの定数の初期値からは使えない
+    fn fold_class_constant(&mut self, class: &str, decl: &mut ConstDecl) -> Result<(), String> {
+        self.evaluate(&format!("{}.{}", class, decl.name), decl)?;
+        Ok(())
+    }
+
+    /// 初期値を評価してリテラルに置き換え、評価した値を返す (評価できる式でなければ None)
+    fn evaluate(&mut self, name: &str, decl: &mut ConstDecl) -> Result<Option<Value>, String> {
+        if !self.is_pure(&decl.value) {
+            return Ok(None);
+        }
+        let value = self
+            .interpreter
+            .get_or_insert_with(Interpreter::new)
+            .eval_constant(&decl.value)
+            .map_err(|e| format!("Invalid constant '{}': {}", name, e))?;
+        if let Some(literal) = to_literal(&value) {
+            decl.value = literal;
+        }
+        Ok(Some(value))
+    }
+
+    fn is_pure(&self, expr: &Expression) -> bool {
+        match expr {
+            Expression::Literal(Literal::List(items) | Literal::Set(items)) => {
+                items.iter().all(|item| self.is_pure(item))
+            }
+            Expression::Literal(Literal::Dict(pairs)) => pairs
+                .iter()
+                .all(|(key, value)| self.is_pure(key) && self.is_pure(value)),
+            Expression::Literal(_) => true,
+            Expression::Identifier(name) => self.constants.contains(name),
+            Expression::BinaryOp(bin) => self.is_pure(&bin.left) && self.is_pure(&bin.right),
+            Expression::UnaryOp(unary) => self.is_pure(&unary.operand),
+            Expression::Call(call) => {
+                matches!(&call.func, Expression::Identifier(name)
+                    if PURE_BUILTINS.contains(&name.as_str()) && !self.shadowed.contains(name))
+                    && call.args.iter().all(|arg| self.is_pure(arg))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// 値をそのまま書いたリテラル (関数などリテラルで書けない値は None)
+fn to_literal(value: &Value) -> Option<Expression> {
+    let literal = match value {
+        Value::Int(n) => Literal::Int(*n),
+        Value::Float(f) => Literal::Float(*f),
+        Value::Duration(d) => Literal::Duration(*d),
+        Value::Size(bytes) => Literal::Size(*bytes),
+        Value::Str(s) => Literal::Str(s.to_string()),
+        Value::Bool(b) => Literal::Bool(*b),
+        Value::None => Literal::None,
+        Value::List(items) => Literal::List(
+            items
+                .borrow()
+                .iter()
+                .map(to_literal)
+                .collect::<Option<_>>()?,
+        ),
+        Value::Set(items) => Literal::Set(
+            items
+                .borrow()
+                .iter()
+                .map(to_literal)
+                .collect::<Option<_>>()?,
+        ),
+        Value::Dict(map) => {
+            let map = map.borrow();
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            Literal::Dict(
+                keys.into_iter()
+                    .map(|key| {
+                        let key_literal = Expression::Literal(Literal::Str(key.clone()));
+                        Some((key_literal, to_literal(&map[key])?))
+                    })
+                    .collect::<Option<_>>()?,
+            )
+        }
+        _ => return None,
+    };
+    Some(Expression::Literal(literal))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use std::collections::HashMap;
+
+    /// 評価してリテラルにしたグローバル定数
+    fn folded(program: &Program) -> HashMap<String, String> {
+        program
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Statement(Statement::Const(ConstDecl {
+                    name,
+                    value: Expression::Literal(literal),
+                    ..
+                })) => Some((name.clone(), format!("{:?}", literal))),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn parse(source: &str) -> Result<Program, String> {
+        Parser::new(Lexer::new(source).tokenize())
+            .parse()
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        let source = "const MINUTES = 60\nconst TIMEOUT = MINUTES * 5 + len(\"abc\")\nconst NAME = \"app-\" + str(2)\nconst LIMITS = [min(3, 1), -MINUTES]\nconst STARTED = input()\nconst LATER = STARTED + \"!\"\nclass Config\n    const RETRIES = MINUTES / 20\n";
+        let program = parse(source).unwrap();
+        let constants = folded(&program);
+        assert_eq!(constants["TIMEOUT"], "Int(303)");
+        assert_eq!(constants["NAME"], "Str(\"app-2\")");
+        assert_eq!(
+            constants["LIMITS"],
+            "List([Literal(Int(1)), Literal(Int(-60))])"
+        );
+        // 入力などに依存する初期値と、それを使う初期値は実行時に評価する
+        assert!(!constants.contains_key("STARTED"));
+        assert!(!constants.contains_key("LATER"));
+        let Item::ClassDef(class) = &program.items[6] else {
+            panic!("expected a class");
+        };
+        assert!(matches!(
+            &class.body[0],
+            ClassBodyItem::Const(ConstDecl {
+                value: Expression::Literal(Literal::Int(3)),
+                ..
+            })
+        ));
+
+        // 同じ名前の関数を定義していれば組み込み関数とはみなさない
+        let program = parse("def len x\n    return 0\nconst N = len(\"abc\")\n").unwrap();
+        assert!(!folded(&program).contains_key("N"));
+
+        assert_eq!(
+            parse("const RATE = 60\nconst PER_SECOND = 1 / (RATE - 60)\n").unwrap_err(),
+            "Invalid constant 'PER_SECOND': Division by zero"
+        );
+        assert!(parse("class A\n    const B = \"x\" - 1\n")
+            .unwrap_err()
+            .starts_with("Invalid constant 'A.B'"));
+    }
+
+    #[test]
+    fn test_constants_cannot_change() {
+        let run = |source: &str| {
+            crate::interpreter::Interpreter::new()
+                .run(&parse(source).unwrap())
+                .map(|value| value.display())
+        };
+        assert_eq!(
+            run("const A = 1\nA = 2\n").unwrap_err(),
+            "Cannot assign to constant 'A'"
+        );
+        assert_eq!(
+            run("const A = 1\nlet A = 2\n").unwrap_err(),
+            "Cannot redefine constant 'A'"
+        );
+        let source = "const A = 1\ndef f\n    let A = 2\n    return A\nlet total = 0\nfor i in range(3)\n    const STEP = i * A\n    total = total + STEP\n[f(), A, total]\n";
+        assert_eq!(run(source).unwrap(), "[2, 1, 3]");
+    }
+}
@@ -0,0 +1,353 @@
+//! コンテンツコレクション (content/)
+//!
+//! content/ の下のディレクトリがそれぞれコレクションになり、プログラムから `content.posts` のように
+//! 中のファイルのリストとして読める (content/posts/*.md と *.json、下のディレクトリも含む)。
+//!
+//! - Markdown は先頭の `---` で囲んだ frontmatter (YAML) のキーと、`slug`、`body` (本文の Markdown)、
+//!   `html` (本文を HTML にしたもの) を持つ
+//! - JSON はオブジェクトのキーと `slug` を持つ
+//! - `slug` はコレクションのディレクトリからのパスから拡張子を除いたもの ("hello"、"guide/intro")。
+//!   frontmatter に slug を書けばそちらを使う
+//!
+//! 要素は `content.posts` という名前の構造体の値で、slug の順に並ぶ。フィールドの型はすべてのファイルの値から
+//! 決め、一部のファイルにしかないキーは none を含む型になる (そのキーのないファイルでは none)。
+//! 型チェックもこの型を使うので、`post.titel` のような誤りは実行する前に見つかる。
+//! ファイルはプログラムを実行する前に一度だけ読む。
+
+use crate::interpreter::Value;
+use crate::typechecker::TypeInfo;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+/// コンテンツを置くディレクトリ
+pub const CONTENT_DIR: &str = "content";
+
+/// Markdown のファイルに加えるフィールド (frontmatter には書けない)
+const MARKDOWN_FIELDS: [&str; 2] = ["body", "html"];
+
+/// content/ の下のコレクション
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Collections {
+    collections: BTreeMap<String, Collection>,
+}
+
+/// 1つのコレクション (content/posts)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Collection {
+    /// フィールドの名前と型 (どの要素もこの順にすべてのフィールドを持つ)
+    pub fields: Vec<(String, TypeInfo)>,
+    entries: Vec<serde_json::Map<String, serde_json::Value>>,
+}
+
+impl Collections {
+    /// ディレクトリの下のコレクションを読む (ディレクトリがなければ None)
+    pub fn load(dir: &Path) -> Result<Option<Self>, String> {
+        if !dir.is_dir() {
+            return Ok(None);
+        }
+        let mut collections = BTreeMap::new();
+        for entry in read_dir(dir)? {
+            if !entry.is_dir() {
+                continue;
+            }
+            let name = entry
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let identifier = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !identifier {
+                return Err(format!(
+                    "{}: collection names must be identifiers (letters, digits and _)",
+                    entry.display()
+                ));
+            }
+            let mut entries = Vec::new();
+            collect_entries(&entry, &entry, &mut entries)?;
+            entries.sort_by(|a, b| a["slug"].as_str().cmp(&b["slug"].as_str()));
+            collections.insert(
+                name,
+                Collection {
+                    fields: field_types(&entries),
+                    entries,
+                },
+            );
+        }
+        Ok(Some(Self { collections }))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Collection)> {
+        self.collections.iter()
+    }
+
+    /// `content` モジュールのメンバー (コレクションの名前 -> 要素のリスト)
+    pub fn values(&self) -> HashMap<String, Value> {
+        self.collections
+            .iter()
+            .map(|(name, collection)| {
+                let struct_name = format!("{}.{}", CONTENT_DIR, name);
+                let items = collection
+                    .entries
+                    .iter()
+                    .map(|entry| {
+                        let fields = collection
+                            .fields
+                            .iter()
+                            .map(|(field, _)| {
+                                let value = entry.get(field).cloned().unwrap_or_default();
+                                (field.clone(), crate::builtins::json_to_value(value))
+                            })
+                            .collect();
+                        Value::Struct(struct_name.clone(), Rc::new(RefCell::new(fields)))
+                    })
+                    .collect();
+                (name.clone(), Value::List(Rc::new(RefCell::new(items))))
+            })
+            .collect()
+    }
+}
+
+fn read_dir(dir: &Path) -> Result<Vec<std::path::PathBuf>, String> {
+    let mut paths = fs::read_dir(dir)
+        .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?
+        .map(|entry| {
+            entry
+                .map(|entry| entry.path())
+                .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.sort();
+    Ok(paths)
+}
+
+/// コレクションのディレクトリの下の .md と .json を読む (ほかのファイルは無視する)
+fn collect_entries(
+    root: &Path,
+    dir: &Path,
+    entries: &mut Vec<serde_json::Map<String, serde_json::Value>>,
+) -> Result<(), String> {
+    for path in read_dir(dir)? {
+        if path.is_dir() {
+            collect_entries(root, &path, entries)?;
+            continue;
+        }
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        if extension != "md" && extension != "json" {
+            continue;
+        }
+        let text = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let mut entry = if extension == "md" {
+            markdown_entry(&text)
+        } else {
+            match serde_json::from_str(&text) {
+                Ok(serde_json::Value::Object(object)) => Ok(object),
+                Ok(_) => Err("the file must contain a JSON object".to_string()),
+                Err(e) => Err(format!("Invalid JSON: {}", e)),
+            }
+        }
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        if !entry.contains_key("slug") {
+            let relative = path.strip_prefix(root).unwrap_or(&path).with_extension("");
+            let parts: Vec<String> = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            entry.insert("slug".to_string(), parts.join("/").into());
+        }
+        if !entry["slug"].is_string() {
+            return Err(format!("{}: slug must be a string", path.display()));
+        }
+        entries.push(entry);
+    }
+    Ok(())
+}
+
+/// frontmatter のキーと body、html
+fn markdown_entry(text: &str) -> Result<serde_json::Map<String, serde_json::Value>, String> {
+    let (frontmatter, body) = split_frontmatter(text);
+    let mut entry = match frontmatter {
+        Some(yaml) if !yaml.trim().is_empty() => {
+            match serde_yaml_ng::from_str(yaml)
+                .map_err(|e| format!("Invalid frontmatter: {}", e))?
+            {
+                serde_json::Value::Object(object) => object,
+                _ => return Err("the frontmatter must be a mapping of keys to values".to_string()),
+            }
+        }
+        _ => serde_json::Map::new(),
+    };
+    if let Some(field) = MARKDOWN_FIELDS.iter().find(|f| entry.contains_key(**f)) {
+        return Err(format!("'{}' cannot be set in the frontmatter", field));
+    }
+    let options = pulldown_cmark::Options::ENABLE_TABLES
+        | pulldown_cmark::Options::ENABLE_STRIKETHROUGH
+        | pulldown_cmark::Options::ENABLE_FOOTNOTES;
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, pulldown_cmark::Parser::new_ext(body, options));
+    entry.insert("body".to_string(), body.into());
+    entry.insert("html".to_string(), html.into());
+    Ok(entry)
+}
+
+/// 先頭の `---` の行から次の `---` の行までを frontmatter として分ける
+fn split_frontmatter(text: &str) -> (Option<&str>, &str) {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let Some(rest) = text
+        .strip_prefix("---\n")
+        .or_else(|| text.strip_prefix("---\r\n"))
+    else {
+        return (None, text);
+    };
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            return (Some(&rest[..offset]), &rest[offset + line.len()..]);
+        }
+        offset += line.len();
+    }
+    (None, text)
+}
+
+/// すべての要素のキーとその型 (slug を先頭に、Markdown の body と html を最後に、ほかは名前順)
+fn field_types(entries: &[serde_json::Map<String, serde_json::Value>]) -> Vec<(String, TypeInfo)> {
+    let mut names: Vec<&String> = entries.iter().flat_map(|entry| entry.keys()).collect();
+    names.sort_by_key(|name| {
+        let rank = match name.as_str() {
+            "slug" => 0,
+            "body" => 2,
+            "html" => 3,
+            _ => 1,
+        };
+        (rank, name.to_string())
+    });
+    names.dedup();
+    names
+        .into_iter()
+        .map(|name| {
+            // キーのないファイルがあれば none を最後に加える (Str | none)
+            let mut types: Vec<TypeInfo> = entries
+                .iter()
+                .filter_map(|entry| entry.get(name).map(json_type))
+                .collect();
+            if entries.iter().any(|entry| !entry.contains_key(name)) {
+                types.push(TypeInfo::None);
+            }
+            (name.clone(), TypeInfo::union(types))
+        })
+        .collect()
+}
+
+fn json_type(value: &serde_json::Value) -> TypeInfo {
+    match value {
+        serde_json::Value::Null => TypeInfo::None,
+        serde_json::Value::Bool(_) => TypeInfo::Bool,
+        serde_json::Value::Number(n) if n.is_i64() => TypeInfo::Int,
+        serde_json::Value::Number(_) => TypeInfo::Float,
+        serde_json::Value::String(_) => TypeInfo::Str,
+        serde_json::Value::Array(items) => {
+            // 要素の型がそろっていなければ要素の型は決めない
+            match TypeInfo::union(items.iter().map(json_type).collect()) {
+                ty if ty.is_concrete() => TypeInfo::List(Box::new(ty)),
+                _ => TypeInfo::List(Box::new(TypeInfo::Unknown)),
+            }
+        }
+        serde_json::Value::Object(_) => TypeInfo::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::typechecker::TypeChecker;
+
+    #[test]
+    fn test_content_collections() {
+        let dir = std::env::temp_dir().join(format!("n7tya-content-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("posts/guide")).unwrap();
+        fs::create_dir_all(dir.join("authors")).unwrap();
+        fs::write(
+            dir.join("posts/hello.md"),
+            "---\ntitle: Hello\ntags: [news, intro]\ndraft: false\n---\n# Hi\n\nFirst *post*.\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("posts/guide/setup.md"),
+            "---\ntitle: Setup\n---\nInstall it.\n",
+        )
+        .unwrap();
+        fs::write(dir.join("posts/notes.txt"), "ignored").unwrap();
+        fs::write(
+            dir.join("authors/taro.json"),
+            "{\"name\": \"Taro\", \"age\": 30}",
+        )
+        .unwrap();
+
+        let collections = Collections::load(&dir).unwrap().unwrap();
+        let (_, posts) = collections
+            .iter()
+            .find(|(name, _)| *name == "posts")
+            .unwrap();
+        let fields: Vec<(&str, &TypeInfo)> =
+            posts.fields.iter().map(|(n, t)| (n.as_str(), t)).collect();
+        assert_eq!(
+            fields,
+            vec![
+                ("slug", &TypeInfo::Str),
+                (
+                    "draft",
+                    &TypeInfo::union(vec![TypeInfo::Bool, TypeInfo::None])
+                ),
+                (
+                    "tags",
+                    &TypeInfo::union(vec![
+                        TypeInfo::List(Box::new(TypeInfo::Str)),
+                        TypeInfo::None
+                    ])
+                ),
+                ("title", &TypeInfo::Str),
+                ("body", &TypeInfo::Str),
+                ("html", &TypeInfo::Str),
+            ]
+        );
+
+        let run = |source: &str| {
+            let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+            let mut checker = TypeChecker::new();
+            checker.set_content(&collections);
+            let errors = checker.check(&program).unwrap();
+            let mut interpreter = Interpreter::new();
+            interpreter.set_content(&collections);
+            (errors, interpreter.run(&program).map(|v| v.display()))
+        };
+        let (errors, result) = run("let out = []\nfor post in content.posts\n    out.append([post.slug, post.title, post.draft])\nout.append(content.posts[1].html)\nout.append(content.authors[0].age)\nout\n");
+        assert_eq!(errors, Vec::<String>::new());
+        assert_eq!(
+            result.unwrap(),
+            "[[guide/setup, Setup, none], [hello, Hello, false], <h1>Hi</h1>\n<p>First <em>post</em>.</p>\n, 30]"
+        );
+        let (errors, _) = run("content.posts[0].titel\ncontent.pages\n");
+        assert_eq!(
+            errors,
+            vec![
+                "Struct 'content.posts' has no field 'titel'",
+                "Module 'content' has no member 'pages'"
+            ]
+        );
+
+        fs::write(dir.join("posts/bad.md"), "---\nhtml: x\n---\n").unwrap();
+        assert!(Collections::load(&dir)
+            .unwrap_err()
+            .contains("'html' cannot be set"));
+        assert_eq!(Collections::load(&dir.join("missing")).unwrap(), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+//! CSRF 対策
+//!
+//! セッションを使っている訪問者からの GET / HEAD / OPTIONS 以外のリクエストは、
+//! セッションごとに作ったトークンをフォームの `_csrf` フィールドか `X-CSRF-Token` ヘッダーで
+//! 送らないと 403 にする。Cookie のセッションがサーバーにない (state や flash、トークンを
+//! まだ保存していない) リクエストは、なりすまされても使われる権限がないので確かめない。
+//! フォームには `<input type="hidden" name="_csrf" value={csrf_token()} />` を書く。
+//! island の api スタブは render_page がページに埋め込んだ `<meta name="csrf-token">` を送る。
+//!
+//! `Content-Type: application/json` のリクエストは、ブラウザが別のサイトから送るには
+//! CORS のプリフライトが必要 (サーバーは許可しない) なので確かめない。
+//! n7tya.toml の `[server] csrf = false` で無効にできる。
+
+use crate::forms::{is_form_content_type, parse_urlencoded};
+use crate::interpreter::{Interpreter, Value};
+use std::collections::HashMap;
+
+/// トークンを送るフォームのフィールド名
+pub const FIELD: &str = "_csrf";
+/// トークンを送るヘッダー名 (小文字)
+pub const HEADER: &str = "x-csrf-token";
+/// セッションの中でトークンを保存するキー
+const SESSION_KEY: &str = "__csrf";
+
+/// 処理中のセッションのトークン (まだなければ作る)
+pub fn token(interpreter: &mut Interpreter) -> String {
+    let session = interpreter.session_data();
+    if let Some(Value::Str(token)) = session.get(SESSION_KEY) {
+        return token.to_string();
+    }
+    let token = crate::interpreter::new_session_id();
+    session.insert(SESSION_KEY.to_string(), Value::Str(token.as_str().into()));
+    token
+}
+
+/// トークンを確かめるリクエストか
+pub fn needs_check(
+    interpreter: &Interpreter,
+    method: &str,
+    headers: &HashMap<String, Value>,
+) -> bool {
+    if matches!(method, "GET" | "HEAD" | "OPTIONS") {
+        return false;
+    }
+    let json = matches!(headers.get("content-type"),
+        Some(Value::Str(t)) if t.starts_with("application/json"));
+    !json && interpreter.has_session()
+}
+
+/// ヘッダーかフォームのフィールドで、セッションのトークンが送られたか (セッションは作らない)
+pub fn verify(interpreter: &Interpreter, headers: &HashMap<String, Value>, body: &str) -> bool {
+    let Some(Value::Str(expected)) = interpreter.session_value(SESSION_KEY) else {
+        return false;
+    };
+    let sent = match headers.get(HEADER) {
+        Some(Value::Str(token)) => Some(token.to_string()),
+        _ => match headers.get("content-type") {
+            Some(Value::Str(t)) if is_form_content_type(t) => parse_urlencoded(body)
+                .into_iter()
+                .find(|(name, _)| name == FIELD)
+                .map(|(_, token)| token),
+            _ => None,
+        },
+    };
+    sent.is_some_and(|token| constant_time_eq(token.as_bytes(), expected.as_bytes()))
+}
+
+/// 一致しない位置によって時間が変わらない比較 (トークンを1文字ずつ推測されないように)
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn form(content_type: &str) -> HashMap<String, Value> {
+        HashMap::from([("content-type".to_string(), Value::Str(content_type.into()))])
+    }
+
+    #[test]
+    fn test_verify() {
+        let mut interpreter = Interpreter::new();
+        let issued = token(&mut interpreter);
+        assert_eq!(token(&mut interpreter), issued);
+
+        let headers = form("application/x-www-form-urlencoded");
+        assert!(needs_check(&interpreter, "POST", &headers));
+        assert!(!needs_check(&interpreter, "GET", &headers));
+        assert!(!needs_check(
+            &interpreter,
+            "POST",
+            &form("application/json")
+        ));
+
+        let body = format!("name=Ann&_csrf={}", issued);
+        assert!(verify(&interpreter, &headers, &body));
+        assert!(!verify(&interpreter, &headers, "name=Ann&_csrf=forged"));
+        assert!(!verify(
+            &interpreter,
+            &headers,
+            &format!("_csrf={}x", issued)
+        ));
+        assert!(!verify(&interpreter, &headers, "name=Ann"));
+
+        let headers = HashMap::from([(HEADER.to_string(), Value::Str(issued.as_str().into()))]);
+        assert!(verify(&interpreter, &headers, ""));
+    }
+
+    #[test]
+    fn test_no_session_is_not_checked() {
+        // セッションを使っていない訪問者のフォームや text/plain の POST は通す
+        let interpreter = Interpreter::new();
+        assert!(!needs_check(
+            &interpreter,
+            "POST",
+            &form("application/x-www-form-urlencoded")
+        ));
+        assert!(!needs_check(&interpreter, "POST", &form("text/plain")));
+        // 確かめてもセッションを作らない
+        assert!(!verify(&interpreter, &form("text/plain"), "_csrf="));
+        assert!(!interpreter.has_session());
+
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+    }
+}
@@ -0,0 +1,189 @@
+//! 外部の CSS ツール (Tailwind CSS, PostCSS など) との連携
+//!
+//! n7tya.toml の `[build] css` に書いたコマンドを n7tya build の途中で実行する。
+//! 実行する前に JSX の class 属性に書いたクラス名を dist/classes.txt に書き出すので、
+//! ユーティリティ CSS のツールはそれを読んで、使われているクラスの CSS だけを作れる。
+//! コマンドが public/ の下に書き出した CSS は render_page のページに `<link>` で読み込まれ、
+//! ほかの静的ファイルと同じく fingerprint が付く (assets)。
+
+use crate::assets::PUBLIC_DIR;
+use crate::ast::*;
+use crate::visit::{walk_expression, Visitor};
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::process::Command;
+
+/// JSX から集めたクラス名の書き出し先
+pub const CLASSES_FILE: &str = "dist/classes.txt";
+
+/// ビルドの設定 (n7tya.toml の [build])
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BuildOptions {
+    /// build で実行する CSS のコマンド
+    pub css: Option<String>,
+    /// コマンドが書き出す CSS のファイル (省略時はコマンドの -o / --output から読み取る)
+    pub css_output: Option<String>,
+}
+
+impl BuildOptions {
+    /// n7tya.toml の内容から [build] を読み込む
+    pub fn from_toml(content: &str) -> Result<Self, String> {
+        let table: toml::Table = content
+            .parse()
+            .map_err(|e| format!("Invalid n7tya.toml: {}", e))?;
+        let mut options = Self::default();
+
+        let Some(build) = table.get("build") else {
+            return Ok(options);
+        };
+        let build = build
+            .as_table()
+            .ok_or_else(|| "[build] must be a table".to_string())?;
+
+        for (key, value) in build {
+            let text = || {
+                value
+                    .as_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| format!("build.{} must be a string, got {}", key, value))
+            };
+            match key.as_str() {
+                "css" => options.css = Some(text()?),
+                "css_output" => options.css_output = Some(text()?),
+                _ => return Err(format!("Unknown build option: {}", key)),
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// CSS のコマンドが書き出すファイル
+    pub fn css_output(&self) -> Option<String> {
+        if self.css_output.is_some() {
+            return self.css_output.clone();
+        }
+        let mut args = self.css.as_deref()?.split_whitespace();
+        while let Some(arg) = args.next() {
+            if arg == "-o" || arg == "--output" {
+                return args.next().map(str::to_string);
+            }
+            if let Some(path) = arg.strip_prefix("--output=") {
+                return Some(path.to_string());
+            }
+        }
+        None
+    }
+
+    /// ページに読み込ませる CSS のパス (public/app.css なら /app.css)
+    ///
+    /// public/ の外に書き出す CSS はサーバーが配信しないので None。
+    pub fn stylesheet(&self) -> Option<String> {
+        let output = self.css_output()?;
+        let output = output.trim_start_matches("./");
+        let path = output.strip_prefix(PUBLIC_DIR)?.strip_prefix('/')?;
+        Some(format!("/{}", path))
+    }
+}
+
+/// CSS のコマンドをシェルで実行する (N7TYA_CLASSES にクラス名のファイルのパスを渡す)
+pub fn run_command(command: &str, classes_file: &Path) -> Result<(), String> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let status = shell
+        .arg(command)
+        .env("N7TYA_CLASSES", classes_file)
+        .status()
+        .map_err(|e| format!("Failed to run build.css: {}", e))?;
+    if !status.success() {
+        return Err(format!("build.css failed ({}): {}", status, command));
+    }
+    Ok(())
+}
+
+/// JSX の class / className 属性に書いた文字列のクラス名を集める
+///
+/// `class={"btn " + size}` のような式の中の文字列リテラルも含める。
+pub fn extract_classes(program: &Program, classes: &mut BTreeSet<String>) {
+    ClassNames {
+        classes,
+        in_class: false,
+    }
+    .visit_program(program);
+}
+
+struct ClassNames<'a> {
+    classes: &'a mut BTreeSet<String>,
+    /// class 属性の値の中か (そこにある文字列をクラス名として集める)
+    in_class: bool,
+}
+
+impl Visitor for ClassNames<'_> {
+    fn visit_expression(&mut self, expr: &Expression) {
+        if let Expression::Literal(Literal::Str(s)) = expr {
+            if self.in_class {
+                self.classes
+                    .extend(s.split_whitespace().map(str::to_string));
+            }
+        }
+        walk_expression(self, expr);
+    }
+
+    fn visit_jsx_element(&mut self, element: &JsxElement) {
+        let outer = self.in_class;
+        for attr in &element.attributes {
+            if let Some(value) = &attr.value {
+                self.in_class = attr.name == "class" || attr.name == "className";
+                self.visit_expression(value);
+            }
+        }
+        self.in_class = false;
+        for child in &element.children {
+            match child {
+                JsxChild::Element(child) => self.visit_jsx_element(child),
+                JsxChild::Expression(expr) => self.visit_expression(expr),
+                JsxChild::Text(_) => {}
+            }
+        }
+        self.in_class = outer;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_extract_classes() {
+        let source = "component Card\n\tstate active = true\n\trender\n\t\t<div class=\"p-4 rounded\">\n\t\t\t<p className={\"text-sm \" + \"font-bold\"} title=\"not-a-class\">x</p>\n\t\t</div>\n";
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let mut classes = BTreeSet::new();
+        extract_classes(&program, &mut classes);
+        let classes: Vec<&str> = classes.iter().map(String::as_str).collect();
+        assert_eq!(classes, vec!["font-bold", "p-4", "rounded", "text-sm"]);
+    }
+
+    #[test]
+    fn test_build_options() {
+        let options = BuildOptions::from_toml(
+            "[build]\ncss = \"npx tailwindcss -i src/app.css -o public/app.css --minify\"\n",
+        )
+        .unwrap();
+        assert_eq!(options.css_output().as_deref(), Some("public/app.css"));
+        assert_eq!(options.stylesheet().as_deref(), Some("/app.css"));
+        let options = BuildOptions::from_toml(
+            "[build]\ncss = \"postcss src/app.css\"\ncss_output = \"dist/app.css\"\n",
+        )
+        .unwrap();
+        assert_eq!(options.stylesheet(), None);
+        assert!(BuildOptions::from_toml("[build]\njs = \"x\"\n").is_err());
+    }
+}
@@ -0,0 +1,319 @@
+//! csv モジュール (CSV の読み書き)
+//!
+//! `csv.read("users.csv")` は1行目を見出しにして、各行を見出しをキーにした辞書のリストで返す。
+//! 値はすべて文字列のまま (数値にするには int() / float() を使う)。
+//! `csv.write("out.csv", rows)` は辞書・構造体・リストのリストを書き出す。辞書の列の順は
+//! オプションの "columns" で決め、書いていなければ最初の行のキーの名前順にする。
+//! どの関数も最後の引数に {"delimiter": ";", "quote": "'", "header": false, "columns": [...]} を渡せる。
+
+use crate::interpreter::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// csv.read(path[, options]) / csv.write(path, rows[, options]) / csv.parse(text[, options]) / csv.stringify(rows[, options])
+pub fn call(name: &str, args: Vec<Value>) -> Result<Value, String> {
+    match (name, args.as_slice()) {
+        ("read", [Value::Str(path), rest @ ..]) if rest.len() <= 1 => {
+            let options = Options::new("csv.read", rest.first())?;
+            let text = std::fs::read_to_string(&**path)
+                .map_err(|e| format!("Failed to read file '{}': {}", path, e))?;
+            parse(&text, &options)
+        }
+        ("parse", [Value::Str(text), rest @ ..]) if rest.len() <= 1 => {
+            parse(text, &Options::new("csv.parse", rest.first())?)
+        }
+        ("write", [Value::Str(path), rows, rest @ ..]) if rest.len() <= 1 => {
+            let text = stringify(rows, &Options::new("csv.write", rest.first())?)?;
+            std::fs::write(&**path, text)
+                .map_err(|e| format!("Failed to write file '{}': {}", path, e))?;
+            Ok(Value::None)
+        }
+        ("stringify", [rows, rest @ ..]) if rest.len() <= 1 => {
+            let text = stringify(rows, &Options::new("csv.stringify", rest.first())?)?;
+            Ok(Value::Str(text.into()))
+        }
+        ("read", _) => Err("csv.read() expects (path: Str, options?: Dict)".to_string()),
+        ("parse", _) => Err("csv.parse() expects (text: Str, options?: Dict)".to_string()),
+        ("write", _) => {
+            Err("csv.write() expects (path: Str, rows: List, options?: Dict)".to_string())
+        }
+        ("stringify", _) => Err("csv.stringify() expects (rows: List, options?: Dict)".to_string()),
+        _ => Err(format!("Module 'csv' has no member '{}'", name)),
+    }
+}
+
+/// 区切り文字などの設定 (省略時は "," と "\"" で、1行目を見出しにする)
+struct Options {
+    function: &'static str,
+    delimiter: u8,
+    quote: u8,
+    header: bool,
+    columns: Option<Vec<String>>,
+}
+
+impl Options {
+    fn new(function: &'static str, options: Option<&Value>) -> Result<Self, String> {
+        let mut result = Options {
+            function,
+            delimiter: b',',
+            quote: b'"',
+            header: true,
+            columns: None,
+        };
+        let options = match options {
+            None => return Ok(result),
+            Some(Value::Dict(options)) => options.borrow(),
+            Some(other) => {
+                return Err(format!(
+                    "{}() expects a dict of options, got {}",
+                    function,
+                    other.display()
+                ))
+            }
+        };
+        for (key, value) in options.iter() {
+            match (key.as_str(), value) {
+                ("delimiter", _) => result.delimiter = result.byte(key, value)?,
+                ("quote", _) => result.quote = result.byte(key, value)?,
+                ("header", Value::Bool(header)) => result.header = *header,
+                ("columns", Value::List(columns)) => {
+                    let columns = columns
+                        .borrow()
+                        .iter()
+                        .map(|column| match column {
+                            Value::Str(s) => Ok(s.to_string()),
+                            _ => Err(format!("{}(): columns must be strings", function)),
+                        })
+                        .collect::<Result<_, _>>()?;
+                    result.columns = Some(columns);
+                }
+                ("header" | "columns", _) => {
+                    return Err(format!(
+                        "{}(): invalid value for option '{}': {}",
+                        function,
+                        key,
+                        value.display()
+                    ))
+                }
+                _ => return Err(format!("{}(): unknown option '{}'", function, key)),
+            }
+        }
+        Ok(result)
+    }
+
+    /// 1文字 (ASCII) の文字列
+    fn byte(&self, key: &str, value: &Value) -> Result<u8, String> {
+        match value {
+            Value::Str(s) if s.len() == 1 && s.is_ascii() => Ok(s.as_bytes()[0]),
+            _ => Err(format!(
+                "{}(): option '{}' must be a single ASCII character, got {}",
+                self.function,
+                key,
+                value.display()
+            )),
+        }
+    }
+}
+
+/// 見出しがあれば辞書のリスト、なければ文字列のリストのリスト
+fn parse(text: &str, options: &Options) -> Result<Value, String> {
+    let mut reader = ::csv::ReaderBuilder::new()
+        .delimiter(options.delimiter)
+        .quote(options.quote)
+        .has_headers(false)
+        .from_reader(text.as_bytes());
+    let mut records = reader.records();
+    let header = match (&options.columns, options.header) {
+        (Some(columns), false) => Some(columns.clone()),
+        (_, false) => None,
+        (columns, true) => match records.next() {
+            Some(record) => {
+                let record = record.map_err(|e| format!("{}(): {}", options.function, e))?;
+                Some(
+                    columns
+                        .clone()
+                        .unwrap_or_else(|| record.iter().map(String::from).collect()),
+                )
+            }
+            None => return Ok(list(Vec::new())),
+        },
+    };
+    let mut rows = Vec::new();
+    for record in records {
+        let record = record.map_err(|e| format!("{}(): {}", options.function, e))?;
+        let row = match &header {
+            Some(header) => {
+                let fields = header
+                    .iter()
+                    .zip(record.iter())
+                    .map(|(key, value)| (key.clone(), Value::Str(value.into())))
+                    .collect();
+                Value::Dict(Rc::new(RefCell::new(fields)))
+            }
+            None => list(
+                record
+                    .iter()
+                    .map(|value| Value::Str(value.into()))
+                    .collect(),
+            ),
+        };
+        rows.push(row);
+    }
+    Ok(list(rows))
+}
+
+/// 辞書・構造体・リストのリストを CSV にする (none は空の欄、文字列以外は str() の表示)
+fn stringify(rows: &Value, options: &Options) -> Result<String, String> {
+    let Value::List(rows) = rows else {
+        return Err(format!(
+            "{}() expects a list of rows, got {}",
+            options.function,
+            rows.display()
+        ));
+    };
+    let rows = rows.borrow();
+    let columns = match (&options.columns, rows.first()) {
+        (Some(columns), _) => Some(columns.clone()),
+        (None, Some(Value::Dict(fields))) => {
+            let mut keys: Vec<String> = fields.borrow().keys().cloned().collect();
+            keys.sort();
+            Some(keys)
+        }
+        (None, Some(Value::Struct(_, fields))) => {
+            Some(fields.borrow().iter().map(|(k, _)| k.clone()).collect())
+        }
+        _ => None,
+    };
+    let mut writer = ::csv::WriterBuilder::new()
+        .delimiter(options.delimiter)
+        .quote(options.quote)
+        .flexible(true)
+        .from_writer(Vec::new());
+    let error = |e: ::csv::Error| format!("{}(): {}", options.function, e);
+    if let (Some(columns), true) = (&columns, options.header) {
+        writer.write_record(columns).map_err(error)?;
+    }
+    for (i, row) in rows.iter().enumerate() {
+        let cells: Vec<String> = match (row, &columns) {
+            (Value::List(items), _) => items.borrow().iter().map(cell).collect(),
+            (Value::Dict(fields), Some(columns)) => {
+                let fields = fields.borrow();
+                columns
+                    .iter()
+                    .map(|column| fields.get(column).map(cell).unwrap_or_default())
+                    .collect()
+            }
+            (Value::Struct(_, fields), Some(columns)) => {
+                let fields: HashMap<_, _> = fields.borrow().iter().cloned().collect();
+                columns
+                    .iter()
+                    .map(|column| fields.get(column).map(cell).unwrap_or_default())
+                    .collect()
+            }
+            _ => {
+                return Err(format!(
+                    "{}(): row {} must be a dict, a struct or a list, got {}",
+                    options.function,
+                    i + 1,
+                    row.display()
+                ))
+            }
+        };
+        writer.write_record(&cells).map_err(error)?;
+    }
+    let bytes = writer.into_inner().map_err(|e| e.to_string())?;
+    String::from_utf8(bytes).map_err(|e| e.to_string())
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::None => String::new(),
+        other => other.display(),
+    }
+}
+
+fn list(items: Vec<Value>) -> Value {
+    Value::List(Rc::new(RefCell::new(items)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::run;
+
+    #[test]
+    fn test_parse_with_header() {
+        let source = "let rows = csv.parse(\"name,age\\nann,30\\n\\\"Lee, B\\\",\\\"4\\\"\\\"2\\\"\\n\")\n[len(rows), rows[1][\"name\"], rows[1][\"age\"], int(rows[0][\"age\"])]\n";
+        assert_eq!(run(source).unwrap(), "[2, Lee, B, 4\"2, 30]");
+        assert_eq!(run("csv.parse(\"\")\n").unwrap(), "[]");
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let source = "csv.parse(\"a;'b;c'\\n1;2\\n\", {\"delimiter\": \";\", \"quote\": \"'\", \"header\": false})\n";
+        assert_eq!(run(source).unwrap(), "[[a, b;c], [1, 2]]");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        // 欄の数が違う行はエラー
+        assert!(run("csv.parse(\"a,b\\n1\\n\")\n")
+            .unwrap_err()
+            .contains("found record with 1 fields, but the previous record has 2 fields"));
+        assert_eq!(
+            run("csv.parse(\"a\", {\"delimiter\": \"::\"})\n").unwrap_err(),
+            "csv.parse(): option 'delimiter' must be a single ASCII character, got ::"
+        );
+        assert_eq!(
+            run("csv.parse(\"a\", {\"sep\": \";\"})\n").unwrap_err(),
+            "csv.parse(): unknown option 'sep'"
+        );
+        assert_eq!(
+            run("csv.parse(1)\n").unwrap_err(),
+            "csv.parse() expects (text: Str, options?: Dict)"
+        );
+    }
+
+    #[test]
+    fn test_stringify_dicts_and_structs() {
+        let source = "csv.stringify([{\"name\": \"ann\", \"age\": 30}, {\"name\": \"Lee, B\", \"age\": none}])\n";
+        assert_eq!(run(source).unwrap(), "age,name\n30,ann\n,\"Lee, B\"\n");
+
+        let source = "struct User\n\tname: Str\n\tage: Int\ncsv.stringify([User { name: \"ann\", age: 30 }], {\"delimiter\": \"\\t\"})\n";
+        assert_eq!(run(source).unwrap(), "name\tage\nann\t30\n");
+    }
+
+    #[test]
+    fn test_stringify_lists_with_columns() {
+        let source =
+            "csv.stringify([[1, \"a\"], [2, \"b\"]], {\"columns\": [\"id\", \"label\"]})\n";
+        assert_eq!(run(source).unwrap(), "id,label\n1,a\n2,b\n");
+    }
+
+    #[test]
+    fn test_stringify_errors() {
+        assert_eq!(
+            run("csv.stringify([1, 2])\n").unwrap_err(),
+            "csv.stringify(): row 1 must be a dict, a struct or a list, got 1"
+        );
+        assert_eq!(
+            run("csv.stringify([{\"a\": 1}], {\"columns\": \"a\"})\n").unwrap_err(),
+            "csv.stringify(): invalid value for option 'columns': a"
+        );
+    }
+
+    #[test]
+    fn test_read_and_write() {
+        let path = std::env::temp_dir().join(format!("n7tya_csv_{}.csv", std::process::id()));
+        let path = path.to_string_lossy().replace('\\', "/");
+        let source = format!(
+            "csv.write(\"{path}\", [{{\"id\": 1, \"note\": \"line\\nbreak\"}}], {{\"columns\": [\"note\", \"id\"]}})\nlet rows = csv.read(\"{path}\")\n[rows[0][\"id\"], rows[0][\"note\"] == \"line\\nbreak\"]\n"
+        );
+        let result = run(&source);
+        std::fs::remove_file(&path).ok();
+        assert_eq!(result.unwrap(), "[1, true]");
+        assert!(run("csv.read(\"/nonexistent/n7tya.csv\")\n")
+            .unwrap_err()
+            .starts_with("Failed to read file '/nonexistent/n7tya.csv'"));
+    }
+}
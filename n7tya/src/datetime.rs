@@ -0,0 +1,470 @@
+//! time モジュール (現在時刻・待機・日時の書式化と解析)
+//!
+//! 時刻は 1970-01-01T00:00:00Z からの秒数 (Float) で表すので、`ts + time.hours(2)` や
+//! `later - earlier` のように普通の数値として計算できる。日付との変換はすべて UTC で行う。
+
+use crate::interpreter::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// time.format / time.parse で書式を省いたときの書式 (ISO 8601)
+const ISO_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+/// 書式を省いた time.parse が順に試す書式
+const PARSE_FORMATS: &[&str] = &[
+    "%Y-%m-%dT%H:%M:%S%z",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%d",
+];
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+const WEEKDAYS: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+
+/// time.* の関数 (name は "now" など、"time." を除いた名前)
+pub fn call(name: &str, args: Vec<Value>) -> Result<Value, String> {
+    match (name, args.as_slice()) {
+        ("now", []) => {
+            let since_epoch = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            Ok(Value::Float(since_epoch.as_secs_f64()))
+        }
+        ("monotonic", []) => {
+            static STARTED: OnceLock<Instant> = OnceLock::new();
+            let started = STARTED.get_or_init(Instant::now);
+            Ok(Value::Float(started.elapsed().as_secs_f64()))
+        }
+        ("sleep", [seconds]) => match crate::units::seconds(seconds) {
+            Some(seconds) if seconds >= 0.0 && seconds.is_finite() => {
+                std::thread::sleep(Duration::from_secs_f64(seconds));
+                Ok(Value::None)
+            }
+            _ => Err("time.sleep() expects a non-negative number of seconds".to_string()),
+        },
+        ("seconds" | "minutes" | "hours" | "days" | "weeks", [n]) => {
+            let unit = match name {
+                "seconds" => 1.0,
+                "minutes" => 60.0,
+                "hours" => 3600.0,
+                "days" => SECONDS_PER_DAY as f64,
+                _ => 7.0 * SECONDS_PER_DAY as f64,
+            };
+            let n = number(n).ok_or_else(|| format!("time.{}() expects a number", name))?;
+            Ok(Value::Float(n * unit))
+        }
+        ("format", [ts]) => format(timestamp(name, ts)?, ISO_FORMAT).map(|s| Value::Str(s.into())),
+        ("format", [ts, Value::Str(fmt)]) => {
+            format(timestamp(name, ts)?, fmt).map(|s| Value::Str(s.into()))
+        }
+        ("parse", [Value::Str(text)]) => PARSE_FORMATS
+            .iter()
+            .find_map(|fmt| parse(text, fmt).ok())
+            .map(Value::Float)
+            .ok_or_else(|| format!("time.parse(): '{}' is not an ISO 8601 date", text)),
+        ("parse", [Value::Str(text), Value::Str(fmt)]) => parse(text, fmt).map(Value::Float),
+        ("date", args) if (3..=6).contains(&args.len()) => {
+            let mut fields = [0i64; 6];
+            for (field, arg) in fields.iter_mut().zip(args) {
+                let Value::Int(n) = arg else {
+                    return Err("time.date() expects integer fields".to_string());
+                };
+                *field = *n;
+            }
+            let [year, month, day, hour, minute, second] = fields;
+            to_timestamp(year, month, day, hour, minute, second as f64).map(Value::Float)
+        }
+        ("parts", [ts]) => Ok(parts(timestamp(name, ts)?)),
+        ("format", _) => {
+            Err("time.format() expects a timestamp and an optional format".to_string())
+        }
+        ("parse", _) => Err("time.parse() expects a string and an optional format".to_string()),
+        ("date", _) => Err(
+            "time.date() expects year, month, day and optional hour, minute, second".to_string(),
+        ),
+        _ => Err(format!("time.{}() got the wrong number of arguments", name)),
+    }
+}
+
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(n) => Some(*n as f64),
+        Value::Float(f) => Some(*f),
+        _ => None,
+    }
+}
+
+fn timestamp(name: &str, value: &Value) -> Result<f64, String> {
+    number(value)
+        .filter(|ts| ts.is_finite())
+        .ok_or_else(|| format!("time.{}() expects a timestamp (seconds since 1970)", name))
+}
+
+/// UTC の日時の各部分
+struct DateTime {
+    year: i64,
+    month: i64,  // 1〜12
+    day: i64,    // 1〜31
+    hour: i64,   // 0〜23
+    minute: i64, // 0〜59
+    second: i64, // 0〜59
+    micros: i64,
+    weekday: usize, // 月曜が 0
+    yearday: i64,   // 1月1日が 1
+}
+
+impl DateTime {
+    fn from_timestamp(ts: f64) -> Self {
+        let whole = ts.floor();
+        let micros = ((ts - whole) * 1_000_000.0).round() as i64;
+        // 丸めで 1 秒に繰り上がった分
+        let (seconds, micros) = (whole as i64 + micros / 1_000_000, micros % 1_000_000);
+        let days = seconds.div_euclid(SECONDS_PER_DAY);
+        let of_day = seconds.rem_euclid(SECONDS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        DateTime {
+            year,
+            month,
+            day,
+            hour: of_day / 3600,
+            minute: of_day % 3600 / 60,
+            second: of_day % 60,
+            micros,
+            weekday: (days + 3).rem_euclid(7) as usize,
+            yearday: days - days_from_civil(year, 1, 1) + 1,
+        }
+    }
+}
+
+/// 1970-01-01 からの日数を年月日にする (先発グレゴリオ暦)
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// 年月日を 1970-01-01 からの日数にする
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// UTC の日時を時刻にする (範囲外の値はエラー)
+fn to_timestamp(
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: f64,
+) -> Result<f64, String> {
+    let valid = (1..=12).contains(&month)
+        && (1..=days_in_month(year, month)).contains(&day)
+        && (0..24).contains(&hour)
+        && (0..60).contains(&minute)
+        && (0.0..60.0).contains(&second)
+        && (-9999..=9999).contains(&year);
+    if !valid {
+        return Err(format!(
+            "Invalid date: {:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            year, month, day, hour, minute, second as i64
+        ));
+    }
+    let seconds = days_from_civil(year, month, day) * SECONDS_PER_DAY + hour * 3600 + minute * 60;
+    Ok(seconds as f64 + second)
+}
+
+/// strftime と同じ書式で書く (%Y %m %d %H %M %S %f %j %a %A %b %B %s %z %Z %%)
+fn format(ts: f64, fmt: &str) -> Result<String, String> {
+    let t = DateTime::from_timestamp(ts);
+    let mut out = String::new();
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => out.push_str(&format!("{:04}", t.year)),
+            Some('m') => out.push_str(&format!("{:02}", t.month)),
+            Some('d') => out.push_str(&format!("{:02}", t.day)),
+            Some('H') => out.push_str(&format!("{:02}", t.hour)),
+            Some('M') => out.push_str(&format!("{:02}", t.minute)),
+            Some('S') => out.push_str(&format!("{:02}", t.second)),
+            Some('f') => out.push_str(&format!("{:06}", t.micros)),
+            Some('j') => out.push_str(&format!("{:03}", t.yearday)),
+            Some('a') => out.push_str(&WEEKDAYS[t.weekday][..3]),
+            Some('A') => out.push_str(WEEKDAYS[t.weekday]),
+            Some('b') => out.push_str(&MONTHS[t.month as usize - 1][..3]),
+            Some('B') => out.push_str(MONTHS[t.month as usize - 1]),
+            Some('s') => out.push_str(&ts.floor().to_string()),
+            Some('z') => out.push_str("+0000"),
+            Some('Z') => out.push_str("UTC"),
+            Some('%') => out.push('%'),
+            Some(other) => {
+                return Err(format!("time.format(): unknown directive '%{}'", other));
+            }
+            None => return Err("time.format(): format ends with '%'".to_string()),
+        }
+    }
+    Ok(out)
+}
+
+/// 書式どおりの文字列を時刻にする (%S は小数の秒も読み、%z は Z / +09:00 / +0900 を読む)
+fn parse(text: &str, fmt: &str) -> Result<f64, String> {
+    let mismatch = || format!("time.parse(): '{}' does not match format '{}'", text, fmt);
+    let mut input = Input { rest: text };
+    let (mut year, mut month, mut day) = (1970, 1, 1);
+    let (mut hour, mut minute, mut second, mut offset) = (0, 0, 0.0, 0);
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            if !input.literal(c) {
+                return Err(mismatch());
+            }
+            continue;
+        }
+        let ok = match chars.next() {
+            Some('Y') => input.digits(4).map(|n| year = n).is_some(),
+            Some('m') => input.digits(2).map(|n| month = n).is_some(),
+            Some('d') => input.digits(2).map(|n| day = n).is_some(),
+            Some('H') => input.digits(2).map(|n| hour = n).is_some(),
+            Some('M') => input.digits(2).map(|n| minute = n).is_some(),
+            Some('S') => input.seconds().map(|s| second = s).is_some(),
+            Some('f') => input.fraction().map(|f| second += f).is_some(),
+            Some('b' | 'B') => input.name(&MONTHS).map(|i| month = i as i64 + 1).is_some(),
+            Some('a' | 'A') => input.name(&WEEKDAYS).is_some(),
+            Some('z') => input.offset().map(|o| offset = o).is_some(),
+            Some('%') => input.literal('%'),
+            Some(other) => return Err(format!("time.parse(): unknown directive '%{}'", other)),
+            None => return Err("time.parse(): format ends with '%'".to_string()),
+        };
+        if !ok {
+            return Err(mismatch());
+        }
+    }
+    if !input.rest.is_empty() {
+        return Err(mismatch());
+    }
+    let ts = to_timestamp(year, month, day, hour, minute, second)
+        .map_err(|e| format!("time.parse(): {}", e))?;
+    Ok(ts - offset as f64)
+}
+
+/// time.parse で読んでいる残りの文字列
+struct Input<'a> {
+    rest: &'a str,
+}
+
+impl Input<'_> {
+    fn literal(&mut self, c: char) -> bool {
+        match self.rest.strip_prefix(c) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 1〜max 桁の数字
+    fn digits(&mut self, max: usize) -> Option<i64> {
+        let len = self
+            .rest
+            .bytes()
+            .take(max)
+            .take_while(u8::is_ascii_digit)
+            .count();
+        if len == 0 {
+            return None;
+        }
+        let n = self.rest[..len].parse().ok()?;
+        self.rest = &self.rest[len..];
+        Some(n)
+    }
+
+    /// 小数点のあとの数字 (".5" なら 0.5)
+    fn fraction(&mut self) -> Option<f64> {
+        let len = self.rest.bytes().take_while(u8::is_ascii_digit).count();
+        if len == 0 {
+            return None;
+        }
+        let fraction = format!("0.{}", &self.rest[..len]).parse().ok()?;
+        self.rest = &self.rest[len..];
+        Some(fraction)
+    }
+
+    fn seconds(&mut self) -> Option<f64> {
+        let whole = self.digits(2)? as f64;
+        if self.rest.starts_with('.') && self.rest[1..].starts_with(|c: char| c.is_ascii_digit()) {
+            self.rest = &self.rest[1..];
+            return Some(whole + self.fraction()?);
+        }
+        Some(whole)
+    }
+
+    /// 名前 (大文字小文字を区別せず、先頭 3 文字の略称でもよい) の添字
+    fn name(&mut self, names: &[&str]) -> Option<usize> {
+        let lower = self.rest.to_ascii_lowercase();
+        names.iter().enumerate().find_map(|(i, name)| {
+            let name = name.to_ascii_lowercase();
+            [name.len(), 3]
+                .into_iter()
+                .find(|len| lower.starts_with(&name[..*len]))
+                .map(|len| {
+                    self.rest = &self.rest[len..];
+                    i
+                })
+        })
+    }
+
+    /// UTC からのずれの秒数 (Z / +09:00 / -0500)
+    fn offset(&mut self) -> Option<i64> {
+        if self.literal('Z') {
+            return Some(0);
+        }
+        let sign = if self.literal('+') {
+            1
+        } else if self.literal('-') {
+            -1
+        } else {
+            return None;
+        };
+        let hours = self.digits(2)?;
+        self.literal(':');
+        let minutes = self.digits(2)?;
+        Some(sign * (hours * 3600 + minutes * 60))
+    }
+}
+
+/// {"year", "month", "day", "hour", "minute", "second", "weekday", "yearday"} (weekday は月曜が 0)
+fn parts(ts: f64) -> Value {
+    let t = DateTime::from_timestamp(ts);
+    let fields = [
+        ("year", t.year),
+        ("month", t.month),
+        ("day", t.day),
+        ("hour", t.hour),
+        ("minute", t.minute),
+        ("second", t.second),
+        ("weekday", t.weekday as i64),
+        ("yearday", t.yearday),
+    ];
+    let map: HashMap<String, Value> = fields
+        .into_iter()
+        .map(|(key, n)| (key.to_string(), Value::Int(n)))
+        .collect();
+    Value::Dict(Rc::new(RefCell::new(map)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::run;
+
+    #[test]
+    fn test_date_arithmetic_and_format() {
+        let source = "let ts = time.date(2024, 2, 28, 23, 30)\nlet later = ts + time.hours(1) + time.minutes(0.5)\n[ts, time.format(later), time.format(later, \"%a %d %b %Y %H:%M:%S.%f %j\"), time.parts(later)[\"weekday\"], time.format(-1)]\n";
+        assert_eq!(
+            run(source).unwrap(),
+            "[1709163000, 2024-02-29T00:30:30Z, Thu 29 Feb 2024 00:30:30.000000 060, 3, 1969-12-31T23:59:59Z]"
+        );
+    }
+
+    #[test]
+    fn test_date_and_format_errors() {
+        assert_eq!(
+            run("time.date(2023, 2, 29)\n").unwrap_err(),
+            "Invalid date: 2023-02-29 00:00:00"
+        );
+        assert_eq!(
+            run("time.format(0, \"%Q\")\n").unwrap_err(),
+            "time.format(): unknown directive '%Q'"
+        );
+        assert_eq!(
+            run("time.hours(\"a\")\n").unwrap_err(),
+            "time.hours() expects a number"
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        let source = "from time import parse\n[parse(\"2024-02-29T00:30:30Z\"), parse(\"2024-02-29T09:30:30.25+09:00\"), parse(\"2024-02-29\"), parse(\"29/February/2024\", \"%d/%B/%Y\")]\n";
+        assert_eq!(
+            run(source).unwrap(),
+            "[1709166630, 1709166630.25, 1709164800, 1709164800]"
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            run("time.parse(\"2023-02-29\")\n").unwrap_err(),
+            "time.parse(): '2023-02-29' is not an ISO 8601 date"
+        );
+        assert_eq!(
+            run("time.parse(\"2023-02-29\", \"%Y-%m-%d\")\n").unwrap_err(),
+            "time.parse(): Invalid date: 2023-02-29 00:00:00"
+        );
+        assert_eq!(
+            run("time.parse(\"12:00\", \"%H:%M:%S\")\n").unwrap_err(),
+            "time.parse(): '12:00' does not match format '%H:%M:%S'"
+        );
+    }
+
+    #[test]
+    fn test_clocks_and_sleep() {
+        let source = "let start = time.monotonic()\ntime.sleep(0.01)\n[time.monotonic() - start >= 0.01, time.now() > time.date(2024, 1, 1)]\n";
+        assert_eq!(run(source).unwrap(), "[true, true]");
+        assert_eq!(
+            run("time.sleep(-1)\n").unwrap_err(),
+            "time.sleep() expects a non-negative number of seconds"
+        );
+    }
+}
@@ -0,0 +1,238 @@
+//! Decimal (お金の計算のための10進数)
+//!
+//! `decimal("19.99")` で作り、Decimal どうしや Int との計算は誤差なく行う (Float と混ぜるとエラー)。
+//! 小数点以下の桁数は計算の結果に引き継ぐ (`decimal("1.10") * 3` は 3.30)。割り算は 28 桁までで丸める。
+//! `d.round(2)` は偶数への丸め、`d.round(2, "half_up")` のように丸め方を選べる。
+//! JSON では精度を落とさないように文字列にする (`json.stringify(value, {"decimal": "number"})` で数値)。
+
+use crate::ast::BinaryOp;
+use crate::interpreter::Value;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::cmp::Ordering;
+
+/// decimal(x): 文字列・Int・Float (表示どおりの値) から作る
+pub fn builtin(args: Vec<Value>) -> Result<Value, String> {
+    let [value] = args.as_slice() else {
+        return Err("decimal() takes exactly 1 argument".to_string());
+    };
+    let decimal = match value {
+        Value::Decimal(d) => *d,
+        Value::Int(n) => Decimal::from(*n),
+        Value::Str(s) => parse(s).ok_or_else(|| format!("Cannot convert '{}' to decimal", s))?,
+        Value::Float(f) => {
+            parse(&f.to_string()).ok_or_else(|| format!("Cannot convert {} to decimal", f))?
+        }
+        _ => {
+            return Err(format!(
+                "decimal() expects a string or a number, got {}",
+                value.display()
+            ))
+        }
+    };
+    Ok(Value::Decimal(decimal))
+}
+
+/// "19.99" "-0.5" "1e-3" (前後の空白は無視する)
+pub fn parse(text: &str) -> Option<Decimal> {
+    let text = text.trim();
+    Decimal::from_str_exact(text)
+        .ok()
+        .or_else(|| Decimal::from_scientific(text).ok())
+}
+
+/// 計算の相手にできる値 (Int は Decimal にする)
+fn operand(value: &Value) -> Option<Decimal> {
+    match value {
+        Value::Decimal(d) => Some(*d),
+        Value::Int(n) => Some(Decimal::from(*n)),
+        _ => None,
+    }
+}
+
+/// Decimal を含む算術演算と比較
+pub fn binary_op(op: &BinaryOp, left: &Value, right: &Value) -> Result<Value, String> {
+    let (Some(a), Some(b)) = (operand(left), operand(right)) else {
+        if matches!(left, Value::Float(_)) || matches!(right, Value::Float(_)) {
+            return Err("Cannot mix Decimal and Float (convert with decimal() first)".to_string());
+        }
+        return Err(format!(
+            "Unsupported operation: {} {} {}",
+            left.display(),
+            op,
+            right.display()
+        ));
+    };
+    let result = match op {
+        BinaryOp::Add => a.checked_add(b),
+        BinaryOp::Sub => a.checked_sub(b),
+        BinaryOp::Mul => a.checked_mul(b),
+        BinaryOp::Div if b.is_zero() => return Err("Division by zero".to_string()),
+        BinaryOp::Div => a.checked_div(b),
+        BinaryOp::Mod if b.is_zero() => return Err("Modulo by zero".to_string()),
+        BinaryOp::Mod => a.checked_rem(b),
+        BinaryOp::Lt => return Ok(Value::Bool(a < b)),
+        BinaryOp::Gt => return Ok(Value::Bool(a > b)),
+        BinaryOp::Le => return Ok(Value::Bool(a <= b)),
+        BinaryOp::Ge => return Ok(Value::Bool(a >= b)),
+        _ => {
+            return Err(format!(
+                "Unsupported operation: {} {} {}",
+                left.display(),
+                op,
+                right.display()
+            ))
+        }
+    };
+    result
+        .map(Value::Decimal)
+        .ok_or_else(|| "Decimal overflow".to_string())
+}
+
+/// == と並べ替え (Decimal どうしと、Decimal と Int)
+pub fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Decimal(_), _) | (_, Value::Decimal(_)) => Some(operand(a)?.cmp(&operand(b)?)),
+        _ => None,
+    }
+}
+
+/// d.round(places[, mode]) / d.to_float()
+pub fn method(decimal: Decimal, method: &str, args: &[Value]) -> Result<Value, String> {
+    match (method, args) {
+        ("round", rest) if rest.len() <= 2 => {
+            let places = match rest.first() {
+                None => 0,
+                Some(Value::Int(n)) if (0..=28).contains(n) => *n as u32,
+                Some(other) => {
+                    return Err(format!(
+                        "round() places must be an integer from 0 to 28, got {}",
+                        other.display()
+                    ))
+                }
+            };
+            let strategy = match rest.get(1) {
+                None => RoundingStrategy::MidpointNearestEven,
+                Some(Value::Str(mode)) => rounding(mode)?,
+                Some(other) => {
+                    return Err(format!(
+                        "round() mode must be a string, got {}",
+                        other.display()
+                    ))
+                }
+            };
+            Ok(Value::Decimal(
+                decimal.round_dp_with_strategy(places, strategy),
+            ))
+        }
+        ("to_float", []) => Ok(Value::Float(to_float(decimal))),
+        _ => Err(format!("'Decimal' has no method '{}'", method)),
+    }
+}
+
+/// 丸め方の名前
+fn rounding(mode: &str) -> Result<RoundingStrategy, String> {
+    Ok(match mode {
+        "half_even" => RoundingStrategy::MidpointNearestEven,
+        "half_up" => RoundingStrategy::MidpointAwayFromZero,
+        "half_down" => RoundingStrategy::MidpointTowardZero,
+        "up" => RoundingStrategy::AwayFromZero,
+        "down" => RoundingStrategy::ToZero,
+        "ceiling" => RoundingStrategy::ToPositiveInfinity,
+        "floor" => RoundingStrategy::ToNegativeInfinity,
+        _ => {
+            return Err(format!(
+                "Unknown rounding mode '{}' (expected half_even, half_up, half_down, up, down, ceiling or floor)",
+                mode
+            ))
+        }
+    })
+}
+
+pub fn to_float(decimal: Decimal) -> f64 {
+    decimal.to_f64().unwrap_or(f64::NAN)
+}
+
+/// sum() で Decimal を含むリスト (Int も足せる)
+pub fn sum(items: &[Value]) -> Result<Value, String> {
+    let mut total = Decimal::ZERO;
+    for item in items {
+        let value = operand(item)
+            .ok_or_else(|| format!("sum() cannot add {} to a Decimal", item.type_name()))?;
+        total = total
+            .checked_add(value)
+            .ok_or_else(|| "Decimal overflow".to_string())?;
+    }
+    Ok(Value::Decimal(total))
+}
+
+/// format() で精度を指定したとき (Float を通さずに丸める)
+pub fn format_fixed(decimal: Decimal, precision: usize) -> String {
+    let places = precision.min(28) as u32;
+    let rounded = decimal.round_dp_with_strategy(places, RoundingStrategy::MidpointNearestEven);
+    format!("{:.*}", precision, rounded)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::run;
+
+    #[test]
+    fn test_arithmetic() {
+        let source = "let price = decimal(\"0.10\")\n[price + decimal(\"0.20\") == decimal(\"0.3\"), price * 3, decimal(\"19.99\") - 20, decimal(1) / 3, decimal(\"10\") % 3, -price, price > 0, type(price)]\n";
+        assert_eq!(
+            run(source).unwrap(),
+            "[true, 0.30, -0.01, 0.3333333333333333333333333333, 1, -0.10, true, Decimal]"
+        );
+    }
+
+    #[test]
+    fn test_builtins_accept_decimals() {
+        let source = "[sum([decimal(\"0.1\"), decimal(\"0.2\"), 1]), sorted([decimal(\"2.5\"), decimal(1), decimal(\"-3\")]), decimal(0.1), decimal(\" 1e-3 \")]\n";
+        assert_eq!(run(source).unwrap(), "[1.3, [-3, 1, 2.5], 0.1, 0.001]");
+    }
+
+    #[test]
+    fn test_arithmetic_errors() {
+        assert_eq!(
+            run("decimal(\"1\") + 0.5\n").unwrap_err(),
+            "Cannot mix Decimal and Float (convert with decimal() first)"
+        );
+        assert_eq!(run("decimal(1) / 0\n").unwrap_err(), "Division by zero");
+        assert_eq!(
+            run("decimal(\"1.50\") * [2]\n").unwrap_err(),
+            "Unsupported operation: 1.50 * [2]"
+        );
+        assert_eq!(
+            run("decimal(\"abc\")\n").unwrap_err(),
+            "Cannot convert 'abc' to decimal"
+        );
+    }
+
+    #[test]
+    fn test_round_and_convert() {
+        let source = "let d = decimal(\"2.345\")\n[d.round(2), d.round(2, \"half_up\"), d.round(2, \"down\"), decimal(\"-2.5\").round(), decimal(\"-2.5\").round(0, \"floor\"), d.to_float(), float(d), int(d), str(d), abs(decimal(\"-1.50\"))]\n";
+        assert_eq!(
+            run(source).unwrap(),
+            "[2.34, 2.35, 2.34, -2, -3, 2.345, 2.345, 2, 2.345, 1.50]"
+        );
+        assert_eq!(
+            run("format(\"{:.2f} {:>8.1}\", decimal(\"1.005\"), decimal(\"12.25\"))\n").unwrap(),
+            "1.00     12.2"
+        );
+        assert_eq!(
+            run("decimal(1).round(2, \"nearest\")\n").unwrap_err(),
+            "Unknown rounding mode 'nearest' (expected half_even, half_up, half_down, up, down, ceiling or floor)"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let source = "let order = {\"total\": decimal(\"19.90\"), \"items\": [decimal(\"0.1\")]}\n[json.stringify(order[\"total\"]), json.stringify(order[\"items\"], {\"decimal\": \"number\"})]\n";
+        assert_eq!(run(source).unwrap(), "[\"19.90\", [0.1]]");
+        assert_eq!(
+            run("json.stringify(decimal(1), {\"decimal\": \"x\"})\n").unwrap_err(),
+            "json.stringify(): option 'decimal' must be \"string\" or \"number\", got x"
+        );
+    }
+}
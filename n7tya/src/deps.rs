@@ -0,0 +1,630 @@
+//! 依存パッケージの管理 (n7tya add / n7tya install)
+//!
+//! n7tya.toml の [dependencies] を読み、git またはローカルのパスのパッケージを
+//! 解決して n7tya.lock に記録する。git のパッケージは .n7tya/packages/ に取得する。
+//! パッケージ名で import すると、そのパッケージの src/lib.n7t が読み込まれる。
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+pub const LOCKFILE: &str = "n7tya.lock";
+
+/// git のパッケージを置くディレクトリ (プロジェクトからの相対パス)
+pub const PACKAGE_CACHE: &str = ".n7tya/packages";
+
+/// パッケージの取得元
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// ローカルのディレクトリ (n7tya.toml のあるディレクトリからの相対パス)
+    Path(PathBuf),
+    /// git リポジトリ (rev はブランチ・タグ・コミット。省略するとデフォルトブランチ)
+    Git { url: String, rev: Option<String> },
+}
+
+impl Source {
+    /// n7tya add の引数から取得元を推測する (URL なら git、それ以外はパス)
+    pub fn from_arg(arg: &str, rev: Option<String>) -> Self {
+        if arg.contains("://") || arg.starts_with("git@") || arg.ends_with(".git") {
+            Source::Git {
+                url: arg.to_string(),
+                rev,
+            }
+        } else {
+            Source::Path(PathBuf::from(arg))
+        }
+    }
+
+    /// パッケージ名の既定値 (URL やパスの最後の部分)
+    pub fn default_name(&self) -> String {
+        let last = match self {
+            Source::Path(path) => path
+                .file_name()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default()
+                .to_string(),
+            Source::Git { url, .. } => url
+                .trim_end_matches('/')
+                .rsplit(['/', ':'])
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        };
+        last.trim_end_matches(".git").replace('-', "_")
+    }
+
+    fn to_inline_table(&self) -> String {
+        match self {
+            Source::Path(path) => format!("{{ path = {} }}", quote(&path.to_string_lossy())),
+            Source::Git { url, rev: None } => format!("{{ git = {} }}", quote(url)),
+            Source::Git {
+                url,
+                rev: Some(rev),
+            } => format!("{{ git = {}, rev = {} }}", quote(url), quote(rev)),
+        }
+    }
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Path(path) => write!(f, "{}", path.display()),
+            Source::Git { url, rev: None } => write!(f, "{}", url),
+            Source::Git {
+                url,
+                rev: Some(rev),
+            } => write!(f, "{}#{}", url, rev),
+        }
+    }
+}
+
+/// n7tya.toml の [dependencies] の1項目
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dependency {
+    pub name: String,
+    pub source: Source,
+}
+
+/// n7tya.lock の1項目 (git なら取得したコミットも記録する)
+#[derive(Debug, Clone, PartialEq)]
+pub struct LockedPackage {
+    pub name: String,
+    pub source: Source,
+    pub commit: Option<String>,
+}
+
+/// n7tya.toml の [dependencies] を読む
+///
+/// ```toml
+/// [dependencies]
+/// strings = { git = "https://github.com/n7tya/strings", rev = "v1.0" }
+/// shared = { path = "../shared" }
+/// ```
+pub fn parse_manifest(content: &str) -> Result<Vec<Dependency>, String> {
+    let table: toml::Table = content
+        .parse()
+        .map_err(|e| format!("Invalid n7tya.toml: {}", e))?;
+    let Some(deps) = table.get("dependencies") else {
+        return Ok(Vec::new());
+    };
+    let deps = deps
+        .as_table()
+        .ok_or_else(|| "[dependencies] must be a table".to_string())?;
+
+    let mut result = Vec::new();
+    for (name, value) in deps {
+        check_name(name)?;
+        let entry = value.as_table();
+        let field = |key: &str| {
+            entry
+                .and_then(|t| t.get(key))
+                .and_then(toml::Value::as_str)
+                .map(str::to_string)
+        };
+        let source = match (field("path"), field("git")) {
+            (Some(path), None) => Source::Path(PathBuf::from(path)),
+            (None, Some(url)) => Source::Git {
+                url,
+                rev: field("rev"),
+            },
+            _ => {
+                return Err(format!(
+                    "dependencies.{} must be {{ path = \"...\" }} or {{ git = \"...\" }}",
+                    name
+                ))
+            }
+        };
+        result.push(Dependency {
+            name: name.clone(),
+            source,
+        });
+    }
+    Ok(result)
+}
+
+/// パッケージ名は import で書けるように識別子に限る
+fn check_name(name: &str) -> Result<(), String> {
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid package name '{}'", name))
+    }
+}
+
+/// n7tya.toml に依存を追加する (同じ名前があれば置き換える)
+///
+/// コメントや他のセクションを残すため、テキストのまま [dependencies] に1行足す
+pub fn add_to_manifest(content: &str, dep: &Dependency) -> Result<String, String> {
+    check_name(&dep.name)?;
+    let line = format!("{} = {}", dep.name, dep.source.to_inline_table());
+
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    let section = lines.iter().position(|l| l.trim() == "[dependencies]");
+    match section {
+        Some(start) => {
+            let end = lines[start + 1..]
+                .iter()
+                .position(|l| l.trim_start().starts_with('['))
+                .map_or(lines.len(), |i| start + 1 + i);
+            let existing = (start + 1..end).find(|&i| {
+                lines[i]
+                    .split_once('=')
+                    .is_some_and(|(key, _)| key.trim() == dep.name)
+            });
+            match existing {
+                Some(i) => lines[i] = line,
+                None => {
+                    // セクション末尾の空行より前に入れる
+                    let mut at = end;
+                    while at > start + 1 && lines[at - 1].trim().is_empty() {
+                        at -= 1;
+                    }
+                    lines.insert(at, line);
+                }
+            }
+        }
+        None => {
+            if lines.last().is_some_and(|l| !l.trim().is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push("[dependencies]".to_string());
+            lines.push(line);
+        }
+    }
+
+    let updated = format!("{}\n", lines.join("\n"));
+    // 書き込む前に読み直せることを確かめる
+    parse_manifest(&updated)?;
+    Ok(updated)
+}
+
+/// n7tya.lock を読む
+pub fn parse_lockfile(content: &str) -> Result<Vec<LockedPackage>, String> {
+    let table: toml::Table = content
+        .parse()
+        .map_err(|e| format!("Invalid {}: {}", LOCKFILE, e))?;
+    let Some(packages) = table.get("package").and_then(toml::Value::as_array) else {
+        return Ok(Vec::new());
+    };
+
+    let mut result = Vec::new();
+    for package in packages {
+        let field = |key: &str| {
+            package
+                .get(key)
+                .and_then(toml::Value::as_str)
+                .map(str::to_string)
+        };
+        let name =
+            field("name").ok_or_else(|| format!("Invalid {}: package without a name", LOCKFILE))?;
+        let source = match (field("path"), field("git")) {
+            (Some(path), _) => Source::Path(PathBuf::from(path)),
+            (None, Some(url)) => Source::Git {
+                url,
+                rev: field("rev"),
+            },
+            (None, None) => {
+                return Err(format!(
+                    "Invalid {}: package '{}' has no source",
+                    LOCKFILE, name
+                ))
+            }
+        };
+        result.push(LockedPackage {
+            name,
+            source,
+            commit: field("commit"),
+        });
+    }
+    Ok(result)
+}
+
+/// n7tya.lock の内容を作る
+pub fn format_lockfile(packages: &[LockedPackage]) -> String {
+    let mut out = String::from("# n7tya が生成するファイルです。手で編集しないでください。\n");
+    for package in packages {
+        out.push_str(&format!("\n[[package]]\nname = {}\n", quote(&package.name)));
+        match &package.source {
+            Source::Path(path) => {
+                out.push_str(&format!("path = {}\n", quote(&path.to_string_lossy())))
+            }
+            Source::Git { url, rev } => {
+                out.push_str(&format!("git = {}\n", quote(url)));
+                if let Some(rev) = rev {
+                    out.push_str(&format!("rev = {}\n", quote(rev)));
+                }
+            }
+        }
+        if let Some(commit) = &package.commit {
+            out.push_str(&format!("commit = {}\n", quote(commit)));
+        }
+    }
+    out
+}
+
+/// n7tya.toml の依存を (依存の依存も含めて) 取得し、n7tya.lock を書き直す (名前順)
+///
+/// n7tya.lock にある git のパッケージは、取得元が同じなら記録したコミットを使う
+pub fn install(project_dir: &Path) -> Result<Vec<LockedPackage>, String> {
+    let manifest = read_manifest(project_dir)?;
+    let locked = match fs::read_to_string(project_dir.join(LOCKFILE)) {
+        Ok(content) => parse_lockfile(&content)?,
+        Err(_) => Vec::new(),
+    };
+
+    let mut resolved: Vec<LockedPackage> = Vec::new();
+    // 依存と、それを書いたパッケージのディレクトリ・git のパッケージならその名前
+    let mut pending: Vec<(Dependency, PathBuf, Option<String>)> = manifest
+        .into_iter()
+        .map(|dep| (dep, PathBuf::new(), None))
+        .collect();
+    pending.reverse();
+
+    while let Some((dep, base, git_parent)) = pending.pop() {
+        // git のパッケージが書いたパスは、取得したパッケージの中だけを指せる
+        if let (Source::Path(path), Some(parent)) = (&dep.source, &git_parent) {
+            let inside = path
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+            if !inside {
+                return Err(format!(
+                    "Package '{}' of git package '{}' points outside it: {}",
+                    dep.name,
+                    parent,
+                    path.display()
+                ));
+            }
+        }
+        // パッケージの依存のパスはそのパッケージからの相対パス
+        let source = match dep.source {
+            Source::Path(path) if path.is_relative() => Source::Path(base.join(path)),
+            source => source,
+        };
+        if let Some(existing) = resolved.iter().find(|p| p.name == dep.name) {
+            if existing.source != source {
+                return Err(format!(
+                    "Package '{}' is required from both {} and {}",
+                    dep.name, existing.source, source
+                ));
+            }
+            continue;
+        }
+
+        let package = match &source {
+            Source::Path(path) => {
+                if !project_dir.join(path).is_dir() {
+                    return Err(format!(
+                        "Package '{}' not found at {}",
+                        dep.name,
+                        path.display()
+                    ));
+                }
+                LockedPackage {
+                    name: dep.name.clone(),
+                    source: source.clone(),
+                    commit: None,
+                }
+            }
+            Source::Git { url, rev } => {
+                let pinned = locked
+                    .iter()
+                    .find(|p| p.name == dep.name && p.source == source)
+                    .and_then(|p| p.commit.clone());
+                let dest = project_dir.join(PACKAGE_CACHE).join(&dep.name);
+                let commit = fetch_git(url, pinned.as_deref().or(rev.as_deref()), &dest)?;
+                LockedPackage {
+                    name: dep.name.clone(),
+                    source: source.clone(),
+                    commit: Some(commit),
+                }
+            }
+        };
+
+        let root = package_dir(project_dir, &package);
+        let (relative_root, git_parent) = match &package.source {
+            Source::Path(path) => (path.clone(), git_parent),
+            Source::Git { .. } => (
+                Path::new(PACKAGE_CACHE).join(&package.name),
+                Some(package.name.clone()),
+            ),
+        };
+        if let Ok(nested) = read_manifest(&root) {
+            for nested_dep in nested.into_iter().rev() {
+                pending.push((nested_dep, relative_root.clone(), git_parent.clone()));
+            }
+        }
+        resolved.push(package);
+    }
+
+    resolved.sort_by(|a, b| a.name.cmp(&b.name));
+    fs::write(project_dir.join(LOCKFILE), format_lockfile(&resolved))
+        .map_err(|e| format!("Failed to write {}: {}", LOCKFILE, e))?;
+    Ok(resolved)
+}
+
+/// n7tya.lock に記録されたパッケージ名とそのディレクトリ (import の解決に使う)
+///
+/// n7tya.lock がなければ空。git のパッケージが未取得ならエラーにする
+pub fn package_roots(project_dir: &Path) -> Result<HashMap<String, PathBuf>, String> {
+    let content = match fs::read_to_string(project_dir.join(LOCKFILE)) {
+        Ok(content) => content,
+        Err(_) => return Ok(HashMap::new()),
+    };
+    let mut roots = HashMap::new();
+    for package in parse_lockfile(&content)? {
+        let dir = package_dir(project_dir, &package);
+        if !dir.is_dir() {
+            return Err(format!(
+                "Package '{}' is not installed. Run 'n7tya install'",
+                package.name
+            ));
+        }
+        roots.insert(package.name, dir);
+    }
+    Ok(roots)
+}
+
+fn package_dir(project_dir: &Path, package: &LockedPackage) -> PathBuf {
+    match &package.source {
+        Source::Path(path) => project_dir.join(path),
+        Source::Git { .. } => project_dir.join(PACKAGE_CACHE).join(&package.name),
+    }
+}
+
+fn read_manifest(dir: &Path) -> Result<Vec<Dependency>, String> {
+    let content = fs::read_to_string(dir.join("n7tya.toml"))
+        .map_err(|e| format!("Failed to read n7tya.toml: {}", e))?;
+    parse_manifest(&content)
+}
+
+/// リポジトリを dest に取得して rev (省略時はデフォルトブランチ) に合わせ、コミットを返す
+///
+/// `-` で始まる URL や rev は git のオプションとして読まれるので受け付けない
+fn fetch_git(url: &str, rev: Option<&str>, dest: &Path) -> Result<String, String> {
+    if url.starts_with('-') {
+        return Err(format!("Invalid git URL '{}'", url));
+    }
+    if let Some(rev) = rev.filter(|rev| rev.starts_with('-')) {
+        return Err(format!("Invalid git rev '{}'", rev));
+    }
+    let dest_str = dest.to_string_lossy();
+    if dest.join(".git").is_dir() {
+        git(&["-C", &dest_str, "fetch", "--quiet", "--tags", "origin"])?;
+    } else {
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        git(&["clone", "--quiet", "--", url, &dest_str])?;
+    }
+
+    let target = match rev {
+        Some(rev) => rev.to_string(),
+        None => "origin/HEAD".to_string(),
+    };
+    // ブランチ名なら取得し直したリモートの先頭を使う
+    let remote_branch = format!("origin/{}", target);
+    let checkout = if git(&[
+        "-C",
+        &dest_str,
+        "rev-parse",
+        "--verify",
+        "--quiet",
+        &remote_branch,
+    ])
+    .is_ok()
+    {
+        remote_branch
+    } else {
+        target
+    };
+    // rev の後の -- で、同じ名前のファイルではなく rev として読ませる
+    git(&[
+        "-C", &dest_str, "checkout", "--quiet", "--detach", &checkout, "--",
+    ])?;
+    git(&["-C", &dest_str, "rev-parse", "HEAD"])
+}
+
+fn git(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to run git (is it installed?): {}", e))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        Err(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// TOML の基本文字列として書く
+fn quote(s: &str) -> String {
+    toml::Value::String(s.to_string()).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{Interpreter, Value};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_add_to_manifest() {
+        let manifest = "[package]\nname = \"app\"\n\n[dependencies]\n\n[server]\nport = 8080\n";
+        let dep = Dependency {
+            name: "strings".to_string(),
+            source: Source::from_arg("https://github.com/n7tya/strings.git", None),
+        };
+        let updated = add_to_manifest(manifest, &dep).unwrap();
+        assert_eq!(
+            updated,
+            "[package]\nname = \"app\"\n\n[dependencies]\nstrings = { git = \"https://github.com/n7tya/strings.git\" }\n\n[server]\nport = 8080\n"
+        );
+
+        // 同じ名前は置き換える
+        let dep = Dependency {
+            name: "strings".to_string(),
+            source: Source::from_arg("../strings", None),
+        };
+        let updated = add_to_manifest(&updated, &dep).unwrap();
+        assert_eq!(parse_manifest(&updated).unwrap(), vec![dep]);
+
+        assert_eq!(
+            Source::from_arg("git@github.com:n7tya/http-utils.git", None).default_name(),
+            "http_utils"
+        );
+        assert!(parse_manifest("[dependencies]\nx = \"1.0\"\n").is_err());
+    }
+
+    #[test]
+    fn test_install_path_dependencies() {
+        let dir = std::env::temp_dir().join(format!("n7tya-deps-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (path, content) in [
+            (
+                "app/n7tya.toml",
+                "[dependencies]\nshared = { path = \"../shared\" }\n",
+            ),
+            (
+                "shared/n7tya.toml",
+                "[dependencies]\nbase = { path = \"../base\" }\n",
+            ),
+            ("base/src/lib.n7t", "let answer = 40\n"),
+            ("base/src/extra.n7t", "let more = 2\n"),
+        ] {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        let app = dir.join("app");
+
+        let packages = install(&app).unwrap();
+        let names: Vec<&str> = packages.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["base", "shared"]);
+        assert_eq!(
+            packages[0].source,
+            Source::Path(PathBuf::from("../shared/../base"))
+        );
+
+        let lock = fs::read_to_string(app.join(LOCKFILE)).unwrap();
+        assert_eq!(parse_lockfile(&lock).unwrap(), packages);
+
+        // パッケージ名で import できる
+        let source = "import base\nimport \"base/extra\"\nbase.answer + extra.more\n";
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_packages(package_roots(&app).unwrap());
+        assert!(matches!(interpreter.run(&program), Ok(Value::Int(42))));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_install_git_dependencies_safely() {
+        let dir = std::env::temp_dir().join(format!("n7tya-deps-git-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (path, content) in [
+            (
+                "good/n7tya.toml",
+                "[dependencies]\ninner = { path = \"vendor/inner\" }\n",
+            ),
+            ("good/src/lib.n7t", "let answer = 42\n"),
+            ("good/vendor/inner/src/lib.n7t", "let inner = 1\n"),
+            (
+                "evil/n7tya.toml",
+                "[dependencies]\nsecret = { path = \"../../..\" }\n",
+            ),
+            ("evil/src/lib.n7t", "let x = 1\n"),
+        ] {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        for repo in ["good", "evil"] {
+            let repo = dir.join(repo).display().to_string();
+            git(&["-C", &repo, "init", "--quiet"]).unwrap();
+            git(&["-C", &repo, "add", "."]).unwrap();
+            git(&[
+                "-C",
+                &repo,
+                "-c",
+                "user.name=n7tya",
+                "-c",
+                "user.email=n7tya@example.com",
+                "commit",
+                "--quiet",
+                "-m",
+                "init",
+            ])
+            .unwrap();
+        }
+        let app = dir.join("app");
+        fs::create_dir_all(&app).unwrap();
+        let install_from = |manifest: String| {
+            fs::write(app.join("n7tya.toml"), manifest).unwrap();
+            install(&app)
+        };
+
+        let packages = install_from(format!(
+            "[dependencies]\ngood = {{ git = \"{}/good\" }}\n",
+            dir.display()
+        ))
+        .unwrap();
+        let names: Vec<&str> = packages.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["good", "inner"]);
+
+        // git のパッケージからパッケージの外を指すパスは使えない
+        let error = install_from(format!(
+            "[dependencies]\nevil = {{ git = \"{}/evil\" }}\n",
+            dir.display()
+        ))
+        .unwrap_err();
+        assert_eq!(
+            error,
+            "Package 'secret' of git package 'evil' points outside it: ../../.."
+        );
+        // - で始まる URL や rev は git のオプションにしない
+        let error =
+            install_from("[dependencies]\nbad = { git = \"--upload-pack=touch pwned\" }\n".into())
+                .unwrap_err();
+        assert_eq!(error, "Invalid git URL '--upload-pack=touch pwned'");
+        let manifest = format!(
+            "[dependencies]\nbad = {{ git = \"{}/good\", rev = \"--orphan=x\" }}\n",
+            dir.display()
+        );
+        assert_eq!(
+            install_from(manifest).unwrap_err(),
+            "Invalid git rev '--orphan=x'"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,303 @@
+//! 値とテキストの差分 (diff / patch / text.diff / assert_eq)
+//!
+//! `diff(a, b)` は辞書・リスト・構造体を中までたどり、違いを
+//! `{"op": "changed", "path": "user.name", "old": ..., "new": ...}` のような辞書のリストで返す
+//! (op は "added" / "removed" / "changed"、path は json.get と同じ書き方)。リストは同じ添字どうしを比べる。
+//! `patch(a, changes)` は diff の結果を a に当てて書き換える。`text.diff(a, b)` は行ごとの unified diff を返す。
+//! `assert_eq(actual, expected)` は違えば差分を並べた実行時エラーにする (`n7tya test` の失敗の表示に使う)。
+
+use crate::interpreter::{values_equal, Value};
+use crate::jsonpath::{self, Part};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::rc::Rc;
+
+/// 1つの違い
+enum Change {
+    Added(Vec<Part>, Value),
+    Removed(Vec<Part>, Value),
+    Changed(Vec<Part>, Value, Value),
+}
+
+/// diff(a, b)
+pub fn diff(args: Vec<Value>) -> Result<Value, String> {
+    let [a, b] = args.as_slice() else {
+        return Err("diff() takes exactly 2 arguments".to_string());
+    };
+    let changes = changes(a, b).into_iter().map(to_dict).collect();
+    Ok(Value::List(Rc::new(RefCell::new(changes))))
+}
+
+/// patch(value, changes): 追加と変更を先に、削除をあとから (リストの添字がずれないように) 当てる
+pub fn patch(args: Vec<Value>) -> Result<Value, String> {
+    let [value, Value::List(changes)] = args.as_slice() else {
+        return Err("patch() expects a value and a list of changes from diff()".to_string());
+    };
+    let mut result = value.clone();
+    let mut removals = Vec::new();
+    for change in changes.borrow().iter() {
+        let (op, parts, new) = from_dict(change)?;
+        match (op.as_str(), parts.is_empty()) {
+            ("removed", _) => removals.push(parts),
+            (_, true) => result = new,
+            _ => jsonpath::set_at(&result, &parts, new).map_err(|e| format!("patch(): {}", e))?,
+        }
+    }
+    for parts in removals.iter().rev() {
+        jsonpath::remove_at(&result, parts).map_err(|e| format!("patch(): {}", e))?;
+    }
+    Ok(result)
+}
+
+/// text.diff(a, b[, {"context": 3, "from": "a", "to": "b"}]) (同じなら空の文字列)
+pub fn text_diff(args: Vec<Value>) -> Result<Value, String> {
+    let (a, b, options) = match args.as_slice() {
+        [Value::Str(a), Value::Str(b)] => (a, b, None),
+        [Value::Str(a), Value::Str(b), Value::Dict(options)] => (a, b, Some(options.borrow())),
+        _ => return Err("text.diff() expects (a: Str, b: Str, options?: Dict)".to_string()),
+    };
+    let (mut context, mut from, mut to) = (3, "a".to_string(), "b".to_string());
+    for (key, value) in options.iter().flat_map(|options| options.iter()) {
+        match (key.as_str(), value) {
+            ("context", Value::Int(n)) if *n >= 0 => context = *n as usize,
+            ("from", Value::Str(label)) => from = label.to_string(),
+            ("to", Value::Str(label)) => to = label.to_string(),
+            _ => {
+                return Err(format!(
+                    "text.diff(): invalid option '{}': {}",
+                    key,
+                    value.display()
+                ))
+            }
+        }
+    }
+    Ok(Value::Str(unified(a, b, context, &from, &to).into()))
+}
+
+/// assert_eq(actual, expected[, message])
+pub fn assert_eq(args: Vec<Value>) -> Result<Value, String> {
+    let (actual, expected, message) = match args.as_slice() {
+        [actual, expected] => (actual, expected, None),
+        [actual, expected, Value::Str(message)] => (actual, expected, Some(message)),
+        _ => return Err("assert_eq() expects (actual, expected, message?: Str)".to_string()),
+    };
+    if values_equal(actual, expected) {
+        return Ok(Value::None);
+    }
+    let mut text = match message {
+        Some(message) => format!("Assertion failed: {}", message),
+        None => "Assertion failed: values are not equal".to_string(),
+    };
+    match (actual, expected) {
+        // 複数行の文字列は行の差分 (期待した値から実際の値への変化) にする
+        (Value::Str(a), Value::Str(e)) if a.contains('\n') || e.contains('\n') => {
+            text.push('\n');
+            text.push_str(unified(e, a, 3, "expected", "actual").trim_end());
+        }
+        _ => {
+            for change in changes(expected, actual) {
+                text.push_str("\n  ");
+                text.push_str(&describe_change(&change));
+            }
+        }
+    }
+    Err(text)
+}
+
+fn unified(a: &str, b: &str, context: usize, from: &str, to: &str) -> String {
+    similar::TextDiff::from_lines(a, b)
+        .unified_diff()
+        .context_radius(context)
+        .header(from, to)
+        .to_string()
+}
+
+/// a から b への違いを path の順に集める
+fn changes(a: &Value, b: &Value) -> Vec<Change> {
+    let mut result = Vec::new();
+    collect(a, b, &mut Vec::new(), &mut result);
+    result
+}
+
+fn collect(a: &Value, b: &Value, path: &mut Vec<Part>, result: &mut Vec<Change>) {
+    if values_equal(a, b) {
+        return;
+    }
+    match (a, b) {
+        (Value::Dict(x), Value::Dict(y)) => {
+            let (x, y) = (x.borrow().clone(), y.borrow().clone());
+            collect_fields(&x, &y, path, result);
+        }
+        (Value::Class(m, x), Value::Class(n, y)) if m == n => {
+            let (x, y) = (x.borrow().clone(), y.borrow().clone());
+            collect_fields(&x, &y, path, result);
+        }
+        (Value::Struct(m, x), Value::Struct(n, y)) if m == n => {
+            let x: HashMap<_, _> = x.borrow().iter().cloned().collect();
+            let y: HashMap<_, _> = y.borrow().iter().cloned().collect();
+            collect_fields(&x, &y, path, result);
+        }
+        (Value::List(x), Value::List(y)) => {
+            let (x, y) = (x.borrow().clone(), y.borrow().clone());
+            for i in 0..x.len().max(y.len()) {
+                path.push(Part::Index(i as i64));
+                match (x.get(i), y.get(i)) {
+                    (Some(a), Some(b)) => collect(a, b, path, result),
+                    (Some(a), None) => result.push(Change::Removed(path.clone(), a.clone())),
+                    (None, Some(b)) => result.push(Change::Added(path.clone(), b.clone())),
+                    (None, None) => {}
+                }
+                path.pop();
+            }
+        }
+        _ => result.push(Change::Changed(path.clone(), a.clone(), b.clone())),
+    }
+}
+
+/// キーの名前順に比べる
+fn collect_fields(
+    x: &HashMap<String, Value>,
+    y: &HashMap<String, Value>,
+    path: &mut Vec<Part>,
+    result: &mut Vec<Change>,
+) {
+    let keys: BTreeSet<&String> = x.keys().chain(y.keys()).collect();
+    for key in keys {
+        path.push(Part::Key(key.clone()));
+        match (x.get(key), y.get(key)) {
+            (Some(a), Some(b)) => collect(a, b, path, result),
+            (Some(a), None) => result.push(Change::Removed(path.clone(), a.clone())),
+            (None, Some(b)) => result.push(Change::Added(path.clone(), b.clone())),
+            (None, None) => {}
+        }
+        path.pop();
+    }
+}
+
+fn to_dict(change: Change) -> Value {
+    let (op, path, old, new) = match change {
+        Change::Added(path, new) => ("added", path, None, Some(new)),
+        Change::Removed(path, old) => ("removed", path, Some(old), None),
+        Change::Changed(path, old, new) => ("changed", path, Some(old), Some(new)),
+    };
+    let mut dict = HashMap::new();
+    dict.insert("op".to_string(), Value::Str(op.into()));
+    dict.insert(
+        "path".to_string(),
+        Value::Str(jsonpath::describe(&path).into()),
+    );
+    if let Some(old) = old {
+        dict.insert("old".to_string(), old);
+    }
+    if let Some(new) = new {
+        dict.insert("new".to_string(), new);
+    }
+    Value::Dict(Rc::new(RefCell::new(dict)))
+}
+
+/// diff() の辞書から (op, path, new)
+fn from_dict(change: &Value) -> Result<(String, Vec<Part>, Value), String> {
+    let invalid = || format!("patch(): invalid change {}", change.display());
+    let Value::Dict(dict) = change else {
+        return Err(invalid());
+    };
+    let dict = dict.borrow();
+    let (Some(Value::Str(op)), Some(Value::Str(path))) = (dict.get("op"), dict.get("path")) else {
+        return Err(invalid());
+    };
+    let new = match (op.as_ref(), dict.get("new")) {
+        ("removed", _) => Value::None,
+        ("added" | "changed", Some(new)) => new.clone(),
+        _ => return Err(invalid()),
+    };
+    Ok((op.to_string(), jsonpath::parse(path)?, new))
+}
+
+/// assert_eq の1行 ("~ user.name: \"ann\" -> \"bob\"")
+fn describe_change(change: &Change) -> String {
+    let path = |parts: &[Part]| match jsonpath::describe(parts) {
+        path if path.is_empty() => "value".to_string(),
+        path => path,
+    };
+    match change {
+        Change::Added(parts, new) => format!("+ {}: {}", path(parts), shown(new)),
+        Change::Removed(parts, old) => format!("- {}: {}", path(parts), shown(old)),
+        Change::Changed(parts, old, new) => {
+            format!("~ {}: {} -> {}", path(parts), shown(old), shown(new))
+        }
+    }
+}
+
+/// 文字列は引用符で囲んで、数値の "1" と 1 を見分けられるようにする
+fn shown(value: &Value) -> String {
+    match value {
+        Value::Str(s) => format!("{:?}", s),
+        other => other.display(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::run;
+
+    #[test]
+    fn test_diff_and_patch() {
+        let source =
+            "let a = {\"name\": \"ann\", \"tags\": [\"x\", \"y\"], \"age\": 30, \"a.b\": 1}
+let b = {\"name\": \"bob\", \"tags\": [\"x\"], \"email\": \"b@example.com\", \"a.b\": 1}
+let changes = diff(a, b)
+let ops = []
+for change in changes
+    ops.append(change[\"op\"] + \" \" + change[\"path\"])
+[ops, diff(a, a), diff(1, 2)[0][\"new\"], patch(a, changes) == b, a == b]
+";
+        assert_eq!(
+            run(source).unwrap(),
+            "[[removed age, added email, changed name, removed tags[1]], [], 2, true, true]"
+        );
+
+        let source = "struct P\n\tx: Int\n\ty: List<Int>\nlet a = P { x: 1, y: [1] }\nlet b = P { x: 2, y: [1, 2, 3] }\npatch(a, diff(a, b))\n";
+        assert_eq!(run(source).unwrap(), "P {x: 2, y: [1, 2, 3]}");
+    }
+
+    #[test]
+    fn test_patch_errors() {
+        assert_eq!(
+            run("patch({}, [{\"op\": \"moved\", \"path\": \"a\"}])\n").unwrap_err(),
+            "patch(): invalid change {op: moved, path: a}"
+        );
+    }
+
+    #[test]
+    fn test_text_diff() {
+        let source =
+            "text.diff(\"a\\nb\\nc\\n\", \"a\\nB\\nc\\n\", {\"from\": \"old\", \"to\": \"new\"})\n";
+        assert_eq!(
+            run(source).unwrap(),
+            "--- old\n+++ new\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n"
+        );
+        assert_eq!(run("text.diff(\"same\", \"same\")\n").unwrap(), "");
+        assert_eq!(
+            run("text.diff(\"a\", \"b\", {\"context\": -1})\n").unwrap_err(),
+            "text.diff(): invalid option 'context': -1"
+        );
+    }
+
+    #[test]
+    fn test_assert_eq() {
+        assert_eq!(run("assert_eq([1, 2], [1, 2])\n").unwrap(), "none");
+        assert_eq!(
+            run("assert_eq({\"name\": \"ann\", \"n\": 1}, {\"name\": \"bob\"}, \"user\")\n")
+                .unwrap_err(),
+            "Assertion failed: user\n  + n: 1\n  ~ name: \"bob\" -> \"ann\""
+        );
+        assert_eq!(
+            run("assert_eq(\"1\", 1)\n").unwrap_err(),
+            "Assertion failed: values are not equal\n  ~ value: 1 -> \"1\""
+        );
+        assert_eq!(
+            run("assert_eq(\"a\\nb\\n\", \"a\\nc\\n\")\n").unwrap_err(),
+            "Assertion failed: values are not equal\n--- expected\n+++ actual\n@@ -1,2 +1,2 @@\n a\n-c\n+b"
+        );
+    }
+}
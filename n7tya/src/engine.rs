@@ -0,0 +1,395 @@
+//! Rust のプログラムに n7tya-lang を埋め込むための API
+//!
+//! CLI (main.rs) もこの API でファイルを実行する。構文解析・型チェック・実行をまとめて行い、
+//! 続けて評価したコードは前に定義した関数や変数を使える。
+//!
+//! ```ignore
+//! let mut engine = Engine::new();
+//! engine.set("limit", 3);
+//! engine.eval_str("def double x\n    return x * 2\n")?;
+//! let value = engine.call_function("double", vec![Value::from(21)])?;
+//! assert_eq!(i64::try_from(value)?, 42);
+//! ```
+
+use crate::interpreter::{Frame, Interpreter, Value};
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::typechecker::{TypeChecker, TypeInfo};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// 埋め込んだコードの実行で起きたエラー
+#[derive(Error, Debug)]
+pub enum EngineError {
+    #[error("Failed to read file '{path}': {message}")]
+    Io { path: String, message: String },
+
+    #[error("Parse error: {0}")]
+    Parse(miette::Report),
+
+    #[error("Type errors: {}", .0.join("; "))]
+    Type(Vec<String>),
+
+    /// trace は最も内側の呼び出しから順
+    #[error("Runtime error: {message}")]
+    Runtime { message: String, trace: Vec<Frame> },
+}
+
+/// 直前の評価で段階ごとにかかった時間 (`n7tya --time-startup` で表示する)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Timings {
+    pub parse: Duration,
+    pub check: Duration,
+    pub run: Duration,
+}
+
+/// n7tya-lang の実行環境 (インタプリタと、続けて評価するコードを検査する型チェッカー)
+pub struct Engine {
+    interpreter: Interpreter,
+    checker: Option<TypeChecker>,
+    errors: usize, // 型チェッカーが報告済みのエラーの数 (次の評価では新しいものだけを返す)
+    timings: Timings,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::from_interpreter(Interpreter::new())
+    }
+
+    /// 設定済みのインタプリタ (権限や設定ファイル、フックなど) で作る
+    pub fn from_interpreter(interpreter: Interpreter) -> Self {
+        Self {
+            interpreter,
+            checker: Some(TypeChecker::new()),
+            errors: 0,
+            timings: Timings::default(),
+        }
+    }
+
+    /// 評価する前に使う型チェッカー (None なら型チェックをしない)
+    pub fn set_type_checker(&mut self, checker: Option<TypeChecker>) {
+        self.errors = 0;
+        self.checker = checker;
+    }
+
+    pub fn interpreter(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
+    }
+
+    /// ソースを評価して最後の式の値を返す
+    pub fn eval_str(&mut self, source: &str) -> Result<Value, EngineError> {
+        self.timings = Timings::default();
+        let start = Instant::now();
+        let program = Parser::new(Lexer::new(source).tokenize())
+            .parse()
+            .map_err(EngineError::Parse)?;
+        self.timings.parse = start.elapsed();
+        let start = Instant::now();
+        if let Some(checker) = &mut self.checker {
+            let errors = checker.check(&program).map_err(EngineError::Parse)?;
+            let new_errors = errors[self.errors.min(errors.len())..].to_vec();
+            self.errors = errors.len();
+            if !new_errors.is_empty() {
+                return Err(EngineError::Type(new_errors));
+            }
+        }
+        self.timings.check = start.elapsed();
+        let start = Instant::now();
+        let result = self.interpreter.run(&program);
+        self.timings.run = start.elapsed();
+        result.map_err(|message| self.runtime_error(message))
+    }
+
+    /// 直前の eval_str / eval_file の構文解析・型チェック・実行の時間 (エラーで止まった段階より後は 0)
+    pub fn timings(&self) -> Timings {
+        self.timings
+    }
+
+    /// ファイルを評価する (import やスタックトレースはこのファイルの位置になる)
+    pub fn eval_file(&mut self, path: &Path) -> Result<Value, EngineError> {
+        let source = std::fs::read_to_string(path).map_err(|e| EngineError::Io {
+            path: path.display().to_string(),
+            message: e.to_string(),
+        })?;
+        self.interpreter.set_file(path);
+        self.eval_str(&source)
+    }
+
+    /// 定義済みの関数 (組み込み関数も) を呼ぶ
+    pub fn call_function(&mut self, name: &str, args: Vec<Value>) -> Result<Value, EngineError> {
+        let Some(function) = self.get(name) else {
+            return Err(self.runtime_error(format!("Undefined function: {}", name)));
+        };
+        let result = self.interpreter.call_function(function, args);
+        result.map_err(|message| self.runtime_error(message))
+    }
+
+    /// トップレベルの変数や関数の値
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.interpreter.global(name)
+    }
+
+    /// トップレベルに変数を定義する (あとで評価するコードから使える)
+    pub fn set(&mut self, name: &str, value: impl Into<Value>) {
+        let value = value.into();
+        if let Some(checker) = &mut self.checker {
+            checker.define_global(name, type_of(&value));
+        }
+        self.interpreter.define_global(name, value);
+    }
+
+    /// 評価したコードで定義したサーバーを起動し、すべて止まるまで待つ
+    pub fn serve(&mut self) -> Result<(), EngineError> {
+        let result = self.interpreter.serve();
+        result.map_err(|message| self.runtime_error(message))
+    }
+
+    fn runtime_error(&mut self, message: String) -> EngineError {
+        EngineError::Runtime {
+            message,
+            trace: self.interpreter.take_stack_trace(),
+        }
+    }
+}
+
+/// set で定義した値の型 (中身で型が決まらない値は Unknown)
+fn type_of(value: &Value) -> TypeInfo {
+    match value {
+        Value::Int(_) => TypeInfo::Int,
+        Value::Float(_) => TypeInfo::Float,
+        Value::Bool(_) => TypeInfo::Bool,
+        Value::Str(_) => TypeInfo::Str,
+        Value::None => TypeInfo::None,
+        _ => TypeInfo::Unknown,
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Int(n)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(f: f64) -> Self {
+        Value::Float(f)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Str(s.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Str(s.into())
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(items: Vec<T>) -> Self {
+        let items = items.into_iter().map(Into::into).collect();
+        Value::List(Rc::new(RefCell::new(items)))
+    }
+}
+
+impl<T: Into<Value>> From<HashMap<String, T>> for Value {
+    fn from(map: HashMap<String, T>) -> Self {
+        let map = map.into_iter().map(|(k, v)| (k, v.into())).collect();
+        Value::Dict(Rc::new(RefCell::new(map)))
+    }
+}
+
+/// None は none になる
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::None, Into::into)
+    }
+}
+
+impl From<serde_json::Value> for Value {
+    fn from(json: serde_json::Value) -> Self {
+        crate::builtins::json_to_value(json)
+    }
+}
+
+impl Value {
+    /// JSON にする (関数など JSON で書けない値は文字列にする)
+    pub fn to_json(&self) -> serde_json::Value {
+        crate::builtins::value_to_json(self)
+    }
+}
+
+fn expected(ty: &str, value: &Value) -> String {
+    format!("Expected {}, got {}", ty, value.type_name())
+}
+
+impl TryFrom<Value> for i64 {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, String> {
+        match value {
+            Value::Int(n) => Ok(n),
+            v => Err(expected("Int", &v)),
+        }
+    }
+}
+
+/// Int も受け付ける
+impl TryFrom<Value> for f64 {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, String> {
+        match value {
+            Value::Float(f) => Ok(f),
+            Value::Int(n) => Ok(n as f64),
+            v => Err(expected("Float", &v)),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, String> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            v => Err(expected("Bool", &v)),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, String> {
+        match value {
+            Value::Str(s) => Ok(s.to_string()),
+            v => Err(expected("Str", &v)),
+        }
+    }
+}
+
+impl TryFrom<Value> for Vec<Value> {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, String> {
+        match value {
+            Value::List(items) => Ok(items.borrow().clone()),
+            v => Err(expected("List", &v)),
+        }
+    }
+}
+
+impl TryFrom<Value> for HashMap<String, Value> {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, String> {
+        match value {
+            Value::Dict(map) => Ok(map.borrow().clone()),
+            v => Err(expected("Dict", &v)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_engine_eval_and_call() {
+        let mut engine = Engine::new();
+        engine.set("base", 10);
+        engine
+            .eval_str("def add_base x\n    return x + base\n")
+            .unwrap();
+        // 前に評価したコードの定義を使える
+        let value = engine.eval_str("add_base(5) * 2\n").unwrap();
+        assert_eq!(i64::try_from(value), Ok(30));
+        let value = engine
+            .call_function("add_base", vec![Value::from(1)])
+            .unwrap();
+        assert_eq!(i64::try_from(value), Ok(11));
+        let value = engine
+            .call_function("len", vec![Value::from(vec!["a", "b"])])
+            .unwrap();
+        assert_eq!(value.display(), "2");
+
+        let json = serde_json::json!({"name": "ann", "tags": [1, 2.5, null]});
+        let value = engine
+            .call_function("str", vec![Value::from(json.clone())])
+            .unwrap();
+        assert_eq!(String::try_from(value.clone()).unwrap(), value.display());
+        assert_eq!(Value::from(json.clone()).to_json(), json);
+        assert_eq!(
+            String::try_from(Value::from(Some(1))),
+            Err("Expected Str, got Int".to_string())
+        );
+
+        // エラーは種類ごとに返し、型エラーは新しく見つかったものだけを返す
+        assert!(matches!(
+            engine.eval_str("let x = \n"),
+            Err(EngineError::Parse(_))
+        ));
+        match engine.eval_str("let s: Str = base\n") {
+            Err(EngineError::Type(errors)) => assert_eq!(errors.len(), 1),
+            other => panic!("expected a type error, got {:?}", other),
+        }
+        assert_eq!(engine.eval_str("base + 1\n").unwrap().display(), "11");
+        match engine.eval_str("def f\n    return 1 / 0\nf()\n") {
+            Err(EngineError::Runtime { message, trace }) => {
+                assert_eq!(message, "Division by zero");
+                assert_eq!(trace[0].function, "f");
+            }
+            other => panic!("expected a runtime error, got {:?}", other),
+        }
+        assert!(matches!(
+            engine.call_function("missing", Vec::new()),
+            Err(EngineError::Runtime { .. })
+        ));
+    }
+
+    #[test]
+    fn test_timings() {
+        let mut engine = Engine::new();
+        engine.eval_str("let total = sum(range(100))\n").unwrap();
+        let timings = engine.timings();
+        assert!(timings.parse > Duration::ZERO && timings.run > Duration::ZERO);
+
+        // 構文エラーなら型チェックと実行の時間は 0
+        assert!(engine.eval_str("let x = \n").is_err());
+        let timings = engine.timings();
+        assert_eq!(
+            (timings.check, timings.run),
+            (Duration::ZERO, Duration::ZERO)
+        );
+
+        // 作り直したインタプリタと型チェッカーも組み込み関数を使える
+        let mut engine = Engine::new();
+        assert_eq!(
+            engine
+                .eval_str("len(json.stringify([1]))\n")
+                .unwrap()
+                .display(),
+            "3"
+        );
+    }
+}
@@ -44,8 +44,18 @@ pub enum N7tyaError {
     #[error("File error: {message}")]
     #[diagnostic(code(n7tya::io))]
     FileError { message: String },
+
+    #[error("internal compiler error: {message}")]
+    #[diagnostic(
+        code(n7tya::ice),
+        help("This is a bug in n7tya. Please report it at {ISSUE_TRACKER_URL}")
+    )]
+    Internal { message: String },
 }
 
+/// 内部エラー (パニック) の報告先
+pub const ISSUE_TRACKER_URL: &str = "https://github.com/n7tya/n7tya-lang/issues";
+
 impl N7tyaError {
     pub fn syntax(message: impl Into<String>, range: Range<usize>) -> Self {
         Self::Syntax {
@@ -79,6 +89,12 @@ impl N7tyaError {
             message: message.into(),
         }
     }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::Internal {
+            message: message.into(),
+        }
+    }
 }
 
 /// エラー収集用のReporter
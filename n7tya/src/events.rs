@@ -0,0 +1,133 @@
+//! events モジュール (名前つきのイベントの発行と購読)
+//!
+//! `events.on("user.*", send_welcome)` で購読し、`events.emit("user.created", user)` で
+//! 名前が合う関数をすべて登録した順に呼ぶ。名前の `*` は任意の文字列 ("." を含む) に合う。
+//! 購読はリクエストをまたいで残るので、ルートからはイベントを発行するだけにして、
+//! メール送信などの後処理をファイルの先頭で登録した関数に分けられる。
+//! island のメソッドでも同じ書き方でブラウザの中のイベントを使える (islands の $events)。
+
+use crate::interpreter::{Interpreter, Value};
+
+/// 購読した関数 (登録した順)
+#[derive(Debug, Default)]
+pub struct Events {
+    handlers: Vec<(String, Value)>,
+}
+
+impl Events {
+    /// name に合う関数 (呼んでいる間に購読が変わっても影響しないように複製する)
+    fn matching(&self, name: &str) -> Vec<Value> {
+        self.handlers
+            .iter()
+            .filter(|(pattern, _)| topic_matches(pattern, name))
+            .map(|(_, handler)| handler.clone())
+            .collect()
+    }
+}
+
+/// events.on(pattern, fn) / events.off(pattern) / events.emit(name[, payload])
+pub fn call(
+    interpreter: &mut Interpreter,
+    function: &str,
+    args: Vec<Value>,
+) -> Result<Value, String> {
+    match (function, args.as_slice()) {
+        ("on", [Value::Str(pattern), handler @ (Value::Fn(..) | Value::BuiltinFn(_))]) => {
+            interpreter
+                .events()
+                .handlers
+                .push((pattern.to_string(), handler.clone()));
+            Ok(Value::None)
+        }
+        ("on", _) => Err("events.on() expects a topic pattern and a function".to_string()),
+        // 同じパターンで登録した関数をすべて外し、外した数を返す
+        ("off", [Value::Str(pattern)]) => {
+            let handlers = &mut interpreter.events().handlers;
+            let before = handlers.len();
+            handlers.retain(|(p, _)| p != &**pattern);
+            Ok(Value::Int((before - handlers.len()) as i64))
+        }
+        ("off", _) => Err("events.off() expects a topic pattern".to_string()),
+        // 呼んだ関数の数を返す (関数がエラーになったら残りは呼ばない)
+        ("emit", [Value::Str(name), rest @ ..]) if rest.len() <= 1 => {
+            if name.contains('*') {
+                return Err("events.emit() expects a topic name without '*'".to_string());
+            }
+            let payload = rest.first().cloned().unwrap_or(Value::None);
+            let handlers = interpreter.events().matching(name);
+            for handler in &handlers {
+                interpreter.call_function(handler.clone(), vec![payload.clone()])?;
+            }
+            Ok(Value::Int(handlers.len() as i64))
+        }
+        ("emit", _) => {
+            Err("events.emit() expects a topic name and an optional payload".to_string())
+        }
+        _ => Err(format!("Module 'events' has no member '{}'", function)),
+    }
+}
+
+/// "*" は任意の文字列に合う ("user.*" は "user.created" にも "user.profile.updated" にも合う)
+pub fn topic_matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn run(interpreter: &mut Interpreter, source: &str) -> Result<String, String> {
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        interpreter.run(&program).map(|value| value.display())
+    }
+
+    #[test]
+    fn test_topic_matches() {
+        assert!(topic_matches("user.created", "user.created"));
+        assert!(topic_matches("user.*", "user.profile.updated"));
+        assert!(topic_matches("*", "anything"));
+        assert!(topic_matches("*.created", "order.created"));
+        assert!(topic_matches("a*b*c", "abc"));
+        assert!(!topic_matches("user.*", "user"));
+        assert!(!topic_matches("user.created", "user.created.x"));
+        assert!(!topic_matches("a*b*c", "acb"));
+    }
+
+    #[test]
+    fn test_events_module() {
+        let mut interpreter = Interpreter::new();
+        let source = "let log = []\ndef on_user payload\n    log.append(\"user:\" + str(payload))\ndef on_any payload\n    log.append(\"any\")\nevents.on(\"user.*\", on_user)\nevents.on(\"*\", on_any)\n";
+        run(&mut interpreter, source).unwrap();
+        // 購読は別の実行からも残る (サーバーのリクエストをまたぐ)
+        let source = "[events.emit(\"user.created\", 1), events.emit(\"order.paid\"), events.off(\"*\"), events.emit(\"order.paid\"), log]\n";
+        assert_eq!(
+            run(&mut interpreter, source).unwrap(),
+            "[2, 1, 1, 0, [user:1, any, any]]"
+        );
+
+        let source = "def broken payload\n    return 1 / 0\nevents.on(\"fail\", broken)\nevents.emit(\"fail\")\n";
+        assert_eq!(
+            run(&mut interpreter, source).unwrap_err(),
+            "Division by zero"
+        );
+        assert!(run(&mut interpreter, "events.emit(\"user.*\")\n").is_err());
+        assert!(run(&mut interpreter, "events.on(\"x\", 1)\n").is_err());
+    }
+}
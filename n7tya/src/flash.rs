@@ -0,0 +1,123 @@
+//! フラッシュメッセージ (次に表示するページで1度だけ出すメッセージ)
+//!
+//! POST のルートで `flash.set("success", "保存しました")` してから `redirect("/")` を返し、
+//! リダイレクト先のページで `<Flash />` を描画すると、メッセージが1度だけ表示される
+//! (post-redirect-get)。メッセージは session に保存する state と同じく、
+//! Cookie のセッション ID ごとに分けて保存する。
+
+use crate::interpreter::{Interpreter, Value};
+use crate::jsx_render::escape_html;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// セッションの中でメッセージを保存するキー
+const SESSION_KEY: &str = "__flash";
+
+/// flash.set(kind, message) / flash.take()
+pub fn call(
+    interpreter: &mut Interpreter,
+    function: &str,
+    args: Vec<Value>,
+) -> Result<Value, String> {
+    match function {
+        "set" => {
+            let [Value::Str(kind), message] = args.as_slice() else {
+                return Err("flash.set() expects a kind and a message".to_string());
+            };
+            let message = match message {
+                Value::Str(s) => s.clone(),
+                v => v.display().into(),
+            };
+            let mut entry = HashMap::new();
+            entry.insert("kind".to_string(), Value::Str(kind.clone()));
+            entry.insert("message".to_string(), Value::Str(message));
+            let entry = Value::Dict(Rc::new(RefCell::new(entry)));
+
+            let session = interpreter.session_data();
+            match session.get(SESSION_KEY) {
+                Some(Value::List(messages)) => messages.borrow_mut().push(entry),
+                _ => {
+                    let messages = Value::List(Rc::new(RefCell::new(vec![entry])));
+                    session.insert(SESSION_KEY.to_string(), messages);
+                }
+            }
+            Ok(Value::None)
+        }
+        "take" => {
+            if !args.is_empty() {
+                return Err("flash.take() expects no arguments".to_string());
+            }
+            Ok(take(interpreter))
+        }
+        _ => Err(format!("Module 'flash' has no member '{}'", function)),
+    }
+}
+
+/// 保存したメッセージを取り出して消す ({"kind", "message"} の辞書のリスト)
+fn take(interpreter: &mut Interpreter) -> Value {
+    interpreter
+        .session_data()
+        .remove(SESSION_KEY)
+        .unwrap_or_else(|| Value::List(Rc::new(RefCell::new(Vec::new()))))
+}
+
+/// `<Flash />` を描画する (メッセージごとに class="flash flash-{kind}" の div)
+///
+/// 種類が error のメッセージは読み上げソフトにすぐ伝わるよう role="alert" にする。
+pub fn render_flash(interpreter: &mut Interpreter) -> Result<String, String> {
+    let Value::List(messages) = take(interpreter) else {
+        return Ok(String::new());
+    };
+    let mut html = String::new();
+    for message in messages.borrow().iter() {
+        let Value::Dict(entry) = message else {
+            continue;
+        };
+        let entry = entry.borrow();
+        let text = |key: &str| match entry.get(key) {
+            Some(Value::Str(s)) => s.to_string(),
+            _ => String::new(),
+        };
+        let kind = text("kind");
+        let role = if kind == "error" { "alert" } else { "status" };
+        html.push_str(&format!(
+            "<div class=\"flash flash-{}\" role=\"{}\">{}</div>",
+            escape_html(&kind),
+            role,
+            escape_html(&text("message"))
+        ));
+    }
+    Ok(html)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::{Interpreter, Value};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_flash_messages() {
+        let source = "flash.set(\"success\", \"Saved\")\nflash.set(\"error\", \"Name is required\")\nlet messages = flash.take()\nlen(messages) * 10 + len(flash.take())\n";
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        assert!(matches!(
+            Interpreter::new().run(&program),
+            Ok(Value::Int(20))
+        ));
+
+        let source = "component Notice\n\trender\n\t\t<div><Flash /></div>\nflash.set(\"success\", \"Saved <draft>\")\n";
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&program).unwrap();
+        assert_eq!(
+            interpreter.render_component_named("Notice").unwrap(),
+            "<div><div class=\"flash flash-success\" role=\"status\">Saved &lt;draft&gt;</div></div>"
+        );
+        // 1度表示したメッセージは次のページには出ない
+        assert_eq!(
+            interpreter.render_component_named("Notice").unwrap(),
+            "<div></div>"
+        );
+    }
+}
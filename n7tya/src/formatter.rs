@@ -0,0 +1,953 @@
+//! n7tya-lang フォーマッタ
+//!
+//! ソースを構文木にして書き出し直したテキストと、元のソースのトークン列を突き合わせて整形する。
+//! インデントの深さ・トークンの間の空白・コメントの位置は構文木から書き出した側に揃え、
+//! トークンそのものは元の書き方 (数値や文字列の書き方、`print x` のようなコマンド形式の呼び出し、
+//! 余分な括弧、構文木に残らない型引数など) のまま残す。構文木にないトークンのまわりと
+//! 複数行に分けて書いた式の中では、元の改行を残してインデントの段数だけを直す。
+//! 整形したあとでトークン列と構文木が変わっていないことを確かめ、変わるなら整形しない。
+//! スタイルは n7tya.toml の [fmt] セクションで設定できる。
+
+use crate::ast::*;
+use crate::lexer::{detect_indent_unit, indent_depth, Lexer, Token};
+use crate::parser::Parser;
+use std::ops::Range;
+
+/// インデントの種類
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IndentStyle {
+    Tab,
+    Spaces(usize),
+}
+
+/// 文字列リテラルの引用符
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuoteStyle {
+    Double,
+    Single,
+}
+
+/// フォーマット設定 (n7tya.toml の [fmt])
+#[derive(Debug, Clone)]
+pub struct FormatOptions {
+    pub indent: IndentStyle,
+    /// これを超える行は警告する (自動での折り返しはしない)
+    pub max_width: usize,
+    pub trailing_newline: bool,
+    pub quote_style: QuoteStyle,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            indent: IndentStyle::Tab,
+            max_width: 100,
+            trailing_newline: true,
+            quote_style: QuoteStyle::Double,
+        }
+    }
+}
+
+impl FormatOptions {
+    /// n7tya.toml の内容から [fmt] を読み込む (未指定の項目はデフォルト)
+    pub fn from_toml(content: &str) -> Result<Self, String> {
+        let table: toml::Table = content
+            .parse()
+            .map_err(|e| format!("Invalid n7tya.toml: {}", e))?;
+        let mut options = Self::default();
+
+        let Some(fmt) = table.get("fmt") else {
+            return Ok(options);
+        };
+        let fmt = fmt
+            .as_table()
+            .ok_or_else(|| "[fmt] must be a table".to_string())?;
+
+        for (key, value) in fmt {
+            match key.as_str() {
+                "indent" => {
+                    options.indent = match value {
+                        toml::Value::String(s) if s == "tab" => IndentStyle::Tab,
+                        toml::Value::Integer(n) if (1..=16).contains(n) => {
+                            IndentStyle::Spaces(*n as usize)
+                        }
+                        _ => {
+                            return Err(format!(
+                                "fmt.indent must be \"tab\" or a number of spaces (1-16), got {}",
+                                value
+                            ))
+                        }
+                    }
+                }
+                "max_width" => {
+                    options.max_width = match value {
+                        toml::Value::Integer(n) if *n > 0 => *n as usize,
+                        _ => {
+                            return Err(format!(
+                                "fmt.max_width must be a positive integer, got {}",
+                                value
+                            ))
+                        }
+                    }
+                }
+                "trailing_newline" => {
+                    options.trailing_newline = value.as_bool().ok_or_else(|| {
+                        format!("fmt.trailing_newline must be true or false, got {}", value)
+                    })?
+                }
+                "quote_style" => {
+                    options.quote_style = match value.as_str() {
+                        Some("double") => QuoteStyle::Double,
+                        Some("single") => QuoteStyle::Single,
+                        _ => {
+                            return Err(format!(
+                                "fmt.quote_style must be \"double\" or \"single\", got {}",
+                                value
+                            ))
+                        }
+                    }
+                }
+                _ => return Err(format!("Unknown fmt option: {}", key)),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// 整形結果
+pub struct Formatted {
+    pub text: String,
+    /// max_width を超えた行 (1始まり)
+    pub long_lines: Vec<usize>,
+}
+
+/// ソースコードを整形する (構文エラーのあるソースは整形しない)
+pub fn format_source(source: &str, options: &FormatOptions) -> Result<Formatted, String> {
+    let indent = match options.indent {
+        IndentStyle::Tab => "\t".to_string(),
+        IndentStyle::Spaces(n) => " ".repeat(n),
+    };
+    let printed = print_source(source, &indent)?;
+    let original = pieces(source);
+    let target = pieces(&printed);
+    let matches = align(&original, &target);
+    let unit = detect_indent_unit(source);
+
+    // 元のソースの空白の改行を残し、行頭を新しいインデントにする
+    let reindent = |whitespace: &str| {
+        let newlines = whitespace.matches('\n').count();
+        let last = whitespace.rsplit('\n').next().unwrap_or("");
+        format!(
+            "{}{}",
+            "\n".repeat(newlines),
+            indent.repeat(indent_depth(last, unit))
+        )
+    };
+    let printed_gap = |j: usize| {
+        let start = j.checked_sub(1).map_or(0, |k| target[k].span.end);
+        &printed[start..target[j].span.start]
+    };
+
+    let mut text = String::with_capacity(source.len());
+    let mut line = 0;
+    let mut continued = Vec::new(); // 複数行の文字列の2行目以降 (長さを警告しない)
+    for (i, piece) in original.iter().enumerate() {
+        let start = i.checked_sub(1).map_or(0, |k| original[k].span.end);
+        let gap = &source[start..piece.span.start];
+        let previous = match i {
+            0 => Some(None),
+            _ => matches[i - 1].map(Some),
+        };
+        let consecutive = matches[i].is_some_and(|j| previous == Some(j.checked_sub(1)));
+
+        let gap = if !gap.trim().is_empty() {
+            // JSX の {...} の中のコメントはトークンにならないので、空白ごとそのまま残す
+            gap.to_string()
+        } else if !gap.contains('\n') {
+            if consecutive {
+                printed_gap(matches[i].unwrap()).to_string()
+            } else if gap.is_empty() {
+                String::new()
+            } else {
+                " ".to_string()
+            }
+        } else {
+            // 改行 (空行) は元のまま、行頭は書き出した側も行頭ならそのインデントにする
+            match matches[i].map(printed_gap) {
+                Some(printed) if printed.contains('\n') => format!(
+                    "{}{}",
+                    "\n".repeat(gap.matches('\n').count()),
+                    printed.rsplit('\n').next().unwrap_or("")
+                ),
+                _ => reindent(gap),
+            }
+        };
+
+        let spelling = match &piece.token {
+            Token::StringLiteral(_) => requote(&source[piece.span.clone()], options.quote_style),
+            Token::Comment(comment) => format!("#{}", comment.trim_end()),
+            Token::JsxText(_) => reindent_text(&source[piece.span.clone()], &indent, unit),
+            _ => source[piece.span.clone()].to_string(),
+        };
+
+        line += gap.matches('\n').count();
+        text.push_str(&gap);
+        let lines = spelling.matches('\n').count();
+        if matches!(
+            piece.token,
+            Token::StringLiteral(_) | Token::MultiLineString(_)
+        ) {
+            continued.extend(line + 1..=line + lines);
+        }
+        line += lines;
+        text.push_str(&spelling);
+    }
+    if options.trailing_newline && !original.is_empty() {
+        text.push('\n');
+    }
+
+    // トークンと構文木が変わっていないか確かめる
+    let formatted = pieces(&text);
+    let same_tokens = formatted.len() == original.len()
+        && formatted
+            .iter()
+            .zip(&original)
+            .all(|(a, b)| match (&a.token, &b.token) {
+                (Token::Comment(a), Token::Comment(b)) => a.trim_end() == b.trim_end(),
+                (a, b) => a == b,
+            });
+    if !same_tokens || print_source(&text, &indent).as_deref() != Ok(printed.as_str()) {
+        return Err("formatting would change the program".to_string());
+    }
+
+    let long_lines = text
+        .lines()
+        .enumerate()
+        .filter(|(i, l)| !continued.contains(i) && line_width(l) > options.max_width)
+        .map(|(i, _)| i + 1)
+        .collect();
+    Ok(Formatted { text, long_lines })
+}
+
+/// JSX のテキストの2行目以降のインデントを直す (行頭と行末の空白は表示に影響しない)
+fn reindent_text(raw: &str, indent: &str, unit: usize) -> String {
+    let lines: Vec<&str> = raw.split('\n').collect();
+    let last = lines.len() - 1;
+    let mut result = String::with_capacity(raw.len());
+    for (k, part) in lines.iter().enumerate() {
+        let mut part = *part;
+        if k > 0 {
+            result.push('\n');
+            let content = part.trim_start();
+            // 最後の行の空白は閉じタグなどのインデントになる
+            if !content.is_empty() || k == last {
+                result.push_str(
+                    &indent.repeat(indent_depth(&part[..part.len() - content.len()], unit)),
+                );
+            }
+            part = content;
+        }
+        result.push_str(if k < last { part.trim_end() } else { part });
+    }
+    result
+}
+
+/// 行の表示幅 (タブは4桁として数える)
+fn line_width(line: &str) -> usize {
+    line.chars().map(|c| if c == '\t' { 4 } else { 1 }).sum()
+}
+
+/// 整形で並べ直す単位 (Newline / Indent / Dedent 以外のトークンと、行末のものを含むコメント)
+struct Piece {
+    token: Token,
+    span: Range<usize>,
+    jsx: bool, // JSX の要素の中 (空白がテキストの一部になるので、元の空白を使う)
+}
+
+fn pieces(source: &str) -> Vec<Piece> {
+    let mut pieces = Vec::new();
+    let mut depth = 0; // JSX の要素の入れ子
+    let mut closing = false; // </name の後の > で要素が閉じる
+    for info in Lexer::new(source).tokenize() {
+        let token = match info.token {
+            Token::Newline => {
+                if let Some(comment) = info.trailing_comment {
+                    let end = info.span.start;
+                    pieces.push(Piece {
+                        span: end - comment.len() - 1..end,
+                        token: Token::Comment(comment),
+                        jsx: false,
+                    });
+                }
+                continue;
+            }
+            Token::Indent | Token::Dedent => continue,
+            token => token,
+        };
+        match token {
+            Token::OpenTag => depth += 1,
+            Token::CloseTag => closing = true,
+            _ => {}
+        }
+        let jsx = depth > 0;
+        match token {
+            Token::SelfClose => depth -= 1,
+            Token::Gt if closing => {
+                depth -= 1;
+                closing = false;
+            }
+            _ => {}
+        }
+        pieces.push(Piece {
+            token,
+            span: info.span,
+            jsx,
+        });
+    }
+    pieces
+}
+
+/// 元のソースの各トークンに、書き出したテキストで対応するトークンの添字を割り当てる
+///
+/// 数値どうし・文字列どうしは書き方が違っても対応させる。JSX の中は対応させない。
+fn align(original: &[Piece], target: &[Piece]) -> Vec<Option<usize>> {
+    let key = |piece: &Piece| match &piece.token {
+        Token::IntLiteral(_)
+        | Token::FloatLiteral(_)
+        | Token::DurationLiteral(_)
+        | Token::SizeLiteral(_) => "0".to_string(),
+        Token::StringLiteral(_) | Token::MultiLineString(_) => "\"".to_string(),
+        Token::Comment(comment) => format!("#{}", comment.trim_end()),
+        token => format!("{:?}", token),
+    };
+    let old: Vec<String> = original.iter().map(key).collect();
+    let new: Vec<String> = target.iter().map(key).collect();
+
+    let mut matches = vec![None; original.len()];
+    for op in similar::capture_diff_slices(similar::Algorithm::Myers, &old, &new) {
+        if let similar::DiffOp::Equal {
+            old_index,
+            new_index,
+            len,
+        } = op
+        {
+            for k in 0..len {
+                if !original[old_index + k].jsx {
+                    matches[old_index + k] = Some(new_index + k);
+                }
+            }
+        }
+    }
+    matches
+}
+
+/// 文字列リテラルの引用符を揃える (エスケープが増える場合は元のまま)
+fn requote(raw: &str, style: QuoteStyle) -> String {
+    let (from, to) = match style {
+        QuoteStyle::Double => ('\'', '"'),
+        QuoteStyle::Single => ('"', '\''),
+    };
+    if raw.len() >= 2 && raw.starts_with(from) && raw.ends_with(from) {
+        if let Some(inner) = swap_quotes(&raw[1..raw.len() - 1], from, to) {
+            return format!("{}{}{}", to, inner, to);
+        }
+    }
+    raw.to_string()
+}
+
+/// from で囲まれていた中身を to で囲める形に直す (\' のような不要になったエスケープは外す)
+fn swap_quotes(inner: &str, from: char, to: char) -> Option<String> {
+    let mut result = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(next) if next == from => result.push(from),
+                Some(next) => {
+                    result.push('\\');
+                    result.push(next);
+                }
+                None => result.push('\\'),
+            },
+            // 新しい引用符がそのまま現れるならエスケープが必要になるので変換しない
+            c if c == to => return None,
+            // '...' は1行に収める必要がある
+            '\n' if to == '\'' => return None,
+            c => result.push(c),
+        }
+    }
+    Some(result)
+}
+
+/// ソースをパースし、構文木を標準の書き方で書き出す
+fn print_source(source: &str, indent: &str) -> Result<String, String> {
+    let program = Parser::new(Lexer::new(source).tokenize())
+        .parse_syntax()
+        .map_err(|e| format!("Parse error: {}", e))?;
+    let mut printer = Printer {
+        indent,
+        depth: 0,
+        lines: Vec::new(),
+    };
+    for item in &program.items {
+        printer.item(item);
+    }
+    let mut text = printer.lines.join("\n");
+    text.push('\n');
+    Ok(text)
+}
+
+/// 構文木をソースコードとして書き出す
+///
+/// 呼び出しはすべて `f(a, b)` の形にし、括弧は必要なところにだけ付ける。
+/// 書き出したテキストは元のソースと突き合わせるためのもので、構文木に残らない書き方は失われる。
+struct Printer<'a> {
+    indent: &'a str,
+    depth: usize,
+    lines: Vec<String>,
+}
+
+impl Printer<'_> {
+    fn line(&mut self, text: String) {
+        self.lines
+            .push(format!("{}{}", self.indent.repeat(self.depth), text));
+    }
+
+    /// 1段深くして書き出す
+    fn indented(&mut self, f: impl FnOnce(&mut Self)) {
+        self.depth += 1;
+        f(self);
+        self.depth -= 1;
+    }
+
+    fn trivia(&mut self, trivia: &Trivia) {
+        match trivia {
+            // 行末コメントは直前の行 (ブロックの先頭ならヘッダの行) の末尾に付ける
+            Trivia::Comment {
+                text,
+                trailing: true,
+            } if !self.lines.is_empty() => {
+                let last = self.lines.last_mut().unwrap();
+                last.push_str(" #");
+                last.push_str(text.trim_end());
+            }
+            Trivia::Comment { text, .. } => self.line(format!("#{}", text.trim_end())),
+            Trivia::BlankLines(n) => self.lines.extend(std::iter::repeat_n(String::new(), *n)),
+        }
+    }
+
+    fn item(&mut self, item: &Item) {
+        match item {
+            Item::FunctionDef(function) => self.function(function, ""),
+            Item::ClassDef(class) => {
+                let mut header = format!("class {}", class.name);
+                if let Some(parent) = &class.parent {
+                    header.push(' ');
+                    header.push_str(parent);
+                }
+                if !class.interfaces.is_empty() {
+                    header.push_str(" implements ");
+                    header.push_str(&class.interfaces.join(", "));
+                }
+                self.line(header);
+                self.indented(|p| {
+                    p.doc(&class.doc);
+                    for member in &class.body {
+                        match member {
+                            ClassBodyItem::Field(field) => p.line(field_def(field)),
+                            ClassBodyItem::Method(method) => p.function(method, ""),
+                            ClassBodyItem::StaticMethod(method) => p.function(method, "static "),
+                            ClassBodyItem::Const(decl) => p.line(declaration(
+                                "const",
+                                &decl.name,
+                                &decl.type_annotation,
+                                &decl.value,
+                            )),
+                            ClassBodyItem::Trivia(trivia) => p.trivia(trivia),
+                        }
+                    }
+                });
+            }
+            Item::StructDef(def) => {
+                self.line(format!("struct {}", def.name));
+                self.indented(|p| {
+                    for field in &def.fields {
+                        p.line(field_def(field));
+                    }
+                });
+            }
+            Item::InterfaceDef(def) => {
+                self.line(format!("interface {}", def.name));
+                self.indented(|p| {
+                    for method in &def.methods {
+                        p.line(format!(
+                            "def {}",
+                            signature(&method.name, &method.params, &method.return_type)
+                        ));
+                    }
+                });
+            }
+            Item::ComponentDef(component) => {
+                let island = if component.island { "island " } else { "" };
+                self.line(format!("{}component {}", island, component.name));
+                self.indented(|p| {
+                    p.doc(&component.doc);
+                    for member in &component.body {
+                        match member {
+                            ComponentBodyItem::State(state) => p.line(state_decl(state)),
+                            ComponentBodyItem::Method(method) => p.function(method, ""),
+                            ComponentBodyItem::Render(render) => p.render(render),
+                            ComponentBodyItem::Trivia(trivia) => p.trivia(trivia),
+                        }
+                    }
+                });
+            }
+            Item::ServerDef(server) => {
+                self.line(format!("server {}", server.name));
+                self.indented(|p| {
+                    for member in &server.body {
+                        match member {
+                            ServerBodyItem::Route(route) => p.route(route),
+                            ServerBodyItem::Trivia(trivia) => p.trivia(trivia),
+                        }
+                    }
+                });
+            }
+            Item::Import(import) => self.line(import_stmt(import)),
+            Item::Statement(statement) => self.statement(statement),
+        }
+    }
+
+    fn function(&mut self, function: &FunctionDef, prefix: &str) {
+        self.line(format!(
+            "{}def {}",
+            prefix,
+            signature(&function.name, &function.params, &function.return_type)
+        ));
+        self.indented(|p| {
+            p.doc(&function.doc);
+            p.block(&function.body);
+        });
+    }
+
+    fn doc(&mut self, doc: &Option<String>) {
+        if let Some(doc) = doc {
+            self.line(string(doc));
+        }
+    }
+
+    fn render(&mut self, render: &RenderBlock) {
+        self.line("render".to_string());
+        self.indented(|p| p.block(&render.body));
+    }
+
+    fn route(&mut self, route: &RouteDef) {
+        let mut header = format!("{} {}", route.method, string(&route.path));
+        if let Some(param) = &route.body_param {
+            header.push_str(&format!(" ({})", param_def(param)));
+        }
+        if let Some(ty) = &route.return_type {
+            header.push_str(&format!(" -> {}", ty));
+        }
+        self.line(header);
+        self.indented(|p| p.block(&route.body));
+    }
+
+    fn block(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Let(decl) => self.line(declaration(
+                "let",
+                &decl.name,
+                &decl.type_annotation,
+                &decl.value,
+            )),
+            Statement::Const(decl) => self.line(declaration(
+                "const",
+                &decl.name,
+                &decl.type_annotation,
+                &decl.value,
+            )),
+            Statement::Return(None) => self.line("return".to_string()),
+            Statement::Return(Some(value)) => self.line(format!("return {}", expression(value))),
+            Statement::Expression(expr) => self.line(expression(expr)),
+            Statement::If(stmt) => self.if_stmt(stmt, "if"),
+            Statement::For(stmt) => {
+                self.line(format!(
+                    "{}for {} in {}",
+                    label(&stmt.label),
+                    stmt.target,
+                    expression(&stmt.iterator)
+                ));
+                self.indented(|p| p.block(&stmt.body));
+            }
+            Statement::While(stmt) => {
+                self.line(format!(
+                    "{}while {}",
+                    label(&stmt.label),
+                    expression(&stmt.condition)
+                ));
+                self.indented(|p| p.block(&stmt.body));
+            }
+            Statement::Match(stmt) => {
+                self.line(format!("match {}", expression(&stmt.value)));
+                self.indented(|p| {
+                    for case in &stmt.cases {
+                        p.line(format!("case {}", pattern(&case.pattern)));
+                        p.indented(|p| p.block(&case.body));
+                    }
+                });
+            }
+            Statement::Break(None) => self.line("break".to_string()),
+            Statement::Break(Some(label)) => self.line(format!("break {}", label)),
+            Statement::Continue(None) => self.line("continue".to_string()),
+            Statement::Continue(Some(label)) => self.line(format!("continue {}", label)),
+            Statement::FunctionDef(function) => self.function(function, ""),
+            Statement::Nonlocal(names) => self.line(format!("nonlocal {}", names.join(", "))),
+            Statement::Copy(names) => self.line(format!("copy {}", names.join(", "))),
+            Statement::State(state) => self.line(state_decl(state)),
+            Statement::Render(render) => self.render(render),
+            Statement::Assignment(assignment) => self.line(format!(
+                "{} = {}",
+                expression(&assignment.target),
+                expression(&assignment.value)
+            )),
+            Statement::Trivia(trivia) => self.trivia(trivia),
+        }
+    }
+
+    fn if_stmt(&mut self, stmt: &IfStmt, keyword: &str) {
+        self.line(format!("{} {}", keyword, expression(&stmt.condition)));
+        self.indented(|p| p.block(&stmt.then_block));
+        match stmt.else_block.as_deref() {
+            Some([Statement::If(inner)]) if stmt.elif => self.if_stmt(inner, "elif"),
+            Some(block) => {
+                self.line("else".to_string());
+                self.indented(|p| p.block(block));
+            }
+            None => {}
+        }
+    }
+}
+
+fn signature(name: &str, params: &[Param], return_type: &Option<Type>) -> String {
+    let mut text = name.to_string();
+    if !params.is_empty() {
+        let params: Vec<String> = params.iter().map(param_def).collect();
+        text.push(' ');
+        text.push_str(&params.join(", "));
+    }
+    if let Some(ty) = return_type {
+        text.push_str(&format!(" -> {}", ty));
+    }
+    text
+}
+
+fn param_def(param: &Param) -> String {
+    match &param.type_annotation {
+        Some(ty) => format!("{}: {}", param.name, ty),
+        None => param.name.clone(),
+    }
+}
+
+fn field_def(field: &FieldDef) -> String {
+    let mut text = format!("{}: {}", field.name, field.type_annotation);
+    for check in &field.checks {
+        text.push_str(&format!(" @{}", check.name));
+        if !check.args.is_empty() {
+            let args: Vec<String> = check.args.iter().map(literal).collect();
+            text.push_str(&format!("({})", args.join(", ")));
+        }
+    }
+    if let Some(default) = &field.default {
+        text.push_str(&format!(" = {}", expression(default)));
+    }
+    text
+}
+
+fn declaration(keyword: &str, name: &str, ty: &Option<Type>, value: &Expression) -> String {
+    match ty {
+        Some(ty) => format!("{} {}: {} = {}", keyword, name, ty, expression(value)),
+        None => format!("{} {} = {}", keyword, name, expression(value)),
+    }
+}
+
+fn state_decl(state: &StateDecl) -> String {
+    match state.persist {
+        Some(persist) => format!(
+            "state {} {} = {}",
+            persist.name(),
+            state.name,
+            expression(&state.value)
+        ),
+        None => format!("state {} = {}", state.name, expression(&state.value)),
+    }
+}
+
+fn import_stmt(import: &ImportStmt) -> String {
+    // ファイルの import はパスを文字列で書く
+    let module = if import
+        .module
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        import.module.clone()
+    } else {
+        string(&import.module)
+    };
+    let lazy = if import.lazy { "lazy " } else { "" };
+    if !import.names.is_empty() {
+        return format!("from {} import {}", module, import.names.join(", "));
+    }
+    match &import.alias {
+        Some(alias) => format!("{}import {} as {}", lazy, module, alias),
+        None => format!("{}import {}", lazy, module),
+    }
+}
+
+fn label(label: &Option<String>) -> String {
+    label
+        .as_ref()
+        .map_or(String::new(), |label| format!("{}: ", label))
+}
+
+/// 二項演算子の結合の強さ (大きいほど強い。単項演算子は 7、後置の演算は 8)
+fn precedence(op: &BinaryOp) -> u8 {
+    match op {
+        BinaryOp::Or => 1,
+        BinaryOp::And => 2,
+        BinaryOp::Eq | BinaryOp::Ne => 3,
+        BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Le | BinaryOp::Ge | BinaryOp::In => 4,
+        BinaryOp::Add | BinaryOp::Sub => 5,
+        BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => 6,
+    }
+}
+
+fn expression(expr: &Expression) -> String {
+    operand(expr, 0)
+}
+
+/// 結合の強さが min より弱い式は括弧で囲む
+fn operand(expr: &Expression, min: u8) -> String {
+    let (text, strength) = match expr {
+        Expression::BinaryOp(binary) => {
+            let strength = precedence(&binary.op);
+            let text = format!(
+                "{} {} {}",
+                operand(&binary.left, strength),
+                binary.op,
+                operand(&binary.right, strength + 1)
+            );
+            (text, strength)
+        }
+        Expression::UnaryOp(unary) => {
+            let op = match unary.op {
+                UnaryOp::Neg => "-",
+                UnaryOp::Not => "not ",
+            };
+            (format!("{}{}", op, operand(&unary.operand, 7)), 7)
+        }
+        Expression::Await(inner) => (format!("await {}", operand(inner, 7)), 7),
+        Expression::Call(call) => (
+            format!("{}({})", operand(&call.func, 8), list(&call.args)),
+            8,
+        ),
+        Expression::MemberAccess(member) => (
+            format!("{}.{}", operand(&member.object, 8), member.member),
+            8,
+        ),
+        Expression::Index(index) => (
+            format!(
+                "{}[{}]",
+                operand(&index.object, 8),
+                expression(&index.index)
+            ),
+            8,
+        ),
+        Expression::Try(inner) => (format!("{}?", operand(inner, 8)), 8),
+        Expression::Lambda(lambda) => {
+            let params = match &lambda.params[..] {
+                [param] => param.clone(),
+                params => format!("({})", params.join(", ")),
+            };
+            (format!("{} -> {}", params, expression(&lambda.body)), 0)
+        }
+        Expression::Literal(value) => (literal(value), 9),
+        Expression::Identifier(name) => (name.clone(), 9),
+        Expression::JsxElement(element) => (jsx(element), 9),
+        Expression::StructLiteral(value) => {
+            let fields: Vec<String> = value
+                .fields
+                .iter()
+                .map(|(name, value)| format!("{}: {}", name, expression(value)))
+                .collect();
+            (format!("{} {{{}}}", value.name, fields.join(", ")), 9)
+        }
+    };
+    if strength < min {
+        format!("({})", text)
+    } else {
+        text
+    }
+}
+
+fn list(items: &[Expression]) -> String {
+    items.iter().map(expression).collect::<Vec<_>>().join(", ")
+}
+
+fn literal(value: &Literal) -> String {
+    match value {
+        Literal::Int(n) => n.to_string(),
+        Literal::Float(f) => format!("{:?}", f),
+        Literal::Duration(d) => format!("{}ms", d.as_millis()),
+        Literal::Size(bytes) => format!("{}b", bytes),
+        Literal::Str(s) => string(s),
+        Literal::Bool(b) => b.to_string(),
+        Literal::List(items) => format!("[{}]", list(items)),
+        Literal::Dict(entries) => {
+            let entries: Vec<String> = entries
+                .iter()
+                .map(|(key, value)| format!("{}: {}", expression(key), expression(value)))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+        Literal::Set(items) => format!("{{{}}}", list(items)),
+        Literal::None => "none".to_string(),
+    }
+}
+
+/// 文字列リテラル ("..." で、改行などはエスケープする)
+fn string(s: &str) -> String {
+    let mut text = String::with_capacity(s.len() + 2);
+    text.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => text.push_str("\\\""),
+            '\\' => text.push_str("\\\\"),
+            '\n' => text.push_str("\\n"),
+            '\t' => text.push_str("\\t"),
+            '\r' => text.push_str("\\r"),
+            '\0' => text.push_str("\\0"),
+            c => text.push(c),
+        }
+    }
+    text.push('"');
+    text
+}
+
+fn jsx(element: &JsxElement) -> String {
+    let mut text = format!("<{}", element.tag);
+    for attribute in &element.attributes {
+        text.push(' ');
+        text.push_str(&attribute.name);
+        match &attribute.value {
+            Some(Expression::Literal(Literal::Str(s))) => text.push_str(&format!("={}", string(s))),
+            Some(value) => text.push_str(&format!("={{{}}}", expression(value))),
+            None => {}
+        }
+    }
+    if element.children.is_empty() {
+        text.push_str(" />");
+        return text;
+    }
+    text.push('>');
+    for child in &element.children {
+        match child {
+            JsxChild::Element(child) => text.push_str(&jsx(child)),
+            JsxChild::Text(s) => text.push_str(s),
+            JsxChild::Expression(value) => text.push_str(&format!("{{{}}}", expression(value))),
+        }
+    }
+    text.push_str(&format!("</{}>", element.tag));
+    text
+}
+
+fn pattern(pattern: &Pattern) -> String {
+    let patterns = |items: &[Pattern]| {
+        items
+            .iter()
+            .map(self::pattern)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    match pattern {
+        Pattern::Literal(value) => literal(value),
+        Pattern::Identifier(name) => name.clone(),
+        Pattern::Wildcard => "_".to_string(),
+        Pattern::Range(start, end) => format!("{}..{}", start, end),
+        Pattern::List(items) => format!("[{}]", patterns(items)),
+        Pattern::Rest(name) => format!("*{}", name),
+        Pattern::Dict(entries) => {
+            let entries: Vec<String> = entries
+                .iter()
+                .map(|(key, value)| format!("{}: {}", string(key), self::pattern(value)))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+        Pattern::Variant(name, inner) => format!("{}({})", name, self::pattern(inner)),
+        Pattern::Constructor(name, fields) => format!("{}({})", name, patterns(fields)),
+        Pattern::Fields(name, fields) => {
+            // {name: name} は {name} と書く
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(field, value)| match value {
+                    Pattern::Identifier(bound) if bound == field => field.clone(),
+                    value => format!("{}: {}", field, self::pattern(value)),
+                })
+                .collect();
+            format!("{} {{{}}}", name, fields.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_options() {
+        let toml = "[package]\nname = \"app\"\n\n[fmt]\nindent = 4\nquote_style = \"single\"\ntrailing_newline = false\n";
+        let options = FormatOptions::from_toml(toml).unwrap();
+        assert_eq!(options.indent, IndentStyle::Spaces(4));
+        assert_eq!(options.quote_style, QuoteStyle::Single);
+
+        let source = "def f\n\tif x\n\t\tprint \"hi\"  \n\tlet s = `a\n  b`\n\n\n";
+        let formatted = format_source(source, &options).unwrap().text;
+        assert_eq!(
+            formatted,
+            "def f\n    if x\n        print 'hi'\n    let s = `a\n  b`"
+        );
+        // 再整形しても変わらない
+        assert_eq!(format_source(&formatted, &options).unwrap().text, formatted);
+
+        assert!(FormatOptions::from_toml("[fmt]\nindent = \"two\"\n").is_err());
+    }
+
+    #[test]
+    fn test_format_from_syntax_tree() {
+        let source = "from math import sqrt,floor\ndef area  w:Int,h: Int->Int # rect\n    return w*h\n\nlet d: Dict<Str, Int> = {\"a\":1}\nlet xs = [\n    1,\n    2 # two\n]\nprint   area(1,2) , xs\nif x>1 and not y\n    print 'big'\nelse\n    if y\n          # small\n        print 'y'\n";
+        let formatted = format_source(source, &FormatOptions::default())
+            .unwrap()
+            .text;
+        // 空白とインデントは構文木に、型引数・コマンド形式の呼び出し・改行した式は元に合わせる
+        assert_eq!(
+            formatted,
+            "from math import sqrt, floor\ndef area w: Int, h: Int -> Int # rect\n\treturn w * h\n\nlet d: Dict<Str, Int> = {\"a\": 1}\nlet xs = [\n\t1,\n\t2 # two\n]\nprint area(1, 2), xs\nif x > 1 and not y\n\tprint \"big\"\nelse\n\tif y\n\t\t# small\n\t\tprint \"y\"\n"
+        );
+        assert_eq!(
+            format_source(&formatted, &FormatOptions::default())
+                .unwrap()
+                .text,
+            formatted
+        );
+
+        // 構文エラーのあるソースは整形しない
+        assert!(format_source("let x = 1 +\n", &FormatOptions::default()).is_err());
+    }
+}
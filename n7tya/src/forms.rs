@@ -0,0 +1,166 @@
+//! 構造体とフォームの対応づけ
+//!
+//! `<form bind={User}>` で構造体のフィールドから入力欄を生成し、
+//! 送信された `application/x-www-form-urlencoded` のボディを同じ構造体に戻す。
+//! 戻すときは JSON のボディと同じく Interpreter::decode_json で型を確かめる。
+
+use crate::ast::{StructDef, Type};
+use crate::interpreter::Value;
+use crate::jsx_render::escape_html;
+
+/// フォームのボディかどうか (Content-Type ヘッダーの値で判定する)
+pub fn is_form_content_type(content_type: &str) -> bool {
+    content_type.split(';').next().is_some_and(|t| {
+        t.trim()
+            .eq_ignore_ascii_case("application/x-www-form-urlencoded")
+    })
+}
+
+/// 構造体のフィールドごとに label と input を並べた HTML を返す
+///
+/// values に構造体か辞書を渡すと、その値を入力欄の初期値にする。
+pub fn render_fields(def: &StructDef, values: Option<&Value>) -> Result<String, String> {
+    let mut html = String::new();
+    for field in &def.fields {
+        let (ty, optional) = unwrap_optional(&field.type_annotation);
+        let input_type = match ty {
+            Type::Int => "number\" step=\"1",
+            Type::Float => "number\" step=\"any",
+            Type::Custom(name) if name == "Decimal" => "number\" step=\"any",
+            Type::Bool => "checkbox",
+            Type::Str => "text",
+            _ => {
+                return Err(format!(
+                    "Field '{}' of {} cannot be a form field ({})",
+                    field.name, def.name, field.type_annotation
+                ))
+            }
+        };
+        let id = format!("{}-{}", def.name.to_lowercase(), field.name);
+        html.push_str(&format!(
+            "<label for=\"{}\">{}</label><input id=\"{}\" name=\"{}\" type=\"{}\"",
+            id,
+            escape_html(&label_text(&field.name)),
+            id,
+            field.name,
+            input_type
+        ));
+        match (ty, field_value(values, &field.name)) {
+            (Type::Bool, Some(Value::Bool(true))) => html.push_str(" checked"),
+            (Type::Bool, _) | (_, None | Some(Value::None)) => {}
+            (_, Some(value)) => {
+                html.push_str(&format!(" value=\"{}\"", escape_html(&value.display())));
+            }
+        }
+        // チェックボックスは外した状態が false なので必須にしない
+        if !optional && !matches!(ty, Type::Bool) {
+            html.push_str(" required");
+        }
+        html.push_str(" />");
+    }
+    Ok(html)
+}
+
+/// フォームのボディを構造体のフィールドの型に合わせた JSON にする
+///
+/// 数値に読めない値は文字列のまま残し、型の検査で弾かれるようにする。
+/// 構造体にないキー (送信ボタンの name など) は無視する。
+pub fn form_to_json(def: &StructDef, body: &str) -> serde_json::Value {
+    use serde_json::Value as Json;
+    let pairs = parse_urlencoded(body);
+    let mut object = serde_json::Map::new();
+    for field in &def.fields {
+        let mut values = pairs
+            .iter()
+            .filter(|(k, _)| k == &field.name)
+            .map(|(_, v)| v.as_str());
+        let (ty, optional) = unwrap_optional(&field.type_annotation);
+        let json = match ty {
+            // 外したチェックボックスは送信されない
+            Type::Bool => Json::Bool(values.next_back().is_some_and(|v| v == "on" || v == "true")),
+            Type::List(inner) => Json::Array(values.map(|v| scalar_json(inner, v)).collect()),
+            _ => match values.next_back() {
+                Some("") if optional => Json::Null,
+                Some(v) => scalar_json(ty, v),
+                None => continue,
+            },
+        };
+        object.insert(field.name.clone(), json);
+    }
+    Json::Object(object)
+}
+
+/// key=value&... を読む (+ は空白、%XX はそのバイト)
+pub fn parse_urlencoded(body: &str) -> Vec<(String, String)> {
+    ::url::form_urlencoded::parse(body.as_bytes())
+        .into_owned()
+        .collect()
+}
+
+fn scalar_json(ty: &Type, text: &str) -> serde_json::Value {
+    use serde_json::Value as Json;
+    let parsed = match ty {
+        Type::Int => text.trim().parse::<i64>().ok().map(Json::from),
+        Type::Float => text.trim().parse::<f64>().ok().map(Json::from),
+        _ => None,
+    };
+    parsed.unwrap_or_else(|| Json::String(text.to_string()))
+}
+
+/// Int? や Int | none の中身の型と、none を受け付けるかを返す
+fn unwrap_optional(ty: &Type) -> (&Type, bool) {
+    match ty {
+        Type::Optional(inner) => (inner, true),
+        Type::Union(members) if members.iter().any(|m| matches!(m, Type::None)) => {
+            let inner = members
+                .iter()
+                .find(|m| !matches!(m, Type::None))
+                .unwrap_or(ty);
+            (inner, true)
+        }
+        _ => (ty, false),
+    }
+}
+
+/// first_name -> First name
+fn label_text(name: &str) -> String {
+    let text = name.replace('_', " ");
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => text,
+    }
+}
+
+fn field_value(values: Option<&Value>, name: &str) -> Option<Value> {
+    match values? {
+        Value::Struct(_, fields) => fields
+            .borrow()
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.clone()),
+        Value::Dict(dict) => dict.borrow().get(name).cloned(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_urlencoded() {
+        assert_eq!(
+            parse_urlencoded("name=Taro+Yamada&note=a%26b%3D%E3%81%82&empty="),
+            vec![
+                ("name".to_string(), "Taro Yamada".to_string()),
+                ("note".to_string(), "a&b=あ".to_string()),
+                ("empty".to_string(), String::new()),
+            ]
+        );
+        assert!(is_form_content_type(
+            "application/x-www-form-urlencoded; charset=UTF-8"
+        ));
+        assert!(!is_form_content_type("application/json"));
+    }
+}
@@ -0,0 +1,290 @@
+//! 環境 (Env) とクロージャの循環参照の回収
+//!
+//! クロージャ (Value::Fn) は定義した環境を Rc で持ち、その環境は変数としてクロージャを持つので、
+//! 関数の中で関数を定義するたびに参照カウントだけでは解放されない循環ができる
+//! (サーバーのように長く動かすと、リクエストごとにメモリが増えていく)。
+//! クロージャが捕まえた環境を Weak で覚えておき、増えてきたら CPython の gc と同じ方法で回収する:
+//!
+//! 1. 覚えている環境からたどれる環境・リスト・辞書などを集め、その中からの参照の数を数える
+//! 2. Rc::strong_count がそれより多いものは外 (インタプリタのフィールドや Rust の変数) から
+//!    使われているので生きている。生きているものからたどれるものも生きている
+//! 3. 残りは循環の中からしか参照されていないので、中身を消して循環を断つ
+//!
+//! 中身をたどらない値 (モジュールやイテレータ) から参照されているものは外から使われているとみなす。
+//! そのため生きている値を消すことはない (回収しきれない循環が残ることはある)。
+
+use crate::interpreter::{Env, Value};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
+/// 最初に回収するまでに覚える環境の数 (以降は回収後に残った数の2倍)
+const INITIAL_THRESHOLD: usize = 1000;
+
+/// クロージャが捕まえた環境を覚えておき、循環だけで残っているものを回収する
+#[derive(Debug)]
+pub struct CycleCollector {
+    tracked: Vec<Weak<RefCell<Env>>>,
+    threshold: usize,
+}
+
+impl Default for CycleCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CycleCollector {
+    pub fn new() -> Self {
+        Self {
+            tracked: Vec::new(),
+            threshold: INITIAL_THRESHOLD,
+        }
+    }
+
+    /// クロージャが捕まえた環境を覚える (覚えた数がしきい値を超えたら回収する)
+    pub fn track(&mut self, env: &Rc<RefCell<Env>>) {
+        if let Some(last) = self.tracked.last() {
+            if last.as_ptr() == Rc::as_ptr(env) {
+                return;
+            }
+        }
+        self.tracked.push(Rc::downgrade(env));
+        if self.tracked.len() >= self.threshold {
+            self.collect();
+        }
+    }
+
+    /// 循環の中からしか参照されていない環境を消し、その数を返す
+    pub fn collect(&mut self) -> usize {
+        let mut seen = HashSet::new();
+        self.tracked
+            .retain(|env| env.strong_count() > 0 && seen.insert(env.as_ptr()));
+        let starts = self
+            .tracked
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(Node::Env)
+            .collect();
+        let collected = Graph::build(starts).clear_garbage();
+        self.tracked.retain(|env| env.strong_count() > 0);
+        self.threshold = (self.tracked.len() * 2).max(INITIAL_THRESHOLD);
+        collected
+    }
+}
+
+/// 中身をたどる値 (Rc で共有され、循環の一部になりうるもの)
+enum Node {
+    Env(Rc<RefCell<Env>>),
+    Items(Rc<RefCell<Vec<Value>>>),            // List, Set
+    Map(Rc<RefCell<HashMap<String, Value>>>),  // Dict, クラスインスタンス
+    Fields(Rc<RefCell<Vec<(String, Value)>>>), // 構造体
+    Wrapped(Rc<Value>),                        // Ok, Err, Some
+}
+
+impl Node {
+    fn key(&self) -> usize {
+        match self {
+            Node::Env(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Items(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Map(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Fields(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Wrapped(rc) => Rc::as_ptr(rc) as *const () as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Env(rc) => Rc::strong_count(rc),
+            Node::Items(rc) => Rc::strong_count(rc),
+            Node::Map(rc) => Rc::strong_count(rc),
+            Node::Fields(rc) => Rc::strong_count(rc),
+            Node::Wrapped(rc) => Rc::strong_count(rc),
+        }
+    }
+
+    /// 直接参照している Node (持っている Rc 1つにつき1つ)。中身を借りられなければ None
+    fn children(&self) -> Option<Vec<Node>> {
+        let mut children = Vec::new();
+        match self {
+            Node::Env(env) => {
+                let env = env.try_borrow().ok()?;
+                env.values
+                    .values()
+                    .for_each(|value| references(value, &mut children));
+                if let Some(parent) = &env.parent {
+                    children.push(Node::Env(parent.clone()));
+                }
+            }
+            Node::Items(items) => items
+                .try_borrow()
+                .ok()?
+                .iter()
+                .for_each(|value| references(value, &mut children)),
+            Node::Map(map) => map
+                .try_borrow()
+                .ok()?
+                .values()
+                .for_each(|value| references(value, &mut children)),
+            Node::Fields(fields) => fields
+                .try_borrow()
+                .ok()?
+                .iter()
+                .for_each(|(_, value)| references(value, &mut children)),
+            Node::Wrapped(value) => references(value, &mut children),
+        }
+        Some(children)
+    }
+
+    /// 中身を空にして循環を断つ (取り出した値は借用を返してから捨てる)。環境なら true
+    fn clear(&self) -> bool {
+        match self {
+            Node::Env(env) => {
+                let Ok(mut env) = env.try_borrow_mut() else {
+                    return false;
+                };
+                let values = std::mem::take(&mut env.values);
+                let parent = env.parent.take();
+                drop(env);
+                drop((values, parent));
+                true
+            }
+            Node::Items(items) => {
+                let taken = items
+                    .try_borrow_mut()
+                    .map(|mut items| std::mem::take(&mut *items));
+                drop(taken);
+                false
+            }
+            Node::Map(map) => {
+                let taken = map
+                    .try_borrow_mut()
+                    .map(|mut map| std::mem::take(&mut *map));
+                drop(taken);
+                false
+            }
+            Node::Fields(fields) => {
+                let taken = fields
+                    .try_borrow_mut()
+                    .map(|mut fields| std::mem::take(&mut *fields));
+                drop(taken);
+                false
+            }
+            // 中身を変えられないので、参照している環境などが消えれば循環も消える
+            Node::Wrapped(_) => false,
+        }
+    }
+}
+
+/// 値が直接持っている Node
+fn references(value: &Value, children: &mut Vec<Node>) {
+    match value {
+        Value::List(items) | Value::Set(items) => children.push(Node::Items(items.clone())),
+        Value::Dict(map) | Value::Class(_, map) => children.push(Node::Map(map.clone())),
+        Value::Struct(_, fields) => children.push(Node::Fields(fields.clone())),
+        Value::Fn(_, env) => children.push(Node::Env(env.clone())),
+        Value::Ok(inner) | Value::Err(inner) | Value::Some(inner) => {
+            children.push(Node::Wrapped(inner.clone()))
+        }
+        Value::Return(inner) => references(inner, children),
+        _ => {}
+    }
+}
+
+/// たどった Node とその間の参照
+struct Graph {
+    nodes: Vec<Node>, // Node ごとに Rc を1つずつ持つ
+    edges: Vec<Vec<usize>>,
+    internal: Vec<usize>, // ほかの Node からの参照の数
+    opaque: Vec<bool>,    // 中身を借りられなかった (使われているので生きているとみなす)
+}
+
+impl Graph {
+    fn build(starts: Vec<Node>) -> Self {
+        let mut graph = Graph {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            internal: Vec::new(),
+            opaque: Vec::new(),
+        };
+        let mut index = HashMap::new();
+        for node in starts {
+            graph.add(&mut index, node);
+        }
+        let mut i = 0;
+        while i < graph.nodes.len() {
+            let children = graph.nodes[i].children();
+            graph.opaque[i] = children.is_none();
+            for child in children.unwrap_or_default() {
+                let j = graph.add(&mut index, child);
+                graph.internal[j] += 1;
+                graph.edges[i].push(j);
+            }
+            i += 1;
+        }
+        graph
+    }
+
+    /// Node の番号 (初めてなら加える。もう持っていれば渡された Rc は捨てる)
+    fn add(&mut self, index: &mut HashMap<usize, usize>, node: Node) -> usize {
+        let key = node.key();
+        if let Some(&i) = index.get(&key) {
+            return i;
+        }
+        index.insert(key, self.nodes.len());
+        self.nodes.push(node);
+        self.edges.push(Vec::new());
+        self.internal.push(0);
+        self.opaque.push(false);
+        self.nodes.len() - 1
+    }
+
+    /// 外から参照されているものとそこからたどれるもの以外を消し、消した環境の数を返す
+    fn clear_garbage(self) -> usize {
+        // 自分が持っている Rc の分 (1) を除いて、Node の外からの参照があれば生きている
+        let mut alive: Vec<bool> = (0..self.nodes.len())
+            .map(|i| self.opaque[i] || self.nodes[i].strong_count() > self.internal[i] + 1)
+            .collect();
+        let mut stack: Vec<usize> = (0..self.nodes.len()).filter(|&i| alive[i]).collect();
+        while let Some(i) = stack.pop() {
+            for &j in &self.edges[i] {
+                if !alive[j] {
+                    alive[j] = true;
+                    stack.push(j);
+                }
+            }
+        }
+        // 消している間は self.nodes が Rc を持っているので、途中で解放されるものはない
+        let collected = self
+            .nodes
+            .iter()
+            .zip(&alive)
+            .filter(|(node, alive)| !**alive && node.clear())
+            .count();
+        drop(self);
+        collected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::Interpreter;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn run(interpreter: &mut Interpreter, source: &str) -> String {
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        interpreter.run(&program).unwrap().display()
+    }
+
+    #[test]
+    fn test_collect_closure_cycles() {
+        let mut interpreter = Interpreter::new();
+        let source = "def make\n    let data = [1, 2, 3]\n    def inner\n        return data\n    return len(inner())\ndef counter\n    let n = 0\n    def inc\n        nonlocal n\n        n = n + 1\n        return n\n    return inc\nlet total = 0\nfor i in range(10)\n    total = total + make()\nlet c = counter()\nc()\n";
+        assert_eq!(run(&mut interpreter, source), "1");
+        // make の呼び出しの環境 10 個は循環だけで残っている。c が捕まえた環境は消さない
+        assert_eq!(interpreter.collect_cycles(), 10);
+        assert_eq!(interpreter.collect_cycles(), 0);
+        assert_eq!(run(&mut interpreter, "[total, c(), c()]\n"), "[30, 2, 3]");
+    }
+}
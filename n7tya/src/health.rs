@@ -0,0 +1,101 @@
+//! ヘルスチェック (ロードバランサーやコンテナの監視から使う)
+//!
+//! サーバーは `/healthz` と `/readyz` (パスは n7tya.toml の [server] で変えられる) に答える。
+//! `/healthz` はプロセスが動いていれば常に 200 を返し、起動してからの秒数とルートの一覧を添える。
+//! `/readyz` は `health.check("db", ping_db)` で登録した関数を順に呼び、
+//! すべて通れば 200、1つでも失敗すれば 503 を返す。
+//! 関数が false か Err(...) を返すか、実行時エラーになれば失敗とする。
+
+use crate::ast::ServerDef;
+use crate::interpreter::{route_infos, Interpreter, Value};
+use serde_json::json;
+use std::time::Duration;
+
+/// health.check(name, fn)
+pub fn call(
+    interpreter: &mut Interpreter,
+    function: &str,
+    args: Vec<Value>,
+) -> Result<Value, String> {
+    match (function, args.as_slice()) {
+        ("check", [Value::Str(name), check @ (Value::Fn(..) | Value::BuiltinFn(_))]) => {
+            interpreter.add_ready_check(name.to_string(), check.clone());
+            Ok(Value::None)
+        }
+        ("check", _) => Err("health.check() expects a name and a function".to_string()),
+        _ => Err(format!("Module 'health' has no member '{}'", function)),
+    }
+}
+
+/// `/healthz` の本文
+pub fn liveness(server: &ServerDef, uptime: Duration) -> String {
+    let routes: Vec<String> = route_infos(server)
+        .iter()
+        .map(|route| route.handler.clone())
+        .collect();
+    json!({
+        "status": "ok",
+        "server": server.name,
+        "uptime_seconds": uptime.as_secs(),
+        "routes": routes,
+    })
+    .to_string()
+}
+
+/// `/readyz` の (ステータス, 本文)
+pub fn readiness(interpreter: &mut Interpreter) -> (&'static str, String) {
+    let mut ready = true;
+    let mut checks = Vec::new();
+    for (name, check) in interpreter.ready_checks() {
+        let error = match interpreter.call_function(check, Vec::new()) {
+            Ok(Value::Bool(false)) => Some("returned false".to_string()),
+            Ok(Value::Err(e)) => Some(e.display()),
+            Ok(_) => None,
+            Err(e) => Some(e),
+        };
+        ready &= error.is_none();
+        checks.push(match error {
+            Some(error) => json!({ "name": name, "ok": false, "error": error }),
+            None => json!({ "name": name, "ok": true }),
+        });
+    }
+    let (status, text) = if ready {
+        ("200 OK", "ready")
+    } else {
+        ("503 Service Unavailable", "unavailable")
+    };
+    (
+        status,
+        json!({ "status": text, "checks": checks }).to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_readiness() {
+        let source = "def db_ok\n\treturn true\ndef cache_down\n\treturn Err(\"connection refused\")\nhealth.check(\"db\", db_ok)\n";
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&program).unwrap();
+        assert_eq!(
+            readiness(&mut interpreter),
+            (
+                "200 OK",
+                r#"{"checks":[{"name":"db","ok":true}],"status":"ready"}"#.to_string()
+            )
+        );
+
+        let program = Parser::new(Lexer::new("health.check(\"cache\", cache_down)\n").tokenize())
+            .parse()
+            .unwrap();
+        interpreter.run(&program).unwrap();
+        let (status, body) = readiness(&mut interpreter);
+        assert_eq!(status, "503 Service Unavailable");
+        assert!(body.contains(r#"{"error":"connection refused","name":"cache","ok":false}"#));
+    }
+}
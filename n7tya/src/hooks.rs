@@ -0,0 +1,211 @@
+//! 埋め込む側 (Rust) がルートの処理の前後に差し込むフック
+//!
+//! 認証やトレース、メトリクスなどを、インタプリタのサーバーのコードを変えずに加えられる。
+//! `Interpreter::add_request_hook` で登録したフックは、静的ファイル以外のリクエストごとに
+//! 登録順に呼ばれる。`before` がレスポンスを返すと、CSRF の確認もルートも呼ばずにそれを返す。
+//!
+//! ```ignore
+//! struct RequireToken;
+//!
+//! impl RequestHook for RequireToken {
+//!     fn before(&mut self, request: &Request) -> Option<Response> {
+//!         let authorized = request.headers.get("authorization").map(String::as_str)
+//!             == Some("Bearer secret");
+//!         (!authorized).then(|| Response::new(401, "Unauthorized"))
+//!     }
+//! }
+//!
+//! interpreter.add_request_hook(RequireToken);
+//! ```
+
+use crate::interpreter::Value;
+use std::collections::HashMap;
+
+/// フックに渡すリクエスト
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    /// リクエストを受けたサーバーの名前
+    pub server: String,
+    pub method: String,
+    /// クエリ文字列を含むパス
+    pub path: String,
+    /// ヘッダー (名前は小文字)
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+impl Request {
+    pub(crate) fn new(
+        server: &str,
+        method: &str,
+        path: &str,
+        headers: &HashMap<String, Value>,
+        body: &str,
+    ) -> Self {
+        let headers = headers
+            .iter()
+            .map(|(name, value)| match value {
+                Value::Str(s) => (name.clone(), s.to_string()),
+                v => (name.clone(), v.display()),
+            })
+            .collect();
+        Self {
+            server: server.to_string(),
+            method: method.to_string(),
+            path: path.to_string(),
+            headers,
+            body: body.to_string(),
+        }
+    }
+}
+
+/// フックが返す、または書き換えるレスポンス
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: String,
+    pub body: String,
+}
+
+impl Response {
+    /// テキストのレスポンス
+    pub fn new(status: u16, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8".to_string(),
+            body: body.into(),
+        }
+    }
+}
+
+/// ルートの処理の前後に呼ばれるフック
+pub trait RequestHook {
+    /// ルートを呼ぶ前。Some を返すと、ルートを呼ばずにそのレスポンスを返す
+    fn before(&mut self, request: &Request) -> Option<Response> {
+        let _ = request;
+        None
+    }
+
+    /// レスポンスを書く前 (before が返したレスポンスにも呼ぶ)。ステータスや本文を書き換えられる
+    fn after(&mut self, request: &Request, response: &mut Response) {
+        let _ = (request, response);
+    }
+}
+
+/// "404 Not Found" のようなステータス行の数値
+pub(crate) fn status_code(status: &str) -> u16 {
+    status
+        .split(' ')
+        .next()
+        .and_then(|code| code.parse().ok())
+        .unwrap_or(500)
+}
+
+/// ステータスの数値からステータス行を作る
+pub(crate) fn status_line(code: u16) -> String {
+    let reason = match code {
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        422 => "Unprocessable Entity",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        _ => return code.to_string(),
+    };
+    format!("{} {}", code, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use std::cell::RefCell;
+    use std::io::{Read, Write};
+    use std::rc::Rc;
+
+    /// /admin は Authorization ヘッダーがなければ 401 にし、レスポンスの数を数える
+    struct Guard {
+        seen: Rc<RefCell<Vec<(String, u16)>>>,
+    }
+
+    impl RequestHook for Guard {
+        fn before(&mut self, request: &Request) -> Option<Response> {
+            let denied = request.path == "/admin" && !request.headers.contains_key("authorization");
+            denied.then(|| Response::new(401, "Unauthorized"))
+        }
+
+        fn after(&mut self, request: &Request, response: &mut Response) {
+            self.seen
+                .borrow_mut()
+                .push((request.path.clone(), response.status));
+            response.body.push_str(" (hooked)");
+        }
+    }
+
+    fn get(port: i64, path: &str, headers: &str) -> String {
+        let mut stream = std::net::TcpStream::connect(("127.0.0.1", port as u16)).unwrap();
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\n{}\r\n",
+            path, headers
+        );
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_request_hooks() {
+        let source = "server Api\n\tget \"/admin\"\n\t\treturn \"secret\"\n\tget \"/stop\"\n\t\tapi.stop()\n\t\treturn \"bye\"\nlet api = Api.start(0)\napi.port()\n";
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        interpreter.add_request_hook(Guard { seen: seen.clone() });
+        let Ok(Value::Int(port)) = interpreter.run(&program) else {
+            panic!("expected a port");
+        };
+
+        let client = std::thread::spawn(move || {
+            [
+                get(port, "/admin", ""),
+                get(port, "/admin", "Authorization: Bearer x\r\n"),
+                get(port, "/stop", ""),
+            ]
+        });
+        let program = Parser::new(Lexer::new("api.wait()\n").tokenize())
+            .parse()
+            .unwrap();
+        interpreter.run(&program).unwrap();
+        let [denied, allowed, _] = client.join().unwrap();
+
+        assert!(denied.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+        assert!(denied.ends_with("\r\n\r\nUnauthorized (hooked)"));
+        assert!(allowed.ends_with("\r\n\r\nsecret (hooked)"));
+        assert_eq!(
+            *seen.borrow(),
+            vec![
+                ("/admin".to_string(), 401),
+                ("/admin".to_string(), 200),
+                ("/stop".to_string(), 200),
+            ]
+        );
+        assert_eq!(status_line(status_code("303 See Other")), "303 See Other");
+    }
+}
@@ -0,0 +1,721 @@
+//! http モジュール (HTTP クライアント)
+//!
+//! `http.get(url, params?, options?)`、`http.post/put/patch(url, body, options?)`、`http.delete(url, options?)`
+//! はレスポンスの辞書を返す: status / ok (2xx か) / headers (名前は小文字) / body / url (リダイレクト後) / json()。
+//! 4xx・5xx もレスポンスとして返し、options の `"raise_for_status": true` のときだけエラーにする。
+//! options には ほかに headers (辞書)、timeout (秒数か 5s のような Duration)、redirects (たどる回数、0 でたどらない) を書ける。
+//! リクエストは値を取り出してから送るので、await_all / race では別スレッドで同時に送れる。
+//!
+//! `http.download(url, path, options?)` はレスポンスの本体をメモリに溜めずにファイルへ書き、
+//! body の代わりに path と size (バイト数) を入れた辞書を返す。2xx 以外はファイルを作らずにエラーにする。
+//! options の progress に関数を渡すと、受け取るたびに (受け取ったバイト数, 全体のバイト数か none) で呼ぶ。
+
+use crate::builtins::{json_to_value, value_to_json};
+use crate::interpreter::Value;
+use crate::permissions::Permissions;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// http モジュールのリクエストを送る関数
+pub const METHODS: &[&str] = &["get", "post", "put", "patch", "delete"];
+
+/// レスポンスの辞書の json キーに入れる関数 (呼ぶと body を JSON として読む)
+pub const JSON_METHOD: &str = "__http_json";
+
+/// http.get などリクエストを送る関数の名前か
+pub fn is_request(name: &str) -> bool {
+    name.strip_prefix("http.")
+        .is_some_and(|method| METHODS.contains(&method))
+}
+
+/// 別スレッドでも送れるように値を取り出した HTTP リクエスト
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpRequest {
+    pub method: &'static str,
+    pub url: String,
+    pub body: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub timeout: Option<Duration>,
+    pub redirects: u32,
+    pub raise_for_status: bool,
+    pub permissions: Permissions, // リダイレクト先の接続も確かめる
+}
+
+/// 受け取ったレスポンス (送ったスレッドから値にする前の形)
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub status_text: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    pub url: String,
+}
+
+impl HttpRequest {
+    /// http.get / http.post などの引数からリクエストを作る
+    pub fn from_args(name: &str, args: &[Value]) -> Result<Self, String> {
+        let (method, verb) = match name {
+            "http.get" => ("get", "GET"),
+            "http.post" => ("post", "POST"),
+            "http.put" => ("put", "PUT"),
+            "http.patch" => ("patch", "PATCH"),
+            "http.delete" => ("delete", "DELETE"),
+            _ => return Err(format!("Unknown builtin function: {}", name)),
+        };
+        let usage = || match method {
+            "get" => "http.get() expects (url: Str, params?: Dict, options?: Dict)".to_string(),
+            "delete" => "http.delete() expects (url: Str, options?: Dict)".to_string(),
+            _ => format!("http.{}() expects (url: Str, body, options?: Dict)", method),
+        };
+        let Some((Value::Str(url), rest)) = args.split_first() else {
+            return Err(usage());
+        };
+        let mut request = HttpRequest {
+            method: verb,
+            url: url.to_string(),
+            body: None,
+            headers: Vec::new(),
+            timeout: None,
+            redirects: 5,
+            raise_for_status: false,
+            permissions: Permissions::allow_all(),
+        };
+        let options = match (method, rest) {
+            ("get", [params, options @ ..]) if options.len() <= 1 => {
+                match params {
+                    // 2つ目の辞書はクエリ文字列にして URL に付ける
+                    Value::Dict(params) => {
+                        request.url = with_query(&request.url, &params.borrow())?
+                    }
+                    Value::None => {}
+                    _ => return Err(usage()),
+                }
+                options.first()
+            }
+            ("get", []) | ("delete", []) => None,
+            ("delete", [options]) => Some(options),
+            ("post" | "put" | "patch", [body, options @ ..]) if options.len() <= 1 => {
+                request.body = Some(match body {
+                    Value::Str(s) => s.to_string(),
+                    // 文字列以外は JSON にして送る
+                    body => serde_json::to_string(&value_to_json(body)).unwrap_or_default(),
+                });
+                options.first()
+            }
+            _ => return Err(usage()),
+        };
+        match options {
+            None | Some(Value::None) => {}
+            Some(Value::Dict(options)) => request.apply_options(name, &options.borrow())?,
+            Some(other) => {
+                return Err(format!(
+                    "{}() options must be a dict, got {}",
+                    name,
+                    other.type_name()
+                ))
+            }
+        }
+        Ok(request)
+    }
+
+    fn apply_options(
+        &mut self,
+        name: &str,
+        options: &HashMap<String, Value>,
+    ) -> Result<(), String> {
+        for (key, value) in crate::interpreter::sorted_entries(options) {
+            match (key.as_str(), value) {
+                ("headers", Value::Dict(headers)) => {
+                    for (header, value) in crate::interpreter::sorted_entries(&headers.borrow()) {
+                        let value = match value {
+                            Value::Str(s) => s.to_string(),
+                            value => value.display(),
+                        };
+                        self.headers.push((header.clone(), value));
+                    }
+                }
+                ("timeout", value) => {
+                    let seconds = crate::units::seconds(value).unwrap_or(f64::NAN);
+                    if !(seconds.is_finite() && seconds > 0.0) {
+                        return Err(format!(
+                            "{}() timeout must be a positive number of seconds, got {}",
+                            name,
+                            value.display()
+                        ));
+                    }
+                    self.timeout = Some(Duration::from_secs_f64(seconds));
+                }
+                ("redirects", Value::Int(n)) if *n >= 0 => {
+                    self.redirects = u32::try_from(*n).unwrap_or(u32::MAX)
+                }
+                ("raise_for_status", Value::Bool(raise)) => self.raise_for_status = *raise,
+                ("headers" | "redirects" | "raise_for_status", value) => {
+                    return Err(format!(
+                        "{}() option '{}' has an invalid value {}",
+                        name,
+                        key,
+                        value.display()
+                    ))
+                }
+                _ => {
+                    return Err(format!(
+                        "{}() has no option '{}' (expected headers, timeout, redirects or raise_for_status)",
+                        name, key
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// リクエストを送ってレスポンスを返す (4xx・5xx も raise_for_status でなければレスポンス)
+    pub fn send(&self) -> Result<HttpResponse, String> {
+        let response = HttpResponse::read(self.call()?)?;
+        if self.raise_for_status && !response.ok() {
+            return Err(self.status_error(&response));
+        }
+        Ok(response)
+    }
+
+    fn status_error(&self, response: &HttpResponse) -> String {
+        format!(
+            "HTTP {} {}: {} {}",
+            response.status, response.status_text, self.method, response.url
+        )
+    }
+
+    /// 送って本体を読む前のレスポンスを返す
+    ///
+    /// ネットワークを絞っているときはリダイレクトを自分でたどり、移る先ごとに --allow-net を確かめる
+    fn call(&self) -> Result<ureq::Response, String> {
+        if !self.permissions.restricts_net() {
+            return self.send_to(self.method, &self.url, self.body.as_deref(), self.redirects);
+        }
+        let (mut method, mut url, mut body) = (self.method, self.url.clone(), self.body.as_deref());
+        let mut followed = 0;
+        loop {
+            let response = self.send_to(method, &url, body, 0)?;
+            let location = match response.status() {
+                301 | 302 | 303 | 307 | 308 => response.header("location"),
+                _ => None,
+            };
+            let Some(location) = location.filter(|_| followed < self.redirects) else {
+                return Ok(response);
+            };
+            url = url::Url::parse(response.get_url())
+                .and_then(|base| base.join(location))
+                .map_err(|e| {
+                    format!(
+                        "HTTP {} error: invalid redirect to {}: {}",
+                        method, location, e
+                    )
+                })?
+                .to_string();
+            self.permissions.check_url(&url)?;
+            // 307・308 のほかは GET で本体なしに移る
+            if !matches!(response.status(), 307 | 308) && method != "HEAD" {
+                method = "GET";
+                body = None;
+            }
+            followed += 1;
+        }
+    }
+
+    fn send_to(
+        &self,
+        method: &str,
+        url: &str,
+        body: Option<&str>,
+        redirects: u32,
+    ) -> Result<ureq::Response, String> {
+        let mut agent = ureq::AgentBuilder::new().redirects(redirects);
+        if let Some(timeout) = self.timeout {
+            agent = agent.timeout(timeout);
+        }
+        let mut request = agent.build().request(method, url);
+        let has_content_type = self
+            .headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("content-type"));
+        if body.is_some() && !has_content_type {
+            request = request.set("Content-Type", "application/json");
+        }
+        for (name, value) in &self.headers {
+            request = request.set(name, value);
+        }
+        let result = match body {
+            Some(body) => request.send_string(body),
+            None => request.call(),
+        };
+        match result {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => Ok(response),
+            Err(e) => Err(format!("HTTP {} error: {}", method, e)),
+        }
+    }
+}
+
+impl HttpResponse {
+    fn read(response: ureq::Response) -> Result<Self, String> {
+        let mut head = Self::head(&response);
+        head.body = response
+            .into_string()
+            .map_err(|e| format!("HTTP response from {} could not be read: {}", head.url, e))?;
+        Ok(head)
+    }
+
+    /// 本体を読まずに、ステータスとヘッダーだけを取り出す
+    fn head(response: &ureq::Response) -> Self {
+        let mut headers: Vec<(String, String)> = Vec::new();
+        for name in response.headers_names() {
+            if headers.iter().any(|(seen, _)| *seen == name) {
+                continue;
+            }
+            // 同じ名前のヘッダーが複数あれば ", " でつなぐ
+            let value = response.all(&name).join(", ");
+            headers.push((name, value));
+        }
+        HttpResponse {
+            status: response.status(),
+            status_text: response.status_text().to_string(),
+            headers,
+            body: String::new(),
+            url: response.get_url().to_string(),
+        }
+    }
+
+    /// 2xx か
+    pub fn ok(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// レスポンスの辞書にする
+    pub fn into_value(self) -> Value {
+        let ok = self.ok();
+        let dict = |entries: HashMap<String, Value>| Value::Dict(Rc::new(RefCell::new(entries)));
+        let headers = self
+            .headers
+            .into_iter()
+            .map(|(name, value)| (name, Value::Str(value.into())))
+            .collect();
+        let mut response = HashMap::new();
+        response.insert("status".to_string(), Value::Int(self.status as i64));
+        response.insert("ok".to_string(), Value::Bool(ok));
+        response.insert("headers".to_string(), dict(headers));
+        response.insert("body".to_string(), Value::Str(self.body.into()));
+        response.insert("url".to_string(), Value::Str(self.url.into()));
+        response.insert(
+            "json".to_string(),
+            Value::BuiltinFn(JSON_METHOD.to_string()),
+        );
+        dict(response)
+    }
+}
+
+/// http.download の引数 (progress の関数はスレッドをまたげないので別に持つ)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Download {
+    pub request: HttpRequest,
+    pub path: PathBuf,
+}
+
+/// 別スレッドでダウンロードしているあいだに届く知らせ
+#[derive(Debug)]
+pub enum DownloadEvent {
+    Progress(u64, Option<u64>), // 受け取ったバイト数, Content-Length
+    Done(Result<(HttpResponse, u64), String>),
+}
+
+impl Download {
+    /// http.download(url, path, options?) の引数から、ダウンロードと progress の関数を取り出す
+    pub fn from_args(args: &[Value]) -> Result<(Self, Option<Value>), String> {
+        let usage = || "http.download() expects (url: Str, path: Str, options?: Dict)".to_string();
+        let (url, path, options) = match args {
+            [Value::Str(url), Value::Str(path)] => (url, path, None),
+            [Value::Str(url), Value::Str(path), options] => (url, path, Some(options)),
+            _ => return Err(usage()),
+        };
+        let mut request = HttpRequest::from_args("http.get", &[Value::Str(url.clone())])?;
+        let mut progress = None;
+        match options {
+            None | Some(Value::None) => {}
+            Some(Value::Dict(options)) => {
+                let mut options = options.borrow().clone();
+                progress = options.remove("progress");
+                if let Some(callback) = &progress {
+                    if !matches!(callback, Value::Fn(..) | Value::BuiltinFn(_)) {
+                        return Err(format!(
+                            "http.download() option 'progress' must be a function, got {}",
+                            callback.type_name()
+                        ));
+                    }
+                }
+                request.apply_options("http.download", &options)?;
+            }
+            Some(other) => {
+                return Err(format!(
+                    "http.download() options must be a dict, got {}",
+                    other.type_name()
+                ))
+            }
+        }
+        request.raise_for_status = true;
+        let download = Download {
+            request,
+            path: PathBuf::from(path.to_string()),
+        };
+        Ok((download, progress))
+    }
+
+    /// ダウンロードして path に書き、レスポンス (body は空) と書いたバイト数を返す
+    ///
+    /// 本体は path の隣の .part に書いてから置き換えるので、途中で失敗しても path は壊れない。
+    pub fn run(&self, events: &mpsc::Sender<DownloadEvent>) -> Result<(HttpResponse, u64), String> {
+        let response = self.request.call()?;
+        let head = HttpResponse::head(&response);
+        if !head.ok() {
+            return Err(self.request.status_error(&head));
+        }
+        let total = response
+            .header("content-length")
+            .and_then(|length| length.trim().parse().ok());
+        let part = part_path(&self.path);
+        let written = write_body(response.into_reader(), &part, total, events);
+        let size = match written {
+            Ok(size) => size,
+            Err(e) => {
+                let _ = std::fs::remove_file(&part);
+                return Err(format!("HTTP download from {} failed: {}", head.url, e));
+            }
+        };
+        std::fs::rename(&part, &self.path).map_err(|e| {
+            let _ = std::fs::remove_file(&part);
+            format!("Failed to write {}: {}", self.path.display(), e)
+        })?;
+        Ok((head, size))
+    }
+
+    /// 別スレッドでダウンロードを始める (終わると DownloadEvent::Done が届く)
+    pub fn spawn(self) -> mpsc::Receiver<DownloadEvent> {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let result = self.run(&sender);
+            let _ = sender.send(DownloadEvent::Done(result));
+        });
+        receiver
+    }
+}
+
+/// ダウンロード中に書くファイル (path.part)
+fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+/// 本体を少しずつ読んでファイルに書き、読むたびに進み具合を知らせる
+fn write_body(
+    mut reader: impl Read,
+    path: &Path,
+    total: Option<u64>,
+    events: &mpsc::Sender<DownloadEvent>,
+) -> std::io::Result<u64> {
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let mut buffer = vec![0; 64 * 1024];
+    let mut received = 0;
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        file.write_all(&buffer[..n])?;
+        received += n as u64;
+        // 待っている側がいなければ (progress がエラーになった) やめる
+        if events.send(DownloadEvent::Progress(received, total)).is_err() {
+            return Err(std::io::Error::other("cancelled"));
+        }
+    }
+    file.flush()?;
+    Ok(received)
+}
+
+/// http.download の結果の辞書 (body の代わりに path と size を入れる)
+pub fn download_value(response: HttpResponse, path: &Path, size: u64) -> Value {
+    let value = response.into_value();
+    if let Value::Dict(dict) = &value {
+        let mut dict = dict.borrow_mut();
+        dict.remove("body");
+        dict.remove("json");
+        dict.insert(
+            "path".to_string(),
+            Value::Str(path.to_string_lossy().into_owned().into()),
+        );
+        dict.insert("size".to_string(), Value::Int(size as i64));
+    }
+    value
+}
+
+/// http.get などを送ってレスポンスの辞書を返す
+pub fn call(name: &str, args: Vec<Value>) -> Result<Value, String> {
+    Ok(HttpRequest::from_args(name, &args)?.send()?.into_value())
+}
+
+/// response.json(): レスポンスの body を JSON として読む
+pub fn response_json(response: &HashMap<String, Value>) -> Result<Value, String> {
+    let Some(Value::Str(body)) = response.get("body") else {
+        return Err("json() expects an HTTP response with a body".to_string());
+    };
+    serde_json::from_str(body)
+        .map(json_to_value)
+        .map_err(|e| format!("The response body is not valid JSON: {}", e))
+}
+
+fn with_query(url: &str, params: &HashMap<String, Value>) -> Result<String, String> {
+    let query = crate::url::build_query(params)?;
+    if query.is_empty() {
+        return Ok(url.to_string());
+    }
+    let separator = if url.contains('?') { '&' } else { '?' };
+    Ok(format!("{}{}{}", url, separator, query))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::testing::run;
+
+    fn request(name: &str, source: &str) -> Result<HttpRequest, String> {
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let Value::List(args) = Interpreter::new().run(&program)? else {
+            panic!("expected a list of arguments");
+        };
+        let args = args.borrow().clone();
+        HttpRequest::from_args(name, &args)
+    }
+
+    #[test]
+    fn test_request_from_args() {
+        let get = request(
+            "http.get",
+            "[\"http://a.test/s?x=1\", {\"q\": \"a b\"}, {\"headers\": {\"X-Token\": 7}, \"timeout\": 1500ms, \"redirects\": 0}]\n",
+        )
+        .unwrap();
+        assert_eq!(get.method, "GET");
+        assert_eq!(get.url, "http://a.test/s?x=1&q=a%20b");
+        assert_eq!(get.headers, vec![("X-Token".to_string(), "7".to_string())]);
+        assert_eq!(get.timeout, Some(Duration::from_millis(1500)));
+        assert_eq!(get.redirects, 0);
+
+        let put = request("http.put", "[\"http://a.test/u\", {\"name\": \"Taro\"}]\n").unwrap();
+        assert_eq!(put.method, "PUT");
+        assert_eq!(put.body.as_deref(), Some("{\"name\":\"Taro\"}"));
+        let delete = request("http.delete", "[\"http://a.test/u\", {\"timeout\": 2}]\n").unwrap();
+        assert_eq!(
+            (delete.body, delete.timeout),
+            (None, Some(Duration::from_secs(2)))
+        );
+    }
+
+    #[test]
+    fn test_request_argument_errors() {
+        assert_eq!(
+            request("http.delete", "[\"http://a.test\", \"body\"]\n").unwrap_err(),
+            "http.delete() options must be a dict, got Str"
+        );
+        assert_eq!(
+            request("http.patch", "[\"http://a.test\"]\n").unwrap_err(),
+            "http.patch() expects (url: Str, body, options?: Dict)"
+        );
+        assert_eq!(
+            request("http.get", "[\"http://a.test\", none, {\"timeout\": 0}]\n").unwrap_err(),
+            "http.get() timeout must be a positive number of seconds, got 0"
+        );
+        assert_eq!(
+            request("http.get", "[\"http://a.test\", none, {\"retries\": 3}]\n").unwrap_err(),
+            "http.get() has no option 'retries' (expected headers, timeout, redirects or raise_for_status)"
+        );
+    }
+
+    #[test]
+    fn test_download_argument_errors() {
+        let args = [
+            Value::Str("http://a.test/f".into()),
+            Value::Str("f.bin".into()),
+            Value::Dict(Rc::new(RefCell::new(HashMap::from([(
+                "progress".to_string(),
+                Value::Int(1),
+            )])))),
+        ];
+        assert_eq!(
+            Download::from_args(&args).unwrap_err(),
+            "http.download() option 'progress' must be a function, got Int"
+        );
+        assert_eq!(
+            Download::from_args(&args[..1]).unwrap_err(),
+            "http.download() expects (url: Str, path: Str, options?: Dict)"
+        );
+    }
+
+    #[test]
+    fn test_response_status_headers_and_redirects() {
+        let source = "server Api
+\tget \"/user\"
+\t\treturn \"{\\\"name\\\": \\\"Taro\\\"}\"
+\tget \"/echo\"
+\t\treturn request[\"headers\"][\"x-token\"]
+\tput \"/user\"
+\t\treturn request[\"method\"]
+\tpatch \"/user\"
+\t\treturn request[\"method\"]
+\tget \"/old\"
+\t\treturn redirect(\"/user\")
+let handle = Api.start(0)
+let base = \"http://127.0.0.1:\" + str(handle.port())
+let user = http.get(base + \"/user\")
+let missing = http.get(base + \"/missing\")
+let moved = http.get(base + \"/old\", none, {\"redirects\": 0})
+let results = [
+\t[user[\"status\"], user[\"ok\"], user.json()[\"name\"], user[\"headers\"][\"content-length\"]],
+\t[missing[\"status\"], missing[\"ok\"], missing[\"body\"]],
+\thttp.get(base + \"/echo\", none, {\"headers\": {\"X-Token\": \"abc\"}})[\"body\"],
+\thttp.put(base + \"/user\", {\"name\": \"Jiro\"})[\"body\"],
+\thttp.patch(base + \"/user\", {\"age\": 21})[\"body\"],
+\t[moved[\"status\"], http.get(base + \"/old\")[\"url\"] == base + \"/user\"]
+]
+handle.stop()
+results
+";
+        assert_eq!(
+            run(source).unwrap(),
+            "[[200, true, Taro, 16], [404, false, Not Found], abc, PUT, PATCH, [303, true]]"
+        );
+    }
+
+    #[test]
+    fn test_raise_for_status() {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/broken", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let _ = stream.read(&mut [0; 1024]);
+                stream
+                    .write_all(b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 4\r\nX-Id: 1\r\nX-Id: 2\r\n\r\nboom")
+                    .unwrap();
+            }
+        });
+        let mut request =
+            HttpRequest::from_args("http.get", &[Value::Str(url.as_str().into())]).unwrap();
+        let response = request.send().unwrap();
+        assert_eq!((response.status, response.ok()), (500, false));
+        assert!(response
+            .headers
+            .contains(&("x-id".to_string(), "1, 2".to_string())));
+        request.raise_for_status = true;
+        assert_eq!(
+            request.send().unwrap_err(),
+            format!("HTTP 500 Internal Server Error: GET {}", url)
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_restricted_redirects_are_checked() {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            for location in ["/next", "http://evil.test/steal"] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let _ = stream.read(&mut [0; 1024]);
+                let response = format!(
+                    "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\n\r\n",
+                    location
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        let url = format!("http://{}/start", addr);
+        let mut request = HttpRequest::from_args("http.get", &[Value::Str(url.into())]).unwrap();
+        let args = [format!("--allow-net={}", addr)];
+        request.permissions = Permissions::from_args(&args).unwrap();
+        let error = request.send().unwrap_err();
+        assert!(
+            error.contains("Requires net access to 'evil.test:80'"),
+            "{}",
+            error
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_download_streams_to_file() {
+        use std::io::{Read, Write};
+        let body: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let sent = body.clone();
+        let server = std::thread::spawn(move || {
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0; 1024];
+                let n = stream.read(&mut request).unwrap();
+                if String::from_utf8_lossy(&request[..n]).starts_with("GET /missing") {
+                    stream
+                        .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 4\r\n\r\nnope")
+                        .unwrap();
+                    continue;
+                }
+                let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", sent.len());
+                stream.write_all(head.as_bytes()).unwrap();
+                // 何回かに分けて送る
+                for chunk in sent.chunks(50_000) {
+                    stream.write_all(chunk).unwrap();
+                    stream.flush().unwrap();
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+            }
+        });
+
+        let dir = std::env::temp_dir().join(format!("n7tya-download-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("data.bin");
+        let source = format!(
+            "let calls = []
+def progress received total
+\tcalls.append([received, total])
+let r = http.download(\"{base}/data\", \"{path}\", {{\"progress\": progress}})
+[r[\"status\"], r[\"size\"], r[\"path\"], len(calls) > 1, calls[len(calls) - 1]]
+",
+            path = path.display()
+        );
+        assert_eq!(
+            run(&source).unwrap(),
+            format!("[200, 200000, {}, true, [200000, 200000]]", path.display())
+        );
+        assert_eq!(std::fs::read(&path).unwrap(), body);
+
+        // 2xx 以外はファイルを作らない
+        let missing = dir.join("missing.bin");
+        let source = format!(
+            "http.download(\"{}/missing\", \"{}\")\n",
+            base,
+            missing.display()
+        );
+        assert_eq!(
+            run(&source).unwrap_err(),
+            format!("HTTP 404 Not Found: GET {}/missing", base)
+        );
+        assert!(!missing.exists() && !part_path(&missing).exists());
+        server.join().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
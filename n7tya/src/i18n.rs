@@ -0,0 +1,385 @@
+//! CLI 出力と診断メッセージの翻訳
+//!
+//! メッセージは英語で組み立て、出力する直前にカタログで置き換える。
+//! カタログの英語側の `{}` は任意の文字列に一致し、日本語側の `{0}`, `{1}` ... に順に埋め込まれる。
+//! カタログにないメッセージは英語のまま表示する。
+
+use std::sync::OnceLock;
+
+/// 表示言語
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lang {
+    En,
+    Ja,
+}
+
+static LANG: OnceLock<Lang> = OnceLock::new();
+
+/// 現在の表示言語 (init 前は英語)
+pub fn lang() -> Lang {
+    LANG.get().copied().unwrap_or(Lang::En)
+}
+
+/// 引数の `--lang ja|en` (または `--lang=ja`) を取り除いて表示言語を決める
+/// (指定がなければ N7TYA_LANG, LC_ALL, LC_MESSAGES, LANG の順にロケールを見る)
+pub fn init(args: &mut Vec<String>) -> Result<(), String> {
+    let lang = take_lang(args)?.unwrap_or_else(detect_from_env);
+    let _ = LANG.set(lang);
+    Ok(())
+}
+
+/// 引数から `--lang` を取り除く (`--` より後はプログラムに渡す引数なのでそのまま残す)
+pub(crate) fn take_lang(args: &mut Vec<String>) -> Result<Option<Lang>, String> {
+    let mut requested = None;
+    let mut i = 0;
+    while i < args.len() && args[i] != "--" {
+        if args[i] == "--lang" {
+            args.remove(i);
+            if i >= args.len() || args[i] == "--" {
+                return Err("--lang requires a value (ja or en)".to_string());
+            }
+            requested = Some(args.remove(i));
+        } else if let Some(value) = args[i].strip_prefix("--lang=") {
+            requested = Some(value.to_string());
+            args.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+
+    match requested.as_deref() {
+        Some("ja") => Ok(Some(Lang::Ja)),
+        Some("en") => Ok(Some(Lang::En)),
+        Some(other) => Err(format!("Unknown language '{}' (expected ja or en)", other)),
+        None => Ok(None),
+    }
+}
+
+fn detect_from_env() -> Lang {
+    let locale = ["N7TYA_LANG", "LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|key| std::env::var(key).ok())
+        .find(|value| !value.is_empty());
+    match locale {
+        Some(locale) if locale.starts_with("ja") => Lang::Ja,
+        _ => Lang::En,
+    }
+}
+
+/// println! と同じ書式で組み立てた行を、翻訳してから出力する
+#[macro_export]
+macro_rules! say {
+    () => {
+        println!()
+    };
+    ($($arg:tt)*) => {
+        println!("{}", $crate::i18n::tr(&format!($($arg)*)))
+    };
+}
+
+/// 英語で組み立てたメッセージを表示言語に翻訳する
+pub fn tr(message: &str) -> String {
+    translate(lang(), message)
+}
+
+fn translate(lang: Lang, message: &str) -> String {
+    if lang == Lang::En {
+        return message.to_string();
+    }
+    for (en, ja) in CATALOG {
+        if let Some(captures) = match_template(en, message) {
+            // 埋め込まれた部分 (エラーメッセージなど) も翻訳する
+            let mut result = ja.to_string();
+            for (i, capture) in captures.iter().enumerate() {
+                result = result.replace(&format!("{{{}}}", i), &translate(lang, capture));
+            }
+            return result;
+        }
+    }
+    message.to_string()
+}
+
+/// message がテンプレートに一致すれば `{}` に当たる部分を返す
+fn match_template<'a>(template: &str, message: &'a str) -> Option<Vec<&'a str>> {
+    let pieces: Vec<&str> = template.split("{}").collect();
+    let (first, rest) = pieces.split_first()?;
+    let mut remaining = message.strip_prefix(first)?;
+    let mut captures = Vec::new();
+
+    for (i, piece) in rest.iter().enumerate() {
+        if i == rest.len() - 1 {
+            // 最後の部分は末尾に一致させる
+            let capture = remaining.strip_suffix(piece)?;
+            if capture.is_empty() {
+                return None;
+            }
+            captures.push(capture);
+            remaining = "";
+        } else {
+            // `{}` は空文字列に一致させない
+            let skip = remaining.chars().next()?.len_utf8();
+            let at = remaining[skip..].find(piece)? + skip;
+            captures.push(&remaining[..at]);
+            remaining = &remaining[at + piece.len()..];
+        }
+    }
+
+    if rest.is_empty() && !remaining.is_empty() {
+        return None;
+    }
+    Some(captures)
+}
+
+/// 翻訳カタログ (英語テンプレート, 日本語)。上から順に試す
+#[rustfmt::skip]
+const CATALOG: &[(&str, &str)] = &[
+    // ===== CLI =====
+    ("Usage:", "使い方:"),
+    ("  n7tya <file.n7t>    Run a file", "  n7tya <file.n7t>    ファイルを実行"),
+    ("  n7tya run           Run project", "  n7tya run           プロジェクトを実行"),
+    ("    --config <file>   Read [server] and [config] from another file", "    --config <file>   [server] と [config] を別のファイルから読む"),
+    ("                      Only allow these capabilities to builtins", "                      組み込み関数にこれらの権限だけを許す"),
+    ("                      Stop the program (and each route handler) past these limits", "                      プログラム (とルートの処理1回ごと) がこの上限を超えたら止める"),
+    ("    --time-startup    Print how long startup, parsing, checking and running took", "    --time-startup    起動・構文解析・型チェック・実行にかかった時間を表示する"),
+    ("  n7tya build         Build project", "  n7tya build         プロジェクトをビルド"),
+    ("    --static          Also render GET routes to dist/ as a static site", "    --static          GET のルートを静的サイトとして dist/ にも書き出す"),
+    ("    --incremental     Only re-render pages whose data files changed", "    --incremental     読んだファイルが変わったページだけを書き直す"),
+    ("  n7tya test          Run tests", "  n7tya test          テストを実行"),
+    ("  n7tya new <name>    Create new project", "  n7tya new <name>    新規プロジェクトを作成"),
+    ("  n7tya fmt           Format code", "  n7tya fmt           コードを整形"),
+    ("  n7tya fix [file]    Apply lint fixes", "  n7tya fix [file]    リンターの自動修正を適用"),
+    ("  n7tya refactor      Rename a name or extract lines into a function", "  n7tya refactor      名前を変更するか、行を関数に切り出す"),
+    ("  n7tya check         Type check", "  n7tya check         型チェック"),
+    ("  n7tya add <source>  Add a dependency", "  n7tya add <source>  依存パッケージを追加"),
+    ("  n7tya --version     Show version", "  n7tya --version     バージョンを表示"),
+    ("  n7tya --update      Update n7tya", "  n7tya --update      n7tya を更新"),
+    ("Full-stack web programming language by @n7tya", "@n7tya によるフルスタック Web プログラミング言語"),
+    ("Usage: n7tya <command> [args]", "使い方: n7tya <command> [args]"),
+    ("Commands:", "コマンド:"),
+    ("Options:", "オプション:"),
+    ("  run                     Run project (requires n7tya.toml)", "  run                     プロジェクトを実行 (n7tya.toml が必要)"),
+    ("  <file.n7t>              Run a specific file", "  <file.n7t>              指定したファイルを実行"),
+    ("    --allow-<kind>[=<list>] Only allow read/write/net/env/run access", "    --allow-<kind>[=<list>] read/write/net/env/run のうち指定した権限だけを許す"),
+    ("    --timeout <secs>      Stop after this long (also --max-steps, --max-memory)", "    --timeout <secs>      この時間を超えたら止める (--max-steps, --max-memory も使える)"),
+    ("    --time-startup        Print how long startup and each phase took", "    --time-startup        起動と段階ごとにかかった時間を表示する"),
+    ("    -- <args>             Pass the remaining arguments to the program (os.args())", "    -- <args>             残りの引数をプログラムに渡す (os.args())"),
+    ("  build                   Type check the project", "  build                   プロジェクトを型チェック"),
+    ("  test                    Run tests (src/test_*.n7t)", "  test                    テストを実行 (src/test_*.n7t)"),
+    ("  fmt                     Format code", "  fmt                     コードを整形"),
+    ("  fix [file]              Apply machine-applicable lint fixes", "  fix [file]              リンターの自動修正を適用"),
+    ("                          Rename a definition and every use of it in the file", "                          定義とファイル内のその使用箇所をすべて改名"),
+    ("                          Move the lines into a new function and call it", "                          行を新しい関数に移して呼び出す形にする"),
+    ("  gen-syntax --target <t> Print an editor grammar (tmlanguage, tree-sitter, ebnf)", "  gen-syntax --target <t> エディタ用の構文定義を出力 (tmlanguage, tree-sitter, ebnf)"),
+    ("  report <file> [--redact] Bundle a bug report archive for a file", "  report <file> [--redact] 不具合報告用のレポートを作成"),
+    ("  bench [dir]             Time the programs in benches/programs", "  bench [dir]             benches/programs のプログラムの実行時間を計測"),
+    ("    --runs <n>            Runs per program (default: 5)", "    --runs <n>            1プログラムあたりの実行回数 (既定: 5)"),
+    ("    --threshold <pct>     Slowdown counted as a regression (default: 10)", "    --threshold <pct>     遅くなったとみなす割合 (既定: 10)"),
+    ("    --save <file>         Save the results as JSON", "    --save <file>         結果を JSON に保存"),
+    ("    --compare <file>      Compare with saved results and fail on regressions", "    --compare <file>      保存した結果と比較し、遅くなっていたら失敗する"),
+    ("  doc                     Generate API docs into dist/doc", "  doc                     API ドキュメントを dist/doc に生成"),
+    ("    --format <md|html>    Output format (default: html)", "    --format <md|html>    出力形式 (既定: html)"),
+    ("  new <name>              Create a new project", "  new <name>              新規プロジェクトを作成"),
+    ("  add <git-url|path>      Add a dependency to n7tya.toml and install it", "  add <git-url|path>      n7tya.toml に依存パッケージを追加して取得"),
+    ("    --name <name>         Package name to import (default: from the source)", "    --name <name>         import に使うパッケージ名 (省略時は取得元から決める)"),
+    ("    --rev <rev>           Branch, tag or commit of a git dependency", "    --rev <rev>           git のパッケージのブランチ・タグ・コミット"),
+    ("  install                 Fetch the dependencies and update n7tya.lock", "  install                 依存パッケージを取得して n7tya.lock を更新"),
+    ("  check <file>            Type check a specific file", "  check <file>            指定したファイルを型チェック"),
+    ("  -v, --version           Show version information", "  -v, --version           バージョン情報を表示"),
+    ("  -h, --help              Show this help message", "  -h, --help              このヘルプを表示"),
+    ("  --update                Update n7tya to the latest version", "  --update                n7tya を最新版に更新"),
+    ("  --lang ja|en            Language of messages (default: from locale)", "  --lang ja|en            メッセージの言語 (省略時はロケールから判定)"),
+    ("Unknown command: {}", "不明なコマンドです: {0}"),
+    ("Run 'n7tya --help' for usage.", "使い方は 'n7tya --help' で確認できます。"),
+    ("Usage: {}", "使い方: {0}"),
+    ("Type errors:", "型エラー:"),
+    ("  - {}", "  - {0}"),
+    ("Type check failed: {}", "型チェックに失敗しました: {0}"),
+    ("Runtime error: {}", "実行時エラー: {0}"),
+    ("  Stack trace (most recent call first):", "  スタックトレース (最後の呼び出しから順に):"),
+    ("    in {}, called from {}:{}", "    {0} ({1}:{2} で呼び出し)"),
+    ("Parse error: {}", "構文エラー: {0}"),
+    ("✓ No type errors in {}", "✓ {0} に型エラーはありません"),
+    ("✗ {} type error(s) in {}", "✗ {1} に {0} 件の型エラーがあります"),
+    ("⚠ {} warning(s) in {}", "⚠ {1} に {0} 件の警告があります"),
+    ("Created project '{}'", "プロジェクト '{0}' を作成しました"),
+    ("✓ Added {} ({})", "✓ {0} を追加しました ({1})"),
+    ("✓ Installed {} package(s)", "✓ {0} 件のパッケージを取得しました"),
+    ("Invalid package name '{}'", "パッケージ名 '{0}' は使えません (英数字と _ のみ)"),
+    ("Package '{}' not found at {}", "パッケージ '{0}' が {1} に見つかりません"),
+    ("Package '{}' is required from both {} and {}", "パッケージ '{0}' が {1} と {2} の両方から要求されています"),
+    ("Package '{}' is not installed. Run 'n7tya install'", "パッケージ '{0}' が取得されていません。'n7tya install' を実行してください"),
+    ("Failed to run git (is it installed?): {}", "git を実行できません (インストールされていますか?): {0}"),
+    ("Building project...", "プロジェクトをビルドしています..."),
+    ("  Checking {}...", "  {0} をチェックしています..."),
+    ("    Error: {}", "    エラー: {0}"),
+    ("    Parse error: {}", "    構文エラー: {0}"),
+    ("  Wrote dist/islands.js ({} island(s))", "  dist/islands.js を書き出しました (island {0} 件)"),
+    ("  Wrote dist/{}", "  dist/{0} を書き出しました"),
+    ("  Wrote {} asset(s) and dist/{}", "  静的ファイル {0} 件と dist/{1} を書き出しました"),
+    ("  Wrote {} class name(s) to {}", "  クラス名 {0} 件を {1} に書き出しました"),
+    ("  Running {}", "  {0} を実行しています"),
+    ("  Rendered {}", "  {0} を書き出しました"),
+    ("  Removed {}", "  {0} を削除しました"),
+    ("  Wrote {} page(s) ({} unchanged) and dist/{}", "  ページ {0} 件 (変更なし {1} 件) と dist/{2} を書き出しました"),
+    ("    Warning: the CSS is not written under public/, so pages do not load it", "    警告: CSS が public/ の下に書き出されないため、ページには読み込まれません"),
+    ("✓ Build successful!", "✓ ビルドに成功しました"),
+    ("✗ Build failed with {} error(s)", "✗ {0} 件のエラーでビルドに失敗しました"),
+    ("Running tests...", "テストを実行しています..."),
+    ("  Running {}...", "  {0} を実行しています..."),
+    ("    ✓ Passed", "    ✓ 成功"),
+    ("    ✗ Failed: {}", "    ✗ 失敗: {0}"),
+    ("    ✗ Parse error: {}", "    ✗ 構文エラー: {0}"),
+    ("No tests found. Create files starting with 'test_' in src/ or tests/", "テストが見つかりません。src/ または tests/ に 'test_' で始まるファイルを作成してください"),
+    ("{} tests: {} passed, {} failed", "{0} 件のテスト: 成功 {1} 件、失敗 {2} 件"),
+    ("Formatting code...", "コードを整形しています..."),
+    ("✓ Formatting complete!", "✓ 整形が完了しました"),
+    ("  Formatting {}...", "  {0} を整形しています..."),
+    ("    Warning: line {} is longer than {} characters", "    警告: {0} 行目が {1} 文字を超えています"),
+    ("    Left unchanged: {}", "    変更しませんでした: {0}"),
+    ("  Skipping {}: {}", "  {0} をスキップしました: {1}"),
+    ("  Fixing {}...", "  {0} を修正しています..."),
+    ("    line {}: {}", "    {0} 行目: {1}"),
+    ("✓ Report written to {}", "✓ レポートを {0} に書き出しました"),
+    ("  Nothing has been uploaded. Attach this file to your bug report.", "  どこにも送信していません。このファイルを不具合報告に添付してください。"),
+    ("Running benchmarks in {}...", "{0} のベンチマークを実行しています..."),
+    ("  {} {}  (baseline {}, {})  ✗", "  {0} {1}  (ベースライン {2}, {3})  ✗"),
+    ("  {} {}  (baseline {}, {})", "  {0} {1}  (ベースライン {2}, {3})"),
+    ("  {} {}  (new)", "  {0} {1}  (新規)"),
+    ("✓ Results saved to {}", "✓ 結果を {0} に保存しました"),
+    ("✓ Wrote {}", "✓ {0} を書き出しました"),
+    ("{} benchmark(s) regressed by more than {}%", "{0} 件のベンチマークが {1}% 以上遅くなりました"),
+    ("Updating n7tya-lang...", "n7tya-lang を更新しています..."),
+    ("✓ n7tya updated successfully!", "✓ n7tya を更新しました"),
+    ("Failed to read file '{}': {}", "ファイル '{0}' を読み込めません: {1}"),
+    ("Failed to write file '{}': {}", "ファイル '{0}' に書き込めません: {1}"),
+    // ===== 型チェック =====
+    ("Undefined variable: {}", "未定義の変数です: {0}"),
+    ("Undefined variable '{}'; did you mean '{}'?", "未定義の変数 '{0}' です。'{1}' のことですか?"),
+    ("Cannot assign to undefined variable '{}' (declare it with let {} = ...)", "未定義の変数 '{0}' には代入できません (let {1} = ... で宣言してください)"),
+    ("'{}' in {} shadows the one in {} (use nonlocal {} to assign to it)", "{1} の '{0}' が {2} の同じ名前を隠しています (外側に代入するなら nonlocal {3} と書きます)"),
+    ("'{}' in {} shadows the one in {}", "{1} の '{0}' が {2} の同じ名前を隠しています"),
+    ("let {} inside a block of {} replaces the variable defined before it (blocks do not create a new scope; use {} = ... to assign)", "{1} のブロックの中の let {0} は前に定義した変数を置き換えます (ブロックはスコープを作りません。代入なら {2} = ... と書きます)"),
+    ("the top level", "トップレベル"),
+    ("a lambda", "ラムダ"),
+    ("component '{}'", "コンポーネント '{0}'"),
+    ("Type mismatch in assignment: expected {}, got {}", "代入の型が一致しません: {0} が必要ですが {1} です"),
+    ("Type mismatch in declaration of '{}': expected {}, got {}", "'{0}' の宣言の型が一致しません: {1} が必要ですが {2} です"),
+    ("'{}' may be none; check it against none before using it", "'{0}' は none の可能性があります。使う前に none と比較してください"),
+    ("Type mismatch for field '{}' of {}: expected {}, got {}", "{1} のフィールド '{0}' の型が一致しません: {2} が必要ですが {3} です"),
+    ("Type mismatch for default of field '{}' in {}: expected {}, got {}", "{1} のフィールド '{0}' の初期値の型が一致しません: {2} が必要ですが {3} です"),
+    ("Attempt to call non-function: {}", "関数ではない値を呼び出そうとしました: {0}"),
+    ("If condition must be Bool, got {}", "if の条件は Bool である必要がありますが {0} です"),
+    ("While condition must be Bool, got {}", "while の条件は Bool である必要がありますが {0} です"),
+    ("Unknown parent class '{}' for class '{}'", "クラス '{1}' の親クラス '{0}' が見つかりません"),
+    ("Unknown interface '{}' for class '{}'", "クラス '{1}' のインターフェース '{0}' が見つかりません"),
+    ("Class '{}' does not implement '{}' required by interface '{}'", "クラス '{0}' にインターフェース '{2}' が要求する '{1}' がありません"),
+    ("Method '{}' of class '{}' does not match interface '{}': expected {}, got {}", "クラス '{1}' のメソッド '{0}' がインターフェース '{2}' と一致しません: {3} が必要ですが {4} です"),
+    ("Duplicate method '{}' in interface {}", "インターフェース {1} のメソッド '{0}' が重複しています"),
+    ("Missing field '{}' in {}", "{1} にフィールド '{0}' がありません"),
+    ("Duplicate field '{}' in struct {}", "構造体 {1} のフィールド '{0}' が重複しています"),
+    ("Struct '{}' has no field '{}'", "構造体 '{0}' にフィールド '{1}' はありません"),
+    ("Class '{}' has no field '{}'", "クラス '{0}' にフィールド '{1}' はありません"),
+    ("'{}' has {} fields, but the pattern has {}", "'{0}' のフィールドは {1} 個ですが、パターンには {2} 個あります"),
+    ("Unknown type in pattern: {}", "パターンの型が定義されていません: {0}"),
+    ("Unused import: {}", "使われていない import です: {0}"),
+    ("Missing return type for '{}': {}", "'{0}' に戻り値型 {1} がありません"),
+    ("Type mismatch in return of route '{}': expected {}, got {}", "ルート '{0}' の戻り値の型が一致しません: {1} が必要ですが {2} です"),
+    ("Request body of route '{}' must be a struct, got '{}'", "ルート '{0}' のリクエストボディは構造体である必要がありますが '{1}' です"),
+    ("Route '{}' must return {}", "ルート '{0}' は {1} を返す必要があります"),
+    // ===== 実行時 =====
+    ("Division by zero", "ゼロで割ることはできません"),
+    ("Modulo by zero", "ゼロで剰余を求めることはできません"),
+    ("Integer overflow", "整数がオーバーフローしました"),
+    ("Index out of bounds", "インデックスが範囲外です"),
+    ("Expected {} arguments, got {}", "引数は {0} 個必要ですが {1} 個渡されました"),
+    ("{}() takes no arguments (define 'init' to accept some)", "{0}() は引数を受け取りません (受け取るには 'init' を定義してください)"),
+    ("{}() takes exactly 1 argument", "{0}() は引数を1つだけ受け取ります"),
+    ("{}() takes exactly 2 arguments", "{0}() は引数を2つだけ受け取ります"),
+    ("Key error: {}", "キーが見つかりません: {0}"),
+    ("Unknown member: {}", "不明なメンバーです: {0}"),
+    ("Unknown class '{}'", "不明なクラスです: '{0}'"),
+    ("Unknown struct: {}", "不明な構造体です: {0}"),
+    ("'{}' has no method '{}'", "'{0}' にメソッド '{1}' はありません"),
+    ("{} has no method '{}'", "{0} にメソッド '{1}' はありません"),
+    ("'{}' has no methods", "'{0}' にメソッドはありません"),
+    ("'super' used outside of a method", "'super' はメソッドの中でのみ使えます"),
+    ("Cannot call {}", "{0} は呼び出せません"),
+    ("value not in list", "値がリストにありません"),
+    ("pop from empty list", "空のリストから pop しようとしました"),
+    ("Unhandled {}", "処理されていない値です: {0}"),
+    ("Called {}() on {}", "{1} に対して {0}() を呼び出しました"),
+    ("Module '{}' not found", "モジュール '{0}' が見つかりません"),
+    ("Circular import: {}", "import が循環しています: {0}"),
+    ("Execution budget exceeded: {}", "実行の上限を超えました: {0}"),
+    ("'{}' not found in module '{}'", "モジュール '{1}' に '{0}' はありません"),
+    ("Module '{}' has no member '{}'", "モジュール '{0}' に '{1}' はありません"),
+    ("Failed to parse module '{}': {}", "モジュール '{0}' の構文解析に失敗しました: {1}"),
+    ("{}() expects a list", "{0}() にはリストを渡してください"),
+    ("{}() expects a non-empty list", "{0}() には空でないリストを渡してください"),
+    ("Unknown component: {}", "不明なコンポーネントです: {0}"),
+    ("Component '{}' has no render block", "コンポーネント '{0}' に render ブロックがありません"),
+    ("render_page() expects a component", "render_page() にはコンポーネントを渡してください"),
+    ("Island '{}' uses {} which cannot run in the browser", "island '{0}' はブラウザで動かせない {1} を使っています"),
+    ("Unknown state persistence '{}' (expected memory, local or session)", "不明な state の保存先です: '{0}' (memory, local, session のいずれか)"),
+    ("State '{}' uses local persistence but {} is not an island component", "state '{0}' は local に保存しますが、{1} は island component ではありません"),
+    ("Invalid island state", "island の state が不正です"),
+    ("<Router routes> expects a dict of paths to components", "<Router routes> にはパスとコンポーネントの辞書を渡してください"),
+    ("<form bind> expects a struct, got {}", "<form bind> には構造体を渡してください (値: {0})"),
+    ("Field '{}' of {} cannot be a form field ({})", "{1} のフィールド '{0}' はフォームの入力欄にできません ({2})"),
+    ("decode_form() expects a struct and a form body", "decode_form() には構造体とフォームのボディを渡してください"),
+    ("'{}' needs a struct type to read a form", "フォームを読むには '{0}' に構造体の型が必要です"),
+    ("'{}' must be a struct to read a form", "フォームを読むには '{0}' が構造体である必要があります"),
+    (
+        "render_email() expects a component or an HTML string",
+        "render_email() にはコンポーネントか HTML 文字列を渡してください",
+    ),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translate() {
+        assert_eq!(translate(Lang::En, "Division by zero"), "Division by zero");
+        assert_eq!(
+            translate(Lang::Ja, "Division by zero"),
+            "ゼロで割ることはできません"
+        );
+        assert_eq!(
+            translate(Lang::Ja, "✗ 2 type error(s) in main.n7t"),
+            "✗ main.n7t に 2 件の型エラーがあります"
+        );
+        assert_eq!(
+            translate(
+                Lang::Ja,
+                "Type mismatch for field 'x' of Point: expected Int, got Str"
+            ),
+            "Point のフィールド 'x' の型が一致しません: Int が必要ですが Str です"
+        );
+        assert_eq!(
+            translate(Lang::Ja, "Runtime error: Division by zero"),
+            "実行時エラー: ゼロで割ることはできません"
+        );
+        // カタログにないものは英語のまま
+        assert_eq!(translate(Lang::Ja, "Something new"), "Something new");
+
+        let mut args: Vec<String> = ["n7tya", "--lang", "ja", "check"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        init(&mut args).unwrap();
+        assert_eq!(args, ["n7tya", "check"]);
+        assert_eq!(lang(), Lang::Ja);
+
+        // `--` より後の --lang はプログラムの引数
+        let mut args: Vec<String> = ["n7tya", "run", "a.n7t", "--lang=en", "--", "--lang", "ja"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(take_lang(&mut args).unwrap(), Some(Lang::En));
+        assert_eq!(args, ["n7tya", "run", "a.n7t", "--", "--lang", "ja"]);
+        let mut args = vec!["--lang".to_string(), "--".to_string()];
+        assert!(take_lang(&mut args).is_err());
+    }
+}
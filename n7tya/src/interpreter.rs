@@ -4,39 +4,91 @@
 //! ASTを直接評価するTree-Walkingインタプリタ
 
 use crate::ast::*;
+use crate::builtins::REDIRECT_PREFIX;
+use crate::http::{Download, DownloadEvent, HttpRequest};
+use crate::iter::{Iter, Range};
+use crate::jsx_render::RenderMode;
 use crate::lexer::Lexer;
 use crate::parser::Parser;
-use std::path::Path;
-use std::cell::RefCell;
-use std::collections::HashMap;
+use crate::resolve::ScopeRules;
+use crate::telemetry::{SpanKind, Telemetry};
+use std::path::{Path, PathBuf};
+use std::cell::{OnceCell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::io::{Read, Write};
-use std::net::TcpListener;
+use std::net::{TcpListener, TcpStream};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
 
 /// 実行時の値
 #[derive(Debug, Clone)]
 pub enum Value {
     Int(i64),
     Float(f64),
-    Str(String),
+    // 5s / 200ms と 10mb (バイト数)
+    Duration(std::time::Duration),
+    Size(u64),
+    Decimal(rust_decimal::Decimal),
+    // sqlite.open の接続
+    Sqlite(Rc<crate::sqlite::Connection>),
+    Str(Rc<str>), // 不変なので clone は参照カウントの増加だけで済む
     Bool(bool),
     List(Rc<RefCell<Vec<Value>>>),
     None,
     Fn(Rc<FunctionDef>, Rc<RefCell<Env>>), // クロージャ
     BuiltinFn(String),
     Class(String, Rc<RefCell<HashMap<String, Value>>>), // クラスインスタンス
+    Struct(String, Rc<RefCell<Vec<(String, Value)>>>),  // 構造体インスタンス (定義順のフィールド)
     Dict(Rc<RefCell<HashMap<String, Value>>>),          // 辞書
     Set(Rc<RefCell<Vec<Value>>>),                       // 集合
+    Ok(Rc<Value>),                                      // Result の成功値
+    Err(Rc<Value>),                                     // Result のエラー値
+    Some(Rc<Value>), // Option の値 (値がない場合は none)
+    Module(Rc<Module>), // import したモジュール (標準ライブラリも含む)
+    Range(Range),            // range の値 (何度でも回せる)
+    Iter(Rc<RefCell<Iter>>), // map や zip の遅延イテレータ
     Return(Box<Value>),                    // return文の値（制御フロー用）
 }
 
 impl Value {
+    /// type() が返す型の名前 (クラスと構造体はその名前)
+    pub fn type_name(&self) -> &str {
+        match self {
+            Value::Int(_) => "Int",
+            Value::Float(_) => "Float",
+            Value::Duration(_) => "Duration",
+            Value::Size(_) => "Size",
+            Value::Decimal(_) => "Decimal",
+            Value::Str(_) => "Str",
+            Value::Bool(_) => "Bool",
+            Value::List(_) => "List",
+            Value::Dict(_) => "Dict",
+            Value::Set(_) => "Set",
+            Value::None => "None",
+            Value::Fn(_, _) => "Fn",
+            Value::BuiltinFn(_) => "BuiltinFn",
+            Value::Class(name, _) | Value::Struct(name, _) => name,
+            Value::Ok(_) | Value::Err(_) => "Result",
+            Value::Some(_) => "Option",
+            Value::Module(_) => "Module",
+            Value::Range(_) => "Range",
+            Value::Iter(_) => "Iterator",
+            Value::Sqlite(_) => "SqliteConnection",
+            Value::Return(_) => "Return",
+        }
+    }
+
     /// 値を文字列として表示
     pub fn display(&self) -> String {
         match self {
             Value::Int(n) => n.to_string(),
             Value::Float(f) => f.to_string(),
-            Value::Str(s) => s.clone(),
+            Value::Duration(d) => crate::units::format_duration(*d),
+            Value::Size(bytes) => crate::units::format_size(*bytes),
+            Value::Decimal(d) => d.to_string(),
+            Value::Str(s) => s.to_string(),
             Value::Bool(b) => b.to_string(),
             Value::List(items) => {
                 let items = items.borrow();
@@ -46,11 +98,23 @@ impl Value {
             Value::None => "none".to_string(),
             Value::Fn(f, _) => format!("<fn {}>", f.name),
             Value::BuiltinFn(name) => format!("<builtin {}>", name),
+            Value::Range(range) => Value::List(Rc::new(RefCell::new(range.items()))).display(),
+            Value::Iter(_) => "<iterator>".to_string(),
+            Value::Sqlite(db) if db.is_open() => format!("<sqlite {}>", db.path()),
+            Value::Sqlite(db) => format!("<sqlite {} (closed)>", db.path()),
             Value::Class(name, _) => format!("<{} instance>", name),
+            Value::Struct(name, fields) => {
+                let fields = fields.borrow();
+                let strs: Vec<String> = fields
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", k, v.display()))
+                    .collect();
+                format!("{} {{{}}}", name, strs.join(", "))
+            }
             Value::Dict(map) => {
                 let map = map.borrow();
-                let strs: Vec<String> = map
-                    .iter()
+                let strs: Vec<String> = sorted_entries(&map)
+                    .into_iter()
                     .map(|(k, v)| format!("{}: {}", k, v.display()))
                     .collect();
                 format!("{{{}}}", strs.join(", "))
@@ -60,6 +124,10 @@ impl Value {
                 let strs: Vec<String> = set.iter().map(|v| v.display()).collect();
                 format!("{{{}}}", strs.join(", "))
             }
+            Value::Ok(v) => format!("Ok({})", v.display()),
+            Value::Err(e) => format!("Err({})", e.display()),
+            Value::Some(v) => format!("Some({})", v.display()),
+            Value::Module(module) => format!("<module {}>", module.name),
             Value::Return(v) => v.display(),
         }
     }
@@ -70,21 +138,383 @@ impl Value {
             Value::Bool(b) => *b,
             Value::Int(n) => *n != 0,
             Value::Float(f) => *f != 0.0,
+            Value::Duration(d) => !d.is_zero(),
+            Value::Size(bytes) => *bytes != 0,
+            Value::Decimal(d) => !d.is_zero(),
             Value::Str(s) => !s.is_empty(),
             Value::List(l) => !l.borrow().is_empty(),
             Value::Dict(d) => !d.borrow().is_empty(),
             Value::Set(s) => !s.borrow().is_empty(),
+            Value::Range(range) => !range.is_empty(),
             Value::None => false,
             _ => true,
         }
     }
+
+    /// for で順に取り出す値 (リストと集合は要素、辞書はキー順のキー、文字列は1文字ずつ、range は数)
+    ///
+    /// 繰り返せない値は None。取り出すのは呼んだ時点の中身なので、ループの中で変えても影響しない。
+    pub fn iter_items(&self) -> Option<Vec<Value>> {
+        match self {
+            Value::List(items) | Value::Set(items) => Some(items.borrow().clone()),
+            Value::Dict(dict) => Some(
+                sorted_entries(&dict.borrow())
+                    .into_iter()
+                    .map(|(key, _)| Value::Str(key.as_str().into()))
+                    .collect(),
+            ),
+            Value::Str(s) => Some(
+                s.chars()
+                    .map(|c| Value::Str(c.to_string().into()))
+                    .collect(),
+            ),
+            Value::Range(range) => Some(range.items()),
+            _ => None,
+        }
+    }
+}
+
+/// 辞書の中身をキー順に並べる (表示や for の順番が実行ごとに変わらないように)
+pub(crate) fn sorted_entries(map: &HashMap<String, Value>) -> Vec<(&String, &Value)> {
+    let mut entries: Vec<(&String, &Value)> = map.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
+/// s.split(sep, max) / s.rsplit(sep, max): 区切り文字で分けたリスト
+/// (sep の省略時は " "、max を渡すと最大 max 回だけ分ける。rsplit は右から分ける)
+fn split_string(s: &str, method: &str, args: &[Value]) -> Result<Value, String> {
+    let (sep, max) = match args {
+        [] => (" ", None),
+        [Value::Str(sep)] => (&**sep, None),
+        [Value::Str(sep), Value::Int(max)] => (&**sep, usize::try_from(*max).ok()),
+        _ => {
+            return Err(format!(
+                "{}() expects a separator string and an optional max split count",
+                method
+            ))
+        }
+    };
+    if sep.is_empty() {
+        return Err(format!("{}() separator cannot be empty", method));
+    }
+    let mut parts: Vec<&str> = match (method, max) {
+        ("split", None) => s.split(sep).collect(),
+        ("split", Some(max)) => s.splitn(max.saturating_add(1), sep).collect(),
+        (_, None) => s.rsplit(sep).collect(),
+        (_, Some(max)) => s.rsplitn(max.saturating_add(1), sep).collect(),
+    };
+    if method == "rsplit" {
+        parts.reverse();
+    }
+    let parts = parts.into_iter().map(|p| Value::Str(p.into())).collect();
+    Ok(Value::List(Rc::new(RefCell::new(parts))))
+}
+
+/// 空白と文字の種類、幅を扱う Str のメソッド (strip / ljust / title / count など)
+fn string_method(s: &str, method: &str, args: &[Value]) -> Result<Value, String> {
+    let text = |value: String| Ok(Value::Str(value.into()));
+    match (method, args) {
+        // 引数の文字列に含まれる文字を取り除く (省略すると空白)
+        ("strip" | "lstrip" | "rstrip", [] | [Value::Str(_)]) => {
+            let chars: Option<Vec<char>> = match args {
+                [Value::Str(set)] => Some(set.chars().collect()),
+                _ => None,
+            };
+            let strip = |c: char| match &chars {
+                Some(chars) => chars.contains(&c),
+                None => c.is_whitespace(),
+            };
+            let stripped = match method {
+                "strip" => s.trim_matches(strip),
+                "lstrip" => s.trim_start_matches(strip),
+                _ => s.trim_end_matches(strip),
+            };
+            text(stripped.to_string())
+        }
+        // \n、\r\n、\r で分ける (最後の改行の後ろは行にしない)
+        ("splitlines", []) => {
+            let mut lines = Vec::new();
+            let mut rest = s;
+            while !rest.is_empty() {
+                let end = rest.find(['\r', '\n']).unwrap_or(rest.len());
+                lines.push(Value::Str(rest[..end].into()));
+                rest = &rest[end..];
+                rest = rest
+                    .strip_prefix("\r\n")
+                    .or_else(|| rest.strip_prefix(['\r', '\n']))
+                    .unwrap_or(rest);
+            }
+            Ok(Value::List(Rc::new(RefCell::new(lines))))
+        }
+        // 文字数が width になるまで fill (省略すると空白) で埋める
+        (
+            "ljust" | "rjust" | "center",
+            [Value::Int(width)] | [Value::Int(width), Value::Str(_)],
+        ) => {
+            let fill = match args {
+                [_, Value::Str(fill)] => {
+                    let mut chars = fill.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) => c,
+                        _ => {
+                            return Err(format!("{}() fill must be exactly one character", method))
+                        }
+                    }
+                }
+                _ => ' ',
+            };
+            let pad = usize::try_from(*width)
+                .unwrap_or(0)
+                .saturating_sub(s.chars().count());
+            let (left, right) = match method {
+                "ljust" => (0, pad),
+                "rjust" => (pad, 0),
+                _ => (pad / 2, pad - pad / 2),
+            };
+            let fill = |n: usize| fill.to_string().repeat(n);
+            text(format!("{}{}{}", fill(left), s, fill(right)))
+        }
+        // 符号の後ろを 0 で埋める
+        ("zfill", [Value::Int(width)]) => {
+            let (sign, digits) = match s.strip_prefix(['+', '-']) {
+                Some(digits) => (&s[..1], digits),
+                None => ("", s),
+            };
+            let pad = usize::try_from(*width)
+                .unwrap_or(0)
+                .saturating_sub(s.chars().count());
+            text(format!("{}{}{}", sign, "0".repeat(pad), digits))
+        }
+        // 単語 (文字が続く部分) ごとに先頭を大文字、残りを小文字にする
+        ("title", []) => {
+            let mut out = String::with_capacity(s.len());
+            let mut in_word = false;
+            for c in s.chars() {
+                if in_word {
+                    out.extend(c.to_lowercase());
+                } else {
+                    out.extend(c.to_uppercase());
+                }
+                in_word = c.is_alphabetic();
+            }
+            text(out)
+        }
+        ("capitalize", []) => {
+            let mut chars = s.chars();
+            text(match chars.next() {
+                Some(first) => first
+                    .to_uppercase()
+                    .chain(chars.as_str().to_lowercase().chars())
+                    .collect(),
+                None => String::new(),
+            })
+        }
+        // 空文字列は false
+        ("isdigit", []) => Ok(Value::Bool(
+            !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()),
+        )),
+        ("isalpha", []) => Ok(Value::Bool(
+            !s.is_empty() && s.chars().all(char::is_alphabetic),
+        )),
+        // 重ならない出現回数
+        ("count", [Value::Str(sub)]) => Ok(Value::Int(s.matches(&**sub).count() as i64)),
+        // 最後に見つかった位置 (find と同じくバイト単位、なければ -1)
+        ("rfind", [Value::Str(sub)]) => Ok(Value::Int(s.rfind(&**sub).map_or(-1, |i| i as i64))),
+        (
+            "strip" | "lstrip" | "rstrip" | "splitlines" | "ljust" | "rjust" | "center" | "zfill"
+            | "title" | "capitalize" | "isdigit" | "isalpha" | "count" | "rfind",
+            _,
+        ) => Err(format!(
+            "{}() expects {}",
+            method,
+            match method {
+                "strip" | "lstrip" | "rstrip" => "an optional string of characters to remove",
+                "ljust" | "rjust" | "center" => "a width and an optional fill character",
+                "zfill" => "a width",
+                "count" | "rfind" => "a string",
+                _ => "no arguments",
+            }
+        )),
+        _ => Err(format!("Str has no method '{}'", method)),
+    }
+}
+
+/// 同じ値を最初の1つだけ残す (集合の要素)
+fn unique(values: Vec<Value>) -> Vec<Value> {
+    let mut result: Vec<Value> = Vec::new();
+    for value in values {
+        if !result.iter().any(|v| values_equal(&value, v)) {
+            result.push(value);
+        }
+    }
+    result
+}
+
+/// Float を含む算術演算と比較
+fn float_op(op: &BinaryOp, left: &Value, right: &Value) -> Result<Value, String> {
+    let number = |value: &Value| match value {
+        Value::Int(n) => *n as f64,
+        Value::Float(f) => *f,
+        _ => f64::NAN,
+    };
+    let (a, b) = (number(left), number(right));
+    Ok(match op {
+        BinaryOp::Add => Value::Float(a + b),
+        BinaryOp::Sub => Value::Float(a - b),
+        BinaryOp::Mul => Value::Float(a * b),
+        BinaryOp::Div | BinaryOp::Mod if b == 0.0 => {
+            let message = match op {
+                BinaryOp::Div => "Division by zero",
+                _ => "Modulo by zero",
+            };
+            return Err(message.to_string());
+        }
+        BinaryOp::Div => Value::Float(a / b),
+        BinaryOp::Mod => Value::Float(a % b),
+        BinaryOp::Lt => Value::Bool(a < b),
+        BinaryOp::Gt => Value::Bool(a > b),
+        BinaryOp::Le => Value::Bool(a <= b),
+        _ => Value::Bool(a >= b),
+    })
+}
+
+/// == / in / index / count の等価性
+///
+/// リスト・辞書・集合・構造体は中身を再帰的に比べ (集合は順序を問わない)、
+/// クラスのインスタンスと関数は同じものかどうかで比べる。
+pub fn values_equal(a: &Value, b: &Value) -> bool {
+    equal_in(a, b, &mut Vec::new())
+}
+
+/// comparing は比較中のコレクションの組 (自分自身を含むリストでも止まるようにする)
+fn equal_in(a: &Value, b: &Value, comparing: &mut Vec<(usize, usize)>) -> bool {
+    match (a, b) {
+        (Value::Int(x), Value::Int(y)) => x == y,
+        (Value::Float(x), Value::Float(y)) => x == y,
+        (Value::Int(x), Value::Float(y)) | (Value::Float(y), Value::Int(x)) => *x as f64 == *y,
+        (Value::Str(x), Value::Str(y)) => x == y,
+        (Value::Bool(x), Value::Bool(y)) => x == y,
+        (Value::Duration(x), Value::Duration(y)) => x == y,
+        (Value::Size(x), Value::Size(y)) => x == y,
+        (Value::Decimal(_), Value::Decimal(_) | Value::Int(_))
+        | (Value::Int(_), Value::Decimal(_)) => {
+            crate::decimal::compare(a, b).is_some_and(|o| o.is_eq())
+        }
+        (Value::None, Value::None) => true,
+        (Value::Ok(x), Value::Ok(y))
+        | (Value::Err(x), Value::Err(y))
+        | (Value::Some(x), Value::Some(y)) => equal_in(x, y, comparing),
+        (Value::Range(x), Value::Range(y)) => {
+            x.len() == y.len() && (0..x.len().min(2) as i64).all(|i| x.get(i) == y.get(i))
+        }
+        (Value::Range(range), list @ Value::List(_))
+        | (list @ Value::List(_), Value::Range(range)) => {
+            let items = Value::List(Rc::new(RefCell::new(range.items())));
+            equal_in(&items, list, comparing)
+        }
+        (Value::List(x), Value::List(y)) => nested(x, y, comparing, |x, y, comparing| {
+            x.len() == y.len() && x.iter().zip(y).all(|(a, b)| equal_in(a, b, comparing))
+        }),
+        (Value::Set(x), Value::Set(y)) => nested(x, y, comparing, |x, y, comparing| {
+            x.len() == y.len()
+                && x.iter()
+                    .all(|a| y.iter().any(|b| equal_in(a, b, comparing)))
+        }),
+        (Value::Dict(x), Value::Dict(y)) => nested(x, y, comparing, |x, y, comparing| {
+            x.len() == y.len()
+                && x.iter()
+                    .all(|(k, a)| y.get(k).is_some_and(|b| equal_in(a, b, comparing)))
+        }),
+        (Value::Struct(m, x), Value::Struct(n, y)) => {
+            m == n
+                && nested(x, y, comparing, |x, y, comparing| {
+                    x.len() == y.len()
+                        && x.iter()
+                            .zip(y)
+                            .all(|((k, a), (l, b))| k == l && equal_in(a, b, comparing))
+                })
+        }
+        (Value::Class(_, x), Value::Class(_, y)) => Rc::ptr_eq(x, y),
+        (Value::Fn(f, _), Value::Fn(g, _)) => Rc::ptr_eq(f, g),
+        (Value::BuiltinFn(x), Value::BuiltinFn(y)) => x == y,
+        (Value::Module(x), Value::Module(y)) => Rc::ptr_eq(x, y),
+        (Value::Sqlite(x), Value::Sqlite(y)) => Rc::ptr_eq(x, y),
+        _ => false,
+    }
+}
+
+/// 同じオブジェクトなら等しく、比較中の組にまた来たら残りの比較の結果に任せる
+fn nested<T>(
+    x: &Rc<RefCell<T>>,
+    y: &Rc<RefCell<T>>,
+    comparing: &mut Vec<(usize, usize)>,
+    compare: impl FnOnce(&T, &T, &mut Vec<(usize, usize)>) -> bool,
+) -> bool {
+    let pair = (Rc::as_ptr(x) as usize, Rc::as_ptr(y) as usize);
+    if pair.0 == pair.1 || comparing.contains(&pair) {
+        return true;
+    }
+    comparing.push(pair);
+    let equal = compare(&x.borrow(), &y.borrow(), comparing);
+    comparing.pop();
+    equal
+}
+
+/// sorted や sorted(list, key) の並べ方 (比べられない組は None)
+///
+/// 数値どうし (Int と Float も)、文字列どうし、真偽値どうしを比べ、リストは先頭の要素から順に比べる。
+pub fn compare_values(a: &Value, b: &Value) -> Option<std::cmp::Ordering> {
+    match (a, b) {
+        (Value::Int(x), Value::Int(y)) => Some(x.cmp(y)),
+        (Value::Float(x), Value::Float(y)) => x.partial_cmp(y),
+        (Value::Int(x), Value::Float(y)) => (*x as f64).partial_cmp(y),
+        (Value::Float(x), Value::Int(y)) => x.partial_cmp(&(*y as f64)),
+        (Value::Str(x), Value::Str(y)) => Some(x.cmp(y)),
+        (Value::Bool(x), Value::Bool(y)) => Some(x.cmp(y)),
+        (Value::Duration(_) | Value::Size(_), _) => crate::units::compare(a, b),
+        (Value::Decimal(_), _) | (_, Value::Decimal(_)) => crate::decimal::compare(a, b),
+        (Value::List(x), Value::List(y)) if Rc::ptr_eq(x, y) => Some(std::cmp::Ordering::Equal),
+        (Value::List(x), Value::List(y)) => {
+            let (x, y) = (x.borrow(), y.borrow());
+            for (a, b) in x.iter().zip(y.iter()) {
+                match compare_values(a, b)? {
+                    std::cmp::Ordering::Equal => {}
+                    order => return Some(order),
+                }
+            }
+            Some(x.len().cmp(&y.len()))
+        }
+        _ => None,
+    }
+}
+
+/// 文字列やリストを n 回繰り返すときの回数 (大きすぎる結果はエラーにする)
+fn repeat_count(len: usize, n: i64) -> Result<usize, String> {
+    let count = usize::try_from(n).unwrap_or(0);
+    match len.checked_mul(count) {
+        Some(total) if total <= MAX_REPEAT_LEN => Ok(count),
+        _ => Err(format!("Repetition result is too large ({} x {})", len, n)),
+    }
 }
 
+/// 繰り返しで作れる文字列のバイト数・リストの要素数の上限
+const MAX_REPEAT_LEN: usize = 1 << 30;
+
 /// 環境（変数バインディング）
 #[derive(Debug, Clone)]
 pub struct Env {
-    values: HashMap<String, Value>,
-    parent: Option<Rc<RefCell<Env>>>,
+    pub(crate) values: HashMap<String, Value>,
+    pub(crate) parent: Option<Rc<RefCell<Env>>>,
+    file: Option<Rc<str>>, // グローバル環境だけが持つ、実行しているファイルのパス
+    nonlocals: Vec<String>, // nonlocal で外側の変数を指すと宣言した名前
+    constants: Vec<String>, // このフレームで const で定義した名前
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Env {
@@ -92,6 +522,9 @@ impl Env {
         Self {
             values: HashMap::new(),
             parent: None,
+            file: None,
+            nonlocals: Vec::new(),
+            constants: Vec::new(),
         }
     }
 
@@ -99,11 +532,38 @@ impl Env {
         Self {
             values: HashMap::new(),
             parent: Some(parent),
+            file: None,
+            nonlocals: Vec::new(),
+            constants: Vec::new(),
         }
     }
 
+    /// このフレームの関数を定義したファイル (グローバル環境まで辿る)
+    fn file(env: &Rc<RefCell<Env>>) -> Option<Rc<str>> {
+        let env = env.borrow();
+        match &env.parent {
+            Some(parent) => Env::file(parent),
+            None => env.file.clone(),
+        }
+    }
+
+    /// このフレームに定義する (親フレームの同名変数は隠すだけで書き換えない)
+    ///
+    /// nonlocal と宣言した名前は、このフレームではなく外側の変数に書く
     pub fn define(&mut self, name: &str, value: Value) {
-        self.values.insert(name.to_string(), value);
+        if self.is_nonlocal(name) {
+            if let Some(parent) = &self.parent {
+                parent.borrow_mut().set(name, value);
+            }
+            return;
+        }
+        // ループ変数のように同じ名前を何度も定義する場合はキーを作り直さない
+        match self.values.get_mut(name) {
+            Some(slot) => *slot = value,
+            None => {
+                self.values.insert(name.to_string(), value);
+            }
+        }
     }
 
     pub fn get(&self, name: &str) -> Option<Value> {
@@ -117,8 +577,8 @@ impl Env {
     }
 
     pub fn set(&mut self, name: &str, value: Value) -> bool {
-        if self.values.contains_key(name) {
-            self.values.insert(name.to_string(), value);
+        if let Some(slot) = self.values.get_mut(name) {
+            *slot = value;
             true
         } else if let Some(parent) = &self.parent {
             parent.borrow_mut().set(name, value)
@@ -126,265 +586,2088 @@ impl Env {
             false
         }
     }
+
+    pub fn is_nonlocal(&self, name: &str) -> bool {
+        self.nonlocals.iter().any(|n| n == name)
+    }
+
+    /// いちばん近い name が const で定義したものか
+    pub fn is_constant(&self, name: &str) -> bool {
+        if self.values.contains_key(name) {
+            self.constants.iter().any(|n| n == name)
+        } else if let Some(parent) = &self.parent {
+            parent.borrow().is_constant(name)
+        } else {
+            false
+        }
+    }
+
+    /// `nonlocal name`: この関数の中の name を外側の関数 (またはグローバル) の変数にする
+    pub fn declare_nonlocal(&mut self, name: &str) -> Result<(), String> {
+        let Some(parent) = &self.parent else {
+            return Err("nonlocal can only be used inside a function".to_string());
+        };
+        if self.values.contains_key(name) {
+            return Err(format!(
+                "'{}' is already defined in this function and cannot be declared nonlocal",
+                name
+            ));
+        }
+        if parent.borrow().get(name).is_none() {
+            return Err(format!(
+                "No binding for nonlocal '{}' in an enclosing scope",
+                name
+            ));
+        }
+        if !self.is_nonlocal(name) {
+            self.nonlocals.push(name.to_string());
+        }
+        Ok(())
+    }
 }
 
-/// インタプリタ
-pub struct Interpreter {
-    env: Rc<RefCell<Env>>,
-    output: Vec<String>, // printの出力を格納
+/// サーバー起動時に1度だけ作るルート表
+///
+/// 各ルートの本体を `request` (と型付きのボディ) を引数に取る関数にしておき、
+/// リクエストごとに定義を探し直したり AST を組み立て直したりしない
+struct RouteTable {
+    handlers: HashMap<(String, String), Rc<FunctionDef>>, // (大文字のメソッド, パス) -> 処理
 }
 
-impl Interpreter {
-    pub fn new() -> Self {
-        let env = Rc::new(RefCell::new(Env::new()));
+impl RouteTable {
+    fn compile(server_def: &ServerDef) -> Self {
+        let mut handlers = HashMap::new();
+        for item in &server_def.body {
+            let ServerBodyItem::Route(route) = item else {
+                continue;
+            };
+            let method = route.method.to_ascii_uppercase();
+            // 同じメソッドとパスのルートが複数あれば先に書いたものを使う
+            handlers
+                .entry((method.clone(), route.path.clone()))
+                .or_insert_with(|| {
+                    let request = Param {
+                        name: "request".to_string(),
+                        type_annotation: None,
+                    };
+                    Rc::new(FunctionDef {
+                        name: format!("{} {}", method, route.path),
+                        params: std::iter::once(request)
+                            .chain(route.body_param.clone())
+                            .collect(),
+                        return_type: route.return_type.clone(),
+                        // コメント・空行は実行時には何もしないので除いておく
+                        body: route
+                            .body
+                            .iter()
+                            .filter(|stmt| !matches!(stmt, Statement::Trivia(_)))
+                            .cloned()
+                            .collect(),
+                        is_async: false,
+                        doc: None,
+                        line: route.line,
+                    })
+                });
+        }
+        Self { handlers }
+    }
 
-        // 組み込み関数を登録
-        let builtins = [
-            "print", "println", "len", "range", "input", "str", "int", "float", "type", "abs",
-            "min", "max", "sum", "sorted", "reversed", "enumerate", "zip",
-            // fs モジュール
-            "fs.read_file", "fs.write_file", "fs.exists", "fs.remove", "fs.read_dir",
-            // json モジュール
-            "json.parse", "json.stringify",
-            // http モジュール
-            "http.get", "http.post",
-            // base64 モジュール
-            "base64.encode", "base64.decode",
-            // sqlite モジュール
-            "sqlite.open", "sqlite.execute", "sqlite.query", "sqlite.close",
-        ];
-        for name in builtins {
-            env.borrow_mut()
-                .define(name, Value::BuiltinFn(name.to_string()));
+    /// 完全一致するルートがなければ、パスが前方一致する "/prefix/*" のうち最も長いもの
+    fn get(&self, method: &str, path: &str) -> Option<&Rc<FunctionDef>> {
+        let method = method.to_ascii_uppercase();
+        let path = path.split('?').next().unwrap_or(path);
+        if let Some(handler) = self.handlers.get(&(method.clone(), path.to_string())) {
+            return Some(handler);
+        }
+        self.handlers
+            .iter()
+            .filter_map(|((m, pattern), handler)| {
+                let prefix = pattern.strip_suffix('*')?;
+                (*m == method && prefix.ends_with('/') && path.starts_with(prefix))
+                    .then_some((prefix.len(), handler))
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, handler)| handler)
+    }
+
+    /// メソッドとパスが完全に一致するルートがあるか ("/*" のルートは含めない)
+    fn has_exact(&self, method: &str, path: &str) -> bool {
+        let path = path.split('?').next().unwrap_or(path);
+        self.handlers
+            .contains_key(&(method.to_ascii_uppercase(), path.to_string()))
+    }
+
+    /// -> Json<T> と宣言したルートは JSON を返す (render_page の結果などの HTML 文書は HTML)
+    fn content_type(&self, method: &str, path: &str, body: &str) -> &'static str {
+        match self
+            .get(method, path)
+            .and_then(|handler| handler.return_type.as_ref())
+        {
+            Some(Type::Json(_)) => "application/json",
+            _ if body.starts_with("<!DOCTYPE html>") => "text/html; charset=utf-8",
+            _ => "text/plain; charset=utf-8",
         }
+    }
+}
+
+/// サーバーの設定 (n7tya.toml の [server] と、サーバーごとの [server.名前])
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerOptions {
+    /// 待ち受けるポート
+    pub port: u16,
+    /// GET 以外のリクエストで CSRF トークンを確かめるか
+    pub csrf: bool,
+    /// 生きているかを返すパス
+    pub health_path: String,
+    /// リクエストを受けられるか (health.check の関数がすべて通るか) を返すパス
+    pub ready_path: String,
+    /// ルートの処理1回ごとの上限 (timeout と max_steps、書いていなければ --timeout などの値)
+    pub limits: crate::limits::Limits,
+    /// 最後のリクエストからこの時間が過ぎたセッションを消す (セッションは全サーバーで共有するので [server] の値を使う)
+    pub session_ttl: std::time::Duration,
+}
 
+impl Default for ServerOptions {
+    fn default() -> Self {
         Self {
-            env,
-            output: Vec::new(),
+            port: 8080,
+            csrf: true,
+            health_path: "/healthz".to_string(),
+            ready_path: "/readyz".to_string(),
+            limits: crate::limits::Limits::default(),
+            session_ttl: std::time::Duration::from_secs(30 * 60),
         }
     }
+}
 
-    pub fn run(&mut self, program: &Program) -> Result<Value, String> {
-        let mut result = Value::None;
+impl ServerOptions {
+    /// n7tya.toml の内容から [server] を読み込む
+    pub fn from_toml(content: &str) -> Result<Self, String> {
+        let mut options = Self::default();
+        if let Some(server) = server_table(content)? {
+            options.apply(&server, "server")?;
+        }
+        Ok(options)
+    }
 
-        for item in &program.items {
-            result = self.eval_item(item)?;
+    /// [server.Admin] のようなサーバーごとの設定 (書いていない項目は [server] の値)
+    pub fn sections_from_toml(content: &str) -> Result<HashMap<String, Self>, String> {
+        let base = Self::from_toml(content)?;
+        let mut sections = HashMap::new();
+        for (name, value) in server_table(content)?.unwrap_or_default() {
+            if let toml::Value::Table(section) = value {
+                let mut options = base.clone();
+                options.apply(&section, &format!("server.{}", name))?;
+                sections.insert(name, options);
+            }
+        }
+        Ok(sections)
+    }
 
-            // Return値が出たら終了
-            if let Value::Return(v) = result {
-                return Ok(*v);
+    fn apply(&mut self, table: &toml::Table, prefix: &str) -> Result<(), String> {
+        if let Some(value) = table.get("port") {
+            self.port = value
+                .as_integer()
+                .and_then(|port| u16::try_from(port).ok())
+                .filter(|port| *port > 0)
+                .ok_or_else(|| format!("{}.port must be a port number, got {}", prefix, value))?;
+        }
+        if let Some(value) = table.get("csrf") {
+            self.csrf = value
+                .as_bool()
+                .ok_or_else(|| format!("{}.csrf must be true or false, got {}", prefix, value))?;
+        }
+        for (key, path) in [
+            ("health_path", &mut self.health_path),
+            ("ready_path", &mut self.ready_path),
+        ] {
+            if let Some(value) = table.get(key) {
+                *path = value
+                    .as_str()
+                    .filter(|p| p.starts_with('/'))
+                    .ok_or_else(|| format!("{}.{} must be a path starting with /", prefix, key))?
+                    .to_string();
             }
         }
+        // timeout = 2.5 (秒) または timeout = "500ms"
+        if let Some(value) = table.get("timeout") {
+            self.limits.time = Some(duration_option(value, prefix, "timeout")?);
+        }
+        if let Some(value) = table.get("session_ttl") {
+            self.session_ttl = duration_option(value, prefix, "session_ttl")?;
+        }
+        if let Some(value) = table.get("max_steps") {
+            let steps = value
+                .as_integer()
+                .and_then(|n| u64::try_from(n).ok())
+                .filter(|n| *n > 0)
+                .ok_or_else(|| {
+                    format!(
+                        "{}.max_steps must be a positive integer, got {}",
+                        prefix, value
+                    )
+                })?;
+            self.limits.steps = Some(steps);
+        }
+        Ok(())
+    }
+}
 
-        Ok(result)
+/// 秒数か "30s" のような時間で書いた設定 (0 は不可)
+fn duration_option(
+    value: &toml::Value,
+    prefix: &str,
+    key: &str,
+) -> Result<std::time::Duration, String> {
+    value
+        .as_float()
+        .or_else(|| value.as_integer().map(|n| n as f64))
+        .and_then(|secs| std::time::Duration::try_from_secs_f64(secs).ok())
+        .or_else(|| value.as_str().and_then(crate::units::parse_duration))
+        .filter(|time| !time.is_zero())
+        .ok_or_else(|| {
+            format!(
+                "{}.{} must be a number of seconds or a duration like \"30s\", got {}",
+                prefix, key, value
+            )
+        })
+}
+
+fn server_table(content: &str) -> Result<Option<toml::Table>, String> {
+    let table: toml::Table = content
+        .parse()
+        .map_err(|e| format!("Invalid n7tya.toml: {}", e))?;
+    match table.get("server") {
+        Some(toml::Value::Table(server)) => Ok(Some(server.clone())),
+        Some(_) => Err("[server] must be a table".to_string()),
+        None => Ok(None),
     }
+}
 
-    pub fn run_server(&mut self, server_def: &ServerDef) -> Result<(), String> {
-        let port = 8080;
-        let addr = format!("127.0.0.1:{}", port);
+/// ルートの情報 (App.routes() と、Rust から埋め込む側に公開する)
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteInfo {
+    pub method: String, // 大文字 (GET, POST ...)
+    pub path: String,
+    pub handler: String,               // ハンドラーの名前 ("POST /users")
+    pub body_type: Option<String>,     // (user: User) の User
+    pub response_type: Option<String>, // -> Json<User> の Json<User>
+}
 
-        let listener =
-            TcpListener::bind(&addr).map_err(|e| format!("Failed to bind port {}: {}", port, e))?;
-        println!("Server '{}' listening on http://{}", server_def.name, addr);
+/// サーバー定義のルートを定義順に列挙する (同じメソッドとパスは先に書いたものだけ)
+pub fn route_infos(server_def: &ServerDef) -> Vec<RouteInfo> {
+    let mut routes: Vec<RouteInfo> = Vec::new();
+    for item in &server_def.body {
+        let ServerBodyItem::Route(route) = item else {
+            continue;
+        };
+        let method = route.method.to_ascii_uppercase();
+        if routes
+            .iter()
+            .any(|r| r.method == method && r.path == route.path)
+        {
+            continue;
+        }
+        routes.push(RouteInfo {
+            handler: format!("{} {}", method, route.path),
+            method,
+            path: route.path.clone(),
+            body_type: route
+                .body_param
+                .as_ref()
+                .and_then(|p| p.type_annotation.as_ref())
+                .map(|t| t.to_string()),
+            response_type: route.return_type.as_ref().map(|t| t.to_string()),
+        });
+    }
+    routes
+}
 
-        // サーバー自体の環境（グローバル環境のコピーなど）を保持したい場合はここで用意
-        // 現状はリクエストごとにグローバルのクローンから開始する形にする
-        let global_env = self.env.clone();
+impl RouteInfo {
+    /// {"method": ..., "path": ..., "handler": ..., "body": ..., "response": ...}
+    fn to_value(&self) -> Value {
+        let text = |s: &str| Value::Str(s.into());
+        let optional = |s: &Option<String>| s.as_deref().map_or(Value::None, text);
+        let mut dict = HashMap::new();
+        dict.insert("method".to_string(), text(&self.method));
+        dict.insert("path".to_string(), text(&self.path));
+        dict.insert("handler".to_string(), text(&self.handler));
+        dict.insert("body".to_string(), optional(&self.body_type));
+        dict.insert("response".to_string(), optional(&self.response_type));
+        Value::Dict(Rc::new(RefCell::new(dict)))
+    }
+}
 
-        for stream in listener.incoming() {
-            let mut stream = stream.map_err(|e| format!("Connection failed: {}", e))?;
+/// 実行時のクラス情報
+struct ClassInfo {
+    def: Rc<ClassDef>,
+    parent: Option<Rc<ClassInfo>>,
+    methods: HashMap<String, Rc<FunctionDef>>,
+    static_methods: HashMap<String, Rc<FunctionDef>>,
+    constants: HashMap<String, Value>, // クラス定義時に評価済み
+    env: Rc<RefCell<Env>>, // クラス定義時の環境 (フィールド初期値・メソッドのクロージャ)
+}
 
-            let mut buffer = [0; 4096];
-            let bytes_read = match stream.read(&mut buffer) {
-                Ok(n) => n,
-                Err(_) => continue,
-            };
-            if bytes_read == 0 {
-                continue;
+impl ClassInfo {
+    /// 継承をたどってメソッドを探す (定義しているクラスと一緒に返す)
+    fn find_method(&self, name: &str) -> Option<(&ClassInfo, Rc<FunctionDef>)> {
+        match self.methods.get(name) {
+            Some(method) => Some((self, method.clone())),
+            None => self.parent.as_ref()?.find_method(name),
+        }
+    }
+
+    /// フィールドの名前 (親クラスのものから定義順、上書きしたフィールドは子クラスの位置)
+    fn field_names(&self) -> Vec<String> {
+        let mut names = match &self.parent {
+            Some(parent) => parent.field_names(),
+            None => Vec::new(),
+        };
+        for item in &self.def.body {
+            if let ClassBodyItem::Field(field) = item {
+                names.retain(|name| name != &field.name);
+                names.push(field.name.clone());
             }
+        }
+        names
+    }
 
-            let request_str = String::from_utf8_lossy(&buffer[..bytes_read]);
-            let mut lines = request_str.lines();
-            
-            // Request Line
-            let first_line = lines.next().unwrap_or("");
-            let parts: Vec<&str> = first_line.split_whitespace().collect();
-
-            let mut response_body = "Not Found".to_string();
-            let mut status = "404 Not Found";
-
-            if parts.len() >= 2 {
-                let method = parts[0].to_string();
-                let path = parts[1].to_string();
-
-                // Parse Headers
-                let mut header_map = HashMap::new();
-                let mut body_start_index = 0;
-                
-                // ヘッダーとボディの境界を探す (\r\n\r\n)
-                if let Some(idx) = request_str.find("\r\n\r\n") {
-                    body_start_index = idx + 4;
-                    // ヘッダー解析
-                    for line in request_str[..idx].lines().skip(1) {
-                        if let Some((k, v)) = line.split_once(':') {
-                            header_map.insert(
-                                k.trim().to_lowercase(),
-                                Value::Str(v.trim().to_string())
-                            );
-                        }
-                    }
-                } else if let Some(idx) = request_str.find("\n\n") {
-                     body_start_index = idx + 2;
-                     // ヘッダー解析 (LFのみの場合)
-                     for line in request_str[..idx].lines().skip(1) {
-                         if let Some((k, v)) = line.split_once(':') {
-                             header_map.insert(
-                                 k.trim().to_lowercase(),
-                                 Value::Str(v.trim().to_string())
-                             );
-                         }
-                     }
-                }
-
-                let body = if body_start_index < request_str.len() {
-                    request_str[body_start_index..].trim_end_matches('\0').to_string()
-                } else {
-                    "".to_string()
-                };
+    /// このクラスか、継承をたどった先が name のクラスか
+    fn is_a(&self, name: &str) -> bool {
+        self.def.name == name || self.parent.as_ref().is_some_and(|parent| parent.is_a(name))
+    }
 
-                for item in &server_def.body {
-                    let crate::ast::ServerBodyItem::Route(route) = item;
-                    if route.method.eq_ignore_ascii_case(&method) && route.path == path {
-                        // ルートマッチ -> 新しいスコープで実行
-                        let request_env =
-                            Rc::new(RefCell::new(Env::with_parent(global_env.clone())));
-                        self.env = request_env;
-
-                        // request オブジェクトを構築して注入
-                        let mut request_data = HashMap::new();
-                        request_data.insert("method".to_string(), Value::Str(method.clone()));
-                        request_data.insert("path".to_string(), Value::Str(path.clone()));
-                        request_data.insert("headers".to_string(), Value::Dict(Rc::new(RefCell::new(header_map))));
-                        request_data.insert("body".to_string(), Value::Str(body.clone()));
-                        // TODO: Query params parsing
-
-                        self.env.borrow_mut().define("request", Value::Dict(Rc::new(RefCell::new(request_data))));
-
-                        let mut route_result = Value::None;
-                        for stmt in &route.body {
-                            match self.eval_statement(stmt) {
-                                Ok(ExecutionResult::Return(v)) => {
-                                    route_result = v;
-                                    break;
-                                }
-                                Ok(ExecutionResult::Value(_)) => {}
-                                Ok(_) => {} // Break/Continue not valid here
-                                Err(e) => {
-                                    println!("Error in route handler: {}", e);
-                                    status = "500 Internal Server Error";
-                                    response_body = format!("Error: {}", e);
-                                    break;
-                                }
-                            }
-                        }
+    /// 継承をたどってクラス定数か static メソッドを探す
+    fn find_static(&self, name: &str) -> Option<Value> {
+        if let Some(value) = self.constants.get(name) {
+            return Some(value.clone());
+        }
+        match self.static_methods.get(name) {
+            Some(method) => Some(Value::Fn(method.clone(), self.env.clone())),
+            None => self.parent.as_ref()?.find_static(name),
+        }
+    }
+}
 
-                        // Returnされた値があればレスポンスにする
-                        if status == "404 Not Found" {
-                            // エラーでなければ
-                            status = "200 OK"; // デフォルト200
-                            if let Value::Str(s) = route_result {
-                                response_body = s;
-                            } else if let Value::None = route_result {
-                                // 何も返さなかった場合は空、あるいはデフォルトメッセージ
-                                if response_body == "Not Found" {
-                                    response_body = "OK".to_string();
-                                }
-                            } else {
-                                // 文字列以外は文字列化
-                                response_body = route_result.display();
-                            }
-                        }
+/// 読み込んだモジュール
+pub struct Module {
+    name: String,
+    exports: HashMap<String, Value>, // トップレベルで定義された名前
+    structs: HashMap<String, Rc<StructDef>>,
+    classes: HashMap<String, Rc<ClassInfo>>,
+    lazy: Option<LazyModule>, // lazy import したモジュール (中身は最初に使うときに読み込む)
+}
 
-                        break;
-                    }
-                }
-            }
+/// lazy import したモジュールの読み込み先
+struct LazyModule {
+    path: PathBuf,
+    modules: std::rc::Weak<RefCell<ModuleCache>>, // import したインタプリタと共有するキャッシュ
+    packages: Rc<HashMap<String, PathBuf>>,
+    scope_rules: ScopeRules,
+    permissions: crate::permissions::Permissions,
+    loaded: OnceCell<Rc<Module>>,
+}
 
-            let response = format!(
-                "HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n{}",
-                status,
-                response_body.len(),
-                response_body
-            );
+impl Module {
+    /// 標準ライブラリのモジュール (fs, json, http など)
+    fn stdlib(name: &str) -> Option<Self> {
+        let (name, functions) = crate::builtins::STDLIB_MODULES
+            .iter()
+            .find(|(module, _)| *module == name)?;
+        let exports = functions
+            .iter()
+            .map(|f| (f.to_string(), Value::BuiltinFn(format!("{}.{}", name, f))))
+            .collect();
+        Some(Self {
+            name: name.to_string(),
+            exports,
+            structs: HashMap::new(),
+            classes: HashMap::new(),
+            lazy: None,
+        })
+    }
 
-            stream.write_all(response.as_bytes()).ok();
-            stream.flush().ok();
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 公開している名前 (lazy import したモジュールは、まだ読み込んでいなければ None)
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        match &self.lazy {
+            Some(lazy) => lazy.loaded.get()?.exports.get(name),
+            None => self.exports.get(name),
         }
+    }
 
-        // Server loop never ends normally usually, but if break loop
-        self.env = global_env; // Restore env
-        Ok(())
+    /// 中身を持つモジュール (lazy import したモジュールは、ここで初めて実行する)
+    fn loaded(&self) -> Result<&Module, String> {
+        let Some(lazy) = &self.lazy else {
+            return Ok(self);
+        };
+        if let Some(module) = lazy.loaded.get() {
+            return Ok(module);
+        }
+        let modules = lazy.modules.upgrade().unwrap_or_default();
+        let module = Interpreter::load_module_file(
+            &self.name,
+            &lazy.path,
+            &modules,
+            &lazy.packages,
+            lazy.scope_rules,
+            &lazy.permissions,
+        )?;
+        Ok(lazy.loaded.get_or_init(|| module))
     }
+}
 
-    pub fn get_output(&self) -> &[String] {
-        &self.output
+impl std::fmt::Debug for Module {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<module {}>", self.name)
     }
+}
 
-    fn eval_item(&mut self, item: &Item) -> Result<Value, String> {
-        match item {
-            Item::FunctionDef(f) => {
-                let func = Value::Fn(Rc::new(f.clone()), self.env.clone());
-                self.env.borrow_mut().define(&f.name, func);
-                Ok(Value::None)
-            }
-            Item::ClassDef(c) => {
-                self.env
-                    .borrow_mut()
-                    .define(&c.name, Value::BuiltinFn(format!("__class_{}", c.name)));
-                Ok(Value::None)
-            }
-            Item::ComponentDef(c) => {
-                // コンポーネント定義を環境に登録 (将来的に使用)
-                self.env
-                    .borrow_mut()
-                    .define(&c.name, Value::BuiltinFn(format!("__component_{}", c.name)));
-                Ok(Value::None)
-            }
-            Item::ServerDef(s) => {
-                // サーバー定義を実行 (簡易HTTPサーバー起動)
-                self.run_server(s)?;
-                Ok(Value::None)
-            }
-            Item::Import(imp) => {
-                self.run_import(imp)?;
-                Ok(Value::None)
-            }
-            Item::Statement(stmt) => self.eval_statement(stmt).map(|res| match res {
-                ExecutionResult::Value(v) => v,
-                ExecutionResult::Return(v) => v, // トップレベルでのreturnは値として扱う
-                _ => Value::None,
-            }),
+/// session に保存する state を訪問者ごとに分けるための Cookie
+const SESSION_COOKIE: &str = "n7tya_session";
+
+/// Cookie ヘッダーからセッション ID を取り出す
+fn session_cookie(headers: &HashMap<String, Value>) -> Option<String> {
+    let Some(Value::Str(cookies)) = headers.get("cookie") else {
+        return None;
+    };
+    cookies.split(';').find_map(|cookie| {
+        let (name, value) = cookie.trim().split_once('=')?;
+        let valid = !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric());
+        (name == SESSION_COOKIE && valid).then(|| value.to_string())
+    })
+}
+
+/// 静的ファイルのレスポンスを書く (fingerprint 付きのファイルは1年キャッシュさせる)
+fn write_static_file(stream: &mut impl Write, file: &crate::assets::StaticFile) {
+    let cache_control = if file.immutable {
+        "public, max-age=31536000, immutable"
+    } else {
+        "no-cache"
+    };
+    let header = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: {}\r\n\r\n",
+        file.content_type,
+        file.body.len(),
+        cache_control
+    );
+    stream.write_all(header.as_bytes()).ok();
+    stream.write_all(&file.body).ok();
+    stream.flush().ok();
+}
+
+/// island から送られた値が、初期値の state に入れられる型か (JSON の整数は Float にも入れられる)
+fn state_value_fits(initial: &Value, value: &Value) -> bool {
+    matches!(initial, Value::None)
+        || initial.type_name() == value.type_name()
+        || matches!((initial, value), (Value::Float(_), Value::Int(_)))
+}
+
+/// 推測されにくいセッション ID (OS の乱数から作る 128 ビット)
+pub(crate) fn new_session_id() -> String {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).expect("failed to read random bytes from the OS");
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// 訪問者ごとのセッション
+struct Session {
+    data: HashMap<String, Value>,
+    used: std::time::Instant, // 最後にリクエストで使った時刻 (session_ttl を過ぎたら消す)
+}
+
+impl Session {
+    fn new() -> Self {
+        Self {
+            data: HashMap::new(),
+            used: std::time::Instant::now(),
         }
     }
+}
 
-    fn eval_statement(&mut self, stmt: &Statement) -> Result<ExecutionResult, String> {
-        match stmt {
-            Statement::Let(decl) => {
-                let value = self.eval_expression(&decl.value)?;
-                self.env.borrow_mut().define(&decl.name, value);
-                Ok(ExecutionResult::Value(Value::None))
-            }
-            Statement::Const(decl) => {
+/// 最初の引数のパスを読む組み込み関数 (静的サイトのページの依存として記録する)
+const READ_BUILTINS: &[&str] = &[
+    "fs.read_file",
+    "fs.try_read_file",
+    "fs.read_dir",
+    "fs.exists",
+    "sqlite.open",
+];
+
+/// リクエストのスパン (名前は "GET /users/*" のようなルートのパターン)
+fn request_span(
+    telemetry: &mut Telemetry,
+    server: &str,
+    routes: &RouteTable,
+    method: &str,
+    path: &str,
+    headers: &HashMap<String, Value>,
+) -> crate::telemetry::Span {
+    let (route_path, query) = path.split_once('?').unwrap_or((path, ""));
+    let route = routes.get(method, path);
+    let name = route.map_or_else(|| format!("{} {}", method, route_path), |r| r.name.clone());
+    let remote = crate::telemetry::SpanContext::from_headers(headers);
+    let mut span = telemetry.start(&name, SpanKind::Server, remote);
+    span.set_str("http.request.method", method);
+    span.set_str("url.path", route_path);
+    if !query.is_empty() {
+        span.set_str("url.query", query);
+    }
+    if let Some(route) = route {
+        let pattern = route.name.split_once(' ').map_or("", |(_, p)| p);
+        span.set_str("http.route", pattern);
+    }
+    span.set_str("n7tya.server", server);
+    span
+}
+
+/// スタックトレースの1段 (呼び出した関数と、呼び出した位置)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub function: String,
+    pub file: Rc<str>,
+    pub line: usize,
+}
+
+impl std::fmt::Display for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "in {}, called from {}:{}",
+            self.function, self.file, self.line
+        )
+    }
+}
+
+/// スタックトレースに出す呼び出し先の名前 (f(x) なら f、user.save() なら user.save)
+fn callee_name(func: &Expression) -> String {
+    match func {
+        Expression::Identifier(name) => name.clone(),
+        Expression::MemberAccess(member) => {
+            format!("{}.{}", callee_name(&member.object), member.member)
+        }
+        Expression::Call(call) => format!("{}()", callee_name(&call.func)),
+        _ => "<anonymous>".to_string(),
+    }
+}
+
+/// 読み込み済みのモジュールと、読み込み中のモジュール
+///
+/// インポートしたモジュールの中の import とも共有し、同じファイルは1度だけ実行する
+#[derive(Default)]
+struct ModuleCache {
+    loaded: HashMap<PathBuf, Rc<Module>>,
+    loading: Vec<(PathBuf, String)>, // 実行中のモジュールのパスと名前 (import した順)
+}
+
+/// 起動したサーバー (App.start() か serve で起動し、stop() まで接続を受け付ける)
+struct RunningServer {
+    def: Rc<ServerDef>,
+    routes: Rc<RouteTable>,
+    global_env: Rc<RefCell<Env>>, // ハンドラーを実行する環境
+    port: u16,
+    started: std::time::Instant,
+    stopping: Arc<AtomicBool>, // 受け付けるスレッドに止まるよう伝える
+    accepting: Option<thread::JoinHandle<()>>, // 接続を受け付けるスレッド
+}
+
+impl RunningServer {
+    fn is_running(&self) -> bool {
+        !self.stopping.load(Ordering::SeqCst)
+    }
+}
+
+/// インタプリタ
+pub struct Interpreter {
+    env: Rc<RefCell<Env>>,
+    output: Vec<String>, // printの出力を格納
+    structs: HashMap<String, Rc<StructDef>>,
+    classes: HashMap<String, Rc<ClassInfo>>,
+    propagating: Option<Value>, // ? で関数から返そうとしている Err / none
+    base_dir: PathBuf,          // import を解決するディレクトリ (実行中のファイルの場所)
+    servers: HashMap<String, Rc<ServerDef>>,
+    components: HashMap<String, Rc<ComponentDef>>,
+    render_mode: RenderMode, // JSX の描画モード (render_email の間だけ Email)
+    rendered_islands: Vec<String>, // render_page の間に描画した island component
+    rendered_router: bool,   // render_page の間に <Router> を描画したか
+    request_path: String,    // 処理中のリクエストのパス (<Router> が使う、リクエスト外では "/")
+    persisted: HashMap<String, Value>, // memory に保存する state ("Component.state" ごと)
+    sessions: HashMap<String, Session>, // session に保存する state など (セッション ID ごと)
+    sessions_swept: std::time::Instant, // 期限切れのセッションを最後に消した時刻
+    session: String,         // 処理中のリクエストのセッション ID (リクエスト外では空)
+    modules: Rc<RefCell<ModuleCache>>,
+    packages: Rc<HashMap<String, PathBuf>>, // 依存パッケージ名とそのディレクトリ
+    assets: Option<crate::assets::Assets>,  // サーバーで配信する静的ファイル (public/ か dist/)
+    stylesheets: Vec<String>,               // render_page のページに <link> で読み込む CSS
+    server_options: ServerOptions,          // n7tya.toml の [server]
+    ready_checks: Vec<(String, Value)>,     // health.check で登録した (名前, 関数)
+    config: HashMap<String, Value>,         // 設定ファイルの [config] (config.get で読む)
+    config_file: Option<PathBuf>,           // SIGHUP で読み直す設定ファイル
+    file: Rc<str>,                          // 実行中の関数を定義したファイル
+    call_stack: Vec<Frame>,                 // 実行中の呼び出し (外側から順)
+    error_trace: Option<(String, Vec<Frame>)>, // 最後のエラーとその時点の呼び出し
+    server_sections: HashMap<String, ServerOptions>, // [server.名前] (サーバーごとの設定)
+    pending_servers: Vec<Rc<ServerDef>>,    // serve で起動するサーバー (定義順)
+    running: Vec<RunningServer>,            // 起動したサーバー (番号順、止めたものも残す)
+    connection_sender: mpsc::Sender<(usize, TcpStream)>, // 受け付けた接続を送る (サーバーの番号つき)
+    connections: mpsc::Receiver<(usize, TcpStream)>,
+    reload: Option<Arc<AtomicBool>>, // SIGHUP を受け取ったら true (最初のサーバーの起動時に登録)
+    request_hooks: Vec<Box<dyn crate::hooks::RequestHook>>, // 埋め込む側がルートの前後に差し込む処理
+    serving: String, // 処理中のリクエストを受けたサーバーの名前 (リクエスト外では空)
+    telemetry: Option<Telemetry>, // [telemetry] があればリクエストなどのトレースを送る
+    reads: Option<Vec<String>>, // 記録中なら、fs.read_file などで読んだパス (静的サイトの依存)
+    permissions: crate::permissions::Permissions, // 組み込み関数に許すファイル・ネットワークなどの権限
+    cycles: crate::gc::CycleCollector, // クロージャが捕まえた環境 (循環だけで残ったものを回収する)
+    limits: crate::limits::Limits,     // run とルートの処理1回ごとのステップ数・時間・メモリの上限
+    budget: crate::limits::Budget,     // 実行中の処理で使ったステップ数と時間
+    cache: crate::cache::Cache,        // cache モジュールで保存した値 (リクエストをまたいで残る)
+    circuits: crate::resilience::Circuits, // circuit モジュールの呼び出し先ごとの失敗の記録
+    args: Vec<String>,                 // os.args() が返すコマンドライン引数
+    scope_rules: ScopeRules,           // let を書かない代入を許すか (import したモジュールにも使う)
+    events: crate::events::Events,     // events.on で購読した関数 (リクエストをまたいで残る)
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+thread_local! {
+    /// 組み込み関数と標準ライブラリのモジュールを入れたグローバル環境の中身
+    /// (スレッドで一度だけ作り、インタプリタを作るたびに複製する。モジュールの中身は共有する)
+    static BUILTIN_GLOBALS: HashMap<String, Value> = Interpreter::builtin_globals();
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        let mut env = Env::new();
+        env.values = BUILTIN_GLOBALS.with(HashMap::clone);
+        let env = Rc::new(RefCell::new(env));
+        let (connection_sender, connections) = mpsc::channel();
+
+        Self {
+            env,
+            output: Vec::new(),
+            structs: HashMap::new(),
+            classes: HashMap::new(),
+            propagating: None,
+            base_dir: PathBuf::from("."),
+            servers: HashMap::new(),
+            components: HashMap::new(),
+            render_mode: RenderMode::Page,
+            rendered_islands: Vec::new(),
+            rendered_router: false,
+            request_path: "/".to_string(),
+            persisted: HashMap::new(),
+            sessions: HashMap::new(),
+            sessions_swept: std::time::Instant::now(),
+            session: String::new(),
+            modules: Rc::default(),
+            packages: Rc::new(HashMap::new()),
+            assets: None,
+            stylesheets: Vec::new(),
+            server_options: ServerOptions::default(),
+            server_sections: HashMap::new(),
+            pending_servers: Vec::new(),
+            running: Vec::new(),
+            connection_sender,
+            connections,
+            reload: None,
+            request_hooks: Vec::new(),
+            serving: String::new(),
+            telemetry: None,
+            reads: None,
+            permissions: crate::permissions::Permissions::default(),
+            cycles: crate::gc::CycleCollector::new(),
+            limits: crate::limits::Limits::default(),
+            budget: crate::limits::Budget::start(crate::limits::Limits::default()),
+            cache: crate::cache::Cache::default(),
+            circuits: crate::resilience::Circuits::default(),
+            args: Vec::new(),
+            scope_rules: ScopeRules::default(),
+            events: crate::events::Events::default(),
+            ready_checks: Vec::new(),
+            config: HashMap::new(),
+            config_file: None,
+            file: Rc::from("<input>"),
+            call_stack: Vec::new(),
+            error_trace: None,
+        }
+    }
+
+    /// 組み込み関数と標準ライブラリのモジュール (BUILTIN_GLOBALS の中身)
+    fn builtin_globals() -> HashMap<String, Value> {
+        // 組み込み関数を登録
+        let builtins = [
+            "print", "println", "len", "range", "input", "str", "int", "float", "type", "abs",
+            "min", "max", "sum", "sorted", "reversed", "enumerate", "zip", "zip_longest",
+            "Ok", "Err", "Some", "await_all", "race", "render_email", "render_page", "decode_form",
+            "redirect", "csrf_token", "map", "filter", "reduce", "list", "set", "hex", "bin", "oct",
+            "bit_length", "popcount", "format", "retry", "assert_valid", "decimal", "diff", "patch",
+            "assert_eq",
+        ];
+        let mut globals = HashMap::new();
+        for name in builtins {
+            globals.insert(name.to_string(), Value::BuiltinFn(name.to_string()));
+        }
+        // 標準ライブラリのモジュールは import しなくても使える
+        for (name, _) in crate::builtins::STDLIB_MODULES {
+            if let Some(module) = Module::stdlib(name) {
+                globals.insert(name.to_string(), Value::Module(Rc::new(module)));
+            }
+        }
+        globals.insert("None".to_string(), Value::None);
+        globals
+    }
+
+    pub fn run(&mut self, program: &Program) -> Result<Value, String> {
+        let mut result = Value::None;
+        self.budget = crate::limits::Budget::start(self.limits);
+
+        // import は書いた位置にかかわらず、ほかのトップレベルの文より先に書いた順で実行する
+        // (モジュールのトップレベルは、そのモジュールが import するモジュールのあとに実行される)
+        for item in &program.items {
+            if let Item::Import(import) = item {
+                self.run_import(import)
+                    .inspect_err(|_| self.propagating = None)?;
+            }
+        }
+
+        for item in &program.items {
+            // コメント・空行と実行済みの import は最後の値を上書きしない
+            if matches!(
+                item,
+                Item::Statement(Statement::Trivia(_)) | Item::Import(_)
+            ) {
+                continue;
+            }
+            result = self
+                .eval_item(item)
+                .inspect_err(|_| self.propagating = None)?;
+
+            // Return値が出たら終了
+            if let Value::Return(v) = result {
+                return Ok(*v);
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// 定義したサーバーのうち、まだ起動していないものをすべて起動して、すべて止まるまで待つ
+    /// (run でプログラムを最後まで実行してから呼ぶ)
+    ///
+    /// サーバーごとのポートで受け付けた接続を、このスレッドで1つずつ処理する。
+    pub fn serve(&mut self) -> Result<(), String> {
+        let servers = std::mem::take(&mut self.pending_servers);
+        let mut ports: HashMap<u16, &str> = HashMap::new();
+        for server_def in &servers {
+            let port = self.options_for(&server_def.name).port;
+            if let Some(other) = ports.insert(port, &server_def.name) {
+                return Err(format!(
+                    "Servers '{}' and '{}' both use port {}; set port in [server.{}]",
+                    other, server_def.name, port, server_def.name
+                ));
+            }
+        }
+        let global_env = self.global_env();
+        for server_def in servers {
+            let port = self.options_for(&server_def.name).port;
+            self.start_server(server_def, port, global_env.clone())?;
+        }
+        while self.running.iter().any(RunningServer::is_running) {
+            let (id, stream) = self
+                .connections
+                .recv()
+                .map_err(|e| format!("Connection failed: {}", e))?;
+            self.handle_connection(id, stream);
+        }
+        Ok(())
+    }
+
+    /// サーバーを起動する (port が 0 なら空いているポート)。返すのはサーバーの番号
+    fn start_server(
+        &mut self,
+        server_def: Rc<ServerDef>,
+        port: u16,
+        global_env: Rc<RefCell<Env>>,
+    ) -> Result<usize, String> {
+        let mut running = self.running.iter().filter(|r| r.is_running());
+        if let Some(running) = running.find(|r| r.def.name == server_def.name) {
+            return Err(format!(
+                "Server '{}' is already running on port {}",
+                running.def.name, running.port
+            ));
+        }
+        self.permissions.check_net("127.0.0.1", port)?;
+        let addr = format!("127.0.0.1:{}", port);
+        let listener =
+            TcpListener::bind(&addr).map_err(|e| format!("Failed to bind port {}: {}", port, e))?;
+        let port = listener.local_addr().map_or(port, |addr| addr.port());
+        println!(
+            "Server '{}' listening on http://127.0.0.1:{}",
+            server_def.name, port
+        );
+
+        // 接続は受け付けるスレッドからこのスレッドに送り、ここで1つずつ処理する
+        let id = self.running.len();
+        let stopping = Arc::new(AtomicBool::new(false));
+        let sender = self.connection_sender.clone();
+        let stop = stopping.clone();
+        let accepting = thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if stop.load(Ordering::SeqCst) || sender.send((id, stream)).is_err() {
+                    break;
+                }
+            }
+        });
+        if self.reload.is_none() {
+            self.reload = Some(crate::config::reload_signal());
+        }
+        self.pending_servers
+            .retain(|pending| pending.name != server_def.name);
+        self.running.push(RunningServer {
+            routes: Rc::new(RouteTable::compile(&server_def)),
+            def: server_def,
+            global_env,
+            port,
+            started: std::time::Instant::now(),
+            stopping,
+            accepting: Some(accepting),
+        });
+        Ok(id)
+    }
+
+    /// サーバーを止める (受け付けるスレッドを起こすため、自分のポートに1度つなぐ)
+    ///
+    /// 受け付けるスレッドが終わってポートを閉じるまで待つ。
+    fn stop_server(&mut self, id: usize) {
+        let Some(running) = self.running.get_mut(id).filter(|r| r.is_running()) else {
+            return;
+        };
+        running.stopping.store(true, Ordering::SeqCst);
+        let _ = TcpStream::connect(("127.0.0.1", running.port));
+        if let Some(accepting) = running.accepting.take() {
+            let _ = accepting.join();
+        }
+        println!("Server '{}' stopped", running.def.name);
+    }
+
+    /// サーバーが止まるまで、届いた接続を処理しながら待つ
+    fn wait_server(&mut self, id: usize) -> Result<(), String> {
+        while self.running.get(id).is_some_and(RunningServer::is_running) {
+            let (id, stream) = self
+                .connections
+                .recv()
+                .map_err(|e| format!("Connection failed: {}", e))?;
+            self.handle_connection(id, stream);
+        }
+        Ok(())
+    }
+
+    /// 別スレッドの結果を待つ (その間に届いたサーバーへの接続も処理する)
+    ///
+    /// 起動したサーバーに同じプログラムから http.get などでリクエストしても止まらないようにする。
+    fn receive<T>(&mut self, receiver: &mpsc::Receiver<T>) -> Option<T> {
+        loop {
+            if !self.running.iter().any(RunningServer::is_running) {
+                return receiver.recv().ok();
+            }
+            match receiver.try_recv() {
+                Ok(value) => return Some(value),
+                Err(mpsc::TryRecvError::Disconnected) => return None,
+                Err(mpsc::TryRecvError::Empty) => {}
+            }
+            let timeout = std::time::Duration::from_millis(5);
+            if let Ok((id, stream)) = self.connections.recv_timeout(timeout) {
+                self.handle_connection(id, stream);
+            }
+        }
+    }
+
+    /// 1つの接続のリクエストを処理してレスポンスを書く
+    fn handle_connection(&mut self, id: usize, stream: TcpStream) {
+        let Some(running) = self.running.get(id).filter(|r| r.is_running()) else {
+            return;
+        };
+        let server_def = running.def.clone();
+        let routes = running.routes.clone();
+        let global_env = running.global_env.clone();
+        let started = running.started;
+        if self
+            .reload
+            .as_ref()
+            .is_some_and(|reload| reload.swap(false, Ordering::SeqCst))
+        {
+            self.reload_config();
+        }
+
+        // リクエストの間だけ、処理中のサーバーとセッションを切り替える
+        // (プログラムの途中で接続を処理することもあるので、終わったら元に戻す)
+        let outer = (
+            std::mem::replace(&mut self.serving, server_def.name.clone()),
+            std::mem::take(&mut self.session),
+            std::mem::replace(&mut self.request_path, "/".to_string()),
+        );
+        self.respond(&server_def, &routes, &global_env, started, stream);
+        (self.serving, self.session, self.request_path) = outer;
+    }
+
+    /// リクエストを読んで、静的ファイルかヘルスチェックかルートのレスポンスを書く
+    fn respond(
+        &mut self,
+        server_def: &ServerDef,
+        routes: &RouteTable,
+        global_env: &Rc<RefCell<Env>>,
+        started: std::time::Instant,
+        mut stream: TcpStream,
+    ) {
+        let mut buffer = [0; 4096];
+        let bytes_read = match stream.read(&mut buffer) {
+            Ok(n) => n,
+            Err(_) => return,
+        };
+        if bytes_read == 0 {
+            return;
+        }
+
+        let request_str = String::from_utf8_lossy(&buffer[..bytes_read]);
+        let mut lines = request_str.lines();
+
+        // Request Line
+        let first_line = lines.next().unwrap_or("");
+        let parts: Vec<&str> = first_line.split_whitespace().collect();
+
+        let mut response_body = "Not Found".to_string();
+        let mut status = "404 Not Found".to_string();
+        let mut content_type = "text/plain; charset=utf-8".to_string();
+        let mut set_cookie = String::new();
+        let mut location = String::new();
+        let mut span = None;
+
+        if parts.len() >= 2 {
+            let method = parts[0].to_string();
+            let path = parts[1].to_string();
+
+            // ルートより先に静的ファイルを探す
+            let file = match &self.assets {
+                Some(assets) if method == "GET" => assets.file(&path),
+                _ => None,
+            };
+            if let Some(file) = file {
+                write_static_file(&mut stream, &file);
+                return;
+            }
+
+            // Parse Headers
+            let mut header_map = HashMap::new();
+            let mut body_start_index = 0;
+
+            // ヘッダーとボディの境界を探す (\r\n\r\n)
+            if let Some(idx) = request_str.find("\r\n\r\n") {
+                body_start_index = idx + 4;
+                // ヘッダー解析
+                for line in request_str[..idx].lines().skip(1) {
+                    if let Some((k, v)) = line.split_once(':') {
+                        header_map.insert(k.trim().to_lowercase(), Value::Str(v.trim().into()));
+                    }
+                }
+            } else if let Some(idx) = request_str.find("\n\n") {
+                body_start_index = idx + 2;
+                // ヘッダー解析 (LFのみの場合)
+                for line in request_str[..idx].lines().skip(1) {
+                    if let Some((k, v)) = line.split_once(':') {
+                        header_map.insert(k.trim().to_lowercase(), Value::Str(v.trim().into()));
+                    }
+                }
+            }
+
+            let body = if body_start_index < request_str.len() {
+                request_str[body_start_index..]
+                    .trim_end_matches('\0')
+                    .to_string()
+            } else {
+                "".to_string()
+            };
+
+            if let Some(telemetry) = &mut self.telemetry {
+                span = Some(request_span(
+                    telemetry,
+                    &server_def.name,
+                    routes,
+                    &method,
+                    &path,
+                    &header_map,
+                ));
+            }
+
+            // session に保存する state は Cookie のセッション ID ごとに分ける
+            let cookie = session_cookie(&header_map);
+            self.session = match self.known_session(cookie) {
+                Some(id) => id,
+                None => {
+                    let id = new_session_id();
+                    set_cookie = format!(
+                        "Set-Cookie: {}={}; Path=/; HttpOnly; SameSite=Lax\r\n",
+                        SESSION_COOKIE, id
+                    );
+                    id
+                }
+            };
+
+            // 埋め込む側のフックがレスポンスを返せば、ルートは呼ばない
+            let hook_request = (!self.request_hooks.is_empty()).then(|| {
+                crate::hooks::Request::new(&server_def.name, &method, &path, &header_map, &body)
+            });
+            let intercepted = hook_request.as_ref().and_then(|request| {
+                self.request_hooks
+                    .iter_mut()
+                    .find_map(|hook| hook.before(request))
+            });
+
+            if let Some(response) = intercepted {
+                status = crate::hooks::status_line(response.status);
+                content_type = response.content_type;
+                response_body = response.body;
+            } else {
+                let options = self.options_for(&server_def.name).clone();
+                let forged = options.csrf
+                    && crate::csrf::needs_check(self, &method, &header_map)
+                    && !crate::csrf::verify(self, &header_map, &body);
+                // ヘルスチェック (同じパスのルートを定義していればそちらを使う)
+                let route_path = path.split('?').next().unwrap_or(&path);
+                let builtin = method == "GET" && !routes.has_exact(&method, route_path);
+                let live = builtin && route_path == options.health_path;
+                let ready = builtin && route_path == options.ready_path;
+                let (route_status, route_body) = if forged {
+                    ("403 Forbidden", "Invalid CSRF token".to_string())
+                } else if live {
+                    let body = crate::health::liveness(server_def, started.elapsed());
+                    ("200 OK", body)
+                } else if ready {
+                    crate::health::readiness(self)
+                } else if method == "POST" && path == crate::islands::STATE_ENDPOINT {
+                    self.save_island_state(&body)
+                } else {
+                    self.dispatch(routes, global_env, &method, &path, header_map, body)
+                };
+                status = route_status.to_string();
+                response_body = route_body;
+                if status == "200 OK" {
+                    content_type = routes
+                        .content_type(&method, &path, &response_body)
+                        .to_string();
+                }
+                if live || ready {
+                    content_type = "application/json".to_string();
+                }
+            }
+            if let Some(request) = &hook_request {
+                let mut response = crate::hooks::Response {
+                    status: crate::hooks::status_code(&status),
+                    content_type,
+                    body: response_body,
+                };
+                for hook in &mut self.request_hooks {
+                    hook.after(request, &mut response);
+                }
+                status = crate::hooks::status_line(response.status);
+                content_type = response.content_type;
+                response_body = response.body;
+            }
+            if status == "303 See Other" {
+                location = format!("Location: {}\r\n", response_body);
+                response_body.clear();
+            }
+        }
+        if let (Some(telemetry), Some(mut span)) = (&mut self.telemetry, span) {
+            let code = crate::hooks::status_code(&status);
+            span.set_int("http.response.status_code", code as i64);
+            if code >= 500 {
+                span.fail(&status);
+            }
+            telemetry.end(span);
+        }
+
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}{}\r\n{}",
+            status,
+            content_type,
+            response_body.len(),
+            set_cookie,
+            location,
+            response_body
+        );
+
+        stream.write_all(response.as_bytes()).ok();
+        stream.flush().ok();
+    }
+
+    /// public/ のファイルを配信する (build 後は dist/ の fingerprint 付きのファイル)
+    pub fn set_assets(&mut self, assets: crate::assets::Assets) {
+        self.assets = Some(assets);
+    }
+
+    /// ルートの処理の前後に呼ぶフックを加える (登録順に呼ぶ)
+    pub fn add_request_hook(&mut self, hook: impl crate::hooks::RequestHook + 'static) {
+        self.request_hooks.push(Box::new(hook));
+    }
+
+    /// CSRF 対策とヘルスチェックのパスを設定する (n7tya.toml の [server])
+    pub fn set_server_options(&mut self, options: ServerOptions) {
+        self.server_options = options;
+    }
+
+    /// [server] の設定と [config] の値を設定する
+    pub fn set_config(&mut self, config: crate::config::AppConfig) {
+        self.server_options = config.server;
+        self.server_sections = config.servers;
+        self.config = config.values;
+        self.cache.set_max_entries(config.cache_entries);
+        // 送信先が変わったときだけ作り直す (前のものは送っていないスパンを送ってから終わる)
+        if config.telemetry.as_ref() != self.telemetry.as_ref().map(Telemetry::options) {
+            self.telemetry = config.telemetry.map(Telemetry::new);
+        }
+    }
+
+    /// サーバーの設定 ([server.名前] があればそちら)
+    fn options_for(&self, server: &str) -> &ServerOptions {
+        self.server_sections
+            .get(server)
+            .unwrap_or(&self.server_options)
+    }
+
+    /// 設定ファイルを読み込む (サーバーの実行中は SIGHUP でこのファイルを読み直す)
+    pub fn load_config(&mut self, path: &Path) -> Result<(), String> {
+        self.set_config(crate::config::AppConfig::load(path)?);
+        self.config_file = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    /// 設定ファイルを読み直す (誤りがあれば前の設定のまま動き続ける)
+    fn reload_config(&mut self) {
+        let Some(path) = self.config_file.clone() else {
+            return;
+        };
+        match crate::config::AppConfig::load(&path) {
+            Ok(config) => {
+                self.set_config(config);
+                println!("Reloaded {}", path.display());
+            }
+            Err(e) => println!("Failed to reload {}: {}", path.display(), e),
+        }
+    }
+
+    pub(crate) fn config_value(&self, key: &str) -> Option<Value> {
+        self.config.get(key).cloned()
+    }
+
+    pub(crate) fn add_ready_check(&mut self, name: String, check: Value) {
+        self.ready_checks.push((name, check));
+    }
+
+    pub(crate) fn ready_checks(&self) -> Vec<(String, Value)> {
+        self.ready_checks.clone()
+    }
+
+    pub(crate) fn cache(&mut self) -> &mut crate::cache::Cache {
+        &mut self.cache
+    }
+
+    pub(crate) fn circuits(&mut self) -> &mut crate::resilience::Circuits {
+        &mut self.circuits
+    }
+
+    pub(crate) fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    pub(crate) fn events(&mut self) -> &mut crate::events::Events {
+        &mut self.events
+    }
+
+    /// render_page のページに読み込む CSS のパス ([build] css のコマンドの出力)
+    pub fn set_stylesheets(&mut self, stylesheets: Vec<String>) {
+        self.stylesheets = stylesheets;
+    }
+
+    /// island のクライアント JS が送ってきた state を、送った訪問者のセッションに保存する
+    ///
+    /// ボディは {"island": "Counter", "state": {"count": 1}}。session に保存する state だけを受け付け、
+    /// 全員で共有する memory の state は書き換えさせない。値は初期値と同じ型でなければならない
+    /// (初期値が none なら何でもよい)。1つでも受け付けられなければ何も保存しない。
+    fn save_island_state(&mut self, body: &str) -> (&'static str, String) {
+        let invalid = || ("400 Bad Request", "Invalid island state".to_string());
+        let Ok(serde_json::Value::Object(mut request)) = serde_json::from_str(body) else {
+            return invalid();
+        };
+        let component = match request.get("island") {
+            Some(serde_json::Value::String(name)) => self.components.get(name).cloned(),
+            _ => None,
+        };
+        let (Some(component), Some(serde_json::Value::Object(state))) =
+            (component.filter(|c| c.island), request.remove("state"))
+        else {
+            return invalid();
+        };
+        let mut values = Vec::new();
+        for (name, value) in state {
+            let decl = component.body.iter().find_map(|item| match item {
+                ComponentBodyItem::State(decl) if decl.name == name => Some(decl),
+                _ => None,
+            });
+            let Some(decl) = decl else {
+                return invalid();
+            };
+            match decl.persist {
+                Some(Persist::Session) => {}
+                Some(Persist::Memory) => {
+                    return (
+                        "403 Forbidden",
+                        format!(
+                            "State '{}' is shared by all visitors and cannot be saved",
+                            name
+                        ),
+                    )
+                }
+                _ => return invalid(),
+            }
+            let Ok(initial) = self.eval_expression(&decl.value) else {
+                return invalid();
+            };
+            let value = crate::builtins::json_to_value(value);
+            if !state_value_fits(&initial, &value) {
+                return (
+                    "400 Bad Request",
+                    format!(
+                        "State '{}' expects {}, got {}",
+                        name,
+                        initial.type_name(),
+                        value.type_name()
+                    ),
+                );
+            }
+            values.push((name, value));
+        }
+        for (name, value) in values {
+            self.persist_state(&component.name, &name, Persist::Session, value);
+        }
+        ("200 OK", "OK".to_string())
+    }
+
+    /// 保存済みの state の値 (保存先がないか、まだ保存していなければ None)
+    fn persisted_state(&self, component: &str, decl: &StateDecl) -> Option<Value> {
+        let key = format!("{}.{}", component, decl.name);
+        match decl.persist? {
+            Persist::Memory => self.persisted.get(&key).cloned(),
+            Persist::Session => self.session_value(&key).cloned(),
+            // localStorage はブラウザにしかないので、クライアント JS が描画後に戻す
+            Persist::Local => None,
+        }
+    }
+
+    /// 処理中のリクエストの Cookie のセッションがサーバーにあるか
+    pub(crate) fn has_session(&self) -> bool {
+        self.sessions.contains_key(&self.session)
+    }
+
+    /// 処理中のリクエストのセッションに保存した値 (セッションがなくても作らない)
+    pub(crate) fn session_value(&self, key: &str) -> Option<&Value> {
+        self.sessions.get(&self.session)?.data.get(key)
+    }
+
+    /// 処理中のリクエストのセッションに保存した値 (flash のメッセージなど)
+    pub(crate) fn session_data(&mut self) -> &mut HashMap<String, Value> {
+        &mut self
+            .sessions
+            .entry(self.session.clone())
+            .or_insert_with(Session::new)
+            .data
+    }
+
+    /// Cookie のセッション ID を使えるか確かめる (サーバーが発行して、期限が切れていないものだけ)
+    ///
+    /// 知らない ID をそのまま使うと、攻撃者が決めた ID を訪問者に使わせられる (セッション固定) ので、
+    /// 呼ぶ側で新しい ID を発行する。期限切れのセッションもここで (1分に1回まとめて) 消す。
+    fn known_session(&mut self, id: Option<String>) -> Option<String> {
+        let ttl = self.server_options.session_ttl;
+        if self.sessions_swept.elapsed() >= std::time::Duration::from_secs(60) {
+            self.sessions
+                .retain(|_, session| session.used.elapsed() < ttl);
+            self.sessions_swept = std::time::Instant::now();
+        }
+        let id = id?;
+        let session = self.sessions.get_mut(&id)?;
+        if session.used.elapsed() >= ttl {
+            self.sessions.remove(&id);
+            return None;
+        }
+        session.used = std::time::Instant::now();
+        Some(id)
+    }
+
+    fn persist_state(&mut self, component: &str, name: &str, persist: Persist, value: Value) {
+        let key = format!("{}.{}", component, name);
+        match persist {
+            Persist::Memory => {
+                self.persisted.insert(key, value);
+            }
+            Persist::Session => {
+                self.session_data().insert(key, value);
+            }
+            Persist::Local => {}
+        }
+    }
+
+    /// マッチしたルートの処理を実行し、(ステータス, レスポンス本文) を返す
+    fn dispatch(
+        &mut self,
+        routes: &RouteTable,
+        global_env: &Rc<RefCell<Env>>,
+        method: &str,
+        path: &str,
+        headers: HashMap<String, Value>,
+        body: String,
+    ) -> (&'static str, String) {
+        let Some(handler) = routes.get(method, path) else {
+            return ("404 Not Found", "Not Found".to_string());
+        };
+        self.request_path = path.split('?').next().unwrap_or(path).to_string();
+
+        // 型付きのボディは JSON (フォームならフォーム) を型どおりに変換してから渡す (合わなければ 400)
+        let form = matches!(headers.get("content-type"),
+            Some(Value::Str(t)) if crate::forms::is_form_content_type(t));
+        let typed_body = match handler.params.get(1) {
+            Some(param) => match self.decode_body(&body, param, form) {
+                Ok(value) => Some(value),
+                Err(e) => return ("400 Bad Request", format!("Invalid request body: {}", e)),
+            },
+            None => None,
+        };
+
+        // request オブジェクトを構築してハンドラに渡す
+        let mut request_data = HashMap::new();
+        request_data.insert("method".to_string(), Value::Str(method.into()));
+        request_data.insert("path".to_string(), Value::Str(path.into()));
+        request_data.insert("headers".to_string(), Value::Dict(Rc::new(RefCell::new(headers))));
+        request_data.insert("body".to_string(), Value::Str(body.into()));
+        let query = path.split_once('?').map_or("", |(_, query)| query);
+        request_data.insert("query".to_string(), crate::url::query_dict(query));
+        let mut args = vec![Value::Dict(Rc::new(RefCell::new(request_data)))];
+        args.extend(typed_body);
+        let json = matches!(handler.return_type, Some(Type::Json(_)));
+
+        // ルートの処理ごとに上限を数え直す (無限ループでもサーバーは止まらず 500 を返す)
+        let limits = self.limits.or(self.options_for(&self.serving).limits);
+        let outer = std::mem::replace(&mut self.budget, crate::limits::Budget::start(limits));
+        let handler = Value::Fn(handler.clone(), global_env.clone());
+        let result = self.call_function(handler, args);
+        self.budget = outer;
+        match result {
+            // redirect(path) は本文の代わりに移動先を返す (run_server が Location ヘッダーにする)
+            Ok(Value::BuiltinFn(handle)) if handle.starts_with(REDIRECT_PREFIX) => {
+                ("303 See Other", handle[REDIRECT_PREFIX.len()..].to_string())
+            }
+            Ok(value) if json => (
+                "200 OK",
+                serde_json::to_string(&crate::builtins::value_to_json(&value)).unwrap_or_default(),
+            ),
+            // Returnされた値をレスポンスにする (文字列以外は文字列化)
+            Ok(Value::Str(s)) => ("200 OK", s.to_string()),
+            Ok(Value::None) => ("200 OK", "OK".to_string()),
+            Ok(value) => ("200 OK", value.display()),
+            Err(e) => {
+                println!("Error in route handler: {}", e);
+                for frame in self.take_stack_trace() {
+                    println!("    {}", frame);
+                }
+                ("500 Internal Server Error", format!("Error: {}", e))
+            }
+        }
+    }
+
+    /// リクエストボディの JSON をルートで宣言した型の値にする
+    fn decode_body(&self, text: &str, param: &Param, form: bool) -> Result<Value, String> {
+        if form {
+            return match &param.type_annotation {
+                Some(ty) => self.decode_form_body(text, ty, &param.name),
+                None => Err(format!(
+                    "'{}' needs a struct type to read a form",
+                    param.name
+                )),
+            };
+        }
+        let json = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        match &param.type_annotation {
+            Some(ty) => self.decode_json(json, ty, &param.name),
+            None => Ok(crate::builtins::json_to_value(json)),
+        }
+    }
+
+    /// JSON の値が型に合っているか確かめながら変換する (path はエラー表示用の位置)
+    fn decode_json(&self, json: serde_json::Value, ty: &Type, path: &str) -> Result<Value, String> {
+        use serde_json::Value as Json;
+        let mismatch = || format!("'{}' must be {}", path, ty);
+        match (ty, json) {
+            (Type::Int, Json::Number(n)) => n.as_i64().map(Value::Int).ok_or_else(mismatch),
+            (Type::Float, Json::Number(n)) => n.as_f64().map(Value::Float).ok_or_else(mismatch),
+            (Type::Str, Json::String(s)) => Ok(Value::Str(s.into())),
+            (Type::Bool, Json::Bool(b)) => Ok(Value::Bool(b)),
+            (Type::None | Type::Optional(_), Json::Null) => Ok(Value::None),
+            (Type::Optional(inner) | Type::Json(inner), json) => {
+                self.decode_json(json, inner, path)
+            }
+            (Type::Union(members), json) => members
+                .iter()
+                .find_map(|m| self.decode_json(json.clone(), m, path).ok())
+                .ok_or_else(mismatch),
+            (Type::List(inner), Json::Array(items)) => {
+                let mut values = Vec::new();
+                for (i, item) in items.into_iter().enumerate() {
+                    values.push(self.decode_json(item, inner, &format!("{}[{}]", path, i))?);
+                }
+                Ok(Value::List(Rc::new(RefCell::new(values))))
+            }
+            // 精度を落とさないように文字列でも受け取る
+            (Type::Custom(name), Json::String(text)) if name == "Decimal" => {
+                crate::decimal::parse(&text)
+                    .map(Value::Decimal)
+                    .ok_or_else(mismatch)
+            }
+            (Type::Custom(name), Json::Number(n)) if name == "Decimal" => {
+                crate::decimal::parse(&n.to_string())
+                    .map(Value::Decimal)
+                    .ok_or_else(mismatch)
+            }
+            (Type::Custom(name), json) => {
+                let def = self
+                    .structs
+                    .get(name)
+                    .ok_or_else(|| format!("'{}' is not a struct", name))?;
+                let Json::Object(mut object) = json else {
+                    return Err(mismatch());
+                };
+                if let Some(key) = object
+                    .keys()
+                    .find(|k| !def.fields.iter().any(|f| &f.name == *k))
+                {
+                    return Err(format!("'{}.{}' is not a field of {}", path, key, name));
+                }
+                let mut fields = Vec::new();
+                for field in &def.fields {
+                    let field_path = format!("{}.{}", path, field.name);
+                    // 書かれていないフィールドは none を受け付ける型のときだけ許す
+                    let value = match object.remove(&field.name) {
+                        Some(value) => {
+                            self.decode_json(value, &field.type_annotation, &field_path)?
+                        }
+                        None => self
+                            .decode_json(Json::Null, &field.type_annotation, &field_path)
+                            .map_err(|_| format!("'{}' is missing", field_path))?,
+                    };
+                    crate::validate::check_field(field, &value, &field_path)?;
+                    fields.push((field.name.clone(), value));
+                }
+                Ok(Value::Struct(name.clone(), Rc::new(RefCell::new(fields))))
+            }
+            // 中身の型を表せないものは検証せずにそのまま変換する
+            (Type::Dict(..) | Type::Set(_) | Type::Fn(..), json) => {
+                Ok(crate::builtins::json_to_value(json))
+            }
+            _ => Err(mismatch()),
+        }
+    }
+
+    /// フォームのボディを構造体の型の値にする (型の検査は JSON と同じ)
+    fn decode_form_body(&self, text: &str, ty: &Type, path: &str) -> Result<Value, String> {
+        let Type::Custom(name) = ty else {
+            return Err(format!("'{}' must be a struct to read a form", path));
+        };
+        let def = self
+            .structs
+            .get(name)
+            .ok_or_else(|| format!("'{}' is not a struct", name))?;
+        self.decode_json(crate::forms::form_to_json(def, text), ty, path)
+    }
+
+    /// decode_form(User, body): フォームのボディを Ok(User) か Err(理由) にする
+    fn decode_form(&self, args: Vec<Value>) -> Result<Value, String> {
+        let expected = || "decode_form() expects a struct and a form body".to_string();
+        let [Value::BuiltinFn(handle), Value::Str(body)] = args.as_slice() else {
+            return Err(expected());
+        };
+        let name = handle.strip_prefix("__struct_").ok_or_else(expected)?;
+        let ty = Type::Custom(name.to_string());
+        let result = match self.decode_form_body(body, &ty, &name.to_lowercase()) {
+            Ok(value) => Value::Ok(Rc::new(value)),
+            Err(e) => Value::Err(Rc::new(Value::Str(e.into()))),
+        };
+        Ok(result)
+    }
+
+    pub(crate) fn struct_def(&self, name: &str) -> Option<Rc<StructDef>> {
+        self.structs.get(name).cloned()
+    }
+
+    /// クラスと親クラスのフィールド定義
+    pub(crate) fn class_fields(&self, name: &str) -> Vec<FieldDef> {
+        let mut fields = Vec::new();
+        let mut class = self.classes.get(name).cloned();
+        while let Some(info) = class {
+            for item in &info.def.body {
+                if let ClassBodyItem::Field(field) = item {
+                    fields.push(field.clone());
+                }
+            }
+            class = info.parent.clone();
+        }
+        fields
+    }
+
+    pub(crate) fn request_path(&self) -> &str {
+        &self.request_path
+    }
+
+    /// リクエストの処理中で CSRF 対策が有効なら、そのセッションのトークン
+    pub(crate) fn request_csrf_token(&mut self) -> Option<String> {
+        (self.options_for(&self.serving).csrf && !self.session.is_empty())
+            .then(|| crate::csrf::token(self))
+    }
+
+    pub(crate) fn note_router(&mut self) {
+        self.rendered_router = true;
+    }
+
+    pub(crate) fn render_mode(&self) -> RenderMode {
+        self.render_mode
+    }
+
+    pub(crate) fn has_component(&self, name: &str) -> bool {
+        self.components.contains_key(name)
+    }
+
+    pub(crate) fn render_component_named(&mut self, name: &str) -> Result<String, String> {
+        let component = self
+            .components
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("Unknown component: {}", name))?;
+        self.render_component(&component)
+    }
+
+    /// コンポーネントの state とメソッドを新しいスコープに定義し、render ブロックを HTML にする
+    pub fn render_component(&mut self, component: &ComponentDef) -> Result<String, String> {
+        let old_env = self.env.clone();
+        self.env = Rc::new(RefCell::new(Env::with_parent(old_env.clone())));
+        let result = self.render_component_body(component);
+        self.env = old_env;
+        result
+    }
+
+    fn render_component_body(&mut self, component: &ComponentDef) -> Result<String, String> {
+        let mut render = None;
+        for item in &component.body {
+            match item {
+                ComponentBodyItem::State(state) => {
+                    let value = match self.persisted_state(&component.name, state) {
+                        Some(value) => value,
+                        None => self.eval_expression(&state.value)?,
+                    };
+                    self.env.borrow_mut().define(&state.name, value);
+                }
+                ComponentBodyItem::Method(method) => {
+                    let func = self.closure(Rc::new(method.clone()))?;
+                    self.env.borrow_mut().define(&method.name, func);
+                }
+                ComponentBodyItem::Render(block) => render = Some(block),
+                ComponentBodyItem::Trivia(_) => {}
+            }
+        }
+        let render =
+            render.ok_or_else(|| format!("Component '{}' has no render block", component.name))?;
+
+        // JSX の式文の結果をつなげる (return した場合はその値で終わる)
+        let mut html = String::new();
+        for stmt in &render.body {
+            match stmt {
+                Statement::Expression(expr @ Expression::JsxElement(_)) => {
+                    html.push_str(&self.eval_expression(expr)?.display());
+                }
+                _ => {
+                    if let ExecutionResult::Return(value) = self.eval_statement(stmt)? {
+                        html.push_str(&value.display());
+                        break;
+                    }
+                }
+            }
+        }
+
+        // 描画中に変わった state を次の描画のために保存する
+        for item in &component.body {
+            if let ComponentBodyItem::State(decl) = item {
+                if let Some(persist) = decl.persist {
+                    let value = self.env.borrow().get(&decl.name).unwrap_or(Value::None);
+                    self.persist_state(&component.name, &decl.name, persist, value);
+                }
+            }
+        }
+        if !component.island || self.render_mode != RenderMode::Page {
+            return Ok(html);
+        }
+
+        // island はブラウザで動かすので、描画後の state を付けて包む
+        let mut state = serde_json::Map::new();
+        for item in &component.body {
+            if let ComponentBodyItem::State(decl) = item {
+                let value = self.env.borrow().get(&decl.name).unwrap_or(Value::None);
+                state.insert(decl.name.clone(), crate::builtins::value_to_json(&value));
+            }
+        }
+        if !self.rendered_islands.contains(&component.name) {
+            self.rendered_islands.push(component.name.clone());
+        }
+        Ok(format!(
+            "<div data-island=\"{}\" data-state=\"{}\">{}</div>",
+            component.name,
+            crate::jsx_render::escape_html(&serde_json::Value::Object(state).to_string()),
+            html
+        ))
+    }
+
+    /// render_page(Component[, title]): HTML ページを返す
+    ///
+    /// ページ内で island component を描画したときだけ、その分のクライアント JS を埋め込む
+    fn render_page(&mut self, args: Vec<Value>) -> Result<Value, String> {
+        let (name, title) = match args.as_slice() {
+            [Value::BuiltinFn(name)] => (name, None),
+            [Value::BuiltinFn(name), Value::Str(title)] => (name, Some(title.to_string())),
+            _ => return Err("render_page() expects a component".to_string()),
+        };
+        let Some(name) = name.strip_prefix("__component_") else {
+            return Err("render_page() expects a component".to_string());
+        };
+
+        let outer_islands = std::mem::take(&mut self.rendered_islands);
+        let outer_router = std::mem::replace(&mut self.rendered_router, false);
+        let previous = std::mem::replace(&mut self.render_mode, RenderMode::Page);
+        let body = self.render_component_named(name);
+        self.render_mode = previous;
+        let islands = std::mem::replace(&mut self.rendered_islands, outer_islands);
+        let router = std::mem::replace(&mut self.rendered_router, outer_router);
+        let mut body = body?;
+
+        // ルーターで移動した先の island も動くように、ルーターがあればすべての island を含める
+        let mut components: Vec<&ComponentDef> = if router {
+            self.components.values().map(|c| c.as_ref()).collect()
+        } else {
+            islands
+                .iter()
+                .filter_map(|island| self.components.get(island).map(|c| c.as_ref()))
+                .collect()
+        };
+        components.sort_by(|a, b| a.name.cmp(&b.name));
+        // ページに埋め込む JS にはソースマップを付けないので、ファイルのパスは空にする
+        let mut servers: Vec<(&str, &ServerDef)> =
+            self.servers.values().map(|s| ("", s.as_ref())).collect();
+        servers.sort_by(|a, b| a.1.name.cmp(&b.1.name));
+        let routes = crate::client_api::api_routes(&servers);
+        let mut script = crate::islands::client_script(&components, &routes)?;
+        if router {
+            script.push_str(crate::router::CLIENT_SCRIPT);
+        }
+        if !script.is_empty() {
+            // 文字列中の </script> でスクリプトが途切れないようにする
+            body.push_str(&format!(
+                "\n<script>\n{}</script>",
+                script.replace("</script", "<\\/script")
+            ));
+        }
+        let mut html =
+            crate::jsx_render::generate_html_page(title.as_deref().unwrap_or(name), &body);
+        if !self.stylesheets.is_empty() {
+            let links: String = self
+                .stylesheets
+                .iter()
+                .map(|href| {
+                    format!(
+                        "    <link rel=\"stylesheet\" href=\"{}\">\n",
+                        crate::jsx_render::escape_html(href)
+                    )
+                })
+                .collect();
+            html = html.replacen("</head>", &format!("{}</head>", links), 1);
+        }
+        // island の api スタブが送る CSRF トークン
+        if let Some(token) = self.request_csrf_token() {
+            let meta = format!("    <meta name=\"csrf-token\" content=\"{}\">\n", token);
+            html = html.replacen("</head>", &format!("{}</head>", meta), 1);
+        }
+        // build 後は public/ のファイルへの参照を fingerprint 付きのパスにする
+        if let Some(assets) = &self.assets {
+            html = assets.rewrite_html(&html);
+        }
+        Ok(Value::Str(html.into()))
+    }
+
+    /// render_email(Component) / render_email(html): メール用の HTML 文書を返す
+    fn render_email(&mut self, args: Vec<Value>) -> Result<Value, String> {
+        let body = match args.as_slice() {
+            [Value::BuiltinFn(name)] if name.starts_with("__component_") => {
+                let previous = std::mem::replace(&mut self.render_mode, RenderMode::Email);
+                let result = self.render_component_named(&name["__component_".len()..]);
+                self.render_mode = previous;
+                result?
+            }
+            [Value::Str(html)] => html.to_string(),
+            _ => return Err("render_email() expects a component or an HTML string".to_string()),
+        };
+        Ok(Value::Str(
+            crate::jsx_render::generate_email_document(&body).into(),
+        ))
+    }
+
+    /// サーバー名で App.routes() を呼べるようにする
+    fn register_server(&mut self, server_def: &ServerDef) {
+        let server_def = Rc::new(server_def.clone());
+        self.servers
+            .insert(server_def.name.clone(), server_def.clone());
+        self.pending_servers.push(server_def.clone());
+        self.env.borrow_mut().define(
+            &server_def.name,
+            Value::BuiltinFn(format!("__server_{}", server_def.name)),
+        );
+    }
+
+    /// App.start() / App.start(port): サーバーを起動してハンドルを返す (port が 0 なら空いているポート)
+    fn start_named_server(&mut self, name: &str, args: Vec<Value>) -> Result<Value, String> {
+        let port = match args.as_slice() {
+            [] => self.options_for(name).port,
+            [Value::Int(port)] => {
+                u16::try_from(*port).map_err(|_| format!("Invalid port number: {}", port))?
+            }
+            _ => return Err(format!("{}.start() expects an optional port", name)),
+        };
+        let Some(server_def) = self.servers.get(name).cloned() else {
+            return Err(format!("Unknown server: {}", name));
+        };
+        let id = self.start_server(server_def, port, self.global_env())?;
+        Ok(Value::BuiltinFn(format!("__running_{}", id)))
+    }
+
+    /// App.start() が返したハンドルのメソッド (port() / stop() / wait())
+    fn call_server_handle(
+        &mut self,
+        id: usize,
+        method: &str,
+        args: Vec<Value>,
+    ) -> Result<Value, String> {
+        let Some(running) = self.running.get(id) else {
+            return Err(format!("Server handle has no method '{}'", method));
+        };
+        if !args.is_empty() {
+            return Err(format!("{}() expects no arguments", method));
+        }
+        match method {
+            "port" => Ok(Value::Int(i64::from(running.port))),
+            "stop" => {
+                self.stop_server(id);
+                Ok(Value::None)
+            }
+            "wait" => {
+                self.wait_server(id)?;
+                Ok(Value::None)
+            }
+            _ => Err(format!("Server handle has no method '{}'", method)),
+        }
+    }
+
+    /// トップレベルの環境 (ルートのハンドラーはこの環境で実行する)
+    fn global_env(&self) -> Rc<RefCell<Env>> {
+        let mut env = self.env.clone();
+        loop {
+            let parent = env.borrow().parent.clone();
+            match parent {
+                Some(parent) => env = parent,
+                None => return env,
+            }
+        }
+    }
+
+    /// 登録済みのサーバーのルート一覧
+    pub fn routes(&self, server: &str) -> Option<Vec<RouteInfo>> {
+        self.servers.get(server).map(|def| route_infos(def))
+    }
+
+    /// 定義したサーバーの名前 (名前順)
+    pub fn server_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.servers.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// サーバーを起動せずに GET のルートを呼び、(Content-Type, 本文) を返す (200 以外はエラー)
+    pub fn render_route(&mut self, server: &str, path: &str) -> Result<(String, String), String> {
+        let server_def = self
+            .servers
+            .get(server)
+            .cloned()
+            .ok_or_else(|| format!("Unknown server: {}", server))?;
+        let routes = RouteTable::compile(&server_def);
+        let global_env = self.global_env();
+        let outer = (
+            std::mem::replace(&mut self.serving, server_def.name.clone()),
+            std::mem::replace(&mut self.session, new_session_id()),
+            std::mem::replace(&mut self.request_path, "/".to_string()),
+        );
+        let (headers, body) = (HashMap::new(), String::new());
+        let (status, body) = self.dispatch(&routes, &global_env, "GET", path, headers, body);
+        (self.serving, self.session, self.request_path) = outer;
+        if status != "200 OK" {
+            return Err(format!("GET {} returned {}: {}", path, status, body));
+        }
+        let content_type = routes.content_type("GET", path, &body).to_string();
+        Ok((content_type, body))
+    }
+
+    /// fs.read_file などで読んだパスを記録し始める (静的サイトのページごとの依存)
+    pub fn record_reads(&mut self) {
+        self.reads = Some(Vec::new());
+    }
+
+    /// 記録したパスを返して記録をやめる
+    pub fn take_reads(&mut self) -> Vec<String> {
+        self.reads.take().unwrap_or_default()
+    }
+
+    /// import をこのディレクトリから解決する (実行するファイルのディレクトリを渡す)
+    pub fn set_base_dir(&mut self, dir: &Path) {
+        self.base_dir = dir.to_path_buf();
+    }
+
+    /// トップレベルの変数や関数の値
+    pub fn global(&self, name: &str) -> Option<Value> {
+        self.global_env().borrow().get(name)
+    }
+
+    /// トップレベルに変数を定義する (埋め込む側が値を渡す)
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        self.global_env().borrow_mut().define(name, value);
+    }
+
+    /// 実行するファイル (スタックトレースに出す位置と、import を解決するディレクトリ)
+    pub fn set_file(&mut self, path: &Path) {
+        self.file = Rc::from(path.display().to_string());
+        self.env.borrow_mut().file = Some(self.file.clone());
+        if let Some(dir) = path.parent() {
+            self.base_dir = dir.to_path_buf();
+        }
+    }
+
+    /// 最後の実行時エラーのスタックトレース (最も内側の呼び出しから順)
+    pub fn take_stack_trace(&mut self) -> Vec<Frame> {
+        let (_, mut frames) = self.error_trace.take().unwrap_or_default();
+        frames.reverse();
+        frames
+    }
+
+    /// content/ のコレクションを `content.posts` で読めるようにする
+    pub fn set_content(&mut self, content: &crate::content::Collections) {
+        let module = Module {
+            name: crate::content::CONTENT_DIR.to_string(),
+            exports: content.values(),
+            structs: HashMap::new(),
+            classes: HashMap::new(),
+            lazy: None,
+        };
+        self.env
+            .borrow_mut()
+            .define(crate::content::CONTENT_DIR, Value::Module(Rc::new(module)));
+    }
+
+    /// 組み込み関数に許す権限 (--allow-read などから作る、省略時はすべて許す)
+    pub fn set_permissions(&mut self, permissions: crate::permissions::Permissions) {
+        self.permissions = permissions;
+    }
+
+    /// run とルートの処理1回ごとの上限 (超えると "Execution budget exceeded" のエラー)
+    pub fn set_limits(&mut self, limits: crate::limits::Limits) {
+        self.limits = limits;
+    }
+
+    /// os.args() で読めるコマンドライン引数 (`--` より後の引数)
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    /// 代入と名前の隠し方の規則 (n7tya.toml の [scope])
+    pub fn set_scope_rules(&mut self, rules: ScopeRules) {
+        self.scope_rules = rules;
+    }
+
+    /// import でパッケージ名を使えるようにする (deps::package_roots の結果を渡す)
+    pub fn set_packages(&mut self, packages: HashMap<String, PathBuf>) {
+        self.packages = Rc::new(packages);
+    }
+
+    pub fn get_output(&self) -> &[String] {
+        &self.output
+    }
+
+    fn eval_item(&mut self, item: &Item) -> Result<Value, String> {
+        match item {
+            Item::FunctionDef(f) => {
+                let func = self.closure(Rc::new(f.clone()))?;
+                self.env.borrow_mut().define(&f.name, func);
+                Ok(Value::None)
+            }
+            Item::ClassDef(c) => {
+                let parent = match &c.parent {
+                    Some(name) => Some(self.classes.get(name).cloned().ok_or_else(|| {
+                        format!("Unknown parent class '{}' for class '{}'", name, c.name)
+                    })?),
+                    None => None,
+                };
+                let mut methods = HashMap::new();
+                let mut static_methods = HashMap::new();
+                let mut constants = HashMap::new();
+                for item in &c.body {
+                    match item {
+                        ClassBodyItem::Method(m) => {
+                            methods.insert(m.name.clone(), Rc::new(m.clone()));
+                        }
+                        ClassBodyItem::StaticMethod(m) => {
+                            static_methods.insert(m.name.clone(), Rc::new(m.clone()));
+                        }
+                        ClassBodyItem::Const(decl) => {
+                            constants.insert(decl.name.clone(), self.eval_expression(&decl.value)?);
+                        }
+                        ClassBodyItem::Field(_) | ClassBodyItem::Trivia(_) => {}
+                    }
+                }
+                let info = ClassInfo {
+                    def: Rc::new(c.clone()),
+                    parent,
+                    methods,
+                    static_methods,
+                    constants,
+                    env: self.env.clone(),
+                };
+                self.classes.insert(c.name.clone(), Rc::new(info));
+                self.env
+                    .borrow_mut()
+                    .define(&c.name, Value::BuiltinFn(format!("__class_{}", c.name)));
+                Ok(Value::None)
+            }
+            Item::StructDef(s) => {
+                // <form bind={User}> や decode_form(User, ...) で型として渡せるようにする
+                self.structs.insert(s.name.clone(), Rc::new(s.clone()));
+                self.env
+                    .borrow_mut()
+                    .define(&s.name, Value::BuiltinFn(format!("__struct_{}", s.name)));
+                Ok(Value::None)
+            }
+            // 実装の検査は型チェック時に行う (実行時はメソッドを動的に呼ぶだけ)
+            Item::InterfaceDef(_) => Ok(Value::None),
+            Item::ComponentDef(c) => {
+                // <Name /> や render_email(Name) で描画できるように登録する
+                self.components.insert(c.name.clone(), Rc::new(c.clone()));
+                self.env
+                    .borrow_mut()
+                    .define(&c.name, Value::BuiltinFn(format!("__component_{}", c.name)));
+                Ok(Value::None)
+            }
+            Item::ServerDef(s) => {
+                // サーバーはプログラムを最後まで実行してから serve でまとめて起動する
+                self.register_server(s);
+                Ok(Value::None)
+            }
+            Item::Import(imp) => {
+                self.run_import(imp)?;
+                Ok(Value::None)
+            }
+            Item::Statement(stmt) => self.eval_statement(stmt).map(|res| match res {
+                ExecutionResult::Value(v) => v,
+                ExecutionResult::Return(v) => v, // トップレベルでのreturnは値として扱う
+                _ => Value::None,
+            }),
+        }
+    }
+
+    /// ループの本体を1回実行する (ループを終えるときはそのループの結果を返す)
+    ///
+    /// 外側のループのラベルを指す break / continue は、そのループまで伝える
+    fn eval_loop_body(
+        &mut self,
+        body: &[Statement],
+        label: &Option<String>,
+    ) -> Result<Option<ExecutionResult>, String> {
+        for s in body {
+            match self.eval_statement(s)? {
+                ExecutionResult::Value(_) => {}
+                ExecutionResult::Break(target) if target.is_none() || target == *label => {
+                    return Ok(Some(ExecutionResult::Value(Value::None)));
+                }
+                ExecutionResult::Continue(target) if target.is_none() || target == *label => {
+                    return Ok(None);
+                }
+                result => return Ok(Some(result)),
+            }
+        }
+        Ok(None)
+    }
+
+    fn eval_statement(&mut self, stmt: &Statement) -> Result<ExecutionResult, String> {
+        self.budget.step()?;
+        match stmt {
+            Statement::Let(decl) => {
                 let value = self.eval_expression(&decl.value)?;
-                self.env.borrow_mut().define(&decl.name, value);
+                self.define_local(&decl.name, value)?;
+                Ok(ExecutionResult::Value(Value::None))
+            }
+            Statement::Const(decl) => {
+                let value = self.eval_expression(&decl.value)?;
+                self.define_constant(&decl.name, value)?;
+                Ok(ExecutionResult::Value(Value::None))
+            }
+            Statement::FunctionDef(func) => {
+                // 今の環境を参照で捕まえる (外側の変数への代入は呼び出しをまたいで残る)
+                let closure = self.closure(Rc::new(func.clone()))?;
+                self.define_local(&func.name, closure)?;
+                Ok(ExecutionResult::Value(Value::None))
+            }
+            // 値は def を実行したときに複製している (closure)
+            Statement::Copy(_) => Ok(ExecutionResult::Value(Value::None)),
+            Statement::Nonlocal(names) => {
+                let mut env = self.env.borrow_mut();
+                for name in names {
+                    env.declare_nonlocal(name)?;
+                }
                 Ok(ExecutionResult::Value(Value::None))
             }
             Statement::Assignment(a) => {
                 let value = self.eval_expression(&a.value)?;
-                if let Expression::Identifier(name) = &a.target {
-                    if !self.env.borrow_mut().set(name, value.clone()) {
-                        self.env.borrow_mut().define(name, value);
+                match &a.target {
+                    Expression::Identifier(name) => {
+                        let mut env = self.env.borrow_mut();
+                        if env.is_constant(name) {
+                            return Err(format!("Cannot assign to constant '{}'", name));
+                        }
+                        if !env.set(name, value.clone()) {
+                            if !self.scope_rules.implicit_declarations {
+                                return Err(format!(
+                                    "Cannot assign to undefined variable '{0}' (declare it with let {0} = ...)",
+                                    name
+                                ));
+                            }
+                            env.define(name, value);
+                        }
                     }
+                    Expression::MemberAccess(m) => self.assign_member(m, value)?,
+                    _ => {}
                 }
                 Ok(ExecutionResult::Value(Value::None))
             }
@@ -417,37 +2700,21 @@ impl Interpreter {
             }
             Statement::While(w) => {
                 while self.eval_expression(&w.condition)?.is_truthy() {
-                    for s in &w.body {
-                        let result = self.eval_statement(s)?;
-                        match result {
-                            ExecutionResult::Return(_) => return Ok(result),
-                            ExecutionResult::Break => {
-                                return Ok(ExecutionResult::Value(Value::None))
-                            }
-                            ExecutionResult::Continue => break, // 内側のforループを抜けてwhile再評価へ (Rustの挙動とは違うが、この実装ではstmtループを抜ける必要がある)
-                            _ => {}
-                        }
+                    if let Some(result) = self.eval_loop_body(&w.body, &w.label)? {
+                        return Ok(result);
                     }
                 }
                 Ok(ExecutionResult::Value(Value::None))
             }
             Statement::For(f) => {
                 let iter_val = self.eval_expression(&f.iterator)?;
-                if let Value::List(items) = iter_val {
-                    let items_vec = items.borrow().clone();
-                    for item in items_vec {
-                        self.env.borrow_mut().define(&f.target, item);
-                        for s in &f.body {
-                            let result = self.eval_statement(s)?;
-                            match result {
-                                ExecutionResult::Return(_) => return Ok(result),
-                                ExecutionResult::Break => {
-                                    return Ok(ExecutionResult::Value(Value::None))
-                                }
-                                ExecutionResult::Continue => break,
-                                _ => {}
-                            }
-                        }
+                let Some(iter) = Iter::over(&iter_val) else {
+                    return Err(format!("Cannot iterate over {}", iter_val.display()));
+                };
+                while let Some(item) = crate::iter::next(self, &iter)? {
+                    self.env.borrow_mut().define(&f.target, item);
+                    if let Some(result) = self.eval_loop_body(&f.body, &f.label)? {
+                        return Ok(result);
                     }
                 }
                 Ok(ExecutionResult::Value(Value::None))
@@ -455,10 +2722,11 @@ impl Interpreter {
             Statement::Match(m) => {
                 let value = self.eval_expression(&m.value)?;
                 for case in &m.cases {
-                    if self.pattern_matches(&case.pattern, &value) {
+                    let mut bindings = Vec::new();
+                    if self.pattern_matches(&case.pattern, &value, &mut bindings) {
                         // パターン変数のバインド
-                        if let Pattern::Identifier(name) = &case.pattern {
-                            self.env.borrow_mut().define(name, value.clone());
+                        for (name, v) in bindings {
+                            self.env.borrow_mut().define(&name, v);
                         }
 
                         for s in &case.body {
@@ -472,29 +2740,149 @@ impl Interpreter {
                 }
                 Ok(ExecutionResult::Value(Value::None))
             }
-            Statement::Break => Ok(ExecutionResult::Break),
-            Statement::Continue => Ok(ExecutionResult::Continue),
+            Statement::Break(label) => Ok(ExecutionResult::Break(label.clone())),
+            Statement::Continue(label) => Ok(ExecutionResult::Continue(label.clone())),
             Statement::Expression(e) => {
                 let v = self.eval_expression(e)?;
                 Ok(ExecutionResult::Value(v))
             }
-            Statement::State(s) => {
-                let value = self.eval_expression(&s.value)?;
-                self.env.borrow_mut().define(&s.name, value);
-                Ok(ExecutionResult::Value(Value::None))
+            Statement::State(s) => {
+                let value = self.eval_expression(&s.value)?;
+                self.env.borrow_mut().define(&s.name, value);
+                Ok(ExecutionResult::Value(Value::None))
+            }
+            Statement::Render(_) => Ok(ExecutionResult::Value(Value::None)), // Renderはコンポーネント内でのみ意味を持つが、実行は可能
+            Statement::Trivia(_) => Ok(ExecutionResult::Value(Value::None)),
+        }
+    }
+
+    /// パターンと値を照合し、束縛する変数を bindings に追加する
+    /// (照合に失敗した場合 bindings の内容は使われない)
+    fn pattern_matches(
+        &self,
+        pattern: &Pattern,
+        value: &Value,
+        bindings: &mut Vec<(String, Value)>,
+    ) -> bool {
+        match pattern {
+            Pattern::Wildcard => true,
+            Pattern::Literal(Literal::Int(n)) => matches!(value, Value::Int(v) if v == n),
+            Pattern::Literal(Literal::Str(s)) => matches!(value, Value::Str(v) if **v == **s),
+            Pattern::Literal(Literal::Bool(b)) => matches!(value, Value::Bool(v) if v == b),
+            Pattern::Literal(Literal::None) => matches!(value, Value::None),
+            Pattern::Identifier(name) => {
+                bindings.push((name.clone(), value.clone()));
+                true
+            }
+            Pattern::List(patterns) => {
+                let Value::List(items) = value else {
+                    return false;
+                };
+                let items = items.borrow();
+                match patterns.iter().position(|p| matches!(p, Pattern::Rest(_))) {
+                    Some(rest_idx) => {
+                        let before = &patterns[..rest_idx];
+                        let after = &patterns[rest_idx + 1..];
+                        if items.len() < before.len() + after.len() {
+                            return false;
+                        }
+                        let rest_end = items.len() - after.len();
+                        let head_ok = before
+                            .iter()
+                            .zip(items.iter())
+                            .all(|(p, v)| self.pattern_matches(p, v, bindings));
+                        let tail_ok = after
+                            .iter()
+                            .zip(items[rest_end..].iter())
+                            .all(|(p, v)| self.pattern_matches(p, v, bindings));
+                        if let Pattern::Rest(name) = &patterns[rest_idx] {
+                            if name != "_" {
+                                let rest = items[before.len()..rest_end].to_vec();
+                                bindings
+                                    .push((name.clone(), Value::List(Rc::new(RefCell::new(rest)))));
+                            }
+                        }
+                        head_ok && tail_ok
+                    }
+                    None => {
+                        items.len() == patterns.len()
+                            && patterns
+                                .iter()
+                                .zip(items.iter())
+                                .all(|(p, v)| self.pattern_matches(p, v, bindings))
+                    }
+                }
+            }
+            Pattern::Dict(entries) => {
+                let Value::Dict(dict) = value else {
+                    return false;
+                };
+                let dict = dict.borrow();
+                entries.iter().all(|(key, p)| match dict.get(key) {
+                    Some(v) => self.pattern_matches(p, v, bindings),
+                    None => false,
+                })
+            }
+            Pattern::Variant(name, inner) => match (name.as_str(), value) {
+                ("Ok", Value::Ok(v)) | ("Err", Value::Err(v)) | ("Some", Value::Some(v)) => {
+                    self.pattern_matches(inner, v, bindings)
+                }
+                _ => false,
+            },
+            Pattern::Constructor(name, patterns) => {
+                let Some(fields) = self.instance_fields(name, value) else {
+                    return false;
+                };
+                let names = match value {
+                    Value::Class(..) => match self.classes.get(name) {
+                        Some(info) => info.field_names(),
+                        None => return false,
+                    },
+                    _ => fields.iter().map(|(field, _)| field.clone()).collect(),
+                };
+                names.len() == patterns.len()
+                    && names.iter().zip(patterns).all(|(field, p)| {
+                        fields
+                            .iter()
+                            .find(|(f, _)| f == field)
+                            .is_some_and(|(_, v)| self.pattern_matches(p, v, bindings))
+                    })
             }
-            Statement::Render(_) => Ok(ExecutionResult::Value(Value::None)), // Renderはコンポーネント内でのみ意味を持つが、実行は可能
+            Pattern::Fields(name, patterns) => {
+                let Some(fields) = self.instance_fields(name, value) else {
+                    return false;
+                };
+                patterns.iter().all(|(field, p)| {
+                    fields
+                        .iter()
+                        .find(|(f, _)| f == field)
+                        .is_some_and(|(_, v)| self.pattern_matches(p, v, bindings))
+                })
+            }
+            _ => false,
         }
     }
 
-    fn pattern_matches(&self, pattern: &Pattern, value: &Value) -> bool {
-        match pattern {
-            Pattern::Wildcard => true,
-            Pattern::Literal(Literal::Int(n)) => matches!(value, Value::Int(v) if v == n),
-            Pattern::Literal(Literal::Str(s)) => matches!(value, Value::Str(v) if v == s),
-            Pattern::Literal(Literal::Bool(b)) => matches!(value, Value::Bool(v) if v == b),
-            Pattern::Identifier(_) => true,
-            _ => false,
+    /// 値が name の構造体か、name のクラス (子クラスを含む) のインスタンスなら、そのフィールド
+    fn instance_fields(&self, name: &str, value: &Value) -> Option<Vec<(String, Value)>> {
+        match value {
+            Value::Struct(struct_name, fields) if struct_name == name => {
+                Some(fields.borrow().clone())
+            }
+            Value::Class(class_name, fields) => {
+                let matches = match self.classes.get(class_name) {
+                    Some(info) => info.is_a(name),
+                    None => class_name == name,
+                };
+                matches.then(|| {
+                    fields
+                        .borrow()
+                        .iter()
+                        .map(|(field, value)| (field.clone(), value.clone()))
+                        .collect()
+                })
+            }
+            _ => None,
         }
     }
 
@@ -515,38 +2903,41 @@ impl Interpreter {
                 let operand = self.eval_expression(&unary.operand)?;
                 match unary.op {
                     UnaryOp::Neg => match operand {
-                        Value::Int(n) => Ok(Value::Int(-n)),
+                        Value::Int(n) => n
+                            .checked_neg()
+                            .map(Value::Int)
+                            .ok_or_else(|| "Integer overflow".to_string()),
                         Value::Float(f) => Ok(Value::Float(-f)),
+                        Value::Decimal(d) => Ok(Value::Decimal(-d)),
                         _ => Err(format!("Cannot negate {:?}", operand)),
                     },
                     UnaryOp::Not => Ok(Value::Bool(!operand.is_truthy())),
                 }
             }
             Expression::Call(call) => {
-                // メソッド呼び出しの特別処理
-                if let Expression::MemberAccess(member) = &call.func {
-                    // モジュール関数かどうかをチェック (fs.read_file など)
-                    if let Expression::Identifier(module_name) = &member.object {
-                        let full_name = format!("{}.{}", module_name, member.member);
-                        // ビルトイン関数として存在するかチェック
-                        let is_module_fn = matches!(self.env.borrow().get(&full_name), Some(Value::BuiltinFn(_)));
-                        if is_module_fn {
-                            let mut args = Vec::new();
-                            for arg in &call.args {
-                                args.push(self.eval_expression(arg)?);
-                            }
-                            return self.call_builtin(&full_name, args);
+                // await_all([...]) / race([...]) は http.get などの要素を並行に送る
+                // (名前は環境から引くので、同じ名前で定義した関数はそのまま呼ばれる)
+                if let (Expression::Identifier(name), [Expression::Literal(Literal::List(items))]) =
+                    (&call.func, call.args.as_slice())
+                {
+                    let callee = self.env.borrow().get(name);
+                    if let Some(Value::BuiltinFn(builtin)) = callee {
+                        if builtin == "await_all" || builtin == "race" {
+                            let tasks = self.tasks(items)?;
+                            return self.await_tasks(tasks, builtin == "race");
                         }
                     }
-                    
-                    // 通常のメソッド呼び出し
+                }
+
+                // メソッド呼び出しの特別処理 (モジュールの関数 fs.read_file などもここ)
+                if let Expression::MemberAccess(member) = &call.func {
                     let obj = self.eval_expression(&member.object)?;
                     let method_name = &member.member;
                     let mut args = Vec::new();
                     for arg in &call.args {
                         args.push(self.eval_expression(arg)?);
                     }
-                    return self.call_method(obj, method_name, args);
+                    return self.traced(call, |interp| interp.call_method(obj, method_name, args));
                 }
 
                 let callee = self.eval_expression(&call.func)?;
@@ -554,21 +2945,54 @@ impl Interpreter {
                 for arg in &call.args {
                     args.push(self.eval_expression(arg)?);
                 }
-                self.call_function(callee, args)
+                self.traced(call, |interp| interp.call_function(callee, args))
             }
             Expression::MemberAccess(m) => {
                 let obj = self.eval_expression(&m.object)?;
                 match obj {
-                    Value::Class(_, fields) => fields
+                    Value::Class(ref class_name, ref fields) => {
+                        if let Some(value) = fields.borrow().get(&m.member) {
+                            return Ok(value.clone());
+                        }
+                        // メソッドは self を束縛した関数値として取り出せる
+                        let class = self.classes.get(class_name);
+                        let method = class
+                            .and_then(|class| class.find_method(&m.member))
+                            .map(|(owner, func)| Self::bind_method(owner, func, obj.clone()))
+                            .or_else(|| class?.find_static(&m.member))
+                            .ok_or_else(|| format!("Unknown member: {}", m.member))?;
+                        // self を持つので、インスタンスのフィールドに入れると循環する
+                        if let Value::Fn(_, env) = &method {
+                            self.cycles.track(env);
+                        }
+                        Ok(method)
+                    }
+                    // ClassName.CONST / ClassName.static_method
+                    Value::BuiltinFn(ref name) if name.starts_with("__class_") => {
+                        let class_name = &name["__class_".len()..];
+                        self.classes
+                            .get(class_name)
+                            .and_then(|class| class.find_static(&m.member))
+                            .ok_or_else(|| {
+                                format!("'{}' has no static member '{}'", class_name, m.member)
+                            })
+                    }
+                    Value::Struct(name, fields) => fields
                         .borrow()
-                        .get(&m.member)
-                        .cloned()
-                        .ok_or_else(|| format!("Unknown member: {}", m.member)),
+                        .iter()
+                        .find(|(k, _)| k == &m.member)
+                        .map(|(_, v)| v.clone())
+                        .ok_or_else(|| format!("Struct '{}' has no field '{}'", name, m.member)),
                     Value::Dict(dict) => dict
                         .borrow()
                         .get(&m.member)
                         .cloned()
                         .ok_or_else(|| format!("Key error: {}", m.member)),
+                    Value::Module(module) => {
+                        self.module_member(&module, &m.member)?.ok_or_else(|| {
+                            format!("Module '{}' has no member '{}'", module.name, m.member)
+                        })
+                    }
                     _ => Err(format!("Cannot access member of {:?}", obj)),
                 }
             }
@@ -581,14 +3005,18 @@ impl Interpreter {
                         .get(i as usize)
                         .cloned()
                         .ok_or_else(|| "Index out of bounds".to_string()),
+                    (Value::Range(range), Value::Int(i)) => range
+                        .get(i)
+                        .map(Value::Int)
+                        .ok_or_else(|| "Index out of bounds".to_string()),
                     (Value::Str(s), Value::Int(i)) => s
                         .chars()
                         .nth(i as usize)
-                        .map(|c| Value::Str(c.to_string()))
+                        .map(|c| Value::Str(c.to_string().into()))
                         .ok_or_else(|| "Index out of bounds".to_string()),
                     (Value::Dict(dict), Value::Str(k)) => dict
                         .borrow()
-                        .get(&k)
+                        .get(&*k)
                         .cloned()
                         .ok_or_else(|| format!("Key error: {}", k)),
                     _ => Err("Invalid index operation".to_string()),
@@ -614,22 +3042,162 @@ impl Interpreter {
                     return_type: None,
                     body: body_stmts,
                     is_async: false,
+                    doc: None,
+                    line: 0,
                 };
 
-                Ok(Value::Fn(Rc::new(func_def), self.env.clone()))
+                self.closure(Rc::new(func_def))
             }
             Expression::Await(inner) => self.eval_expression(inner),
+            // Int? のような none になりうる値にも使える (none 以外はそのまま)
+            Expression::Try(inner) => match self.eval_expression(inner)? {
+                Value::Ok(v) | Value::Some(v) => Ok((*v).clone()),
+                value @ (Value::Err(_) | Value::None) => {
+                    // エラーとして巻き戻し、最も近い関数呼び出しで戻り値に変える
+                    let message = format!("Unhandled {}", value.display());
+                    self.propagating = Some(value);
+                    Err(message)
+                }
+                value => Ok(value),
+            },
             Expression::JsxElement(element) => {
-                crate::jsx_render::render_jsx(element, self).map(Value::Str)
+                crate::jsx_render::render_jsx(element, self).map(|html| Value::Str(html.into()))
+            }
+            Expression::StructLiteral(lit) => self.eval_struct_literal(lit),
+        }
+    }
+
+    /// await_all / race のリストの要素を評価する
+    ///
+    /// http.get / http.post などの呼び出しは引数だけ評価して送る前のリクエストにし、
+    /// それ以外の要素はその場で順に評価する。
+    fn tasks(&mut self, items: &[Expression]) -> Result<Vec<Task>, String> {
+        let mut tasks = Vec::new();
+        for item in items {
+            tasks.push(match self.http_request(item)? {
+                Some(request) => Task::Request(request),
+                None => Task::Ready(self.eval_expression(item)),
+            });
+        }
+        Ok(tasks)
+    }
+
+    /// リクエストをスレッドで同時に送り、await_all は結果を同じ順のリストで、race は最初に完了した結果を返す
+    ///
+    /// 評価済みの要素は送るより前に完了しているので、race は評価済みの要素があればその最初を返す。
+    fn await_tasks(&mut self, tasks: Vec<Task>, race: bool) -> Result<Value, String> {
+        let mut results: Vec<Option<Result<Value, String>>> = Vec::new();
+        let mut requests = Vec::new();
+        for (i, task) in tasks.into_iter().enumerate() {
+            match task {
+                Task::Request(request) => {
+                    requests.push((i, request));
+                    results.push(None);
+                }
+                Task::Ready(result) => results.push(Some(result)),
+            }
+        }
+
+        if race {
+            if let Some(ready) = results.iter_mut().find_map(Option::take) {
+                return ready;
+            }
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        for (i, request) in requests {
+            let sender = sender.clone();
+            // race で負けたリクエストは結果を捨てるだけなので、待たずに切り離す
+            thread::spawn(move || {
+                let _ = sender.send((i, request.send()));
+            });
+        }
+        drop(sender);
+
+        while let Some((i, response)) = self.receive(&receiver) {
+            let response = response.map(|response| response.into_value());
+            if race {
+                return response;
+            }
+            results[i] = Some(response);
+        }
+        if race {
+            return Err("race() expects a non-empty list".to_string());
+        }
+
+        let mut values = Vec::new();
+        for result in results {
+            values.push(result.unwrap_or(Ok(Value::None))?);
+        }
+        Ok(Value::List(Rc::new(RefCell::new(values))))
+    }
+
+    /// 並行に送れる http.get / http.post などの呼び出しなら、引数を評価してリクエストにする
+    fn http_request(&mut self, expr: &Expression) -> Result<Option<HttpRequest>, String> {
+        let Expression::Call(call) = expr else {
+            return Ok(None);
+        };
+        // http.get / import http as web の web.get / from http import get の get
+        let callee = match &call.func {
+            Expression::Identifier(name) => self.env.borrow().get(name),
+            Expression::MemberAccess(member) => match &member.object {
+                Expression::Identifier(module) => match self.env.borrow().get(module) {
+                    Some(Value::Module(module)) => module.get(&member.member).cloned(),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        };
+        let Some(Value::BuiltinFn(name)) = callee else {
+            return Ok(None);
+        };
+        if !crate::http::is_request(&name) {
+            return Ok(None);
+        }
+        let mut args = Vec::new();
+        for arg in &call.args {
+            args.push(self.eval_expression(arg)?);
+        }
+        self.permissions.check_builtin(&name, &args)?;
+        self.new_request(&name, &args).map(Some)
+    }
+
+    /// 構造体リテラルを評価 (定義順にフィールドを並べ、過不足をチェック)
+    fn eval_struct_literal(&mut self, lit: &StructLiteral) -> Result<Value, String> {
+        let def = self
+            .structs
+            .get(&lit.name)
+            .cloned()
+            .ok_or_else(|| format!("Unknown struct: {}", lit.name))?;
+
+        for (name, _) in &lit.fields {
+            if !def.fields.iter().any(|f| &f.name == name) {
+                return Err(format!("Struct '{}' has no field '{}'", def.name, name));
             }
         }
+
+        let mut fields = Vec::new();
+        for field in &def.fields {
+            let expr = lit
+                .fields
+                .iter()
+                .find(|(name, _)| name == &field.name)
+                .map(|(_, e)| e)
+                .ok_or_else(|| format!("Missing field '{}' in {}", field.name, def.name))?;
+            fields.push((field.name.clone(), self.eval_expression(expr)?));
+        }
+
+        Ok(Value::Struct(def.name.clone(), Rc::new(RefCell::new(fields))))
     }
 
     fn eval_literal(&mut self, lit: &Literal) -> Result<Value, String> {
         Ok(match lit {
             Literal::Int(n) => Value::Int(*n),
             Literal::Float(f) => Value::Float(*f),
-            Literal::Str(s) => Value::Str(s.clone()),
+            Literal::Duration(d) => Value::Duration(*d),
+            Literal::Size(bytes) => Value::Size(*bytes),
+            Literal::Str(s) => Value::Str(s.as_str().into()),
             Literal::Bool(b) => Value::Bool(*b),
             Literal::None => Value::None,
             Literal::List(items) => {
@@ -645,7 +3213,7 @@ impl Interpreter {
                     let key = self.eval_expression(k)?;
                     let value = self.eval_expression(v)?;
                     if let Value::Str(s) = key {
-                        map.insert(s, value);
+                        map.insert(s.to_string(), value);
                     } else {
                         return Err("Dict keys must be strings".to_string());
                     }
@@ -667,62 +3235,207 @@ impl Interpreter {
     fn eval_binary_op(&self, op: &BinaryOp, left: Value, right: Value) -> Result<Value, String> {
         match (op, &left, &right) {
             // 算術演算
-            (BinaryOp::Add, Value::Int(a), Value::Int(b)) => Ok(Value::Int(a + b)),
+            (BinaryOp::Add, Value::Int(a), Value::Int(b)) => a
+                .checked_add(*b)
+                .map(Value::Int)
+                .ok_or_else(|| "Integer overflow".to_string()),
             (BinaryOp::Add, Value::Float(a), Value::Float(b)) => Ok(Value::Float(a + b)),
-            (BinaryOp::Add, Value::Str(a), Value::Str(b)) => Ok(Value::Str(format!("{}{}", a, b))),
-            (BinaryOp::Sub, Value::Int(a), Value::Int(b)) => Ok(Value::Int(a - b)),
-            (BinaryOp::Mul, Value::Int(a), Value::Int(b)) => Ok(Value::Int(a * b)),
+            (BinaryOp::Add, Value::Str(a), Value::Str(b)) => Ok(Value::Str(format!("{}{}", a, b).into())),
+            (BinaryOp::Add, Value::List(a), Value::List(b)) => {
+                let items = a
+                    .borrow()
+                    .iter()
+                    .chain(b.borrow().iter())
+                    .cloned()
+                    .collect();
+                Ok(Value::List(Rc::new(RefCell::new(items))))
+            }
+            (BinaryOp::Sub, Value::Int(a), Value::Int(b)) => a
+                .checked_sub(*b)
+                .map(Value::Int)
+                .ok_or_else(|| "Integer overflow".to_string()),
+            (BinaryOp::Mul, Value::Int(a), Value::Int(b)) => a
+                .checked_mul(*b)
+                .map(Value::Int)
+                .ok_or_else(|| "Integer overflow".to_string()),
+            // "-" * 40 / [0] * n (0 以下の回数なら空)
+            (BinaryOp::Mul, Value::Str(s), Value::Int(n))
+            | (BinaryOp::Mul, Value::Int(n), Value::Str(s)) => {
+                let count = repeat_count(s.len(), *n)?;
+                self.budget.reserve((s.len() * count) as u64)?;
+                Ok(Value::Str(s.repeat(count).into()))
+            }
+            (BinaryOp::Mul, Value::List(list), Value::Int(n))
+            | (BinaryOp::Mul, Value::Int(n), Value::List(list)) => {
+                let list = list.borrow();
+                let count = repeat_count(list.len(), *n)?;
+                let size = list.len() * count * std::mem::size_of::<Value>();
+                self.budget.reserve(size as u64)?;
+                let items = (0..count).flat_map(|_| list.iter().cloned()).collect();
+                Ok(Value::List(Rc::new(RefCell::new(items))))
+            }
             (BinaryOp::Div, Value::Int(a), Value::Int(b)) => {
                 if *b == 0 {
                     Err("Division by zero".to_string())
                 } else {
-                    Ok(Value::Int(a / b))
+                    a.checked_div(*b)
+                        .map(Value::Int)
+                        .ok_or_else(|| "Integer overflow".to_string())
+                }
+            }
+            (BinaryOp::Mod, Value::Int(a), Value::Int(b)) => {
+                if *b == 0 {
+                    Err("Modulo by zero".to_string())
+                } else {
+                    a.checked_rem(*b)
+                        .map(Value::Int)
+                        .ok_or_else(|| "Integer overflow".to_string())
                 }
             }
-            (BinaryOp::Mod, Value::Int(a), Value::Int(b)) => Ok(Value::Int(a % b)),
 
             // 比較演算
             (BinaryOp::Eq, Value::Int(a), Value::Int(b)) => Ok(Value::Bool(a == b)),
             (BinaryOp::Eq, Value::Str(a), Value::Str(b)) => Ok(Value::Bool(a == b)),
             (BinaryOp::Eq, Value::Bool(a), Value::Bool(b)) => Ok(Value::Bool(a == b)),
             (BinaryOp::Ne, Value::Int(a), Value::Int(b)) => Ok(Value::Bool(a != b)),
+            // イテレータは回してみないと中身が分からない (回すと中身が減る) ので比べない
+            (BinaryOp::Eq | BinaryOp::Ne, Value::Iter(_), _)
+            | (BinaryOp::Eq | BinaryOp::Ne, _, Value::Iter(_)) => {
+                Err("Cannot compare an iterator; convert it with list() first".to_string())
+            }
+            // none との比較 (none 同士だけが等しい)
+            (BinaryOp::Eq | BinaryOp::Ne, Value::None, _)
+            | (BinaryOp::Eq | BinaryOp::Ne, _, Value::None) => {
+                let equal = matches!((&left, &right), (Value::None, Value::None));
+                Ok(Value::Bool(equal == matches!(op, BinaryOp::Eq)))
+            }
             (BinaryOp::Lt, Value::Int(a), Value::Int(b)) => Ok(Value::Bool(a < b)),
             (BinaryOp::Gt, Value::Int(a), Value::Int(b)) => Ok(Value::Bool(a > b)),
             (BinaryOp::Le, Value::Int(a), Value::Int(b)) => Ok(Value::Bool(a <= b)),
             (BinaryOp::Ge, Value::Int(a), Value::Int(b)) => Ok(Value::Bool(a >= b)),
+            // Float を含む数値の演算と比較 (Int は Float にしてから計算する)
+            (
+                BinaryOp::Add
+                | BinaryOp::Sub
+                | BinaryOp::Mul
+                | BinaryOp::Div
+                | BinaryOp::Mod
+                | BinaryOp::Lt
+                | BinaryOp::Gt
+                | BinaryOp::Le
+                | BinaryOp::Ge,
+                Value::Int(_) | Value::Float(_),
+                Value::Int(_) | Value::Float(_),
+            ) => float_op(op, &left, &right),
+            // 5s + 500ms / 10mb * 2 など
+            (
+                BinaryOp::Add
+                | BinaryOp::Sub
+                | BinaryOp::Mul
+                | BinaryOp::Div
+                | BinaryOp::Mod
+                | BinaryOp::Lt
+                | BinaryOp::Gt
+                | BinaryOp::Le
+                | BinaryOp::Ge,
+                Value::Duration(_) | Value::Size(_),
+                _,
+            )
+            | (
+                BinaryOp::Mul,
+                Value::Int(_) | Value::Float(_),
+                Value::Duration(_) | Value::Size(_),
+            ) => crate::units::binary_op(op, &left, &right),
+            // decimal("19.99") * 3 (Int とは計算できるが Float とはできない)
+            (
+                BinaryOp::Add
+                | BinaryOp::Sub
+                | BinaryOp::Mul
+                | BinaryOp::Div
+                | BinaryOp::Mod
+                | BinaryOp::Lt
+                | BinaryOp::Gt
+                | BinaryOp::Le
+                | BinaryOp::Ge,
+                Value::Decimal(_),
+                _,
+            )
+            | (
+                BinaryOp::Add
+                | BinaryOp::Sub
+                | BinaryOp::Mul
+                | BinaryOp::Div
+                | BinaryOp::Mod
+                | BinaryOp::Lt
+                | BinaryOp::Gt
+                | BinaryOp::Le
+                | BinaryOp::Ge,
+                _,
+                Value::Decimal(_),
+            ) => crate::decimal::binary_op(op, &left, &right),
 
             // 論理演算
             (BinaryOp::And, _, _) => Ok(Value::Bool(left.is_truthy() && right.is_truthy())),
             (BinaryOp::Or, _, _) => Ok(Value::Bool(left.is_truthy() || right.is_truthy())),
 
+            // そのほかの値の ==、!= (コレクションは中身で比べる)
+            (BinaryOp::Eq, _, _) => Ok(Value::Bool(values_equal(&left, &right))),
+            (BinaryOp::Ne, _, _) => Ok(Value::Bool(!values_equal(&left, &right))),
+
             // In 演算子
-            (BinaryOp::In, _, Value::List(list)) => Ok(Value::Bool(
-                list.borrow().iter().any(|v| self.values_equal(&left, v)),
+            (BinaryOp::In, _, Value::List(list) | Value::Set(list)) => Ok(Value::Bool(
+                list.borrow().iter().any(|v| values_equal(&left, v)),
             )),
-            (BinaryOp::In, Value::Str(sub), Value::Str(s)) => Ok(Value::Bool(s.contains(sub))),
+            (BinaryOp::In, Value::Str(sub), Value::Str(s)) => Ok(Value::Bool(s.contains(&**sub))),
+            (BinaryOp::In, _, Value::Range(range)) => Ok(Value::Bool(match left {
+                Value::Int(n) => range.contains(n),
+                Value::Float(f) if f.fract() == 0.0 => range.contains(f as i64),
+                _ => false,
+            })),
 
             _ => Err(format!(
-                "Unsupported operation: {:?} {:?} {:?}",
-                left, op, right
+                "Unsupported operation: {} {} {}",
+                left.display(),
+                op,
+                right.display()
             )),
         }
     }
 
-    fn values_equal(&self, a: &Value, b: &Value) -> bool {
-        match (a, b) {
-            (Value::Int(x), Value::Int(y)) => x == y,
-            (Value::Str(x), Value::Str(y)) => x == y,
-            (Value::Bool(x), Value::Bool(y)) => x == y,
-            // List/Dict/Setの比較はリファレンス等価性か中身か？ Pythonは中身。
-            // ここでは簡易的にfalseにしておくか、再帰比較する。
-            // 一旦RefCell比較はアドレス比較(同じオブジェクトか)にするのが簡単だが、
-            // userは [1] == [1] を期待する。
-            // 簡易比較として実装せず、とりあえずfalse
-            _ => false, 
+    /// 呼び出しの間だけ呼び出し位置をスタックに積む
+    ///
+    /// エラーになったら、その時点のスタックを残す (外側の呼び出しに伝わる間は最も内側のものを保つ)。
+    fn traced(
+        &mut self,
+        call: &CallExpr,
+        f: impl FnOnce(&mut Self) -> Result<Value, String>,
+    ) -> Result<Value, String> {
+        self.call_stack.push(Frame {
+            function: callee_name(&call.func),
+            file: self.file.clone(),
+            line: call.line,
+        });
+        let result = f(self);
+        if let Err(e) = &result {
+            let fresh = !matches!(&self.error_trace, Some((message, _)) if message == e);
+            if fresh && self.propagating.is_none() {
+                self.error_trace = Some((e.clone(), self.call_stack.clone()));
+            }
         }
+        self.call_stack.pop();
+        result
+    }
+
+    /// 組み込み関数の中で要素を1つ回す (時間とメモリの上限をときどき確かめる)
+    pub(crate) fn tick(&mut self) -> Result<(), String> {
+        self.budget.tick()
     }
 
-    fn call_function(&mut self, callee: Value, args: Vec<Value>) -> Result<Value, String> {
+    pub(crate) fn call_function(
+        &mut self,
+        callee: Value,
+        args: Vec<Value>,
+    ) -> Result<Value, String> {
         match callee {
             Value::Fn(func, closure_env) => {
                 // 新しいスコープを作成
@@ -741,22 +3454,38 @@ impl Interpreter {
                     local_env.borrow_mut().define(&param.name, arg.clone());
                 }
 
-                // 関数を評価
+                // 関数を評価 (import したモジュールの関数ならそのファイルの中の位置になる)
                 let old_env = self.env.clone();
                 self.env = local_env;
+                let old_file = match Env::file(&self.env) {
+                    Some(file) if !Rc::ptr_eq(&file, &self.file) => {
+                        Some(std::mem::replace(&mut self.file, file))
+                    }
+                    _ => None,
+                };
 
+                // エラーでも呼び出し元の環境に戻す
+                let mut result = Ok(Value::None);
                 for stmt in &func.body {
-                    match self.eval_statement(stmt)? {
-                        ExecutionResult::Return(v) => {
-                            self.env = old_env;
-                            return Ok(v);
+                    match self.eval_statement(stmt) {
+                        Ok(ExecutionResult::Return(v)) => {
+                            result = Ok(v);
+                            break;
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            // ? で返された Err / none はこの関数の戻り値になる
+                            result = self.propagating.take().ok_or(e);
+                            break;
                         }
-                        _ => {}
                     }
                 }
 
                 self.env = old_env;
-                Ok(Value::None)
+                if let Some(file) = old_file {
+                    self.file = file;
+                }
+                result
             }
             Value::BuiltinFn(name) => self.call_builtin(&name, args),
             _ => Err(format!("Cannot call {:?}", callee)),
@@ -764,12 +3493,433 @@ impl Interpreter {
     }
 
     fn call_builtin(&mut self, name: &str, args: Vec<Value>) -> Result<Value, String> {
+        // 標準ライブラリの関数 (fs.read_file など) の呼び出しはトレースのスパンにする
+        let Some(telemetry) = self.telemetry.as_mut().filter(|_| name.contains('.')) else {
+            return self.run_builtin(name, args);
+        };
+        let mut span = telemetry.start_builtin(name, &args);
+        let result = self.run_builtin(name, args);
+        if let Err(e) = &result {
+            span.fail(e);
+        }
+        if let Some(telemetry) = &mut self.telemetry {
+            telemetry.end(span);
+        }
+        result
+    }
+
+    fn run_builtin(&mut self, name: &str, args: Vec<Value>) -> Result<Value, String> {
+        self.permissions.check_builtin(name, &args)?;
+        if let (Some(reads), Some(Value::Str(path))) = (&mut self.reads, args.first()) {
+            if READ_BUILTINS.contains(&name) {
+                reads.push(path.to_string());
+            }
+        }
+        if let ("sqlite.open", [Value::Str(path)]) = (name, args.as_slice()) {
+            // ファイルの権限を絞っているときは、ATTACH DATABASE で許していないファイルを開けないようにする
+            return crate::sqlite::open(path, !self.permissions.restricts_files());
+        }
+        if name == "render_email" {
+            return self.render_email(args);
+        }
+        if name == "render_page" {
+            return self.render_page(args);
+        }
+        if name == "decode_form" {
+            return self.decode_form(args);
+        }
+        if name == "csrf_token" {
+            if !args.is_empty() {
+                return Err("csrf_token() expects no arguments".to_string());
+            }
+            return Ok(Value::Str(crate::csrf::token(self).into()));
+        }
+        if name == "sorted" {
+            return match args.as_slice() {
+                [iterable] => crate::iter::sorted(self, iterable, None),
+                [iterable, key] => crate::iter::sorted(self, iterable, Some(key.clone())),
+                _ => Err("sorted() expects a collection and an optional key function".to_string()),
+            };
+        }
+        // 時間の上限があれば、上限を過ぎるところで止める
+        if let ("time.sleep", [seconds]) = (name, args.as_slice()) {
+            if let Some(seconds) = crate::units::seconds(seconds).filter(|s| *s >= 0.0) {
+                let duration = std::time::Duration::try_from_secs_f64(seconds)
+                    .unwrap_or(std::time::Duration::MAX);
+                self.budget.sleep(duration)?;
+                return Ok(Value::None);
+            }
+        }
+        if name == "reduce" {
+            return crate::iter::reduce(self, args);
+        }
+        if name == "assert_valid" {
+            return crate::validate::assert_valid(self, &args);
+        }
+        if name == "retry" {
+            return crate::resilience::retry(self, args);
+        }
+        // 変数に入れたリストなど、評価済みの値だけを受け取ったとき
+        if name == "await_all" || name == "race" {
+            let [Value::List(items)] = args.as_slice() else {
+                return Err(format!("{}() expects a list", name));
+            };
+            let tasks = items
+                .borrow()
+                .iter()
+                .cloned()
+                .map(Ok)
+                .map(Task::Ready)
+                .collect();
+            return self.await_tasks(tasks, name == "race");
+        }
+        if name == "list" {
+            return match args.as_slice() {
+                [value] => match Iter::over(value) {
+                    Some(iter) => {
+                        let items = crate::iter::collect(self, &iter)?;
+                        Ok(Value::List(Rc::new(RefCell::new(items))))
+                    }
+                    None => Err(format!("Cannot convert {} to a list", value.display())),
+                },
+                _ => Err("list() takes exactly 1 argument".to_string()),
+            };
+        }
+        if name == "set" {
+            return match args.as_slice() {
+                [] => Ok(Value::Set(Rc::new(RefCell::new(Vec::new())))),
+                [value] => match Iter::over(value) {
+                    Some(iter) => {
+                        let items = unique(crate::iter::collect(self, &iter)?);
+                        Ok(Value::Set(Rc::new(RefCell::new(items))))
+                    }
+                    None => Err(format!("Cannot convert {} to a set", value.display())),
+                },
+                _ => Err("set() takes at most 1 argument".to_string()),
+            };
+        }
+        if let ("sum" | "min" | "max", [value @ (Value::Iter(_) | Value::Range(_))]) =
+            (name, args.as_slice())
+        {
+            return crate::iter::fold(self, name, value);
+        }
+        // reversed には、range やイテレータを回し切ったリストを渡す
+        if let ("reversed", [value @ (Value::Iter(_) | Value::Range(_))]) = (name, args.as_slice())
+        {
+            let items = crate::iter::collect(self, &Iter::over(value).expect("iterable"))?;
+            return crate::builtins::call_builtin(
+                name,
+                vec![Value::List(Rc::new(RefCell::new(items)))],
+            );
+        }
+        if let Some(function) = name.strip_prefix("flash.") {
+            return crate::flash::call(self, function, args);
+        }
+        if let Some(function) = name.strip_prefix("health.") {
+            return crate::health::call(self, function, args);
+        }
+        if let Some(function) = name.strip_prefix("config.") {
+            return crate::config::call(self, function, args);
+        }
+        if let Some(function) = name.strip_prefix("cache.") {
+            return crate::cache::call(self, function, args);
+        }
+        if let Some(function) = name.strip_prefix("circuit.") {
+            return crate::resilience::call(self, function, args);
+        }
+        if let Some(function) = name.strip_prefix("os.") {
+            return crate::os::call(self, function, args);
+        }
+        if let Some(function) = name.strip_prefix("events.") {
+            return crate::events::call(self, function, args);
+        }
+        if name == "http.download" {
+            return self.download(args);
+        }
+        if crate::http::is_request(name) {
+            let request = self.new_request(name, &args)?;
+            if !self.running.iter().any(RunningServer::is_running) {
+                return Ok(request.send()?.into_value());
+            }
+            // 起動したサーバーがあれば、応答を待つ間もそのサーバーへの接続を処理する
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || {
+                let _ = sender.send(request.send());
+            });
+            let response = self
+                .receive(&receiver)
+                .unwrap_or_else(|| Err("HTTP request failed".to_string()))?;
+            return Ok(response.into_value());
+        }
+        if let Some(class) = name
+            .strip_prefix("__class_")
+            .and_then(|class_name| self.classes.get(class_name))
+        {
+            return self.instantiate(class.clone(), args);
+        }
         crate::builtins::call_builtin(name, args)
     }
 
+    /// http.get などの引数からリクエストを作る (リダイレクト先もこのプログラムの権限で確かめる)
+    fn new_request(&self, name: &str, args: &[Value]) -> Result<HttpRequest, String> {
+        let mut request = HttpRequest::from_args(name, args)?;
+        request.permissions = self.permissions.clone();
+        Ok(request)
+    }
+
+    /// http.download: 別スレッドでファイルに書き、進み具合はこのスレッドで progress の関数に渡す
+    ///
+    /// progress がエラーになったらダウンロードをやめる (受け取る側がいなくなるとスレッドが止まる)
+    fn download(&mut self, args: Vec<Value>) -> Result<Value, String> {
+        let (mut download, progress) = Download::from_args(&args)?;
+        download.request.permissions = self.permissions.clone();
+        let path = download.path.clone();
+        let receiver = download.spawn();
+        loop {
+            match self.receive(&receiver) {
+                Some(DownloadEvent::Progress(received, total)) => {
+                    if let Some(progress) = &progress {
+                        let total = total.map_or(Value::None, |total| Value::Int(total as i64));
+                        let args = vec![Value::Int(received as i64), total];
+                        self.call_function(progress.clone(), args)?;
+                    }
+                }
+                Some(DownloadEvent::Done(result)) => {
+                    let (response, size) = result?;
+                    return Ok(crate::http::download_value(response, &path, size));
+                }
+                None => return Err("HTTP download failed".to_string()),
+            }
+        }
+    }
+
+    /// クラスのインスタンスを作る (フィールド初期値を評価してから init を呼ぶ)
+    fn instantiate(&mut self, class: Rc<ClassInfo>, args: Vec<Value>) -> Result<Value, String> {
+        let mut fields = HashMap::new();
+        self.init_fields(&class, &mut fields)?;
+
+        let instance = Value::Class(class.def.name.clone(), Rc::new(RefCell::new(fields)));
+        match class.find_method("init") {
+            Some((owner, init)) => {
+                self.call_bound_method(owner, init, instance.clone(), args)?;
+            }
+            None if !args.is_empty() => {
+                return Err(format!(
+                    "{}() takes no arguments (define 'init' to accept some)",
+                    class.def.name
+                ));
+            }
+            None => {}
+        }
+        Ok(instance)
+    }
+
+    /// 親クラスから順にフィールド初期値を評価する (子クラスの同名フィールドが上書きする)
+    fn init_fields(
+        &mut self,
+        class: &ClassInfo,
+        fields: &mut HashMap<String, Value>,
+    ) -> Result<(), String> {
+        if let Some(parent) = &class.parent {
+            self.init_fields(parent, fields)?;
+        }
+
+        // 初期値はクラス定義時の環境で評価する
+        let old_env = std::mem::replace(&mut self.env, class.env.clone());
+        let result = class.def.body.iter().try_for_each(|item| {
+            if let ClassBodyItem::Field(f) = item {
+                let value = match &f.default {
+                    Some(expr) => self.eval_expression(expr)?,
+                    None => Value::None,
+                };
+                fields.insert(f.name.clone(), value);
+            }
+            Ok(())
+        });
+        self.env = old_env;
+        result
+    }
+
+    /// self を束縛してメソッドを呼ぶ
+    /// (親クラスがあれば super も束縛する。super.method() は定義元の親クラスから探す)
+    fn call_bound_method(
+        &mut self,
+        class: &ClassInfo,
+        method: Rc<FunctionDef>,
+        instance: Value,
+        args: Vec<Value>,
+    ) -> Result<Value, String> {
+        let bound = Self::bind_method(class, method, instance);
+        self.call_function(bound, args)
+    }
+
+    /// 今の環境を捕まえたクロージャ (環境は循環の回収のために覚えておく)
+    /// 関数値を作る (今の環境を参照で捕まえる)
+    ///
+    /// 本体の先頭の copy に書いた変数だけは、今の値を複製したフレームを間に挟んで捕まえる。
+    /// 関数の中ではその複製を読み書きするので、外側の変数をあとで変えても影響しない。
+    fn closure(&mut self, func: Rc<FunctionDef>) -> Result<Value, String> {
+        self.cycles.track(&self.env);
+        let copies: Vec<&String> = func
+            .body
+            .iter()
+            .take_while(|s| matches!(s, Statement::Copy(_) | Statement::Trivia(_)))
+            .flat_map(|s| match s {
+                Statement::Copy(names) => names.as_slice(),
+                _ => &[],
+            })
+            .collect();
+        if copies.is_empty() {
+            return Ok(Value::Fn(func, self.env.clone()));
+        }
+        let mut frame = Env::with_parent(self.env.clone());
+        for name in copies {
+            let value = self
+                .env
+                .borrow()
+                .get(name)
+                .ok_or_else(|| format!("Cannot copy '{}': it is not defined", name))?;
+            frame.define(name, value);
+        }
+        let frame = Rc::new(RefCell::new(frame));
+        self.cycles.track(&frame);
+        Ok(Value::Fn(func, frame))
+    }
+
+    /// 循環参照だけで残っている環境 (関数の中で定義したクロージャなど) を解放し、その数を返す
+    ///
+    /// 実行中にも自動で行うので、普通は呼ばなくてよい
+    pub fn collect_cycles(&mut self) -> usize {
+        self.cycles.collect()
+    }
+
+    /// self (と super) を束縛したメソッドを関数値として返す
+    fn bind_method(class: &ClassInfo, method: Rc<FunctionDef>, instance: Value) -> Value {
+        let env = Rc::new(RefCell::new(Env::with_parent(class.env.clone())));
+        env.borrow_mut().define("self", instance);
+        if let Some(parent) = &class.parent {
+            env.borrow_mut()
+                .define("super", Value::BuiltinFn(format!("__super_{}", parent.def.name)));
+        }
+        Value::Fn(method, env)
+    }
+
+    /// super.method(args)
+    fn call_super_method(
+        &mut self,
+        class_name: &str,
+        method: &str,
+        args: Vec<Value>,
+    ) -> Result<Value, String> {
+        let class = self
+            .classes
+            .get(class_name)
+            .cloned()
+            .ok_or_else(|| format!("Unknown class '{}'", class_name))?;
+        let instance = self
+            .env
+            .borrow()
+            .get("self")
+            .ok_or_else(|| "'super' used outside of a method".to_string())?;
+        match class.find_method(method) {
+            Some((owner, func)) => self.call_bound_method(owner, func, instance, args),
+            None => Err(format!("'{}' has no method '{}'", class_name, method)),
+        }
+    }
+
+    /// let / const / 入れ子の def: 今のフレームに新しい変数を作る
+    fn define_local(&mut self, name: &str, value: Value) -> Result<(), String> {
+        let mut env = self.env.borrow_mut();
+        if env.is_nonlocal(name) {
+            return Err(format!(
+                "'{}' is declared nonlocal and cannot be redefined here",
+                name
+            ));
+        }
+        if env.constants.iter().any(|n| n == name) {
+            return Err(format!("Cannot redefine constant '{}'", name));
+        }
+        env.define(name, value);
+        Ok(())
+    }
+
+    /// const で定義する (ループの中の const は回るたびに定義し直せる)
+    pub(crate) fn define_constant(&mut self, name: &str, value: Value) -> Result<(), String> {
+        {
+            let mut env = self.env.borrow_mut();
+            env.constants.retain(|n| n != name);
+        }
+        self.define_local(name, value)?;
+        self.env.borrow_mut().constants.push(name.to_string());
+        Ok(())
+    }
+
+    /// const の初期値を評価する (consteval がパース時に使う)
+    pub(crate) fn eval_constant(&mut self, expr: &Expression) -> Result<Value, String> {
+        self.eval_expression(expr)
+    }
+
+    /// obj.field = value
+    fn assign_member(&mut self, target: &MemberExpr, value: Value) -> Result<(), String> {
+        match self.eval_expression(&target.object)? {
+            Value::Class(_, fields) => {
+                fields.borrow_mut().insert(target.member.clone(), value);
+                Ok(())
+            }
+            Value::Struct(name, fields) => {
+                let mut fields = fields.borrow_mut();
+                match fields.iter_mut().find(|(k, _)| k == &target.member) {
+                    Some((_, slot)) => {
+                        *slot = value;
+                        Ok(())
+                    }
+                    None => Err(format!("Struct '{}' has no field '{}'", name, target.member)),
+                }
+            }
+            Value::Dict(dict) => {
+                dict.borrow_mut().insert(target.member.clone(), value);
+                Ok(())
+            }
+            obj => Err(format!("Cannot assign member of {:?}", obj)),
+        }
+    }
+
     /// メソッド呼び出し (obj.method(args))
     fn call_method(&mut self, obj: Value, method: &str, args: Vec<Value>) -> Result<Value, String> {
         match obj {
+            Value::BuiltinFn(name) if name.starts_with("__super_") => {
+                self.call_super_method(&name["__super_".len()..], method, args)
+            }
+            Value::BuiltinFn(name) if name.starts_with("__server_") => {
+                let server = &name["__server_".len()..];
+                match (method, self.routes(server)) {
+                    ("routes", Some(routes)) => {
+                        let routes = routes.iter().map(RouteInfo::to_value).collect();
+                        Ok(Value::List(Rc::new(RefCell::new(routes))))
+                    }
+                    ("start", Some(_)) => self.start_named_server(server, args),
+                    _ => Err(format!("'{}' has no method '{}'", server, method)),
+                }
+            }
+            Value::BuiltinFn(name) if name.starts_with("__running_") => {
+                let id = name["__running_".len()..].parse().unwrap_or(usize::MAX);
+                self.call_server_handle(id, method, args)
+            }
+            Value::BuiltinFn(name) if name.starts_with("__class_") => {
+                let class_name = &name["__class_".len()..];
+                match self.classes.get(class_name).and_then(|c| c.find_static(method)) {
+                    Some(value) => self.call_function(value, args),
+                    None => Err(format!("'{}' has no static method '{}'", class_name, method)),
+                }
+            }
+            Value::Module(module) => match self.module_member(&module, method)? {
+                Some(value) => self.call_function(value, args),
+                None => Err(format!(
+                    "Module '{}' has no member '{}'",
+                    module.name, method
+                )),
+            },
+
             // List メソッド
             Value::List(list) => match method {
                 "append" => {
@@ -788,7 +3938,11 @@ impl Interpreter {
                         return Err("insert() takes exactly 2 arguments".to_string());
                     }
                     if let Value::Int(idx) = &args[0] {
-                        list.borrow_mut().insert(*idx as usize, args[1].clone());
+                        // 範囲外のインデックスは Python と同様に端へ丸める
+                        let mut list = list.borrow_mut();
+                        let len = list.len() as i64;
+                        let idx = if *idx < 0 { (len + idx).max(0) } else { (*idx).min(len) };
+                        list.insert(idx as usize, args[1].clone());
                         Ok(Value::None)
                     } else {
                         Err("insert() first argument must be int".to_string())
@@ -804,7 +3958,7 @@ impl Interpreter {
                     }
                     let list = list.borrow();
                     for (i, v) in list.iter().enumerate() {
-                        if self.values_equal(&args[0], v) {
+                        if values_equal(&args[0], v) {
                             return Ok(Value::Int(i as i64));
                         }
                     }
@@ -814,28 +3968,109 @@ impl Interpreter {
                     if args.len() != 1 {
                         return Err("count() takes exactly 1 argument".to_string());
                     }
-                    let count = list.borrow().iter().filter(|v| self.values_equal(&args[0], v)).count();
+                    let count = list.borrow().iter().filter(|v| values_equal(&args[0], v)).count();
                     Ok(Value::Int(count as i64))
                 }
                 "copy" => {
                     let copy = list.borrow().clone();
                     Ok(Value::List(Rc::new(RefCell::new(copy))))
                 }
+                "extend" => {
+                    let [other] = args.as_slice() else {
+                        return Err("extend() takes exactly 1 argument".to_string());
+                    };
+                    let Some(iter) = Iter::over(other) else {
+                        return Err(format!("extend() cannot iterate over {}", other.display()));
+                    };
+                    // 自分自身を渡されても回し終えてから足す
+                    let items = crate::iter::collect(self, &iter)?;
+                    list.borrow_mut().extend(items);
+                    Ok(Value::None)
+                }
+                "sort" => {
+                    // sort() / sort(key) / sort(reverse) / sort(key, reverse)、key は none でもよい
+                    let (key, reverse) = match args.as_slice() {
+                        [] => (None, false),
+                        [Value::Bool(reverse)] => (None, *reverse),
+                        [Value::None] => (None, false),
+                        [key] => (Some(key.clone()), false),
+                        [Value::None, Value::Bool(reverse)] => (None, *reverse),
+                        [key, Value::Bool(reverse)] => (Some(key.clone()), *reverse),
+                        _ => return Err(
+                            "sort() takes an optional key function and an optional reverse flag"
+                                .to_string(),
+                        ),
+                    };
+                    let items = list.borrow().clone();
+                    let sorted = crate::iter::sort_items(self, items, key, reverse)?;
+                    *list.borrow_mut() = sorted;
+                    Ok(Value::None)
+                }
+                "remove" => {
+                    let [value] = args.as_slice() else {
+                        return Err("remove() takes exactly 1 argument".to_string());
+                    };
+                    let mut list = list.borrow_mut();
+                    match list.iter().position(|v| values_equal(value, v)) {
+                        Some(i) => {
+                            list.remove(i);
+                            Ok(Value::None)
+                        }
+                        None => Err("value not in list".to_string()),
+                    }
+                }
+                "reverse" => {
+                    list.borrow_mut().reverse();
+                    Ok(Value::None)
+                }
+                "slice" => {
+                    // slice(start) / slice(start, end)、負の位置は末尾から数え、範囲外は端に丸める
+                    let list = list.borrow();
+                    let len = list.len() as i64;
+                    let clamp = |i: i64| if i < 0 { (len + i).max(0) } else { i.min(len) } as usize;
+                    let (start, end) = match args.as_slice() {
+                        [Value::Int(start)] => (clamp(*start), len as usize),
+                        [Value::Int(start), Value::Int(end)] => (clamp(*start), clamp(*end)),
+                        [Value::Int(start), Value::None] => (clamp(*start), len as usize),
+                        _ => {
+                            return Err(
+                                "slice() takes a start index and an optional end index".to_string()
+                            )
+                        }
+                    };
+                    let items = list[start..end.max(start)].to_vec();
+                    Ok(Value::List(Rc::new(RefCell::new(items))))
+                }
                 _ => Err(format!("List has no method '{}'", method)),
             },
 
             // String メソッド
             Value::Str(s) => match method {
-                "upper" => Ok(Value::Str(s.to_uppercase())),
-                "lower" => Ok(Value::Str(s.to_lowercase())),
-                "strip" => Ok(Value::Str(s.trim().to_string())),
-                "split" => {
-                    let sep = if let Some(Value::Str(sep)) = args.first() {
-                        sep.as_str()
+                "upper" => Ok(Value::Str(s.to_uppercase().into())),
+                "lower" => Ok(Value::Str(s.to_lowercase().into())),
+                "split" | "rsplit" => split_string(&s, method, &args),
+                "format" => Ok(Value::Str(
+                    crate::builtins::format_string(&s, &args)?.into(),
+                )),
+                "partition" | "rpartition" => {
+                    let [Value::Str(sep)] = args.as_slice() else {
+                        return Err(format!("{}() takes exactly 1 string argument", method));
+                    };
+                    if sep.is_empty() {
+                        return Err(format!("{}() separator cannot be empty", method));
+                    }
+                    let found = if method == "partition" {
+                        s.split_once(&**sep)
                     } else {
-                        " "
+                        s.rsplit_once(&**sep)
+                    };
+                    // 見つからなければ partition は [s, "", ""]、rpartition は ["", "", s]
+                    let parts = match found {
+                        Some((before, after)) => [before, &**sep, after],
+                        None if method == "partition" => [&*s, "", ""],
+                        None => ["", "", &*s],
                     };
-                    let parts: Vec<Value> = s.split(sep).map(|p| Value::Str(p.to_string())).collect();
+                    let parts = parts.iter().map(|p| Value::Str((*p).into())).collect();
                     Ok(Value::List(Rc::new(RefCell::new(parts))))
                 }
                 "join" => {
@@ -845,12 +4080,12 @@ impl Interpreter {
                     if let Value::List(list) = &args[0] {
                         let strings: Result<Vec<String>, String> = list.borrow().iter().map(|v| {
                             if let Value::Str(s) = v {
-                                Ok(s.clone())
+                                Ok(s.to_string())
                             } else {
                                 Err("join() requires list of strings".to_string())
                             }
                         }).collect();
-                        Ok(Value::Str(strings?.join(&s)))
+                        Ok(Value::Str(strings?.join(&s).into()))
                     } else {
                         Err("join() requires a list argument".to_string())
                     }
@@ -860,7 +4095,7 @@ impl Interpreter {
                         return Err("replace() takes exactly 2 arguments".to_string());
                     }
                     if let (Value::Str(old), Value::Str(new)) = (&args[0], &args[1]) {
-                        Ok(Value::Str(s.replace(old, new)))
+                        Ok(Value::Str(s.replace(&**old, new).into()))
                     } else {
                         Err("replace() requires string arguments".to_string())
                     }
@@ -870,7 +4105,7 @@ impl Interpreter {
                         return Err("startswith() takes exactly 1 argument".to_string());
                     }
                     if let Value::Str(prefix) = &args[0] {
-                        Ok(Value::Bool(s.starts_with(prefix)))
+                        Ok(Value::Bool(s.starts_with(&**prefix)))
                     } else {
                         Err("startswith() requires string argument".to_string())
                     }
@@ -880,7 +4115,7 @@ impl Interpreter {
                         return Err("endswith() takes exactly 1 argument".to_string());
                     }
                     if let Value::Str(suffix) = &args[0] {
-                        Ok(Value::Bool(s.ends_with(suffix)))
+                        Ok(Value::Bool(s.ends_with(&**suffix)))
                     } else {
                         Err("endswith() requires string argument".to_string())
                     }
@@ -890,7 +4125,7 @@ impl Interpreter {
                         return Err("find() takes exactly 1 argument".to_string());
                     }
                     if let Value::Str(sub) = &args[0] {
-                        Ok(Value::Int(s.find(sub).map(|i| i as i64).unwrap_or(-1)))
+                        Ok(Value::Int(s.find(&**sub).map(|i| i as i64).unwrap_or(-1)))
                     } else {
                         Err("find() requires string argument".to_string())
                     }
@@ -900,28 +4135,41 @@ impl Interpreter {
                         return Err("contains() takes exactly 1 argument".to_string());
                     }
                     if let Value::Str(sub) = &args[0] {
-                        Ok(Value::Bool(s.contains(sub)))
+                        Ok(Value::Bool(s.contains(&**sub)))
                     } else {
                         Err("contains() requires string argument".to_string())
                     }
                 }
-                _ => Err(format!("Str has no method '{}'", method)),
+                _ => string_method(&s, method, &args),
             },
 
             // Dict メソッド
             Value::Dict(dict) => match method {
+                // keys / values / items はキー順
                 "keys" => {
-                    let keys: Vec<Value> = dict.borrow().keys().map(|k| Value::Str(k.clone())).collect();
+                    let keys: Vec<Value> = sorted_entries(&dict.borrow())
+                        .into_iter()
+                        .map(|(k, _)| Value::Str(k.clone().into()))
+                        .collect();
                     Ok(Value::List(Rc::new(RefCell::new(keys))))
                 }
                 "values" => {
-                    let values: Vec<Value> = dict.borrow().values().cloned().collect();
+                    let values: Vec<Value> = sorted_entries(&dict.borrow())
+                        .into_iter()
+                        .map(|(_, v)| v.clone())
+                        .collect();
                     Ok(Value::List(Rc::new(RefCell::new(values))))
                 }
                 "items" => {
-                    let items: Vec<Value> = dict.borrow().iter().map(|(k, v)| {
-                        Value::List(Rc::new(RefCell::new(vec![Value::Str(k.clone()), v.clone()])))
-                    }).collect();
+                    let items: Vec<Value> = sorted_entries(&dict.borrow())
+                        .into_iter()
+                        .map(|(k, v)| {
+                            Value::List(Rc::new(RefCell::new(vec![
+                                Value::Str(k.clone().into()),
+                                v.clone(),
+                            ])))
+                        })
+                        .collect();
                     Ok(Value::List(Rc::new(RefCell::new(items))))
                 }
                 "get" => {
@@ -930,7 +4178,7 @@ impl Interpreter {
                     }
                     if let Value::Str(key) = &args[0] {
                         let default = args.get(1).cloned().unwrap_or(Value::None);
-                        Ok(dict.borrow().get(key).cloned().unwrap_or(default))
+                        Ok(dict.borrow().get(&**key).cloned().unwrap_or(default))
                     } else {
                         Err("get() key must be string".to_string())
                     }
@@ -940,7 +4188,7 @@ impl Interpreter {
                         return Err("pop() takes exactly 1 argument".to_string());
                     }
                     if let Value::Str(key) = &args[0] {
-                        dict.borrow_mut().remove(key).ok_or_else(|| format!("Key error: {}", key))
+                        dict.borrow_mut().remove(&**key).ok_or_else(|| format!("Key error: {}", key))
                     } else {
                         Err("pop() key must be string".to_string())
                     }
@@ -954,95 +4202,352 @@ impl Interpreter {
                         return Err("contains() takes exactly 1 argument".to_string());
                     }
                     if let Value::Str(key) = &args[0] {
-                        Ok(Value::Bool(dict.borrow().contains_key(key)))
+                        Ok(Value::Bool(dict.borrow().contains_key(&**key)))
                     } else {
                         Err("contains() key must be string".to_string())
                     }
                 }
-                _ => {
-                    // メソッド名がDictのキーとして存在し、かつそれが呼び出し可能であれば呼び出す
-                    let val = dict.borrow().get(method).cloned();
-                    if let Some(v) = val {
-                        self.call_function(v, args)
+                "update" => {
+                    let [Value::Dict(other)] = args.as_slice() else {
+                        return Err("update() takes exactly 1 dict argument".to_string());
+                    };
+                    let entries = other.borrow().clone();
+                    dict.borrow_mut().extend(entries);
+                    Ok(Value::None)
+                }
+                "merge" => {
+                    // 元の辞書は変えず、引数の値で上書きした新しい辞書を返す
+                    let [Value::Dict(other)] = args.as_slice() else {
+                        return Err("merge() takes exactly 1 dict argument".to_string());
+                    };
+                    let mut merged = dict.borrow().clone();
+                    merged.extend(other.borrow().clone());
+                    Ok(Value::Dict(Rc::new(RefCell::new(merged))))
+                }
+                "setdefault" => {
+                    let (key, default) = match args.as_slice() {
+                        [Value::Str(key)] => (key, Value::None),
+                        [Value::Str(key), default] => (key, default.clone()),
+                        [_] | [_, _] => return Err("setdefault() key must be string".to_string()),
+                        _ => return Err("setdefault() takes 1 or 2 arguments".to_string()),
+                    };
+                    let mut dict = dict.borrow_mut();
+                    Ok(dict.entry(key.to_string()).or_insert(default).clone())
+                }
+                _ => {
+                    // メソッド名がDictのキーとして存在し、かつそれが呼び出し可能であれば呼び出す
+                    let val = dict.borrow().get(method).cloned();
+                    if let Some(v) = val {
+                        // http のレスポンスの json() はその辞書の body を読む
+                        if matches!(&v, Value::BuiltinFn(name) if name == crate::http::JSON_METHOD)
+                        {
+                            return crate::http::response_json(&dict.borrow());
+                        }
+                        self.call_function(v, args)
+                    } else {
+                        Err(format!("Dict has no method '{}'", method))
+                    }
+                }
+            },
+
+            // Set メソッド (union / intersection はリストも受け取り、新しい集合を返す)
+            Value::Set(set) => match method {
+                "add" => {
+                    let [value] = args.as_slice() else {
+                        return Err("add() takes exactly 1 argument".to_string());
+                    };
+                    let mut set = set.borrow_mut();
+                    if !set.iter().any(|v| values_equal(value, v)) {
+                        set.push(value.clone());
+                    }
+                    Ok(Value::None)
+                }
+                "remove" => {
+                    let [value] = args.as_slice() else {
+                        return Err("remove() takes exactly 1 argument".to_string());
+                    };
+                    let mut set = set.borrow_mut();
+                    match set.iter().position(|v| values_equal(value, v)) {
+                        Some(i) => {
+                            set.remove(i);
+                            Ok(Value::None)
+                        }
+                        None => Err("value not in set".to_string()),
+                    }
+                }
+                "union" | "intersection" => {
+                    let [Value::Set(other) | Value::List(other)] = args.as_slice() else {
+                        return Err(format!("{}() takes exactly 1 set or list argument", method));
+                    };
+                    let other = other.borrow().clone();
+                    let set = set.borrow();
+                    let result = if method == "union" {
+                        set.iter().chain(&other).cloned().collect()
                     } else {
-                        Err(format!("Dict has no method '{}'", method))
-                    }
+                        set.iter()
+                            .filter(|v| other.iter().any(|o| values_equal(v, o)))
+                            .cloned()
+                            .collect()
+                    };
+                    Ok(Value::Set(Rc::new(RefCell::new(unique(result)))))
                 }
+                _ => Err(format!("Set has no method '{}'", method)),
             },
 
+            // ユーザー定義クラス (子クラスの定義を優先して継承をたどる)
+            Value::Class(ref class_name, ref fields) => {
+                let class = self.classes.get(class_name).cloned();
+                if let Some((owner, func)) = class.as_ref().and_then(|c| c.find_method(method)) {
+                    return self.call_bound_method(owner, func, obj.clone(), args);
+                }
+                // 関数を入れたフィールドや static メソッドはそのまま呼び出す
+                let field = fields.borrow().get(method).cloned();
+                match field.or_else(|| class?.find_static(method)) {
+                    Some(value @ (Value::Fn(..) | Value::BuiltinFn(_))) => {
+                        self.call_function(value, args)
+                    }
+                    _ => Err(format!("'{}' has no method '{}'", class_name, method)),
+                }
+            }
+
+            Value::Ok(_) | Value::Err(_) | Value::Some(_) | Value::None => {
+                Self::call_variant_method(&obj, method, args)
+            }
+
+            Value::Duration(_) | Value::Size(_) => crate::units::method(&obj, method, &args),
+            Value::Decimal(d) => crate::decimal::method(d, method, &args),
+            // 接続のメソッドは sqlite モジュールの関数として呼ぶ (権限とトレースも同じになる)
+            Value::Sqlite(_) if crate::sqlite::is_method(method) => {
+                let args = std::iter::once(obj.clone()).chain(args).collect();
+                self.call_builtin(&format!("sqlite.{}", method), args)
+            }
+
             _ => Err(format!("'{}' has no methods", obj.display())),
         }
     }
 
+    /// Result (Ok / Err) と Option (Some / none) のメソッド
+    fn call_variant_method(obj: &Value, method: &str, args: Vec<Value>) -> Result<Value, String> {
+        match (method, obj) {
+            ("is_ok", _) => Ok(Value::Bool(matches!(obj, Value::Ok(_)))),
+            ("is_err", _) => Ok(Value::Bool(matches!(obj, Value::Err(_)))),
+            ("is_some", _) => Ok(Value::Bool(matches!(obj, Value::Some(_)))),
+            ("is_none", _) => Ok(Value::Bool(matches!(obj, Value::None))),
+            ("unwrap", Value::Ok(v) | Value::Some(v)) => Ok((**v).clone()),
+            ("unwrap", _) => Err(format!("Called unwrap() on {}", obj.display())),
+            ("unwrap_or", _) => {
+                if args.len() != 1 {
+                    return Err("unwrap_or() takes exactly 1 argument".to_string());
+                }
+                match obj {
+                    Value::Ok(v) | Value::Some(v) => Ok((**v).clone()),
+                    _ => Ok(args[0].clone()),
+                }
+            }
+            ("error", Value::Err(e)) => Ok((**e).clone()),
+            ("error", _) => Err(format!("Called error() on {}", obj.display())),
+            _ => Err(format!("'{}' has no method '{}'", obj.display(), method)),
+        }
+    }
+
     /// モジュールインポートを実行
     fn run_import(&mut self, import: &ImportStmt) -> Result<(), String> {
-        let builtins = ["fs", "json", "http", "sqlite", "base64", "math"];
-        if builtins.contains(&import.module.as_str()) {
-            return Ok(()); // ビルトインモジュールは既にロード済み
+        // 標準ライブラリのモジュールが優先 (ファイルは読まない)
+        let module = match Module::stdlib(&import.module) {
+            Some(module) => Rc::new(module),
+            None if import.lazy => self.lazy_module(&import.module)?,
+            None => self.load_module(&import.module)?,
+        };
+        if module.lazy.is_none() {
+            self.use_definitions(&module);
         }
 
-        let path_str = if import.module.ends_with(".n7t") {
-            import.module.clone()
-        } else {
-            format!("{}.n7t", import.module)
-        };
-        
-        let path = Path::new(&path_str);
-        
-        // ファイル読み込み
-        let source = std::fs::read_to_string(path)
-            .map_err(|e| format!("Failed to import '{}': {}", path_str, e))?;
-            
-        // 字句解析・構文解析
-        let mut lexer = Lexer::new(&source);
-        let tokens = lexer.tokenize();
-        let mut parser = Parser::new(tokens);
-        let program = parser.parse().map_err(|e| format!("{:?}", e))?;
-        
-        // 新しいInterpreterで実行
-        let mut module_interp = Interpreter::new();
-        module_interp.run(&program)?;
-        
-        // モジュールのグローバルスコープを取得
-        // module_interp.env.borrow().values は private かもしれないが
-        // 同じモジュール内なのでアクセスできるはず
-        let module_scope = module_interp.env.borrow().values.clone();
-        
-        // 現在の環境にインポート
-        if let Some(alias) = &import.alias {
-             // import module as alias
-             // モジュール全体をDictとしてインポート
-             let mut dict = HashMap::new();
-             for (k, v) in module_scope {
-                 dict.insert(k, v);
-             }
-             self.env.borrow_mut().define(alias, Value::Dict(Rc::new(RefCell::new(dict))));
-        } else if !import.names.is_empty() {
+        if !import.names.is_empty() {
             // from module import A, B
             for name in &import.names {
-                if let Some(val) = module_scope.get(name) {
-                    self.env.borrow_mut().define(name, val.clone());
-                } else {
-                    return Err(format!("'{}' not found in module '{}'", name, import.module));
+                match module.exports.get(name) {
+                    Some(value) => self.env.borrow_mut().define(name, value.clone()),
+                    None => {
+                        return Err(format!(
+                            "'{}' not found in module '{}'",
+                            name, import.module
+                        ))
+                    }
                 }
             }
         } else {
-            // import module
-            // モジュール名をキーにしてDictを登録
-            let module_name = Path::new(&import.module)
-                .file_stem()
-                .and_then(|s| s.to_str())
-                .unwrap_or("module");
-                
-             let mut dict = HashMap::new();
-             for (k, v) in module_scope {
-                 dict.insert(k, v);
-             }
-             self.env.borrow_mut().define(module_name, Value::Dict(Rc::new(RefCell::new(dict))));
-        }
-        
+            // import module / import module as alias
+            // モジュールを値として登録し、module.name で参照する
+            let name = match &import.alias {
+                Some(alias) => alias.as_str(),
+                None => Path::new(&import.module)
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or("module"),
+            };
+            self.env.borrow_mut().define(name, Value::Module(module));
+        }
+
         Ok(())
     }
+
+    /// モジュールの関数が返す構造体やクラスも使えるようにする
+    fn use_definitions(&mut self, module: &Module) {
+        for (name, def) in &module.structs {
+            self.structs.entry(name.clone()).or_insert_with(|| def.clone());
+        }
+        for (name, class) in &module.classes {
+            self.classes.entry(name.clone()).or_insert_with(|| class.clone());
+        }
+    }
+
+    /// module.name の値 (lazy import したモジュールは、ここで初めて実行する)
+    fn module_member(&mut self, module: &Module, name: &str) -> Result<Option<Value>, String> {
+        let module = module.loaded()?;
+        self.use_definitions(module);
+        Ok(module.exports.get(name).cloned())
+    }
+
+    /// lazy import: ファイルだけ探しておき、実行は最初に名前を使うときまで遅らせる
+    fn lazy_module(&mut self, name: &str) -> Result<Rc<Module>, String> {
+        let path = self.resolve_module(name)?;
+        if let Some(module) = self.modules.borrow().loaded.get(&path) {
+            return Ok(module.clone());
+        }
+        Ok(Rc::new(Module {
+            name: name.to_string(),
+            exports: HashMap::new(),
+            structs: HashMap::new(),
+            classes: HashMap::new(),
+            lazy: Some(LazyModule {
+                path,
+                modules: Rc::downgrade(&self.modules),
+                packages: self.packages.clone(),
+                scope_rules: self.scope_rules,
+                permissions: self.permissions.clone(),
+                loaded: OnceCell::new(),
+            }),
+        }))
+    }
+
+    /// モジュールを読み込んで実行する (読み込み済みならキャッシュを返す)
+    fn load_module(&mut self, name: &str) -> Result<Rc<Module>, String> {
+        let path = self.resolve_module(name)?;
+        Self::load_module_file(
+            name,
+            &path,
+            &self.modules,
+            &self.packages,
+            self.scope_rules,
+            &self.permissions,
+        )
+    }
+
+    /// path のモジュールを1度だけ実行する (実行中のモジュールをまた import したら循環としてエラー)
+    ///
+    /// モジュールのファイルも --allow-read で確かめ、モジュールのコードは import した側と同じ権限で実行する
+    fn load_module_file(
+        name: &str,
+        path: &Path,
+        modules: &Rc<RefCell<ModuleCache>>,
+        packages: &Rc<HashMap<String, PathBuf>>,
+        scope_rules: ScopeRules,
+        permissions: &crate::permissions::Permissions,
+    ) -> Result<Rc<Module>, String> {
+        {
+            let cache = modules.borrow();
+            if let Some(module) = cache.loaded.get(path) {
+                return Ok(module.clone());
+            }
+            if let Some(start) = cache.loading.iter().position(|(p, _)| p == path) {
+                let cycle: Vec<&str> = cache.loading[start..]
+                    .iter()
+                    .map(|(_, name)| name.as_str())
+                    .chain([name])
+                    .collect();
+                return Err(format!("Circular import: {}", cycle.join(" -> ")));
+            }
+        }
+
+        permissions.check_read(&path.to_string_lossy())?;
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to import '{}': {}", path.display(), e))?;
+        let tokens = Lexer::new(&source).tokenize();
+        let program = Parser::new(tokens)
+            .parse()
+            .map_err(|e| format!("Failed to parse module '{}': {}", name, e))?;
+
+        // モジュールは自分のグローバル環境で実行し、実行中の印を付けておく
+        let mut module_interp = Interpreter::new();
+        module_interp.modules = modules.clone();
+        module_interp.packages = packages.clone();
+        module_interp.scope_rules = scope_rules;
+        module_interp.permissions = permissions.clone();
+        // スタックトレースにはカレントディレクトリからのパスで出す
+        let cwd = std::env::current_dir().unwrap_or_default();
+        module_interp.set_file(path.strip_prefix(&cwd).unwrap_or(path));
+        modules
+            .borrow_mut()
+            .loading
+            .push((path.to_path_buf(), name.to_string()));
+        let result = module_interp.run(&program);
+        modules.borrow_mut().loading.pop();
+        result?;
+
+        // モジュールのトップレベルで定義された名前を公開する (組み込み関数は含めない。
+        // `let events = []` のように同じ名前で定義し直したものは公開する)
+        let globals: HashSet<String> = crate::resolve::resolve(&program)
+            .globals()
+            .map(|symbol| symbol.name.clone())
+            .collect();
+        let mut exports = module_interp.env.borrow().values.clone();
+        exports.retain(|name, _| globals.contains(name));
+        let module = Rc::new(Module {
+            name: name.to_string(),
+            exports,
+            structs: module_interp.structs,
+            classes: module_interp.classes,
+            lazy: None,
+        });
+        modules
+            .borrow_mut()
+            .loaded
+            .insert(path.to_path_buf(), module.clone());
+        Ok(module)
+    }
+
+    /// import utils を、インポートしたファイルと同じディレクトリか src/ の utils.n7t に解決する
+    /// (なければ依存パッケージ: import pkg は pkg/src/lib.n7t、import "pkg/sub" は pkg/src/sub.n7t)
+    fn resolve_module(&self, name: &str) -> Result<PathBuf, String> {
+        let file = if name.ends_with(".n7t") {
+            name.to_string()
+        } else {
+            format!("{}.n7t", name)
+        };
+        let package = match name.split_once('/') {
+            Some((package, rest)) => {
+                let rest = rest.trim_end_matches(".n7t");
+                self.packages
+                    .get(package)
+                    .map(|root| root.join("src").join(format!("{}.n7t", rest)))
+            }
+            None => self.packages.get(name).map(|root| root.join("src/lib.n7t")),
+        };
+        [self.base_dir.join(&file), Path::new("src").join(&file)]
+            .into_iter()
+            .chain(package)
+            .find(|path| path.is_file())
+            .map(|path| path.canonicalize().unwrap_or(path))
+            .ok_or_else(|| format!("Module '{}' not found", name))
+    }
+}
+
+/// await_all / race に渡された要素
+enum Task {
+    /// 評価済みの値 (またはエラー)
+    Ready(Result<Value, String>),
+    /// まだ送っていない http.get などのリクエスト
+    Request(HttpRequest),
 }
 
 /// 実行制御結果
@@ -1050,6 +4555,1416 @@ impl Interpreter {
 enum ExecutionResult {
     Value(Value),
     Return(Value),
-    Break,
-    Continue,
+    Break(Option<String>), // ラベルは抜けるループ (None なら最も内側)
+    Continue(Option<String>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str) -> Result<Value, String> {
+        let mut lexer = Lexer::new(source);
+        let tokens = lexer.tokenize();
+        let mut parser = Parser::new(tokens);
+        let program = parser.parse().map_err(|e| e.to_string())?;
+        Interpreter::new().run(&program)
+    }
+
+    #[test]
+    fn test_structural_equality() {
+        let source = "let a = [1, [2, 3], {\"k\": [4]}]
+let b = [1, [2, 3], {\"k\": [4]}]
+let c = [1]
+c.append(c)
+let d = [1]
+d.append(d)
+[a == b, a != b, [[1, 2]] == [[1, 3]], [1, 2] in [[0], [1, 2]], [[1], [2], [1]].count([1]), [[1], [2]].index([2]), 1 == 1.0, c == d]
+";
+        let Ok(Value::List(results)) = run(source) else {
+            panic!("expected a list");
+        };
+        assert_eq!(
+            Value::List(results).display(),
+            "[true, false, false, true, 2, 1, true, true]"
+        );
+
+        // 集合は順序を問わない
+        let set = |items: Vec<i64>| {
+            Value::Set(Rc::new(RefCell::new(
+                items.into_iter().map(Value::Int).collect(),
+            )))
+        };
+        assert!(values_equal(&set(vec![1, 2]), &set(vec![2, 1])));
+        assert!(!values_equal(&set(vec![1, 2]), &set(vec![1, 3])));
+    }
+
+    #[test]
+    fn test_repetition_and_concatenation() {
+        let source =
+            "\"-\" * 3 + str(3 * [0]) + str([1, 2] + [\"a\"]) + str([1] * -1) + 2 * \"ab\"\n";
+        assert_eq!(
+            run(source).unwrap().display(),
+            "---[0, 0, 0][1, 2, a][]abab"
+        );
+        assert!(run("\"ab\" * 9223372036854775807\n")
+            .unwrap_err()
+            .contains("too large"));
+    }
+
+    #[test]
+    fn test_for_iterables() {
+        let source = "let out = []\nlet keys = []\nfor k in {\"a\": 1}\n    keys.append(k)\nout.append(keys)\nfor ch in \"héy\"\n    out.append(ch)\nfor x in range(2)\n    out.append(x)\nout.append(list(zip(\"ab\", [1, 2])))\nout\n";
+        assert_eq!(
+            run(source).unwrap().display(),
+            "[[a], h, é, y, 0, 1, [[a, 1], [b, 2]]]"
+        );
+        assert!(run("for x in 3\n    x\n")
+            .unwrap_err()
+            .contains("Cannot iterate over 3"));
+    }
+
+    #[test]
+    fn test_loop_labels() {
+        let source = "let out = []\nlet i = 0\nwhile i < 5\n    i = i + 1\n    if i == 2\n        continue\n    out.append(i)\nouter: for a in range(3)\n    for b in range(3)\n        if b == 1\n            continue outer\n        if a == 2\n            break outer\n        out.append([a, b])\nout\n";
+        assert_eq!(
+            run(source).unwrap().display(),
+            "[1, 3, 4, 5, [0, 0], [1, 0]]"
+        );
+        // ループの中で定義した関数の break はそのループを指さない
+        assert!(run("for x in [1]\n    def f\n        break\n")
+            .unwrap_err()
+            .contains("'break' outside of a loop"));
+        assert!(run("for x in [1]\n    continue inner\n")
+            .unwrap_err()
+            .contains("Unknown loop label 'inner'"));
+        assert!(run("a: while true\n    a: for x in [1]\n        break a\n")
+            .unwrap_err()
+            .contains("already used"));
+    }
+
+    #[test]
+    fn test_closure_mutation() {
+        // 呼び出しごとに別の count を捕まえ、代入は外側の変数を書き換える
+        let source = "def make_counter\n    let count = 0\n    def increment\n        count = count + 1\n        return count\n    return increment\nlet a = make_counter()\nlet b = make_counter()\na()\na()\n[a(), b(), a()]\n";
+        assert_eq!(run(source).unwrap().display(), "[3, 1, 4]");
+
+        // let は外側の変数を隠す新しい変数を作り、外側は変わらない
+        let source = "let total = 0\ndef shadow\n    let total = 10\n    total = total + 1\n    return total\ndef add x\n    total = total + x\n[shadow(), add(5), add(2), total]\n";
+        assert_eq!(run(source).unwrap().display(), "[11, none, none, 7]");
+
+        // nonlocal ではループ変数も外側の変数になる
+        let source = "def last items\n    let found = none\n    def scan\n        nonlocal found\n        for found in items\n            found\n    scan()\n    return found\nlast([1, 2, 3])\n";
+        assert_eq!(run(source).unwrap().display(), "3");
+
+        assert!(run("def f\n    nonlocal missing\nf()\n")
+            .unwrap_err()
+            .contains("No binding for nonlocal 'missing'"));
+        assert!(
+            run("let x = 1\ndef f\n    nonlocal x\n    let x = 2\nf()\n")
+                .unwrap_err()
+                .contains("declared nonlocal")
+        );
+        assert!(run("def f x\n    nonlocal x\nf(1)\n")
+            .unwrap_err()
+            .contains("already defined"));
+        assert!(run("nonlocal x\n")
+            .unwrap_err()
+            .contains("inside a function"));
+    }
+
+    #[test]
+    fn test_closure_capture_in_loop() {
+        // for はスコープを作らないので、ループの中の def はどれも同じ i を捕まえる
+        let source = "let callbacks = []
+for i in [1, 2, 3]
+	def show
+		return i
+	callbacks.append(show)
+[callbacks[0](), callbacks[1](), callbacks[2]()]
+";
+        assert_eq!(run(source).unwrap().display(), "[3, 3, 3]");
+
+        // copy すると def を実行したときの値を捕まえる
+        let source = source.replace("\t\treturn i", "\t\tcopy i\n\t\treturn i");
+        assert_eq!(run(&source).unwrap().display(), "[1, 2, 3]");
+
+        // 複製への代入はそのクロージャの中だけで、呼び出しをまたいで残る
+        let source = "def make start
+	let count = start
+	def next
+		copy count
+		count = count + 1
+		return count
+	count = 100
+	return next
+let a = make(0)
+let b = make(10)
+[a(), a(), b()]
+";
+        assert_eq!(run(source).unwrap().display(), "[1, 2, 11]");
+
+        // 複製するのは変数で、リストや辞書の中身は共有する
+        let source = "let items = [1]
+def size
+	copy items
+	return len(items)
+items.append(2)
+let before = size()
+items = []
+[before, size()]
+";
+        assert_eq!(run(source).unwrap().display(), "[2, 2]");
+
+        assert_eq!(
+            run("def f
+	copy missing
+	return missing
+").unwrap_err(),
+            "Cannot copy 'missing': it is not defined"
+        );
+    }
+
+    #[test]
+    fn test_assignment_needs_declaration() {
+        assert_eq!(
+            run("let total = 0\ntotl = 1\n").unwrap_err(),
+            "Cannot assign to undefined variable 'totl' (declare it with let totl = ...)"
+        );
+        // 外側の関数やグローバルの変数には let なしで代入できる
+        assert_eq!(
+            run("let n = 0\ndef add\n\tn = n + 1\nadd()\nadd()\nn\n")
+                .unwrap()
+                .display(),
+            "2"
+        );
+
+        // implicit_declarations なら今のスコープに作る
+        let program = Parser::new(Lexer::new("def f\n\tx = 1\n\treturn x\nf()\n").tokenize())
+            .parse()
+            .unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_scope_rules(ScopeRules {
+            implicit_declarations: true,
+            warn_shadowing: true,
+        });
+        assert_eq!(interpreter.run(&program).unwrap().display(), "1");
+        assert!(interpreter.env.borrow().get("x").is_none());
+    }
+
+    #[test]
+    fn test_copy_placement() {
+        let parse = |source: &str| Parser::new(Lexer::new(source).tokenize()).parse();
+        assert!(parse("let x = 1
+def f
+	\"doc\"
+	copy x
+	# c
+	copy y
+	return x
+").is_ok());
+        // copy という名前の変数や関数はそのまま使える
+        assert!(parse("let copy = 1
+print(copy)
+def f
+	return copy(1)
+").is_ok());
+
+        let error = |source: &str| parse(source).unwrap_err().to_string();
+        assert_eq!(
+            error("def f
+	let y = 1
+	copy x
+"),
+            "copy x must come first in the body of a function"
+        );
+        assert_eq!(
+            error("copy x
+"),
+            "copy x must come first in the body of a function"
+        );
+        assert_eq!(
+            error("def f x
+	copy x
+"),
+            "'x' is a parameter of 'f' and cannot be copied"
+        );
+        assert_eq!(
+            error("class A
+	def m
+		copy x
+"),
+            "copy x must come first in the body of a function"
+        );
+    }
+
+    #[test]
+    fn test_server_handle() {
+        // 起動したサーバーに同じプログラムからリクエストし、止める
+        let source = "server Api
+\tget \"/\"
+\t\treturn \"hello\"
+let handle = Api.start(0)
+let url = \"http://127.0.0.1:\" + str(handle.port()) + \"/\"
+let bodies = [http.get(url)[\"body\"], await_all([http.get(url)])[0][\"body\"]]
+handle.stop()
+handle.wait()
+[bodies, handle.port()]
+";
+        let Ok(Value::List(results)) = run(source) else {
+            panic!("expected a list");
+        };
+        let results = results.borrow();
+        assert_eq!(results[0].display(), "[hello, hello]");
+        let Value::Int(port) = results[1] else {
+            panic!("expected a port");
+        };
+        assert!(std::net::TcpStream::connect(("127.0.0.1", port as u16)).is_err());
+    }
+
+    #[test]
+    fn test_server_options() {
+        let options = ServerOptions::from_toml(
+            "[server]\nport = 8080\ncsrf = false\nready_path = \"/ready\"\n",
+        )
+        .unwrap();
+        assert!(!options.csrf);
+        assert_eq!(options.health_path, "/healthz");
+        assert_eq!(options.ready_path, "/ready");
+        assert_eq!(
+            ServerOptions::from_toml("[package]\nname = \"app\"\n").unwrap(),
+            ServerOptions::default()
+        );
+        assert!(ServerOptions::from_toml("[server]\nhealth_path = \"healthz\"\n").is_err());
+        assert!(ServerOptions::from_toml("[server]\nport = 70000\n").is_err());
+
+        let sections = ServerOptions::sections_from_toml(
+            "[server]\ncsrf = false\n[server.Admin]\nport = 9090\n",
+        )
+        .unwrap();
+        assert_eq!(sections.len(), 1);
+        assert_eq!(sections["Admin"].port, 9090);
+        assert!(!sections["Admin"].csrf);
+
+        // サーバーは serve まで起動せず、同じポートを使うサーバーは起動前にエラーにする
+        let source = "server Api\n\tget \"/\"\n\t\treturn \"api\"\nserver Admin\n\tget \"/\"\n\t\treturn \"admin\"\nlen(Api.routes())\n";
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        assert!(matches!(interpreter.run(&program), Ok(Value::Int(1))));
+        assert_eq!(
+            interpreter.serve().unwrap_err(),
+            "Servers 'Api' and 'Admin' both use port 8080; set port in [server.Admin]"
+        );
+    }
+
+    #[test]
+    fn test_stack_trace() {
+        let source = "def inner x\n\treturn x + missing\ndef outer\n\tlet a = 1\n\treturn inner(a)\nprintln \"start\"\nouter()\n";
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_file(Path::new("src/main.n7t"));
+        assert_eq!(
+            interpreter.run(&program).unwrap_err(),
+            "Undefined variable: missing"
+        );
+        let trace: Vec<String> = interpreter
+            .take_stack_trace()
+            .iter()
+            .map(Frame::to_string)
+            .collect();
+        assert_eq!(
+            trace,
+            vec![
+                "in inner, called from src/main.n7t:5",
+                "in outer, called from src/main.n7t:7",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_and_partition() {
+        let source = "let s = \"a,b,c,d\"\n[s.split(\",\"), s.split(\",\", 2), s.rsplit(\",\", 1), s.split(\",\", -1), \"a b\".split()]\n";
+        assert_eq!(
+            run(source).unwrap().display(),
+            "[[a, b, c, d], [a, b, c,d], [a,b,c, d], [a, b, c, d], [a, b]]"
+        );
+        let source = "let path = \"dir/sub/file.txt\"\n[path.partition(\"/\"), path.rpartition(\"/\"), path.partition(\":\"), path.rpartition(\":\")]\n";
+        assert_eq!(
+            run(source).unwrap().display(),
+            "[[dir, /, sub/file.txt], [dir/sub, /, file.txt], [dir/sub/file.txt, , ], [, , dir/sub/file.txt]]"
+        );
+        assert_eq!(
+            run("\"abc\".split(\"\")\n").unwrap_err(),
+            "split() separator cannot be empty"
+        );
+        assert!(run("\"abc\".partition(1)\n").is_err());
+    }
+
+    #[test]
+    fn test_string_methods() {
+        let source = "[\"a\\r\\nb\\n\\nc\\n\".splitlines(), \"ab\".ljust(4, \".\"), \"ab\".rjust(4), \"ab\".center(5, \"*\"), \"-42\".zfill(5), \"abc\".zfill(2)]\n";
+        assert_eq!(
+            run(source).unwrap().display(),
+            "[[a, b, , c], ab..,   ab, *ab**, -0042, abc]"
+        );
+        let source = "[\"xx-a-xx\".strip(\"x\"), \"xx-a-xx\".lstrip(\"x-\"), \"  a  \".rstrip(), \"hello wORLD o'neil\".title(), \"hELLO\".capitalize()]\n";
+        assert_eq!(
+            run(source).unwrap().display(),
+            "[-a-, a-xx,   a, Hello World O'Neil, Hello]"
+        );
+        let source = "[\"0123\".isdigit(), \"-1\".isdigit(), \"\".isdigit(), \"日本abc\".isalpha(), \"a1\".isalpha(), \"banana\".count(\"an\"), \"banana\".rfind(\"an\"), \"banana\".rfind(\"x\")]\n";
+        assert_eq!(
+            run(source).unwrap().display(),
+            "[true, false, false, true, false, 2, 3, -1]"
+        );
+        assert_eq!(
+            run("\"a\".ljust(3, \"ab\")\n").unwrap_err(),
+            "ljust() fill must be exactly one character"
+        );
+        assert_eq!(
+            run("\"a\".count()\n").unwrap_err(),
+            "count() expects a string"
+        );
+        assert_eq!(
+            run("\"a\".shout()\n").unwrap_err(),
+            "Str has no method 'shout'"
+        );
+    }
+
+    #[test]
+    fn test_collection_methods() {
+        let source = "def length s
+    return len(s)
+let items = [3, 1, 2]
+items.extend(set([4, 4]))
+items.extend(items)
+let words = [\"bb\", \"a\", \"cc\", \"d\"]
+words.sort(length, true)
+let nums = [3, 1, 2]
+nums.sort()
+nums.remove(2)
+nums.reverse()
+[items, words, nums, [1, 2, 3, 4].slice(1, -1), [1, 2].slice(-5), [1, 2].slice(2, 1)]
+";
+        assert_eq!(
+            run(source).unwrap().display(),
+            "[[3, 1, 2, 4, 3, 1, 2, 4], [bb, cc, a, d], [3, 1], [2, 3], [1, 2], []]"
+        );
+        let source = "let d = {\"a\": 1}
+d.update({\"b\": 2})
+let first = d.setdefault(\"a\", 9)
+let added = d.setdefault(\"c\", [])
+added.append(3)
+let merged = d.merge({\"a\": 0, \"z\": 26})
+let s = set([1, 2])
+s.add(2)
+s.add(3)
+s.remove(1)
+[d, first, merged, s, s.union([3, 4]), set(\"abc\").intersection(set(\"cbx\")), set()]
+";
+        assert_eq!(
+            run(source).unwrap().display(),
+            "[{a: 1, b: 2, c: [3]}, 1, {a: 0, b: 2, c: [3], z: 26}, {2, 3}, {2, 3, 4}, {b, c}, {}]"
+        );
+        assert_eq!(run("[1].remove(2)\n").unwrap_err(), "value not in list");
+        assert_eq!(run("set([1]).remove(2)\n").unwrap_err(), "value not in set");
+        assert_eq!(
+            run("[1, \"a\"].sort()\n").unwrap_err(),
+            "sort() cannot compare a and 1"
+        );
+        assert_eq!(
+            run("{\"a\": 1}.update([1])\n").unwrap_err(),
+            "update() takes exactly 1 dict argument"
+        );
+        assert_eq!(
+            run("set().union(1)\n").unwrap_err(),
+            "union() takes exactly 1 set or list argument"
+        );
+    }
+
+    #[test]
+    fn test_number_bases() {
+        let source = "[hex(255), hex(-255), bin(5), oct(8), hex(0), int(\"ff\", 16), int(\"0xff\", 16), int(\"-0b101\", 0), int(\"017\", 8), int(\" z \", 36)]\n";
+        assert_eq!(
+            run(source).unwrap().display(),
+            "[0xff, -0xff, 0b101, 0o10, 0x0, 255, 255, -5, 15, 35]"
+        );
+        let source = "[bit_length(0), bit_length(255), bit_length(-256), popcount(255), popcount(-7), int(bin(-9223372036854775807 - 1), 0)]\n";
+        assert_eq!(
+            run(source).unwrap().display(),
+            "[0, 8, 9, 8, 3, -9223372036854775808]"
+        );
+        assert_eq!(
+            run("int(\"12\", 2)\n").unwrap_err(),
+            "Cannot convert '12' to int with base 2"
+        );
+        assert_eq!(
+            run("int(\"1\", 37)\n").unwrap_err(),
+            "int() base must be 0 or between 2 and 36"
+        );
+        assert!(run("hex(1.5)\n").is_err());
+    }
+
+    #[test]
+    fn test_format_strings() {
+        let source = "[format(\"User {} has {} points\", \"ann\", 42), \"{:>6}|{:<5}|{:^7}|{:*^7}\".format(\"r\", \"l\", \"mid\", 3.5)]\n";
+        assert_eq!(
+            run(source).unwrap().display(),
+            "[User ann has 42 points,      r|l    |  mid  |**3.5**]"
+        );
+        let source = "format(\"{:.2} {:,.2f} {:+d} {:08.3f} {:x} {:e} {:.1%} {1}{0} {{x}} {:.3}\", 3.14159, 1234567.891, 5, -3.14159, 255, 1234.5, 0.256, \"abcdef\")\n";
+        assert_eq!(
+            run(source).unwrap().display(),
+            "3.14 1,234,567.89 +5 -003.142 ff 1.234500e+03 25.6% 1234567.8913.14159 {x} abc"
+        );
+        assert_eq!(
+            run("format(\"{} {}\", 1)\n").unwrap_err(),
+            "format(): no argument for field 1 (got 1)"
+        );
+        assert_eq!(
+            run("format(\"{:x}\", \"a\")\n").unwrap_err(),
+            "format(): 'x' format requires an integer, got Str"
+        );
+        let source = "\"{name} has {points:>4} points ({0})\".format({\"name\": \"ann\", \"n\": 1, \"points\": 42})\n";
+        assert_eq!(
+            run(source).unwrap().display(),
+            "ann has   42 points ({n: 1, name: ann, points: 42})"
+        );
+        assert_eq!(
+            run("format(\"{name}\", 1)\n").unwrap_err(),
+            "format(): no argument for field 'name'"
+        );
+        assert!(run("format(\"{:q}\", 1)\n").is_err());
+        assert!(run("\"{\".format(1)\n").is_err());
+    }
+
+    #[test]
+    fn test_struct_literal() {
+        let source = "struct Point\n\tx: Int\n\ty: Int\nlet p = Point { y: 2, x: 1 }\np.x * 10 + p.y\n";
+        assert!(matches!(run(source), Ok(Value::Int(12))));
+    }
+
+    #[test]
+    fn test_match_list_and_dict_patterns() {
+        let source = "let data = {\"type\": \"move\", \"args\": [1, 2, 3]}
+let result = 0
+match data
+	case {\"type\": \"stop\"}
+		result = -1
+	case {\"type\": \"move\", \"args\": [first, *rest]}
+		result = first * 10 + len(rest)
+result
+";
+        assert!(matches!(run(source), Ok(Value::Int(12))));
+    }
+
+    #[test]
+    fn test_match_instance_patterns() {
+        let source = "struct Point
+	x: Int
+	y: Int
+class User
+	name: Str
+	role: Str = \"member\"
+	def init name
+		self.name = name
+class Admin User
+	level: Int = 1
+def describe value
+	match value
+		case Point(x, 0)
+			return \"on x axis at \" + str(x)
+		case Point {y}
+			return \"y = \" + str(y)
+		case Admin(name, _, level)
+			return \"admin \" + name + str(level)
+		case User {name: n, role: \"member\"}
+			return \"user \" + n
+		case _
+			return \"other\"
+[describe(Point { x: 3, y: 0 }), describe(Point { x: 3, y: 4 }), describe(User(\"ann\")), describe(Admin(\"bob\")), describe(1)]
+";
+        assert_eq!(
+            run(source).unwrap().display(),
+            "[on x axis at 3, y = 4, user ann, admin bob1, other]"
+        );
+    }
+
+    #[test]
+    fn test_arithmetic_errors_do_not_panic() {
+        assert_eq!(run("5 % 0\n").unwrap_err(), "Modulo by zero");
+        assert_eq!(run("9223372036854775807 + 1\n").unwrap_err(), "Integer overflow");
+        assert_eq!(run("1.5 / 0\n").unwrap_err(), "Division by zero");
+        let source = "[2.5 - 1, 3 * 0.5, 7 / 2.0, 7.5 % 2, 0.5 < 1, 2 >= 2.0]\n";
+        assert_eq!(run(source).unwrap().display(), "[1.5, 1.5, 3.5, 1.5, true, true]");
+        let source = "let a = [1]\na.insert(100, 2)\na.insert(-100, 0)\na\n";
+        assert_eq!(run(source).unwrap().display(), "[0, 1, 2]");
+        assert_eq!(
+            run("[1] - [2]\n").unwrap_err(),
+            "Unsupported operation: [1] - [2]"
+        );
+    }
+
+    #[test]
+    fn test_class_constructor() {
+        let source = "class Counter
+\tcount: Int = 10
+\tlabel: Str
+\tdef init label
+\t\tself.label = label
+\t\tself.count = self.count + 1
+let c = Counter(\"clicks\")
+c.label + str(c.count)
+";
+        assert!(matches!(run(source), Ok(Value::Str(s)) if &*s == "clicks11"));
+        assert!(run("class Empty\n\tx: Int\nEmpty(1)\n").is_err());
+    }
+
+    #[test]
+    fn test_class_inheritance() {
+        // Leaf は Mid の init を継承し、その中の super は Mid の親 (Base) を指す
+        let source = "class Base
+\tlog: Str = \"b\"
+\tdef init
+\t\tself.log = self.log + \"B\"
+class Mid Base
+\tdef init
+\t\tsuper.init()
+\t\tself.log = self.log + \"M\"
+class Leaf Mid
+\textra: Int = 1
+let leaf = Leaf()
+leaf.log + str(leaf.extra)
+";
+        assert!(matches!(run(source), Ok(Value::Str(s)) if &*s == "bBM1"));
+        assert!(run("class Orphan Missing\n\tx: Int\n").is_err());
+    }
+
+    #[test]
+    fn test_instance_methods() {
+        // describe は Shape で定義され、self.name() は Square の実装に解決される
+        let source = "class Shape
+\tsize: Int = 2
+\tdef name
+\t\treturn \"shape\"
+\tdef describe
+\t\treturn self.name() + \":\" + str(self.area())
+\tdef area
+\t\treturn 0
+class Square Shape
+\tdef name
+\t\treturn \"square\"
+\tdef area
+\t\treturn self.size * self.size
+let sq = Square()
+let area = sq.area
+sq.describe() + \"/\" + str(area())
+";
+        assert!(matches!(run(source), Ok(Value::Str(s)) if &*s == "square:4/4"));
+        assert_eq!(
+            run("class A\n\tx: Int\nA().missing()\n").unwrap_err(),
+            "'A' has no method 'missing'"
+        );
+    }
+
+    #[test]
+    fn test_static_members() {
+        let source = "class Temperature
+\tconst FREEZING = 0
+\tconst SCALE: Str = \"C\"
+\tdegrees: Int = 0
+\tstatic def of degrees
+\t\tlet t = Temperature()
+\t\tt.degrees = degrees
+\t\treturn t
+\tdef label
+\t\treturn str(self.degrees) + Temperature.SCALE
+class Kelvin Temperature
+\tconst SCALE = \"K\"
+let t = Temperature.of(21)
+t.label() + \"/\" + str(t.FREEZING) + \"/\" + Kelvin.SCALE + \"/\" + Kelvin.of(5).label()
+";
+        assert!(matches!(run(source), Ok(Value::Str(s)) if &*s == "21C/0/K/5C"));
+        assert_eq!(
+            run("class A\n\tx: Int\nA.make()\n").unwrap_err(),
+            "'A' has no static method 'make'"
+        );
+    }
+
+    #[test]
+    fn test_closure_shares_frames() {
+        // 定義後の変更は見え、関数内の let は外側を書き換えない
+        let source = "let count = 1
+let label = \"outer\"
+def read
+\tlet label = \"inner\"
+\treturn str(count) + label
+count = 2
+read() + \"/\" + label
+";
+        assert!(matches!(run(source), Ok(Value::Str(s)) if &*s == "2inner/outer"));
+    }
+
+    #[test]
+    fn test_none_comparison() {
+        let source = "let a: Int? = none\nlet b: Int? = 1\n[a == none, b == none, b != none, none == none, 0 == none]\n";
+        let Ok(Value::List(items)) = run(source) else {
+            panic!("expected a list");
+        };
+        let flags: Vec<bool> = items.borrow().iter().map(Value::is_truthy).collect();
+        assert_eq!(flags, vec![true, false, true, true, false]);
+    }
+
+    #[test]
+    fn test_result_and_try_operator() {
+        let source = "def parse_age text
+	let data = json.try_parse(text)?
+	let age = data.get(\"age\")?
+	return Ok(age + 1)
+
+def describe result
+	match result
+		case Ok(age)
+			return \"ok \" + str(age)
+		case Err(_)
+			return \"invalid\"
+		case None
+			return \"missing\"
+
+[describe(parse_age(\"{\\\"age\\\": 3}\")), describe(parse_age(\"oops\")), describe(parse_age(\"{}\")), Some(2).unwrap_or(0), None.unwrap_or(5), Err(\"x\").is_err()]
+";
+        let Ok(Value::List(items)) = run(source) else {
+            panic!("expected a list");
+        };
+        let shown: Vec<String> = items.borrow().iter().map(Value::display).collect();
+        assert_eq!(shown, vec!["ok 4", "invalid", "missing", "2", "5", "true"]);
+
+        let err = run("let r = Err(\"boom\")\nr?\n").unwrap_err();
+        assert_eq!(err, "Unhandled Err(boom)");
+    }
+
+    #[test]
+    fn test_await_all_sends_requests_concurrently() {
+        use std::time::{Duration, Instant};
+
+        // 1リクエストごとに 300ms かかるサーバー
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                std::thread::spawn(move || {
+                    let mut stream = stream;
+                    let mut buffer = [0; 1024];
+                    let n = stream.read(&mut buffer).unwrap_or(0);
+                    let request = String::from_utf8_lossy(&buffer[..n]).to_string();
+                    let path = request.split_whitespace().nth(1).unwrap_or("/").to_string();
+                    std::thread::sleep(Duration::from_millis(300));
+                    let _ = write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        path.len(),
+                        path
+                    );
+                });
+            }
+        });
+
+        let base = format!("http://127.0.0.1:{}", port);
+        let source = format!(
+            "await_all([http.get(\"{0}/a\"), 1 + 1, http.get(\"{0}/b\"), http.get(\"{0}/c\")])\n",
+            base
+        );
+        let start = Instant::now();
+        let Ok(Value::List(items)) = run(&source) else {
+            panic!("expected a list");
+        };
+        assert!(start.elapsed() < Duration::from_millis(800));
+        let shown: Vec<String> = items
+            .borrow()
+            .iter()
+            .map(|item| match item {
+                Value::Dict(response) => response.borrow()["body"].display(),
+                value => value.display(),
+            })
+            .collect();
+        assert_eq!(shown, vec!["/a", "2", "/b", "/c"]);
+
+        let source = format!(
+            "race([http.get(\"{0}/a\"), http.get(\"{0}/b\")])[\"body\"]\n",
+            base
+        );
+        let Ok(Value::Str(body)) = run(&source) else {
+            panic!("expected a string");
+        };
+        assert!(&*body == "/a" || &*body == "/b");
+        assert!(matches!(run("race([http.get(\"x\"), 7])\n"), Ok(Value::Int(7))));
+    }
+
+    #[test]
+    fn test_race_returns_first_completed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                std::thread::spawn(move || {
+                    let mut stream = stream;
+                    let mut buffer = [0; 1024];
+                    let n = stream.read(&mut buffer).unwrap_or(0);
+                    let request = String::from_utf8_lossy(&buffer[..n]).to_string();
+                    let path = request.split_whitespace().nth(1).unwrap_or("/").to_string();
+                    let delay = if path == "/slow" { 500 } else { 0 };
+                    std::thread::sleep(std::time::Duration::from_millis(delay));
+                    let _ = write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        path.len(),
+                        path
+                    );
+                });
+            }
+        });
+
+        // 先頭の要素ではなく、先に返ってきたレスポンス (別名で呼んでも同じ)
+        let source = format!(
+            "let first = race\nfirst([http.get(\"http://127.0.0.1:{0}/slow\"), http.get(\"http://127.0.0.1:{0}/fast\")])[\"body\"]\n",
+            port
+        );
+        assert_eq!(run(&source).unwrap().display(), "/fast");
+    }
+
+    #[test]
+    fn test_await_all_and_race_values() {
+        // 評価済みのリストはそのまま (race は最初に完了した先頭の値)
+        assert_eq!(
+            run("let tasks = [1, 2]\n[await_all(tasks), race(tasks)]\n")
+                .unwrap()
+                .display(),
+            "[[1, 2], 1]"
+        );
+        // 同じ名前で定義した関数は組み込みの代わりに呼ばれる
+        assert_eq!(
+            run("def race items\n    return len(items)\nrace([http.get(\"x\"), 2, 3])\n")
+                .unwrap_err(),
+            run("http.get(\"x\")\n").unwrap_err()
+        );
+        assert_eq!(
+            run("def await_all items\n    return \"mine\"\nawait_all([1])\n")
+                .unwrap()
+                .display(),
+            "mine"
+        );
+        assert_eq!(
+            run("race([])\n").unwrap_err(),
+            "race() expects a non-empty list"
+        );
+        assert_eq!(
+            run("await_all(1)\n").unwrap_err(),
+            "await_all() expects a list"
+        );
+    }
+
+    #[test]
+    fn test_route_dispatch() {
+        let source = "def greet name
+\treturn \"Hello, \" + name
+server App
+\tGET \"/\"
+\t\t# コメント
+\t\treturn greet(request.get(\"method\"))
+\tPOST \"/echo\"
+\t\treturn request.get(\"body\")
+\tGET \"/search\"
+\t\treturn request[\"query\"]
+\tGET \"/fail\"
+\t\treturn 1 / 0
+";
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        let mut routes = None;
+        for item in &program.items {
+            match item {
+                Item::ServerDef(server) => routes = Some(RouteTable::compile(server)),
+                _ => {
+                    interpreter.eval_item(item).unwrap();
+                }
+            }
+        }
+        let routes = routes.unwrap();
+        let env = interpreter.env.clone();
+        let mut request = |method: &str, path: &str, body: &str| {
+            interpreter.dispatch(&routes, &env, method, path, HashMap::new(), body.to_string())
+        };
+
+        assert_eq!(request("get", "/", ""), ("200 OK", "Hello, get".to_string()));
+        assert_eq!(request("POST", "/echo", "hi"), ("200 OK", "hi".to_string()));
+        assert_eq!(request("GET", "/missing", ""), ("404 Not Found", "Not Found".to_string()));
+        assert_eq!(
+            request("GET", "/fail", ""),
+            ("500 Internal Server Error", "Error: Division by zero".to_string())
+        );
+        // 失敗したリクエストの後も次のリクエストは処理できる
+        assert_eq!(request("GET", "/", ""), ("200 OK", "Hello, GET".to_string()));
+        // クエリ文字列は request["query"] に辞書で入る
+        assert_eq!(
+            request("GET", "/search?q=a+b&tag=x&tag=y%26z", ""),
+            ("200 OK", "{q: a b, tag: [x, y&z]}".to_string())
+        );
+    }
+
+    #[test]
+    fn test_typed_route_body_and_json_response() {
+        let source = "struct User
+\tname: Str
+\tage: Int @min(0)
+\temail: Str?
+server App
+\tpost \"/users\" (user: User) -> Json<User>
+\t\treturn User { name: user.name, age: user.age + 1, email: user.email }
+";
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        let mut routes = None;
+        for item in &program.items {
+            match item {
+                Item::ServerDef(server) => routes = Some(RouteTable::compile(server)),
+                _ => {
+                    interpreter.eval_item(item).unwrap();
+                }
+            }
+        }
+        let routes = routes.unwrap();
+        let env = interpreter.env.clone();
+        let mut post = |body: &str| {
+            interpreter.dispatch(&routes, &env, "POST", "/users", HashMap::new(), body.to_string())
+        };
+
+        let (status, body) = post(r#"{"name": "Taro", "age": 20}"#);
+        assert_eq!(status, "200 OK");
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json, serde_json::json!({"name": "Taro", "age": 21, "email": null}));
+        assert_eq!(
+            routes.content_type("post", "/users", &body),
+            "application/json"
+        );
+
+        assert_eq!(
+            post(r#"{"name": "Taro", "age": "20"}"#),
+            ("400 Bad Request", "Invalid request body: 'user.age' must be Int".to_string())
+        );
+        assert_eq!(
+            post(r#"{"name": "Taro"}"#),
+            ("400 Bad Request", "Invalid request body: 'user.age' is missing".to_string())
+        );
+        assert_eq!(
+            post(r#"{"name": "Taro", "age": 1, "admin": true}"#),
+            (
+                "400 Bad Request",
+                "Invalid request body: 'user.admin' is not a field of User".to_string()
+            )
+        );
+        assert_eq!(
+            post(r#"{"name": "Taro", "age": -1}"#),
+            (
+                "400 Bad Request",
+                "Invalid request body: 'user.age' must be at least 0".to_string()
+            )
+        );
+        assert_eq!(post("not json").0, "400 Bad Request");
+    }
+
+    #[test]
+    fn test_form_binding() {
+        let source = "struct Signup
+\tname: Str
+\tage: Int
+\tnewsletter: Bool
+\tnote: Str?
+component SignupForm
+\tstate draft = Signup { name: \"Taro\", age: 20, newsletter: true, note: none }
+\trender
+\t\t<form bind={Signup} values={draft} action=\"/signup\" />
+server App
+\tpost \"/signup\" (signup: Signup) -> Json<Signup>
+\t\treturn signup
+";
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        let mut routes = None;
+        for item in &program.items {
+            match item {
+                Item::ServerDef(server) => routes = Some(RouteTable::compile(server)),
+                _ => {
+                    interpreter.eval_item(item).unwrap();
+                }
+            }
+        }
+
+        let html = interpreter.render_component_named("SignupForm").unwrap();
+        assert_eq!(
+            html,
+            "<form action=\"/signup\" method=\"post\">\
+             <label for=\"signup-name\">Name</label><input id=\"signup-name\" name=\"name\" type=\"text\" value=\"Taro\" required />\
+             <label for=\"signup-age\">Age</label><input id=\"signup-age\" name=\"age\" type=\"number\" step=\"1\" value=\"20\" required />\
+             <label for=\"signup-newsletter\">Newsletter</label><input id=\"signup-newsletter\" name=\"newsletter\" type=\"checkbox\" checked />\
+             <label for=\"signup-note\">Note</label><input id=\"signup-note\" name=\"note\" type=\"text\" />\
+             <button type=\"submit\">Submit</button></form>"
+        );
+
+        // フォームで送られたボディも型付きの引数に変換される
+        let routes = routes.unwrap();
+        let env = interpreter.env.clone();
+        let mut post = |body: &str| {
+            let headers = HashMap::from([(
+                "content-type".to_string(),
+                Value::Str("application/x-www-form-urlencoded".into()),
+            )]);
+            interpreter.dispatch(&routes, &env, "POST", "/signup", headers, body.to_string())
+        };
+        let (status, body) = post("name=Hanako+Y&age=31&note=&submit=Join");
+        assert_eq!(status, "200 OK");
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"name": "Hanako Y", "age": 31, "newsletter": false, "note": null})
+        );
+        assert_eq!(
+            post("name=Hanako&age=x"),
+            (
+                "400 Bad Request",
+                "Invalid request body: 'signup.age' must be Int".to_string()
+            )
+        );
+
+        let err = run("decode_form(1, \"a=b\")\n").unwrap_err();
+        assert_eq!(err, "decode_form() expects a struct and a form body");
+    }
+
+    #[test]
+    fn test_render_email() {
+        let source = "component Footer
+	render
+		<footer><p>Bye</p></footer>
+component Welcome
+	state name = \"Taro\"
+	render
+		<section style={{\"color\": \"red\"}}><h1>{name}</h1><script>x</script><a href=\"/u\" onclick=\"go()\">u</a><button>Go</button><Footer /></section>
+render_email(Welcome)
+";
+        let Ok(Value::Str(html)) = run(source) else {
+            panic!("expected a string");
+        };
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains(
+            "<div style=\"color: red;\"><h1 style=\"margin: 0 0 16px; font-size: 24px; line-height: 32px;\">Taro</h1>\
+             <a href=\"/u\" style=\"color: #1a73e8; text-decoration: underline;\">u</a>\
+             <div><p style=\"margin: 0 0 16px; font-size: 16px; line-height: 24px;\">Bye</p></div></div>"
+        ));
+
+        let err = run("render_email(1)\n").unwrap_err();
+        assert_eq!(err, "render_email() expects a component or an HTML string");
+    }
+
+    #[test]
+    fn test_render_page_islands() {
+        let source = "island component Counter
+\tstate count = 0
+\tdef increment
+\t\tcount = count + 1
+\trender
+\t\t<button onclick={increment}>{count}</button>
+component Page
+\trender
+\t\t<main><h1>Hi</h1><Counter /></main>
+render_page(Page, \"Top\")
+";
+        let Ok(Value::Str(html)) = run(source) else {
+            panic!("expected a string");
+        };
+        assert!(html.contains("<title>Top</title>"));
+        assert!(html.contains(
+            "<div data-island=\"Counter\" data-state=\"{&quot;count&quot;:0}\">\
+             <button data-on-click=\"increment\">0</button></div>"
+        ));
+        assert!(html.contains("<script>"));
+        assert!(html.contains("$s.count = ($s.count + 1);"));
+
+        // island を含まないページにはスクリプトを出さない
+        let source = "component Plain
+\trender
+\t\t<p>Static</p>
+render_page(Plain)
+";
+        let Ok(Value::Str(html)) = run(source) else {
+            panic!("expected a string");
+        };
+        assert!(!html.contains("<script>"));
+
+        let err = run("render_page(1)\n").unwrap_err();
+        assert_eq!(err, "render_page() expects a component");
+    }
+
+    #[test]
+    fn test_router() {
+        let source = "component Home
+\trender
+\t\t<p>Home</p>
+component About
+\trender
+\t\t<p>About</p>
+component Missing
+\trender
+\t\t<p>Missing</p>
+component Shell
+\trender
+\t\t<main><a href=\"/about\">About</a><Router routes={{\"/\": Home, \"/about\": About, \"*\": Missing}} /></main>
+server App
+\tget \"/api\"
+\t\treturn \"api\"
+\tget \"/*\"
+\t\treturn render_page(Shell)
+";
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        let mut routes = None;
+        for item in &program.items {
+            match item {
+                Item::ServerDef(server) => routes = Some(RouteTable::compile(server)),
+                _ => {
+                    interpreter.eval_item(item).unwrap();
+                }
+            }
+        }
+        let routes = routes.unwrap();
+        let env = interpreter.env.clone();
+        let mut get = |path: &str| {
+            interpreter.dispatch(&routes, &env, "GET", path, HashMap::new(), String::new())
+        };
+
+        // "/*" のルートがどのパスでもページを描画し、<Router> がパスに合うコンポーネントを選ぶ
+        let (status, page) = get("/about?tab=1");
+        assert_eq!(status, "200 OK");
+        assert!(page.contains(
+            "<div data-router=\"[&quot;*&quot;,&quot;/&quot;,&quot;/about&quot;]\"><p>About</p></div>"
+        ));
+        assert!(page.contains("n7tya が生成したクライアントルーター"));
+        assert!(get("/").1.contains("<p>Home</p>"));
+        assert!(get("/nowhere").1.contains("<p>Missing</p>"));
+        assert_eq!(get("/api"), ("200 OK", "api".to_string()));
+        assert_eq!(
+            routes.content_type("GET", "/x", &page),
+            "text/html; charset=utf-8"
+        );
+    }
+
+    #[test]
+    fn test_persisted_state() {
+        let source = "component Visits
+\tstate memory total = 0
+\tstate session mine = 0
+\trender
+\t\ttotal = total + 1
+\t\tmine = mine + 1
+\t\t<div><p>{total}</p><p>{mine}</p></div>
+";
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&program).unwrap();
+        let mut render_as = |session: &str| {
+            interpreter.session = session.to_string();
+            interpreter.render_component_named("Visits").unwrap()
+        };
+        assert_eq!(render_as("a"), "<div><p>1</p><p>1</p></div>");
+        assert_eq!(render_as("a"), "<div><p>2</p><p>2</p></div>");
+        // memory は全員で共有し、session は訪問者ごとに分かれる
+        assert_eq!(render_as("b"), "<div><p>3</p><p>1</p></div>");
+
+        let headers = HashMap::from([(
+            "cookie".to_string(),
+            Value::Str("theme=dark; n7tya_session=abc123".into()),
+        )]);
+        assert_eq!(session_cookie(&headers), Some("abc123".to_string()));
+    }
+
+    #[test]
+    fn test_save_island_state() {
+        let source = "component Visits
+\tstate session mine = 0
+\trender
+\t\t<p>{mine}</p>
+island component Prefs
+\tstate session theme = \"light\"
+\tstate session zoom = 1.0
+\tstate memory hits = 0
+\trender
+\t\t<p>{theme} {zoom} {hits}</p>
+";
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&program).unwrap();
+        interpreter.session = "b".to_string();
+        let mut save = |body: &str| interpreter.save_island_state(body);
+
+        // island から送られた session の値は、送った訪問者のセッションに保存する (JSON の整数は Float にも入る)
+        assert_eq!(
+            save(r#"{"island": "Prefs", "state": {"theme": "dark", "zoom": 2}}"#),
+            ("200 OK", "OK".to_string())
+        );
+        // 全員で共有する memory の state は書き換えさせない
+        assert_eq!(
+            save(r#"{"island": "Prefs", "state": {"hits": 1000}}"#),
+            (
+                "403 Forbidden",
+                "State 'hits' is shared by all visitors and cannot be saved".to_string()
+            )
+        );
+        // 初期値と違う型は保存しない (一緒に送った値も保存しない)
+        assert_eq!(
+            save(r#"{"island": "Prefs", "state": {"zoom": 3, "theme": 5}}"#),
+            (
+                "400 Bad Request",
+                "State 'theme' expects Str, got Int".to_string()
+            )
+        );
+        for body in [
+            r#"{"island": "Visits", "state": {"mine": 10}}"#,
+            r#"{"island": "Nope", "state": {}}"#,
+            r#"{"island": "Prefs", "state": {"missing": 1}}"#,
+            r#"{"island": "Prefs"}"#,
+            "not json",
+        ] {
+            assert_eq!(save(body).0, "400 Bad Request", "{}", body);
+        }
+        let html = interpreter.render_component_named("Prefs").unwrap();
+        assert!(html.contains("<p>dark 2 0</p>"), "{}", html);
+        interpreter.session = "c".to_string();
+        let html = interpreter.render_component_named("Prefs").unwrap();
+        assert!(html.contains("<p>light 1 0</p>"), "{}", html);
+    }
+
+    #[test]
+    fn test_sessions() {
+        use std::time::Duration;
+        let mut interpreter = Interpreter::new();
+        let id = new_session_id();
+        assert_eq!(id.len(), 32);
+        assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(new_session_id(), id);
+
+        // サーバーが発行していない ID は使わない (セッション固定を防ぐ)
+        assert_eq!(
+            interpreter.known_session(Some("attacker".to_string())),
+            None
+        );
+        assert_eq!(interpreter.known_session(None), None);
+        interpreter.session = id.clone();
+        interpreter
+            .session_data()
+            .insert("k".to_string(), Value::Int(1));
+        assert_eq!(
+            interpreter.known_session(Some(id.clone())),
+            Some(id.clone())
+        );
+
+        // しばらく使われなかったセッションは消す
+        interpreter.server_options.session_ttl = Duration::from_millis(1);
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(interpreter.known_session(Some(id.clone())), None);
+        assert!(!interpreter.has_session());
+        interpreter.session_data();
+        interpreter.sessions_swept -= Duration::from_secs(60);
+        std::thread::sleep(Duration::from_millis(5));
+        interpreter.known_session(None);
+        assert!(interpreter.sessions.is_empty());
+
+        let options = ServerOptions::from_toml("[server]\nsession_ttl = \"2h\"\n").unwrap();
+        assert_eq!(options.session_ttl, Duration::from_secs(7200));
+        assert!(ServerOptions::from_toml("[server]\nsession_ttl = 0\n").is_err());
+    }
+
+    #[test]
+    fn test_route_reflection() {
+        let source = "struct User
+\tname: Str
+server App
+\tget \"/\"
+\t\tlet paths = []
+\t\tfor r in App.routes()
+\t\t\tpaths.append(r.method + \" \" + r.path)
+\t\treturn paths
+\tpost \"/users\" (user: User) -> Json<User>
+\t\treturn user
+\tGET \"/\"
+\t\treturn \"shadowed\"
+";
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        let mut routes = None;
+        for item in &program.items {
+            match item {
+                Item::ServerDef(server) => {
+                    interpreter.register_server(server);
+                    routes = Some(RouteTable::compile(server));
+                }
+                _ => {
+                    interpreter.eval_item(item).unwrap();
+                }
+            }
+        }
+
+        assert_eq!(
+            interpreter.routes("App").unwrap(),
+            vec![
+                RouteInfo {
+                    method: "GET".to_string(),
+                    path: "/".to_string(),
+                    handler: "GET /".to_string(),
+                    body_type: None,
+                    response_type: None,
+                },
+                RouteInfo {
+                    method: "POST".to_string(),
+                    path: "/users".to_string(),
+                    handler: "POST /users".to_string(),
+                    body_type: Some("User".to_string()),
+                    response_type: Some("Json<User>".to_string()),
+                },
+            ]
+        );
+        assert_eq!(interpreter.routes("Other"), None);
+
+        let env = interpreter.env.clone();
+        let response = interpreter.dispatch(
+            &routes.unwrap(),
+            &env,
+            "GET",
+            "/",
+            HashMap::new(),
+            String::new(),
+        );
+        assert_eq!(response, ("200 OK", "[GET /, POST /users]".to_string()));
+    }
+
+    #[test]
+    fn test_stdlib_modules_are_importable() {
+        let source = "import json as j
+from base64 import encode, decode
+let encoded = encode(j.stringify([1, 2]))
+let stringify = json.stringify
+[decode(encoded), stringify(true), str(j), type(fs)]
+";
+        let Ok(Value::List(items)) = run(source) else {
+            panic!("expected a list");
+        };
+        let shown: Vec<String> = items.borrow().iter().map(Value::display).collect();
+        assert_eq!(shown, vec!["[1,2]", "true", "<module json>", "Module"]);
+
+        assert_eq!(
+            run("from json import dump\n").unwrap_err(),
+            "'dump' not found in module 'json'"
+        );
+        assert_eq!(
+            run("json.dump(1)\n").unwrap_err(),
+            "Module 'json' has no member 'dump'"
+        );
+    }
+
+    #[test]
+    fn test_import_local_modules() {
+        let dir = std::env::temp_dir().join(format!("n7tya-import-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("utils.n7t"),
+            "struct Point\n\tx: Int\n\ty: Int\nlet loads = []\nloads.append(1)\ndef origin\n\treturn Point { x: 0, y: 0 }\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("a.n7t"), "import b\n").unwrap();
+        std::fs::write(dir.join("b.n7t"), "import a\n").unwrap();
+
+        let run_in_dir = |source: &str| {
+            let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+            let mut interpreter = Interpreter::new();
+            interpreter.set_base_dir(&dir);
+            interpreter.run(&program)
+        };
+
+        // 3回インポートしてもモジュールは1度だけ実行される
+        let source = "import utils
+from utils import loads, origin, Point
+import utils as u
+let p = Point { x: 1, y: 2 }
+[len(loads), len(u.loads), utils.origin().x, origin().y, p.y]
+";
+        let Ok(Value::List(items)) = run_in_dir(source) else {
+            panic!("expected a list");
+        };
+        let shown: Vec<String> = items.borrow().iter().map(Value::display).collect();
+        assert_eq!(shown, vec!["1", "1", "0", "0", "2"]);
+
+        assert_eq!(
+            run_in_dir("from utils import missing\n").unwrap_err(),
+            "'missing' not found in module 'utils'"
+        );
+        assert_eq!(
+            run_in_dir("import nowhere\n").unwrap_err(),
+            "Module 'nowhere' not found"
+        );
+        // 実行したディレクトリからのパスとしては探さない
+        assert_eq!(
+            run_in_dir("import \"examples/lib_module\"\n").unwrap_err(),
+            "Module 'examples/lib_module' not found"
+        );
+        assert_eq!(
+            run_in_dir("import a\n").unwrap_err(),
+            "Circular import: a -> b -> a"
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_module_initialization_order() {
+        let dir = std::env::temp_dir().join(format!("n7tya-init-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let files = [
+            ("log.n7t", "let events = []\n"),
+            (
+                "first.n7t",
+                "from log import events\nevents.append(\"first\")\n",
+            ),
+            (
+                "heavy.n7t",
+                "from log import events\nevents.append(\"heavy\")\ndef value\n\treturn 42\n",
+            ),
+            (
+                "ping.n7t",
+                "lazy import pong\ndef ping\n\treturn pong.pong()\ndef name\n\treturn \"ping\"\n",
+            ),
+            ("pong.n7t", "import ping\ndef pong\n\treturn ping.name()\n"),
+        ];
+        for (name, source) in files {
+            std::fs::write(dir.join(name), source).unwrap();
+        }
+        let run_in_dir = |source: &str| {
+            let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+            let mut interpreter = Interpreter::new();
+            interpreter.set_base_dir(&dir);
+            interpreter.run(&program).map(|value| value.display())
+        };
+
+        // import は書いた位置にかかわらず、トップレベルの文より先に実行される
+        let source = "from log import events\nevents.append(\"main\")\nimport first\nevents\n";
+        assert_eq!(run_in_dir(source).unwrap(), "[first, main]");
+        // lazy import したモジュールは最初に使うときに実行される
+        let source = "from log import events\nlazy import heavy\nevents.append(\"main\")\nlet v = heavy.value()\n[events, v, heavy.value()]\n";
+        assert_eq!(run_in_dir(source).unwrap(), "[[main, heavy], 42, 42]");
+        // 循環する import も、片方を lazy にすれば使うときには読み込み済み
+        assert_eq!(run_in_dir("import ping\nping.ping()\n").unwrap(), "ping");
+        assert!(run_in_dir("lazy import nowhere\n").is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_struct_literal_missing_field() {
+        let source = "struct Point\n\tx: Int\n\ty: Int\nPoint { x: 1 }\n";
+        assert_eq!(run(source).unwrap_err(), "Missing field 'y' in Point");
+    }
 }
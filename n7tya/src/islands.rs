@@ -0,0 +1,899 @@
+//! island component のクライアント JS 生成
+//!
+//! island を付けたコンポーネントだけを JS に変換し、サーバーで描画した HTML に
+//! イベントをつなぐ。island でないコンポーネントは HTML のみでブラウザには何も送らない。
+//!
+//! サーバー側は island を `<div data-island="Name" data-state="{...}">` で包んで描画し
+//! (Interpreter::render_component)、ここで生成した JS がその state から動き始める。
+//! JS に変換できるのは state / メソッド / render で使う基本的な式と文だけ。
+//! メソッドからは `api.get_users()` でサーバーのルートを呼べる (client_api)。
+//! `events.on` / `events.emit` はページの中の island どうしでイベントを送る (サーバーの events と同じ名前の規則)。
+//! `mount` メソッドがあれば hydrate の後に1回呼ぶので、そこで events.on で購読できる。
+//! ソースのコメントと空行 (AST の Trivia) は生成した JS にもそのまま残す。
+//! `n7tya build` は生成した JS の行を .n7t の行に対応させるソースマップも書く
+//! (メソッド・render・スタブは定義の行、呼び出しを含む文はその呼び出しの行)。
+
+use crate::ast::*;
+use crate::client_api::{client_stub, ApiRoute, CLIENT_FETCH};
+use crate::jsx_render::escape_html;
+use crate::sourcemap::SourceMap;
+use crate::visit::{walk_expression, Visitor};
+use std::collections::{BTreeSet, HashSet};
+
+/// session に保存する state をクライアントから送るエンドポイント (memory の state はサーバーでの描画だけで保存する)
+pub const STATE_ENDPOINT: &str = "/__n7tya/state";
+
+/// ブラウザで処理するイベント (onclick なら data-on-click として描画される)
+const EVENTS: &[&str] = &["click", "input", "change", "submit", "keydown"];
+
+/// 描画結果とそろえるためのヘルパー (jsx_render の escape_html / Value::display / style_text と同じ規則)
+const RUNTIME: &str = r#"  const $esc = (v) => String(v).replace(/&/g, "&amp;").replace(/</g, "&lt;").replace(/>/g, "&gt;").replace(/"/g, "&quot;").replace(/'/g, "&#39;");
+  const $show = (v) => v === null || v === undefined ? "none" : Array.isArray(v) ? "[" + v.map($show).join(", ") + "]" : String(v);
+  const $style = (v) => v !== null && typeof v === "object" ? Object.keys(v).sort().map((k) => k + ": " + $show(v[k]) + ";").join(" ") : $show(v);
+  const $div = (a, b) => Number.isInteger(a) && Number.isInteger(b) ? Math.trunc(a / b) : a / b;
+  const $range = (a, b) => b === undefined ? $range(0, a) : Array.from({ length: Math.max(b - a, 0) }, (_, i) => a + i);
+  const $key = (name, k) => "n7tya:" + name + ":" + k;
+  const $save = (name, persist, s) => {
+    const synced = {};
+    for (const [k, how] of Object.entries(persist)) {
+      if (how === "local") localStorage.setItem($key(name, k), JSON.stringify(s[k]));
+      else if (how === "session") synced[k] = s[k];
+    }
+    if (Object.keys(synced).length > 0) {
+      fetch(STATE_ENDPOINT, { method: "POST", headers: { "Content-Type": "application/json" }, body: JSON.stringify({ island: name, state: synced }) });
+    }
+  };
+  const $topic = (p, name) => new RegExp("^" + p.split("*").map((s) => s.replace(/[.+?^${}()|[\]\\]/g, "\\$&")).join(".*") + "$").test(name);
+  const $bus = [];
+  // 購読した island は関数を呼んだあとに描画し直す
+  const $events = (update) => ({
+    on: (p, fn) => { $bus.push([p, (x) => { const r = fn(x); update(); if (r instanceof Promise) r.then(update); }]); },
+    off: (p) => { const n = $bus.length; $bus.splice(0, n, ...$bus.filter(([q]) => q !== p)); return n - $bus.length; },
+    emit: (name, x) => { const hs = $bus.filter(([p]) => $topic(p, name)); for (const [, h] of hs) h(x); return hs.length; },
+  });
+"#;
+
+/// island のコンポーネントを動かす JS を生成する (island がなければ空文字列)
+///
+/// routes は `api.xxx()` で呼べるサーバーのルート (client_api::api_routes)。
+pub fn client_script(components: &[&ComponentDef], routes: &[ApiRoute]) -> Result<String, String> {
+    let components: Vec<(&str, &ComponentDef)> = components.iter().map(|c| ("", *c)).collect();
+    client_script_with_map(&components, routes, &mut SourceMap::new())
+}
+
+/// client_script と同じ JS を生成し、その行と .n7t の行の対応を map に書く
+///
+/// components は定義したファイルのパスとの組 (パスはそのままソースマップの sources になる)。
+pub fn client_script_with_map(
+    components: &[(&str, &ComponentDef)],
+    routes: &[ApiRoute],
+    map: &mut SourceMap,
+) -> Result<String, String> {
+    let islands: Vec<&(&str, &ComponentDef)> =
+        components.iter().filter(|(_, c)| c.island).collect();
+    if islands.is_empty() {
+        return Ok(String::new());
+    }
+
+    let mut out = Script::default();
+    out.push(
+        None,
+        "// n7tya が生成した island のクライアントコード\n(() => {\n",
+    );
+    out.push(
+        None,
+        &RUNTIME.replace("STATE_ENDPOINT", &js_string(STATE_ENDPOINT)),
+    );
+    let mut used_api = BTreeSet::new();
+    let mut compiled = Script::default();
+    for (file, component) in islands {
+        let source = map.add_source(file);
+        compile_component(component, source, routes, &mut used_api, &mut compiled)?;
+    }
+    // 使われたスタブだけを定義する
+    let used: Vec<&ApiRoute> = routes
+        .iter()
+        .filter(|r| used_api.contains(&r.name))
+        .collect();
+    if !used.is_empty() {
+        out.push(None, CLIENT_FETCH);
+        out.push(None, "  const api = {\n");
+        for route in used {
+            let origin = Some((map.add_source(&route.file), route.line));
+            out.push(origin, &client_stub(route));
+        }
+        out.push(None, "  };\n");
+    }
+    out.push(None, "  const islands = {\n");
+    out.append(compiled);
+    out.push(None, "  };\n");
+    out.push(
+        None,
+        &format!(
+            r#"  // ルーターで差し替えた部分も hydrate できるように公開する
+  const hydrate = (scope) => {{
+    for (const root of scope.querySelectorAll("[data-island]")) {{
+      const island = islands[root.dataset.island];
+      if (!island) continue;
+      const name = root.dataset.island;
+      const $s = JSON.parse(root.dataset.state || "{{}}");
+      const update = () => {{
+        root.innerHTML = island.render($s, $m);
+        $save(name, island.persist, $s);
+      }};
+      const $m = island.methods($s, $events(update));
+      // local に保存した state は前回の値に戻して描画し直す
+      let restored = false;
+      for (const [k, how] of Object.entries(island.persist)) {{
+        const saved = how === "local" ? localStorage.getItem($key(name, k)) : null;
+        if (saved !== null) {{
+          $s[k] = JSON.parse(saved);
+          restored = true;
+        }}
+      }}
+      if (restored) root.innerHTML = island.render($s, $m);
+      for (const type of {events}) {{
+        root.addEventListener(type, (event) => {{
+          const target = event.target.closest("[data-on-" + type + "]");
+          if (!target || !root.contains(target)) return;
+          const result = $m[target.getAttribute("data-on-" + type)](event);
+          update();
+          // await を使うメソッドは終わったときにもう一度描画する
+          if (result instanceof Promise) result.then(update);
+        }});
+      }}
+      if ($m.mount) {{
+        const result = $m.mount();
+        update();
+        if (result instanceof Promise) result.then(update);
+      }}
+    }}
+  }};
+  hydrate(document);
+  window.n7tyaIslands = {{ hydrate }};
+}})();
+"#,
+            events = serde_json::to_string(EVENTS).unwrap_or_default()
+        ),
+    );
+
+    for (line, origin) in out.origins.iter().enumerate() {
+        if let Some((source, source_line)) = origin {
+            map.map_line(line, *source, *source_line);
+        }
+    }
+    Ok(out.text)
+}
+
+/// 生成した行の元の位置 (ソースマップの sources の添字, 行)
+type Origin = Option<(usize, usize)>;
+
+/// 生成中の JS と、その各行の元の位置
+#[derive(Default)]
+struct Script {
+    text: String,
+    origins: Vec<Origin>,
+}
+
+impl Script {
+    /// 改行で終わる行の並びを、origin から生成したものとして加える
+    fn push(&mut self, origin: Origin, text: &str) {
+        let lines = text.matches('\n').count();
+        self.origins.extend(std::iter::repeat_n(origin, lines));
+        self.text.push_str(text);
+    }
+
+    fn append(&mut self, other: Script) {
+        self.text.push_str(&other.text);
+        self.origins.extend(other.origins);
+    }
+}
+
+fn compile_component(
+    component: &ComponentDef,
+    source: usize,
+    routes: &[ApiRoute],
+    used_api: &mut BTreeSet<String>,
+    out: &mut Script,
+) -> Result<(), String> {
+    let mut compiler = Compiler {
+        component: &component.name,
+        state: HashSet::new(),
+        methods: HashSet::new(),
+        locals: Vec::new(),
+        routes,
+        used_api: BTreeSet::new(),
+        in_method: false,
+        awaits: false,
+        source,
+        line: component.line,
+    };
+    let mut render = None;
+    let mut methods = Vec::new();
+    let mut persist = serde_json::Map::new();
+    // メソッドの前のコメントはそのメソッドの前に書き出す
+    let mut leading = Vec::new();
+    for item in &component.body {
+        match item {
+            ComponentBodyItem::State(state) => {
+                compiler.state.insert(state.name.clone());
+                if let Some(how) = state.persist {
+                    persist.insert(state.name.clone(), how.name().into());
+                }
+                leading.clear();
+            }
+            ComponentBodyItem::Method(method) => {
+                compiler.methods.insert(method.name.clone());
+                methods.push((std::mem::take(&mut leading), method));
+            }
+            ComponentBodyItem::Render(block) => {
+                render = Some(block);
+                leading.clear();
+            }
+            ComponentBodyItem::Trivia(trivia) => leading.push(trivia),
+        }
+    }
+    let render =
+        render.ok_or_else(|| format!("Component '{}' has no render block", component.name))?;
+
+    let origin = Some((source, component.line));
+    out.push(origin, &format!("    {}: {{\n", js_string(&component.name)));
+    out.push(
+        origin,
+        &format!("      persist: {},\n", serde_json::Value::Object(persist)),
+    );
+    out.push(
+        origin,
+        "      methods: ($s, $events) => {\n        const $m = {};\n",
+    );
+    compiler.in_method = true;
+    for (leading, method) in methods {
+        let origin = Some((source, method.line));
+        for trivia in leading {
+            out.push(origin, &trivia_line(trivia, 4));
+        }
+        compiler.locals = method.params.iter().map(|p| p.name.clone()).collect();
+        compiler.awaits = false;
+        compiler.line = method.line;
+        let mut body = Script::default();
+        compiler.block(&method.body, 5, &mut body)?;
+        let params: Vec<&str> = method.params.iter().map(|p| p.name.as_str()).collect();
+        out.push(
+            origin,
+            &format!(
+                "        $m.{} = {}({}) => {{\n",
+                method.name,
+                if compiler.awaits { "async " } else { "" },
+                params.join(", ")
+            ),
+        );
+        out.append(body);
+        out.push(origin, "        };\n");
+    }
+    out.push(origin, "        return $m;\n      },\n");
+
+    // render は JSX の式文をつなげた HTML を返す (Interpreter::render_component と同じ)
+    compiler.in_method = false;
+    compiler.locals.clear();
+    compiler.line = render.line;
+    let render_origin = Some((source, render.line));
+    out.push(
+        render_origin,
+        "      render: ($s, $m) => {\n        let $html = \"\";\n",
+    );
+    for stmt in &render.body {
+        let stmt_origin = compiler.origin(stmt);
+        match stmt {
+            Statement::Expression(expr @ Expression::JsxElement(_)) => {
+                let html = compiler.expr(expr)?;
+                out.push(stmt_origin, &format!("        $html += {};\n", html));
+            }
+            Statement::Return(Some(expr)) => {
+                let value = compiler.expr(expr)?;
+                out.push(
+                    stmt_origin,
+                    &format!("        return $html + $show({});\n", value),
+                );
+            }
+            _ => compiler.statement(stmt, 4, out)?,
+        }
+    }
+    out.push(render_origin, "        return $html;\n      },\n");
+    out.push(origin, "    },\n");
+    used_api.extend(compiler.used_api);
+    Ok(())
+}
+
+struct Compiler<'a> {
+    component: &'a str,
+    state: HashSet<String>,
+    methods: HashSet<String>,
+    locals: Vec<String>, // 引数と let で定義した名前 (state より優先する)
+    routes: &'a [ApiRoute],
+    used_api: BTreeSet<String>,
+    in_method: bool, // await はメソッドの中だけ (render は同期的に描画する)
+    awaits: bool,    // コンパイル中のメソッドが await を使ったか
+    source: usize,   // コンポーネントを定義したファイル (ソースマップの sources の添字)
+    line: usize,     // コンパイル中のメソッドか render の行 (文の行が分からないときに使う)
+}
+
+impl Compiler<'_> {
+    fn unsupported(&self, what: &str) -> String {
+        format!(
+            "Island '{}' uses {} which cannot run in the browser",
+            self.component, what
+        )
+    }
+
+    /// 文の元の位置 (文の中に呼び出しがあればその行、なければメソッドか render の行)
+    fn origin(&self, stmt: &Statement) -> Origin {
+        let mut first = FirstCall(None);
+        first.visit_statement(stmt);
+        Some((self.source, first.0.unwrap_or(self.line)))
+    }
+
+    fn block(&mut self, stmts: &[Statement], depth: usize, out: &mut Script) -> Result<(), String> {
+        let scope = self.locals.len();
+        for stmt in stmts {
+            self.statement(stmt, depth, out)?;
+        }
+        self.locals.truncate(scope);
+        Ok(())
+    }
+
+    fn statement(
+        &mut self,
+        stmt: &Statement,
+        depth: usize,
+        out: &mut Script,
+    ) -> Result<(), String> {
+        let indent = "  ".repeat(depth);
+        let origin = self.origin(stmt);
+        let line = match stmt {
+            Statement::Let(decl) => {
+                let value = self.expr(&decl.value)?;
+                self.locals.push(decl.name.clone());
+                format!("let {} = {};", decl.name, value)
+            }
+            Statement::Const(decl) => {
+                let value = self.expr(&decl.value)?;
+                self.locals.push(decl.name.clone());
+                format!("const {} = {};", decl.name, value)
+            }
+            Statement::Assignment(assign) => {
+                format!(
+                    "{} = {};",
+                    self.expr(&assign.target)?,
+                    self.expr(&assign.value)?
+                )
+            }
+            Statement::Return(Some(expr)) => format!("return {};", self.expr(expr)?),
+            Statement::Return(None) => "return;".to_string(),
+            Statement::Expression(expr) => format!("{};", self.expr(expr)?),
+            Statement::If(stmt) => {
+                let condition = self.expr(&stmt.condition)?;
+                out.push(origin, &format!("{}if ({}) {{\n", indent, condition));
+                self.block(&stmt.then_block, depth + 1, out)?;
+                if let Some(else_block) = &stmt.else_block {
+                    out.push(origin, &format!("{}}} else {{\n", indent));
+                    self.block(else_block, depth + 1, out)?;
+                }
+                out.push(origin, &format!("{}}}\n", indent));
+                return Ok(());
+            }
+            Statement::For(stmt) => {
+                let iterator = self.expr(&stmt.iterator)?;
+                out.push(
+                    origin,
+                    &format!(
+                        "{}{}for (const {} of {}) {{\n",
+                        indent,
+                        loop_label(&stmt.label),
+                        stmt.target,
+                        iterator
+                    ),
+                );
+                self.locals.push(stmt.target.clone());
+                self.block(&stmt.body, depth + 1, out)?;
+                self.locals.pop();
+                out.push(origin, &format!("{}}}\n", indent));
+                return Ok(());
+            }
+            Statement::While(stmt) => {
+                let condition = self.expr(&stmt.condition)?;
+                out.push(
+                    origin,
+                    &format!(
+                        "{}{}while ({}) {{\n",
+                        indent,
+                        loop_label(&stmt.label),
+                        condition
+                    ),
+                );
+                self.block(&stmt.body, depth + 1, out)?;
+                out.push(origin, &format!("{}}}\n", indent));
+                return Ok(());
+            }
+            Statement::Break(None) => "break;".to_string(),
+            Statement::Break(Some(label)) => format!("break {};", label),
+            Statement::Continue(None) => "continue;".to_string(),
+            Statement::Continue(Some(label)) => format!("continue {};", label),
+            Statement::Trivia(trivia) => {
+                out.push(origin, &trivia_line(trivia, depth));
+                return Ok(());
+            }
+            Statement::Match(_) => return Err(self.unsupported("match")),
+            Statement::FunctionDef(_) => return Err(self.unsupported("a nested function")),
+            Statement::Nonlocal(_) => return Err(self.unsupported("nonlocal")),
+            Statement::Copy(_) => return Err(self.unsupported("copy")),
+            Statement::State(_) | Statement::Render(_) => {
+                return Err(self.unsupported("a nested state or render block"))
+            }
+        };
+        out.push(origin, &format!("{}{}\n", indent, line));
+        Ok(())
+    }
+
+    fn expr(&mut self, expr: &Expression) -> Result<String, String> {
+        Ok(match expr {
+            Expression::Literal(lit) => self.literal(lit)?,
+            Expression::Identifier(name) => self.identifier(name)?,
+            Expression::BinaryOp(bin) => {
+                let left = self.expr(&bin.left)?;
+                let right = self.expr(&bin.right)?;
+                match bin.op {
+                    BinaryOp::Div => format!("$div({}, {})", left, right),
+                    BinaryOp::In => format!("({}).includes({})", right, left),
+                    ref op => {
+                        let op = match op {
+                            BinaryOp::Add => "+",
+                            BinaryOp::Sub => "-",
+                            BinaryOp::Mul => "*",
+                            BinaryOp::Mod => "%",
+                            BinaryOp::Eq => "===",
+                            BinaryOp::Ne => "!==",
+                            BinaryOp::Lt => "<",
+                            BinaryOp::Gt => ">",
+                            BinaryOp::Le => "<=",
+                            BinaryOp::Ge => ">=",
+                            BinaryOp::And => "&&",
+                            _ => "||",
+                        };
+                        format!("({} {} {})", left, op, right)
+                    }
+                }
+            }
+            Expression::UnaryOp(unary) => match unary.op {
+                UnaryOp::Neg => format!("(-{})", self.expr(&unary.operand)?),
+                UnaryOp::Not => format!("(!{})", self.expr(&unary.operand)?),
+            },
+            Expression::Call(call) => self.call(call)?,
+            Expression::MemberAccess(member) => {
+                format!("{}.{}", self.expr(&member.object)?, member.member)
+            }
+            Expression::Index(index) => {
+                format!(
+                    "{}[{}]",
+                    self.expr(&index.object)?,
+                    self.expr(&index.index)?
+                )
+            }
+            Expression::JsxElement(element) => self.jsx(element)?,
+            Expression::Lambda(_) => return Err(self.unsupported("a lambda")),
+            Expression::Await(inner) => {
+                if !self.in_method {
+                    return Err(self.unsupported("await in render"));
+                }
+                self.awaits = true;
+                format!("(await {})", self.expr(inner)?)
+            }
+            Expression::Try(_) => return Err(self.unsupported("the ? operator")),
+            Expression::StructLiteral(lit) => {
+                return Err(self.unsupported(&format!("struct '{}'", lit.name)))
+            }
+        })
+    }
+
+    /// 引数・ローカル変数はそのまま、state は $s.name、メソッドは $m.name
+    fn identifier(&self, name: &str) -> Result<String, String> {
+        if self.locals.iter().any(|local| local == name) {
+            Ok(name.to_string())
+        } else if self.state.contains(name) {
+            Ok(format!("$s.{}", name))
+        } else if self.methods.contains(name) {
+            Ok(format!("$m.{}", name))
+        } else if name == "None" {
+            Ok("null".to_string())
+        } else {
+            Err(self.unsupported(&format!("'{}' from outside the component", name)))
+        }
+    }
+
+    fn literal(&mut self, lit: &Literal) -> Result<String, String> {
+        Ok(match lit {
+            Literal::Int(n) => n.to_string(),
+            Literal::Float(f) => f.to_string(),
+            // ブラウザでは setTimeout などに合わせてミリ秒の数値にする
+            Literal::Duration(d) => (d.as_secs_f64() * 1000.0).to_string(),
+            Literal::Size(bytes) => bytes.to_string(),
+            Literal::Str(s) => js_string(s),
+            Literal::Bool(b) => b.to_string(),
+            Literal::None => "null".to_string(),
+            Literal::List(items) => {
+                let items = items
+                    .iter()
+                    .map(|item| self.expr(item))
+                    .collect::<Result<Vec<_>, _>>()?;
+                format!("[{}]", items.join(", "))
+            }
+            Literal::Dict(entries) => {
+                let mut fields = Vec::new();
+                for (key, value) in entries {
+                    fields.push(format!("[{}]: {}", self.expr(key)?, self.expr(value)?));
+                }
+                format!("{{{}}}", fields.join(", "))
+            }
+            Literal::Set(_) => return Err(self.unsupported("a set")),
+        })
+    }
+
+    fn call(&mut self, call: &CallExpr) -> Result<String, String> {
+        let mut args = Vec::new();
+        for arg in &call.args {
+            args.push(self.expr(arg)?);
+        }
+        let args_text = args.join(", ");
+
+        if let Expression::MemberAccess(member) = &call.func {
+            if matches!(&member.object, Expression::Identifier(name) if name == "api")
+                && self.identifier("api").is_err()
+            {
+                return self.api_call(&member.member, &args);
+            }
+            if matches!(&member.object, Expression::Identifier(name) if name == "events")
+                && self.identifier("events").is_err()
+            {
+                return self.events_call(&member.member, &args_text);
+            }
+            let object = self.expr(&member.object)?;
+            let method = match member.member.as_str() {
+                "append" => "push",
+                "pop" => "pop",
+                "upper" => "toUpperCase",
+                "lower" => "toLowerCase",
+                "strip" => "trim",
+                "contains" => "includes",
+                "join" => "join",
+                other => return Err(self.unsupported(&format!("the method '{}'", other))),
+            };
+            return Ok(format!("{}.{}({})", object, method, args_text));
+        }
+
+        let Expression::Identifier(name) = &call.func else {
+            return Err(self.unsupported("this call"));
+        };
+        if self.methods.contains(name.as_str()) || self.locals.iter().any(|l| l == name) {
+            return Ok(format!("{}({})", self.identifier(name)?, args_text));
+        }
+        Ok(match (name.as_str(), args.as_slice()) {
+            ("len", [value]) => format!("{}.length", value),
+            ("str", [value]) => format!("$show({})", value),
+            ("int", [value]) => format!("Math.trunc(Number({}))", value),
+            ("float", [value]) => format!("Number({})", value),
+            ("abs", [value]) => format!("Math.abs({})", value),
+            ("min", _) => format!("Math.min({})", args_text),
+            ("max", _) => format!("Math.max({})", args_text),
+            ("range", [_] | [_, _]) => format!("$range({})", args_text),
+            ("print" | "println", _) => format!("console.log({})", args_text),
+            _ => return Err(self.unsupported(&format!("the function '{}'", name))),
+        })
+    }
+
+    /// api.get_users() をサーバーのルートのスタブの呼び出しにする
+    fn api_call(&mut self, name: &str, args: &[String]) -> Result<String, String> {
+        let route = self.routes.iter().find(|r| r.name == name).ok_or_else(|| {
+            format!(
+                "Island '{}' calls api.{} but no server route has that name",
+                self.component, name
+            )
+        })?;
+        if args.len() != route.arity() {
+            return Err(format!(
+                "api.{} ({} {}) expects {} argument(s), got {}",
+                name,
+                route.method,
+                route.path,
+                route.arity(),
+                args.len()
+            ));
+        }
+        self.used_api.insert(name.to_string());
+        Ok(format!("api.{}({})", name, args.join(", ")))
+    }
+
+    /// events.on / events.off / events.emit をページの中のイベントの呼び出しにする
+    fn events_call(&self, name: &str, args_text: &str) -> Result<String, String> {
+        if !self.in_method {
+            return Err(self.unsupported(&format!("events.{} in render", name)));
+        }
+        match name {
+            "on" | "off" | "emit" => Ok(format!("$events.{}({})", name, args_text)),
+            _ => Err(self.unsupported(&format!("events.{}", name))),
+        }
+    }
+
+    /// JSX を HTML 文字列を作る JS の式にする (ページの描画 jsx_render::render_jsx と同じ形)
+    fn jsx(&mut self, element: &JsxElement) -> Result<String, String> {
+        let mut parts = Vec::new();
+        self.jsx_parts(element, &mut parts)?;
+        Ok(join_parts(parts))
+    }
+
+    fn jsx_parts(&mut self, element: &JsxElement, parts: &mut Vec<Part>) -> Result<(), String> {
+        if element.tag.starts_with(char::is_uppercase) {
+            return Err(self.unsupported(&format!("the component <{} />", element.tag)));
+        }
+        parts.push(Part::Text(format!("<{}", element.tag)));
+        let mut style = None;
+        for attr in &element.attributes {
+            // onclick={increment} は data-on-click="increment" にしてイベントをつなぐ
+            if let (Some(event), Some(Expression::Identifier(method))) =
+                (attr.name.strip_prefix("on"), &attr.value)
+            {
+                if self.methods.contains(method.as_str()) {
+                    parts.push(Part::Text(format!(
+                        " data-on-{}=\"{}\"",
+                        event.to_ascii_lowercase(),
+                        method
+                    )));
+                    continue;
+                }
+            }
+            let value = match &attr.value {
+                Some(expr) => self.expr(expr)?,
+                None => "true".to_string(),
+            };
+            if attr.name == "style" {
+                style = Some(value);
+                continue;
+            }
+            parts.push(Part::Text(format!(" {}=\"", attr.name)));
+            parts.push(Part::Code(format!("$esc($show({}))", value)));
+            parts.push(Part::Text("\"".to_string()));
+        }
+        if let Some(style) = style {
+            parts.push(Part::Text(" style=\"".to_string()));
+            parts.push(Part::Code(format!("$esc($style({}))", style)));
+            parts.push(Part::Text("\"".to_string()));
+        }
+
+        if element.children.is_empty() {
+            parts.push(Part::Text(" />".to_string()));
+            return Ok(());
+        }
+        parts.push(Part::Text(">".to_string()));
+        for child in &element.children {
+            match child {
+                JsxChild::Element(child) => self.jsx_parts(child, parts)?,
+                JsxChild::Text(text) => parts.push(Part::Text(escape_html(text))),
+                JsxChild::Expression(expr) => {
+                    let value = self.expr(expr)?;
+                    parts.push(Part::Code(format!("$esc($show({}))", value)));
+                }
+            }
+        }
+        parts.push(Part::Text(format!("</{}>", element.tag)));
+        Ok(())
+    }
+}
+
+/// 生成する HTML の断片 (そのままの文字列か、実行時に値を埋め込む JS の式)
+enum Part {
+    Text(String),
+    Code(String),
+}
+
+/// 断片を + でつないだ JS の式にする (隣り合う文字列はまとめる)
+fn join_parts(parts: Vec<Part>) -> String {
+    let mut pieces: Vec<String> = Vec::new();
+    let mut text = String::new();
+    for part in parts {
+        match part {
+            Part::Text(t) => text.push_str(&t),
+            Part::Code(code) => {
+                if !text.is_empty() {
+                    pieces.push(js_string(&std::mem::take(&mut text)));
+                }
+                pieces.push(code);
+            }
+        }
+    }
+    if !text.is_empty() || pieces.is_empty() {
+        pieces.push(js_string(&text));
+    }
+    format!("({})", pieces.join(" + "))
+}
+
+fn js_string(s: &str) -> String {
+    serde_json::Value::from(s).to_string()
+}
+
+/// コメントは JS の行コメントに、続く空行は1行の空行にする
+///
+/// 行末のコメントも次の行に書く。`</script>` で終わらないよう `</` はエスケープする。
+fn trivia_line(trivia: &Trivia, depth: usize) -> String {
+    match trivia {
+        Trivia::Comment { text, .. } => {
+            format!("{}//{}\n", "  ".repeat(depth), text.replace("</", "<\\/"))
+        }
+        Trivia::BlankLines(_) => "\n".to_string(),
+    }
+}
+
+/// 文の中で最初に書いた呼び出しの行 (入れ子のブロックの中は見ない)
+struct FirstCall(Option<usize>);
+
+impl Visitor for FirstCall {
+    fn visit_block(&mut self, _block: &[Statement]) {}
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        if let (None, Expression::Call(call)) = (self.0, expression) {
+            self.0 = Some(call.line);
+        }
+        walk_expression(self, expression)
+    }
+}
+
+/// ラベル付きのループの先頭 ("outer: ")
+fn loop_label(label: &Option<String>) -> String {
+    label
+        .as_ref()
+        .map_or(String::new(), |label| format!("{}: ", label))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Item;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn compile(source: &str) -> Result<String, String> {
+        let tokens = Lexer::new(source).tokenize();
+        let program = Parser::new(tokens).parse().unwrap();
+        let components: Vec<&ComponentDef> = program
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Item::ComponentDef(c) if c.island => Some(c),
+                _ => None,
+            })
+            .collect();
+        let servers: Vec<(&str, &ServerDef)> = program
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Item::ServerDef(s) => Some(("", s)),
+                _ => None,
+            })
+            .collect();
+        client_script(&components, &crate::client_api::api_routes(&servers))
+    }
+
+    #[test]
+    fn test_compile_island() {
+        let script = compile(
+            "island component Toggle\n\tstate on = False\n\tdef flip\n\t\ton = not on\n\trender\n\t\t<button onclick={flip}>{on}</button>\ncomponent Static\n\trender\n\t\t<p>x</p>\n",
+        )
+        .unwrap();
+        assert!(script.contains("\"Toggle\": {"));
+        assert!(script.contains("$s.on = (!$s.on);"));
+        assert!(!script.contains("\"Static\""));
+    }
+
+    #[test]
+    fn test_comments_are_kept() {
+        let script = compile(
+            "island component Counter\n\tstate n = 0\n\t# 1つ増やす\n\tdef inc\n\t\t# 上限なし </script>\n\t\tn = n + 1 # 行末\n\n\t\tn = n + 1\n\trender\n\t\t<p>{n}</p>\n",
+        )
+        .unwrap();
+        assert!(script.contains("        // 1つ増やす\n        $m.inc = () => {\n"));
+        assert!(script.contains(
+            "          // 上限なし <\\/script>\n          $s.n = ($s.n + 1);\n          // 行末\n\n          $s.n = ($s.n + 1);\n"
+        ));
+    }
+
+    #[test]
+    fn test_persisted_state() {
+        let script = compile(
+            "island component Draft\n\tstate local text = \"\"\n\tstate session saved = 0\n\trender\n\t\t<p>{text}</p>\n",
+        )
+        .unwrap();
+        assert!(script.contains("persist: {\"saved\":\"session\",\"text\":\"local\"},"));
+        assert!(script.contains("fetch(\"/__n7tya/state\""));
+    }
+
+    #[test]
+    fn test_api_stubs() {
+        let server = "struct Todo\n\ttitle: Str\nserver App\n\tget \"/todos\" -> Json<List<Todo>>\n\t\treturn []\n\tpost \"/todos\" (todo: Todo) -> Json<Todo>\n\t\treturn todo\n\tget \"/health\"\n\t\treturn \"ok\"\n";
+        let script = compile(&format!(
+            "{}island component Todos\n\tstate items = []\n\tdef load\n\t\titems = await api.get_todos()\n\tdef add title\n\t\tapi.post_todos({{\"title\": title}})\n\trender\n\t\t<p>{{len(items)}}</p>\n",
+            server
+        ))
+        .unwrap();
+        assert!(script.contains("$m.load = async () => {"));
+        assert!(script.contains("$s.items = (await api.get_todos());"));
+        assert!(script.contains(
+            "    // POST /todos (todo: Todo) -> Json<Todo>\n    post_todos: (body) => $fetch(\"POST\", \"/todos\", body),\n"
+        ));
+        assert!(!script.contains("get_health"));
+
+        let err = compile(&format!(
+            "{}island component Bad\n\tdef load\n\t\tapi.post_todos()\n\trender\n\t\t<p>x</p>\n",
+            server
+        ))
+        .unwrap_err();
+        assert_eq!(
+            err,
+            "api.post_todos (POST /todos) expects 1 argument(s), got 0"
+        );
+    }
+
+    #[test]
+    fn test_events() {
+        let script = compile(
+            "island component Badge\n\tstate count = 0\n\tdef mount\n\t\tevents.on(\"cart.*\", bump)\n\tdef bump item\n\t\tcount = count + 1\n\trender\n\t\t<span>{count}</span>\nisland component Add\n\tdef add\n\t\tevents.emit(\"cart.added\", 1)\n\trender\n\t\t<button onclick={add}>+</button>\n",
+        )
+        .unwrap();
+        assert!(script.contains("$events.on(\"cart.*\", $m.bump);"));
+        assert!(script.contains("$events.emit(\"cart.added\", 1);"));
+        assert!(script.contains("if ($m.mount) {"));
+
+        let err = compile("island component Bad\n\trender\n\t\t<p>{events.emit(\"x\")}</p>\n")
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "Island 'Bad' uses events.emit in render which cannot run in the browser"
+        );
+    }
+
+    #[test]
+    fn test_unsupported_construct() {
+        let err = compile(
+            "island component Bad\n\tstate n = 0\n\trender\n\t\t<p>{read_file(\"x\")}</p>\n",
+        )
+        .unwrap_err();
+        assert!(err.starts_with("Island 'Bad' uses "), "{}", err);
+    }
+
+    #[test]
+    fn test_source_map() {
+        let parse = |source: &str| Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let api = parse("server App\n\tget \"/todos\"\n\t\treturn \"[]\"\n");
+        let cart = parse(
+            "island component Cart\n\tstate n = 0\n\tdef add\n\t\tn = n + 1\n\t\tevents.emit(\"cart.added\", n)\n\t\tapi.get_todos()\n\trender\n\t\t<p>{n}</p>\n",
+        );
+        let (Item::ServerDef(server), Item::ComponentDef(component)) =
+            (&api.items[0], &cart.items[0])
+        else {
+            panic!("unexpected items");
+        };
+        let routes = crate::client_api::api_routes(&[("src/api.n7t", server)]);
+        let mut map = SourceMap::new();
+        let script =
+            client_script_with_map(&[("src/cart.n7t", component)], &routes, &mut map).unwrap();
+
+        let origin = |needle: &str| {
+            let line = script.lines().position(|l| l.contains(needle)).unwrap();
+            map.origin(line)
+                .map(|(source, line)| (map.sources()[source].as_str(), line))
+        };
+        assert_eq!(origin("\"Cart\": {"), Some(("src/cart.n7t", 1)));
+        assert_eq!(origin("$m.add = () => {"), Some(("src/cart.n7t", 3)));
+        assert_eq!(origin("$events.emit("), Some(("src/cart.n7t", 5)));
+        assert_eq!(origin("api.get_todos();"), Some(("src/cart.n7t", 6)));
+        assert_eq!(origin("$html += "), Some(("src/cart.n7t", 7)));
+        assert_eq!(origin("get_todos: () =>"), Some(("src/api.n7t", 2)));
+        assert_eq!(origin("const hydrate"), None);
+    }
+}
@@ -0,0 +1,496 @@
+//! range の値と遅延イテレータ (enumerate, zip, zip_longest, map, filter の戻り値)、関数を呼びながら回す reduce と sorted
+//!
+//! 値は for で取り出すときに1つずつ作るので、`range(10_000_000)` でも要素のリストは作らない。
+//! range は何度でも回せ、len・添字・in も使える。ほかのイテレータは一度しか回せないので、
+//! 中身が必要なら `list(zip(a, b))` のように変換する。
+//! リストなどのコレクションから作るイテレータは、作った時点の中身を回す。
+
+use crate::interpreter::{compare_values, Interpreter, Value};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// range(start, end, step) の値 (step が 0 なら空)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub start: i64,
+    pub end: i64,
+    pub step: i64,
+}
+
+impl Range {
+    pub fn len(&self) -> usize {
+        let (start, end, step) = (self.start as i128, self.end as i128, self.step as i128);
+        let len = match step {
+            1.. if start < end => (end - start - 1) / step + 1,
+            ..=-1 if start > end => (start - end - 1) / -step + 1,
+            _ => 0,
+        };
+        len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// index 番目の値 (範囲外なら None)
+    pub fn get(&self, index: i64) -> Option<i64> {
+        let index = usize::try_from(index).ok().filter(|&i| i < self.len())?;
+        Some((self.start as i128 + index as i128 * self.step as i128) as i64)
+    }
+
+    pub fn contains(&self, n: i64) -> bool {
+        let offset = n as i128 - self.start as i128;
+        self.step != 0
+            && offset % self.step as i128 == 0
+            && usize::try_from(offset / self.step as i128).is_ok_and(|i| i < self.len())
+    }
+
+    /// 最初と最後の値 (空なら None)
+    fn bounds(&self) -> Option<(i128, i128)> {
+        let len = self.len() as i128;
+        let start = self.start as i128;
+        (len > 0).then(|| (start, start + (len - 1) * self.step as i128))
+    }
+
+    pub fn items(&self) -> Vec<Value> {
+        (0..self.len() as i64)
+            .filter_map(|i| self.get(i))
+            .map(Value::Int)
+            .collect()
+    }
+}
+
+/// イテレータの状態
+#[derive(Debug)]
+pub enum Iter {
+    /// range を回すときの次の値
+    Range { next: i64, end: i64, step: i64 },
+    /// コレクションの要素 (Value::iter_items の結果)
+    Items(std::vec::IntoIter<Value>),
+    /// enumerate(x, start) の元のイテレータと次のインデックス
+    Enumerate(Rc<RefCell<Iter>>, i64),
+    /// zip(a, b, ...) の元のイテレータ (zip_longest は終わったものを None にして最も長いものまで回す)
+    Zip {
+        iters: Vec<Option<Rc<RefCell<Iter>>>>,
+        longest: bool,
+    },
+    /// map(f, x) の元のイテレータと関数
+    Map(Rc<RefCell<Iter>>, Value),
+    /// filter(f, x) の元のイテレータと関数
+    Filter(Rc<RefCell<Iter>>, Value),
+}
+
+impl Iter {
+    pub fn enumerate(value: &Value, start: i64) -> Option<Value> {
+        Some(Self::Enumerate(Self::over(value)?, start).into_value())
+    }
+
+    pub fn zip(values: &[Value], longest: bool) -> Option<Value> {
+        let iters = values
+            .iter()
+            .map(|value| Self::over(value).map(Some))
+            .collect::<Option<_>>()?;
+        Some(Self::Zip { iters, longest }.into_value())
+    }
+
+    pub fn map(function: Value, value: &Value) -> Option<Value> {
+        Some(Self::Map(Self::over(value)?, function).into_value())
+    }
+
+    pub fn filter(function: Value, value: &Value) -> Option<Value> {
+        Some(Self::Filter(Self::over(value)?, function).into_value())
+    }
+
+    /// 値を回すイテレータ (イテレータはそのもの、range は先頭から、コレクションは今の中身)。繰り返せない値は None
+    pub fn over(value: &Value) -> Option<Rc<RefCell<Iter>>> {
+        match value {
+            Value::Iter(iter) => Some(iter.clone()),
+            Value::Range(range) => Some(Rc::new(RefCell::new(Self::Range {
+                next: range.start,
+                end: range.end,
+                step: range.step,
+            }))),
+            _ => Some(Rc::new(RefCell::new(Self::Items(
+                value.iter_items()?.into_iter(),
+            )))),
+        }
+    }
+
+    fn into_value(self) -> Value {
+        Value::Iter(Rc::new(RefCell::new(self)))
+    }
+}
+
+/// 次の値を取り出す (map の関数を呼ぶのでインタプリタが要る)
+pub(crate) fn next(
+    interpreter: &mut Interpreter,
+    iter: &Rc<RefCell<Iter>>,
+) -> Result<Option<Value>, String> {
+    let mut state = iter.borrow_mut();
+    match &mut *state {
+        Iter::Range { next, end, step } => {
+            let value = *next;
+            let more = (*step > 0 && value < *end) || (*step < 0 && value > *end);
+            if !more {
+                return Ok(None);
+            }
+            *next = value.saturating_add(*step);
+            Ok(Some(Value::Int(value)))
+        }
+        Iter::Items(items) => Ok(items.next()),
+        // 元のイテレータや map の関数が同じイテレータに触れても借用が重ならないよう、先に手放す
+        Iter::Enumerate(inner, index) => {
+            let (inner, i) = (inner.clone(), *index);
+            *index = index.saturating_add(1);
+            drop(state);
+            Ok(next(interpreter, &inner)?.map(|item| list(vec![Value::Int(i), item])))
+        }
+        Iter::Zip { iters, longest } => {
+            let (inners, longest) = (iters.clone(), *longest);
+            drop(state);
+            if inners.is_empty() {
+                return Ok(None);
+            }
+            let mut items = Vec::with_capacity(inners.len());
+            let mut finished = Vec::new();
+            for (i, inner) in inners.iter().enumerate() {
+                let item = match inner {
+                    Some(inner) => next(interpreter, inner)?,
+                    None => None,
+                };
+                match item {
+                    Some(item) => items.push(item),
+                    None if longest => {
+                        finished.push(i);
+                        items.push(Value::None);
+                    }
+                    None => return Ok(None),
+                }
+            }
+            if finished.len() == inners.len() {
+                return Ok(None);
+            }
+            // 終わったイテレータはもう回さない
+            if let Iter::Zip { iters, .. } = &mut *iter.borrow_mut() {
+                for i in finished {
+                    iters[i] = None;
+                }
+            }
+            Ok(Some(list(items)))
+        }
+        Iter::Map(inner, function) => {
+            let (inner, function) = (inner.clone(), function.clone());
+            drop(state);
+            match next(interpreter, &inner)? {
+                Some(item) => interpreter.call_function(function, vec![item]).map(Some),
+                None => Ok(None),
+            }
+        }
+        Iter::Filter(inner, function) => {
+            let (inner, function) = (inner.clone(), function.clone());
+            drop(state);
+            while let Some(item) = next(interpreter, &inner)? {
+                if interpreter
+                    .call_function(function.clone(), vec![item.clone()])?
+                    .is_truthy()
+                {
+                    return Ok(Some(item));
+                }
+            }
+            Ok(None)
+        }
+    }
+}
+
+/// 残りをすべて取り出してリストにする (list(x) や sum(x) など)
+pub(crate) fn collect(
+    interpreter: &mut Interpreter,
+    iter: &Rc<RefCell<Iter>>,
+) -> Result<Vec<Value>, String> {
+    let mut items = Vec::new();
+    while let Some(item) = next(interpreter, iter)? {
+        interpreter.tick()?;
+        items.push(item);
+    }
+    Ok(items)
+}
+
+/// sum / min / max を range やイテレータに使う (リストを作らずに1つずつ畳み込む)
+///
+/// range は回さずに最初と最後の値から求める。
+pub(crate) fn fold(
+    interpreter: &mut Interpreter,
+    name: &str,
+    value: &Value,
+) -> Result<Value, String> {
+    let call = |items: Vec<Value>| crate::builtins::call_builtin(name, vec![list(items)]);
+    if let Value::Range(range) = value {
+        let Some((first, last)) = range.bounds() else {
+            return call(Vec::new());
+        };
+        let result = match name {
+            "sum" => range.len() as i128 * (first + last) / 2,
+            "min" => first.min(last),
+            _ => first.max(last),
+        };
+        return i64::try_from(result)
+            .map(Value::Int)
+            .map_err(|_| "Integer overflow".to_string());
+    }
+    let iter = Iter::over(value).expect("iterable");
+    let mut result = None;
+    while let Some(item) = next(interpreter, &iter)? {
+        interpreter.tick()?;
+        result = Some(match result {
+            Some(result) => call(vec![result, item])?,
+            None => item,
+        });
+    }
+    call(result.into_iter().collect())
+}
+
+/// reduce(f, x) / reduce(f, x, initial): 前の結果と次の要素で f を呼び続ける
+pub(crate) fn reduce(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    let (function, iterable, initial) = match <[Value; 2]>::try_from(args) {
+        Ok([function, iterable]) => (function, iterable, None),
+        Err(args) => match <[Value; 3]>::try_from(args) {
+            Ok([function, iterable, initial]) => (function, iterable, Some(initial)),
+            Err(_) => {
+                return Err(
+                    "reduce() expects a function, a collection and an optional initial value"
+                        .to_string(),
+                )
+            }
+        },
+    };
+    let Some(iter) = Iter::over(&iterable) else {
+        return Err(format!(
+            "reduce() cannot iterate over {}",
+            iterable.display()
+        ));
+    };
+    let Some(mut result) = initial.map_or_else(|| next(interpreter, &iter), |v| Ok(Some(v)))?
+    else {
+        return Err("reduce() of an empty collection with no initial value".to_string());
+    };
+    while let Some(item) = next(interpreter, &iter)? {
+        result = interpreter.call_function(function.clone(), vec![result, item])?;
+    }
+    Ok(result)
+}
+
+/// sorted(x) / sorted(x, key): 要素 (key があればその戻り値) の順に並べたリスト (同じ値の要素は元の順のまま)
+pub(crate) fn sorted(
+    interpreter: &mut Interpreter,
+    iterable: &Value,
+    key: Option<Value>,
+) -> Result<Value, String> {
+    let Some(iter) = Iter::over(iterable) else {
+        return Err(format!(
+            "sorted() cannot iterate over {}",
+            iterable.display()
+        ));
+    };
+    let items = collect(interpreter, &iter)?;
+    let keys = match key {
+        Some(key) => items
+            .iter()
+            .map(|item| interpreter.call_function(key.clone(), vec![item.clone()]))
+            .collect::<Result<Vec<_>, _>>()?,
+        None => items.clone(),
+    };
+    let sorted = sort_order(interpreter, &keys)?
+        .into_iter()
+        .map(|i| items[i].clone())
+        .collect();
+    Ok(Value::List(Rc::new(RefCell::new(sorted))))
+}
+
+/// list.sort(key, reverse): key の戻り値 (なければ要素そのもの) の順に並べる
+///
+/// reverse でも同じ値の要素は元の順のまま (逆順にして並べてから戻す)。
+pub(crate) fn sort_items(
+    interpreter: &mut Interpreter,
+    mut items: Vec<Value>,
+    key: Option<Value>,
+    reverse: bool,
+) -> Result<Vec<Value>, String> {
+    if reverse {
+        items.reverse();
+    }
+    let keys = match key {
+        Some(key) => items
+            .iter()
+            .map(|item| interpreter.call_function(key.clone(), vec![item.clone()]))
+            .collect::<Result<Vec<_>, _>>()?,
+        None => items.clone(),
+    };
+    let order = sort_order(interpreter, &keys).map_err(|e| e.replacen("sorted()", "sort()", 1))?;
+    let mut sorted: Vec<Value> = order.into_iter().map(|i| items[i].clone()).collect();
+    if reverse {
+        sorted.reverse();
+    }
+    Ok(sorted)
+}
+
+/// keys を並べたときの添字の順 (比べられない値や実行の上限を超えたらエラー)
+fn sort_order(interpreter: &mut Interpreter, keys: &[Value]) -> Result<Vec<usize>, String> {
+    let mut error = None;
+    let mut order: Vec<usize> = (0..keys.len()).collect();
+    order.sort_by(|&a, &b| {
+        if error.is_some() {
+            return std::cmp::Ordering::Equal;
+        }
+        if let Err(e) = interpreter.tick() {
+            error = Some(e);
+            return std::cmp::Ordering::Equal;
+        }
+        compare_values(&keys[a], &keys[b]).unwrap_or_else(|| {
+            error.get_or_insert_with(|| {
+                format!(
+                    "sorted() cannot compare {} and {}",
+                    keys[a].display(),
+                    keys[b].display()
+                )
+            });
+            std::cmp::Ordering::Equal
+        })
+    });
+    match error {
+        Some(e) => Err(e),
+        None => Ok(order),
+    }
+}
+
+fn list(items: Vec<Value>) -> Value {
+    Value::List(Rc::new(RefCell::new(items)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::run;
+
+    #[test]
+    fn test_lazy_iterators() {
+        let source = "def double x\n    return x * 2\nlet total = 0\nfor i in range(10000000)\n    if i == 3\n        break\n    total = total + i\nlet m = map(double, [1, 2])\nlet first = list(m)\n[total, first, list(m), list(enumerate(map(double, range(0, 6, 2)))), list(zip(\"ab\", range(5))), sum(range(5)), type(m)]\n";
+        assert_eq!(
+            run(source).unwrap(),
+            "[3, [2, 4], [], [[0, 0], [1, 4], [2, 8]], [[a, 0], [b, 1]], 10, Iterator]"
+        );
+    }
+
+    #[test]
+    fn test_argument_errors() {
+        assert_eq!(
+            run("map(1, 2)\n").unwrap_err(),
+            "map() expects a function and a list, dict, string, set or iterator"
+        );
+        assert_eq!(
+            run("enumerate([1], \"a\")\n").unwrap_err(),
+            "enumerate() expects a collection and an optional start index"
+        );
+        assert_eq!(
+            run("zip([1], 2)\n").unwrap_err(),
+            "zip() expects lists, dicts, strings, sets or iterators"
+        );
+        assert_eq!(
+            run("reduce(str, [])\n").unwrap_err(),
+            "reduce() of an empty collection with no initial value"
+        );
+        assert_eq!(
+            run("sorted([1, \"a\"])\n").unwrap_err(),
+            "sorted() cannot compare a and 1"
+        );
+    }
+
+    #[test]
+    fn test_range_is_reusable() {
+        let source = "let r = range(3)\nlet total = 0\nfor i in r\n    total = total + i\n[list(r), total, sum(r), type(r), r]\n";
+        assert_eq!(run(source).unwrap(), "[[0, 1, 2], 3, 3, Range, [0, 1, 2]]");
+        assert_eq!(run("list(range(5, 0, -2))\n").unwrap(), "[5, 3, 1]");
+        assert_eq!(run("list(range(3, 0))\n").unwrap(), "[]");
+        assert_eq!(run("list(range(0, 5, 0))\n").unwrap(), "[]");
+    }
+
+    #[test]
+    fn test_range_len_index_and_in() {
+        let source = "let r = range(10, 0, -3)\n[len(r), len(range(5)), len(range(-9223372036854775807, 9223372036854775807, 4611686018427387904)), r[0], r[3], range(5)[1], 4 in r, 5 in r, 2.0 in range(5), \"2\" in range(5), 10 in range(10)]\n";
+        assert_eq!(
+            run(source).unwrap(),
+            "[4, 5, 4, 10, 1, 1, true, false, true, false, false]"
+        );
+        assert_eq!(run("range(3)[3]\n").unwrap_err(), "Index out of bounds");
+        assert_eq!(run("range(3)[-1]\n").unwrap_err(), "Index out of bounds");
+    }
+
+    #[test]
+    fn test_sum_min_max_do_not_build_a_list() {
+        let source = "[sum(range(2000000000)), min(range(10, 0, -3)), max(range(10, 0, -3)), sum(range(5, 0)), sum(map(int, [\"1\", \"2\"])), max(map(abs, [-5, 3]))]\n";
+        assert_eq!(
+            run(source).unwrap(),
+            "[1999999999000000000, 1, 10, 0, 3, 5]"
+        );
+        assert_eq!(
+            run("min(range(0))\n").unwrap_err(),
+            "min() arg is an empty list"
+        );
+        assert_eq!(
+            run("sum(range(4611686018427387904, 4611686018427387907))\n").unwrap_err(),
+            "Integer overflow"
+        );
+        assert_eq!(
+            run("len(range(-9223372036854775807, 9223372036854775807))\n").unwrap_err(),
+            "Integer overflow"
+        );
+    }
+
+    #[test]
+    fn test_range_equality_and_json() {
+        let source = "[range(3) == [0, 1, 2], [0, 1] == range(2), range(3) == [0, 1], range(0, 6, 2) == range(0, 5, 2), range(0) == range(5, 1), range(2) != range(3), json.stringify(range(3))]\n";
+        assert_eq!(
+            run(source).unwrap(),
+            "[true, true, false, true, true, true, [0,1,2]]"
+        );
+    }
+
+    #[test]
+    fn test_iterators_refuse_equality_and_json() {
+        let error = "Cannot compare an iterator; convert it with list() first";
+        assert_eq!(run("map(str, [1]) == [\"1\"]\n").unwrap_err(), error);
+        assert_eq!(run("[1] != zip([1], [2])\n").unwrap_err(), error);
+        let error = "json.stringify() cannot serialize an iterator; convert it with list() first";
+        assert_eq!(run("json.stringify(enumerate([1]))\n").unwrap_err(), error);
+        assert_eq!(
+            run("json.stringify({\"pairs\": zip([1], [2])})\n").unwrap_err(),
+            error
+        );
+        assert_eq!(
+            run("json.stringify(list(zip([1], [2])))\n").unwrap(),
+            "[[1,2]]"
+        );
+    }
+
+    #[test]
+    fn test_enumerate_start_and_zip_many() {
+        let source = "[list(enumerate(\"ab\", 1)), list(zip([1, 2, 3], \"xy\", range(10))), list(zip_longest([1, 2, 3], \"xy\")), list(zip({\"k\": 1})), list(zip())]\n";
+        assert_eq!(
+            run(source).unwrap(),
+            "[[[1, a], [2, b]], [[1, x, 0], [2, y, 1]], [[1, x], [2, y], [3, none]], [[k]], []]"
+        );
+        // 遅延イテレータどうしも組み合わせられ、zip_longest は終わったイテレータをもう回さない
+        let source = "def is_even x\n    return x % 2 == 0\nlet evens = filter(is_even, range(5))\nlist(zip_longest(evens, enumerate(range(2), 10)))\n";
+        assert_eq!(
+            run(source).unwrap(),
+            "[[0, [10, 0]], [2, [11, 1]], [4, none]]"
+        );
+    }
+
+    #[test]
+    fn test_higher_order_builtins() {
+        let source = "def is_even x\n    return x % 2 == 0\ndef add total, x\n    return total + x\ndef length s\n    return len(s)\n[list(filter(is_even, range(7))), reduce(add, [1, 2, 3]), reduce(add, [], 10), sorted([\"ccc\", \"a\", \"bb\", \"d\"], length), sorted([2.5, 1, 3]), sorted(\"cab\")]\n";
+        assert_eq!(
+            run(source).unwrap(),
+            "[[0, 2, 4, 6], 6, 10, [a, d, bb, ccc], [1, 2.5, 3], [a, b, c]]"
+        );
+    }
+}
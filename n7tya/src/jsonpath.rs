@@ -0,0 +1,308 @@
+//! json.get / json.set (パスで入れ子の値を読み書きする)
+//!
+//! パスは `"user.address.city"` のようにキーを "." でつなぎ、リストの要素は `"items[0]"` (`[-1]` は最後) と書く。
+//! "." や "[" を含むキーは `["a.b"]` のように引用符で囲む。
+//! `json.get(data, path, default)` は途中のキーがない・型が違う・値が none のときにエラーにせず default を返す。
+//! `json.set(data, path, value)` は途中のないキーに辞書 (次が `[0]` ならリスト) を作って値を入れ、data を返す。
+
+use crate::interpreter::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// パスの1つ分
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Part {
+    Key(String),
+    Index(i64),
+}
+
+/// json.get(data, path[, default])
+pub fn get(args: Vec<Value>) -> Result<Value, String> {
+    let (data, path, default) = match args.as_slice() {
+        [data, Value::Str(path)] => (data, path, Value::None),
+        [data, Value::Str(path), default] => (data, path, default.clone()),
+        _ => return Err("json.get() expects (data, path: Str, default?)".to_string()),
+    };
+    let mut current = data.clone();
+    for part in parse(path)? {
+        match child(&current, &part) {
+            Some(value) => current = value,
+            None => return Ok(default),
+        }
+    }
+    Ok(match current {
+        Value::None => default,
+        value => value,
+    })
+}
+
+/// json.set(data, path, value)
+pub fn set(args: Vec<Value>) -> Result<Value, String> {
+    let [data, Value::Str(path), value] = args.as_slice() else {
+        return Err("json.set() expects (data, path: Str, value)".to_string());
+    };
+    let parts = parse(path)?;
+    if parts.is_empty() {
+        return Err("json.set() expects a non-empty path".to_string());
+    }
+    set_at(data, &parts, value.clone()).map_err(|e| format!("json.set(): {}", e))?;
+    Ok(data.clone())
+}
+
+/// パスの位置に値を入れる (途中のないキーには入れ物を作る)
+pub(crate) fn set_at(data: &Value, parts: &[Part], value: Value) -> Result<(), String> {
+    let Some((last, parents)) = parts.split_last() else {
+        return Err("the path is empty".to_string());
+    };
+    let mut current = data.clone();
+    for (i, part) in parents.iter().enumerate() {
+        current = match child(&current, part) {
+            Some(
+                next @ (Value::Dict(_) | Value::List(_) | Value::Struct(..) | Value::Class(..)),
+            ) => next,
+            Some(Value::None) | None => {
+                // 次がリストの添字ならリストを、それ以外は辞書を作る
+                let created = if matches!(parts[i + 1], Part::Index(_)) {
+                    Value::List(Rc::new(RefCell::new(Vec::new())))
+                } else {
+                    Value::Dict(Rc::new(RefCell::new(HashMap::new())))
+                };
+                assign(&current, part, created.clone(), &parts[..=i])?;
+                created
+            }
+            Some(other) => {
+                return Err(format!(
+                    "'{}' is a {}, not a dict or a list",
+                    describe(&parts[..=i]),
+                    other.type_name()
+                ))
+            }
+        };
+    }
+    assign(&current, last, value, parts)
+}
+
+/// パスの位置の値を取り除く (辞書とクラスはキーを消し、リストは要素を詰める)
+pub(crate) fn remove_at(data: &Value, parts: &[Part]) -> Result<(), String> {
+    let Some((last, parents)) = parts.split_last() else {
+        return Err("the path is empty".to_string());
+    };
+    let mut container = data.clone();
+    for (i, part) in parents.iter().enumerate() {
+        container = child(&container, part)
+            .ok_or_else(|| format!("'{}' does not exist", describe(&parts[..=i])))?;
+    }
+    let removed = match (&container, last) {
+        (Value::Dict(entries), Part::Key(key)) => entries.borrow_mut().remove(key).is_some(),
+        (Value::Class(_, fields), Part::Key(key)) => fields.borrow_mut().remove(key).is_some(),
+        (Value::List(items), Part::Index(index)) => {
+            let mut items = items.borrow_mut();
+            let position = position(*index, items.len());
+            position.map(|i| items.remove(i)).is_some()
+        }
+        _ => false,
+    };
+    if !removed {
+        return Err(format!("cannot remove '{}'", describe(parts)));
+    }
+    Ok(())
+}
+
+/// "a.b[0]" を [Key("a"), Key("b"), Index(0)] にする
+pub(crate) fn parse(path: &str) -> Result<Vec<Part>, String> {
+    let invalid = || format!("Invalid json path '{}'", path);
+    let mut parts = Vec::new();
+    let mut rest = path;
+    while !rest.is_empty() {
+        if let Some(bracket) = rest.strip_prefix('[') {
+            if let Some(quoted) = bracket.strip_prefix('"') {
+                let end = quoted.find("\"]").ok_or_else(invalid)?;
+                parts.push(Part::Key(quoted[..end].to_string()));
+                rest = &quoted[end + 2..];
+            } else {
+                let end = bracket.find(']').ok_or_else(invalid)?;
+                parts.push(Part::Index(bracket[..end].parse().map_err(|_| invalid())?));
+                rest = &bracket[end + 1..];
+            }
+        } else {
+            let end = rest.find(['.', '[']).unwrap_or(rest.len());
+            if end == 0 {
+                return Err(invalid());
+            }
+            parts.push(Part::Key(rest[..end].to_string()));
+            rest = &rest[end..];
+        }
+        // キーのあとは "." か "[" か終わり ("." のあとにはキーが続く)
+        if let Some(after) = rest.strip_prefix('.') {
+            if after.is_empty() || after.starts_with(['.', '[']) {
+                return Err(invalid());
+            }
+            rest = after;
+        } else if !rest.is_empty() && !rest.starts_with('[') {
+            return Err(invalid());
+        }
+    }
+    Ok(parts)
+}
+
+/// 添字が負なら末尾から数える
+fn position(index: i64, len: usize) -> Option<usize> {
+    let position = if index < 0 { len as i64 + index } else { index };
+    usize::try_from(position).ok().filter(|p| *p < len)
+}
+
+/// 辞書のキー、構造体・クラスのフィールド、リストの要素 (リストには "items.0" のような数字のキーも使える)
+fn child(value: &Value, part: &Part) -> Option<Value> {
+    match (value, part) {
+        (Value::Dict(entries), Part::Key(key)) => entries.borrow().get(key).cloned(),
+        (Value::Class(_, fields), Part::Key(key)) => fields.borrow().get(key).cloned(),
+        (Value::Struct(_, fields), Part::Key(key)) => fields
+            .borrow()
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.clone()),
+        (Value::List(items), Part::Index(index)) => {
+            let items = items.borrow();
+            position(*index, items.len()).map(|i| items[i].clone())
+        }
+        (Value::List(_), Part::Key(key)) => child(value, &Part::Index(key.parse().ok()?)),
+        _ => None,
+    }
+}
+
+/// 入れ物の part の位置に値を入れる (リストは末尾の次に入れると追加になる)
+fn assign(container: &Value, part: &Part, value: Value, path: &[Part]) -> Result<(), String> {
+    match (container, part) {
+        (Value::Dict(entries), Part::Key(key)) => {
+            entries.borrow_mut().insert(key.clone(), value);
+        }
+        (Value::Class(_, fields), Part::Key(key)) => {
+            fields.borrow_mut().insert(key.clone(), value);
+        }
+        (Value::Struct(name, fields), Part::Key(key)) => {
+            let mut fields = fields.borrow_mut();
+            let Some((_, field)) = fields.iter_mut().find(|(field, _)| field == key) else {
+                return Err(format!(
+                    "struct '{}' has no field '{}'",
+                    name, key
+                ));
+            };
+            *field = value;
+        }
+        (Value::List(items), Part::Index(index)) => {
+            let mut items = items.borrow_mut();
+            let len = items.len();
+            match position(*index, len) {
+                Some(i) => items[i] = value,
+                None if *index == len as i64 => items.push(value),
+                None => {
+                    return Err(format!(
+                        "index {} is out of range for '{}' (length {})",
+                        index,
+                        describe(&path[..path.len() - 1]),
+                        len
+                    ))
+                }
+            }
+        }
+        (Value::List(_), Part::Key(key)) if key.parse::<i64>().is_ok() => {
+            let index = Part::Index(key.parse().unwrap_or_default());
+            return assign(container, &index, value, path);
+        }
+        _ => {
+            return Err(format!(
+                "cannot set '{}' on a {}",
+                describe(path),
+                container.type_name()
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// パスの文字列 ("a.b[0]"、"." や "[" を含むキーは ["a.b"])
+pub(crate) fn describe(parts: &[Part]) -> String {
+    let mut text = String::new();
+    for part in parts {
+        match part {
+            Part::Key(key) if key.is_empty() || key.contains(['.', '[', ']', '"']) => {
+                text.push_str(&format!("[\"{}\"]", key))
+            }
+            Part::Key(key) if text.is_empty() => text.push_str(key),
+            Part::Key(key) => text.push_str(&format!(".{}", key)),
+            Part::Index(index) => text.push_str(&format!("[{}]", index)),
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::run;
+
+    #[test]
+    fn test_parse() {
+        let key = |k: &str| Part::Key(k.to_string());
+        assert_eq!(
+            parse("a.b[0][-1].c").unwrap(),
+            vec![
+                key("a"),
+                key("b"),
+                Part::Index(0),
+                Part::Index(-1),
+                key("c")
+            ]
+        );
+        assert_eq!(parse("[\"x.y\"].z").unwrap(), vec![key("x.y"), key("z")]);
+        assert_eq!(parse("").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for path in ["a..b", ".a", "a.", "a[x]", "a[0", "a[0]b"] {
+            assert_eq!(
+                parse(path).unwrap_err(),
+                format!("Invalid json path '{}'", path)
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_and_set() {
+        let source = "let data = json.parse(`{\"user\": {\"name\": \"ann\", \"tags\": [\"a\", \"b\"], \"bio\": null}}`)
+[json.get(data, \"user.name\"), json.get(data, \"user.tags[-1]\"), json.get(data, \"user.tags.0\"), json.get(data, \"user.bio\", \"-\"), json.get(data, \"user.address.city\", \"?\"), json.get(data, \"user.name.first\"), json.get(none, \"a\", 0)]
+";
+        assert_eq!(run(source).unwrap(), "[ann, b, a, -, ?, none, 0]");
+
+        let source = "let data = {\"user\": {\"tags\": [\"a\"]}}
+json.set(data, \"user.tags[1]\", \"b\")
+json.set(data, \"user.tags[0]\", \"z\")
+json.set(data, \"user.address.city\", \"Tokyo\")
+json.set(data, \"items[0].id\", 7)
+[data[\"user\"][\"tags\"], data[\"user\"][\"address\"][\"city\"], json.set({}, \"a\", 1), json.get(data, \"items[0].id\")]
+";
+        assert_eq!(run(source).unwrap(), "[[z, b], Tokyo, {a: 1}, 7]");
+    }
+
+    #[test]
+    fn test_get_and_set_errors() {
+        assert_eq!(
+            run("json.set({\"a\": 1}, \"a.b\", 2)\n").unwrap_err(),
+            "json.set(): 'a' is a Int, not a dict or a list"
+        );
+        assert_eq!(
+            run("json.set({\"a\": []}, \"a[2]\", 1)\n").unwrap_err(),
+            "json.set(): index 2 is out of range for 'a' (length 0)"
+        );
+        assert_eq!(
+            run("json.get({}, \"a..b\")\n").unwrap_err(),
+            "Invalid json path 'a..b'"
+        );
+        assert_eq!(
+            run("json.set({}, \"\", 1)\n").unwrap_err(),
+            "json.set() expects a non-empty path"
+        );
+    }
+}
@@ -1,5 +1,3 @@
-#![allow(dead_code)]
-#![allow(unused_variables)]
 //! JSX Rendering for n7tya-lang
 //!
 //! JSX要素をHTML文字列に変換するレンダラ
@@ -7,35 +5,180 @@
 use crate::ast::*;
 use crate::interpreter::{Interpreter, Value};
 
+/// 描画モード
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenderMode {
+    /// ブラウザ向けの通常の HTML
+    Page,
+    /// メールクライアント向け (スタイルをインライン化し、使えないタグを除く)
+    Email,
+}
+
+/// メールでは描画しないタグ (中身ごと除く)
+const EMAIL_DROPPED_TAGS: &[&str] = &[
+    "script", "style", "link", "meta", "noscript", "iframe", "object", "embed", "video", "audio",
+    "canvas", "svg", "form", "input", "select", "textarea", "button",
+];
+
+/// メールクライアントによっては解釈されないので div にするタグ
+const EMAIL_BLOCK_TAGS: &[&str] = &[
+    "header", "footer", "nav", "section", "article", "main", "aside",
+];
+
+/// メールでタグごとに先に付けておくスタイル (要素自身の style が後に来るので上書きできる)
+const EMAIL_TAG_STYLES: &[(&str, &str)] = &[
+    (
+        "h1",
+        "margin: 0 0 16px; font-size: 24px; line-height: 32px;",
+    ),
+    (
+        "h2",
+        "margin: 0 0 12px; font-size: 20px; line-height: 28px;",
+    ),
+    ("h3", "margin: 0 0 8px; font-size: 16px; line-height: 24px;"),
+    ("p", "margin: 0 0 16px; font-size: 16px; line-height: 24px;"),
+    ("a", "color: #1a73e8; text-decoration: underline;"),
+    ("img", "border: 0; display: block; max-width: 100%;"),
+    ("table", "border-collapse: collapse;"),
+    ("td", "vertical-align: top;"),
+];
+
+/// 子要素を持たないタグ
+const VOID_TAGS: &[&str] = &["img", "br", "hr", "input", "meta", "link"];
+
 /// JSX要素をHTMLに変換
 pub fn render_jsx(element: &JsxElement, interpreter: &mut Interpreter) -> Result<String, String> {
+    let mode = interpreter.render_mode();
+
+    // 大文字で始まる定義済みのコンポーネントはその render の結果に置き換える
+    if element.tag.starts_with(char::is_uppercase) && interpreter.has_component(&element.tag) {
+        return interpreter.render_component_named(&element.tag);
+    }
+    // 同名のコンポーネントがなければ <Router> は組み込みのルーター
+    if element.tag == "Router" {
+        return crate::router::render_router(element, interpreter);
+    }
+    // <Flash /> は flash.set で保存したメッセージ
+    if element.tag == "Flash" {
+        return crate::flash::render_flash(interpreter);
+    }
+
+    let mut tag = element.tag.as_str();
+    if mode == RenderMode::Email {
+        if EMAIL_DROPPED_TAGS.contains(&tag) {
+            return Ok(String::new());
+        }
+        if EMAIL_BLOCK_TAGS.contains(&tag) {
+            tag = "div";
+        }
+    }
+
     let mut html = String::new();
 
     // 開始タグ
-    html.push_str(&format!("<{}", element.tag));
+    html.push_str(&format!("<{}", tag));
 
     // 属性
+    let mut style = match mode {
+        RenderMode::Email => EMAIL_TAG_STYLES
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, s)| s.to_string())
+            .unwrap_or_default(),
+        RenderMode::Page => String::new(),
+    };
+    // <form bind={User} values={user}> は構造体のフィールドから入力欄を作る
+    let mut bind = None;
+    let mut bind_values = None;
     for attr in &element.attributes {
-        let value_str = if let Some(expr) = &attr.value {
-            match eval_jsx_expression(expr, interpreter)? {
-                Value::Str(s) => s,
-                v => v.display(),
+        // メールではスクリプトが動かないのでイベントハンドラは出力しない
+        if mode == RenderMode::Email && attr.name.starts_with("on") {
+            continue;
+        }
+        let value = match &attr.value {
+            Some(expr) => eval_jsx_expression(expr, interpreter)?,
+            None => Value::Str("true".into()), // Boolean attribute
+        };
+        // onclick={method} は island のクライアント JS がイベントをつなぐ目印にする
+        if let (Some(event), Value::Fn(method, _)) = (attr.name.strip_prefix("on"), &value) {
+            html.push_str(&format!(
+                " data-on-{}=\"{}\"",
+                event.to_ascii_lowercase(),
+                escape_html(&method.name)
+            ));
+            continue;
+        }
+        if tag == "form" && attr.name == "bind" {
+            bind = Some(value);
+            continue;
+        }
+        if tag == "form" && attr.name == "values" {
+            bind_values = Some(value);
+            continue;
+        }
+        if attr.name == "style" {
+            if !style.is_empty() {
+                style.push(' ');
             }
-        } else {
-            "true".to_string() // Boolean attribute
+            style.push_str(&style_text(&value));
+            continue;
+        }
+        let value_str = match value {
+            Value::Str(s) => s.to_string(),
+            v => v.display(),
         };
         html.push_str(&format!(" {}=\"{}\"", attr.name, escape_html(&value_str)));
     }
+    if !style.is_empty() {
+        html.push_str(&format!(" style=\"{}\"", escape_html(&style)));
+    }
+    if let Some(bind) = bind {
+        let def = match &bind {
+            Value::BuiltinFn(handle) => handle
+                .strip_prefix("__struct_")
+                .and_then(|name| interpreter.struct_def(name)),
+            _ => None,
+        }
+        .ok_or_else(|| format!("<form bind> expects a struct, got {}", bind.display()))?;
+        if !element.attributes.iter().any(|a| a.name == "method") {
+            html.push_str(" method=\"post\"");
+        }
+        html.push('>');
+        html.push_str(&crate::forms::render_fields(&def, bind_values.as_ref())?);
+        if let Some(token) = interpreter.request_csrf_token() {
+            html.push_str(&format!(
+                "<input type=\"hidden\" name=\"{}\" value=\"{}\" />",
+                crate::csrf::FIELD,
+                token
+            ));
+        }
+        // 送信ボタンを書いていなければ既定のものを付ける
+        if element.children.is_empty() {
+            html.push_str("<button type=\"submit\">Submit</button>");
+        }
+        html.push_str(&render_children(element, interpreter)?);
+        html.push_str("</form>");
+        return Ok(html);
+    }
 
-    // 子要素がない場合は自己閉じタグ
-    if element.children.is_empty() {
+    // 子要素がない場合は自己閉じタグ (メールでは空要素以外は閉じタグを書く)
+    if element.children.is_empty() && (mode == RenderMode::Page || VOID_TAGS.contains(&tag)) {
         html.push_str(" />");
         return Ok(html);
     }
 
     html.push('>');
+    html.push_str(&render_children(element, interpreter)?);
+
+    // 閉じタグ
+    html.push_str(&format!("</{}>", tag));
 
-    // 子要素
+    Ok(html)
+}
+
+/// 子要素を順に描画する
+fn render_children(element: &JsxElement, interpreter: &mut Interpreter) -> Result<String, String> {
+    let mut html = String::new();
     for child in &element.children {
         match child {
             JsxChild::Element(child_elem) => {
@@ -50,41 +193,30 @@ pub fn render_jsx(element: &JsxElement, interpreter: &mut Interpreter) -> Result
             }
         }
     }
-
-    // 閉じタグ
-    html.push_str(&format!("</{}>", element.tag));
-
     Ok(html)
 }
 
 /// JSX内の式を評価
 fn eval_jsx_expression(expr: &Expression, interpreter: &mut Interpreter) -> Result<Value, String> {
-    // Interpreterの eval_expression はprivateだが、公開メソッドやリフレクションは使えない
-    // 解決策: Interpreterに `eval_jsx_expr_public` のようなメソッドを追加するか、
-    // ここで部分的に評価するか。
-    // しかし `interpreter` は `&mut Interpreter` なので、メソッドを呼べばOK。
-    // ただし `eval_expression` は private なので、pubにするか、`eval_expr_public` を作る必要がある。
-    // ここでは `eval_expression` が private である前提で、Interpreterに `pub fn eval_expr(&mut self, e: &Expression)` を追加したと仮定してそれを呼ぶべき。
-    // 現状 `interpreter.rs` の `eval_expression` は private なので、pubに変更する修正が必要。
-
-    // 一旦、修正済みの `interpreter.rs` で `pub` になっていることを期待して呼び出す、
-    // または `interpreter` 自体に評価メソッドを追加する。
-    // ここでは `interpreter.eval_expr_public` を呼ぶ形にする。
-
-    // しかし Rustの可視性ルールでコンパイルエラーになるため、
-    // interpreter.rs 側で `eval_expression` を `pub(crate)` にするのが正解。
-    // 今回の変更で `eval_expression` 自体を pub(crate) に変更したいが、
-    // replace_file_content で interpreter.rs を修正済みかどうか確認が必要。
-    // 修正していないので、まず interpreter.rs の `eval_expression` を修正する。
-
-    // 仮実装: まだ呼び出せないので、ダミーから変更しないと動かない。
-    // interpreter.rs を修正するステップが必要。
-
-    Err("Initialize logic pending pub(crate) access".to_string())
+    interpreter.eval_expression(expr)
+}
+
+/// style 属性の値を CSS にする (辞書ならキー順に "key: value;" を並べる)
+fn style_text(value: &Value) -> String {
+    match value {
+        Value::Dict(dict) => {
+            crate::interpreter::sorted_entries(&dict.borrow())
+                .into_iter()
+                .map(|(k, v)| format!("{}: {};", k, v.display()))
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
+        v => v.display(),
+    }
 }
 
 /// HTMLエスケープ
-fn escape_html(s: &str) -> String {
+pub(crate) fn escape_html(s: &str) -> String {
     s.replace('&', "&amp;")
         .replace('<', "&lt;")
         .replace('>', "&gt;")
@@ -95,24 +227,9 @@ fn escape_html(s: &str) -> String {
 /// ComponentDefからHTMLを生成
 pub fn render_component(
     component: &ComponentDef,
-    _interpreter: &mut Interpreter,
+    interpreter: &mut Interpreter,
 ) -> Result<String, String> {
-    // コンポーネントのrender部分を見つけてHTMLに変換
-    for item in &component.body {
-        if let ComponentBodyItem::Render(render) = item {
-            // render内の文を評価（JSX要素を探す）
-            for stmt in &render.body {
-                if let Statement::Expression(Expression::JsxElement(jsx)) = stmt {
-                    // ダミーのinterpreterで評価
-                    // コンポーネントのプロパティやステートを渡したいが、
-                    // 現状の簡易実装では新規Envで実行
-                    let mut temp_interpreter = Interpreter::new();
-                    return render_jsx(jsx, &mut temp_interpreter);
-                }
-            }
-        }
-    }
-    Ok("<div>Empty component</div>".to_string())
+    interpreter.render_component(component)
 }
 
 /// フルHTMLページを生成
@@ -141,3 +258,27 @@ pub fn generate_html_page(title: &str, body: &str) -> String {
         body
     )
 }
+
+/// メール用の HTML 文書を生成 (本文は表のレイアウトで中央に置く)
+pub fn generate_email_document(body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="UTF-8">
+<meta name="viewport" content="width=device-width, initial-scale=1.0">
+</head>
+<body style="margin: 0; padding: 0; background: #ffffff;">
+<table role="presentation" width="100%" cellpadding="0" cellspacing="0" border="0">
+<tr>
+<td align="center" style="padding: 24px; font-family: Arial, Helvetica, sans-serif; color: #222222;">
+{}
+</td>
+</tr>
+</table>
+</body>
+</html>
+"#,
+        body
+    )
+}
@@ -36,7 +36,7 @@ fn process_string_escapes(s: &str) -> String {
 
 /// トークンの種類
 #[derive(Logos, Debug, Clone, PartialEq)]
-#[logos(skip r"[ \r]")] // 単一のスペースとCRはスキップ（4スペース or タブはインデントとして認識）
+#[logos(skip r"[\t \r]+")] // 空白は捨てる (行頭のインデントは tokenize が Indent / Dedent にする)
 pub enum Token {
     // ===== キーワード =====
     #[token("def")]
@@ -47,6 +47,8 @@ pub enum Token {
     Let,
     #[token("const")]
     Const,
+    #[token("nonlocal")]
+    Nonlocal,
     #[token("if")]
     If,
     #[token("else")]
@@ -69,6 +71,10 @@ pub enum Token {
     Class,
     #[token("struct")]
     Struct,
+    #[token("interface")]
+    Interface,
+    #[token("implements")]
+    Implements,
     #[token("enum")]
     Enum,
     #[token("match")]
@@ -107,22 +113,14 @@ pub enum Token {
     Component,
     #[token("server")]
     Server,
-    #[token("route")]
-    Route,
-    #[token("test")]
-    Test,
     #[token("assert")]
     Assert,
     #[token("self")]
     SelfKw,
     #[token("super")]
     Super,
-    #[token("render")]
-    Render,
-    #[token("state")]
-    State,
-    #[token("props")]
-    Props,
+    #[token("static")]
+    Static,
 
     // ===== リテラル =====
     #[regex(r"[0-9]+", |lex| lex.slice().parse::<i64>().ok())]
@@ -131,11 +129,23 @@ pub enum Token {
     #[regex(r"[0-9]+\.[0-9]+", |lex| lex.slice().parse::<f64>().ok())]
     FloatLiteral(f64),
 
-    // 文字列リテラル (エスケープシーケンス対応)
+    // 5s / 200ms / 1.5h (単位は ms, s, m, h, d)
+    #[regex(r"[0-9]+(\.[0-9]+)?(ms|s|m|h|d)", |lex| crate::units::parse_duration(lex.slice()))]
+    DurationLiteral(std::time::Duration),
+
+    // 10mb / 512b (単位は b, kb, mb, gb, tb)
+    #[regex(r"[0-9]+(\.[0-9]+)?(b|kb|mb|gb|tb)", |lex| crate::units::parse_size(lex.slice()))]
+    SizeLiteral(u64),
+
+    // 文字列リテラル (エスケープシーケンス対応、'...' は1行のみ)
     #[regex(r#""([^"\\]|\\.)*""#, |lex| {
         let s = lex.slice();
         Some(process_string_escapes(&s[1..s.len()-1]))
     })]
+    #[regex(r#"'([^'\\\n]|\\.)*'"#, |lex| {
+        let s = lex.slice();
+        Some(process_string_escapes(&s[1..s.len()-1]))
+    })]
     StringLiteral(String),
 
     // 複数行文字列リテラル (バッククォート)
@@ -184,6 +194,12 @@ pub enum Token {
     Dot,
     #[token("..")]
     DotDot,
+    #[token("?")]
+    Question,
+    #[token("|")]
+    Pipe,
+    #[token("@")]
+    At,
 
     // ===== 括弧 =====
     #[token("(")]
@@ -200,21 +216,28 @@ pub enum Token {
     RBrace,
 
     // ===== JSX =====
-    // < と > は Lt/Gt を再利用
+    // 要素の中は JSX モードで読む (Lexer::jsx_element)。> は Gt を再利用
+    /// 要素の始まりの `<` (比較の `<` は Lt)
+    OpenTag,
     #[token("/>")]
     SelfClose,
     #[token("</")]
     CloseTag,
+    /// 要素の中のテキスト (空白は整えたもの)
+    JsxText(String),
 
     // ===== インデント・改行 =====
-    #[regex(r"\t|    ")]
-    Tab,
+    /// インデントが前の行より深くなった (ブロックの始まり)
+    Indent,
+    /// インデントが浅くなった (閉じるブロック1つごとに1つ)
+    Dedent,
     #[token("\n")]
     Newline,
 
     // ===== コメント =====
-    #[regex(r"#[^\n]*", logos::skip)]
-    Comment,
+    // 行コメントはトークンとして残し、行末コメントは直後の改行に付与する (フォーマッタ用)
+    #[regex(r"#[^\n]*", |lex| lex.slice()[1..].to_string())]
+    Comment(String),
 
     // ===== エラー =====
     Error,
@@ -227,6 +250,8 @@ pub struct TokenInfo {
     pub span: std::ops::Range<usize>,
     pub line: usize,
     pub column: usize,
+    /// 行末コメント (Newline トークンにのみ付与される)
+    pub trailing_comment: Option<String>,
 }
 
 /// Lexer構造体
@@ -235,6 +260,142 @@ pub struct Lexer<'a> {
     source: &'a str,
     line: usize,
     line_start: usize,
+    indent_unit: usize,
+    jsx_depth: usize, // 読んでいる JSX の要素の入れ子の深さ
+}
+
+/// ファイル内で最初にスペースでインデントされた行から、1段あたりのスペース数を求める
+/// (スペースインデントがなければ 4)
+pub fn detect_indent_unit(source: &str) -> usize {
+    source
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start_matches(' ').len())
+        .find(|&spaces| spaces > 0)
+        .unwrap_or(4)
+}
+
+/// JSX のテキストの空白を整える (React と同じく、改行を含む前後の空白は捨て、行どうしは空白1つでつなぐ)
+fn jsx_text(raw: &str) -> String {
+    let lines: Vec<&str> = raw.split('\n').collect();
+    let last = lines.len() - 1;
+    let mut parts = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let mut line = line.trim_end_matches('\r');
+        if i > 0 {
+            line = line.trim_start();
+        }
+        if i < last {
+            line = line.trim_end();
+        }
+        if !line.is_empty() {
+            parts.push(line);
+        }
+    }
+    parts.join(" ")
+}
+
+/// タグ名・属性名に使える文字 (data-id や aria-label、xlink:href も書ける)
+fn is_jsx_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | ':' | '.')
+}
+
+/// 行頭の空白をインデント段数に換算する (タブは1段、スペースは unit 個で1段)
+pub fn indent_depth(whitespace: &str, unit: usize) -> usize {
+    let tabs = whitespace.chars().filter(|&c| c == '\t').count();
+    let spaces = whitespace.chars().filter(|&c| c == ' ').count();
+    tabs + spaces / unit.max(1)
+}
+
+/// 行頭のインデントを Indent / Dedent にするための状態
+///
+/// 空行とコメントだけの行はインデントを変えない。ブロックの変わり目に並んだ空行とコメント行は、
+/// コメントの段数で内側と外側に分け、その境目に Indent / Dedent を置く。
+#[derive(Default)]
+struct Layout {
+    /// 開いているブロックのインデントの段数 (外側から順に。トップレベルの 0 は含めない)
+    indents: Vec<usize>,
+    /// 開いている括弧の数 (括弧の中の行はインデントを比べない)
+    brackets: usize,
+    /// 直前のコードの行を終えた位置 (ここから空行とコメント行が続く)
+    run_start: usize,
+    /// run_start 以降のコメント行の (行を終えた位置, 段数)
+    comments: Vec<(usize, usize)>,
+    /// 読んでいる行がコメント行ならその段数
+    comment: Option<usize>,
+    /// 読んでいる行がコードの行か
+    code: bool,
+}
+
+impl Layout {
+    /// コードの行の最初のトークンの前で、インデントの変化を Indent / Dedent にする
+    ///
+    /// 前の行より浅く、外側のどのブロックとも段数が合わない行は、いまのブロックの続きとして読む。
+    fn code_line(&mut self, depth: usize, at: &TokenInfo, tokens: &mut Vec<TokenInfo>) {
+        let top = self.indents.last().copied().unwrap_or(0);
+        let mut inserts = Vec::new();
+        if depth > top {
+            // ブロックの先頭のコメント行 (いまの段数より深いもの) はブロックに入れる
+            let position = self
+                .comments
+                .iter()
+                .filter(|(_, d)| *d <= top)
+                .map(|(end, _)| *end)
+                .max()
+                .unwrap_or(self.run_start);
+            inserts.push((position, Token::Indent));
+            self.indents.push(depth);
+        }
+        while let Some(&inner) = self.indents.last() {
+            if depth >= inner {
+                break;
+            }
+            let outer = self
+                .indents
+                .len()
+                .checked_sub(2)
+                .map_or(0, |i| self.indents[i]);
+            if depth > outer {
+                *self.indents.last_mut().unwrap() = depth;
+                break;
+            }
+            self.indents.pop();
+            // 閉じるブロックの末尾のコメント行はブロックに残す
+            // (外側より深いものと、次の行より浅くてどちらにも揃っていないもの)
+            let position = self
+                .comments
+                .iter()
+                .filter(|(_, d)| *d > outer || *d < depth)
+                .map(|(end, _)| *end)
+                .max()
+                .unwrap_or(self.run_start);
+            inserts.push((position, Token::Dedent));
+        }
+        // 後ろの位置から入れると、前の位置はずれない
+        for (position, token) in inserts.into_iter().rev() {
+            let info = match tokens.get(position) {
+                Some(next) => TokenInfo {
+                    span: next.span.start..next.span.start,
+                    line: next.line,
+                    column: next.column,
+                    ..at.clone()
+                },
+                None => at.clone(),
+            };
+            tokens.insert(position, TokenInfo { token, ..info });
+        }
+        self.comments.clear();
+        self.code = true;
+    }
+
+    /// 括弧の外の Newline を入れた直後に呼ぶ (end は Newline の次の位置)
+    fn end_line(&mut self, end: usize) {
+        if std::mem::take(&mut self.code) {
+            self.run_start = end;
+        } else if let Some(depth) = self.comment.take() {
+            self.comments.push((end, depth));
+        }
+    }
 }
 
 impl<'a> Lexer<'a> {
@@ -244,11 +405,16 @@ impl<'a> Lexer<'a> {
             source,
             line: 1,
             line_start: 0,
+            indent_unit: detect_indent_unit(source),
+            jsx_depth: 0,
         }
     }
 
     pub fn tokenize(&mut self) -> Vec<TokenInfo> {
         let mut tokens: Vec<TokenInfo> = Vec::new();
+        let mut trailing_comment: Option<String> = None;
+        let mut layout = Layout::default();
+        let mut at_line_start = true;
 
         while let Some(result) = self.inner.next() {
             let span = self.inner.span();
@@ -259,30 +425,65 @@ impl<'a> Lexer<'a> {
                 Err(_) => Token::Error,
             };
 
-            // タブ(空白)処理: 行頭以外のタブは無視する
-            if matches!(token, Token::Tab) {
-                let is_at_start_of_line = if let Some(last) = tokens.last() {
-                    matches!(last.token, Token::Newline)
-                } else {
-                    true // ファイル先頭
-                };
-
-                if !is_at_start_of_line {
+            // コメント処理: 行頭ならトークンとして残し、
+            // 文の後ろにある行末コメントは次の Newline に付与する
+            if let Token::Comment(text) = &token {
+                if !at_line_start {
+                    trailing_comment = Some(text.clone());
                     continue;
                 }
             }
 
-            // 改行時に行番号を更新
+            // 行の最初のトークンで、行頭の空白の段数を前の行と比べる (空行と括弧の中の行は比べない)
+            if at_line_start && !matches!(token, Token::Newline) {
+                at_line_start = false;
+                if layout.brackets == 0 {
+                    let depth =
+                        indent_depth(&self.source[self.line_start..span.start], self.indent_unit);
+                    if matches!(token, Token::Comment(_)) {
+                        layout.comment = Some(depth);
+                    } else {
+                        let at = TokenInfo {
+                            token: Token::Indent,
+                            span: span.start..span.start,
+                            line: self.line,
+                            column,
+                            trailing_comment: None,
+                        };
+                        layout.code_line(depth, &at, &mut tokens);
+                    }
+                }
+            }
+
+            match token {
+                Token::LParen | Token::LBracket | Token::LBrace => layout.brackets += 1,
+                Token::RParen | Token::RBracket | Token::RBrace => {
+                    layout.brackets = layout.brackets.saturating_sub(1)
+                }
+                _ => {}
+            }
+
+            // 式を書ける位置の `<タグ名` からは要素の終わりまで JSX モードで読む
+            if matches!(token, Token::Lt) && self.starts_jsx(&tokens, span.start) {
+                let end = self.jsx_element(span.start, &mut tokens);
+                self.inner = Token::lexer(self.source);
+                self.inner.bump(end);
+                continue;
+            }
+
+            // 改行時に行番号を更新 (空行も Newline として残し、Parser が空行として数える)
             if matches!(token, Token::Newline) {
-                // 連続する空行(Newline -> Newline)の場合、余分なNewlineをスキップするか、
-                // あるいはParser側で処理するか。Parserのparse_indented_blockは空行をスキップする。
-                // ここではそのまま保持。
                 tokens.push(TokenInfo {
                     token,
                     span: span.clone(),
                     line: self.line,
                     column,
+                    trailing_comment: trailing_comment.take(),
                 });
+                if layout.brackets == 0 {
+                    layout.end_line(tokens.len());
+                }
+                at_line_start = true;
                 self.line += 1;
                 self.line_start = span.end;
                 continue;
@@ -293,11 +494,241 @@ impl<'a> Lexer<'a> {
                 span,
                 line: self.line,
                 column,
+                trailing_comment: None,
             });
         }
 
+        // 最後の行が改行で終わっていなくても Newline で終える (行末コメントも失わない)
+        let end = self.source.len();
+        let eof = TokenInfo {
+            token: Token::Newline,
+            span: end..end,
+            line: self.line,
+            column: end - self.line_start + 1,
+            trailing_comment,
+        };
+        if !at_line_start || eof.trailing_comment.is_some() {
+            tokens.push(eof.clone());
+            layout.end_line(tokens.len());
+        }
+        // 開いているブロックをすべて閉じる
+        layout.code_line(0, &eof, &mut tokens);
+
         tokens
     }
+
+    /// at の `<` が JSX の要素の始まりか (直後がタグ名で、直前が値の終わりではない)
+    ///
+    /// `a < b` や `List<Int>` の `<` は直前が識別子なので比較や型引数になる。
+    fn starts_jsx(&self, tokens: &[TokenInfo], at: usize) -> bool {
+        let tag = self.source[at + 1..].starts_with(|c: char| c.is_ascii_alphabetic());
+        let after_value = matches!(
+            tokens.last().map(|t| &t.token),
+            Some(
+                Token::Identifier(_)
+                    | Token::IntLiteral(_)
+                    | Token::FloatLiteral(_)
+                    | Token::DurationLiteral(_)
+                    | Token::SizeLiteral(_)
+                    | Token::StringLiteral(_)
+                    | Token::MultiLineString(_)
+                    | Token::True
+                    | Token::False
+                    | Token::None
+                    | Token::SelfKw
+                    | Token::Super
+                    | Token::RParen
+                    | Token::RBracket
+                    | Token::RBrace
+                    | Token::Question
+                    | Token::SelfClose
+            )
+        );
+        tag && !after_value
+    }
+
+    fn push(&self, tokens: &mut Vec<TokenInfo>, token: Token, span: std::ops::Range<usize>) {
+        tokens.push(TokenInfo {
+            token,
+            column: span.start - self.line_start + 1,
+            span,
+            line: self.line,
+            trailing_comment: None,
+        });
+    }
+
+    /// from..to にある改行のぶん行番号を進める
+    fn skip_lines(&mut self, from: usize, to: usize) {
+        for (i, c) in self.source[from..to].char_indices() {
+            if c == '\n' {
+                self.line += 1;
+                self.line_start = from + i + 1;
+            }
+        }
+    }
+
+    /// pos から空白を読み飛ばした位置
+    fn skip_whitespace(&mut self, pos: usize) -> usize {
+        let rest = &self.source[pos..];
+        let end = pos + rest.len() - rest.trim_start().len();
+        self.skip_lines(pos, end);
+        end
+    }
+
+    /// pos から名前を読んで Identifier にする (名前がなければ pos のまま)
+    fn jsx_name(&mut self, pos: usize, tokens: &mut Vec<TokenInfo>) -> usize {
+        let rest = &self.source[pos..];
+        let len = rest.find(|c| !is_jsx_name_char(c)).unwrap_or(rest.len());
+        if len > 0 {
+            self.push(
+                tokens,
+                Token::Identifier(rest[..len].to_string()),
+                pos..pos + len,
+            );
+        }
+        pos + len
+    }
+
+    /// start の `<` から要素の終わりまでを読み、読み終えた位置を返す
+    ///
+    /// 属性の値とテキストはそのまま読み、`{...}` の中だけを通常のトークンとして読む。
+    fn jsx_element(&mut self, start: usize, tokens: &mut Vec<TokenInfo>) -> usize {
+        // 深すぎる入れ子は残りをエラーにする (構文解析が入れ子の上限のエラーを報告する)
+        if self.jsx_depth > crate::parser::MAX_NESTING_DEPTH {
+            self.push(tokens, Token::Error, start..self.source.len());
+            return self.source.len();
+        }
+        self.jsx_depth += 1;
+        let end = self.jsx_element_body(start, tokens);
+        self.jsx_depth -= 1;
+        end
+    }
+
+    fn jsx_element_body(&mut self, start: usize, tokens: &mut Vec<TokenInfo>) -> usize {
+        self.push(tokens, Token::OpenTag, start..start + 1);
+        let mut pos = self.jsx_name(start + 1, tokens);
+
+        // 属性
+        loop {
+            pos = self.skip_whitespace(pos);
+            let rest = &self.source[pos..];
+            if rest.is_empty() {
+                return pos;
+            } else if rest.starts_with("/>") {
+                self.push(tokens, Token::SelfClose, pos..pos + 2);
+                return pos + 2;
+            } else if rest.starts_with('>') {
+                self.push(tokens, Token::Gt, pos..pos + 1);
+                pos += 1;
+                break;
+            } else if rest.starts_with('=') {
+                self.push(tokens, Token::Assign, pos..pos + 1);
+                pos += 1;
+            } else if rest.starts_with('{') {
+                pos = self.jsx_code(pos, tokens);
+            } else if let Some(quote) = rest.chars().next().filter(|c| matches!(c, '"' | '\'')) {
+                // 閉じていない引用符は行末までを値にする
+                let body = &rest[1..];
+                let len = body.find(quote).unwrap_or(body.len());
+                let value = process_string_escapes(&body[..len]);
+                let end = (pos + 1 + len + 1).min(self.source.len());
+                self.push(tokens, Token::StringLiteral(value), pos..end);
+                self.skip_lines(pos, end);
+                pos = end;
+            } else {
+                let end = self.jsx_name(pos, tokens);
+                if end == pos {
+                    let len = rest.chars().next().map_or(1, char::len_utf8);
+                    self.push(tokens, Token::Error, pos..pos + len);
+                    pos += len;
+                } else {
+                    pos = end;
+                }
+            }
+        }
+
+        // 子要素とテキスト (`<` はタグ名か `/` が続くときだけタグとみなす)
+        loop {
+            let rest = &self.source[pos..];
+            if rest.is_empty() {
+                return pos;
+            } else if rest.starts_with("</") {
+                self.push(tokens, Token::CloseTag, pos..pos + 2);
+                pos = self.skip_whitespace(pos + 2);
+                pos = self.jsx_name(pos, tokens);
+                pos = self.skip_whitespace(pos);
+                if self.source[pos..].starts_with('>') {
+                    self.push(tokens, Token::Gt, pos..pos + 1);
+                    pos += 1;
+                }
+                return pos;
+            } else if rest.starts_with('<') && self.starts_jsx(&[], pos) {
+                pos = self.jsx_element(pos, tokens);
+            } else if rest.starts_with('{') {
+                pos = self.jsx_code(pos, tokens);
+            } else {
+                let len = rest
+                    .char_indices()
+                    .skip(1)
+                    .find(|&(i, c)| {
+                        c == '{'
+                            || (c == '<'
+                                && (rest[i + 1..].starts_with('/')
+                                    || self.starts_jsx(&[], pos + i)))
+                    })
+                    .map_or(rest.len(), |(i, _)| i);
+                let text = jsx_text(&rest[..len]);
+                let line = self.line;
+                let line_start = self.line_start;
+                self.skip_lines(pos, pos + len);
+                if !text.is_empty() {
+                    tokens.push(TokenInfo {
+                        token: Token::JsxText(text),
+                        span: pos..pos + len,
+                        line,
+                        column: pos - line_start + 1,
+                        trailing_comment: None,
+                    });
+                }
+                pos += len;
+            }
+        }
+    }
+
+    /// JSX の中の `{...}` を通常のトークンとして読み、閉じ括弧の次の位置を返す
+    /// (改行と空白、コメントは捨てる。中の JSX もまた JSX モードで読む)
+    fn jsx_code(&mut self, start: usize, tokens: &mut Vec<TokenInfo>) -> usize {
+        let mut depth = 0;
+        let mut pos = start;
+        'relex: loop {
+            let mut inner = Token::lexer(&self.source[pos..]);
+            while let Some(result) = inner.next() {
+                let span = pos + inner.span().start..pos + inner.span().end;
+                let token = result.unwrap_or(Token::Error);
+                match token {
+                    Token::Comment(_) => continue,
+                    Token::Newline => {
+                        self.skip_lines(span.start, span.end);
+                        continue;
+                    }
+                    Token::Lt if self.starts_jsx(tokens, span.start) => {
+                        pos = self.jsx_element(span.start, tokens);
+                        continue 'relex;
+                    }
+                    Token::LBrace => depth += 1,
+                    Token::RBrace => depth -= 1,
+                    _ => {}
+                }
+                let (start, end) = (span.start, span.end);
+                self.push(tokens, token, span);
+                self.skip_lines(start, end);
+                if depth == 0 {
+                    return end;
+                }
+            }
+            return self.source.len();
+        }
+    }
 }
 
 #[cfg(test)]
@@ -326,6 +757,163 @@ mod tests {
         assert!(matches!(&tokens[1].token, Token::Identifier(s) if s == "add"));
     }
 
+    #[test]
+    fn test_comment_trivia() {
+        let source = "# header\nlet x = 1 # note\n";
+        let mut lexer = Lexer::new(source);
+        let tokens = lexer.tokenize();
+
+        assert!(matches!(&tokens[0].token, Token::Comment(s) if s == " header"));
+        assert!(matches!(tokens[1].token, Token::Newline));
+        let newline = tokens.last().unwrap();
+        assert!(matches!(newline.token, Token::Newline));
+        assert_eq!(newline.trailing_comment.as_deref(), Some(" note"));
+    }
+
+    #[test]
+    fn test_jsx_mode() {
+        let tokens = |source: &str| -> Vec<Token> {
+            Lexer::new(source)
+                .tokenize()
+                .into_iter()
+                .map(|t| t.token)
+                .collect()
+        };
+        let name = |s: &str| Token::Identifier(s.to_string());
+
+        // 直前が値なら比較と型引数、式を書ける位置なら要素
+        assert_eq!(tokens("a < b")[1], Token::Lt);
+        assert_eq!(tokens("let x: List<Int> = []")[4], Token::Lt);
+        assert_eq!(
+            tokens("x = [<br/>]"),
+            vec![
+                name("x"),
+                Token::Assign,
+                Token::LBracket,
+                Token::OpenTag,
+                name("br"),
+                Token::SelfClose,
+                Token::RBracket,
+                Token::Newline
+            ]
+        );
+
+        // テキストの < や # や ' はそのまま読み、属性名には - を書ける
+        assert_eq!(
+            tokens("<p data-id=\"1\" class={c}>1 < 2 # it's {n}</p>"),
+            vec![
+                Token::OpenTag,
+                name("p"),
+                name("data-id"),
+                Token::Assign,
+                Token::StringLiteral("1".to_string()),
+                name("class"),
+                Token::Assign,
+                Token::LBrace,
+                name("c"),
+                Token::RBrace,
+                Token::Gt,
+                Token::JsxText("1 < 2 # it's ".to_string()),
+                Token::LBrace,
+                name("n"),
+                Token::RBrace,
+                Token::CloseTag,
+                name("p"),
+                Token::Gt,
+                Token::Newline,
+            ]
+        );
+
+        // 改行をまたぐテキストは行ごとに整えて空白1つでつなぎ、行番号は進める
+        let source = "<ul>\n\t<li>a\n\t\tb</li>\n</ul>\nx";
+        let infos = Lexer::new(source).tokenize();
+        assert!(infos
+            .iter()
+            .any(|t| t.token == Token::JsxText("a b".to_string())));
+        let last = &infos[infos.len() - 2];
+        assert_eq!((&last.token, last.line, last.column), (&name("x"), 5, 1));
+    }
+
+    #[test]
+    fn test_indent_and_dedent() {
+        let tokens = |source: &str| -> Vec<Token> {
+            Lexer::new(source)
+                .tokenize()
+                .into_iter()
+                .map(|t| t.token)
+                .collect()
+        };
+        let name = |s: &str| Token::Identifier(s.to_string());
+        use Token::{Dedent, Indent, Newline};
+
+        // 空行はインデントを変えず、閉じるブロックの数だけ Dedent が入る (最後の行にも Newline を補う)
+        assert_eq!(
+            tokens("if a\n    if b\n        c\n\n    d\ne"),
+            vec![
+                Token::If,
+                name("a"),
+                Newline,
+                Indent,
+                Token::If,
+                name("b"),
+                Newline,
+                Indent,
+                name("c"),
+                Newline,
+                Dedent,
+                Newline,
+                name("d"),
+                Newline,
+                Dedent,
+                name("e"),
+                Newline,
+            ]
+        );
+
+        // コメント行は段数で閉じるブロックの内側と外側に分け、括弧の中の行は比べない
+        assert_eq!(
+            tokens("def f\n\t# in\n\tx = [\n1]\n\t# still in\n# out\ny"),
+            vec![
+                Token::Def,
+                name("f"),
+                Newline,
+                Indent,
+                Token::Comment(" in".to_string()),
+                Newline,
+                name("x"),
+                Token::Assign,
+                Token::LBracket,
+                Newline,
+                Token::IntLiteral(1),
+                Token::RBracket,
+                Newline,
+                Token::Comment(" still in".to_string()),
+                Newline,
+                Dedent,
+                Token::Comment(" out".to_string()),
+                Newline,
+                name("y"),
+                Newline,
+            ]
+        );
+
+        // どのブロックとも段数が合わない行はいまのブロックの続き
+        assert_eq!(
+            tokens("if a\n\t\tb\n\tc\n"),
+            vec![
+                Token::If,
+                name("a"),
+                Newline,
+                Indent,
+                name("b"),
+                Newline,
+                name("c"),
+                Newline,
+                Dedent,
+            ]
+        );
+    }
+
     #[test]
     fn test_string_literal() {
         let source = r#"let name = "hello""#;
@@ -0,0 +1,71 @@
+//! n7tya-lang の処理系
+//!
+//! 字句解析・構文解析・型チェック・インタプリタなど。
+//! CLI (main.rs) とベンチマーク (benches/) から使う。
+//! ほかの Rust のプログラムに埋め込むときは engine::Engine を使う。
+//! AST を解析・書き換えるパスは visit::Visitor / visit::MutVisitor を実装して作る。
+//! 名前の定義と参照 (スコープ) は resolve::resolve で求める。
+
+pub mod apidoc;
+pub mod assets;
+pub mod ast;
+pub mod bench;
+pub mod builtins;
+pub mod cache;
+pub mod client_api;
+pub mod config;
+pub mod consteval;
+pub mod content;
+pub mod csrf;
+pub mod css;
+pub mod csv;
+pub mod datetime;
+pub mod decimal;
+pub mod deps;
+pub mod diff;
+pub mod engine;
+pub mod errors;
+pub mod events;
+pub mod flash;
+pub mod formatter;
+pub mod forms;
+pub mod gc;
+pub mod health;
+pub mod hooks;
+pub mod http;
+pub mod i18n;
+pub mod interpreter;
+pub mod islands;
+pub mod iter;
+pub mod jsonpath;
+pub mod jsx_render;
+pub mod lexer;
+pub mod limits;
+pub mod linalg;
+pub mod lint;
+pub mod os;
+pub mod parser;
+pub mod permissions;
+pub mod python;
+pub mod re;
+pub mod refactor;
+pub mod report;
+pub mod resilience;
+pub mod resolve;
+pub mod router;
+pub mod sitemap;
+pub mod sourcemap;
+pub mod sqlite;
+pub mod ssg;
+pub mod stats;
+pub mod syntax;
+pub mod telemetry;
+#[cfg(test)]
+mod testing;
+pub mod toml;
+pub mod typechecker;
+pub mod units;
+pub mod url;
+pub mod validate;
+pub mod visit;
+pub mod yaml;
@@ -0,0 +1,338 @@
+//! 実行の上限 (ステップ数・実行時間・メモリ)
+//!
+//! 文を1つ実行するたびに1ステップと数え、上限を超えたら
+//! "Execution budget exceeded: ..." のエラーで実行を止める。
+//! プログラム全体 (Interpreter::run) と、サーバーのルートの処理1回ごとに数え直す。
+//! 一度超えたあとは次のステップもエラーになるので、エラーを無視する組み込み関数の中でも止まる。
+//! 要素を1つずつ回す組み込み関数 (list, sum, sorted など) と time.sleep・文字列やリストの繰り返しも、
+//! 途中で時間とメモリの上限を確かめる。
+
+use std::time::{Duration, Instant};
+
+/// 上限を超えたときのエラーの先頭 (埋め込む側はこれで見分けられる)
+pub const BUDGET_EXCEEDED: &str = "Execution budget exceeded";
+
+/// 時間とメモリはこのステップ数ごとに確かめる
+const CHECK_INTERVAL: u64 = 256;
+/// メモリは読むのに時間がかかるので、さらに間をあけて確かめる
+const MEMORY_CHECK_INTERVAL: u64 = CHECK_INTERVAL * 16;
+
+/// 実行の上限 (None の項目は制限しない)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    pub steps: Option<u64>,
+    pub time: Option<Duration>,
+    pub memory: Option<u64>, // プロセスの常駐メモリのバイト数 (Linux のみ)
+}
+
+impl Limits {
+    /// `--max-steps=N` `--timeout=秒` `--max-memory=MB` から作る (ほかの引数は無視する)
+    ///
+    /// `--timeout=30s` `--max-memory=512mb` のように単位をつけてもよい。
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut limits = Self::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) => (flag, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            if !matches!(flag, "--max-steps" | "--timeout" | "--max-memory") {
+                continue;
+            }
+            let value = match inline {
+                Some(value) => value,
+                None => args
+                    .next()
+                    .ok_or_else(|| format!("{} expects a number", flag))?,
+            };
+            match flag {
+                "--max-steps" => limits.steps = Some(parse_number(flag, value)?),
+                "--timeout" => limits.time = Some(parse_seconds(flag, value)?),
+                _ => limits.memory = Some(parse_megabytes(flag, value)?),
+            }
+        }
+        Ok(limits)
+    }
+
+    /// other で指定してある項目を other の値にしたもの
+    pub fn or(self, other: Limits) -> Limits {
+        Limits {
+            steps: other.steps.or(self.steps),
+            time: other.time.or(self.time),
+            memory: other.memory.or(self.memory),
+        }
+    }
+}
+
+fn parse_number(flag: &str, value: &str) -> Result<u64, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|n| *n > 0)
+        .ok_or_else(|| format!("{} expects a positive number, got '{}'", flag, value))
+}
+
+/// 単位のない数は MB
+fn parse_megabytes(flag: &str, value: &str) -> Result<u64, String> {
+    match crate::units::parse_size(value) {
+        Some(bytes) if bytes > 0 => Ok(bytes),
+        Some(_) => Err(format!("{} expects a positive size, got '{}'", flag, value)),
+        None => Ok(parse_number(flag, value)? * 1024 * 1024),
+    }
+}
+
+/// 単位のない数は秒
+fn parse_seconds(flag: &str, value: &str) -> Result<Duration, String> {
+    crate::units::parse_duration(value)
+        .or_else(|| {
+            let secs = value.parse::<f64>().ok()?;
+            Duration::try_from_secs_f64(secs).ok()
+        })
+        .filter(|duration| !duration.is_zero())
+        .ok_or_else(|| {
+            format!(
+                "{} expects a positive number of seconds, got '{}'",
+                flag, value
+            )
+        })
+}
+
+/// 実行中に使ったステップ数と時間
+#[derive(Debug)]
+pub struct Budget {
+    limits: Limits,
+    steps: u64,
+    ticks: u64, // 組み込み関数の中で回した要素の数 (ステップには数えない)
+    started: Instant,
+}
+
+impl Budget {
+    pub fn start(limits: Limits) -> Self {
+        Self {
+            limits,
+            steps: 0,
+            ticks: 0,
+            started: Instant::now(),
+        }
+    }
+
+    /// 1ステップ使う (上限を超えていればエラー)
+    pub fn step(&mut self) -> Result<(), String> {
+        self.steps += 1;
+        if let Some(max) = self.limits.steps {
+            if self.steps > max {
+                return Err(exceeded(format!("more than {} steps", max)));
+            }
+        }
+        self.check_every(self.steps)
+    }
+
+    /// 組み込み関数の中で要素を1つ回す (ステップには数えず、ときどき時間とメモリを確かめる)
+    pub fn tick(&mut self) -> Result<(), String> {
+        self.ticks += 1;
+        self.check_every(self.ticks)
+    }
+
+    /// duration だけ待つ (時間の上限を過ぎるなら、上限まで待ってエラーにする)
+    pub fn sleep(&self, duration: Duration) -> Result<(), String> {
+        if let Some(max) = self.limits.time {
+            let remaining = max.saturating_sub(self.started.elapsed());
+            if duration > remaining {
+                std::thread::sleep(remaining);
+                return Err(exceeded(format!("ran longer than {:?}", max)));
+            }
+        }
+        std::thread::sleep(duration);
+        Ok(())
+    }
+
+    /// これから bytes バイトを確保してもメモリの上限を超えないか (小さいものは確かめない)
+    pub fn reserve(&self, bytes: u64) -> Result<(), String> {
+        match self.limits.memory {
+            Some(max) if bytes >= 1 << 20 => {
+                let needed = resident_memory().unwrap_or(0).saturating_add(bytes);
+                if needed > max {
+                    return Err(exceeded(format!(
+                        "needs {} MB of memory (limit {} MB)",
+                        needed / 1024 / 1024,
+                        max / 1024 / 1024
+                    )));
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_every(&self, count: u64) -> Result<(), String> {
+        if !count.is_multiple_of(CHECK_INTERVAL) {
+            return Ok(());
+        }
+        self.check_time()?;
+        if let Some(max) = self.limits.memory {
+            if count.is_multiple_of(MEMORY_CHECK_INTERVAL) {
+                if let Some(used) = resident_memory().filter(|used| used > &max) {
+                    return Err(exceeded(format!(
+                        "using {} MB of memory (limit {} MB)",
+                        used / 1024 / 1024,
+                        max / 1024 / 1024
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_time(&self) -> Result<(), String> {
+        match self.limits.time {
+            Some(max) if self.started.elapsed() > max => {
+                Err(exceeded(format!("ran longer than {:?}", max)))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn exceeded(reason: String) -> String {
+    format!("{}: {}", BUDGET_EXCEEDED, reason)
+}
+
+/// プロセスの常駐メモリ (/proc/self/status の VmRSS、読めなければ None)
+fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn run(limits: Limits, source: &str) -> Result<String, String> {
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_limits(limits);
+        interpreter.run(&program).map(|value| value.display())
+    }
+
+    #[test]
+    fn test_execution_budget() {
+        let steps = Limits {
+            steps: Some(1000),
+            ..Limits::default()
+        };
+        assert_eq!(
+            run(steps, "let n = 0\nwhile true\n    n = n + 1\n").unwrap_err(),
+            "Execution budget exceeded: more than 1000 steps"
+        );
+        assert_eq!(
+            run(steps, "let n = 0\nfor i in range(100)\n    n = n + i\nn\n").unwrap(),
+            "4950"
+        );
+
+        let time = Limits {
+            time: Some(Duration::from_millis(50)),
+            ..Limits::default()
+        };
+        let error = run(time, "while true\n    let x = 1\n").unwrap_err();
+        assert!(error.starts_with("Execution budget exceeded: ran longer than"));
+
+        let args: Vec<String> = ["--max-steps", "10", "--timeout=1.5", "--max-memory=64"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(
+            Limits::from_args(&args).unwrap(),
+            Limits {
+                steps: Some(10),
+                time: Some(Duration::from_millis(1500)),
+                memory: Some(64 * 1024 * 1024),
+            }
+        );
+        let args = ["--timeout=2m".to_string(), "--max-memory=1gb".to_string()];
+        assert_eq!(
+            Limits::from_args(&args).unwrap(),
+            Limits {
+                steps: None,
+                time: Some(Duration::from_secs(120)),
+                memory: Some(1 << 30),
+            }
+        );
+        assert_eq!(
+            Limits::from_args(&["--timeout=0".to_string()]).unwrap_err(),
+            "--timeout expects a positive number of seconds, got '0'"
+        );
+    }
+
+    #[test]
+    fn test_builtins_check_the_budget() {
+        let time = Limits {
+            time: Some(Duration::from_millis(100)),
+            ..Limits::default()
+        };
+        let started = Instant::now();
+        for source in [
+            "time.sleep(8)\n",
+            "sum(map(int, range(2000000000)))\n",
+            "sorted(range(2000000000))\n",
+            "list(map(str, range(2000000000)))\n",
+        ] {
+            let error = run(time, source).unwrap_err();
+            assert!(
+                error.starts_with("Execution budget exceeded: ran longer than"),
+                "{}",
+                error
+            );
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            run(time, "time.sleep(0.01)\nsorted([3, 1, 2])\n").unwrap(),
+            "[1, 2, 3]"
+        );
+
+        let memory = Limits {
+            memory: Some(50 * 1024 * 1024),
+            ..Limits::default()
+        };
+        for source in ["\"ab\" * 400000000\n", "[0] * 400000000\n"] {
+            let error = run(memory, source).unwrap_err();
+            assert!(
+                error.starts_with("Execution budget exceeded: needs"),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn test_route_budget() {
+        let source = "server App\n\tget \"/loop\"\n\t\twhile true\n\t\t\tlet x = 1\n\tget \"/\"\n\t\treturn \"ok\"\n";
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_limits(Limits {
+            steps: Some(100),
+            ..Limits::default()
+        });
+        interpreter.run(&program).unwrap();
+        // 上限を超えたルートは 500 になり、ほかのルートはリクエストごとに数え直して動く
+        let error = interpreter.render_route("App", "/loop").unwrap_err();
+        assert!(error.contains("500 Internal Server Error: Error: Execution budget exceeded"));
+        assert_eq!(interpreter.render_route("App", "/").unwrap().1, "ok");
+
+        let options = crate::interpreter::ServerOptions::from_toml(
+            "[server]\ntimeout = 2.5\nmax_steps = 10000\n",
+        )
+        .unwrap();
+        assert_eq!(options.limits.time, Some(Duration::from_millis(2500)));
+        assert_eq!(options.limits.steps, Some(10000));
+        let options =
+            crate::interpreter::ServerOptions::from_toml("[server]\ntimeout = \"500ms\"\n")
+                .unwrap();
+        assert_eq!(options.limits.time, Some(Duration::from_millis(500)));
+    }
+}
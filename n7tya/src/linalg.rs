@@ -0,0 +1,298 @@
+//! linalg モジュール (ベクトルと行列の計算)
+//!
+//! ベクトルは数値のリスト、行列は同じ長さの行のリストで表す。
+//! 計算結果の要素は Float になる (整数の値なら 2 のように表示される)。
+
+use crate::interpreter::Value;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// これより絶対値が小さいピボットは 0 とみなす (特異行列)
+const EPSILON: f64 = 1e-12;
+
+type Matrix = Vec<Vec<f64>>;
+
+/// linalg.* の関数 (name は "dot" など、"linalg." を除いた名前)
+pub fn call(name: &str, args: Vec<Value>) -> Result<Value, String> {
+    match (name, args.as_slice()) {
+        ("identity", [Value::Int(n)]) if *n >= 0 => Ok(matrix_value(identity(*n as usize))),
+        ("zeros", [Value::Int(rows), Value::Int(cols)]) if *rows >= 0 && *cols >= 0 => {
+            Ok(matrix_value(vec![
+                vec![0.0; *cols as usize];
+                *rows as usize
+            ]))
+        }
+        ("dot", [a, b]) => {
+            let (a, b) = (vector(name, a)?, vector(name, b)?);
+            same_length(name, &a, &b)?;
+            Ok(Value::Float(dot(&a, &b)))
+        }
+        ("cross", [a, b]) => match (&vector(name, a)?[..], &vector(name, b)?[..]) {
+            ([a1, a2, a3], [b1, b2, b3]) => Ok(vector_value(vec![
+                a2 * b3 - a3 * b2,
+                a3 * b1 - a1 * b3,
+                a1 * b2 - a2 * b1,
+            ])),
+            _ => Err("linalg.cross() expects two vectors of length 3".to_string()),
+        },
+        ("norm", [v]) => {
+            let v = vector(name, v)?;
+            Ok(Value::Float(dot(&v, &v).sqrt()))
+        }
+        ("add" | "sub", [a, b]) => {
+            let sign = if name == "add" { 1.0 } else { -1.0 };
+            match (a, b) {
+                (Value::List(items), _)
+                    if matches!(items.borrow().first(), Some(Value::List(_))) =>
+                {
+                    let (a, b) = (matrix(name, a)?, matrix(name, b)?);
+                    if a.len() != b.len() || a.first().map(Vec::len) != b.first().map(Vec::len) {
+                        return Err(format!(
+                            "linalg.{}() expects matrices of the same size",
+                            name
+                        ));
+                    }
+                    let rows = a
+                        .iter()
+                        .zip(&b)
+                        .map(|(a, b)| a.iter().zip(b).map(|(x, y)| x + sign * y).collect())
+                        .collect();
+                    Ok(matrix_value(rows))
+                }
+                _ => {
+                    let (a, b) = (vector(name, a)?, vector(name, b)?);
+                    same_length(name, &a, &b)?;
+                    Ok(vector_value(
+                        a.iter().zip(&b).map(|(x, y)| x + sign * y).collect(),
+                    ))
+                }
+            }
+        }
+        ("scale", [k, x]) => {
+            let k = number(k).ok_or("linalg.scale() expects a number and a vector or matrix")?;
+            match x {
+                Value::List(items) if matches!(items.borrow().first(), Some(Value::List(_))) => {
+                    let rows = matrix(name, x)?
+                        .into_iter()
+                        .map(|row| row.into_iter().map(|v| k * v).collect())
+                        .collect();
+                    Ok(matrix_value(rows))
+                }
+                _ => Ok(vector_value(
+                    vector(name, x)?.into_iter().map(|v| k * v).collect(),
+                )),
+            }
+        }
+        ("transpose", [m]) => Ok(matrix_value(transpose(&matrix(name, m)?))),
+        ("matmul", [a, b]) => {
+            let a = matrix(name, a)?;
+            let inner = a.first().map_or(0, Vec::len);
+            // 右が行列でなくベクトルなら、結果もベクトル
+            if let Ok(v) = vector(name, b) {
+                if v.len() != inner {
+                    return Err(size_mismatch(&a, v.len(), 1));
+                }
+                return Ok(vector_value(a.iter().map(|row| dot(row, &v)).collect()));
+            }
+            let b = matrix(name, b)?;
+            if b.len() != inner {
+                return Err(size_mismatch(&a, b.len(), b.first().map_or(0, Vec::len)));
+            }
+            let columns = transpose(&b);
+            let rows = a
+                .iter()
+                .map(|row| columns.iter().map(|col| dot(row, col)).collect())
+                .collect();
+            Ok(matrix_value(rows))
+        }
+        ("det", [m]) => {
+            let m = square(name, m)?;
+            Ok(Value::Float(eliminate(m).map_or(0.0, |(det, _)| det)))
+        }
+        ("inverse", [m]) => {
+            let m = square(name, m)?;
+            eliminate(m)
+                .map(|(_, inverse)| matrix_value(inverse))
+                .ok_or_else(|| "linalg.inverse(): matrix is singular".to_string())
+        }
+        _ => Err(format!("linalg.{}() got the wrong arguments", name)),
+    }
+}
+
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(n) => Some(*n as f64),
+        Value::Float(f) => Some(*f),
+        _ => None,
+    }
+}
+
+/// 数値のリスト
+fn vector(name: &str, value: &Value) -> Result<Vec<f64>, String> {
+    let error = || format!("linalg.{}() expects a list of numbers", name);
+    let Value::List(items) = value else {
+        return Err(error());
+    };
+    let items = items.borrow();
+    items
+        .iter()
+        .map(|item| number(item).ok_or_else(error))
+        .collect()
+}
+
+/// 同じ長さの行のリスト (空でない)
+fn matrix(name: &str, value: &Value) -> Result<Matrix, String> {
+    let error = || {
+        format!(
+            "linalg.{}() expects a matrix (a list of equal-length rows)",
+            name
+        )
+    };
+    let Value::List(rows) = value else {
+        return Err(error());
+    };
+    let rows = rows
+        .borrow()
+        .iter()
+        .map(|row| vector(name, row).map_err(|_| error()))
+        .collect::<Result<Matrix, String>>()?;
+    let width = rows.first().map_or(0, Vec::len);
+    if width == 0 || rows.iter().any(|row| row.len() != width) {
+        return Err(error());
+    }
+    Ok(rows)
+}
+
+fn square(name: &str, value: &Value) -> Result<Matrix, String> {
+    let m = matrix(name, value)?;
+    if m.len() != m[0].len() {
+        return Err(format!("linalg.{}() expects a square matrix", name));
+    }
+    Ok(m)
+}
+
+fn same_length(name: &str, a: &[f64], b: &[f64]) -> Result<(), String> {
+    if a.len() != b.len() {
+        return Err(format!(
+            "linalg.{}() expects vectors of the same length, got {} and {}",
+            name,
+            a.len(),
+            b.len()
+        ));
+    }
+    Ok(())
+}
+
+fn size_mismatch(a: &Matrix, rows: usize, cols: usize) -> String {
+    format!(
+        "linalg.matmul() cannot multiply a {}x{} matrix by a {}x{} matrix",
+        a.len(),
+        a[0].len(),
+        rows,
+        cols
+    )
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn transpose(m: &Matrix) -> Matrix {
+    let width = m.first().map_or(0, Vec::len);
+    (0..width)
+        .map(|j| m.iter().map(|row| row[j]).collect())
+        .collect()
+}
+
+fn identity(n: usize) -> Matrix {
+    (0..n)
+        .map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+        .collect()
+}
+
+/// ガウス・ジョルダン法で行列式と逆行列を求める (特異行列なら None)
+fn eliminate(mut m: Matrix) -> Option<(f64, Matrix)> {
+    let n = m.len();
+    let mut inverse = identity(n);
+    let mut det = 1.0;
+    for col in 0..n {
+        // 絶対値が最も大きい行をピボットにして誤差を抑える
+        let pivot = (col..n).max_by(|&a, &b| m[a][col].abs().total_cmp(&m[b][col].abs()))?;
+        if m[pivot][col].abs() < EPSILON {
+            return None;
+        }
+        if pivot != col {
+            m.swap(pivot, col);
+            inverse.swap(pivot, col);
+            det = -det;
+        }
+        let p = m[col][col];
+        det *= p;
+        for j in 0..n {
+            m[col][j] /= p;
+            inverse[col][j] /= p;
+        }
+        for row in 0..n {
+            let factor = m[row][col];
+            if row == col || factor == 0.0 {
+                continue;
+            }
+            for j in 0..n {
+                m[row][j] -= factor * m[col][j];
+                inverse[row][j] -= factor * inverse[col][j];
+            }
+        }
+    }
+    Some((det, inverse))
+}
+
+fn vector_value(items: Vec<f64>) -> Value {
+    let items = items.into_iter().map(Value::Float).collect();
+    Value::List(Rc::new(RefCell::new(items)))
+}
+
+fn matrix_value(rows: Matrix) -> Value {
+    let rows = rows.into_iter().map(vector_value).collect();
+    Value::List(Rc::new(RefCell::new(rows)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::run;
+
+    #[test]
+    fn test_vectors() {
+        let source = "let v = [3, 4]\n[linalg.dot(v, [1, 2]), linalg.norm(v), linalg.cross([1, 0, 0], [0, 1, 0]), linalg.add(v, [1, 1]), linalg.scale(2, v)]\n";
+        assert_eq!(run(source).unwrap(), "[11, 5, [0, 0, 1], [4, 5], [6, 8]]");
+    }
+
+    #[test]
+    fn test_matrices() {
+        let source = "from linalg import matmul, inverse, transpose\nlet m = [[2, 0], [1, 4]]\n[transpose([[1, 2, 3]]), matmul(m, [1, 1]), inverse(m), matmul(m, inverse(m)), linalg.det(m)]\n";
+        assert_eq!(
+            run(source).unwrap(),
+            "[[[1], [2], [3]], [2, 5], [[0.5, 0], [-0.125, 0.25]], [[1, 0], [0, 1]], 8]"
+        );
+        assert_eq!(run("linalg.det([[0, 1], [1, 0]])\n").unwrap(), "-1");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            run("linalg.inverse([[1, 2], [2, 4]])\n").unwrap_err(),
+            "linalg.inverse(): matrix is singular"
+        );
+        assert_eq!(
+            run("linalg.matmul([[1, 2]], [[1, 2]])\n").unwrap_err(),
+            "linalg.matmul() cannot multiply a 1x2 matrix by a 1x2 matrix"
+        );
+        assert_eq!(
+            run("linalg.dot([1], [1, 2])\n").unwrap_err(),
+            "linalg.dot() expects vectors of the same length, got 1 and 2"
+        );
+        assert_eq!(
+            run("linalg.inverse([[1, 2]])\n").unwrap_err(),
+            "linalg.inverse() expects a square matrix"
+        );
+    }
+}
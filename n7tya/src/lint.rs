@@ -0,0 +1,327 @@
+//! n7tya-lang リンター
+//!
+//! 機械的に直せる指摘を集め、`n7tya fix` で適用する。
+//! 修正はトークン位置に対するテキスト編集なので、それ以外の書式やコメントはそのまま残る。
+
+use crate::lexer::{Lexer, Token, TokenInfo};
+use crate::parser::Parser;
+use crate::resolve::{Resolver, SymbolTable};
+use crate::typechecker::{TypeChecker, TypeInfo};
+use std::ops::Range;
+
+/// ソースへのテキスト編集
+#[derive(Debug, Clone)]
+pub struct Edit {
+    pub span: Range<usize>,
+    pub replacement: String,
+}
+
+/// 自動修正つきの指摘
+#[derive(Debug, Clone)]
+pub struct Suggestion {
+    pub line: usize,
+    pub message: String,
+    pub edits: Vec<Edit>,
+}
+
+/// ソースを解析して自動修正できる指摘を集める
+pub fn lint(source: &str) -> Result<Vec<Suggestion>, String> {
+    let tokens = Lexer::new(source).tokenize();
+    let program = Parser::new(tokens.clone())
+        .parse()
+        .map_err(|e| format!("Parse error: {}", e))?;
+    let mut checker = TypeChecker::new();
+    checker.check(&program).map_err(|e| e.to_string())?;
+    let table = Resolver::new()
+        .with_tokens(source, &tokens)
+        .resolve(&program);
+
+    let mut suggestions = Vec::new();
+    suggestions.extend(unused_imports(source, &tokens));
+    suggestions.extend(misspelled_names(&table, &checker));
+    suggestions.extend(missing_return_types(&tokens, &checker));
+    suggestions.sort_by_key(|s| s.line);
+    Ok(suggestions)
+}
+
+/// 指摘の編集をまとめて適用する (他の編集と重なるものは飛ばす)
+pub fn apply_fixes(source: &str, suggestions: &[Suggestion]) -> String {
+    let mut edits: Vec<&Edit> = suggestions.iter().flat_map(|s| &s.edits).collect();
+    edits.sort_by_key(|e| e.span.start);
+
+    let mut result = String::with_capacity(source.len());
+    let mut last = 0;
+    for edit in edits {
+        if edit.span.start < last {
+            continue;
+        }
+        result.push_str(&source[last..edit.span.start]);
+        result.push_str(&edit.replacement);
+        last = edit.span.end;
+    }
+    result.push_str(&source[last..]);
+    result
+}
+
+/// 使われていない import を削除する
+/// (`from X import a, b` と `import X [as Y]` のみ。ファイルの import は対象外)
+fn unused_imports(source: &str, tokens: &[TokenInfo]) -> Vec<Suggestion> {
+    let mut suggestions = Vec::new();
+
+    for (i, info) in tokens.iter().enumerate() {
+        // 行頭の import 文だけを見る
+        if i > 0
+            && !matches!(
+                tokens[i - 1].token,
+                Token::Newline | Token::Indent | Token::Dedent
+            )
+        {
+            continue;
+        }
+        let end = tokens[i..]
+            .iter()
+            .position(|t| matches!(t.token, Token::Newline))
+            .map_or(tokens.len(), |n| i + n);
+        let stmt = &tokens[i..end];
+
+        // (名前, トークン位置) の一覧
+        let names: Vec<(&str, usize)> = match (&info.token, stmt.get(1).map(|t| &t.token)) {
+            (Token::From, _) => match stmt.iter().position(|t| matches!(t.token, Token::Import)) {
+                Some(import_at) => stmt[import_at + 1..]
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(k, t)| match &t.token {
+                        Token::Identifier(name) => Some((name.as_str(), i + import_at + 1 + k)),
+                        _ => None,
+                    })
+                    .collect(),
+                None => continue,
+            },
+            (Token::Import, Some(Token::Identifier(module))) => match stmt.get(3) {
+                Some(TokenInfo {
+                    token: Token::Identifier(alias),
+                    ..
+                }) if matches!(stmt[2].token, Token::As) => vec![(alias.as_str(), i + 3)],
+                _ => vec![(module.as_str(), i + 1)],
+            },
+            _ => continue,
+        };
+        if names.is_empty() {
+            continue;
+        }
+
+        let is_used = |name: &str| {
+            tokens.iter().enumerate().any(|(k, t)| {
+                !(i..end).contains(&k) && t.token == Token::Identifier(name.to_string())
+            })
+        };
+        let (unused, used): (Vec<_>, Vec<_>) = names.iter().partition(|(name, _)| !is_used(name));
+        if unused.is_empty() {
+            continue;
+        }
+
+        let edit = if used.is_empty() {
+            // 行ごと削除する
+            let line_start = source[..info.span.start].rfind('\n').map_or(0, |p| p + 1);
+            let line_end = tokens.get(end).map_or(source.len(), |t| t.span.end);
+            Edit {
+                span: line_start..line_end,
+                replacement: String::new(),
+            }
+        } else {
+            // 使われている名前だけを残す
+            let first = tokens[names[0].1].span.start;
+            let last = tokens[names[names.len() - 1].1].span.end;
+            Edit {
+                span: first..last,
+                replacement: used
+                    .iter()
+                    .map(|(name, _)| *name)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            }
+        };
+        let unused: Vec<&str> = unused.iter().map(|(name, _)| *name).collect();
+        suggestions.push(Suggestion {
+            line: info.line,
+            message: format!("Unused import: {}", unused.join(", ")),
+            edits: vec![edit],
+        });
+    }
+
+    suggestions
+}
+
+/// 未定義の変数名を、よく似た定義済みの名前に置き換える
+/// (名前解決で定義が見つからなかった参照だけを書き換え、同名の別の束縛には触れない)
+fn misspelled_names(table: &SymbolTable, checker: &TypeChecker) -> Vec<Suggestion> {
+    let candidates: Vec<&String> = checker
+        .defined_names()
+        .filter(|name| !name.contains('.'))
+        .collect();
+    // 標準ライブラリのモジュール名と同じくらい似ていれば、プログラムで定義した名前を選ぶ
+    let is_module = |name: &str| {
+        crate::builtins::STDLIB_MODULES
+            .iter()
+            .any(|(module, _)| *module == name)
+    };
+    let own_names: Vec<&String> = candidates
+        .iter()
+        .copied()
+        .filter(|name| !is_module(name))
+        .collect();
+
+    let mut suggestions = Vec::new();
+    for reference in table.undefined() {
+        // 組み込みの名前や前もって定義したグローバルは除く
+        if !checker.is_unresolved(&reference.name) {
+            continue;
+        }
+        let Some(position) = &reference.position else {
+            continue;
+        };
+        let name = &reference.name;
+        let Some(replacement) =
+            closest_name(name, &own_names).or_else(|| closest_name(name, &candidates))
+        else {
+            continue;
+        };
+
+        suggestions.push(Suggestion {
+            line: position.line,
+            message: format!(
+                "Undefined variable '{}'; did you mean '{}'?",
+                name, replacement
+            ),
+            edits: vec![Edit {
+                span: position.span.clone(),
+                replacement: replacement.to_string(),
+            }],
+        });
+    }
+    suggestions
+}
+
+/// 編集距離が十分小さく、一意に決まる候補を返す
+fn closest_name<'a>(name: &str, candidates: &[&'a String]) -> Option<&'a str> {
+    let limit = (name.chars().count() / 3).max(1);
+    let mut best: Option<(usize, &str)> = None;
+    let mut tied = false;
+    for candidate in candidates {
+        let distance = edit_distance(name, candidate);
+        // 同名の定義は別のスコープのものなので候補にしない
+        if distance == 0 || distance > limit {
+            continue;
+        }
+        match best {
+            Some((d, _)) if distance > d => {}
+            Some((d, _)) if distance == d => tied = true,
+            _ => {
+                best = Some((distance, candidate.as_str()));
+                tied = false;
+            }
+        }
+    }
+    if tied {
+        None
+    } else {
+        best.map(|(_, name)| name)
+    }
+}
+
+/// レーベンシュタイン距離
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = prev[j] + usize::from(ca != *cb);
+            current.push(substitute.min(prev[j + 1] + 1).min(current[j] + 1));
+        }
+        prev = current;
+    }
+    prev[b.len()]
+}
+
+/// 戻り値型の注釈がない関数に、推論した型を書き足す
+fn missing_return_types(tokens: &[TokenInfo], checker: &TypeChecker) -> Vec<Suggestion> {
+    let mut suggestions = Vec::new();
+    for (name, ty) in checker.inferred_return_types() {
+        let Some(annotation) = type_annotation(ty) else {
+            continue;
+        };
+
+        // 同名の関数 (別クラスのメソッドなど) があると位置が決まらないので飛ばす
+        let defs: Vec<usize> = tokens
+            .windows(2)
+            .enumerate()
+            .filter(|(_, w)| {
+                matches!(w[0].token, Token::Def) && w[1].token == Token::Identifier(name.clone())
+            })
+            .map(|(k, _)| k)
+            .collect();
+        let [def_at] = defs[..] else {
+            continue;
+        };
+        let Some(newline_at) = tokens[def_at..]
+            .iter()
+            .position(|t| matches!(t.token, Token::Newline))
+            .map(|n| def_at + n)
+        else {
+            continue;
+        };
+
+        let insert_at = tokens[newline_at - 1].span.end;
+        suggestions.push(Suggestion {
+            line: tokens[def_at].line,
+            message: format!("Missing return type for '{}': {}", name, annotation),
+            edits: vec![Edit {
+                span: insert_at..insert_at,
+                replacement: format!(" -> {}", annotation),
+            }],
+        });
+    }
+    suggestions
+}
+
+/// 推論した型を型注釈の文字列にする
+fn type_annotation(ty: &TypeInfo) -> Option<String> {
+    match ty {
+        TypeInfo::Int => Some("Int".to_string()),
+        TypeInfo::Float => Some("Float".to_string()),
+        TypeInfo::Bool => Some("Bool".to_string()),
+        TypeInfo::Str => Some("Str".to_string()),
+        TypeInfo::Class(name) => Some(name.clone()),
+        TypeInfo::List(inner) => Some(format!("List<{}>", type_annotation(inner)?)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fix(source: &str) -> String {
+        apply_fixes(source, &lint(source).unwrap())
+    }
+
+    #[test]
+    fn test_fixes() {
+        let source = "from math import sqrt, floor\nimport os\n\ndef area w: Int, h: Int # rect\n\treturn w * h\n\nlet total = 1\nprint totl + area(1, 2) + floor(1)\n";
+        assert_eq!(
+            fix(source),
+            "from math import floor\n\ndef area w: Int, h: Int -> Int # rect\n\treturn w * h\n\nlet total = 1\nprint total + area(1, 2) + floor(1)\n"
+        );
+    }
+
+    #[test]
+    fn test_misspelling_fix_leaves_shadowed_bindings() {
+        // 関数内の totl は自分の束縛なので、外側の未定義の totl だけを直す
+        let source = "def count -> Int\n\tlet totl = 1\n\treturn totl\n\nlet total = 2\nprint totl + count()\n";
+        assert_eq!(
+            fix(source),
+            "def count -> Int\n\tlet totl = 1\n\treturn totl\n\nlet total = 2\nprint total + count()\n"
+        );
+    }
+}
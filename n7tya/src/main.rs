@@ -2,24 +2,29 @@
 //!
 //! フルスタックWebアプリを1言語で開発するためのプログラミング言語
 
-mod ast;
-mod builtins;
-mod errors;
-mod interpreter;
-mod jsx_render;
-mod lexer;
-mod parser;
-mod python;
-mod typechecker;
-
-use interpreter::Interpreter;
-use lexer::Lexer;
 use miette::{Diagnostic, NamedSource, SourceSpan};
-use parser::Parser;
+use n7tya::ast::{ComponentDef, Item, ServerBodyItem, ServerDef};
+use n7tya::engine::{Engine, EngineError};
+use n7tya::formatter::{format_source, FormatOptions};
+use n7tya::interpreter::{Frame, Interpreter};
+use n7tya::lexer::Lexer;
+use n7tya::limits::Limits;
+use n7tya::parser::Parser;
+use n7tya::permissions::Permissions;
+use n7tya::resolve::ScopeRules;
+use n7tya::sourcemap::SourceMap;
+use n7tya::typechecker::TypeChecker;
+use n7tya::{
+    apidoc, assets, bench, content, css, deps, errors, i18n, lint, refactor, report, say, sitemap,
+    ssg, syntax,
+};
+use rayon::prelude::*;
+use std::collections::{BTreeSet, HashMap};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 use thiserror::Error;
-use typechecker::TypeChecker;
 
 /// コンパイラエラー
 #[derive(Error, Debug, Diagnostic)]
@@ -36,22 +41,71 @@ pub struct CompileError {
     help: String,
 }
 
+/// プロセスが main に入った時刻 (`--time-startup` の起点)
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
 fn main() -> miette::Result<()> {
-    let args: Vec<String> = std::env::args().collect();
+    STARTED.get_or_init(Instant::now);
+    // 内部のパニックで CLI ごと落とさず、バグ報告を促す診断に変換する
+    std::panic::set_hook(Box::new(|info| {
+        let message = if let Some(s) = info.payload().downcast_ref::<&str>() {
+            s.to_string()
+        } else if let Some(s) = info.payload().downcast_ref::<String>() {
+            s.clone()
+        } else {
+            "unknown panic".to_string()
+        };
+        let message = match info.location() {
+            Some(loc) => format!("{} ({}:{})", message, loc.file(), loc.line()),
+            None => message,
+        };
+        eprintln!("{:?}", miette::Report::new(errors::N7tyaError::internal(message)));
+    }));
+
+    match std::panic::catch_unwind(run_cli) {
+        Ok(result) => result.map_err(|report| {
+            let message = report.to_string();
+            let translated = i18n::tr(&message);
+            if translated == message {
+                report
+            } else {
+                miette::miette!("{}", translated)
+            }
+        }),
+        // 診断はパニックフックで表示済み
+        Err(_) => std::process::exit(101),
+    }
+}
+
+fn run_cli() -> miette::Result<()> {
+    let mut args: Vec<String> = std::env::args().collect();
+    i18n::init(&mut args).map_err(|e| miette::miette!("{}", e))?;
 
     if args.len() < 2 {
-        println!("n7tya-lang v0.2.0");
-        println!();
-        println!("Usage:");
-        println!("  n7tya <file.n7t>    Run a file");
-        println!("  n7tya run           Run project");
-        println!("  n7tya build         Build project");
-        println!("  n7tya test          Run tests");
-        println!("  n7tya new <name>    Create new project");
-        println!("  n7tya fmt           Format code");
-        println!("  n7tya check         Type check");
-        println!("  n7tya --version     Show version");
-        println!("  n7tya --update      Update n7tya");
+        say!("n7tya-lang v0.2.0");
+        say!();
+        say!("Usage:");
+        say!("  n7tya <file.n7t>    Run a file");
+        say!("  n7tya run           Run project");
+        say!("    --config <file>   Read [server] and [config] from another file");
+        say!("    --allow-read[=<paths>], --allow-write[=<paths>], --allow-net[=<hosts>],");
+        say!("    --allow-env[=<names>], --allow-run[=<commands>], --allow-all");
+        say!("                      Only allow these capabilities to builtins");
+        say!("    --max-steps <n>, --timeout <secs>, --max-memory <mb>");
+        say!("                      Stop the program (and each route handler) past these limits");
+        say!("    --time-startup    Print how long startup, parsing, checking and running took");
+        say!("  n7tya build         Build project");
+        say!("    --static          Also render GET routes to dist/ as a static site");
+        say!("    --incremental     Only re-render pages whose data files changed");
+        say!("  n7tya test          Run tests");
+        say!("  n7tya new <name>    Create new project");
+        say!("  n7tya add <source>  Add a dependency");
+        say!("  n7tya fmt           Format code");
+        say!("  n7tya fix [file]    Apply lint fixes");
+        say!("  n7tya refactor      Rename a name or extract lines into a function");
+        say!("  n7tya check         Type check");
+        say!("  n7tya --version     Show version");
+        say!("  n7tya --update      Update n7tya");
         return Ok(());
     }
 
@@ -59,36 +113,83 @@ fn main() -> miette::Result<()> {
 
     match command.as_str() {
         "run" => {
-            run_project()?;
+            run_project(&args[2..])?;
         }
         "build" => {
-            build_project()?;
+            build_project(&args[2..])?;
         }
         "test" => {
             run_tests()?;
         }
         "new" => {
             if args.len() < 3 {
-                println!("Usage: n7tya new <project-name>");
+                say!("Usage: n7tya new <project-name>");
                 return Ok(());
             }
             create_project(&args[2])?;
         }
+        "add" => {
+            add_dependency(&args[2..])?;
+        }
+        "install" => {
+            install_dependencies()?;
+        }
         "fmt" => {
             format_project()?;
         }
+        "fix" => {
+            fix_project(args.get(2).map(String::as_str))?;
+        }
+        "refactor" => {
+            refactor_command(&args[2..])?;
+        }
+        "report" => {
+            let file = args[2..].iter().find(|a| !a.starts_with("--"));
+            let redact = args[2..].iter().any(|a| a == "--redact");
+            match file {
+                Some(file) => {
+                    let out = report::create_report(&PathBuf::from(file), redact)
+                        .map_err(|e| miette::miette!("{}", e))?;
+                    say!("✓ Report written to {}", out.display());
+                    say!("  Nothing has been uploaded. Attach this file to your bug report.");
+                }
+                None => say!("Usage: n7tya report <file.n7t> [--redact]"),
+            }
+        }
+        "bench" => {
+            bench_command(&args[2..])?;
+        }
+        "doc" => {
+            doc_project(&args[2..])?;
+        }
+        "gen-syntax" => match (args.get(2).map(String::as_str), args.get(3)) {
+            (Some("--target"), Some(target)) => {
+                let target =
+                    syntax::SyntaxTarget::parse(target).map_err(|e| miette::miette!("{}", e))?;
+                print!("{}", syntax::generate(target));
+            }
+            _ => say!("Usage: n7tya gen-syntax --target tmlanguage|tree-sitter|ebnf"),
+        },
         "check" => {
             if args.len() < 3 {
-                println!("Usage: n7tya check <file.n7t>");
+                say!("Usage: n7tya check <file.n7t>");
                 return Ok(());
             }
             check_file(&args[2])?;
         }
         file if file.ends_with(".n7t") => {
-            run_file(file)?;
+            let (options, script_args) = split_script_args(&args[2..]);
+            run_file(
+                file,
+                config_option(options)?,
+                permissions_option(options)?,
+                limits_option(options)?,
+                script_args,
+                time_startup_option(options),
+            )?;
         }
         "--version" | "-v" => {
-            println!("n7tya-lang v0.2.0");
+            say!("n7tya-lang v0.2.0");
         }
         "--help" | "-h" => {
             print_help();
@@ -97,63 +198,185 @@ fn main() -> miette::Result<()> {
             perform_update()?;
         }
         _ => {
-            println!("Unknown command: {}", command);
-            println!("Run 'n7tya --help' for usage.");
+            say!("Unknown command: {}", command);
+            say!("Run 'n7tya --help' for usage.");
         }
     }
 
     Ok(())
 }
 
-/// ファイルを実行
-fn run_file(path: &str) -> miette::Result<()> {
-    let source = fs::read_to_string(path)
-        .map_err(|e| miette::miette!("Failed to read file '{}': {}", path, e))?;
+/// `--config <file>` で指定した設定ファイル
+fn config_option(args: &[String]) -> miette::Result<Option<&str>> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if let Some(file) = arg.strip_prefix("--config=") {
+            return Ok(Some(file));
+        }
+        if arg == "--config" {
+            return match args.next() {
+                Some(file) => Ok(Some(file)),
+                None => Err(miette::miette!("--config expects a file")),
+            };
+        }
+    }
+    Ok(None)
+}
 
-    let mut lexer = Lexer::new(&source);
-    let tokens = lexer.tokenize();
+/// `--` より前の n7tya のオプションと、後のプログラムに渡す引数 (os.args())
+fn split_script_args(args: &[String]) -> (&[String], Vec<String>) {
+    match args.iter().position(|arg| arg == "--") {
+        Some(i) => (&args[..i], args[i + 1..].to_vec()),
+        None => (args, Vec::new()),
+    }
+}
 
-    let mut parser = Parser::new(tokens);
-    match parser.parse() {
-        Ok(program) => {
-            // 型チェック
-            let mut checker = TypeChecker::new();
-            match checker.check(&program) {
-                Ok(errors) => {
-                    if !errors.is_empty() {
-                        println!("Type errors:");
-                        for err in &errors {
-                            println!("  - {}", err);
-                        }
-                        return Ok(());
-                    }
-                }
-                Err(e) => {
-                    println!("Type check failed: {:?}", e);
-                    return Ok(());
-                }
-            }
+/// `--allow-read` などで許した権限 (1つも渡さなければすべて許す)
+fn permissions_option(args: &[String]) -> miette::Result<Permissions> {
+    Permissions::from_args(args).map_err(|e| miette::miette!("{}", e))
+}
 
-            // 実行
-            let mut interpreter = Interpreter::new();
-            match interpreter.run(&program) {
-                Ok(_result) => {
-                    // 結果は print で出力されているので追加表示は不要
-                }
-                Err(e) => {
-                    println!("Runtime error: {}", e);
-                }
+/// `--max-steps` `--timeout` `--max-memory` で指定した実行の上限 (省略時は制限しない)
+fn limits_option(args: &[String]) -> miette::Result<Limits> {
+    Limits::from_args(args).map_err(|e| miette::miette!("{}", e))
+}
+
+/// `--time-startup` を渡したか
+fn time_startup_option(args: &[String]) -> bool {
+    args.iter().any(|arg| arg == "--time-startup")
+}
+
+/// ファイルを実行 (config は [server] と [config] を読む設定ファイル、省略時は n7tya.toml)
+fn run_file(
+    path: &str,
+    config: Option<&str>,
+    permissions: Permissions,
+    limits: Limits,
+    script_args: Vec<String>,
+    time_startup: bool,
+) -> miette::Result<()> {
+    let mut interpreter = project_interpreter(path, config)?;
+    interpreter.set_permissions(permissions);
+    interpreter.set_limits(limits);
+    interpreter.set_args(script_args);
+    let mut engine = Engine::from_interpreter(interpreter);
+    engine.set_type_checker(Some(project_checker()?));
+    let init = STARTED.get().map(Instant::elapsed).unwrap_or_default();
+
+    // 定義したサーバーはファイルを最後まで実行してからまとめて起動する
+    // (結果は print で出力されているので追加表示は不要)
+    let result = engine.eval_file(Path::new(path));
+    if time_startup {
+        print_startup_time(init, &engine);
+    }
+    match result.and_then(|_| engine.serve()) {
+        Ok(()) => Ok(()),
+        Err(e @ EngineError::Io { .. }) => Err(miette::miette!("{}", e)),
+        Err(EngineError::Parse(e)) => {
+            say!("Parse error: {:?}", e);
+            Ok(())
+        }
+        Err(EngineError::Type(errors)) => {
+            say!("Type errors:");
+            for err in &errors {
+                say!("  - {}", err);
             }
+            Ok(())
         }
-        Err(e) => {
-            println!("Parse error: {:?}", e);
+        Err(EngineError::Runtime { message, trace }) => {
+            say!("Runtime error: {}", message);
+            print_stack_trace(trace);
+            Ok(())
         }
     }
+}
 
-    Ok(())
+/// main に入ってから実行を終えるまでの時間を段階ごとに標準エラーに出す (プログラムの出力と混ざらないように)
+fn print_startup_time(init: Duration, engine: &Engine) {
+    let timings = engine.timings();
+    let ms = |duration: Duration| bench::format_ns(duration.as_nanos() as u64);
+    eprintln!(
+        "startup: {} (init {}, parse {}, check {}, run {})",
+        ms(init + timings.parse + timings.check + timings.run),
+        ms(init),
+        ms(timings.parse),
+        ms(timings.check),
+        ms(timings.run)
+    );
+}
+
+/// ファイルを実行するインタプリタ (import はこのファイルのディレクトリと依存パッケージから探す)
+fn project_interpreter(path: &str, config: Option<&str>) -> miette::Result<Interpreter> {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_file(Path::new(path));
+    interpreter.set_packages(installed_packages()?);
+    interpreter.set_scope_rules(scope_rules()?);
+    if let Some(assets) =
+        assets::Assets::load(Path::new(".")).map_err(|e| miette::miette!("{}", e))?
+    {
+        interpreter.set_assets(assets);
+    }
+    if let Some(content) = project_content()? {
+        interpreter.set_content(content);
+    }
+    // [build] css のコマンドが書き出す CSS をページに読み込ませる
+    if let Ok(manifest) = fs::read_to_string("n7tya.toml") {
+        let options =
+            css::BuildOptions::from_toml(&manifest).map_err(|e| miette::miette!("{}", e))?;
+        interpreter.set_stylesheets(options.stylesheet().into_iter().collect());
+    }
+    let config = match config {
+        Some(file) => Some(PathBuf::from(file)),
+        None => Some(PathBuf::from("n7tya.toml")).filter(|file| file.exists()),
+    };
+    if let Some(file) = config {
+        interpreter
+            .load_config(&file)
+            .map_err(|e| miette::miette!("{}", e))?;
+    }
+    Ok(interpreter)
+}
+
+/// プロジェクトの content/ のコレクション (ファイルごとの型チェックで読み直さないよう一度だけ読む)
+fn project_content() -> miette::Result<Option<&'static content::Collections>> {
+    static CONTENT: OnceLock<Result<Option<content::Collections>, String>> = OnceLock::new();
+    CONTENT
+        .get_or_init(|| content::Collections::load(Path::new(content::CONTENT_DIR)))
+        .as_ref()
+        .map(Option::as_ref)
+        .map_err(|e| miette::miette!("{}", e))
+}
+
+/// content/ のコレクションの型も使う型チェッカー
+fn project_checker() -> miette::Result<TypeChecker> {
+    let mut checker = TypeChecker::new();
+    checker.set_scope_rules(scope_rules()?);
+    if let Some(content) = project_content()? {
+        checker.set_content(content);
+    }
+    Ok(checker)
+}
+
+/// n7tya.toml の [scope] (プロジェクトの外ではデフォルト)
+fn scope_rules() -> miette::Result<ScopeRules> {
+    match fs::read_to_string("n7tya.toml") {
+        Ok(manifest) => ScopeRules::from_toml(&manifest).map_err(|e| miette::miette!("{}", e)),
+        Err(_) => Ok(ScopeRules::default()),
+    }
 }
 
 /// 型チェックのみ実行
+/// 実行時エラーになった呼び出しを内側から順に表示する
+fn print_stack_trace(frames: Vec<Frame>) {
+    if frames.is_empty() {
+        return;
+    }
+    say!("  Stack trace (most recent call first):");
+    for frame in frames {
+        say!("    {}", frame);
+    }
+}
+
 fn check_file(path: &str) -> miette::Result<()> {
     let source = fs::read_to_string(path)
         .map_err(|e| miette::miette!("Failed to read file '{}': {}", path, e))?;
@@ -164,25 +387,32 @@ fn check_file(path: &str) -> miette::Result<()> {
     let mut parser = Parser::new(tokens);
     match parser.parse() {
         Ok(program) => {
-            let mut checker = TypeChecker::new();
+            let mut checker = project_checker()?;
             match checker.check(&program) {
                 Ok(errors) => {
                     if errors.is_empty() {
-                        println!("✓ No type errors in {}", path);
+                        say!("✓ No type errors in {}", path);
                     } else {
-                        println!("✗ {} type error(s) in {}", errors.len(), path);
+                        say!("✗ {} type error(s) in {}", errors.len(), path);
                         for err in &errors {
-                            println!("  - {}", err);
+                            say!("  - {}", err);
+                        }
+                    }
+                    let warnings = checker.warnings();
+                    if !warnings.is_empty() {
+                        say!("⚠ {} warning(s) in {}", warnings.len(), path);
+                        for warning in warnings {
+                            say!("  - {}", warning);
                         }
                     }
                 }
                 Err(e) => {
-                    println!("Type check failed: {:?}", e);
+                    say!("Type check failed: {:?}", e);
                 }
             }
         }
         Err(e) => {
-            println!("Parse error: {:?}", e);
+            say!("Parse error: {:?}", e);
         }
     }
 
@@ -190,7 +420,7 @@ fn check_file(path: &str) -> miette::Result<()> {
 }
 
 /// プロジェクトを実行
-fn run_project() -> miette::Result<()> {
+fn run_project(args: &[String]) -> miette::Result<()> {
     // n7tya.toml を探す
     if !PathBuf::from("n7tya.toml").exists() {
         return Err(miette::miette!(
@@ -201,7 +431,15 @@ fn run_project() -> miette::Result<()> {
     // src/main.n7t を実行
     let main_file = "src/main.n7t";
     if PathBuf::from(main_file).exists() {
-        run_file(main_file)?;
+        let (options, script_args) = split_script_args(args);
+        run_file(
+            main_file,
+            config_option(options)?,
+            permissions_option(options)?,
+            limits_option(options)?,
+            script_args,
+            time_startup_option(options),
+        )?;
     } else {
         return Err(miette::miette!("No src/main.n7t found"));
     }
@@ -209,6 +447,71 @@ fn run_project() -> miette::Result<()> {
     Ok(())
 }
 
+/// カレントディレクトリのプロジェクトの n7tya.lock にあるパッケージ
+fn installed_packages() -> miette::Result<HashMap<String, PathBuf>> {
+    deps::package_roots(Path::new(".")).map_err(|e| miette::miette!("{}", e))
+}
+
+/// n7tya add <git-url|path> [--name <name>] [--rev <rev>]
+fn add_dependency(args: &[String]) -> miette::Result<()> {
+    let mut source = None;
+    let mut name = None;
+    let mut rev = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--name" => name = iter.next().cloned(),
+            "--rev" => rev = iter.next().cloned(),
+            _ => source = Some(arg.as_str()),
+        }
+    }
+    let Some(source) = source else {
+        say!("Usage: n7tya add <git-url|path> [--name <name>] [--rev <rev>]");
+        return Ok(());
+    };
+
+    let content = fs::read_to_string("n7tya.toml").map_err(|_| {
+        miette::miette!("No n7tya.toml found. Are you in a n7tya project directory?")
+    })?;
+    let source = deps::Source::from_arg(source, rev);
+    let dep = deps::Dependency {
+        name: name.unwrap_or_else(|| source.default_name()),
+        source,
+    };
+    let updated = deps::add_to_manifest(&content, &dep).map_err(|e| miette::miette!("{}", e))?;
+    fs::write("n7tya.toml", updated)
+        .map_err(|e| miette::miette!("Failed to write n7tya.toml: {}", e))?;
+
+    // 取得できなければ n7tya.toml を元に戻す
+    if let Err(e) = install_dependencies() {
+        let _ = fs::write("n7tya.toml", content);
+        return Err(e);
+    }
+    say!("✓ Added {} ({})", dep.name, dep.source);
+    Ok(())
+}
+
+/// n7tya.toml の依存を取得して n7tya.lock を更新
+fn install_dependencies() -> miette::Result<()> {
+    if !PathBuf::from("n7tya.toml").exists() {
+        return Err(miette::miette!(
+            "No n7tya.toml found. Are you in a n7tya project directory?"
+        ));
+    }
+    let packages = deps::install(Path::new(".")).map_err(|e| miette::miette!("{}", e))?;
+    for package in &packages {
+        match &package.commit {
+            Some(commit) => {
+                let short = &commit[..7.min(commit.len())];
+                say!("  {} {} ({})", package.name, package.source, short)
+            }
+            None => say!("  {} {}", package.name, package.source),
+        }
+    }
+    say!("✓ Installed {} package(s)", packages.len());
+    Ok(())
+}
+
 /// 新規プロジェクト作成
 fn create_project(name: &str) -> miette::Result<()> {
     let project_dir = PathBuf::from(name);
@@ -251,23 +554,35 @@ main
     fs::write(project_dir.join("src/main.n7t"), main_content)
         .map_err(|e| miette::miette!("Failed to write main.n7t: {}", e))?;
 
-    println!("Created project '{}'", name);
-    println!();
-    println!("  cd {}", name);
-    println!("  n7tya run");
+    say!("Created project '{}'", name);
+    say!();
+    say!("  cd {}", name);
+    say!("  n7tya run");
 
     Ok(())
 }
 
 /// プロジェクトをビルド
-fn build_project() -> miette::Result<()> {
-    println!("Building project...");
-
-    if !PathBuf::from("n7tya.toml").exists() {
-        return Err(miette::miette!(
-            "No n7tya.toml found. Are you in a n7tya project directory?"
-        ));
+fn build_project(args: &[String]) -> miette::Result<()> {
+    let mut static_site = false;
+    let mut incremental = false;
+    for arg in args {
+        match arg.as_str() {
+            "--static" => static_site = true,
+            "--incremental" => incremental = true,
+            _ => return Err(miette::miette!("Unknown option for build: {}", arg)),
+        }
+    }
+    if incremental && !static_site {
+        return Err(miette::miette!("--incremental requires --static"));
     }
+    say!("Building project...");
+
+    let manifest = fs::read_to_string("n7tya.toml").map_err(|_| {
+        miette::miette!("No n7tya.toml found. Are you in a n7tya project directory?")
+    })?;
+    let options = css::BuildOptions::from_toml(&manifest).map_err(|e| miette::miette!("{}", e))?;
+    let site = sitemap::SiteOptions::from_toml(&manifest).map_err(|e| miette::miette!("{}", e))?;
 
     // srcディレクトリの全.n7tファイルを型チェック
     let src_dir = PathBuf::from("src");
@@ -275,55 +590,291 @@ fn build_project() -> miette::Result<()> {
         return Err(miette::miette!("No src directory found"));
     }
 
-    let mut error_count = 0;
+    let mut paths = Vec::new();
     for entry in fs::read_dir(&src_dir).map_err(|e| miette::miette!("Failed to read src: {}", e))? {
         let entry = entry.map_err(|e| miette::miette!("Failed to read entry: {}", e))?;
         let path = entry.path();
-        if path.extension().map_or(false, |e| e == "n7t") {
-            println!("  Checking {}...", path.display());
+        if path.extension().is_some_and(|e| e == "n7t") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
 
-            let source = fs::read_to_string(&path)
-                .map_err(|e| miette::miette!("Failed to read file: {}", e))?;
+    // ファイル同士は独立しているので並列に検査し、表示はパス順にそろえる
+    let results: Vec<miette::Result<FileCheck>> =
+        paths.par_iter().map(|path| check_project_file(path)).collect();
 
-            let mut lexer = Lexer::new(&source);
-            let tokens = lexer.tokenize();
-            let mut parser = Parser::new(tokens);
-
-            match parser.parse() {
-                Ok(program) => {
-                    let mut checker = TypeChecker::new();
-                    if let Ok(errors) = checker.check(&program) {
-                        if !errors.is_empty() {
-                            error_count += errors.len();
-                            for err in &errors {
-                                println!("    Error: {}", err);
-                            }
-                        }
-                    }
+    let mut error_count = 0;
+    let mut islands = Vec::new();
+    let mut servers = Vec::new();
+    let mut classes = BTreeSet::new();
+    for (path, result) in paths.iter().zip(results) {
+        say!("  Checking {}...", path.display());
+        match result? {
+            FileCheck::Checked {
+                errors,
+                islands: components,
+                servers: server_defs,
+                classes: file_classes,
+            } => {
+                error_count += errors.len();
+                for err in &errors {
+                    say!("    Error: {}", err);
                 }
-                Err(e) => {
-                    error_count += 1;
-                    println!("    Parse error: {:?}", e);
+                // ソースマップに書くパス (プロジェクトからの相対パス)
+                let file = path.to_string_lossy().replace('\\', "/");
+                islands.extend(components.into_iter().map(|c| (file.clone(), c)));
+                servers.extend(server_defs.into_iter().map(|s| (file.clone(), s)));
+                classes.extend(file_classes);
+            }
+            FileCheck::ParseError(e) => {
+                error_count += 1;
+                say!("    Parse error: {:?}", e);
+            }
+        }
+    }
+
+    // island component があればクライアント JS を dist/islands.js に、
+    // その行を .n7t の行に対応させるソースマップを dist/islands.js.map に書き出す
+    if error_count == 0 && !islands.is_empty() {
+        let components: Vec<(&str, &ComponentDef)> =
+            islands.iter().map(|(file, c)| (file.as_str(), c)).collect();
+        let servers: Vec<(&str, &ServerDef)> =
+            servers.iter().map(|(file, s)| (file.as_str(), s)).collect();
+        let routes = n7tya::client_api::api_routes(&servers);
+        let mut map = SourceMap::new();
+        match n7tya::islands::client_script_with_map(&components, &routes, &mut map) {
+            Ok(script) => {
+                for (index, file) in map.sources().to_vec().iter().enumerate() {
+                    if let Ok(content) = fs::read_to_string(file) {
+                        map.set_content(index, content);
+                    }
                 }
+                fs::create_dir_all("dist")
+                    .map_err(|e| miette::miette!("Failed to create dist: {}", e))?;
+                fs::write(
+                    "dist/islands.js",
+                    script + &SourceMap::comment("islands.js.map"),
+                )
+                .map_err(|e| miette::miette!("Failed to write dist/islands.js: {}", e))?;
+                // マップは dist/ にあるので、sources (src/...) は ../ から参照する
+                fs::write("dist/islands.js.map", map.to_json("islands.js", "../"))
+                    .map_err(|e| miette::miette!("Failed to write dist/islands.js.map: {}", e))?;
+                say!("  Wrote dist/islands.js ({} island(s))", islands.len());
             }
+            Err(e) => {
+                error_count += 1;
+                say!("    Error: {}", e);
+            }
+        }
+    }
+
+    // [build] css のコマンド (Tailwind CSS など) に JSX のクラス名を渡して実行する
+    if let (0, Some(command)) = (error_count, &options.css) {
+        fs::create_dir_all("dist").map_err(|e| miette::miette!("Failed to create dist: {}", e))?;
+        let lines: Vec<&str> = classes.iter().map(String::as_str).collect();
+        fs::write(css::CLASSES_FILE, lines.join("\n") + "\n")
+            .map_err(|e| miette::miette!("Failed to write {}: {}", css::CLASSES_FILE, e))?;
+        say!(
+            "  Wrote {} class name(s) to {}",
+            classes.len(),
+            css::CLASSES_FILE
+        );
+        say!("  Running {}", command);
+        if let Err(e) = css::run_command(command, Path::new(css::CLASSES_FILE)) {
+            error_count += 1;
+            say!("    Error: {}", e);
+        } else if options.stylesheet().is_none() {
+            say!("    Warning: the CSS is not written under public/, so pages do not load it");
         }
     }
 
+    // public/ のファイルを fingerprint 付きの名前で dist/ にコピーする
+    let public = Path::new(assets::PUBLIC_DIR);
+    if error_count == 0 && public.is_dir() {
+        let manifest = assets::build(public, Path::new(assets::DIST_DIR))
+            .map_err(|e| miette::miette!("{}", e))?;
+        say!(
+            "  Wrote {} asset(s) and dist/{}",
+            manifest.len(),
+            assets::MANIFEST
+        );
+    }
+
+    // [site] があれば GET のルートから dist/sitemap.xml と dist/robots.txt を書き出す
+    if let (0, Some(site)) = (error_count, &site) {
+        let routes: Vec<String> = servers
+            .iter()
+            .flat_map(|(_, server)| &server.body)
+            .filter_map(|item| match item {
+                ServerBodyItem::Route(route) if route.method.eq_ignore_ascii_case("get") => {
+                    Some(route.path.clone())
+                }
+                _ => None,
+            })
+            .collect();
+        let written = sitemap::build(site, &routes, Path::new(assets::DIST_DIR))
+            .map_err(|e| miette::miette!("{}", e))?;
+        for file in written {
+            say!("  Wrote dist/{}", file);
+        }
+    }
+
+    // GET のルートを静的なページとして dist/ に書き出す
+    if error_count == 0 && static_site {
+        error_count += build_static_site(incremental)?;
+    }
+
     if error_count == 0 {
-        println!("✓ Build successful!");
+        say!("✓ Build successful!");
     } else {
-        println!("✗ Build failed with {} error(s)", error_count);
+        say!("✗ Build failed with {} error(s)", error_count);
     }
 
     Ok(())
 }
 
+/// src/main.n7t を実行し、定義したサーバーのページを書き出す (戻り値はエラーの数)
+fn build_static_site(incremental: bool) -> miette::Result<usize> {
+    let main_file = "src/main.n7t";
+    let source = fs::read_to_string(main_file)
+        .map_err(|e| miette::miette!("Failed to read file '{}': {}", main_file, e))?;
+    let program = match Parser::new(Lexer::new(&source).tokenize()).parse() {
+        Ok(program) => program,
+        Err(e) => {
+            say!("    Parse error: {:?}", e);
+            return Ok(1);
+        }
+    };
+    let mut interpreter = project_interpreter(main_file, None)?;
+    if let Err(e) = interpreter.run(&program) {
+        say!("Runtime error: {}", e);
+        print_stack_trace(interpreter.take_stack_trace());
+        return Ok(1);
+    }
+
+    let report = ssg::build(&mut interpreter, Path::new("."), incremental)
+        .map_err(|e| miette::miette!("{}", e))?;
+    for path in &report.rendered {
+        say!("  Rendered {}", path);
+    }
+    for path in &report.removed {
+        say!("  Removed {}", path);
+    }
+    for err in &report.errors {
+        say!("    Error: {}", err);
+    }
+    say!(
+        "  Wrote {} page(s) ({} unchanged) and dist/{}",
+        report.rendered.len(),
+        report.unchanged,
+        ssg::PAGES_MANIFEST
+    );
+    Ok(report.errors.len())
+}
+
+/// src の .n7t から API ドキュメントを生成して dist/doc に書き出す
+fn doc_project(args: &[String]) -> miette::Result<()> {
+    let mut format = apidoc::DocFormat::Html;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--format" => {
+                let Some(value) = iter.next() else {
+                    return Err(miette::miette!("Missing value for {}", arg));
+                };
+                format = apidoc::DocFormat::parse(value).map_err(|e| miette::miette!("{}", e))?;
+            }
+            _ => return Err(miette::miette!("Unknown option for doc: {}", arg)),
+        }
+    }
+
+    let manifest = fs::read_to_string("n7tya.toml").map_err(|_| {
+        miette::miette!("No n7tya.toml found. Are you in a n7tya project directory?")
+    })?;
+    let name = manifest
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|t| t.get("package")?.get("name")?.as_str().map(str::to_string))
+        .unwrap_or_else(|| "n7tya".to_string());
+
+    let mut paths = Vec::new();
+    for entry in fs::read_dir("src").map_err(|e| miette::miette!("Failed to read src: {}", e))? {
+        let entry = entry.map_err(|e| miette::miette!("Failed to read entry: {}", e))?;
+        let path = entry.path();
+        if path.extension().is_some_and(|e| e == "n7t") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut modules = Vec::new();
+    for path in &paths {
+        let source =
+            fs::read_to_string(path).map_err(|e| miette::miette!("Failed to read file: {}", e))?;
+        let program = Parser::new(Lexer::new(&source).tokenize()).parse()?;
+        modules.push((path.display().to_string(), apidoc::module_entries(&program)));
+    }
+
+    let out = Path::new("dist/doc").join(format.file_name());
+    fs::create_dir_all("dist/doc")
+        .map_err(|e| miette::miette!("Failed to create dist/doc: {}", e))?;
+    let title = format!("{} API", name);
+    fs::write(&out, apidoc::render(&title, &modules, format))
+        .map_err(|e| miette::miette!("Failed to write {}: {}", out.display(), e))?;
+    say!("✓ Wrote {}", out.display());
+    Ok(())
+}
+
+/// build で1ファイルを検査した結果
+enum FileCheck {
+    Checked {
+        errors: Vec<String>,
+        islands: Vec<ComponentDef>,
+        servers: Vec<ServerDef>, // island から api.xxx() で呼べるサーバー
+        classes: Vec<String>,    // JSX の class 属性のクラス名 ([build] css のコマンドに渡す)
+    },
+    ParseError(miette::Report),
+}
+
+fn check_project_file(path: &Path) -> miette::Result<FileCheck> {
+    let source =
+        fs::read_to_string(path).map_err(|e| miette::miette!("Failed to read file: {}", e))?;
+
+    let tokens = Lexer::new(&source).tokenize();
+    match Parser::new(tokens).parse() {
+        Ok(program) => {
+            let errors = project_checker()?.check(&program).unwrap_or_default();
+            let mut classes = BTreeSet::new();
+            css::extract_classes(&program, &mut classes);
+            let mut islands = Vec::new();
+            let mut servers = Vec::new();
+            for item in program.items {
+                match item {
+                    Item::ComponentDef(c) if c.island => islands.push(c),
+                    Item::ServerDef(s) => servers.push(s),
+                    _ => {}
+                }
+            }
+            Ok(FileCheck::Checked {
+                errors,
+                islands,
+                servers,
+                classes: classes.into_iter().collect(),
+            })
+        }
+        Err(e) => Ok(FileCheck::ParseError(e)),
+    }
+}
+
 /// テストを実行
 fn run_tests() -> miette::Result<()> {
-    println!("Running tests...");
+    say!("Running tests...");
 
     // testsディレクトリまたはtest_で始まるファイルを探す
     let test_dirs = vec![PathBuf::from("tests"), PathBuf::from("src")];
+    let packages = installed_packages()?;
+    let rules = scope_rules()?;
     let mut test_count = 0;
     let mut passed = 0;
     let mut failed = 0;
@@ -338,34 +889,35 @@ fn run_tests() -> miette::Result<()> {
             let path = entry.path();
             let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
 
-            if path.extension().map_or(false, |e| e == "n7t") && name.starts_with("test_") {
+            if path.extension().is_some_and(|e| e == "n7t") && name.starts_with("test_") {
                 test_count += 1;
-                println!("  Running {}...", name);
-
-                let source = fs::read_to_string(&path)
-                    .map_err(|e| miette::miette!("Failed to read test file: {}", e))?;
-
-                let mut lexer = Lexer::new(&source);
-                let tokens = lexer.tokenize();
-                let mut parser = Parser::new(tokens);
-
-                match parser.parse() {
-                    Ok(program) => {
-                        let mut interpreter = Interpreter::new();
-                        match interpreter.run(&program) {
-                            Ok(_) => {
-                                passed += 1;
-                                println!("    ✓ Passed");
-                            }
-                            Err(e) => {
-                                failed += 1;
-                                println!("    ✗ Failed: {}", e);
-                            }
-                        }
+                say!("  Running {}...", name);
+
+                let mut interpreter = Interpreter::new();
+                interpreter.set_packages(packages.clone());
+                interpreter.set_scope_rules(rules);
+                let mut engine = Engine::from_interpreter(interpreter);
+                engine.set_type_checker(None);
+                match engine.eval_file(&path) {
+                    Ok(_) => {
+                        passed += 1;
+                        say!("    ✓ Passed");
+                    }
+                    Err(EngineError::Io { message, .. }) => {
+                        return Err(miette::miette!("Failed to read test file: {}", message));
+                    }
+                    Err(EngineError::Parse(e)) => {
+                        failed += 1;
+                        say!("    ✗ Parse error: {:?}", e);
+                    }
+                    Err(EngineError::Runtime { message, trace }) => {
+                        failed += 1;
+                        say!("    ✗ Failed: {}", message);
+                        print_stack_trace(trace);
                     }
                     Err(e) => {
                         failed += 1;
-                        println!("    ✗ Parse error: {:?}", e);
+                        say!("    ✗ Failed: {}", e);
                     }
                 }
             }
@@ -373,10 +925,10 @@ fn run_tests() -> miette::Result<()> {
     }
 
     if test_count == 0 {
-        println!("No tests found. Create files starting with 'test_' in src/ or tests/");
+        say!("No tests found. Create files starting with 'test_' in src/ or tests/");
     } else {
-        println!();
-        println!("{} tests: {} passed, {} failed", test_count, passed, failed);
+        say!();
+        say!("{} tests: {} passed, {} failed", test_count, passed, failed);
     }
 
     Ok(())
@@ -384,78 +936,287 @@ fn run_tests() -> miette::Result<()> {
 
 /// コードをフォーマット
 fn format_project() -> miette::Result<()> {
-    println!("Formatting code...");
+    say!("Formatting code...");
+
+    // n7tya.toml の [fmt] があればそのスタイルで整形する
+    let options = match fs::read_to_string("n7tya.toml") {
+        Ok(content) => FormatOptions::from_toml(&content).map_err(|e| miette::miette!("{}", e))?,
+        Err(_) => FormatOptions::default(),
+    };
 
     let src_dir = PathBuf::from("src");
     if !src_dir.exists() {
         // カレントディレクトリの.n7tファイルをフォーマット
-        format_directory(&PathBuf::from("."))?;
+        format_directory(&PathBuf::from("."), &options)?;
     } else {
-        format_directory(&src_dir)?;
+        format_directory(&src_dir, &options)?;
     }
 
-    println!("✓ Formatting complete!");
+    say!("✓ Formatting complete!");
     Ok(())
 }
 
-fn format_directory(dir: &PathBuf) -> miette::Result<()> {
+fn format_directory(dir: &PathBuf, options: &FormatOptions) -> miette::Result<()> {
     for entry in fs::read_dir(dir).map_err(|e| miette::miette!("Failed to read dir: {}", e))? {
         let entry = entry.map_err(|e| miette::miette!("Failed to read entry: {}", e))?;
         let path = entry.path();
 
-        if path.extension().map_or(false, |e| e == "n7t") {
-            println!("  Formatting {}...", path.display());
+        if path.extension().is_some_and(|e| e == "n7t") {
+            say!("  Formatting {}...", path.display());
 
             let source = fs::read_to_string(&path)
                 .map_err(|e| miette::miette!("Failed to read file: {}", e))?;
 
-            // シンプルなフォーマット: 末尾空白の削除、一貫したインデント
-            let formatted: Vec<String> = source
-                .lines()
-                .map(|line| {
-                    // 先頭のスペースをタブに変換（4スペース=1タブ）
-                    let leading_spaces = line.len() - line.trim_start().len();
-                    let tabs = leading_spaces / 4;
-                    let content = line.trim();
-                    if content.is_empty() {
-                        String::new()
-                    } else {
-                        format!("{}{}", "\t".repeat(tabs), content)
+            let formatted = match format_source(&source, options) {
+                Ok(formatted) => formatted,
+                Err(e) => {
+                    say!("    Left unchanged: {}", e);
+                    continue;
+                }
+            };
+            for line in &formatted.long_lines {
+                say!(
+                    "    Warning: line {} is longer than {} characters",
+                    line, options.max_width
+                );
+            }
+            fs::write(&path, formatted.text)
+                .map_err(|e| miette::miette!("Failed to write file: {}", e))?;
+        }
+    }
+    Ok(())
+}
+
+/// リンターの自動修正を適用する
+fn fix_project(file: Option<&str>) -> miette::Result<()> {
+    if let Some(file) = file {
+        return fix_file(&PathBuf::from(file));
+    }
+
+    let src_dir = PathBuf::from("src");
+    let dir = if src_dir.exists() {
+        src_dir
+    } else {
+        PathBuf::from(".")
+    };
+    for entry in fs::read_dir(&dir).map_err(|e| miette::miette!("Failed to read dir: {}", e))? {
+        let entry = entry.map_err(|e| miette::miette!("Failed to read entry: {}", e))?;
+        let path = entry.path();
+        if path.extension().is_some_and(|e| e == "n7t") {
+            fix_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+fn fix_file(path: &PathBuf) -> miette::Result<()> {
+    let source =
+        fs::read_to_string(path).map_err(|e| miette::miette!("Failed to read file: {}", e))?;
+
+    let suggestions = match lint::lint(&source) {
+        Ok(suggestions) => suggestions,
+        Err(e) => {
+            say!("  Skipping {}: {}", path.display(), e);
+            return Ok(());
+        }
+    };
+    if suggestions.is_empty() {
+        return Ok(());
+    }
+
+    say!("  Fixing {}...", path.display());
+    for suggestion in &suggestions {
+        say!("    line {}: {}", suggestion.line, suggestion.message);
+    }
+    fs::write(path, lint::apply_fixes(&source, &suggestions))
+        .map_err(|e| miette::miette!("Failed to write file: {}", e))?;
+    Ok(())
+}
+
+/// 名前の変更と関数の抽出
+/// (n7tya refactor rename <old> <new> --at file:line / n7tya refactor extract <name> --at file:start-end)
+fn refactor_command(args: &[String]) -> miette::Result<()> {
+    let usage = || {
+        say!("Usage: n7tya refactor rename <old> <new> --at <file>:<line>");
+        say!("       n7tya refactor extract <name> --at <file>:<start>-<end>");
+    };
+    let at = args.iter().position(|arg| arg == "--at");
+    let (Some(at), Some(command)) = (at, args.first()) else {
+        usage();
+        return Ok(());
+    };
+    let names: Vec<&str> = args[1..at].iter().map(String::as_str).collect();
+    let Some((file, lines)) = args.get(at + 1).and_then(|location| location.rsplit_once(':'))
+    else {
+        return Err(miette::miette!("--at expects <file>:<line>"));
+    };
+    let line = |text: &str| {
+        text.parse::<usize>()
+            .map_err(|_| miette::miette!("Invalid line number: {}", text))
+    };
+    let source =
+        fs::read_to_string(file).map_err(|e| miette::miette!("Failed to read file: {}", e))?;
+
+    let (edits, message) = match (command.as_str(), names.as_slice()) {
+        ("rename", [old, new]) => {
+            let edits = refactor::rename(&source, line(lines)?, old, new)
+                .map_err(|e| miette::miette!("{}", e))?;
+            let message = format!("✓ Renamed '{}' to '{}' ({} places)", old, new, edits.len());
+            (edits, message)
+        }
+        ("extract", [name]) => {
+            let (start, end) = lines.split_once('-').unwrap_or((lines, lines));
+            let edits = refactor::extract_function(&source, line(start)?..=line(end)?, name)
+                .map_err(|e| miette::miette!("{}", e))?;
+            (edits, format!("✓ Extracted function '{}'", name))
+        }
+        _ => {
+            usage();
+            return Ok(());
+        }
+    };
+    fs::write(file, refactor::apply(&source, &edits))
+        .map_err(|e| miette::miette!("Failed to write file: {}", e))?;
+    say!("{} in {}", message, file);
+    Ok(())
+}
+
+/// ベンチマークを実行
+/// (n7tya bench [dir] [--runs n] [--threshold pct] [--save file] [--compare file])
+fn bench_command(args: &[String]) -> miette::Result<()> {
+    let mut dir = PathBuf::from("benches/programs");
+    let mut runs = 5;
+    let mut threshold = bench::DEFAULT_THRESHOLD;
+    let mut save = None;
+    let mut baseline = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--runs" | "--threshold" | "--save" | "--compare" => {
+                let Some(value) = iter.next() else {
+                    return Err(miette::miette!("Missing value for {}", arg));
+                };
+                match arg.as_str() {
+                    "--runs" => {
+                        runs = value
+                            .parse()
+                            .map_err(|_| miette::miette!("Invalid value for --runs: {}", value))?
                     }
-                })
-                .collect();
+                    "--threshold" => {
+                        threshold = value.parse().map_err(|_| {
+                            miette::miette!("Invalid value for --threshold: {}", value)
+                        })?
+                    }
+                    "--save" => save = Some(PathBuf::from(value)),
+                    _ => baseline = Some(PathBuf::from(value)),
+                }
+            }
+            _ => dir = PathBuf::from(arg),
+        }
+    }
 
-            let formatted_content = formatted.join("\n") + "\n";
-            fs::write(&path, formatted_content)
-                .map_err(|e| miette::miette!("Failed to write file: {}", e))?;
+    say!("Running benchmarks in {}...", dir.display());
+    let results = bench::run_dir(&dir, runs).map_err(|e| miette::miette!("{}", e))?;
+
+    let mut regressions = 0;
+    match &baseline {
+        Some(path) => {
+            let text = fs::read_to_string(path)
+                .map_err(|e| miette::miette!("Failed to read file '{}': {}", path.display(), e))?;
+            let baseline = bench::from_json(&text).map_err(|e| miette::miette!("{}", e))?;
+            for c in bench::compare(&baseline, &results) {
+                let current = bench::format_ns(c.current_ns);
+                match (c.baseline_ns, c.change()) {
+                    (Some(base), Some(change)) if change > threshold => {
+                        regressions += 1;
+                        say!(
+                            "  {:<12} {:>10}  (baseline {}, {:+.1}%)  ✗",
+                            c.name,
+                            current,
+                            bench::format_ns(base),
+                            change
+                        );
+                    }
+                    (Some(base), Some(change)) => say!(
+                        "  {:<12} {:>10}  (baseline {}, {:+.1}%)",
+                        c.name,
+                        current,
+                        bench::format_ns(base),
+                        change
+                    ),
+                    _ => say!("  {:<12} {:>10}  (new)", c.name, current),
+                }
+            }
+        }
+        None => {
+            for r in &results {
+                say!("  {:<12} {:>10}", r.name, bench::format_ns(r.median_ns));
+            }
         }
     }
+
+    if let Some(path) = save {
+        fs::write(&path, bench::to_json(&results))
+            .map_err(|e| miette::miette!("Failed to write file '{}': {}", path.display(), e))?;
+        say!("✓ Results saved to {}", path.display());
+    }
+
+    if regressions > 0 {
+        return Err(miette::miette!(
+            "{} benchmark(s) regressed by more than {}%",
+            regressions,
+            threshold
+        ));
+    }
     Ok(())
 }
 
 fn print_help() {
-    println!("n7tya-lang v0.1.0");
-    println!("Full-stack web programming language by @n7tya");
-    println!();
-    println!("Usage: n7tya <command> [args]");
-    println!();
-    println!("Commands:");
-    println!("  run                     Run project (requires n7tya.toml)");
-    println!("  <file.n7t>              Run a specific file");
-    println!("  build                   Type check the project");
-    println!("  test                    Run tests (src/test_*.n7t)");
-    println!("  fmt                     Format code");
-    println!("  new <name>              Create a new project");
-    println!("  check <file>            Type check a specific file");
-    println!();
-    println!("Options:");
-    println!("  -v, --version           Show version information");
-    println!("  -h, --help              Show this help message");
-    println!("  --update                Update n7tya to the latest version");
+    say!("n7tya-lang v0.1.0");
+    say!("Full-stack web programming language by @n7tya");
+    say!();
+    say!("Usage: n7tya <command> [args]");
+    say!();
+    say!("Commands:");
+    say!("  run                     Run project (requires n7tya.toml)");
+    say!("  <file.n7t>              Run a specific file");
+    say!("    --allow-<kind>[=<list>] Only allow read/write/net/env/run access");
+    say!("    --timeout <secs>      Stop after this long (also --max-steps, --max-memory)");
+    say!("    --time-startup        Print how long startup and each phase took");
+    say!("    -- <args>             Pass the remaining arguments to the program (os.args())");
+    say!("  build                   Type check the project");
+    say!("  test                    Run tests (src/test_*.n7t)");
+    say!("  fmt                     Format code");
+    say!("  fix [file]              Apply machine-applicable lint fixes");
+    say!("  refactor rename <old> <new> --at <file>:<line>");
+    say!("                          Rename a definition and every use of it in the file");
+    say!("  refactor extract <name> --at <file>:<start>-<end>");
+    say!("                          Move the lines into a new function and call it");
+    say!("  gen-syntax --target <t> Print an editor grammar (tmlanguage, tree-sitter, ebnf)");
+    say!("  report <file> [--redact] Bundle a bug report archive for a file");
+    say!("  bench [dir]             Time the programs in benches/programs");
+    say!("    --runs <n>            Runs per program (default: 5)");
+    say!("    --threshold <pct>     Slowdown counted as a regression (default: 10)");
+    say!("    --save <file>         Save the results as JSON");
+    say!("    --compare <file>      Compare with saved results and fail on regressions");
+    say!("  doc                     Generate API docs into dist/doc");
+    say!("    --format <md|html>    Output format (default: html)");
+    say!("  new <name>              Create a new project");
+    say!("  add <git-url|path>      Add a dependency to n7tya.toml and install it");
+    say!("    --name <name>         Package name to import (default: from the source)");
+    say!("    --rev <rev>           Branch, tag or commit of a git dependency");
+    say!("  install                 Fetch the dependencies and update n7tya.lock");
+    say!("  check <file>            Type check a specific file");
+    say!();
+    say!("Options:");
+    say!("  -v, --version           Show version information");
+    say!("  -h, --help              Show this help message");
+    say!("  --update                Update n7tya to the latest version");
+    say!("  --lang ja|en            Language of messages (default: from locale)");
 }
 
 fn perform_update() -> miette::Result<()> {
-    println!("Updating n7tya-lang...");
+    say!("Updating n7tya-lang...");
 
     // Check if cargo is installed
     if std::process::Command::new("cargo")
@@ -468,7 +1229,7 @@ fn perform_update() -> miette::Result<()> {
         ));
     }
 
-    println!("Executing: cargo install --git https://github.com/n7tya/n7tya-lang --force");
+    say!("Executing: cargo install --git https://github.com/n7tya/n7tya-lang --force");
 
     // cargo install --git ...
     let status = std::process::Command::new("cargo")
@@ -482,7 +1243,7 @@ fn perform_update() -> miette::Result<()> {
         .map_err(|e| miette::miette!("Failed to execute cargo install: {}", e))?;
 
     if status.success() {
-        println!("✓ n7tya updated successfully!");
+        say!("✓ n7tya updated successfully!");
         // print new version
         let _ = std::process::Command::new("n7tya")
             .arg("--version")
@@ -0,0 +1,101 @@
+//! os モジュール (環境変数・コマンドライン引数・作業ディレクトリ・終了)
+//!
+//! os.args() は `n7tya script.n7t -- a b` の `--` より後の引数 (Interpreter::set_args で渡したもの)。
+//! os.env / os.set_env は --allow-env で許した名前だけ使える。
+
+use crate::interpreter::{Interpreter, Value};
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+/// os.env(name[, default]) / os.set_env(name, value) / os.args() / os.cwd() / os.platform() / os.exit(code)
+pub fn call(
+    interpreter: &mut Interpreter,
+    function: &str,
+    args: Vec<Value>,
+) -> Result<Value, String> {
+    match (function, args.as_slice()) {
+        ("env", [Value::Str(name), rest @ ..]) if rest.len() <= 1 => {
+            let default = rest.first().cloned().unwrap_or(Value::None);
+            Ok(std::env::var(&**name).map_or(default, |value| Value::Str(value.into())))
+        }
+        ("env", _) => Err("os.env() expects (name: Str, default?)".to_string()),
+        // none を渡すと環境変数を消す
+        ("set_env", [Value::Str(name), value]) => {
+            if name.is_empty() || name.contains(['=', '\0']) {
+                return Err(format!("os.set_env(): invalid variable name '{}'", name));
+            }
+            match value {
+                Value::None => std::env::remove_var(&**name),
+                Value::Str(value) if !value.contains('\0') => std::env::set_var(&**name, &**value),
+                _ => return Err("os.set_env() expects a string value or none".to_string()),
+            }
+            Ok(Value::None)
+        }
+        ("set_env", _) => Err("os.set_env() expects (name: Str, value: Str | None)".to_string()),
+        ("args", []) => {
+            let items = interpreter
+                .args()
+                .iter()
+                .map(|arg| Value::Str(arg.as_str().into()))
+                .collect();
+            Ok(Value::List(Rc::new(RefCell::new(items))))
+        }
+        ("cwd", []) => std::env::current_dir()
+            .map(|dir| Value::Str(dir.to_string_lossy().into_owned().into()))
+            .map_err(|e| format!("os.cwd() failed: {}", e)),
+        // "linux" / "macos" / "windows" など
+        ("platform", []) => Ok(Value::Str(std::env::consts::OS.into())),
+        ("exit", []) => exit(0),
+        ("exit", [Value::Int(code)]) => exit(i32::try_from(*code).unwrap_or(1)),
+        ("exit", _) => Err("os.exit() expects an integer exit code".to_string()),
+        ("args" | "cwd" | "platform", _) => Err(format!("os.{}() takes no arguments", function)),
+        _ => Err(format!("Module 'os' has no member '{}'", function)),
+    }
+}
+
+/// print で書いた出力を書き出してから終了する
+fn exit(code: i32) -> ! {
+    std::io::stdout().flush().ok();
+    std::process::exit(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn run(interpreter: &mut Interpreter, source: &str) -> Result<String, String> {
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        interpreter.run(&program).map(|value| value.display())
+    }
+
+    #[test]
+    fn test_os_module() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_args(vec!["--name".to_string(), "ann".to_string()]);
+        let source = "os.set_env(\"N7TYA_OS_TEST\", \"1\")\nlet before = os.env(\"N7TYA_OS_TEST\")\nos.set_env(\"N7TYA_OS_TEST\", none)\n[os.args(), before, os.env(\"N7TYA_OS_TEST\"), os.env(\"N7TYA_OS_TEST\", \"0\"), os.platform() == \"\", len(os.cwd()) > 0]\n";
+        assert_eq!(
+            run(&mut interpreter, source).unwrap(),
+            "[[--name, ann], 1, none, 0, false, true]"
+        );
+        assert!(run(&mut interpreter, "os.set_env(\"A=B\", \"1\")\n").is_err());
+        assert!(run(&mut interpreter, "os.exit(\"1\")\n").is_err());
+    }
+
+    #[test]
+    fn test_args_keep_options_after_double_dash() {
+        let mut argv: Vec<String> = ["n7tya", "run", "tool.n7t", "--", "--lang", "ja", "-v"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(crate::i18n::take_lang(&mut argv).unwrap(), None);
+        let mut interpreter = Interpreter::new();
+        interpreter.set_args(argv[4..].to_vec());
+        assert_eq!(
+            run(&mut interpreter, "os.args()\n").unwrap(),
+            "[--lang, ja, -v]"
+        );
+    }
+}
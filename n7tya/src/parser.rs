@@ -2,12 +2,68 @@
 
 use crate::ast::*;
 use crate::lexer::{Token, TokenInfo};
+use crate::visit::{self, Visitor};
 use miette::Result;
 
+/// インデントブロックの要素型。コメント・空行をその要素として保持できるかを表す
+trait BlockItem: Sized {
+    fn from_trivia(trivia: Trivia) -> Option<Self>;
+}
+
+impl BlockItem for Statement {
+    fn from_trivia(trivia: Trivia) -> Option<Self> {
+        Some(Statement::Trivia(trivia))
+    }
+}
+
+impl BlockItem for ClassBodyItem {
+    fn from_trivia(trivia: Trivia) -> Option<Self> {
+        Some(ClassBodyItem::Trivia(trivia))
+    }
+}
+
+impl BlockItem for ComponentBodyItem {
+    fn from_trivia(trivia: Trivia) -> Option<Self> {
+        Some(ComponentBodyItem::Trivia(trivia))
+    }
+}
+
+impl BlockItem for ServerBodyItem {
+    fn from_trivia(trivia: Trivia) -> Option<Self> {
+        Some(ServerBodyItem::Trivia(trivia))
+    }
+}
+
+// 構造体フィールド・インターフェースのメソッド宣言・match の case はトリビアを保持しない
+impl BlockItem for FieldDef {
+    fn from_trivia(_trivia: Trivia) -> Option<Self> {
+        None
+    }
+}
+
+impl BlockItem for MethodSignature {
+    fn from_trivia(_trivia: Trivia) -> Option<Self> {
+        None
+    }
+}
+
+impl BlockItem for MatchCase {
+    fn from_trivia(_trivia: Trivia) -> Option<Self> {
+        None
+    }
+}
+
+/// 決まった位置でだけキーワードになる名前 (ほかの位置では変数名などに使える)
+pub(crate) const SOFT_KEYWORDS: &[&str] = &["state", "render", "island", "lazy", "copy"];
+
+/// 式・ブロック・JSX のネストの上限 (深すぎる入力でスタックを溢れさせないため)
+pub(crate) const MAX_NESTING_DEPTH: usize = 100;
+
 pub struct Parser {
     tokens: Vec<TokenInfo>,
     current: usize,
-    indent_level: usize,
+    depth: usize,
+    loops: Vec<Option<String>>, // 囲んでいるループのラベル (内側が最後)
 }
 
 impl Parser {
@@ -15,26 +71,55 @@ impl Parser {
         Self {
             tokens,
             current: 0,
-            indent_level: 0,
+            depth: 0,
+            loops: Vec::new(),
         }
     }
 
     /// プログラム全体をパース
     pub fn parse(&mut self) -> Result<Program> {
+        let mut program = self.parse_syntax()?;
+        check_copies(&program)?;
+        crate::consteval::fold_constants(&mut program).map_err(|e| miette::miette!("{}", e))?;
+        Ok(program)
+    }
+
+    /// 書いたとおりの構文木を返す (定数の畳み込みと copy の検査はしない。フォーマッタ用)
+    pub fn parse_syntax(&mut self) -> Result<Program> {
         let mut items = Vec::new();
+        let mut blank_lines = 0;
 
         while !self.is_at_end() {
-            // トップレベルの改行はスキップ
+            // トップレベルの改行は空行として数える
             if self.match_token(Token::Newline) {
+                blank_lines += 1;
+                continue;
+            }
+            if blank_lines > 0 {
+                items.push(Item::Statement(Statement::Trivia(Trivia::BlankLines(
+                    blank_lines,
+                ))));
+                blank_lines = 0;
+            }
+
+            if let Some(trivia) = self.parse_comment_line() {
+                items.push(Item::Statement(Statement::Trivia(trivia)));
                 continue;
             }
 
+            let start = self.current;
             if let Some(item) = self.parse_item()? {
+                self.ensure_progress(start)?;
                 items.push(item);
+                if let Some(trivia) = self.take_trailing_comment() {
+                    items.push(Item::Statement(Statement::Trivia(trivia)));
+                }
             } else {
-                // パースできないトークンがあった場合、とりあえずエラーにするかスキップするか
-                // ここでは1つ進めてみる（簡易的なエラー回復）
-                self.advance();
+                return Err(miette::miette!(
+                    "Unexpected {:?} at line {}",
+                    self.peek_token(),
+                    self.current_line()
+                ));
             }
         }
 
@@ -50,14 +135,54 @@ impl Parser {
             return Ok(Some(Item::ClassDef(self.parse_class_def()?)));
         }
 
+        if self.match_token(Token::Struct) {
+            return Ok(Some(Item::StructDef(self.parse_struct_def()?)));
+        }
+
+        if self.match_token(Token::Interface) {
+            return Ok(Some(Item::InterfaceDef(self.parse_interface_def()?)));
+        }
+
         if self.match_token(Token::Component) {
             return Ok(Some(Item::ComponentDef(self.parse_component_def()?)));
         }
 
+        // island component Name (island は component が続くときだけ修飾子として扱う)
+        if matches!(self.peek_token(), Some(Token::Identifier(word)) if word == "island")
+            && matches!(
+                self.tokens.get(self.current + 1).map(|t| &t.token),
+                Some(Token::Component)
+            )
+        {
+            self.current += 2;
+            let mut component = self.parse_component_def()?;
+            component.island = true;
+            return Ok(Some(Item::ComponentDef(component)));
+        }
+
         if self.match_token(Token::Server) {
             return Ok(Some(Item::ServerDef(self.parse_server_def()?)));
         }
 
+        // lazy import X (lazy は import が続くときだけ修飾子として扱う)
+        if matches!(self.peek_token(), Some(Token::Identifier(word)) if word == "lazy")
+            && matches!(
+                self.tokens.get(self.current + 1).map(|t| &t.token),
+                Some(Token::Import | Token::From)
+            )
+        {
+            self.current += 1;
+            if self.match_token(Token::From) {
+                return Err(miette::miette!(
+                    "'lazy' can only be used with 'import module', not 'from module import'"
+                ));
+            }
+            self.current += 1;
+            let mut import = self.parse_import()?;
+            import.lazy = true;
+            return Ok(Some(Item::Import(import)));
+        }
+
         // Import文
         if self.match_token(Token::Import) {
             return Ok(Some(Item::Import(self.parse_import()?)));
@@ -89,21 +214,50 @@ impl Parser {
             None
         };
 
+        // class Name [Parent] implements A, B
+        let mut interfaces = Vec::new();
+        if self.match_token(Token::Implements) {
+            loop {
+                let interface = self.consume_identifier("Expect interface name after 'implements'")?;
+                interfaces.push(interface);
+                if !self.match_token(Token::Comma) {
+                    break;
+                }
+            }
+        }
+
         self.consume(Token::Newline, "Expect newline after class name")?;
 
+        let doc = self.parse_docstring();
         let body = self.parse_indented_block(|parser| {
             if parser.match_token(Token::Def) {
                 let func = parser.parse_function_def()?;
                 return Ok(Some(ClassBodyItem::Method(func)));
             }
+            if parser.match_token(Token::Static) {
+                parser.consume(Token::Def, "Expect 'def' after 'static'")?;
+                let func = parser.parse_function_def()?;
+                return Ok(Some(ClassBodyItem::StaticMethod(func)));
+            }
+            if parser.match_token(Token::Const) {
+                return Ok(Some(ClassBodyItem::Const(parser.parse_const()?)));
+            }
             if let Some(Token::Identifier(id)) = parser.peek_token().cloned() {
                 parser.advance();
                 if parser.match_token(Token::Colon) {
                     let type_annotation = parser.parse_type_annotation()?;
+                    let checks = parser.parse_field_checks()?;
+                    let default = if parser.match_token(Token::Assign) {
+                        Some(parser.parse_expression()?)
+                    } else {
+                        None
+                    };
                     parser.consume(Token::Newline, "Expect newline after field definition")?;
                     return Ok(Some(ClassBodyItem::Field(FieldDef {
                         name: id,
                         type_annotation,
+                        default,
+                        checks,
                     })));
                 } else {
                     return Err(miette::miette!("Expect ':' for field definition"));
@@ -112,7 +266,97 @@ impl Parser {
             Ok(None)
         })?;
 
-        Ok(ClassDef { name, parent, body })
+        Ok(ClassDef {
+            name,
+            parent,
+            interfaces,
+            body,
+            doc,
+        })
+    }
+
+    fn parse_interface_def(&mut self) -> Result<InterfaceDef> {
+        let name = self.consume_identifier("Expect interface name")?;
+        self.consume(Token::Newline, "Expect newline after interface name")?;
+
+        let methods = self.parse_indented_block(|parser| {
+            if parser.match_token(Token::Def) {
+                let signature = parser.parse_signature()?;
+                parser.consume(Token::Newline, "Expect newline after method signature")?;
+                if parser.check(Token::Indent) {
+                    return Err(miette::miette!(
+                        "Interface method '{}' cannot have a body",
+                        signature.name
+                    ));
+                }
+                return Ok(Some(signature));
+            }
+            Ok(None)
+        })?;
+
+        Ok(InterfaceDef { name, methods })
+    }
+
+    fn parse_struct_def(&mut self) -> Result<StructDef> {
+        let name = self.consume_identifier("Expect struct name")?;
+        self.consume(Token::Newline, "Expect newline after struct name")?;
+
+        let fields = self.parse_indented_block(|parser| {
+            if let Some(Token::Identifier(id)) = parser.peek_token().cloned() {
+                parser.advance();
+                parser.consume(Token::Colon, "Expect ':' for struct field definition")?;
+                let type_annotation = parser.parse_type_annotation()?;
+                let checks = parser.parse_field_checks()?;
+                parser.match_token(Token::Newline);
+                return Ok(Some(FieldDef {
+                    name: id,
+                    type_annotation,
+                    default: None,
+                    checks,
+                }));
+            }
+            Ok(None)
+        })?;
+
+        Ok(StructDef { name, fields })
+    }
+
+    /// フィールドの型のあとの `@min(0) @max(120)` (引数はリテラルか負の数)
+    fn parse_field_checks(&mut self) -> Result<Vec<FieldCheck>> {
+        let mut checks = Vec::new();
+        while self.match_token(Token::At) {
+            let name = self.consume_identifier("Expect a check name after '@'")?;
+            let not_literal = || miette::miette!("@{} expects literal arguments", name);
+            let mut args = Vec::new();
+            if self.match_token(Token::LParen) {
+                while !self.check(Token::RParen) {
+                    let arg = match self.parse_expression()? {
+                        Expression::Literal(literal) => literal,
+                        Expression::UnaryOp(unary) => match *unary {
+                            UnaryExpr {
+                                op: UnaryOp::Neg,
+                                operand: Expression::Literal(Literal::Int(n)),
+                            } => Literal::Int(-n),
+                            UnaryExpr {
+                                op: UnaryOp::Neg,
+                                operand: Expression::Literal(Literal::Float(f)),
+                            } => Literal::Float(-f),
+                            _ => return Err(not_literal()),
+                        },
+                        _ => return Err(not_literal()),
+                    };
+                    args.push(arg);
+                    if !self.match_token(Token::Comma) {
+                        break;
+                    }
+                }
+                self.consume(Token::RParen, "Expect ')' after check arguments")?;
+            }
+            let check = FieldCheck { name, args };
+            crate::validate::check_definition(&check).map_err(|e| miette::miette!("{}", e))?;
+            checks.push(check);
+        }
+        Ok(checks)
     }
 
     fn parse_server_def(&mut self) -> Result<ServerDef> {
@@ -120,12 +364,11 @@ impl Parser {
         self.consume(Token::Newline, "Expect newline after server name")?;
 
         let body = self.parse_indented_block(|parser| {
-            // メソッド名を取得（Identifier または Route キーワード）
+            // メソッド名を取得 (GET / post / route など)
+            let line = parser.current_line();
             let method = if let Some(Token::Identifier(s)) = parser.peek_token().cloned() {
                 parser.advance();
                 s
-            } else if parser.match_token(Token::Route) {
-                "route".to_string()
             } else {
                 return Ok(None);
             };
@@ -133,14 +376,40 @@ impl Parser {
             let path_token = parser.peek_token().cloned();
             if let Some(Token::StringLiteral(path) | Token::MultiLineString(path)) = path_token {
                 parser.advance(); // consume path
+
+                // (user: User) -> Json<User>
+                let body_param = if parser.match_token(Token::LParen) {
+                    let name = parser.consume_identifier("Expect request body name")?;
+                    parser.consume(Token::Colon, "Expect ':' after request body name")?;
+                    let ty = parser.parse_type_annotation()?;
+                    parser.consume(Token::RParen, "Expect ')' after request body type")?;
+                    Some(Param {
+                        name,
+                        type_annotation: Some(ty),
+                    })
+                } else {
+                    None
+                };
+                let return_type = if parser.match_token(Token::Arrow) {
+                    Some(parser.parse_type_annotation()?)
+                } else {
+                    None
+                };
                 parser.consume(Token::Newline, "Expect newline after route path")?;
                 let body = parser.parse_block()?;
-                return Ok(Some(ServerBodyItem::Route(RouteDef { path, method, body })));
+                Ok(Some(ServerBodyItem::Route(RouteDef {
+                    path,
+                    method,
+                    body_param,
+                    return_type,
+                    body,
+                    line,
+                })))
             } else {
-                return Err(miette::miette!(
+                Err(miette::miette!(
                     "Expect string literal (path) after route method, got {:?}",
                     parser.peek_token()
-                ));
+                ))
             }
         })?;
 
@@ -148,11 +417,13 @@ impl Parser {
     }
 
     fn parse_component_def(&mut self) -> Result<ComponentDef> {
+        let line = self.previous_line();
         let name = self.consume_identifier("Expect component name")?;
         self.consume(Token::Newline, "Expect newline after component name")?;
 
+        let doc = self.parse_docstring();
         let body = self.parse_indented_block(|parser| {
-            if parser.match_token(Token::State) {
+            if parser.match_soft_keyword("state") {
                 let state = parser.parse_state_decl()?;
                 return Ok(Some(ComponentBodyItem::State(state)));
             }
@@ -160,7 +431,7 @@ impl Parser {
                 let func = parser.parse_function_def()?;
                 return Ok(Some(ComponentBodyItem::Method(func)));
             }
-            if parser.match_token(Token::Render) {
+            if parser.match_soft_keyword("render") {
                 let render = parser.parse_render_block()?;
                 return Ok(Some(ComponentBodyItem::Render(render)));
             }
@@ -169,11 +440,46 @@ impl Parser {
             Ok(None)
         })?;
 
-        Ok(ComponentDef { name, body })
+        Ok(ComponentDef {
+            name,
+            body,
+            island: false,
+            doc,
+            line,
+        })
     }
 
     fn parse_function_def(&mut self) -> Result<FunctionDef> {
         // "def" は既に消費済み
+        let line = self.previous_line();
+        let MethodSignature {
+            name,
+            params,
+            return_type,
+        } = self.parse_signature()?;
+
+        self.consume(Token::Newline, "Expect newline after function signature")?;
+
+        // 関数本体 (ループの中で定義しても、本体の break はそのループを指さない)
+        let doc = self.parse_docstring();
+        let loops = std::mem::take(&mut self.loops);
+        let body = self.parse_block();
+        self.loops = loops;
+        let body = body?;
+
+        Ok(FunctionDef {
+            name,
+            params,
+            return_type,
+            body,
+            is_async: false, // TODO: async keyword check
+            doc,
+            line,
+        })
+    }
+
+    /// 関数名・パラメータ・戻り値型 (行末の改行は消費しない)
+    fn parse_signature(&mut self) -> Result<MethodSignature> {
         let name = self.consume_identifier("Expect function name")?;
 
         // パラメータ
@@ -210,30 +516,53 @@ impl Parser {
             return_type = Some(self.parse_type_annotation()?);
         }
 
-        self.consume(Token::Newline, "Expect newline after function signature")?;
-
-        // 関数本体
-        let body = self.parse_block()?;
-
-        Ok(FunctionDef {
+        Ok(MethodSignature {
             name,
             params,
             return_type,
-            body,
-            is_async: false, // TODO: async keyword check
         })
     }
 
+    /// 型注釈 (Int | none のような合併型を含む)
     fn parse_type_annotation(&mut self) -> Result<Type> {
+        let first = self.parse_optional_type()?;
+        if !self.check(Token::Pipe) {
+            return Ok(first);
+        }
+        let mut members = vec![first];
+        while self.match_token(Token::Pipe) {
+            members.push(self.parse_optional_type()?);
+        }
+        Ok(Type::Union(members))
+    }
+
+    /// 単一の型 (末尾の ? で Optional になる)
+    fn parse_optional_type(&mut self) -> Result<Type> {
+        let ty = if self.match_token(Token::None) {
+            Type::None
+        } else {
+            self.parse_single_type()?
+        };
+        if self.match_token(Token::Question) {
+            return Ok(Type::Optional(Box::new(ty)));
+        }
+        Ok(ty)
+    }
+
+    fn parse_single_type(&mut self) -> Result<Type> {
         let name = self.consume_identifier("Expect type name")?;
 
-        if name == "List" {
+        if name == "List" || name == "Json" {
             if self.match_token(Token::Lt) {
-                let inner = self.parse_type_annotation()?;
+                let inner = Box::new(self.parse_type_annotation()?);
                 self.consume(Token::Gt, "Expect '>' after generic type")?;
-                return Ok(Type::List(Box::new(inner)));
+                return Ok(if name == "List" {
+                    Type::List(inner)
+                } else {
+                    Type::Json(inner)
+                });
             } else {
-                return Err(miette::miette!("Expect generic argument for List"));
+                return Err(miette::miette!("Expect generic argument for {}", name));
             }
         }
 
@@ -256,69 +585,133 @@ impl Parser {
 
     /// ブロック（インデントされた一連の文）をパース
     fn parse_block(&mut self) -> Result<Vec<Statement>> {
-        self.parse_indented_block(|parser| parser.parse_statement())
+        self.nested(|parser| parser.parse_indented_block(|parser| parser.parse_statement()))
     }
 
-    /// 汎用的なインデントブロックパース
+    /// 汎用的なインデントブロックパース (Indent から対応する Dedent まで)
     fn parse_indented_block<T, F>(&mut self, mut parse_fn: F) -> Result<Vec<T>>
     where
+        T: BlockItem,
         F: FnMut(&mut Self) -> Result<Option<T>>,
     {
         let mut items = Vec::new();
+        let mut blank_lines = 0;
 
-        // インデントが増えていることを確認
-        self.indent_level += 1;
+        // ヘッダ行 (def f ... など) の行末コメントはブロックの先頭に置く
+        if let Some(item) = self.take_trailing_comment().and_then(T::from_trivia) {
+            items.push(item);
+        }
 
-        while !self.is_at_end() {
-            // 行頭のインデントチェック
-            let current_indent = self.count_indent();
+        // 次の行がインデントされていなければ空のブロック
+        if !self.match_token(Token::Indent) {
+            return Ok(items);
+        }
 
-            if current_indent < self.indent_level {
-                // インデントが戻ったらブロック終了
-                break;
+        while !self.is_at_end() && !self.match_token(Token::Dedent) {
+            // ブロックの途中で深くなった行 (ネストしたブロックはヘッダの後で読み終えている)
+            if self.check(Token::Indent) {
+                return Err(miette::miette!(
+                    "Unexpected indent at line {}",
+                    self.current_line()
+                ));
             }
 
-            // インデントトークンを消費
-            // Note: current_indent > indent_level の場合は、
-            // その行のインデントを全て消費してから parse_fn に委ねる。
-            // ネストしたブロックは再帰的に parse_indented_block が呼ばれることで処理される。
-            for _ in 0..current_indent {
-                if self.check(Token::Tab) {
-                    self.advance();
-                }
+            // 空行は数えておき、次の要素の前にまとめて記録する
+            if self.match_token(Token::Newline) {
+                blank_lines += 1;
+                continue;
+            }
+            if blank_lines > 0 {
+                items.extend(T::from_trivia(Trivia::BlankLines(blank_lines)));
+                blank_lines = 0;
             }
 
-            // 空行はスキップ
-            if self.match_token(Token::Newline) {
+            if let Some(trivia) = self.parse_comment_line() {
+                items.extend(T::from_trivia(trivia));
                 continue;
             }
 
-            if let Some(item) = parse_fn(self)? {
-                items.push(item);
-            } else {
-                // 認識できないアイテムがあった場合、ブロック終了とみなす
-                // エラー回復を入れるならここでスキップ処理が必要
-                break;
+            let start = self.current;
+            match parse_fn(self)? {
+                Some(item) => {
+                    self.ensure_progress(start)?;
+                    items.push(item);
+                    if let Some(item) = self.take_trailing_comment().and_then(T::from_trivia) {
+                        items.push(item);
+                    }
+                }
+                // ブロックに書けないもの (クラスの中の文など) はエラーにする
+                None => {
+                    return Err(miette::miette!(
+                        "Unexpected {:?} at line {}",
+                        self.peek_token(),
+                        self.current_line()
+                    ))
+                }
             }
         }
 
-        self.indent_level -= 1;
+        if blank_lines > 0 {
+            items.extend(T::from_trivia(Trivia::BlankLines(blank_lines)));
+        }
         Ok(items)
     }
 
-    /// 現在の行のインデント（タブ数）をカウント（消費はしない）
-    fn count_indent(&self) -> usize {
-        let mut count = 0;
-        let mut i = self.current;
-        while i < self.tokens.len() {
-            if matches!(self.tokens[i].token, Token::Tab) {
-                count += 1;
-                i += 1;
-            } else {
-                break;
-            }
+    /// 行コメントのみの行を読み取る
+    fn parse_comment_line(&mut self) -> Option<Trivia> {
+        if let Some(Token::Comment(text)) = self.peek_token().cloned() {
+            self.advance();
+            self.match_token(Token::Newline);
+            return Some(Trivia::Comment {
+                text,
+                trailing: false,
+            });
+        }
+        None
+    }
+
+    /// 直前に消費した改行に付いている行末コメントを取り出す (二重に記録しないよう take する)
+    fn take_trailing_comment(&mut self) -> Option<Trivia> {
+        if self.current == 0 {
+            return None;
+        }
+        let prev = &mut self.tokens[self.current - 1];
+        if !matches!(prev.token, Token::Newline) {
+            return None;
         }
-        count
+        prev.trailing_comment.take().map(|text| Trivia::Comment {
+            text,
+            trailing: true,
+        })
+    }
+
+    /// ブロックの先頭行が文字列リテラルだけなら docstring として読み進める
+    ///
+    /// ヘッダ行の行末コメントは docstring の行に移し、続くブロックの先頭に残す
+    fn parse_docstring(&mut self) -> Option<String> {
+        let i = self.current;
+        if !matches!(self.tokens.get(i).map(|t| &t.token), Some(Token::Indent)) {
+            return None;
+        }
+        let text = match self.tokens.get(i + 1).map(|t| &t.token) {
+            Some(Token::StringLiteral(s) | Token::MultiLineString(s)) => s.clone(),
+            _ => return None,
+        };
+        if !matches!(
+            self.tokens.get(i + 2).map(|t| &t.token),
+            Some(Token::Newline)
+        ) {
+            return None;
+        }
+        let header_comment = self.tokens[i - 1].trailing_comment.take();
+        let newline = &mut self.tokens[i + 2];
+        if newline.trailing_comment.is_none() {
+            newline.trailing_comment = header_comment;
+        }
+        // Indent を docstring の行の後ろに移し、残りの行をブロックとして読めるようにする
+        self.tokens[i..i + 3].rotate_left(1);
+        self.current = i + 2;
+        Some(dedent_doc(&text))
     }
 
     fn parse_statement(&mut self) -> Result<Option<Statement>> {
@@ -328,10 +721,10 @@ impl Parser {
         if self.match_token(Token::Const) {
             return Ok(Some(Statement::Const(self.parse_const()?)));
         }
-        if self.match_token(Token::State) {
+        if self.match_soft_keyword("state") {
             return Ok(Some(Statement::State(self.parse_state_decl()?)));
         }
-        if self.match_token(Token::Render) {
+        if self.match_soft_keyword("render") {
             return Ok(Some(Statement::Render(self.parse_render_block()?)));
         }
         if self.match_token(Token::Return) {
@@ -344,42 +737,83 @@ impl Parser {
             return Ok(Some(Statement::Return(expr)));
         }
         if self.match_token(Token::Break) {
+            let label = self.loop_label("break")?;
             self.match_token(Token::Newline);
-            return Ok(Some(Statement::Break));
+            return Ok(Some(Statement::Break(label)));
         }
         if self.match_token(Token::Continue) {
+            let label = self.loop_label("continue")?;
             self.match_token(Token::Newline);
-            return Ok(Some(Statement::Continue));
+            return Ok(Some(Statement::Continue(label)));
         }
         if self.match_token(Token::If) {
             return Ok(Some(Statement::If(self.parse_if()?)));
         }
         if self.match_token(Token::While) {
-            return Ok(Some(Statement::While(self.parse_while()?)));
+            return Ok(Some(Statement::While(self.parse_while(None)?)));
         }
         if self.match_token(Token::For) {
-            return Ok(Some(Statement::For(self.parse_for()?)));
+            return Ok(Some(Statement::For(self.parse_for(None)?)));
+        }
+        // outer: for ... / outer: while ...
+        if let (
+            Some(Token::Identifier(label)),
+            Some(Token::Colon),
+            Some(Token::For | Token::While),
+        ) = (
+            self.peek_token().cloned(),
+            self.tokens.get(self.current + 1).map(|t| &t.token),
+            self.tokens.get(self.current + 2).map(|t| &t.token),
+        ) {
+            if self.loops.contains(&Some(label.clone())) {
+                return Err(miette::miette!(
+                    "Loop label '{}' is already used by an enclosing loop",
+                    label
+                ));
+            }
+            self.current += 2;
+            if self.match_token(Token::While) {
+                return Ok(Some(Statement::While(self.parse_while(Some(label))?)));
+            }
+            self.advance();
+            return Ok(Some(Statement::For(self.parse_for(Some(label))?)));
         }
         if self.match_token(Token::Match) {
             return Ok(Some(Statement::Match(self.parse_match()?)));
         }
-
-        // 式文 or 代入
-        if let Ok(expr) = self.parse_expression() {
-            if self.match_token(Token::Assign) {
-                let value = self.parse_expression()?;
-                self.match_token(Token::Newline);
-                return Ok(Some(Statement::Assignment(AssignmentStmt {
-                    target: expr,
-                    value,
-                })));
+        if self.match_token(Token::Def) {
+            return Ok(Some(Statement::FunctionDef(self.parse_function_def()?)));
+        }
+        if self.match_token(Token::Nonlocal) {
+            let mut names = vec![self.consume_identifier("Expect variable name after nonlocal")?];
+            while self.match_token(Token::Comma) {
+                names.push(self.consume_identifier("Expect variable name after ','")?);
             }
+            self.match_token(Token::Newline);
+            return Ok(Some(Statement::Nonlocal(names)));
+        }
+        if self.match_soft_keyword("copy") {
+            let mut names = vec![self.consume_identifier("Expect variable name after copy")?];
+            while self.match_token(Token::Comma) {
+                names.push(self.consume_identifier("Expect variable name after ','")?);
+            }
+            self.match_token(Token::Newline);
+            return Ok(Some(Statement::Copy(names)));
+        }
 
+        // 式文 or 代入 (式として読めなければそのエラーを返す)
+        let expr = self.parse_expression()?;
+        if self.match_token(Token::Assign) {
+            let value = self.parse_expression()?;
             self.match_token(Token::Newline);
-            return Ok(Some(Statement::Expression(expr)));
+            return Ok(Some(Statement::Assignment(AssignmentStmt {
+                target: expr,
+                value,
+            })));
         }
 
-        Ok(None)
+        self.match_token(Token::Newline);
+        Ok(Some(Statement::Expression(expr)))
     }
 
     fn parse_let(&mut self) -> Result<LetDecl> {
@@ -439,6 +873,7 @@ impl Parser {
             module,
             names: vec![],
             alias,
+            lazy: false,
         })
     }
 
@@ -468,6 +903,7 @@ impl Parser {
             module,
             names,
             alias: None,
+            lazy: false,
         })
     }
 
@@ -489,6 +925,52 @@ impl Parser {
     }
 
     fn parse_pattern(&mut self) -> Result<Pattern> {
+        // リストパターン [a, b, *rest]
+        if self.match_token(Token::LBracket) {
+            let mut elements = Vec::new();
+            let mut has_rest = false;
+            while !self.check(Token::RBracket) && !self.is_at_end() {
+                if self.match_token(Token::Star) {
+                    if has_rest {
+                        return Err(miette::miette!(
+                            "Only one '*rest' is allowed in a list pattern"
+                        ));
+                    }
+                    has_rest = true;
+                    elements.push(Pattern::Rest(self.consume_identifier("Expect name after '*'")?));
+                } else {
+                    elements.push(self.parse_pattern()?);
+                }
+                if !self.match_token(Token::Comma) {
+                    break;
+                }
+            }
+            self.consume(Token::RBracket, "Expect ']' after list pattern")?;
+            return Ok(Pattern::List(elements));
+        }
+        // 辞書パターン {"key": pattern}
+        if self.match_token(Token::LBrace) {
+            let mut entries = Vec::new();
+            while !self.check(Token::RBrace) && !self.is_at_end() {
+                let key = match self.peek_token().cloned() {
+                    Some(Token::StringLiteral(k)) => {
+                        self.advance();
+                        k
+                    }
+                    _ => return Err(miette::miette!("Expect string key in dict pattern")),
+                };
+                self.consume(Token::Colon, "Expect ':' after dict pattern key")?;
+                entries.push((key, self.parse_pattern()?));
+                if !self.match_token(Token::Comma) {
+                    break;
+                }
+            }
+            self.consume(Token::RBrace, "Expect '}' after dict pattern")?;
+            return Ok(Pattern::Dict(entries));
+        }
+        if self.match_token(Token::None) {
+            return Ok(Pattern::Literal(Literal::None));
+        }
         if let Some(Token::IntLiteral(n)) = self.peek_token().cloned() {
             self.advance();
             return Ok(Pattern::Literal(Literal::Int(n)));
@@ -509,23 +991,86 @@ impl Parser {
             if name == "_" {
                 return Ok(Pattern::Wildcard);
             }
+            if name == "None" {
+                return Ok(Pattern::Literal(Literal::None));
+            }
+            // Ok(v) / Err(e) / Some(x)
+            if matches!(name.as_str(), "Ok" | "Err" | "Some") && self.match_token(Token::LParen) {
+                let inner = self.parse_pattern()?;
+                self.consume(Token::RParen, "Expect ')' after pattern")?;
+                return Ok(Pattern::Variant(name, Box::new(inner)));
+            }
+            // Point(x, 0) / User {name: n}
+            if self.match_token(Token::LParen) {
+                let mut fields = Vec::new();
+                while !self.check(Token::RParen) && !self.is_at_end() {
+                    fields.push(self.parse_pattern()?);
+                    if !self.match_token(Token::Comma) {
+                        break;
+                    }
+                }
+                self.consume(Token::RParen, "Expect ')' after constructor pattern")?;
+                return Ok(Pattern::Constructor(name, fields));
+            }
+            if self.match_token(Token::LBrace) {
+                let mut fields = Vec::new();
+                while !self.check(Token::RBrace) && !self.is_at_end() {
+                    let field = self.consume_identifier("Expect field name in pattern")?;
+                    // {name} は {name: name} と同じ
+                    let pattern = if self.match_token(Token::Colon) {
+                        self.parse_pattern()?
+                    } else {
+                        Pattern::Identifier(field.clone())
+                    };
+                    fields.push((field, pattern));
+                    if !self.match_token(Token::Comma) {
+                        break;
+                    }
+                }
+                self.consume(Token::RBrace, "Expect '}' after field pattern")?;
+                return Ok(Pattern::Fields(name, fields));
+            }
             return Ok(Pattern::Identifier(name));
         }
         Err(miette::miette!("Invalid pattern"))
     }
 
     fn parse_state_decl(&mut self) -> Result<StateDecl> {
+        // state session cart = [] のように名前の前に保存先を書ける (予約語ではない)
+        let mut persist = None;
+        if let (Some(Token::Identifier(word)), Some(Token::Identifier(_))) = (
+            self.peek_token(),
+            self.tokens.get(self.current + 1).map(|t| &t.token),
+        ) {
+            persist = match word.as_str() {
+                "memory" => Some(Persist::Memory),
+                "local" => Some(Persist::Local),
+                "session" => Some(Persist::Session),
+                _ => {
+                    return Err(miette::miette!(
+                        "Unknown state persistence '{}' (expected memory, local or session)",
+                        word
+                    ))
+                }
+            };
+            self.advance();
+        }
         let name = self.consume_identifier("Expect state name")?;
         self.consume(Token::Assign, "Expect '='")?;
         let value = self.parse_expression()?;
         self.match_token(Token::Newline);
-        Ok(StateDecl { name, value })
+        Ok(StateDecl {
+            name,
+            value,
+            persist,
+        })
     }
 
     fn parse_render_block(&mut self) -> Result<RenderBlock> {
+        let line = self.previous_line();
         self.consume(Token::Newline, "Expect newline after render")?;
         let body = self.parse_block()?;
-        Ok(RenderBlock { body })
+        Ok(RenderBlock { body, line })
     }
 
     fn parse_if(&mut self) -> Result<IfStmt> {
@@ -534,6 +1079,7 @@ impl Parser {
         let then_block = self.parse_block()?;
 
         let mut else_block = None;
+        let mut elif = false;
         if self.match_token(Token::Else) {
             self.consume(Token::Newline, "Expect newline after else")?;
             else_block = Some(self.parse_block()?);
@@ -542,37 +1088,72 @@ impl Parser {
             // Pythonのように `elif cond:` -> `else: if cond:`
             let elif_stmt = Statement::If(self.parse_if()?);
             else_block = Some(vec![elif_stmt]);
+            elif = true;
         }
 
         Ok(IfStmt {
             condition,
             then_block,
             else_block,
+            elif,
         })
     }
 
-    fn parse_while(&mut self) -> Result<WhileStmt> {
+    fn parse_while(&mut self, label: Option<String>) -> Result<WhileStmt> {
         let condition = self.parse_expression()?;
         self.consume(Token::Newline, "Expect newline after while condition")?;
-        let body = self.parse_block()?;
-        Ok(WhileStmt { condition, body })
+        let body = self.parse_loop_body(&label)?;
+        Ok(WhileStmt {
+            condition,
+            body,
+            label,
+        })
     }
 
-    fn parse_for(&mut self) -> Result<ForStmt> {
+    fn parse_for(&mut self, label: Option<String>) -> Result<ForStmt> {
         let target = self.consume_identifier("Expect for loop variable")?;
         self.consume(Token::In, "Expect 'in' after for loop variable")?;
         let iterator = self.parse_expression()?;
         self.consume(Token::Newline, "Expect newline after for loop header")?;
-        let body = self.parse_block()?;
+        let body = self.parse_loop_body(&label)?;
         Ok(ForStmt {
             target,
             iterator,
             body,
+            label,
         })
     }
 
+    /// ループの本体 (中の break / continue はこのループか外側のループを指す)
+    fn parse_loop_body(&mut self, label: &Option<String>) -> Result<Vec<Statement>> {
+        self.loops.push(label.clone());
+        let body = self.parse_block();
+        self.loops.pop();
+        body
+    }
+
+    /// break / continue の後のラベル (囲んでいるループのものか確かめる)
+    fn loop_label(&mut self, keyword: &str) -> Result<Option<String>> {
+        let label = match self.peek_token() {
+            Some(Token::Identifier(name)) => Some(name.clone()),
+            _ => None,
+        };
+        if label.is_some() {
+            self.advance();
+        }
+        if self.loops.is_empty() {
+            return Err(miette::miette!("'{}' outside of a loop", keyword));
+        }
+        if let Some(name) = &label {
+            if !self.loops.contains(&label) {
+                return Err(miette::miette!("Unknown loop label '{}'", name));
+            }
+        }
+        Ok(label)
+    }
+
     fn parse_expression(&mut self) -> Result<Expression> {
-        self.parse_logic_or()
+        self.nested(|parser| parser.parse_logic_or())
     }
 
     fn parse_logic_or(&mut self) -> Result<Expression> {
@@ -604,9 +1185,9 @@ impl Parser {
     fn parse_equality(&mut self) -> Result<Expression> {
         let mut expr = self.parse_comparison()?;
         while self.match_token(Token::Eq) || self.match_token(Token::NotEq) {
-            let op = match self.previous().token {
-                Token::Eq => BinaryOp::Eq,
-                Token::NotEq => BinaryOp::Ne,
+            let op = match self.previous_token() {
+                Some(Token::Eq) => BinaryOp::Eq,
+                Some(Token::NotEq) => BinaryOp::Ne,
                 _ => unreachable!(),
             };
             let right = self.parse_comparison()?;
@@ -625,12 +1206,14 @@ impl Parser {
             || self.match_token(Token::Gt)
             || self.match_token(Token::LtEq)
             || self.match_token(Token::GtEq)
+            || self.match_token(Token::In)
         {
-            let op = match self.previous().token {
-                Token::Lt => BinaryOp::Lt,
-                Token::Gt => BinaryOp::Gt,
-                Token::LtEq => BinaryOp::Le,
-                Token::GtEq => BinaryOp::Ge,
+            let op = match self.previous_token() {
+                Some(Token::Lt) => BinaryOp::Lt,
+                Some(Token::Gt) => BinaryOp::Gt,
+                Some(Token::LtEq) => BinaryOp::Le,
+                Some(Token::GtEq) => BinaryOp::Ge,
+                Some(Token::In) => BinaryOp::In,
                 _ => unreachable!(),
             };
             let right = self.parse_term()?;
@@ -648,9 +1231,9 @@ impl Parser {
         let mut expr = self.parse_factor()?;
 
         while self.match_token(Token::Plus) || self.match_token(Token::Minus) {
-            let op = match self.previous().token {
-                Token::Plus => BinaryOp::Add,
-                Token::Minus => BinaryOp::Sub,
+            let op = match self.previous_token() {
+                Some(Token::Plus) => BinaryOp::Add,
+                Some(Token::Minus) => BinaryOp::Sub,
                 _ => unreachable!(),
             };
             let right = self.parse_factor()?;
@@ -672,10 +1255,10 @@ impl Parser {
             || self.match_token(Token::Slash)
             || self.match_token(Token::Percent)
         {
-            let op = match self.previous().token {
-                Token::Star => BinaryOp::Mul,
-                Token::Slash => BinaryOp::Div,
-                Token::Percent => BinaryOp::Mod,
+            let op = match self.previous_token() {
+                Some(Token::Star) => BinaryOp::Mul,
+                Some(Token::Slash) => BinaryOp::Div,
+                Some(Token::Percent) => BinaryOp::Mod,
                 _ => unreachable!(),
             };
             let right = self.parse_unary()?;
@@ -692,24 +1275,29 @@ impl Parser {
     /// 単項演算子 (-x, not x)
     fn parse_unary(&mut self) -> Result<Expression> {
         if self.match_token(Token::Minus) {
-            let operand = self.parse_unary()?;
+            let operand = self.nested(|parser| parser.parse_unary())?;
             return Ok(Expression::UnaryOp(Box::new(UnaryExpr {
                 op: UnaryOp::Neg,
                 operand,
             })));
         }
         if self.match_token(Token::Not) {
-            let operand = self.parse_unary()?;
+            let operand = self.nested(|parser| parser.parse_unary())?;
             return Ok(Expression::UnaryOp(Box::new(UnaryExpr {
                 op: UnaryOp::Not,
                 operand,
             })));
         }
+        if self.match_token(Token::Await) {
+            let operand = self.nested(|parser| parser.parse_unary())?;
+            return Ok(Expression::Await(Box::new(operand)));
+        }
         self.parse_call()
     }
 
     /// 関数呼び出し (func arg1, arg2)
     fn parse_call(&mut self) -> Result<Expression> {
+        let line = self.current_line();
         let func = self.parse_postfix()?; // term -> call -> postfix
 
         // 引数の開始判定
@@ -728,37 +1316,44 @@ impl Parser {
                     break;
                 }
             }
-            return Ok(Expression::Call(Box::new(CallExpr { func, args })));
+            return Ok(Expression::Call(Box::new(CallExpr { func, args, line })));
         }
 
         Ok(func)
     }
 
     fn is_arg_start(&self) -> bool {
-        if let Some(token) = self.peek_token() {
-            match token {
+        matches!(
+            self.peek_token(),
+            Some(
                 Token::Identifier(_)
-                | Token::IntLiteral(_)
-                | Token::StringLiteral(_)
-                | Token::MultiLineString(_)
-                | Token::FloatLiteral(_)
-                | Token::LParen
-                | Token::LBrace
-                | Token::SelfKw => true,
-                _ => false,
-            }
-        } else {
-            false
-        }
+                    | Token::IntLiteral(_)
+                    | Token::StringLiteral(_)
+                    | Token::MultiLineString(_)
+                    | Token::FloatLiteral(_)
+                    | Token::DurationLiteral(_)
+                    | Token::SizeLiteral(_)
+                    | Token::LParen
+                    | Token::LBrace
+                    | Token::SelfKw
+                    | Token::Super
+            )
+        )
     }
 
-    /// メンバアクセス (obj.prop) と 関数呼び出し (obj())
+    /// メンバアクセス (obj.prop) と 関数呼び出し (obj()) と ? 演算子 (expr?)
     fn parse_postfix(&mut self) -> Result<Expression> {
         let mut expr = self.parse_atom()?;
 
         loop {
+            let line = self.current_line();
             if self.match_token(Token::Dot) {
-                let member = self.consume_identifier("Expect member name")?;
+                // re.match のように、キーワードの match もメンバ名には使える
+                let member = if self.match_token(Token::Match) {
+                    "match".to_string()
+                } else {
+                    self.consume_identifier("Expect member name")?
+                };
                 expr = Expression::MemberAccess(Box::new(MemberExpr {
                     object: expr,
                     member,
@@ -776,11 +1371,17 @@ impl Parser {
                     }
                 }
                 self.consume(Token::RParen, "Expect ')' after arguments")?;
-                expr = Expression::Call(Box::new(CallExpr { func: expr, args }));
+                expr = Expression::Call(Box::new(CallExpr {
+                    func: expr,
+                    args,
+                    line,
+                }));
             } else if self.match_token(Token::LBracket) {
                 let index = self.parse_expression()?;
                 self.consume(Token::RBracket, "Expect ']' after index")?;
                 expr = Expression::Index(Box::new(IndexExpr { object: expr, index }));
+            } else if self.match_token(Token::Question) {
+                expr = Expression::Try(Box::new(expr));
             } else {
                 break;
             }
@@ -793,9 +1394,12 @@ impl Parser {
         if self.match_token(Token::SelfKw) {
             return Ok(Expression::Identifier("self".to_string())); // SelfKwをIdentifierとして扱うか、専用にするか。一旦Identifier。
         }
+        if self.match_token(Token::Super) {
+            return Ok(Expression::Identifier("super".to_string()));
+        }
 
         // JSX Element
-        if self.match_token(Token::Lt) {
+        if self.match_token(Token::OpenTag) {
             return Ok(Expression::JsxElement(Box::new(self.parse_jsx_element()?)));
         }
 
@@ -804,12 +1408,12 @@ impl Parser {
             let mut elements = Vec::new();
             if !self.check(Token::RBracket) {
                 loop {
-                    // 改行は許可（整形用）
-                    while self.match_token(Token::Newline) {}
+                    // 改行・インデント・コメントは許可（整形用）
+                    self.skip_layout();
 
                     elements.push(self.parse_expression()?);
 
-                    while self.match_token(Token::Newline) {}
+                    self.skip_layout();
 
                     if self.match_token(Token::Comma) {
                         continue;
@@ -819,11 +1423,33 @@ impl Parser {
                 }
             }
             // 末尾カンマ後の改行対応
-            while self.match_token(Token::Newline) {}
+            self.skip_layout();
             self.consume(Token::RBracket, "Expect ']' after list elements")?;
             return Ok(Expression::Literal(Literal::List(elements)));
         }
 
+        // 辞書リテラル {"a": 1, "b": 2}
+        if self.match_token(Token::LBrace) {
+            let mut entries = Vec::new();
+            loop {
+                self.skip_layout();
+                if self.check(Token::RBrace) {
+                    break;
+                }
+                let key = self.parse_expression()?;
+                self.consume(Token::Colon, "Expect ':' after dict key")?;
+                let value = self.parse_expression()?;
+                entries.push((key, value));
+                self.skip_layout();
+                if !self.match_token(Token::Comma) {
+                    break;
+                }
+            }
+            self.skip_layout();
+            self.consume(Token::RBrace, "Expect '}' after dict entries")?;
+            return Ok(Expression::Literal(Literal::Dict(entries)));
+        }
+
         // 括弧 (expression)
         if self.match_token(Token::LParen) {
             let expr = self.parse_expression()?;
@@ -832,6 +1458,11 @@ impl Parser {
         }
 
         if let Ok(id) = self.consume_identifier("") {
+            if self.is_struct_literal_start(&id) {
+                return Ok(Expression::StructLiteral(Box::new(
+                    self.parse_struct_literal(id)?,
+                )));
+            }
             return Ok(Expression::Identifier(id));
         }
 
@@ -848,6 +1479,14 @@ impl Parser {
                     self.advance();
                     return Ok(Expression::Literal(Literal::Float(f)));
                 }
+                Token::DurationLiteral(d) => {
+                    self.advance();
+                    return Ok(Expression::Literal(Literal::Duration(d)));
+                }
+                Token::SizeLiteral(bytes) => {
+                    self.advance();
+                    return Ok(Expression::Literal(Literal::Size(bytes)));
+                }
                 Token::StringLiteral(s) | Token::MultiLineString(s) => {
                     self.advance();
                     return Ok(Expression::Literal(Literal::Str(s)));
@@ -874,13 +1513,57 @@ impl Parser {
         ))
     }
 
+    /// `Name {` の後が `}` か `field:` なら構造体リテラルとみなす
+    /// (型名は大文字始まりという規約で、コマンド呼び出し `f {...}` と区別する)
+    fn is_struct_literal_start(&self, name: &str) -> bool {
+        if !name.starts_with(|c: char| c.is_ascii_uppercase()) || !self.check(Token::LBrace) {
+            return false;
+        }
+        let mut i = self.current + 1;
+        while matches!(
+            self.tokens.get(i).map(|t| &t.token),
+            Some(Token::Newline | Token::Comment(_))
+        ) {
+            i += 1;
+        }
+        match self.tokens.get(i).map(|t| &t.token) {
+            Some(Token::RBrace) => true,
+            Some(Token::Identifier(_)) => {
+                matches!(self.tokens.get(i + 1).map(|t| &t.token), Some(Token::Colon))
+            }
+            _ => false,
+        }
+    }
+
+    fn parse_struct_literal(&mut self, name: String) -> Result<StructLiteral> {
+        self.consume(Token::LBrace, "Expect '{' after struct name")?;
+        let mut fields = Vec::new();
+        loop {
+            self.skip_layout();
+            if self.check(Token::RBrace) {
+                break;
+            }
+            let field = self.consume_identifier("Expect field name in struct literal")?;
+            self.consume(Token::Colon, "Expect ':' after field name")?;
+            let value = self.parse_expression()?;
+            fields.push((field, value));
+            self.skip_layout();
+            if !self.match_token(Token::Comma) {
+                break;
+            }
+        }
+        self.skip_layout();
+        self.consume(Token::RBrace, "Expect '}' after struct fields")?;
+        Ok(StructLiteral { name, fields })
+    }
+
     fn parse_jsx_element(&mut self) -> Result<JsxElement> {
         let tag = self.consume_identifier("Expect tag name")?;
 
         let mut attributes = Vec::new();
         // 属性パース
         while !self.check(Token::Gt) && !self.check(Token::SelfClose) && !self.is_at_end() {
-            if let Ok(name) = self.consume_identifier("") {
+            if let Some(name) = self.jsx_attribute_name() {
                 let mut value = None;
                 if self.match_token(Token::Assign) {
                     if let Some(token) = self.peek_token().cloned() {
@@ -929,42 +1612,26 @@ impl Parser {
         let mut children = Vec::new();
         // 子要素パース
         while !self.check(Token::CloseTag) && !self.is_at_end() {
-            if self.check(Token::Lt) {
+            if self.match_token(Token::OpenTag) {
                 // 子要素の開始
-                self.advance();
-                let child = self.parse_jsx_element()?;
+                let child = self.nested(|parser| parser.parse_jsx_element())?;
                 children.push(JsxChild::Element(child));
             } else if self.match_token(Token::LBrace) {
                 // {expression}
                 let expr = self.parse_expression()?;
                 self.match_token(Token::RBrace);
                 children.push(JsxChild::Expression(expr));
+            } else if let Some(Token::JsxText(text)) = self.peek_token().cloned() {
+                // テキストノード (空白は字句解析で整えてある)
+                self.advance();
+                children.push(JsxChild::Text(text));
             } else {
-                // テキストノード（トークンを文字列化）
-                // StringLiteralならそのまま、Identifierなら名前、それ以外はトークンの文字表現
-                if let Some(token) = self.peek_token().cloned() {
-                    match token {
-                        Token::StringLiteral(s) => {
-                            self.advance();
-                            children.push(JsxChild::Text(s));
-                        }
-                        Token::Identifier(s) => {
-                            self.advance();
-                            children.push(JsxChild::Text(s));
-                        }
-                        Token::Tab | Token::Newline => {
-                            // 空白と改行は無視（整形用）
-                            self.advance();
-                        }
-                        _ => {
-                            // 他のトークンもテキストとして扱う？
-                            // 簡易実装としてIdentifierだけ扱う
-                            self.advance();
-                        }
-                    }
-                } else {
-                    break;
-                }
+                return Err(miette::miette!(
+                    "Unexpected {:?} in <{}> at line {}",
+                    self.peek_token(),
+                    tag,
+                    self.current_line()
+                ));
             }
         }
 
@@ -990,15 +1657,82 @@ impl Parser {
 
     // ===== ヘルパーメソッド =====
 
-    fn advance(&mut self) -> &TokenInfo {
+    /// 文脈キーワードで始まる構文なら読み進める
+    ///
+    /// `state [保存先] 名前 = ...` の state、`copy 名前` の copy と、それだけで1行の render のときだけ
+    /// キーワードとして読み、`let state = ...` や `render(page)` のような位置では普通の名前のままにする。
+    fn match_soft_keyword(&mut self, keyword: &str) -> bool {
+        let matches = matches!(self.peek_token(), Some(Token::Identifier(word)) if word == keyword)
+            && match keyword {
+                "state" | "copy" => matches!(
+                    self.tokens.get(self.current + 1).map(|t| &t.token),
+                    Some(Token::Identifier(_))
+                ),
+                _ => matches!(
+                    self.tokens.get(self.current + 1).map(|t| &t.token),
+                    Some(Token::Newline)
+                ),
+            };
+        if matches {
+            self.advance();
+        }
+        matches
+    }
+
+    /// ネストの深さを数えながら f を実行する
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            return Err(miette::miette!(
+                "Nesting too deep at line {} (limit is {})",
+                self.current_line(),
+                MAX_NESTING_DEPTH
+            ));
+        }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// 要素のパースがトークンを1つも消費していなければエラーにする (無限ループ防止)
+    fn ensure_progress(&self, start: usize) -> Result<()> {
+        if self.current == start {
+            return Err(miette::miette!(
+                "Unexpected {:?} at line {}",
+                self.peek_token(),
+                self.current_line()
+            ));
+        }
+        Ok(())
+    }
+
+    fn current_line(&self) -> usize {
+        self.tokens
+            .get(self.current)
+            .or_else(|| self.tokens.last())
+            .map_or(1, |t| t.line)
+    }
+
+    fn advance(&mut self) {
         if !self.is_at_end() {
             self.current += 1;
         }
-        self.previous()
     }
 
-    fn previous(&self) -> &TokenInfo {
-        &self.tokens[self.current - 1]
+    /// 直前に消費したトークンの行 (def や component のキーワードの位置)
+    fn previous_line(&self) -> usize {
+        self.current
+            .checked_sub(1)
+            .and_then(|i| self.tokens.get(i))
+            .map_or(1, |t| t.line)
+    }
+
+    /// 直前に消費したトークン (先頭ではパニックせず None)
+    fn previous_token(&self) -> Option<&Token> {
+        self.current
+            .checked_sub(1)
+            .and_then(|i| self.tokens.get(i))
+            .map(|t| &t.token)
     }
 
     fn is_at_end(&self) -> bool {
@@ -1034,15 +1768,30 @@ impl Parser {
         }
     }
 
-    fn consume(&mut self, token_type: Token, message: &str) -> Result<&TokenInfo> {
+    fn consume(&mut self, token_type: Token, message: &str) -> Result<()> {
         if self.check(token_type) {
-            Ok(self.advance())
+            self.advance();
+            Ok(())
         } else {
             // TODO: 正しいエラー位置報告
             Err(miette::miette!("{}", message))
         }
     }
 
+    /// 括弧内の改行とコメントを読み飛ばす (括弧の中では Indent / Dedent は作られない)
+    fn skip_layout(&mut self) {
+        while self.match_token(Token::Newline) || self.match_token(Token::Comment(String::new())) {}
+    }
+
+    /// JSX の属性名 (字句解析で class や data-id もそのまま名前になっている)
+    fn jsx_attribute_name(&mut self) -> Option<String> {
+        let Some(Token::Identifier(name)) = self.peek_token().cloned() else {
+            return None;
+        };
+        self.advance();
+        Some(name)
+    }
+
     fn consume_identifier(&mut self, message: &str) -> Result<String> {
         if let Some(Token::Identifier(s)) = self.peek_token().cloned() {
             self.advance();
@@ -1052,3 +1801,318 @@ impl Parser {
         }
     }
 }
+
+/// docstring の前後の空行と、各行に共通するインデントを取り除く
+fn dedent_doc(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().map(str::trim_end).collect();
+    let start = lines
+        .iter()
+        .position(|l| !l.is_empty())
+        .unwrap_or(lines.len());
+    let end = lines
+        .iter()
+        .rposition(|l| !l.is_empty())
+        .map_or(start, |i| i + 1);
+    let lines = &lines[start..end];
+    let indent = lines
+        .iter()
+        .filter(|l| !l.is_empty())
+        .map(|l| l.len() - l.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    lines
+        .iter()
+        .map(|l| l.get(indent..).unwrap_or(""))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// copy は関数 (クラスのメソッドを除く) の本体の先頭にだけ書ける
+///
+/// 値を複製するのは def を実行したときなので、本体の途中やブロックの中では意味がない。
+fn check_copies(program: &Program) -> Result<()> {
+    let mut checker = CopyPlacement { error: None };
+    checker.visit_program(program);
+    match checker.error {
+        Some(error) => Err(miette::miette!("{}", error)),
+        None => Ok(()),
+    }
+}
+
+struct CopyPlacement {
+    error: Option<String>,
+}
+
+impl Visitor for CopyPlacement {
+    fn visit_item(&mut self, item: &Item) {
+        match item {
+            // メソッドはクラスの環境で動くので、捕まえる変数がない
+            Item::ClassDef(class) => {
+                for member in &class.body {
+                    if let ClassBodyItem::Method(method) | ClassBodyItem::StaticMethod(method) =
+                        member
+                    {
+                        self.visit_block(&method.body);
+                    }
+                }
+            }
+            _ => visit::walk_item(self, item),
+        }
+    }
+
+    fn visit_function(&mut self, function: &FunctionDef) {
+        let leading = function
+            .body
+            .iter()
+            .take_while(|s| matches!(s, Statement::Copy(_) | Statement::Trivia(_)));
+        for statement in leading {
+            let Statement::Copy(names) = statement else {
+                continue;
+            };
+            if let Some(param) = function.params.iter().find(|p| names.contains(&p.name)) {
+                self.error.get_or_insert(format!(
+                    "'{}' is a parameter of '{}' and cannot be copied",
+                    param.name, function.name
+                ));
+            }
+        }
+        let rest = function
+            .body
+            .iter()
+            .skip_while(|s| matches!(s, Statement::Copy(_) | Statement::Trivia(_)));
+        for statement in rest {
+            self.visit_statement(statement);
+        }
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Copy(names) => {
+                self.error.get_or_insert(format!(
+                    "copy {} must come first in the body of a function",
+                    names.join(", ")
+                ));
+            }
+            _ => visit::walk_statement(self, statement),
+        }
+    }
+
+    // 式の中に文は書けない
+    fn visit_expression(&mut self, _expression: &Expression) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    fn parse(source: &str) -> Program {
+        let mut lexer = Lexer::new(source);
+        let tokens = lexer.tokenize();
+        let mut parser = Parser::new(tokens);
+        parser.parse().unwrap()
+    }
+
+    #[test]
+    fn test_trivia_preserved() {
+        let source = "# header\n\ndef f # sig\n\t# body\n\tlet x = 1 # note\n\t\n\treturn x\n";
+        let items = parse(source).items;
+        assert!(matches!(
+            &items[0],
+            Item::Statement(Statement::Trivia(Trivia::Comment { text, trailing: false })) if text == " header"
+        ));
+        assert!(matches!(
+            &items[1],
+            Item::Statement(Statement::Trivia(Trivia::BlankLines(1)))
+        ));
+        let Item::FunctionDef(f) = &items[2] else {
+            panic!("expected function");
+        };
+        let trivia: Vec<&Trivia> = f
+            .body
+            .iter()
+            .filter_map(|s| match s {
+                Statement::Trivia(t) => Some(t),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            trivia,
+            vec![
+                &Trivia::Comment {
+                    text: " sig".to_string(),
+                    trailing: true
+                },
+                &Trivia::Comment {
+                    text: " body".to_string(),
+                    trailing: false
+                },
+                &Trivia::Comment {
+                    text: " note".to_string(),
+                    trailing: true
+                },
+                &Trivia::BlankLines(1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_docstring() {
+        let source = "def f # sig\n\t`\n\t1行目\n\t  字下げ\n\t`\n\treturn 1\ncomponent App\n\t\"画面\"\n\trender\n\t\t<p>x</p>\n";
+        let items = parse(source).items;
+        let Item::FunctionDef(f) = &items[0] else {
+            panic!("expected function");
+        };
+        assert_eq!(f.doc.as_deref(), Some("1行目\n  字下げ"));
+        assert!(matches!(
+            &f.body[..],
+            [
+                Statement::Trivia(Trivia::Comment { trailing: true, .. }),
+                Statement::Return(_)
+            ]
+        ));
+        let Item::ComponentDef(c) = &items[1] else {
+            panic!("expected component");
+        };
+        assert_eq!(c.doc.as_deref(), Some("画面"));
+    }
+
+    #[test]
+    fn test_indented_blocks() {
+        // ネストしたブロックの後の else と、浅い位置に書いたコメント行はブロックを終わらせない
+        let source = "def f x\n\tif x\n\t\treturn 1\n# 浅いコメント\n\telse\n\t\treturn 2\n\n\treturn 3\nf(true)\n";
+        let items = parse(source).items;
+        let Item::FunctionDef(f) = &items[0] else {
+            panic!("expected function");
+        };
+        let Statement::If(stmt) = &f.body[0] else {
+            panic!("expected if, got {:?}", f.body[0]);
+        };
+        assert!(matches!(
+            &stmt.then_block[..],
+            [
+                Statement::Return(_),
+                Statement::Trivia(Trivia::Comment { .. })
+            ]
+        ));
+        assert!(matches!(
+            &stmt.else_block.as_deref(),
+            Some([Statement::Return(_)])
+        ));
+        assert!(matches!(
+            &f.body[1..],
+            [
+                Statement::Trivia(Trivia::BlankLines(1)),
+                Statement::Return(_)
+            ]
+        ));
+        assert!(matches!(
+            &items[1],
+            Item::Statement(Statement::Expression(_))
+        ));
+
+        for (source, message) in [
+            (
+                "def f\n\tlet a = 1\n\t\tlet b = 2\n",
+                "Unexpected indent at line 3",
+            ),
+            (
+                "class A\n\tx: Int\n\tprint(1)\n",
+                "Expect ':' for field definition",
+            ),
+            (
+                "struct A\n\tx: Int\n\t1\n",
+                "Unexpected Some(IntLiteral(1)) at line 3",
+            ),
+        ] {
+            let mut parser = Parser::new(Lexer::new(source).tokenize());
+            let err = parser.parse().unwrap_err();
+            assert_eq!(err.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn test_soft_keywords() {
+        // state / render / route / test / props は構文の位置以外では普通の名前
+        let source = "let state = 1\nlet test = state + 1\nlet props = {\"route\": test}\nrender(props)\nstate = 2\n";
+        let items = parse(source).items;
+        assert_eq!(items.len(), 5);
+        assert!(items[..3]
+            .iter()
+            .all(|item| matches!(item, Item::Statement(Statement::Let(_)))));
+        assert!(matches!(
+            &items[3],
+            Item::Statement(Statement::Expression(_))
+        ));
+
+        let source = "component Counter\n\tstate count = 0\n\tstate local draft = \"\"\n\tdef render_count\n\t\tlet state = count\n\t\treturn state\n\trender\n\t\t<p>{count}</p>\n";
+        let Item::ComponentDef(component) = &parse(source).items[0] else {
+            panic!("expected component");
+        };
+        assert!(matches!(
+            &component.body[..],
+            [
+                ComponentBodyItem::State(_),
+                ComponentBodyItem::State(_),
+                ComponentBodyItem::Method(_),
+                ComponentBodyItem::Render(_)
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_deep_nesting_is_an_error() {
+        let source = format!("let x = {}1{}\n", "(".repeat(500), ")".repeat(500));
+        let mut parser = Parser::new(Lexer::new(&source).tokenize());
+        let err = parser.parse().unwrap_err();
+        assert!(err.to_string().contains("Nesting too deep"));
+        let source = format!("let x = {}1{}\n", "-".repeat(500), "");
+        let mut parser = Parser::new(Lexer::new(&source).tokenize());
+        assert!(parser.parse().is_err());
+        let source = format!("let x = {}{}\n", "<a>".repeat(5000), "</a>".repeat(5000));
+        let mut parser = Parser::new(Lexer::new(&source).tokenize());
+        let err = parser.parse().unwrap_err();
+        assert!(err.to_string().contains("Nesting too deep"));
+    }
+
+    #[test]
+    fn test_expression_statement_errors() {
+        let error = |source: &str| {
+            let mut parser = Parser::new(Lexer::new(source).tokenize());
+            parser.parse().unwrap_err().to_string()
+        };
+        // 式文の中の深すぎる入れ子も、読み飛ばさずにエラーにする
+        let deep = format!("println({}1{})\nprintln(2)\n", "(".repeat(150), ")".repeat(150));
+        assert!(error(&deep).contains("Nesting too deep"));
+        assert!(error(&"(".repeat(100_000)).contains("Nesting too deep"));
+        assert!(error(&"[".repeat(5000)).contains("Nesting too deep"));
+        // 途中で切れた入力
+        for source in ["(\n", "[\n", "<div\n", "println (1 +\n", "def f\n\tprintln(1 +\n"] {
+            assert!(!error(source).is_empty(), "{:?}", source);
+        }
+        assert_eq!(error(")\n"), "Expect expression, got Some(RParen)");
+    }
+
+    #[test]
+    fn test_jsx() {
+        let source = "let ok = a < b and c > d\nlet link = <a href=\"/x\" data-id={id}>Hi, {name}! (1 < 2)</a>\n";
+        let items = parse(source).items;
+        let Item::Statement(Statement::Let(LetDecl {
+            value: Expression::JsxElement(link),
+            ..
+        })) = &items[1]
+        else {
+            panic!("expected a JSX element, got {:?}", items[1]);
+        };
+        let names: Vec<&str> = link.attributes.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, ["href", "data-id"]);
+        assert!(matches!(
+            &link.children[..],
+            [JsxChild::Text(hi), JsxChild::Expression(_), JsxChild::Text(rest)] if hi == "Hi, " && rest == "! (1 < 2)"
+        ));
+
+        let mut parser = Parser::new(Lexer::new("let x = <p>a</b>\n").tokenize());
+        let err = parser.parse().unwrap_err();
+        assert!(err.to_string().contains("Tag mismatch"));
+    }
+}
@@ -0,0 +1,511 @@
+//! 組み込み関数から使えるファイル・ネットワーク・環境変数・サブプロセスの権限
+//!
+//! `n7tya run --allow-net --allow-read=./data` のように `--allow-*` を1つでも渡すと、
+//! 渡したものだけが許される (何も渡さなければ今までどおりすべて許す)。
+//! 権限は組み込み関数を呼ぶときに確かめるので、import したパッケージのコードにも効く。
+//!
+//! | フラグ | 確かめる組み込み関数 |
+//! |---|---|
+//! | `--allow-read[=パス,...]` | fs.read_file, fs.try_read_file, fs.exists, fs.read_dir, csv.read, sqlite.open, import するモジュールのファイル |
+//! | `--allow-write[=パス,...]` | fs.write_file, fs.remove, csv.write, sqlite.open, http.download |
+//! | `--allow-net[=ホスト[:ポート],...]` | http.get, http.post, http.put, http.patch, http.delete, http.download, サーバーの起動 |
+//! | `--allow-env[=名前,...]` | env.get, os.env, os.set_env |
+//! | `--allow-run[=コマンド,...]` | process.run |
+//!
+//! `--allow-all` (`-A`) はすべて許す。ファイルの権限を絞ると sqlite の ATTACH DATABASE は使えず、
+//! 接続先を絞ると http のリダイレクト先も1つずつ確かめる。
+
+use crate::interpreter::Value;
+use std::path::{Component, Path, PathBuf};
+
+/// 1つの種類の権限で許すもの
+#[derive(Debug, Clone, PartialEq)]
+pub enum Grant<T> {
+    All,
+    Only(Vec<T>),
+}
+
+impl<T> Grant<T> {
+    fn allows(&self, allowed: impl Fn(&T) -> bool) -> bool {
+        match self {
+            Grant::All => true,
+            Grant::Only(items) => items.iter().any(allowed),
+        }
+    }
+
+    fn extend(&mut self, more: Grant<T>) {
+        match (self, more) {
+            (Grant::Only(items), Grant::Only(more)) => items.extend(more),
+            (this, more) => {
+                if let Grant::All = more {
+                    *this = Grant::All;
+                }
+            }
+        }
+    }
+}
+
+/// 実行中のプログラムに許す権限 (None はその種類を何も許さない)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Permissions {
+    read: Option<Grant<PathBuf>>,
+    write: Option<Grant<PathBuf>>,
+    net: Option<Grant<(String, Option<u16>)>>,
+    env: Option<Grant<String>>,
+    run: Option<Grant<String>>,
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Self::allow_all()
+    }
+}
+
+impl Permissions {
+    pub fn allow_all() -> Self {
+        Self {
+            read: Some(Grant::All),
+            write: Some(Grant::All),
+            net: Some(Grant::All),
+            env: Some(Grant::All),
+            run: Some(Grant::All),
+        }
+    }
+
+    /// コマンドライン引数の `--allow-*` から作る (ほかの引数は無視する)
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut permissions = Self {
+            read: None,
+            write: None,
+            net: None,
+            env: None,
+            run: None,
+        };
+        let mut restricted = false;
+        for arg in args {
+            if arg == "--allow-all" || arg == "-A" {
+                return Ok(Self::allow_all());
+            }
+            let Some(flag) = arg.strip_prefix("--allow-") else {
+                continue;
+            };
+            restricted = true;
+            let (kind, list) = match flag.split_once('=') {
+                Some((kind, list)) => (kind, Some(list)),
+                None => (flag, None),
+            };
+            let items = match list {
+                Some(list) => {
+                    let items: Vec<&str> = list.split(',').filter(|s| !s.is_empty()).collect();
+                    if items.is_empty() {
+                        return Err(format!("--allow-{}= expects a comma-separated list", kind));
+                    }
+                    Some(items)
+                }
+                None => None,
+            };
+            match kind {
+                "read" => add(&mut permissions.read, items, |path| Ok(resolve(path)))?,
+                "write" => add(&mut permissions.write, items, |path| Ok(resolve(path)))?,
+                "net" => add(&mut permissions.net, items, host_port)?,
+                "env" => add(&mut permissions.env, items, |name| Ok(name.to_string()))?,
+                "run" => add(&mut permissions.run, items, |command| {
+                    Ok(command.to_string())
+                })?,
+                _ => return Err(format!("Unknown permission flag: {}", arg)),
+            }
+        }
+        if restricted {
+            Ok(permissions)
+        } else {
+            Ok(Self::allow_all())
+        }
+    }
+
+    pub fn check_read(&self, path: &str) -> Result<(), String> {
+        check_path(&self.read, path, "read")
+    }
+
+    pub fn check_write(&self, path: &str) -> Result<(), String> {
+        check_path(&self.write, path, "write")
+    }
+
+    /// host:port への接続 (ホストだけを許したときはどのポートでもよい)
+    pub fn check_net(&self, host: &str, port: u16) -> Result<(), String> {
+        let host = normalize_host(host);
+        let allowed = self.net.as_ref().is_some_and(|grant| {
+            grant.allows(|(allowed, allowed_port)| {
+                normalize_host(allowed) == host && allowed_port.is_none_or(|p| p == port)
+            })
+        });
+        if allowed {
+            Ok(())
+        } else {
+            Err(denied("net", &format!("{}:{}", host, port)))
+        }
+    }
+
+    /// URL の接続先 (http は 80、https は 443 番が省略時のポート)
+    pub fn check_url(&self, url: &str) -> Result<(), String> {
+        if let Some(Grant::All) = self.net {
+            return Ok(());
+        }
+        match url_host(url) {
+            Some((host, port)) => self.check_net(&host, port),
+            None => Err(denied("net", url)),
+        }
+    }
+
+    pub fn check_env(&self, name: &str) -> Result<(), String> {
+        check_name(&self.env, name, "env")
+    }
+
+    pub fn check_run(&self, command: &str) -> Result<(), String> {
+        check_name(&self.run, command, "run")
+    }
+
+    /// ファイルの読み書きを一部しか許していないか (sqlite の ATTACH DATABASE を止めるのに使う)
+    pub fn restricts_files(&self) -> bool {
+        !matches!(
+            (&self.read, &self.write),
+            (Some(Grant::All), Some(Grant::All))
+        )
+    }
+
+    /// 接続先を一部しか許していないか (http のリダイレクトを1つずつ確かめるのに使う)
+    pub fn restricts_net(&self) -> bool {
+        !matches!(self.net, Some(Grant::All))
+    }
+
+    /// 組み込み関数を呼ぶ前に、最初の引数 (パス・URL・名前) が許されているか確かめる
+    ///
+    /// 引数が文字列でなければ何もしない (引数の誤りは組み込み関数が報告する)
+    pub fn check_builtin(&self, name: &str, args: &[Value]) -> Result<(), String> {
+        let Some(Value::Str(arg)) = args.first() else {
+            return Ok(());
+        };
+        match name {
+            "fs.read_file" | "fs.try_read_file" | "fs.exists" | "fs.read_dir" | "csv.read" => {
+                self.check_read(arg)
+            }
+            "fs.write_file" | "fs.remove" | "csv.write" => self.check_write(arg),
+            "sqlite.open" if &**arg != ":memory:" => {
+                self.check_read(arg)?;
+                self.check_write(arg)
+            }
+            _ if crate::http::is_request(name) => self.check_url(arg),
+            "http.download" => {
+                self.check_url(arg)?;
+                match args.get(1) {
+                    Some(Value::Str(path)) => self.check_write(path),
+                    _ => Ok(()),
+                }
+            }
+            "env.get" | "os.env" | "os.set_env" => self.check_env(arg),
+            "process.run" => self.check_run(arg),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// `--allow-x` (items が None) はすべて、`--allow-x=a,b` は a と b を許す
+fn add<T>(
+    grant: &mut Option<Grant<T>>,
+    items: Option<Vec<&str>>,
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> Result<(), String> {
+    let more = match items {
+        Some(items) => Grant::Only(items.into_iter().map(parse).collect::<Result<_, _>>()?),
+        None => Grant::All,
+    };
+    match grant {
+        Some(grant) => grant.extend(more),
+        None => *grant = Some(more),
+    }
+    Ok(())
+}
+
+fn check_path(grant: &Option<Grant<PathBuf>>, path: &str, kind: &str) -> Result<(), String> {
+    let allowed = match grant {
+        Some(Grant::All) => true,
+        Some(grant) => {
+            let path = resolve(path);
+            grant.allows(|allowed| path.starts_with(allowed))
+        }
+        None => false,
+    };
+    if allowed {
+        Ok(())
+    } else {
+        Err(denied(kind, path))
+    }
+}
+
+fn check_name(grant: &Option<Grant<String>>, name: &str, kind: &str) -> Result<(), String> {
+    if grant
+        .as_ref()
+        .is_some_and(|grant| grant.allows(|allowed| allowed == name))
+    {
+        Ok(())
+    } else {
+        Err(denied(kind, name))
+    }
+}
+
+fn denied(kind: &str, target: &str) -> String {
+    format!(
+        "Requires {} access to '{}' (run with --allow-{})",
+        kind, target, kind
+    )
+}
+
+/// 絶対パスにする (`..` を畳み、シンボリックリンクはある所までたどって実際の場所にする)
+fn resolve(path: &str) -> PathBuf {
+    let absolute = std::env::current_dir().unwrap_or_default().join(path);
+    let mut normal = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::ParentDir => {
+                normal.pop();
+            }
+            Component::CurDir => {}
+            other => normal.push(other),
+        }
+    }
+    // まだないファイルは、ある親ディレクトリまでを実際の場所にしてから残りをつなぐ
+    let mut existing: &Path = &normal;
+    let mut rest = Vec::new();
+    loop {
+        if let Ok(real) = existing.canonicalize() {
+            return rest.iter().rev().fold(real, |path, name| path.join(name));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name);
+                existing = parent;
+            }
+            _ => return normal,
+        }
+    }
+}
+
+/// "host"、"host:port"、"[::1]:port" を分ける
+fn host_port(entry: &str) -> Result<(String, Option<u16>), String> {
+    let (host, port) = match entry.strip_prefix('[') {
+        Some(rest) => match rest.split_once(']') {
+            Some((host, "")) => (host, None),
+            Some((host, port)) => (host, port.strip_prefix(':')),
+            None => (entry, None),
+        },
+        None => match entry.rsplit_once(':') {
+            Some((host, port)) if !host.contains(':') => (host, Some(port)),
+            _ => (entry, None),
+        },
+    };
+    let port = match port {
+        Some(port) => Some(
+            port.parse()
+                .map_err(|_| format!("Invalid port in --allow-net: {}", entry))?,
+        ),
+        None => None,
+    };
+    Ok((host.to_ascii_lowercase(), port))
+}
+
+/// URL の接続先のホストとポート
+fn url_host(url: &str) -> Option<(String, u16)> {
+    let (scheme, rest) = url.split_once("://")?;
+    let default_port = match scheme.to_ascii_lowercase().as_str() {
+        "http" => 80,
+        "https" => 443,
+        _ => return None,
+    };
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let authority = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let (host, port) = host_port(authority).ok()?;
+    (!host.is_empty()).then(|| (host, port.unwrap_or(default_port)))
+}
+
+/// localhost と 127.0.0.1 は同じホストとして扱う
+fn normalize_host(host: &str) -> String {
+    match host.to_ascii_lowercase().as_str() {
+        "localhost" => "127.0.0.1".to_string(),
+        host => host.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn permissions(args: &[&str]) -> Permissions {
+        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        Permissions::from_args(&args).unwrap()
+    }
+
+    #[test]
+    fn test_permission_flags() {
+        let all = permissions(&["--config", "prod.toml"]);
+        assert_eq!(all, Permissions::allow_all());
+        assert!(all.check_run("ls").is_ok());
+
+        let dir = std::env::temp_dir().join("n7tya_permissions_test");
+        std::fs::create_dir_all(dir.join("data")).unwrap();
+        let data = dir.join("data").display().to_string();
+        let limited = permissions(&[
+            &format!("--allow-read={}", data),
+            "--allow-net=example.com,localhost:8080",
+            "--allow-env=HOME",
+        ]);
+        assert!(limited
+            .check_read(&format!("{}/posts/new.json", data))
+            .is_ok());
+        let outside = limited
+            .check_read(&format!("{}/data/../secret.txt", dir.display()))
+            .unwrap_err();
+        assert!(outside.contains("--allow-read"));
+        assert!(limited.check_write(&data).is_err());
+        assert!(limited.check_url("https://example.com/feed?x=1").is_ok());
+        assert!(limited.check_url("http://user@EXAMPLE.com:8443/").is_ok());
+        assert!(limited.check_url("http://127.0.0.1:8080/api").is_ok());
+        assert!(limited.check_net("localhost", 3000).is_err());
+        assert!(limited.check_url("https://evil.example/").is_err());
+        assert!(limited.check_env("HOME").is_ok());
+        assert!(limited.check_env("AWS_SECRET_ACCESS_KEY").is_err());
+        assert!(limited.check_run("ls").is_err());
+
+        let memory = vec![Value::Str(":memory:".into())];
+        assert!(limited.check_builtin("sqlite.open", &memory).is_ok());
+        assert!(limited
+            .check_builtin("fs.read_file", &[Value::Int(1)])
+            .is_ok());
+        assert_eq!(
+            permissions(&["--allow-run", "-A"]),
+            Permissions::allow_all()
+        );
+        let args = ["--allow-disk".to_string()];
+        assert!(Permissions::from_args(&args).is_err());
+        let args = ["--allow-net=example.com:http".to_string()];
+        assert!(Permissions::from_args(&args).is_err());
+    }
+
+    #[test]
+    fn test_builtins_respect_permissions() {
+        let source = "fs.exists(\"n7tya.toml\")\n";
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_permissions(permissions(&["--allow-net"]));
+        let error = interpreter.run(&program).unwrap_err();
+        assert!(error.contains("Requires read access"), "{}", error);
+
+        let source = "let r = process.run(\"echo\", [\"hi\"])\n[r[\"status\"], r[\"stdout\"], env.get(\"N7TYA_PERMISSIONS_UNSET\", \"none\")]\n";
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_permissions(permissions(&["--allow-run=echo", "--allow-env"]));
+        let value = interpreter.run(&program).unwrap();
+        assert_eq!(value.display(), "[0, hi\n, none]");
+    }
+
+    #[test]
+    fn test_io_builtins_need_a_grant() {
+        let nothing = Permissions {
+            read: None,
+            write: None,
+            net: None,
+            env: None,
+            run: None,
+        };
+        let str = |s: &str| Value::Str(s.into());
+        let guarded = [
+            ("fs.read_file", vec![str("a.txt")]),
+            ("fs.try_read_file", vec![str("a.txt")]),
+            ("fs.write_file", vec![str("a.txt"), str("x")]),
+            ("fs.exists", vec![str("a.txt")]),
+            ("fs.remove", vec![str("a.txt")]),
+            ("fs.read_dir", vec![str(".")]),
+            ("csv.read", vec![str("a.csv")]),
+            ("csv.write", vec![str("a.csv"), Value::None]),
+            ("sqlite.open", vec![str("app.db")]),
+            ("http.get", vec![str("http://a.test/")]),
+            ("http.post", vec![str("http://a.test/"), str("x")]),
+            ("http.put", vec![str("http://a.test/"), str("x")]),
+            ("http.patch", vec![str("http://a.test/"), str("x")]),
+            ("http.delete", vec![str("http://a.test/")]),
+            ("http.download", vec![str("http://a.test/"), str("a.bin")]),
+            ("env.get", vec![str("HOME")]),
+            ("os.env", vec![str("HOME")]),
+            ("os.set_env", vec![str("HOME"), str("/")]),
+            ("process.run", vec![str("ls")]),
+        ];
+        // ファイル・ネットワーク・環境変数・プロセスに触れないもの (接続は sqlite.open で確かめる)
+        let harmless = [
+            "csv.parse",
+            "csv.stringify",
+            "sqlite.execute",
+            "sqlite.query",
+            "sqlite.close",
+            "os.args",
+            "os.cwd",
+            "os.platform",
+            "os.exit",
+        ];
+        for (module, functions) in crate::builtins::STDLIB_MODULES {
+            if !["fs", "csv", "sqlite", "http", "env", "process", "os"].contains(module) {
+                continue;
+            }
+            for function in *functions {
+                let name = format!("{}.{}", module, function);
+                if harmless.contains(&name.as_str()) {
+                    continue;
+                }
+                let Some((_, args)) = guarded.iter().find(|(guarded, _)| *guarded == name) else {
+                    panic!("{} is not listed as guarded or harmless", name);
+                };
+                let error = nothing.check_builtin(&name, args).unwrap_err();
+                assert!(error.starts_with("Requires "), "{}: {}", name, error);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sqlite_attach_needs_full_file_access() {
+        let source = "let db = sqlite.open(\":memory:\")\ndb.execute(\"ATTACH DATABASE ':memory:' AS other\")\n";
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        assert!(Interpreter::new().run(&program).is_ok());
+        let mut interpreter = Interpreter::new();
+        interpreter.set_permissions(permissions(&["--allow-net"]));
+        let error = interpreter.run(&program).unwrap_err();
+        assert!(error.contains("too many attached databases"), "{}", error);
+    }
+
+    #[test]
+    fn test_imports_respect_permissions() {
+        let dir = std::env::temp_dir().join("n7tya_permissions_import");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("peek.n7t"),
+            "let found = fs.exists(\"/etc/hostname\")\n",
+        )
+        .unwrap();
+        let source = format!("import \"{}/peek\"\n", dir.display());
+        let program = Parser::new(Lexer::new(&source).tokenize()).parse().unwrap();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_permissions(permissions(&["--allow-net"]));
+        let error = interpreter.run(&program).unwrap_err();
+        assert!(error.contains("Requires read access"), "{}", error);
+        assert!(error.contains("peek.n7t"), "{}", error);
+
+        // モジュールのトップレベルのコードも import した側の権限で動く
+        let mut interpreter = Interpreter::new();
+        interpreter.set_permissions(permissions(&[&format!("--allow-read={}", dir.display())]));
+        let error = interpreter.run(&program).unwrap_err();
+        assert!(error.contains("'/etc/hostname'"), "{}", error);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
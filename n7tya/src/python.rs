@@ -46,8 +46,10 @@ pub fn call_python_function(func: &PyObject, args: Vec<Value>) -> Result<Value,
     Python::with_gil(|py| {
         let py_args: Vec<PyObject> = args.iter().map(|v| value_to_py(py, v)).collect();
 
+        let py_args =
+            PyTuple::new(py, &py_args).map_err(|e| format!("Python call error: {}", e))?;
         let result = func
-            .call1(py, PyTuple::new(py, &py_args).unwrap())
+            .call1(py, py_args)
             .map_err(|e| format!("Python call error: {}", e))?;
 
         py_to_value(py, &result)
@@ -90,7 +92,10 @@ pub fn value_to_py(py: Python, value: &Value) -> PyObject {
         Value::List(items) => {
             let items = items.borrow();
             let py_items: Vec<PyObject> = items.iter().map(|v| value_to_py(py, v)).collect();
-            PyList::new(py, &py_items).unwrap().into_py(py)
+            match PyList::new(py, &py_items) {
+                Ok(list) => list.into_py(py),
+                Err(_) => py.None(),
+            }
         }
         _ => py.None(),
     }
@@ -111,7 +116,7 @@ pub fn py_to_value(py: Python, obj: &PyObject) -> Result<Value, String> {
         return Ok(Value::Bool(val));
     }
     if let Ok(val) = obj_ref.extract::<String>() {
-        return Ok(Value::Str(val));
+        return Ok(Value::Str(val.into()));
     }
     if obj_ref.is_none() {
         return Ok(Value::None);
@@ -135,7 +140,8 @@ pub fn install_python_package(package: &str) -> Result<(), String> {
             .import("subprocess")
             .map_err(|e| format!("Failed to import subprocess: {}", e))?;
 
-        let args = PyList::new(py, &["pip", "install", package]).unwrap();
+        let args = PyList::new(py, ["pip", "install", package])
+            .map_err(|e| format!("Failed to install '{}': {}", package, e))?;
         subprocess
             .call_method1("run", (args,))
             .map_err(|e| format!("Failed to install '{}': {}", package, e))?;
@@ -167,8 +173,10 @@ impl PythonModule {
 
             let py_args: Vec<PyObject> = args.iter().map(|v| value_to_py(py, v)).collect();
 
+            let py_args =
+                PyTuple::new(py, &py_args).map_err(|e| format!("Call error: {}", e))?;
             let result = func
-                .call1(PyTuple::new(py, &py_args).unwrap())
+                .call1(py_args)
                 .map_err(|e| format!("Call error: {}", e))?;
 
             py_to_value(py, &result.into_py(py))
@@ -0,0 +1,162 @@
+//! re モジュール (正規表現、regex クレートの構文)
+//!
+//! re.match / re.search は一致を辞書 {"text", "start", "end", "groups", "named"} で返し、
+//! 一致しなければ none を返す。start / end は文字列の添字と同じく文字単位の位置。
+//! コンパイルしたパターンはスレッドごとに覚えておき、同じパターンを何度も使っても作り直さない。
+
+use crate::interpreter::Value;
+use regex::{Captures, Regex};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// 覚えておくパターンの数 (超えたら作り直す)
+const CACHE_SIZE: usize = 64;
+
+thread_local! {
+    static CACHE: RefCell<HashMap<String, Regex>> = RefCell::new(HashMap::new());
+}
+
+/// re.* の関数 (name は "match" など、"re." を除いた名前)
+pub fn call(name: &str, args: Vec<Value>) -> Result<Value, String> {
+    match (name, args.as_slice()) {
+        // match は先頭から一致するときだけ、search はどこで一致してもよい
+        ("match", [Value::Str(pattern), Value::Str(text)]) => {
+            let regex = compile(&format!(r"\A(?:{})", pattern))?;
+            Ok(regex
+                .captures(text)
+                .map_or(Value::None, |caps| match_value(&regex, text, &caps)))
+        }
+        ("search", [Value::Str(pattern), Value::Str(text)]) => {
+            let regex = compile(pattern)?;
+            Ok(regex
+                .captures(text)
+                .map_or(Value::None, |caps| match_value(&regex, text, &caps)))
+        }
+        ("find_all", [Value::Str(pattern), Value::Str(text)]) => {
+            let found = compile(pattern)?
+                .find_iter(text)
+                .map(|m| Value::Str(m.as_str().into()))
+                .collect();
+            Ok(list(found))
+        }
+        // 置き換える文字列の $1 / ${name} はグループに一致した部分になる
+        (
+            "replace",
+            [Value::Str(pattern), Value::Str(text), Value::Str(replacement), rest @ ..],
+        ) if rest.len() <= 1 => {
+            let limit = match rest {
+                [] => 0,
+                [Value::Int(n)] if *n >= 0 => *n as usize,
+                _ => return Err("re.replace() count must be a non-negative integer".to_string()),
+            };
+            let replaced = compile(pattern)?.replacen(text, limit, replacement.as_ref());
+            Ok(Value::Str(replaced.into()))
+        }
+        // 分割する回数を渡せばそれだけ分ける (負なら制限なし)
+        ("split", [Value::Str(pattern), Value::Str(text), rest @ ..]) if rest.len() <= 1 => {
+            let regex = compile(pattern)?;
+            let parts: Vec<&str> = match rest {
+                [Value::Int(n)] if *n >= 0 => regex.splitn(text, *n as usize + 1).collect(),
+                [] | [Value::Int(_)] => regex.split(text).collect(),
+                _ => return Err("re.split() limit must be an integer".to_string()),
+            };
+            Ok(list(
+                parts
+                    .into_iter()
+                    .map(|part| Value::Str(part.into()))
+                    .collect(),
+            ))
+        }
+        ("escape", [Value::Str(text)]) => Ok(Value::Str(regex::escape(text).into())),
+        _ => Err(format!(
+            "re.{}() expects a pattern and a string (see the docs for optional arguments)",
+            name
+        )),
+    }
+}
+
+pub(crate) fn compile(pattern: &str) -> Result<Regex, String> {
+    CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if let Some(regex) = cache.get(pattern) {
+            return Ok(regex.clone());
+        }
+        let regex = Regex::new(pattern)
+            .map_err(|e| format!("Invalid regular expression '{}': {}", pattern, e))?;
+        if cache.len() >= CACHE_SIZE {
+            cache.clear();
+        }
+        cache.insert(pattern.to_string(), regex.clone());
+        Ok(regex)
+    })
+}
+
+/// 一致を表す辞書 (一致しなかったグループは none)
+fn match_value(regex: &Regex, text: &str, caps: &Captures) -> Value {
+    let whole = caps.get(0).expect("group 0 is always present");
+    let position = |byte: usize| Value::Int(text[..byte].chars().count() as i64);
+    let group = |m: Option<regex::Match>| m.map_or(Value::None, |m| Value::Str(m.as_str().into()));
+    let groups = caps.iter().skip(1).map(group).collect();
+    let named: HashMap<String, Value> = regex
+        .capture_names()
+        .flatten()
+        .map(|name| (name.to_string(), group(caps.name(name))))
+        .collect();
+    let fields = HashMap::from([
+        ("text".to_string(), Value::Str(whole.as_str().into())),
+        ("start".to_string(), position(whole.start())),
+        ("end".to_string(), position(whole.end())),
+        ("groups".to_string(), list(groups)),
+        (
+            "named".to_string(),
+            Value::Dict(Rc::new(RefCell::new(named))),
+        ),
+    ]);
+    Value::Dict(Rc::new(RefCell::new(fields)))
+}
+
+fn list(items: Vec<Value>) -> Value {
+    Value::List(Rc::new(RefCell::new(items)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::run;
+
+    #[test]
+    fn test_search_and_match() {
+        let source = r#"let m = re.search("(?P<user>\\w+)@(\\w+)(\\.org)?", "mail: ann@example.com")
+[m.text, m.start, m.end, m.groups, m.named["user"], re.match("\\d+", "a1"), re.match("\\d+", "12a").text]
+"#;
+        assert_eq!(
+            run(source).unwrap(),
+            "[ann@example, 6, 17, [ann, example, none], ann, none, 12]"
+        );
+    }
+
+    #[test]
+    fn test_find_replace_and_split() {
+        let source = r#"from re import find_all, replace, split
+[find_all("\\d+", "a1b22c333"), replace("(\\w+)@(\\w+)", "ann@x bob@y", "$2:$1"), replace("o", "foo", "0", 1), split("[,;]\\s*", "a, b;c"), split(",", "a,b,c", 1), re.escape("a.b")]
+"#;
+        assert_eq!(
+            run(source).unwrap(),
+            r"[[1, 22, 333], x:ann y:bob, f0o, [a, b, c], [a, b,c], a\.b]"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = run("re.search(\"(\", \"x\")\n").unwrap_err();
+        assert!(
+            error.starts_with("Invalid regular expression '('"),
+            "{}",
+            error
+        );
+        assert_eq!(
+            run("re.search(1, \"x\")\n").unwrap_err(),
+            "re.search() expects a pattern and a string (see the docs for optional arguments)"
+        );
+    }
+}
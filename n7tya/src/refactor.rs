@@ -0,0 +1,541 @@
+//! リファクタリング (名前の変更・関数の抽出)
+//!
+//! どちらも resolve の名前解決で求めた位置に対するテキスト編集 (lint::Edit) を返すので、
+//! 書き換えた場所以外の書式やコメントはそのまま残る。
+//! CLI の `n7tya refactor` と、エディタのコードアクション (code_actions) から使う。
+//! 名前の変更は 1 つのファイルの中だけで、import しているほかのファイルは書き換えない。
+
+use crate::ast::*;
+use crate::lexer::{Lexer, Token, TokenInfo};
+use crate::lint::{apply_fixes, Edit, Suggestion};
+use crate::parser::Parser;
+use crate::resolve::{
+    Position, ReferenceKind, Resolver, ScopeKind, SymbolId, SymbolKind, SymbolTable,
+};
+use crate::visit::{self, Visitor};
+use std::ops::RangeInclusive;
+
+/// エディタに出すリファクタリングの候補 (LSP の textDocument/codeAction の CodeAction)
+#[derive(Debug, Clone)]
+pub struct CodeAction {
+    pub title: String,
+    pub kind: &'static str, // LSP の CodeActionKind
+    pub edits: Vec<Edit>,
+}
+
+/// 選択した行 (1 から数える) に対して使えるリファクタリング
+pub fn code_actions(source: &str, lines: RangeInclusive<usize>) -> Vec<CodeAction> {
+    let mut actions = Vec::new();
+    if let Ok(edits) = extract_function(source, lines, "extracted") {
+        actions.push(CodeAction {
+            title: "Extract function".to_string(),
+            kind: "refactor.extract.function",
+            edits,
+        });
+    }
+    actions
+}
+
+/// 編集を適用したソース
+pub fn apply(source: &str, edits: &[Edit]) -> String {
+    let suggestion = Suggestion {
+        line: 0,
+        message: String::new(),
+        edits: edits.to_vec(),
+    };
+    apply_fixes(source, &[suggestion])
+}
+
+/// 構文解析して位置つきで名前解決する
+fn analyze(source: &str) -> Result<(Vec<TokenInfo>, SymbolTable), String> {
+    let tokens = Lexer::new(source).tokenize();
+    let program = Parser::new(tokens.clone())
+        .parse()
+        .map_err(|e| format!("Parse error: {}", e))?;
+    let table = Resolver::new()
+        .with_tokens(source, &tokens)
+        .resolve(&program);
+    Ok((tokens, table))
+}
+
+// ===== 名前の変更 =====
+
+/// line 行目に書いた old の定義と、それを指す全ての参照を new に変える (`n7tya refactor rename`)
+pub fn rename(source: &str, line: usize, old: &str, new: &str) -> Result<Vec<Edit>, String> {
+    let (tokens, table) = analyze(source)?;
+    let on_line = |position: &Option<Position>| position.as_ref().is_some_and(|p| p.line == line);
+    let defined = table
+        .symbols
+        .iter()
+        .position(|s| s.name == old && on_line(&s.position));
+    let id = match defined {
+        Some(id) => id,
+        None => {
+            let reference = table
+                .references
+                .iter()
+                .find(|r| r.name == old && on_line(&r.position))
+                .ok_or_else(|| format!("'{}' not found at line {}", old, line))?;
+            reference
+                .symbol
+                .ok_or_else(|| format!("'{}' at line {} is not defined in this file", old, line))?
+        }
+    };
+    rename_symbol(&tokens, &table, id, new)
+}
+
+/// offset の位置の名前を new に変える (LSP の textDocument/rename)
+pub fn rename_at(source: &str, offset: usize, new: &str) -> Result<Vec<Edit>, String> {
+    let (tokens, table) = analyze(source)?;
+    let id = table
+        .symbol_at(offset)
+        .ok_or_else(|| "No symbol defined in this file at the cursor".to_string())?;
+    rename_symbol(&tokens, &table, id, new)
+}
+
+fn rename_symbol(
+    tokens: &[TokenInfo],
+    table: &SymbolTable,
+    id: SymbolId,
+    new: &str,
+) -> Result<Vec<Edit>, String> {
+    let symbol = &table.symbols[id];
+    if !is_identifier(new) {
+        return Err(format!("'{}' is not a valid name", new));
+    }
+    let Some(position) = &symbol.position else {
+        return Err(format!(
+            "'{}' is defined implicitly and cannot be renamed",
+            symbol.name
+        ));
+    };
+    match symbol.kind {
+        // self.name や Class.NAME のメンバーは名前解決していないので書き換えられない
+        SymbolKind::Field | SymbolKind::Method => {
+            return Err(format!(
+                "Renaming fields and methods is not supported ('{}' is accessed as a member)",
+                symbol.name
+            ))
+        }
+        SymbolKind::Constant if table.scopes[symbol.scope].kind == ScopeKind::Class => {
+            return Err(format!(
+                "Renaming class constants is not supported ('{}' is accessed as a member)",
+                symbol.name
+            ))
+        }
+        // from m import name の name は m の中の名前 (as で付けた別名なら変えられる)
+        SymbolKind::Import => {
+            let index = tokens.iter().position(|t| t.span == position.span);
+            let alias = index.is_some_and(|i| i > 0 && tokens[i - 1].token == Token::As);
+            if !alias {
+                return Err(format!(
+                    "'{}' is imported by name; rename it in the module that defines it",
+                    symbol.name
+                ));
+            }
+        }
+        _ => {}
+    }
+
+    // 参照している全てのスコープで、new が別の定義を指していないこと
+    let scopes = std::iter::once(symbol.scope).chain(
+        table
+            .references
+            .iter()
+            .filter(|r| r.symbol == Some(id))
+            .map(|r| r.scope),
+    );
+    for scope in scopes {
+        if let Some(other) = table.lookup(scope, new) {
+            let line = table.symbols[other].position.as_ref().map_or(0, |p| p.line);
+            return Err(format!("'{}' is already defined (line {})", new, line));
+        }
+    }
+
+    Ok(table
+        .occurrences(id)
+        .into_iter()
+        .map(|position| Edit {
+            span: position.span.clone(),
+            replacement: new.to_string(),
+        })
+        .collect())
+}
+
+/// 名前として使える (キーワードではない) か
+fn is_identifier(name: &str) -> bool {
+    let tokens = Lexer::new(name).tokenize();
+    let words: Vec<&TokenInfo> = tokens
+        .iter()
+        .filter(|t| !matches!(t.token, Token::Newline))
+        .collect();
+    matches!(words.as_slice(), [t] if t.token == Token::Identifier(name.to_string()))
+}
+
+// ===== 関数の抽出 =====
+
+/// lines の行 (1 から数える) の文を新しい関数 name に移し、元の場所はその呼び出しにする
+///
+/// 関数は抽出した行を含むトップレベルの定義の前に置く。
+/// 抽出した行が使う外側の関数の変数は引数にし、抽出した行で定義して後で使う変数は戻り値にする
+/// (戻り値は 1 つだけ。モジュールのトップレベルの変数は関数から見えるので引数にしない)。
+pub fn extract_function(
+    source: &str,
+    lines: RangeInclusive<usize>,
+    name: &str,
+) -> Result<Vec<Edit>, String> {
+    if !is_identifier(name) {
+        return Err(format!("'{}' is not a valid name", name));
+    }
+    let (_, table) = analyze(source)?;
+    let text: Vec<&str> = source.lines().collect();
+    let (start, end) = (*lines.start(), *lines.end());
+    if start == 0 || start > end || end > text.len() {
+        return Err(format!(
+            "Invalid line range {}-{} ({} lines in file)",
+            start,
+            end,
+            text.len()
+        ));
+    }
+
+    // 抽出する行はひとまとまりの文 (同じブロックの途中で始まり、途中で終わらない)
+    let selected = &text[start - 1..end];
+    let base = selected
+        .iter()
+        .find(|line| !line.trim().is_empty())
+        .map(|line| indentation(line))
+        .ok_or_else(|| "No statements in the selected lines".to_string())?;
+    if selected
+        .iter()
+        .any(|line| !line.trim().is_empty() && indentation(line).len() < base.len())
+    {
+        return Err("Selected lines must be statements in the same block".to_string());
+    }
+    let next = text[end..].iter().find(|line| !line.trim().is_empty());
+    if next.is_some_and(|line| indentation(line).len() > base.len()) {
+        return Err(format!(
+            "The statement at line {} continues past the selected lines",
+            end
+        ));
+    }
+    let body: Vec<String> = selected
+        .iter()
+        .map(|line| line.get(base.len()..).unwrap_or("").to_string())
+        .collect();
+    check_extractable(&body.join("\n"))?;
+
+    // 抽出する行の中の参照で、外側の関数で定義した変数は引数にする
+    let inside = |position: &Option<Position>| {
+        position
+            .as_ref()
+            .is_some_and(|p| p.line >= start && p.line <= end)
+    };
+    let mut params: Vec<&str> = Vec::new();
+    let mut written: Vec<SymbolId> = Vec::new();
+    for reference in table.references.iter().filter(|r| inside(&r.position)) {
+        let Some(id) = reference.symbol else {
+            continue;
+        };
+        let symbol = &table.symbols[id];
+        if symbol.scope == 0 || inside(&symbol.position) {
+            continue;
+        }
+        if reference.kind == ReferenceKind::Write {
+            if !written.contains(&id) {
+                written.push(id);
+            }
+        } else if !params.contains(&symbol.name.as_str()) {
+            params.push(&symbol.name);
+        }
+    }
+
+    // 抽出する行で定義したか書き換えて、後の行で使う変数は戻り値にする
+    let used_after = |id: SymbolId| {
+        table
+            .references
+            .iter()
+            .any(|r| r.symbol == Some(id) && r.position.as_ref().is_some_and(|p| p.line > end))
+    };
+    let defined = table
+        .symbols
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| inside(&s.position))
+        .map(|(id, _)| id);
+    let results: Vec<(SymbolId, bool)> = defined
+        .map(|id| (id, true))
+        .chain(written.into_iter().map(|id| (id, false)))
+        .filter(|&(id, _)| used_after(id))
+        .collect();
+    if results.len() > 1 {
+        let names: Vec<&str> = results
+            .iter()
+            .map(|&(id, _)| table.symbols[id].name.as_str())
+            .collect();
+        return Err(format!(
+            "Selected lines define more than one variable used afterwards: {}",
+            names.join(", ")
+        ));
+    }
+    if let Some(other) = table.lookup(0, name) {
+        let line = table.symbols[other].position.as_ref().map_or(0, |p| p.line);
+        return Err(format!("'{}' is already defined (line {})", name, line));
+    }
+
+    // 新しい関数
+    let mut function = format!("def {}", name);
+    if !params.is_empty() {
+        function.push(' ');
+        function.push_str(&params.join(", "));
+    }
+    function.push('\n');
+    for line in &body {
+        if !line.trim().is_empty() {
+            function.push_str("    ");
+            function.push_str(line);
+        }
+        function.push('\n');
+    }
+    let result = results
+        .first()
+        .map(|&(id, defined)| (&table.symbols[id].name, defined));
+    if let Some((result, _)) = result {
+        function.push_str(&format!("    return {}\n", result));
+    }
+    function.push('\n');
+
+    // 元の行は呼び出しにする
+    let call = format!("{}({})", name, params.join(", "));
+    let call = match result {
+        Some((result, true)) => format!("{}let {} = {}\n", base, result, call),
+        Some((result, false)) => format!("{}{} = {}\n", base, result, call),
+        None => format!("{}{}\n", base, call),
+    };
+
+    let offsets = line_offsets(source);
+    let selected_span = offsets[start - 1]..offsets.get(end).copied().unwrap_or(source.len());
+    let insert_at = offsets[definition_start(&text, start, base.is_empty()) - 1];
+    let call = if selected_span.end == source.len() && !source.ends_with('\n') {
+        call.trim_end_matches('\n').to_string()
+    } else {
+        call
+    };
+    Ok(vec![
+        Edit {
+            span: insert_at..insert_at,
+            replacement: function,
+        },
+        Edit {
+            span: selected_span,
+            replacement: call,
+        },
+    ])
+}
+
+/// 抽出する文が関数の中に移せるもの (return でその関数から抜けない) か
+fn check_extractable(body: &str) -> Result<(), String> {
+    let program = Parser::new(Lexer::new(body).tokenize())
+        .parse()
+        .map_err(|e| format!("Selected lines are not complete statements: {}", e))?;
+    let mut finder = ReturnFinder { found: false };
+    for item in &program.items {
+        match item {
+            Item::Statement(statement) => finder.visit_statement(statement),
+            Item::Import(_) => return Err("Imports cannot be extracted into a function".into()),
+            _ => return Err("Only statements can be extracted into a function".to_string()),
+        }
+    }
+    if finder.found {
+        return Err("Selected lines contain a return from the enclosing function".to_string());
+    }
+    Ok(())
+}
+
+/// 入れ子の関数とラムダの外にある return を探す
+struct ReturnFinder {
+    found: bool,
+}
+
+impl Visitor for ReturnFinder {
+    fn visit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Return(_) => self.found = true,
+            Statement::FunctionDef(_) => {}
+            _ => visit::walk_statement(self, statement),
+        }
+    }
+
+    fn visit_expression(&mut self, _expression: &Expression) {}
+}
+
+/// 新しい関数を置く行 (抽出する行を含むトップレベルの定義の先頭。直前のコメントはその定義に付ける)
+fn definition_start(text: &[&str], start: usize, top_level: bool) -> usize {
+    let mut line = start;
+    if !top_level {
+        while line > 1
+            && (text[line - 1].trim().is_empty() || text[line - 1].starts_with([' ', '\t']))
+        {
+            line -= 1;
+        }
+    }
+    while line > 1 && text[line - 2].starts_with('#') {
+        line -= 1;
+    }
+    line
+}
+
+fn indentation(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+/// 各行の先頭のバイト位置
+fn line_offsets(source: &str) -> Vec<usize> {
+    std::iter::once(0)
+        .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn renamed(source: &str, line: usize, old: &str, new: &str) -> Result<String, String> {
+        rename(source, line, old, new).map(|edits| apply(source, &edits))
+    }
+
+    fn extracted(source: &str, lines: RangeInclusive<usize>) -> Result<String, String> {
+        extract_function(source, lines, "helper").map(|edits| apply(source, &edits))
+    }
+
+    #[test]
+    fn test_rename() {
+        let source = "let total = 0
+def add n
+    # total を増やす
+    total = total + n
+    let total = 1
+    return total
+print(total)
+";
+        // 関数の中の let total は別の変数なので変えない
+        assert_eq!(
+            renamed(source, 7, "total", "sum").unwrap(),
+            "let sum = 0
+def add n
+    # total を増やす
+    sum = sum + n
+    let total = 1
+    return total
+print(sum)
+"
+        );
+        assert_eq!(
+            renamed(source, 2, "n", "amount").unwrap(),
+            source
+                .replace("add n", "add amount")
+                .replace("+ n", "+ amount")
+        );
+
+        assert_eq!(
+            renamed(source, 7, "total", "add").unwrap_err(),
+            "'add' is already defined (line 2)"
+        );
+        assert_eq!(
+            renamed(source, 7, "total", "def").unwrap_err(),
+            "'def' is not a valid name"
+        );
+        assert_eq!(
+            renamed(source, 7, "print", "show").unwrap_err(),
+            "'print' at line 7 is not defined in this file"
+        );
+        assert_eq!(
+            renamed(source, 1, "count", "n").unwrap_err(),
+            "'count' not found at line 1"
+        );
+        let imports = "from utils import helper\nimport json as j\nprint(helper(j))\n";
+        assert!(renamed(imports, 3, "helper", "h").is_err());
+        assert_eq!(
+            renamed(imports, 3, "j", "codec").unwrap(),
+            "from utils import helper\nimport json as codec\nprint(helper(codec))\n"
+        );
+    }
+
+    #[test]
+    fn test_rename_at_offset() {
+        let source = "struct Point\n    x: Int\nlet p = Point { x: 1 }\n";
+        let edits = rename_at(source, source.rfind("Point").unwrap(), "Vec2").unwrap();
+        assert_eq!(
+            apply(source, &edits),
+            "struct Vec2\n    x: Int\nlet p = Vec2 { x: 1 }\n"
+        );
+    }
+
+    #[test]
+    fn test_extract_function() {
+        let source = "def report items
+    let header = \"Items\"
+    let count = 0
+    for item in items
+        count = count + 1
+    print(header + \": \" + str(count))
+    return count
+";
+        assert_eq!(
+            extracted(source, 3..=5).unwrap(),
+            "def helper items
+    let count = 0
+    for item in items
+        count = count + 1
+    return count
+
+def report items
+    let header = \"Items\"
+    let count = helper(items)
+    print(header + \": \" + str(count))
+    return count
+"
+        );
+        // 外側の変数を書き換えて後で使うなら、戻り値を代入し直す
+        assert_eq!(
+            extracted(source, 4..=5).unwrap(),
+            "def helper items, count
+    for item in items
+        count = count + 1
+    return count
+
+def report items
+    let header = \"Items\"
+    let count = 0
+    count = helper(items, count)
+    print(header + \": \" + str(count))
+    return count
+"
+        );
+
+        assert_eq!(
+            extracted(source, 4..=4).unwrap_err(),
+            "The statement at line 4 continues past the selected lines"
+        );
+        assert_eq!(
+            extracted(source, 6..=7).unwrap_err(),
+            "Selected lines contain a return from the enclosing function"
+        );
+        assert_eq!(
+            extracted(source, 2..=3).unwrap_err(),
+            "Selected lines define more than one variable used afterwards: header, count"
+        );
+        assert_eq!(
+            extract_function(source, 3..=5, "report").unwrap_err(),
+            "'report' is already defined (line 1)"
+        );
+
+        let script = "let xs = [1, 2]\nprint(len(xs))\nprint(xs)\n";
+        assert_eq!(
+            extracted(script, 2..=3).unwrap(),
+            "let xs = [1, 2]\ndef helper\n    print(len(xs))\n    print(xs)\n\nhelper()\n"
+        );
+        assert_eq!(code_actions(script, 2..=3).len(), 1);
+        assert!(code_actions(script, 4..=4).is_empty());
+    }
+}
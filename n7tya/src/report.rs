@@ -0,0 +1,137 @@
+//! 不具合報告用のレポート (n7tya report)
+//!
+//! バージョン・OS・対象ファイル・トークン列・AST を1つの tar にまとめる。
+//! どこにも送信せず、ファイルを書き出すだけ。
+
+use crate::lexer::{Lexer, Token, TokenInfo};
+use crate::parser::Parser;
+use crate::typechecker::TypeChecker;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// レポートを作成し、書き出した tar のパスを返す
+/// (redact なら文字列リテラルとコメントの中身を伏せる)
+pub fn create_report(path: &Path, redact: bool) -> Result<PathBuf, String> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read file '{}': {}", path.display(), e))?;
+    let source = if redact {
+        redact_source(&source)
+    } else {
+        source
+    };
+
+    let tokens = Lexer::new(&source).tokenize();
+    let (ast, diagnostics) = match Parser::new(tokens.clone()).parse() {
+        Ok(program) => {
+            let mut checker = TypeChecker::new();
+            let errors = checker
+                .check(&program)
+                .unwrap_or_else(|e| vec![e.to_string()]);
+            (format!("{:#?}\n", program), errors.join("\n"))
+        }
+        Err(e) => (String::new(), format!("Parse error: {}", e)),
+    };
+
+    let files = [
+        ("environment.txt", environment(path, redact)),
+        ("source.n7t", source),
+        ("tokens.txt", dump_tokens(&tokens)),
+        ("ast.txt", ast),
+        ("diagnostics.txt", diagnostics),
+    ];
+
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("source");
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let out = PathBuf::from(format!("n7tya-report-{}-{}.tar", stem, timestamp));
+    write_archive(&out, &format!("n7tya-report-{}", stem), &files)?;
+    Ok(out)
+}
+
+fn environment(path: &Path, redact: bool) -> String {
+    format!(
+        "n7tya: {}\nos: {}\narch: {}\nfile: {}\nredacted: {}\n",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        path.file_name().and_then(|s| s.to_str()).unwrap_or(""),
+        redact
+    )
+}
+
+fn dump_tokens(tokens: &[TokenInfo]) -> String {
+    tokens
+        .iter()
+        .map(|t| format!("{}:{} {:?}\n", t.line, t.column, t.token))
+        .collect()
+}
+
+/// 文字列リテラルとコメントの中身を x に置き換える (行・桁・トークンの種類は変わらない)
+fn redact_source(source: &str) -> String {
+    let mut bytes: Vec<u8> = source.as_bytes().to_vec();
+    let mut mask = |range: std::ops::Range<usize>| {
+        for b in &mut bytes[range] {
+            // マルチバイト文字も1バイトずつ置き換えるので、表示幅は変わりうる
+            if !matches!(*b, b'\n' | b'\r' | b'\\') {
+                *b = b'x';
+            }
+        }
+    };
+
+    for info in Lexer::new(source).tokenize() {
+        match &info.token {
+            Token::StringLiteral(_) | Token::MultiLineString(_) => {
+                // 両端の引用符は残す
+                mask(info.span.start + 1..info.span.end - 1)
+            }
+            Token::Comment(_) => mask(info.span.start + 1..info.span.end),
+            Token::Newline => {
+                if let Some(comment) = &info.trailing_comment {
+                    let end = source[..info.span.start]
+                        .strip_suffix('\r')
+                        .map_or(info.span.start, str::len);
+                    mask(end - comment.len()..end);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn write_archive(out: &Path, dir: &str, files: &[(&str, String)]) -> Result<(), String> {
+    let file =
+        fs::File::create(out).map_err(|e| format!("Failed to create {}: {}", out.display(), e))?;
+    let mut builder = tar::Builder::new(file);
+    for (name, content) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, format!("{}/{}", dir, name), content.as_bytes())
+            .map_err(|e| format!("Failed to write {}: {}", out.display(), e))?;
+    }
+    builder
+        .finish()
+        .map_err(|e| format!("Failed to write {}: {}", out.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_source() {
+        let source = "let key = \"secret\" # token abc\n# note\nprint 'a\\'b'\n";
+        assert_eq!(
+            redact_source(source),
+            "let key = \"xxxxxx\" #xxxxxxxxxx\n#xxxxx\nprint 'x\\xx'\n"
+        );
+    }
+}
@@ -0,0 +1,226 @@
+//! retry と circuit モジュール (不安定な呼び出し先への対策)
+//!
+//! `retry(fetch, 3, 0.5)` は fetch() が失敗したら 0.5 秒、1 秒と間隔を倍にしながら最大 3 回まで呼ぶ。
+//! `circuit.call("rates", fetch)` は失敗が続いた呼び出し先をしばらく呼ばずに、すぐ Err を返す。
+//! どちらも関数が実行時エラーになるか Err(...) を返したときを失敗とみなす。
+//! 実行の上限 (--timeout など) を超えたエラーは失敗として数えず、そのまま返す。
+
+use crate::interpreter::{Interpreter, Value};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// circuit.call で回数と秒数を省略したときの値
+pub const DEFAULT_FAILURES: u32 = 5;
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// retry(fn, attempts) / retry(fn, attempts, backoff)
+pub fn retry(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    let (function, attempts, backoff) = match args.as_slice() {
+        [function, Value::Int(attempts), rest @ ..] if *attempts > 0 && rest.len() <= 1 => {
+            let backoff = match rest {
+                [] => Duration::ZERO,
+                [seconds] => seconds_arg("retry() backoff", seconds)?,
+                _ => unreachable!(),
+            };
+            (function.clone(), *attempts, backoff)
+        }
+        _ => {
+            return Err(
+                "retry() expects a function, a positive number of attempts and an optional backoff in seconds"
+                    .to_string(),
+            )
+        }
+    };
+    let mut wait = backoff;
+    for attempt in 1..=attempts {
+        let result = interpreter.call_function(function.clone(), Vec::new());
+        if attempt == attempts || !failed(&result) {
+            return result;
+        }
+        std::thread::sleep(wait);
+        wait = wait.saturating_mul(2);
+    }
+    unreachable!("attempts is positive")
+}
+
+/// 名前ごとのサーキットブレーカー (リクエストをまたいで残る)
+#[derive(Debug, Default)]
+pub struct Circuits {
+    circuits: HashMap<String, Circuit>,
+}
+
+#[derive(Debug)]
+struct Circuit {
+    failures: u32,           // 続けて失敗した回数
+    opened: Option<Instant>, // 開いた (呼ばなくなった) 時刻
+    cooldown: Duration,      // 開いてから次に試すまでの時間
+}
+
+impl Circuits {
+    /// "closed" (呼ぶ) / "open" (呼ばない) / "half_open" (次の1回を試す)
+    pub fn status(&self, name: &str) -> &'static str {
+        match self
+            .circuits
+            .get(name)
+            .and_then(|c| c.opened.map(|t| (t, c)))
+        {
+            Some((opened, circuit)) if opened.elapsed() < circuit.cooldown => "open",
+            Some(_) => "half_open",
+            None => "closed",
+        }
+    }
+
+    pub fn reset(&mut self, name: &str) {
+        self.circuits.remove(name);
+    }
+
+    /// 呼び出しの結果を記録する (失敗が failures 回続くか、試した1回が失敗したら開く)
+    fn record(&mut self, name: &str, failed: bool, failures: u32, cooldown: Duration) {
+        if !failed {
+            self.circuits.remove(name);
+            return;
+        }
+        let circuit = self.circuits.entry(name.to_string()).or_insert(Circuit {
+            failures: 0,
+            opened: None,
+            cooldown,
+        });
+        circuit.failures += 1;
+        circuit.cooldown = cooldown;
+        if circuit.opened.is_some() || circuit.failures >= failures {
+            circuit.opened = Some(Instant::now());
+        }
+    }
+}
+
+/// circuit.call(name, fn[, failures, cooldown]) / circuit.status(name) / circuit.reset(name)
+pub fn call(
+    interpreter: &mut Interpreter,
+    function: &str,
+    args: Vec<Value>,
+) -> Result<Value, String> {
+    match (function, args.as_slice()) {
+        ("call", [Value::Str(name), callee, rest @ ..]) => {
+            let (failures, cooldown) = match rest {
+                [] => (DEFAULT_FAILURES, DEFAULT_COOLDOWN),
+                [Value::Int(failures), cooldown] if *failures > 0 => (
+                    u32::try_from(*failures).unwrap_or(u32::MAX),
+                    seconds_arg("circuit.call() cooldown", cooldown)?,
+                ),
+                _ => {
+                    return Err(
+                        "circuit.call() expects a name, a function, and optionally a positive failure count and a cooldown in seconds"
+                            .to_string(),
+                    )
+                }
+            };
+            if interpreter.circuits().status(name) == "open" {
+                let message = format!("Circuit '{}' is open", name);
+                return Ok(Value::Err(Rc::new(Value::Str(message.into()))));
+            }
+            let result = interpreter.call_function(callee.clone(), Vec::new());
+            if !is_budget_error(&result) {
+                let failed = failed(&result);
+                interpreter
+                    .circuits()
+                    .record(name, failed, failures, cooldown);
+            }
+            result
+        }
+        ("status", [Value::Str(name)]) => {
+            Ok(Value::Str(interpreter.circuits().status(name).into()))
+        }
+        ("reset", [Value::Str(name)]) => {
+            interpreter.circuits().reset(name);
+            Ok(Value::None)
+        }
+        ("call" | "status" | "reset", _) => Err(format!(
+            "circuit.{}() expects a circuit name as its first argument",
+            function
+        )),
+        _ => Err(format!("Module 'circuit' has no member '{}'", function)),
+    }
+}
+
+/// 実行時エラーか Err(...) を返したら失敗 (実行の上限を超えたときは再試行しない)
+fn failed(result: &Result<Value, String>) -> bool {
+    match result {
+        Ok(value) => matches!(value, Value::Err(_)),
+        Err(_) => !is_budget_error(result),
+    }
+}
+
+fn is_budget_error(result: &Result<Value, String>) -> bool {
+    matches!(result, Err(e) if e.starts_with(crate::limits::BUDGET_EXCEEDED))
+}
+
+fn seconds_arg(what: &str, value: &Value) -> Result<Duration, String> {
+    let seconds = crate::units::seconds(value).unwrap_or(f64::NAN);
+    if !(seconds.is_finite() && seconds >= 0.0) {
+        return Err(format!(
+            "{} must be a non-negative number of seconds, got {}",
+            what,
+            value.display()
+        ));
+    }
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn run(interpreter: &mut Interpreter, source: &str) -> Result<String, String> {
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        interpreter.run(&program).map(|value| value.display())
+    }
+
+    #[test]
+    fn test_retry() {
+        let mut interpreter = Interpreter::new();
+        let source = "let calls = []\ndef flaky\n    calls.append(1)\n    if len(calls) < 3\n        return Err(\"down\")\n    return Ok(len(calls))\n[retry(flaky, 5, 0.001), len(calls)]\n";
+        assert_eq!(run(&mut interpreter, source).unwrap(), "[Ok(3), 3]");
+
+        // 回数を使い切ったら最後の結果 (実行時エラーならそのエラー)
+        let source =
+            "let calls = []\ndef broken\n    calls.append(1)\n    return 1 / 0\nretry(broken, 2)\n";
+        assert_eq!(
+            run(&mut interpreter, source).unwrap_err(),
+            "Division by zero"
+        );
+        assert_eq!(run(&mut interpreter, "len(calls)\n").unwrap(), "2");
+        assert!(run(&mut interpreter, "retry(len, 0)\n").is_err());
+        assert!(run(&mut interpreter, "retry(len, 1, -1)\n").is_err());
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let mut interpreter = Interpreter::new();
+        let source = "let calls = []\ndef down\n    calls.append(1)\n    return Err(\"down\")\ndef up\n    return Ok(1)\n";
+        run(&mut interpreter, source).unwrap();
+        let source = "[circuit.call(\"api\", down, 2, 0.05), circuit.status(\"api\"), circuit.call(\"api\", down, 2, 0.05), circuit.status(\"api\"), circuit.call(\"api\", down, 2, 0.05), len(calls)]\n";
+        assert_eq!(
+            run(&mut interpreter, source).unwrap(),
+            "[Err(down), closed, Err(down), open, Err(Circuit 'api' is open), 2]"
+        );
+
+        // 時間が経ったら1回だけ試し、失敗すればまた開く
+        std::thread::sleep(Duration::from_millis(60));
+        let source = "[circuit.status(\"api\"), circuit.call(\"api\", down, 2, 0.05), circuit.status(\"api\"), len(calls)]\n";
+        assert_eq!(
+            run(&mut interpreter, source).unwrap(),
+            "[half_open, Err(down), open, 3]"
+        );
+        std::thread::sleep(Duration::from_millis(60));
+        let source = "[circuit.call(\"api\", up, 2, 0.05), circuit.status(\"api\")]\n";
+        assert_eq!(run(&mut interpreter, source).unwrap(), "[Ok(1), closed]");
+
+        let source =
+            "circuit.call(\"api\", down, 1, 60)\ncircuit.reset(\"api\")\ncircuit.status(\"api\")\n";
+        assert_eq!(run(&mut interpreter, source).unwrap(), "closed");
+        assert!(run(&mut interpreter, "circuit.call(\"api\", up, 0, 1)\n").is_err());
+    }
+}
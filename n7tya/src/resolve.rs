@@ -0,0 +1,1280 @@
+//! 名前解決 (シンボルテーブル)
+//!
+//! AST をたどって、スコープごとの定義と、名前の参照がどの定義を指すかを集める。
+//! 型チェッカーは未定義の名前と nonlocal の誤りをここから報告し、
+//! インタプリタは import したモジュールが公開する名前をここから決める。
+//! トークン列を渡すと定義と参照にソース上の位置が付き、定義へのジャンプや名前の変更に使える。
+//!
+//! スコープはインタプリタの環境と同じく、関数 (メソッド・ラムダ・ルートを含む) とコンポーネントが作る。
+//! if / for / match の中で定義した名前は、それを含む関数のスコープに入る。
+//! 同じ関数 (とモジュールのトップレベル) の中では、参照より前に定義した名前だけが見える。
+//! 外側のスコープの名前は呼び出すときにあればよいので、関数より後で定義したものも見える。
+//! import はどこに書いても先に実行されるので、モジュールの先頭で定義したものとする。
+//! クラスの本体もスコープを作るが、メソッドの中からは見えない (self.x や Class.X で参照する)。
+//!
+//! 名前を隠す (shadowing) のは、関数の中の let・引数・for の変数が外側の関数の変数と同じ名前のときだけ。
+//! 紛らわしいものは警告として集める (外側の関数の変数を隠す定義と、ブロックの中で前の変数を置き換える let)。
+//! let を書かない代入を許すかは ScopeRules で決める (決めるのは型チェッカーとインタプリタ)。
+
+use crate::ast::*;
+use crate::lexer::{Token, TokenInfo};
+use crate::visit::{self, Visitor};
+use std::ops::Range;
+
+/// SymbolTable::scopes の添字
+pub type ScopeId = usize;
+/// SymbolTable::symbols の添字
+pub type SymbolId = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScopeKind {
+    Module,
+    Function,
+    Class,
+    Component,
+}
+
+#[derive(Debug, Clone)]
+pub struct Scope {
+    pub kind: ScopeKind,
+    pub name: Option<String>, // 関数・クラス・コンポーネントの名前 (トップレベルとラムダは None)
+    pub parent: Option<ScopeId>,
+    pub symbols: Vec<SymbolId>, // 定義した順
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Variable,
+    Constant,
+    Parameter,
+    Function,
+    Class,
+    Struct,
+    Interface,
+    Component,
+    Server,
+    Import,
+    State,
+    Field,
+    Method,
+    Implicit, // let を書かずに代入して作った変数
+}
+
+/// ソース上の位置 (トークン列を渡したときだけ付く)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Position {
+    pub span: Range<usize>,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// 名前の定義
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub scope: ScopeId,
+    pub position: Option<Position>, // self や request のように暗黙に定義される名前は None
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReferenceKind {
+    Read,
+    Write,    // 代入の左辺 (for の変数などで nonlocal の名前に書く場合も)
+    Type,     // 型注釈・構造体リテラル・パターン・JSX のコンポーネント名
+    Nonlocal, // nonlocal で宣言した名前
+}
+
+/// 名前の参照
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub name: String,
+    pub kind: ReferenceKind,
+    pub scope: ScopeId,           // 参照を書いたスコープ
+    pub symbol: Option<SymbolId>, // 組み込みの名前や未定義の名前は None
+    pub position: Option<Position>,
+}
+
+/// 名前解決の結果
+#[derive(Debug, Clone)]
+pub struct SymbolTable {
+    pub scopes: Vec<Scope>, // 0 番目がモジュールのトップレベル
+    pub symbols: Vec<Symbol>,
+    pub references: Vec<Reference>, // たどった順
+    pub errors: Vec<String>,        // nonlocal の使い方の誤り
+    pub warnings: Vec<String>,      // 紛らわしい名前の隠し方
+}
+
+impl SymbolTable {
+    /// モジュールのトップレベルで定義した名前
+    pub fn globals(&self) -> impl Iterator<Item = &Symbol> {
+        self.scopes[0].symbols.iter().map(|&id| &self.symbols[id])
+    }
+
+    /// 定義が見つからなかった参照 (組み込みの名前も含むので、呼ぶ側で除く)
+    ///
+    /// 型の名前は構造体やクラスのほかに Int や Dict もあるので含めない。
+    pub fn undefined(&self) -> impl Iterator<Item = &Reference> {
+        self.references
+            .iter()
+            .filter(|r| r.symbol.is_none() && r.kind != ReferenceKind::Type)
+    }
+
+    /// scope から見える name の定義 (書いた位置によらない)
+    pub fn lookup(&self, scope: ScopeId, name: &str) -> Option<SymbolId> {
+        self.find(scope, name, usize::MAX)
+    }
+
+    /// scope では先頭から visible 個までの定義、外側のスコープでは全ての定義から探す
+    fn find(&self, scope: ScopeId, name: &str, visible: usize) -> Option<SymbolId> {
+        let mut scope = scope;
+        let mut visible = visible;
+        loop {
+            let symbols = &self.scopes[scope].symbols;
+            let found = symbols[..visible.min(symbols.len())]
+                .iter()
+                .rev()
+                .find(|&&id| self.symbols[id].name == name);
+            if let Some(&id) = found {
+                return Some(id);
+            }
+            scope = self.enclosing(scope)?;
+            visible = usize::MAX;
+        }
+    }
+
+    /// 名前を探しに行く外側のスコープ (クラスの本体は飛ばす)
+    fn enclosing(&self, scope: ScopeId) -> Option<ScopeId> {
+        let mut parent = self.scopes[scope].parent?;
+        while self.scopes[parent].kind == ScopeKind::Class {
+            parent = self.scopes[parent].parent?;
+        }
+        Some(parent)
+    }
+
+    /// offset の位置に書いた名前が指す定義 (定義そのものの位置でもよい)
+    pub fn symbol_at(&self, offset: usize) -> Option<SymbolId> {
+        let contains = |position: &Option<Position>| {
+            position
+                .as_ref()
+                .is_some_and(|p| p.span.start <= offset && offset < p.span.end)
+        };
+        self.symbols
+            .iter()
+            .position(|symbol| contains(&symbol.position))
+            .or_else(|| {
+                self.references
+                    .iter()
+                    .find(|reference| contains(&reference.position))
+                    .and_then(|reference| reference.symbol)
+            })
+    }
+
+    /// offset の位置の名前の定義 (定義へのジャンプ)
+    pub fn definition_at(&self, offset: usize) -> Option<&Symbol> {
+        self.symbol_at(offset).map(|id| &self.symbols[id])
+    }
+
+    /// 定義と、それを指す全ての参照の位置 (ソースの順。名前の変更はここを全て書き換える)
+    pub fn occurrences(&self, id: SymbolId) -> Vec<&Position> {
+        let mut positions: Vec<&Position> = self.symbols[id]
+            .position
+            .iter()
+            .chain(
+                self.references
+                    .iter()
+                    .filter(|r| r.symbol == Some(id))
+                    .filter_map(|r| r.position.as_ref()),
+            )
+            .collect();
+        positions.sort_by_key(|p| p.span.start);
+        positions
+    }
+}
+
+/// 代入と名前の隠し方の規則 (n7tya.toml の [scope])
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScopeRules {
+    /// let を書かずに代入した名前を変数として作る (false なら未定義の変数への代入はエラー)
+    pub implicit_declarations: bool,
+    /// 紛らわしい名前の隠し方を型チェックで警告する
+    pub warn_shadowing: bool,
+}
+
+impl Default for ScopeRules {
+    fn default() -> Self {
+        Self {
+            implicit_declarations: false,
+            warn_shadowing: true,
+        }
+    }
+}
+
+impl ScopeRules {
+    /// n7tya.toml の内容から [scope] を読み込む (未指定の項目はデフォルト)
+    pub fn from_toml(content: &str) -> Result<Self, String> {
+        let table: toml::Table = content
+            .parse()
+            .map_err(|e| format!("Invalid n7tya.toml: {}", e))?;
+        let mut rules = Self::default();
+        let Some(section) = table.get("scope") else {
+            return Ok(rules);
+        };
+        let section = section
+            .as_table()
+            .ok_or_else(|| "[scope] must be a table".to_string())?;
+        for (key, value) in section {
+            let flag = value
+                .as_bool()
+                .ok_or_else(|| format!("scope.{} must be true or false, got {}", key, value))?;
+            match key.as_str() {
+                "implicit_declarations" => rules.implicit_declarations = flag,
+                "warn_shadowing" => rules.warn_shadowing = flag,
+                _ => return Err(format!("Unknown key scope.{}", key)),
+            }
+        }
+        Ok(rules)
+    }
+}
+
+/// 位置を付けずに名前を解決する
+pub fn resolve(program: &Program) -> SymbolTable {
+    Resolver::new().resolve(program)
+}
+
+/// AST をたどってシンボルテーブルを作る
+///
+/// 位置はパーサーが AST を作った順 (= ソースに書いた順) にトークン列から同じ名前を探して付ける。
+/// そのため名前を含むノードは、参照として記録しないもの (メンバー名や属性名など) も書いた順に読み飛ばす。
+pub struct Resolver<'a> {
+    table: SymbolTable,
+    scope: ScopeId,
+    pending: Vec<(usize, ScopeId, usize)>, // (参照の添字, 探し始めるスコープ, そこで見える定義の数)
+    nonlocals: Vec<(ScopeId, String)>,
+    branches: Vec<(usize, usize)>, // 今いるブロック (if / match などの文の番号, 何番目のブロックか)
+    paths: Vec<Vec<(usize, usize)>>, // 定義ごとの、定義したときの branches
+    statements: usize,             // ブロックを持つ文に振った番号の数
+    source: &'a str,
+    tokens: &'a [TokenInfo],
+    cursor: usize,
+}
+
+impl Default for Resolver<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new() -> Self {
+        Self {
+            table: SymbolTable {
+                scopes: vec![Scope {
+                    kind: ScopeKind::Module,
+                    name: None,
+                    parent: None,
+                    symbols: Vec::new(),
+                }],
+                symbols: Vec::new(),
+                references: Vec::new(),
+                errors: Vec::new(),
+                warnings: Vec::new(),
+            },
+            scope: 0,
+            pending: Vec::new(),
+            nonlocals: Vec::new(),
+            branches: Vec::new(),
+            paths: Vec::new(),
+            statements: 0,
+            source: "",
+            tokens: &[],
+            cursor: 0,
+        }
+    }
+
+    /// program を構文解析したトークン列とソースから、定義と参照の位置を付ける
+    pub fn with_tokens(mut self, source: &'a str, tokens: &'a [TokenInfo]) -> Self {
+        self.source = source;
+        self.tokens = tokens;
+        self
+    }
+
+    pub fn resolve(mut self, program: &Program) -> SymbolTable {
+        // import は先に実行されるので、位置は後で書いた順にたどるときに付ける
+        for item in &program.items {
+            if let Item::Import(import) = item {
+                for name in imported_names(import) {
+                    self.define(&name, SymbolKind::Import, None);
+                }
+            }
+        }
+        self.visit_program(program);
+
+        for (index, scope, visible) in std::mem::take(&mut self.pending) {
+            let name = &self.table.references[index].name;
+            self.table.references[index].symbol = self.table.find(scope, name, visible);
+        }
+        self.table
+    }
+
+    // ===== スコープと定義 =====
+
+    fn push_scope(&mut self, kind: ScopeKind, name: Option<&str>) {
+        self.table.scopes.push(Scope {
+            kind,
+            name: name.map(str::to_string),
+            parent: Some(self.scope),
+            symbols: Vec::new(),
+        });
+        self.scope = self.table.scopes.len() - 1;
+    }
+
+    fn pop_scope(&mut self) {
+        if let Some(parent) = self.table.scopes[self.scope].parent {
+            self.scope = parent;
+        }
+    }
+
+    fn define(&mut self, name: &str, kind: SymbolKind, position: Option<Position>) -> SymbolId {
+        let id = self.table.symbols.len();
+        self.table.symbols.push(Symbol {
+            name: name.to_string(),
+            kind,
+            scope: self.scope,
+            position,
+        });
+        self.table.scopes[self.scope].symbols.push(id);
+        self.paths.push(self.branches.clone());
+        id
+    }
+
+    /// let / const / def: nonlocal と宣言した名前は定義し直せない
+    fn declare(&mut self, name: &str, kind: SymbolKind, position: Option<Position>) {
+        if self.is_nonlocal(name) {
+            self.table.errors.push(format!(
+                "'{}' is declared nonlocal and cannot be redefined here",
+                name
+            ));
+            self.reference(name, ReferenceKind::Write, position);
+        } else {
+            self.define(name, kind, position);
+        }
+    }
+
+    /// for の変数やパターンの名前: nonlocal と宣言した名前なら外側の変数に書く
+    fn bind(&mut self, name: &str, position: Option<Position>) {
+        if self.is_nonlocal(name) {
+            self.reference(name, ReferenceKind::Write, position);
+        } else {
+            self.check_shadowing(name, false);
+            self.define(name, SymbolKind::Variable, position);
+        }
+    }
+
+    /// 関数の中の定義が、外側の関数やコンポーネントの変数を隠すなら警告する
+    /// (グローバル変数を隠すのはよくあることなので警告しない)
+    fn check_shadowing(&mut self, name: &str, assignable: bool) {
+        if name == "_" || self.table.scopes[self.scope].kind != ScopeKind::Function {
+            return;
+        }
+        let Some(outer) = self
+            .table
+            .enclosing(self.scope)
+            .and_then(|parent| self.table.lookup(parent, name))
+        else {
+            return;
+        };
+        let symbol = &self.table.symbols[outer];
+        if self.table.scopes[symbol.scope].kind == ScopeKind::Module || !is_variable(symbol.kind) {
+            return;
+        }
+        let mut warning = format!(
+            "'{}' in {} shadows the one in {}",
+            name,
+            self.describe(self.scope),
+            self.describe(symbol.scope)
+        );
+        if assignable {
+            warning.push_str(&format!(" (use nonlocal {} to assign to it)", name));
+        }
+        self.table.warnings.push(warning);
+    }
+
+    /// ブロックの中の let が、同じスコープで前に定義した変数を置き換えるなら警告する
+    /// (if と else のように、どちらか一方しか実行しないブロック同士は除く)
+    fn check_redeclaration(&mut self, name: &str) {
+        if self.branches.is_empty() {
+            return;
+        }
+        let replaced = self.table.scopes[self.scope].symbols.iter().any(|&id| {
+            self.table.symbols[id].name == name
+                && is_variable(self.table.symbols[id].kind)
+                && !exclusive(&self.paths[id], &self.branches)
+        });
+        if replaced {
+            self.table.warnings.push(format!(
+                "let {} inside a block of {} replaces the variable defined before it \
+                 (blocks do not create a new scope; use {} = ... to assign)",
+                name,
+                self.describe(self.scope),
+                name
+            ));
+        }
+    }
+
+    /// 警告に出すスコープの名前
+    fn describe(&self, scope: ScopeId) -> String {
+        let scope = &self.table.scopes[scope];
+        match (&scope.name, scope.kind) {
+            (_, ScopeKind::Module) => "the top level".to_string(),
+            (None, _) => "a lambda".to_string(),
+            (Some(name), ScopeKind::Component) => format!("component '{}'", name),
+            (Some(name), ScopeKind::Class) => format!("class '{}'", name),
+            (Some(name), ScopeKind::Function) => format!("'{}'", name),
+        }
+    }
+
+    /// ブロックの中身をたどる (statement は if / match などの文の番号)
+    fn branch(&mut self, statement: usize, index: usize, block: &[Statement]) {
+        self.branches.push((statement, index));
+        self.visit_block(block);
+        self.branches.pop();
+    }
+
+    fn next_statement(&mut self) -> usize {
+        self.statements += 1;
+        self.statements
+    }
+
+    fn is_nonlocal(&self, name: &str) -> bool {
+        self.nonlocals
+            .iter()
+            .any(|(scope, n)| *scope == self.scope && n == name)
+    }
+
+    /// 参照を記録する (どの定義を指すかは全てたどり終えてから決める)
+    fn reference(&mut self, name: &str, kind: ReferenceKind, position: Option<Position>) {
+        let (scope, visible) = if kind == ReferenceKind::Nonlocal || self.is_nonlocal(name) {
+            (self.table.enclosing(self.scope).unwrap_or(0), usize::MAX)
+        } else if kind == ReferenceKind::Type {
+            (self.scope, usize::MAX)
+        } else {
+            (self.scope, self.visible(self.scope))
+        };
+        self.pending
+            .push((self.table.references.len(), scope, visible));
+        self.table.references.push(Reference {
+            name: name.to_string(),
+            kind,
+            scope: self.scope,
+            symbol: None,
+            position,
+        });
+    }
+
+    /// 今の時点で scope から見える定義の数 (関数とモジュールは書いた順、それ以外は全て)
+    fn visible(&self, scope: ScopeId) -> usize {
+        match self.table.scopes[scope].kind {
+            ScopeKind::Module | ScopeKind::Function => self.table.scopes[scope].symbols.len(),
+            ScopeKind::Class | ScopeKind::Component => usize::MAX,
+        }
+    }
+
+    /// 代入: 見える変数がなければインタプリタと同じく今のスコープに作る (未定義として報告する)
+    fn assign(&mut self, name: &str, position: Option<Position>) {
+        let defined = self.is_nonlocal(name)
+            || self
+                .table
+                .find(self.scope, name, self.visible(self.scope))
+                .is_some();
+        if defined {
+            self.reference(name, ReferenceKind::Write, position);
+        } else {
+            self.table.references.push(Reference {
+                name: name.to_string(),
+                kind: ReferenceKind::Write,
+                scope: self.scope,
+                symbol: None,
+                position: position.clone(),
+            });
+            self.define(name, SymbolKind::Implicit, position);
+        }
+    }
+
+    fn nonlocal(&mut self, name: &str, position: Option<Position>) {
+        if self.table.scopes[self.scope].kind == ScopeKind::Module {
+            self.table
+                .errors
+                .push("nonlocal can only be used inside a function".to_string());
+            return;
+        }
+        let defined_here = self.table.scopes[self.scope]
+            .symbols
+            .iter()
+            .any(|&id| self.table.symbols[id].name == name);
+        if defined_here {
+            self.table.errors.push(format!(
+                "'{}' is already defined in this function and cannot be declared nonlocal",
+                name
+            ));
+            return;
+        }
+        self.reference(name, ReferenceKind::Nonlocal, position);
+        if !self.is_nonlocal(name) {
+            self.nonlocals.push((self.scope, name.to_string()));
+        }
+    }
+
+    // ===== トークンの位置 =====
+
+    /// カーソルより後で、name と同じ名前のトークンの位置 (見つかればその次にカーソルを進める)
+    ///
+    /// member が true ならメンバー名 (直前が `.`)、false ならそれ以外の名前だけを探す。
+    fn find_token(&mut self, name: &str, member: bool) -> Option<Position> {
+        let found = (self.cursor..self.tokens.len()).find(|&i| {
+            let info = &self.tokens[i];
+            let after_dot = i > 0 && self.tokens[i - 1].token == Token::Dot;
+            !matches!(
+                info.token,
+                Token::StringLiteral(_)
+                    | Token::MultiLineString(_)
+                    | Token::JsxText(_)
+                    | Token::Comment(_)
+            ) && after_dot == member
+                && self.source.get(info.span.clone()) == Some(name)
+        })?;
+        self.cursor = found + 1;
+        let info = &self.tokens[found];
+        Some(Position {
+            span: info.span.clone(),
+            line: info.line,
+            column: info.column,
+        })
+    }
+
+    fn locate(&mut self, name: &str) -> Option<Position> {
+        self.find_token(name, false)
+    }
+
+    /// 参照として記録しない名前 (メンバー名・属性名・ルートのメソッドなど) を読み飛ばす
+    fn skip(&mut self, name: &str) {
+        self.find_token(name, false);
+    }
+
+    /// List と Json 以外の型引数 (`Dict<Str, Int>`) は AST に残らないので読み飛ばす
+    fn skip_type_args(&mut self) {
+        if self.tokens.get(self.cursor).map(|t| &t.token) != Some(&Token::Lt) {
+            return;
+        }
+        if let Some(end) =
+            (self.cursor..self.tokens.len()).find(|&i| self.tokens[i].token == Token::Gt)
+        {
+            self.cursor = end + 1;
+        }
+    }
+
+    /// JSX の閉じタグの名前の位置 (`/>` で閉じていれば None)
+    fn close_tag(&mut self, tag: &str) -> Option<Position> {
+        let end = (self.cursor..self.tokens.len())
+            .find(|&i| matches!(self.tokens[i].token, Token::SelfClose | Token::CloseTag))?;
+        self.cursor = end + 1;
+        if self.tokens[end].token == Token::SelfClose {
+            return None;
+        }
+        self.locate(tag)
+    }
+
+    /// 構造体パターンの `{name}` (`{name: n}` の省略形) か
+    fn is_shorthand_field(&self) -> bool {
+        !self.tokens.is_empty()
+            && self.tokens.get(self.cursor).map(|t| &t.token) != Some(&Token::Colon)
+    }
+
+    // ===== 定義のたどり方 =====
+
+    /// def: 名前を先に定義してから本体をたどる (再帰呼び出しのため)
+    fn function(&mut self, function: &FunctionDef, kind: SymbolKind, implicit: &[&str]) {
+        let position = self.locate(&function.name);
+        if kind == SymbolKind::Function {
+            self.declare(&function.name, kind, position);
+        } else {
+            self.define(&function.name, kind, position);
+        }
+        self.push_scope(ScopeKind::Function, Some(&function.name));
+        let branches = std::mem::take(&mut self.branches);
+        for name in implicit {
+            self.define(name, SymbolKind::Parameter, None);
+        }
+        self.params(&function.params);
+        if let Some(ty) = &function.return_type {
+            self.visit_type(ty);
+        }
+        // copy の変数は def を実行したときに外側で読む (本体では外側と同じ変数として扱う)
+        let inner = self.scope;
+        self.scope = self.table.scopes[inner].parent.unwrap_or(0);
+        let copies = function
+            .body
+            .iter()
+            .take_while(|s| matches!(s, Statement::Copy(_) | Statement::Trivia(_)));
+        for statement in copies {
+            if let Statement::Copy(names) = statement {
+                for name in names {
+                    let position = self.locate(name);
+                    self.reference(name, ReferenceKind::Read, position);
+                }
+            }
+        }
+        self.scope = inner;
+        self.visit_block(&function.body);
+        self.branches = branches;
+        self.pop_scope();
+    }
+
+    fn params(&mut self, params: &[Param]) {
+        for param in params {
+            let position = self.locate(&param.name);
+            if let Some(ty) = &param.type_annotation {
+                self.visit_type(ty);
+            }
+            self.check_shadowing(&param.name, false);
+            self.define(&param.name, SymbolKind::Parameter, position);
+        }
+    }
+
+    fn field(&mut self, field: &FieldDef, kind: Option<SymbolKind>) {
+        let position = self.locate(&field.name);
+        if let Some(kind) = kind {
+            self.define(&field.name, kind, position);
+        }
+        self.visit_type(&field.type_annotation);
+        for check in &field.checks {
+            self.skip(&check.name);
+        }
+        if let Some(default) = &field.default {
+            self.visit_expression(default);
+        }
+    }
+
+    /// let / const: 値の中の同じ名前は外側の変数を指す
+    fn declaration(&mut self, name: &str, ty: &Option<Type>, value: &Expression, kind: SymbolKind) {
+        let position = self.locate(name);
+        if let Some(ty) = ty {
+            self.visit_type(ty);
+        }
+        self.visit_expression(value);
+        if !self.is_nonlocal(name) && kind == SymbolKind::Variable {
+            self.check_shadowing(name, true);
+            self.check_redeclaration(name);
+        }
+        self.declare(name, kind, position);
+    }
+
+    fn import(&mut self, import: &ImportStmt) {
+        if import.lazy {
+            self.skip("lazy");
+        }
+        let module = self.locate(&import.module);
+        let mut positions = Vec::new();
+        if let Some(alias) = &import.alias {
+            positions.push(self.locate(alias));
+        } else if import.names.is_empty() {
+            positions.push(module);
+        } else {
+            for name in &import.names {
+                positions.push(self.locate(name));
+            }
+        }
+        // 先に定義しておいた import の名前に位置を付ける
+        for (name, position) in imported_names(import).iter().zip(positions) {
+            let id = self.table.scopes[0].symbols.iter().copied().find(|&id| {
+                let symbol = &self.table.symbols[id];
+                symbol.kind == SymbolKind::Import
+                    && symbol.name == *name
+                    && symbol.position.is_none()
+            });
+            if let Some(id) = id {
+                self.table.symbols[id].position = position;
+            }
+        }
+    }
+}
+
+/// 値を入れ直せる名前 (警告の対象にする定義)
+fn is_variable(kind: SymbolKind) -> bool {
+    matches!(
+        kind,
+        SymbolKind::Variable
+            | SymbolKind::Constant
+            | SymbolKind::Parameter
+            | SymbolKind::Implicit
+            | SymbolKind::State
+    )
+}
+
+/// 2つのブロックの並びが、同じ文の別々のブロック (if と else、match の別の case) に分かれるか
+fn exclusive(a: &[(usize, usize)], b: &[(usize, usize)]) -> bool {
+    a.iter()
+        .zip(b)
+        .find(|(x, y)| x != y)
+        .is_some_and(|(x, y)| x.0 == y.0)
+}
+
+/// import で定義される名前 (import utils は utils、import "pkg/sub" は sub)
+fn imported_names(import: &ImportStmt) -> Vec<String> {
+    if let Some(alias) = &import.alias {
+        vec![alias.clone()]
+    } else if !import.names.is_empty() {
+        import.names.clone()
+    } else {
+        let stem = std::path::Path::new(&import.module)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("module");
+        vec![stem.to_string()]
+    }
+}
+
+impl Visitor for Resolver<'_> {
+    fn visit_item(&mut self, item: &Item) {
+        match item {
+            Item::FunctionDef(function) => self.function(function, SymbolKind::Function, &[]),
+            Item::ClassDef(class) => {
+                let position = self.locate(&class.name);
+                self.define(&class.name, SymbolKind::Class, position);
+                for name in class.parent.iter().chain(&class.interfaces) {
+                    let position = self.locate(name);
+                    self.reference(name, ReferenceKind::Type, position);
+                }
+                self.push_scope(ScopeKind::Class, Some(&class.name));
+                let mut implicit = vec!["self"];
+                if class.parent.is_some() {
+                    implicit.push("super");
+                }
+                for member in &class.body {
+                    match member {
+                        ClassBodyItem::Field(field) => self.field(field, Some(SymbolKind::Field)),
+                        ClassBodyItem::Method(method) => {
+                            self.function(method, SymbolKind::Method, &implicit)
+                        }
+                        ClassBodyItem::StaticMethod(method) => {
+                            self.function(method, SymbolKind::Method, &[])
+                        }
+                        ClassBodyItem::Const(decl) => self.declaration(
+                            &decl.name,
+                            &decl.type_annotation,
+                            &decl.value,
+                            SymbolKind::Constant,
+                        ),
+                        ClassBodyItem::Trivia(_) => {}
+                    }
+                }
+                self.pop_scope();
+            }
+            Item::StructDef(def) => {
+                let position = self.locate(&def.name);
+                self.define(&def.name, SymbolKind::Struct, position);
+                for field in &def.fields {
+                    self.field(field, None);
+                }
+            }
+            Item::InterfaceDef(def) => {
+                let position = self.locate(&def.name);
+                self.define(&def.name, SymbolKind::Interface, position);
+                for method in &def.methods {
+                    self.skip(&method.name);
+                    for param in &method.params {
+                        self.skip(&param.name);
+                        if let Some(ty) = &param.type_annotation {
+                            self.visit_type(ty);
+                        }
+                    }
+                    if let Some(ty) = &method.return_type {
+                        self.visit_type(ty);
+                    }
+                }
+            }
+            Item::ComponentDef(component) => {
+                if component.island {
+                    self.skip("island");
+                }
+                let position = self.locate(&component.name);
+                self.define(&component.name, SymbolKind::Component, position);
+                self.push_scope(ScopeKind::Component, Some(&component.name));
+                self.define("self", SymbolKind::Parameter, None);
+                // island のメソッドは api.get_users() でサーバーのルートを呼べる
+                if component.island {
+                    self.define("api", SymbolKind::Parameter, None);
+                }
+                for member in &component.body {
+                    match member {
+                        ComponentBodyItem::State(state) => {
+                            self.visit_statement(&Statement::State(state.clone()))
+                        }
+                        ComponentBodyItem::Method(method) => {
+                            self.function(method, SymbolKind::Method, &[])
+                        }
+                        ComponentBodyItem::Render(render) => {
+                            self.skip("render");
+                            self.visit_block(&render.body);
+                        }
+                        ComponentBodyItem::Trivia(_) => {}
+                    }
+                }
+                self.pop_scope();
+            }
+            Item::ServerDef(server) => {
+                let position = self.locate(&server.name);
+                self.define(&server.name, SymbolKind::Server, position);
+                for member in &server.body {
+                    let ServerBodyItem::Route(route) = member else {
+                        continue;
+                    };
+                    self.skip(&route.method);
+                    // ルートの本体は request (と型付きのボディ) を引数に取る関数として実行される
+                    let name = format!("{} {}", route.method, route.path);
+                    self.push_scope(ScopeKind::Function, Some(&name));
+                    self.define("request", SymbolKind::Parameter, None);
+                    self.params(route.body_param.as_slice());
+                    if let Some(ty) = &route.return_type {
+                        self.visit_type(ty);
+                    }
+                    self.visit_block(&route.body);
+                    self.pop_scope();
+                }
+            }
+            Item::Import(import) => self.import(import),
+            Item::Statement(statement) => self.visit_statement(statement),
+        }
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Let(decl) => self.declaration(
+                &decl.name,
+                &decl.type_annotation,
+                &decl.value,
+                SymbolKind::Variable,
+            ),
+            Statement::Const(decl) => self.declaration(
+                &decl.name,
+                &decl.type_annotation,
+                &decl.value,
+                SymbolKind::Constant,
+            ),
+            Statement::State(state) => {
+                self.skip("state");
+                if let Some(persist) = state.persist {
+                    self.skip(persist.name());
+                }
+                self.declaration(&state.name, &None, &state.value, SymbolKind::State);
+            }
+            Statement::FunctionDef(function) => self.function(function, SymbolKind::Function, &[]),
+            Statement::Nonlocal(names) => {
+                for name in names {
+                    let position = self.locate(name);
+                    self.nonlocal(name, position);
+                }
+            }
+            Statement::Assignment(assignment) => match &assignment.target {
+                Expression::Identifier(name) => {
+                    let position = self.locate(name);
+                    self.visit_expression(&assignment.value);
+                    self.assign(name, position);
+                }
+                target => {
+                    self.visit_expression(target);
+                    self.visit_expression(&assignment.value);
+                }
+            },
+            Statement::For(stmt) => {
+                if let Some(label) = &stmt.label {
+                    self.skip(label);
+                }
+                let position = self.locate(&stmt.target);
+                self.visit_expression(&stmt.iterator);
+                self.bind(&stmt.target, position);
+                let id = self.next_statement();
+                self.branch(id, 0, &stmt.body);
+            }
+            Statement::While(stmt) => {
+                if let Some(label) = &stmt.label {
+                    self.skip(label);
+                }
+                self.visit_expression(&stmt.condition);
+                let id = self.next_statement();
+                self.branch(id, 0, &stmt.body);
+            }
+            Statement::If(stmt) => {
+                self.visit_expression(&stmt.condition);
+                let id = self.next_statement();
+                self.branch(id, 0, &stmt.then_block);
+                if let Some(else_block) = &stmt.else_block {
+                    self.branch(id, 1, else_block);
+                }
+            }
+            Statement::Match(stmt) => {
+                self.visit_expression(&stmt.value);
+                let id = self.next_statement();
+                for (index, case) in stmt.cases.iter().enumerate() {
+                    // case のパターンの名前もそのブロックの中で定義したものとする
+                    self.branches.push((id, index));
+                    self.visit_pattern(&case.pattern);
+                    self.visit_block(&case.body);
+                    self.branches.pop();
+                }
+            }
+            Statement::Break(Some(label)) | Statement::Continue(Some(label)) => self.skip(label),
+            Statement::Render(render) => {
+                self.skip("render");
+                self.visit_block(&render.body);
+            }
+            _ => visit::walk_statement(self, statement),
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Identifier(name) => {
+                let position = self.locate(name);
+                self.reference(name, ReferenceKind::Read, position);
+            }
+            Expression::MemberAccess(member) => {
+                self.visit_expression(&member.object);
+                self.find_token(&member.member, true);
+            }
+            Expression::Lambda(lambda) => {
+                self.push_scope(ScopeKind::Function, None);
+                let branches = std::mem::take(&mut self.branches);
+                for param in &lambda.params {
+                    let position = self.locate(param);
+                    self.check_shadowing(param, false);
+                    self.define(param, SymbolKind::Parameter, position);
+                }
+                self.visit_expression(&lambda.body);
+                self.branches = branches;
+                self.pop_scope();
+            }
+            Expression::StructLiteral(literal) => {
+                let position = self.locate(&literal.name);
+                self.reference(&literal.name, ReferenceKind::Type, position);
+                for (field, value) in &literal.fields {
+                    self.skip(field);
+                    self.visit_expression(value);
+                }
+            }
+            _ => visit::walk_expression(self, expression),
+        }
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Identifier(name) => {
+                let position = self.locate(name);
+                self.bind(name, position);
+            }
+            Pattern::Rest(name) if name != "_" => {
+                let position = self.locate(name);
+                self.bind(name, position);
+            }
+            Pattern::Variant(name, inner) => {
+                self.skip(name);
+                self.visit_pattern(inner);
+            }
+            Pattern::Constructor(name, elements) => {
+                let position = self.locate(name);
+                self.reference(name, ReferenceKind::Type, position);
+                for element in elements {
+                    self.visit_pattern(element);
+                }
+            }
+            Pattern::Fields(name, entries) => {
+                let position = self.locate(name);
+                self.reference(name, ReferenceKind::Type, position);
+                for (field, element) in entries {
+                    let position = self.locate(field);
+                    match element {
+                        // User {name} はフィールド名がそのまま変数名になる
+                        Pattern::Identifier(binding)
+                            if binding == field && self.is_shorthand_field() =>
+                        {
+                            self.bind(binding, position)
+                        }
+                        _ => self.visit_pattern(element),
+                    }
+                }
+            }
+            _ => visit::walk_pattern(self, pattern),
+        }
+    }
+
+    fn visit_jsx_element(&mut self, element: &JsxElement) {
+        // 大文字で始まるタグはコンポーネント
+        let component = element.tag.starts_with(|c: char| c.is_ascii_uppercase());
+        let position = self.locate(&element.tag);
+        if component {
+            self.reference(&element.tag, ReferenceKind::Type, position);
+        }
+        for attribute in &element.attributes {
+            self.skip(&attribute.name);
+            if let Some(value) = &attribute.value {
+                self.visit_expression(value);
+            }
+        }
+        for child in &element.children {
+            match child {
+                JsxChild::Element(child) => self.visit_jsx_element(child),
+                JsxChild::Expression(expression) => self.visit_expression(expression),
+                JsxChild::Text(_) => {}
+            }
+        }
+        let position = self.close_tag(&element.tag);
+        if component && position.is_some() {
+            self.reference(&element.tag, ReferenceKind::Type, position);
+        }
+    }
+
+    fn visit_type(&mut self, ty: &Type) {
+        match ty {
+            Type::Custom(name) => {
+                let position = self.locate(name);
+                self.skip_type_args();
+                self.reference(name, ReferenceKind::Type, position);
+            }
+            Type::Int => self.skip("Int"),
+            Type::Float => self.skip("Float"),
+            Type::Bool => self.skip("Bool"),
+            Type::Str => self.skip("Str"),
+            Type::List(inner) => {
+                self.skip("List");
+                self.visit_type(inner);
+            }
+            Type::Json(inner) => {
+                self.skip("Json");
+                self.visit_type(inner);
+            }
+            _ => visit::walk_type(self, ty),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn resolve_source(source: &str) -> SymbolTable {
+        let tokens = Lexer::new(source).tokenize();
+        let program = Parser::new(tokens.clone()).parse().unwrap();
+        Resolver::new()
+            .with_tokens(source, &tokens)
+            .resolve(&program)
+    }
+
+    /// 参照ごとに (名前, 指す定義の行) を並べる (未解決は None)
+    fn targets(table: &SymbolTable) -> Vec<(String, Option<usize>)> {
+        table
+            .references
+            .iter()
+            .filter(|r| r.kind != ReferenceKind::Type)
+            .map(|r| {
+                let line = r
+                    .symbol
+                    .and_then(|id| table.symbols[id].position.as_ref())
+                    .map(|p| p.line);
+                (r.name.clone(), line)
+            })
+            .collect()
+    }
+
+    fn named(name: &str, line: Option<usize>) -> (String, Option<usize>) {
+        (name.to_string(), line)
+    }
+
+    #[test]
+    fn test_scopes_and_order() {
+        let source = "let x = 1
+def f a
+    let y = x + a
+    let x = y
+    return g(x)
+def g n
+    return n
+let z = w
+let w = f(2)
+";
+        let table = resolve_source(source);
+        assert_eq!(
+            targets(&table),
+            vec![
+                // 関数の中の x は、後の let x より前なので外側の x
+                named("x", Some(1)),
+                named("a", Some(2)),
+                named("y", Some(3)),
+                // 外側の関数 g は後で定義していても呼ぶときにはある
+                named("g", Some(6)),
+                named("x", Some(4)),
+                named("n", Some(6)),
+                // トップレベルでは定義より前の参照は解決しない
+                named("w", None),
+                named("f", Some(2)),
+            ]
+        );
+        let globals: Vec<&str> = table.globals().map(|s| s.name.as_str()).collect();
+        assert_eq!(globals, vec!["x", "f", "g", "z", "w"]);
+        assert_eq!(
+            table
+                .undefined()
+                .map(|r| r.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["w"]
+        );
+    }
+
+    #[test]
+    fn test_implicit_and_nonlocal() {
+        let source = "def counter
+    let count = 0
+    def inc
+        nonlocal count
+        count = count + 1
+        for count in [1]
+            total = count
+        return total
+    return inc
+nonlocal count
+";
+        let table = resolve_source(source);
+        let count = table
+            .symbols
+            .iter()
+            .position(|s| s.name == "count")
+            .unwrap();
+        let lines: Vec<usize> = table.occurrences(count).iter().map(|p| p.line).collect();
+        assert_eq!(lines, vec![2, 4, 5, 5, 6, 7]);
+        // let を書かない代入は変数を作るが、未定義として報告する
+        let total = table.symbols.iter().find(|s| s.name == "total").unwrap();
+        assert_eq!(total.kind, SymbolKind::Implicit);
+        assert_eq!(
+            table
+                .undefined()
+                .map(|r| r.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["total"]
+        );
+        assert_eq!(
+            table.errors,
+            vec!["nonlocal can only be used inside a function"]
+        );
+
+        let table = resolve_source("def f x\n    nonlocal x\n    nonlocal y\n    let z = 1\n");
+        assert_eq!(
+            table.errors,
+            vec!["'x' is already defined in this function and cannot be declared nonlocal"]
+        );
+        let missing: Vec<_> = table.undefined().map(|r| (&*r.name, r.kind)).collect();
+        assert_eq!(missing, vec![("y", ReferenceKind::Nonlocal)]);
+    }
+
+    #[test]
+    fn test_definition_positions() {
+        let source = "from utils import helper
+struct Point
+    x: Int
+    y: Int
+component Card
+    state title = \"hi\"
+    render
+        <div class={title}><Badge></Badge></div>
+component Badge
+    render
+        <span />
+def describe p: Point -> Str
+    let x = p.x
+    match p
+        case Point {x, y: other}
+            return str(x + other)
+    return helper(Point { x: x, y: 0 })
+";
+        let table = resolve_source(source);
+        let at = |needle: &str, nth: usize| source.match_indices(needle).nth(nth).unwrap().0;
+        let definition = |offset| {
+            table
+                .definition_at(offset)
+                .map(|s| (s.name.as_str(), s.kind))
+        };
+
+        assert_eq!(
+            definition(at("title", 1)),
+            Some(("title", SymbolKind::State))
+        );
+        assert_eq!(
+            definition(at("helper", 1)),
+            Some(("helper", SymbolKind::Import))
+        );
+        // 構造体パターンの {x} は新しい変数、p.x はメンバー名なので何も指さない
+        assert_eq!(definition(at("p.x", 0) + 2), None);
+        let x_in_match = table.symbol_at(at("{x", 0) + 1).unwrap();
+        assert_eq!(table.symbol_at(at("x + other", 0)), Some(x_in_match));
+        let x_local = table.symbol_at(at("let x", 0) + 4).unwrap();
+        assert_ne!(x_local, x_in_match);
+        // match のパターンの変数も関数のスコープに入るので、その後の x はパターンの x
+        assert_eq!(table.symbol_at(at("x: x", 0) + 3), Some(x_in_match));
+
+        // 型注釈・構造体リテラル・パターンの名前も構造体の定義を指す
+        let point = table.symbol_at(at("Point", 0)).unwrap();
+        let uses: Vec<usize> = table.occurrences(point).iter().map(|p| p.line).collect();
+        assert_eq!(uses, vec![2, 12, 15, 17]);
+        // 開きタグと閉じタグの両方がコンポーネントを指す
+        let badge = table.symbol_at(at("Badge", 0) + 1).unwrap();
+        assert_eq!(table.occurrences(badge).len(), 3);
+        assert!(table.undefined().all(|r| r.name == "str"));
+    }
+
+    #[test]
+    fn test_shadowing_warnings() {
+        let source = "let items = []
+def total items
+    let sum = 0
+    for item in items
+        let sum = sum + item
+    if sum > 10
+        let label = \"big\"
+    elif sum > 0
+        let label = \"small\"
+    else
+        let label = \"none\"
+    match label
+        case \"big\"
+            let size = 2
+        case _
+            let size = 1
+    def report sum
+        for label in [1]
+            print(label)
+        let _ = 0
+    return sum
+";
+        let table = resolve_source(source);
+        // グローバル変数を隠す引数と、if / elif / else や match の case ごとの let は警告しない
+        assert_eq!(
+            table.warnings,
+            vec![
+                "let sum inside a block of 'total' replaces the variable defined before it \
+                 (blocks do not create a new scope; use sum = ... to assign)",
+                "'sum' in 'report' shadows the one in 'total'",
+                "'label' in 'report' shadows the one in 'total'",
+            ]
+        );
+        let table = resolve_source("let x = 1\nlet x = 2\nif x > 1\n    x = 3\n");
+        assert!(table.warnings.is_empty());
+
+        let rules = ScopeRules::from_toml("[scope]\nimplicit_declarations = true\n").unwrap();
+        assert!(rules.implicit_declarations && rules.warn_shadowing);
+        assert_eq!(ScopeRules::from_toml("").unwrap(), ScopeRules::default());
+        assert_eq!(
+            ScopeRules::from_toml("[scope]\nshadowing = false\n").unwrap_err(),
+            "Unknown key scope.shadowing"
+        );
+    }
+}
@@ -0,0 +1,127 @@
+//! クライアントサイドルーター
+//!
+//! `<Router routes={{"/": Home, "/about": About}} />` はリクエストのパスに合うコンポーネントを
+//! サーバーで描画し、`<div data-router="[...]">` で包む。ブラウザでは CLIENT_SCRIPT が
+//! ルート表にあるパスへのリンクを横取りし、そのパスのページを取得して data-router の部分だけを
+//! 差し替える (pushState で履歴も残す)。直接開いたパスはサーバーのルート
+//! (`get "/*"` など) がそのまま描画するので、ブラウザとサーバーで同じ結果になる。
+
+use crate::ast::JsxElement;
+use crate::interpreter::{Interpreter, Value};
+use crate::jsx_render::{escape_html, RenderMode};
+
+/// どのパスにも合わないときに使うルート
+const FALLBACK: &str = "*";
+
+/// リンクの横取りと履歴の処理 (ページ内の island は差し替え後に hydrate し直す)
+pub const CLIENT_SCRIPT: &str = r#"// n7tya が生成したクライアントルーター
+(() => {
+  const outlet = () => document.querySelector("[data-router]");
+  const handles = (path) => {
+    const current = outlet();
+    if (!current) return false;
+    const paths = JSON.parse(current.dataset.router);
+    return paths.includes(path) || paths.includes("*");
+  };
+  const navigate = async (url, push) => {
+    try {
+      const response = await fetch(url);
+      const page = new DOMParser().parseFromString(await response.text(), "text/html");
+      const next = page.querySelector("[data-router]");
+      const current = outlet();
+      if (!response.ok || !next || !current) throw new Error(url);
+      const adopted = document.adoptNode(next);
+      current.replaceWith(adopted);
+      document.title = page.title;
+      if (push) history.pushState({}, "", url);
+      window.scrollTo(0, 0);
+      if (window.n7tyaIslands) window.n7tyaIslands.hydrate(adopted);
+    } catch {
+      // 取得や差し替えができなければ通常の遷移にする
+      location.assign(url);
+    }
+  };
+  document.addEventListener("click", (event) => {
+    const link = event.target.closest("a[href]");
+    if (!link || event.defaultPrevented || event.button !== 0) return;
+    if (event.metaKey || event.ctrlKey || event.shiftKey || event.altKey) return;
+    if (link.target || link.hasAttribute("download")) return;
+    const url = new URL(link.href, location.href);
+    if (url.origin !== location.origin || !handles(url.pathname)) return;
+    event.preventDefault();
+    navigate(url.pathname + url.search, true);
+  });
+  window.addEventListener("popstate", () => navigate(location.pathname + location.search, false));
+})();
+"#;
+
+/// `<Router routes={...} />` を描画する
+pub fn render_router(
+    element: &JsxElement,
+    interpreter: &mut Interpreter,
+) -> Result<String, String> {
+    let expected = || "<Router routes> expects a dict of paths to components".to_string();
+    let routes = match element.attributes.iter().find(|a| a.name == "routes") {
+        Some(attr) => match &attr.value {
+            Some(expr) => interpreter.eval_expression(expr)?,
+            None => return Err(expected()),
+        },
+        None => return Err(expected()),
+    };
+    let Value::Dict(dict) = routes else {
+        return Err(expected());
+    };
+    let mut table = Vec::new();
+    for (path, value) in dict.borrow().iter() {
+        let component = match value {
+            Value::BuiltinFn(handle) => handle.strip_prefix("__component_"),
+            _ => None,
+        }
+        .ok_or_else(expected)?;
+        table.push((path.clone(), component.to_string()));
+    }
+    table.sort();
+
+    let html = match match_route(&table, interpreter.request_path()) {
+        Some(component) => interpreter.render_component_named(component)?,
+        None => String::new(),
+    };
+    if interpreter.render_mode() != RenderMode::Page {
+        return Ok(html);
+    }
+    interpreter.note_router();
+    let paths: Vec<&str> = table.iter().map(|(path, _)| path.as_str()).collect();
+    Ok(format!(
+        "<div data-router=\"{}\">{}</div>",
+        escape_html(&serde_json::to_string(&paths).unwrap_or_default()),
+        html
+    ))
+}
+
+/// パスに合うコンポーネント名 (完全一致がなければ "*" のルート)
+pub fn match_route<'a>(table: &'a [(String, String)], path: &str) -> Option<&'a str> {
+    let find = |key: &str| {
+        table
+            .iter()
+            .find(|(p, _)| p == key)
+            .map(|(_, component)| component.as_str())
+    };
+    find(path).or_else(|| find(FALLBACK))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_route() {
+        let table = vec![
+            ("*".to_string(), "NotFound".to_string()),
+            ("/".to_string(), "Home".to_string()),
+            ("/about".to_string(), "About".to_string()),
+        ];
+        assert_eq!(match_route(&table, "/about"), Some("About"));
+        assert_eq!(match_route(&table, "/missing"), Some("NotFound"));
+        assert_eq!(match_route(&table[1..], "/missing"), None);
+    }
+}
@@ -0,0 +1,351 @@
+//! sitemap.xml と robots.txt の生成 (n7tya.toml の [site])
+//!
+//! `n7tya build` は GET のルートのうちパスに `*` を含まず、ファイル名 (最後の部分に `.`) でないものを
+//! dist/sitemap.xml に並べ、dist/robots.txt を書く。どちらも名前を変えずに dist/assets.json に載せるので、
+//! build 後に起動したサーバーもそのまま配信する。同じパスのルートを定義していれば、そちらを優先して書かない。
+//!
+//! ```toml
+//! [site]
+//! url = "https://example.com"
+//! disallow = ["/admin"]
+//!
+//! [site.routes."/"]
+//! priority = 1.0
+//! changefreq = "daily"
+//!
+//! [site.routes."/drafts"]
+//! exclude = true
+//! ```
+//!
+//! [site.routes] にルート表にないパス (`*` のルートが返すページなど) を書くと sitemap に加える。
+//! disallow に書いたパスの下のページは sitemap に載せない。
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+pub const SITEMAP_FILE: &str = "sitemap.xml";
+pub const ROBOTS_FILE: &str = "robots.txt";
+
+const CHANGEFREQS: [&str; 7] = [
+    "always", "hourly", "daily", "weekly", "monthly", "yearly", "never",
+];
+
+/// [site] の設定
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SiteOptions {
+    /// サイトの URL ("https://example.com")。sitemap は絶対 URL で書くので必要
+    pub url: Option<String>,
+    /// sitemap.xml を書くか (既定は true)
+    pub sitemap: bool,
+    /// robots.txt を書くか (既定は true)
+    pub robots: bool,
+    /// robots.txt で巡回させないパス
+    pub disallow: Vec<String>,
+    /// パスごとの設定 ([site.routes."/about"])
+    pub routes: BTreeMap<String, PageOptions>,
+}
+
+/// sitemap のページごとの設定
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PageOptions {
+    pub exclude: bool,
+    pub priority: Option<f64>,
+    pub changefreq: Option<String>,
+    pub lastmod: Option<String>,
+}
+
+impl SiteOptions {
+    /// n7tya.toml の内容から [site] を読む (なければ None)
+    pub fn from_toml(content: &str) -> Result<Option<Self>, String> {
+        let table: toml::Table = content
+            .parse()
+            .map_err(|e| format!("Invalid n7tya.toml: {}", e))?;
+        let site = match table.get("site") {
+            Some(toml::Value::Table(site)) => site,
+            Some(_) => return Err("[site] must be a table".to_string()),
+            None => return Ok(None),
+        };
+        let mut options = Self {
+            sitemap: true,
+            robots: true,
+            ..Self::default()
+        };
+        for (key, value) in site {
+            match (key.as_str(), value) {
+                ("url", toml::Value::String(url)) => {
+                    if !url.starts_with("http://") && !url.starts_with("https://") {
+                        return Err(format!(
+                            "[site] url must start with http:// or https://, got '{}'",
+                            url
+                        ));
+                    }
+                    options.url = Some(url.trim_end_matches('/').to_string());
+                }
+                ("sitemap", toml::Value::Boolean(b)) => options.sitemap = *b,
+                ("robots", toml::Value::Boolean(b)) => options.robots = *b,
+                ("disallow", toml::Value::Array(paths)) => {
+                    options.disallow = paths
+                        .iter()
+                        .map(|path| match path.as_str() {
+                            Some(path) if path.starts_with('/') => Ok(path.to_string()),
+                            _ => Err(format!(
+                                "[site] disallow must be a list of paths starting with '/', got {}",
+                                path
+                            )),
+                        })
+                        .collect::<Result<_, _>>()?;
+                }
+                ("routes", toml::Value::Table(routes)) => {
+                    for (path, page) in routes {
+                        options
+                            .routes
+                            .insert(path.clone(), PageOptions::from_toml(path, page)?);
+                    }
+                }
+                ("url" | "sitemap" | "robots" | "disallow" | "routes", _) => {
+                    return Err(format!("[site] {} has the wrong type: {}", key, value))
+                }
+                _ => return Err(format!("Unknown site option: {}", key)),
+            }
+        }
+        if options.sitemap && options.url.is_none() {
+            return Err(
+                "[site] url is required to generate sitemap.xml (or set sitemap = false)"
+                    .to_string(),
+            );
+        }
+        Ok(Some(options))
+    }
+
+    /// sitemap に載せるパス (ルートのパスと [site.routes] のパス、パス順)
+    pub fn pages(&self, routes: &[String]) -> Vec<String> {
+        let listed = routes.iter().filter(|path| {
+            let last = path.rsplit('/').next().unwrap_or("");
+            !path.contains('*') && !last.contains('.')
+        });
+        let mut pages: Vec<String> = listed.chain(self.routes.keys()).cloned().collect();
+        pages.sort();
+        pages.dedup();
+        pages.retain(|path| {
+            let excluded = self.routes.get(path).is_some_and(|page| page.exclude);
+            !excluded && !self.disallowed(path)
+        });
+        pages
+    }
+
+    fn disallowed(&self, path: &str) -> bool {
+        self.disallow.iter().any(|prefix| {
+            let prefix = prefix.trim_end_matches('/');
+            prefix.is_empty()
+                || path == prefix
+                || path
+                    .strip_prefix(prefix)
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+    }
+
+    pub fn sitemap_xml(&self, routes: &[String]) -> String {
+        let base = self.url.as_deref().unwrap_or("");
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+        );
+        for path in self.pages(routes) {
+            xml.push_str("  <url>\n");
+            xml.push_str(&format!(
+                "    <loc>{}{}</loc>\n",
+                escape(base),
+                escape(&path)
+            ));
+            if let Some(page) = self.routes.get(&path) {
+                if let Some(lastmod) = &page.lastmod {
+                    xml.push_str(&format!("    <lastmod>{}</lastmod>\n", escape(lastmod)));
+                }
+                if let Some(changefreq) = &page.changefreq {
+                    xml.push_str(&format!("    <changefreq>{}</changefreq>\n", changefreq));
+                }
+                if let Some(priority) = page.priority {
+                    xml.push_str(&format!("    <priority>{:.1}</priority>\n", priority));
+                }
+            }
+            xml.push_str("  </url>\n");
+        }
+        xml.push_str("</urlset>\n");
+        xml
+    }
+
+    pub fn robots_txt(&self) -> String {
+        let mut text = String::from("User-agent: *\n");
+        if self.disallow.is_empty() {
+            text.push_str("Disallow:\n");
+        }
+        for path in &self.disallow {
+            text.push_str(&format!("Disallow: {}\n", path));
+        }
+        if let (true, Some(url)) = (self.sitemap, &self.url) {
+            text.push_str(&format!("\nSitemap: {}/{}\n", url, SITEMAP_FILE));
+        }
+        text
+    }
+}
+
+impl PageOptions {
+    fn from_toml(path: &str, value: &toml::Value) -> Result<Self, String> {
+        let Some(table) = value.as_table() else {
+            return Err(format!("[site.routes.\"{}\"] must be a table", path));
+        };
+        if !path.starts_with('/') {
+            return Err(format!(
+                "[site.routes] paths must start with '/', got '{}'",
+                path
+            ));
+        }
+        let mut page = Self::default();
+        for (key, value) in table {
+            let wrong = || {
+                format!(
+                    "[site.routes.\"{}\"] {} has the wrong type: {}",
+                    path, key, value
+                )
+            };
+            match key.as_str() {
+                "exclude" => page.exclude = value.as_bool().ok_or_else(wrong)?,
+                "priority" => {
+                    let priority = value
+                        .as_float()
+                        .or(value.as_integer().map(|n| n as f64))
+                        .ok_or_else(wrong)?;
+                    if !(0.0..=1.0).contains(&priority) {
+                        return Err(format!(
+                            "[site.routes.\"{}\"] priority must be between 0.0 and 1.0",
+                            path
+                        ));
+                    }
+                    page.priority = Some(priority);
+                }
+                "changefreq" => {
+                    let changefreq = value.as_str().ok_or_else(wrong)?;
+                    if !CHANGEFREQS.contains(&changefreq) {
+                        return Err(format!(
+                            "[site.routes.\"{}\"] changefreq must be one of {}",
+                            path,
+                            CHANGEFREQS.join(", ")
+                        ));
+                    }
+                    page.changefreq = Some(changefreq.to_string());
+                }
+                // lastmod = 2026-10-01 (TOML の日付) と lastmod = "2026-10-01" のどちらも書ける
+                "lastmod" => {
+                    page.lastmod = Some(match value {
+                        toml::Value::String(s) => s.clone(),
+                        toml::Value::Datetime(d) => d.to_string(),
+                        _ => return Err(wrong()),
+                    })
+                }
+                _ => {
+                    return Err(format!(
+                        "Unknown option for [site.routes.\"{}\"]: {}",
+                        path, key
+                    ))
+                }
+            }
+        }
+        Ok(page)
+    }
+}
+
+/// sitemap.xml と robots.txt を dist に書き、dist/assets.json に載せる (書いたファイルの名前を返す)
+///
+/// routes は GET のルートのパス。同じパスのルートがあるファイルは書かない。
+pub fn build(options: &SiteOptions, routes: &[String], dist: &Path) -> Result<Vec<String>, String> {
+    let mut files = Vec::new();
+    if options.sitemap {
+        files.push((SITEMAP_FILE, options.sitemap_xml(routes)));
+    }
+    if options.robots {
+        files.push((ROBOTS_FILE, options.robots_txt()));
+    }
+    files.retain(|(name, _)| {
+        !routes
+            .iter()
+            .any(|path| path.trim_start_matches('/') == *name)
+    });
+
+    fs::create_dir_all(dist).map_err(|e| format!("Failed to create {}: {}", dist.display(), e))?;
+    let mut written = Vec::new();
+    for (name, content) in files {
+        let out = dist.join(name);
+        fs::write(&out, content)
+            .map_err(|e| format!("Failed to write {}: {}", out.display(), e))?;
+        written.push(name.to_string());
+    }
+    let paths: Vec<String> = written.iter().map(|name| format!("/{}", name)).collect();
+    crate::assets::register(dist, &paths)?;
+    Ok(written)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sitemap_and_robots() {
+        let options = SiteOptions::from_toml(
+            "[site]\nurl = \"https://example.com/\"\ndisallow = [\"/admin\"]\n[site.routes.\"/\"]\npriority = 1\nchangefreq = \"daily\"\nlastmod = 2026-10-01\n[site.routes.\"/drafts\"]\nexclude = true\n[site.routes.\"/posts/hello\"]\n",
+        )
+        .unwrap()
+        .unwrap();
+        let routes: Vec<String> = [
+            "/",
+            "/about",
+            "/drafts",
+            "/admin",
+            "/admin/users",
+            "/feed.xml",
+            "/posts/*",
+        ]
+        .iter()
+        .map(|path| path.to_string())
+        .collect();
+        assert_eq!(options.pages(&routes), vec!["/", "/about", "/posts/hello"]);
+        let xml = options.sitemap_xml(&routes);
+        assert!(xml.contains("<loc>https://example.com/</loc>\n    <lastmod>2026-10-01</lastmod>\n    <changefreq>daily</changefreq>\n    <priority>1.0</priority>"));
+        assert!(xml.contains("<loc>https://example.com/about</loc>\n  </url>"));
+        assert_eq!(
+            options.robots_txt(),
+            "User-agent: *\nDisallow: /admin\n\nSitemap: https://example.com/sitemap.xml\n"
+        );
+
+        assert!(SiteOptions::from_toml("[site]\n")
+            .unwrap_err()
+            .contains("url is required"));
+        assert!(SiteOptions::from_toml("[site]\nsitemap = false\n")
+            .unwrap()
+            .is_some());
+        assert!(SiteOptions::from_toml(
+            "[site]\nurl = \"https://a\"\n[site.routes.\"/\"]\nchangefreq = \"sometimes\"\n"
+        )
+        .is_err());
+        assert_eq!(SiteOptions::from_toml("[package]\n").unwrap(), None);
+
+        // 同じパスのルートがあるファイルは書かず、書いたものは build 後のサーバーが配信する
+        let dir = std::env::temp_dir().join(format!("n7tya-sitemap-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let dist = dir.join(crate::assets::DIST_DIR);
+        let written = build(&options, &["/robots.txt".to_string()], &dist).unwrap();
+        assert_eq!(written, vec!["sitemap.xml"]);
+        assert!(!dist.join(ROBOTS_FILE).exists());
+        let assets = crate::assets::Assets::load(&dir).unwrap().unwrap();
+        let file = assets.file("/sitemap.xml").unwrap();
+        assert_eq!(file.content_type, "application/xml");
+        assert!(!file.immutable);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,168 @@
+//! ソースマップ (Source Map v3) の生成
+//!
+//! 生成した JS の行を、元になった .n7t ファイルの行に対応させる。
+//! 対応は行単位 (各行の先頭を元の行の先頭に対応させる) なので、
+//! ブラウザのエラーやデバッガは生成した JS ではなく .n7t の行を指す。
+//! 元のソースは sourcesContent に埋め込むので、.n7t ファイルを配信しなくても開発者ツールで見られる。
+
+/// 生成したファイルの行から元のソースの行への対応
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    sources: Vec<String>,
+    contents: Vec<Option<String>>,
+    lines: Vec<Option<(usize, usize)>>, // 生成した行ごとの (sources の添字, 1 始まりの行)
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// ソースを加えて添字を返す (同じパスなら同じ添字)
+    pub fn add_source(&mut self, path: &str) -> usize {
+        if let Some(index) = self.sources.iter().position(|s| s == path) {
+            return index;
+        }
+        self.sources.push(path.to_string());
+        self.contents.push(None);
+        self.sources.len() - 1
+    }
+
+    pub fn sources(&self) -> &[String] {
+        &self.sources
+    }
+
+    /// ソースの内容を sourcesContent に埋め込む
+    pub fn set_content(&mut self, source: usize, content: String) {
+        if let Some(slot) = self.contents.get_mut(source) {
+            *slot = Some(content);
+        }
+    }
+
+    /// 生成した行 (0 始まり) を source の line 行目 (1 始まり) に対応させる。line が 0 なら対応させない
+    pub fn map_line(&mut self, generated: usize, source: usize, line: usize) {
+        if line == 0 {
+            return;
+        }
+        if self.lines.len() <= generated {
+            self.lines.resize(generated + 1, None);
+        }
+        self.lines[generated] = Some((source, line));
+    }
+
+    /// 元の位置 (sources の添字, 行)。対応のない行は None
+    pub fn origin(&self, generated: usize) -> Option<(usize, usize)> {
+        self.lines.get(generated).copied().flatten()
+    }
+
+    /// 生成したファイルの末尾に付けるコメント
+    pub fn comment(url: &str) -> String {
+        format!("//# sourceMappingURL={}\n", url)
+    }
+
+    /// file は生成したファイルの名前、source_root は sources の前に付けるパス (どちらもマップから見た相対パス)
+    pub fn to_json(&self, file: &str, source_root: &str) -> String {
+        serde_json::json!({
+            "version": 3,
+            "file": file,
+            "sourceRoot": source_root,
+            "sources": self.sources,
+            "sourcesContent": self.contents,
+            "names": [],
+            "mappings": self.mappings(),
+        })
+        .to_string()
+    }
+
+    /// 行を ; で区切り、対応のある行に1つずつセグメントを書く。
+    /// セグメントは (生成した列, ソースの添字, 元の行, 元の列) で、列以外は前のセグメントとの差
+    fn mappings(&self) -> String {
+        let mut out = String::new();
+        let (mut prev_source, mut prev_line) = (0, 0);
+        for (i, origin) in self.lines.iter().enumerate() {
+            if i > 0 {
+                out.push(';');
+            }
+            let Some((source, line)) = *origin else {
+                continue;
+            };
+            // 元の行は 0 始まりで書く
+            let line = line as i64 - 1;
+            vlq(0, &mut out);
+            vlq(source as i64 - prev_source, &mut out);
+            vlq(line - prev_line, &mut out);
+            vlq(0, &mut out);
+            prev_source = source as i64;
+            prev_line = line;
+        }
+        out
+    }
+}
+
+/// Base64 VLQ (最下位ビットが符号、5ビットずつ下から書き、続きがあれば 6ビット目を立てる)
+fn vlq(value: i64, out: &mut String) {
+    const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut rest = if value < 0 {
+        (value.unsigned_abs() << 1) | 1
+    } else {
+        (value as u64) << 1
+    };
+    loop {
+        let mut digit = rest & 31;
+        rest >>= 5;
+        if rest > 0 {
+            digit |= 32;
+        }
+        out.push(BASE64[digit as usize] as char);
+        if rest == 0 {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vlq() {
+        let encode = |value| {
+            let mut out = String::new();
+            vlq(value, &mut out);
+            out
+        };
+        assert_eq!(encode(0), "A");
+        assert_eq!(encode(1), "C");
+        assert_eq!(encode(-1), "D");
+        assert_eq!(encode(15), "e");
+        assert_eq!(encode(16), "gB");
+        assert_eq!(encode(-17), "jB");
+        assert_eq!(encode(1000), "w+B");
+    }
+
+    #[test]
+    fn test_mappings() {
+        let mut map = SourceMap::new();
+        let app = map.add_source("src/app.n7t");
+        let api = map.add_source("src/api.n7t");
+        assert_eq!(map.add_source("src/app.n7t"), app);
+        map.set_content(app, "island component A\n".to_string());
+        map.map_line(1, app, 1);
+        map.map_line(2, app, 3);
+        map.map_line(4, api, 2);
+        map.map_line(5, api, 0);
+
+        let json: serde_json::Value =
+            serde_json::from_str(&map.to_json("islands.js", "../")).unwrap();
+        assert_eq!(json["version"], 3);
+        assert_eq!(json["file"], "islands.js");
+        assert_eq!(json["sourceRoot"], "../");
+        assert_eq!(json["sources"][1], "src/api.n7t");
+        assert_eq!(json["sourcesContent"][0], "island component A\n");
+        assert!(json["sourcesContent"][1].is_null());
+        // 1行目 → app 0行目、2行目 → app 2行目、4行目 → api 1行目
+        assert_eq!(json["mappings"], ";AAAA;AAEA;;ACDA");
+        assert_eq!(map.origin(2), Some((app, 3)));
+        assert_eq!(map.origin(3), None);
+    }
+}
@@ -0,0 +1,232 @@
+//! sqlite モジュール (SQLite データベース)
+//!
+//! `sqlite.open(path)` は接続の値 (type() は SqliteConnection) を返す。`sqlite.query(db, sql)` のように
+//! 最初の引数に渡すか、`db.query(sql)` のようにメソッドとして呼ぶ。path が ":memory:" ならメモリ上に作る。
+//! SQL の `?` には残りの引数を順に入れる。リストを1つ渡せばその要素を、辞書を渡せば `:name` に同じ名前のキーを入れる。
+//! query は行ごとに列名をキーにした辞書のリストを返す。接続は close するか、値が使われなくなったときに閉じる。
+
+use crate::interpreter::Value;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use rusqlite::types::{Value as SqlValue, ValueRef};
+use rusqlite::ToSql;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// 開いた接続 (close したあとは None)
+#[derive(Debug)]
+pub struct Connection {
+    path: String,
+    inner: RefCell<Option<rusqlite::Connection>>,
+}
+
+impl Connection {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.inner.borrow().is_some()
+    }
+}
+
+/// sqlite.open(path) / sqlite.execute(db, sql, params...) / sqlite.query(db, sql, params...) / sqlite.close(db)
+pub fn call(name: &str, args: Vec<Value>) -> Result<Value, String> {
+    match (name, args.as_slice()) {
+        ("open", [Value::Str(path)]) => open(path, true),
+        ("open", _) => Err("sqlite.open() expects a path string".to_string()),
+        ("execute", [Value::Sqlite(db), Value::Str(sql), params @ ..]) => {
+            let bindings = Bindings::new(params)?;
+            let affected = with_connection(db, |conn| match &bindings {
+                Bindings::Positional(values) => {
+                    conn.execute(sql, rusqlite::params_from_iter(values))
+                }
+                Bindings::Named(values) => conn.execute(sql, named(values).as_slice()),
+            })
+            .map_err(|e| format!("SQLite execute error: {}", e))?;
+            Ok(Value::Int(affected as i64))
+        }
+        ("query", [Value::Sqlite(db), Value::Str(sql), params @ ..]) => {
+            let bindings = Bindings::new(params)?;
+            let rows = with_connection(db, |conn| query(conn, sql, &bindings))
+                .map_err(|e| format!("SQLite query error: {}", e))?;
+            Ok(Value::List(Rc::new(RefCell::new(rows))))
+        }
+        ("close", [Value::Sqlite(db)]) => {
+            db.inner.borrow_mut().take();
+            Ok(Value::None)
+        }
+        ("execute" | "query", _) => Err(format!(
+            "sqlite.{}() expects (db: SqliteConnection, sql: Str, params...)",
+            name
+        )),
+        ("close", _) => Err("sqlite.close() expects a connection".to_string()),
+        _ => Err(format!("Module 'sqlite' has no member '{}'", name)),
+    }
+}
+
+/// sqlite.open(path) (attach が false なら ATTACH DATABASE でほかのファイルを開けない)
+pub fn open(path: &str, attach: bool) -> Result<Value, String> {
+    let inner =
+        rusqlite::Connection::open(path).map_err(|e| format!("SQLite open error: {}", e))?;
+    if !attach {
+        inner.set_limit(rusqlite::limits::Limit::SQLITE_LIMIT_ATTACHED, 0);
+    }
+    Ok(Value::Sqlite(Rc::new(Connection {
+        path: path.to_string(),
+        inner: RefCell::new(Some(inner)),
+    })))
+}
+
+/// db.execute(sql, ...) / db.query(sql, ...) / db.close() で呼べる関数
+pub fn is_method(method: &str) -> bool {
+    matches!(method, "execute" | "query" | "close")
+}
+
+/// 閉じた接続はエラーにする
+fn with_connection<T>(
+    db: &Connection,
+    f: impl FnOnce(&rusqlite::Connection) -> rusqlite::Result<T>,
+) -> Result<T, String> {
+    match &*db.inner.borrow() {
+        Some(conn) => f(conn).map_err(|e| e.to_string()),
+        None => Err(format!("the connection to '{}' is closed", db.path)),
+    }
+}
+
+/// SQL のパラメータに入れる値
+enum Bindings {
+    Positional(Vec<SqlValue>),
+    Named(Vec<(String, SqlValue)>),
+}
+
+impl Bindings {
+    fn new(params: &[Value]) -> Result<Self, String> {
+        match params {
+            [Value::List(items)] => Ok(Bindings::Positional(
+                items
+                    .borrow()
+                    .iter()
+                    .map(sql_value)
+                    .collect::<Result<_, _>>()?,
+            )),
+            [Value::Dict(entries)] => {
+                let mut values = Vec::new();
+                for (key, value) in entries.borrow().iter() {
+                    // `:name` `@name` `$name` のどれでも書けるように、印がなければ `:` を付ける
+                    let key = if key.starts_with([':', '@', '$']) {
+                        key.clone()
+                    } else {
+                        format!(":{}", key)
+                    };
+                    values.push((key, sql_value(value)?));
+                }
+                Ok(Bindings::Named(values))
+            }
+            _ => Ok(Bindings::Positional(
+                params.iter().map(sql_value).collect::<Result<_, _>>()?,
+            )),
+        }
+    }
+}
+
+fn named(values: &[(String, SqlValue)]) -> Vec<(&str, &dyn ToSql)> {
+    values
+        .iter()
+        .map(|(key, value)| (key.as_str(), value as &dyn ToSql))
+        .collect()
+}
+
+/// Decimal は精度を落とさないように文字列で入れる
+fn sql_value(value: &Value) -> Result<SqlValue, String> {
+    Ok(match value {
+        Value::None => SqlValue::Null,
+        Value::Int(n) => SqlValue::Integer(*n),
+        Value::Float(f) => SqlValue::Real(*f),
+        Value::Bool(b) => SqlValue::Integer(*b as i64),
+        Value::Str(s) => SqlValue::Text(s.to_string()),
+        Value::Decimal(d) => SqlValue::Text(d.to_string()),
+        other => {
+            return Err(format!(
+                "Cannot bind a {} to a SQL parameter",
+                other.type_name()
+            ))
+        }
+    })
+}
+
+/// 行を列名をキーにした辞書にする (BLOB は Base64 の文字列)
+fn query(
+    conn: &rusqlite::Connection,
+    sql: &str,
+    bindings: &Bindings,
+) -> rusqlite::Result<Vec<Value>> {
+    let mut stmt = conn.prepare(sql)?;
+    let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+    let mut rows = match bindings {
+        Bindings::Positional(values) => stmt.query(rusqlite::params_from_iter(values))?,
+        Bindings::Named(values) => stmt.query(named(values).as_slice())?,
+    };
+    let mut result = Vec::new();
+    while let Some(row) = rows.next()? {
+        let mut dict = HashMap::new();
+        for (i, column) in columns.iter().enumerate() {
+            let value = match row.get_ref(i)? {
+                ValueRef::Null => Value::None,
+                ValueRef::Integer(n) => Value::Int(n),
+                ValueRef::Real(f) => Value::Float(f),
+                ValueRef::Text(t) => Value::Str(String::from_utf8_lossy(t).into()),
+                ValueRef::Blob(b) => Value::Str(BASE64.encode(b).into()),
+            };
+            dict.insert(column.clone(), value);
+        }
+        result.push(Value::Dict(Rc::new(RefCell::new(dict))));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::run;
+
+    #[test]
+    fn test_execute_and_query() {
+        let source = "let db = sqlite.open(\":memory:\")
+sqlite.execute(db, \"CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, score REAL, note TEXT)\")
+sqlite.execute(db, \"INSERT INTO users (name, score, note) VALUES (?, ?, ?)\", \"ann\", 1.5, none)
+db.execute(\"INSERT INTO users (name, score) VALUES (?, ?)\", [\"bob\", 2])
+db.execute(\"INSERT INTO users (name, score) VALUES (:name, :score)\", {\"name\": \"cy'd\", \"score\": decimal(\"3.25\")})
+let rows = db.query(\"SELECT * FROM users WHERE score > ? ORDER BY id\", 1)
+[type(db), len(rows), rows[0][\"name\"], rows[0][\"note\"], rows[1][\"score\"], rows[2][\"name\"], rows[2][\"score\"], db.execute(\"DELETE FROM users\")]
+";
+        assert_eq!(
+            run(source).unwrap(),
+            "[SqliteConnection, 3, ann, none, 2, cy'd, 3.25, 3]"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let source = "let db = sqlite.open(\":memory:\")\ndb.query(\"SELECT * FROM missing\")\n";
+        assert_eq!(
+            run(source).unwrap_err(),
+            "SQLite query error: no such table: missing"
+        );
+
+        let source =
+            "let db = sqlite.open(\":memory:\")\ndb.close()\nsqlite.query(db, \"SELECT 1\")\n";
+        assert_eq!(
+            run(source).unwrap_err(),
+            "SQLite query error: the connection to ':memory:' is closed"
+        );
+        let source = "let db = sqlite.open(\":memory:\")\ndb.query(\"SELECT ?\", [[1]])\n";
+        assert_eq!(
+            run(source).unwrap_err(),
+            "Cannot bind a List to a SQL parameter"
+        );
+        assert_eq!(
+            run("sqlite.query(1, \"SELECT 1\")\n").unwrap_err(),
+            "sqlite.query() expects (db: SqliteConnection, sql: Str, params...)"
+        );
+    }
+}
@@ -0,0 +1,341 @@
+//! 静的サイトの生成 (`n7tya build --static`)
+//!
+//! GET のルートのうちパスに `*` を含まないものを呼び、結果を dist/ に書き出す
+//! ("/" → dist/index.html、"/about" → dist/about/index.html、"/feed.xml" → dist/feed.xml)。
+//! ページごとに、ルートの処理中に読んだファイル (fs.read_file、fs.read_dir、sqlite.open など) と
+//! その内容のハッシュを dist/pages.json に記録する。
+//!
+//! `--incremental` では、ソース (src/ と content/ と n7tya.toml、island と public のビルド結果) が前回と同じなら、
+//! 読んだファイルが変わっていないページを書き直さない。ソースが変わればすべてのページを書き直す。
+//! 前回書いたファイルのうち今回どのルートからも書かなかったもの (ルートがなくなったページや
+//! 書き出せなかったページ) は、`--incremental` でなくても消す。
+
+use crate::assets::fingerprint;
+use crate::interpreter::Interpreter;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// ページの依存を記録するファイル (dist の中)
+pub const PAGES_MANIFEST: &str = "pages.json";
+
+/// 前回の生成の記録
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Manifest {
+    sources: String,
+    /// ページのパス -> (dist の中のファイル, 読んだパスとその内容のハッシュ)
+    pages: BTreeMap<String, (String, BTreeMap<String, String>)>,
+}
+
+/// 生成の結果
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SiteReport {
+    /// 書き出したページのパス
+    pub rendered: Vec<String>,
+    /// 変わっていないので書き直さなかったページの数
+    pub unchanged: usize,
+    /// 今回書かなかったので消したページのパス (ルートがなくなったか、書き出せなかった)
+    pub removed: Vec<String>,
+    /// 書き出せなかったページのエラー
+    pub errors: Vec<String>,
+}
+
+/// 定義したサーバーの静的なページを project_dir/dist に書き出す (プログラムは実行済みのもの)
+pub fn build(
+    interpreter: &mut Interpreter,
+    project_dir: &Path,
+    incremental: bool,
+) -> Result<SiteReport, String> {
+    let dist = project_dir.join(crate::assets::DIST_DIR);
+    let manifest_path = dist.join(PAGES_MANIFEST);
+    let previous = fs::read_to_string(&manifest_path)
+        .ok()
+        .and_then(|text| Manifest::parse(&text))
+        .unwrap_or_default();
+    let sources = sources_fingerprint(project_dir)?;
+    let reusable = incremental && previous.sources == sources;
+
+    let mut report = SiteReport::default();
+    let mut manifest = Manifest {
+        sources,
+        pages: BTreeMap::new(),
+    };
+    let mut owners: BTreeMap<String, String> = BTreeMap::new();
+    for server in interpreter.server_names() {
+        let routes = interpreter.routes(&server).unwrap_or_default();
+        for route in routes {
+            if route.method != "GET" || route.path.contains('*') {
+                continue;
+            }
+            if let Some(other) = owners.insert(route.path.clone(), server.clone()) {
+                report.errors.push(format!(
+                    "{}: defined by both '{}' and '{}'",
+                    route.path, other, server
+                ));
+                continue;
+            }
+            let file = match output_file(&route.path) {
+                Ok(file) => file,
+                Err(e) => {
+                    report.errors.push(format!("{}: {}", route.path, e));
+                    continue;
+                }
+            };
+            if let Some(page @ (previous_file, reads)) = previous.pages.get(&route.path) {
+                let fresh = reusable
+                    && *previous_file == file
+                    && dist.join(&file).exists()
+                    && reads
+                        .iter()
+                        .all(|(path, hash)| read_fingerprint(project_dir, path) == *hash);
+                if fresh {
+                    manifest.pages.insert(route.path.clone(), page.clone());
+                    report.unchanged += 1;
+                    continue;
+                }
+            }
+
+            interpreter.record_reads();
+            let rendered = interpreter.render_route(&server, &route.path);
+            let reads = interpreter.take_reads();
+            match rendered {
+                Ok((_, body)) => {
+                    let out = dist.join(&file);
+                    if let Some(dir) = out.parent() {
+                        fs::create_dir_all(dir)
+                            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+                    }
+                    fs::write(&out, body)
+                        .map_err(|e| format!("Failed to write {}: {}", out.display(), e))?;
+                    let reads = reads
+                        .into_iter()
+                        .map(|path| {
+                            let hash = read_fingerprint(project_dir, &path);
+                            (path, hash)
+                        })
+                        .collect();
+                    manifest.pages.insert(route.path.clone(), (file, reads));
+                    report.rendered.push(route.path);
+                }
+                Err(e) => report.errors.push(format!("{}: {}", route.path, e)),
+            }
+        }
+    }
+
+    // 今回どのページも書かなかったファイルを消す (ほかのページが同じファイルに書いたものは残す)
+    for (path, (file, _)) in &previous.pages {
+        if !manifest.pages.values().any(|(f, _)| f == file) {
+            crate::assets::remove_output(&dist, file);
+            report.removed.push(path.clone());
+        }
+    }
+
+    fs::create_dir_all(&dist).map_err(|e| format!("Failed to create {}: {}", dist.display(), e))?;
+    fs::write(&manifest_path, manifest.to_json() + "\n")
+        .map_err(|e| format!("Failed to write {}: {}", manifest_path.display(), e))?;
+    Ok(report)
+}
+
+impl Manifest {
+    fn parse(text: &str) -> Option<Self> {
+        let json: serde_json::Value = serde_json::from_str(text).ok()?;
+        let pages = json["pages"]
+            .as_object()?
+            .iter()
+            .map(|(path, page)| {
+                let reads = page["reads"]
+                    .as_object()?
+                    .iter()
+                    .map(|(read, hash)| Some((read.clone(), hash.as_str()?.to_string())))
+                    .collect::<Option<_>>()?;
+                Some((path.clone(), (page["file"].as_str()?.to_string(), reads)))
+            })
+            .collect::<Option<_>>()?;
+        Some(Self {
+            sources: json["sources"].as_str()?.to_string(),
+            pages,
+        })
+    }
+
+    fn to_json(&self) -> String {
+        let pages: serde_json::Map<String, serde_json::Value> = self
+            .pages
+            .iter()
+            .map(|(path, (file, reads))| (path.clone(), json!({ "file": file, "reads": reads })))
+            .collect();
+        serde_json::to_string_pretty(&json!({ "sources": self.sources, "pages": pages }))
+            .unwrap_or_default()
+    }
+}
+
+/// ページのパスから dist の中のファイル名を決める
+fn output_file(path: &str) -> Result<String, String> {
+    let path = path.trim_matches('/');
+    if path.split('/').any(|part| part == ".." || part == ".") {
+        return Err("the path cannot contain '.' or '..'".to_string());
+    }
+    let last = path.rsplit('/').next().unwrap_or("");
+    Ok(if path.is_empty() {
+        "index.html".to_string()
+    } else if last.contains('.') {
+        path.to_string()
+    } else {
+        format!("{}/index.html", path)
+    })
+}
+
+/// ページの出力を変えうるソースのハッシュ
+fn sources_fingerprint(project_dir: &Path) -> Result<String, String> {
+    let mut files = Vec::new();
+    collect_files(&project_dir.join("src"), &mut files)?;
+    // content/ のコレクション (下のディレクトリ) はどのページからも読めるので、変われば全ページを書き直す
+    if let Ok(entries) = fs::read_dir(project_dir.join(crate::content::CONTENT_DIR)) {
+        for entry in entries.flatten() {
+            if entry.path().is_dir() {
+                collect_files(&entry.path(), &mut files)?;
+            }
+        }
+    }
+    files.sort();
+    let dist = project_dir.join(crate::assets::DIST_DIR);
+    files.extend([
+        project_dir.join("n7tya.toml"),
+        dist.join(crate::assets::MANIFEST),
+        dist.join("islands.js"),
+    ]);
+    let mut content = Vec::new();
+    for file in files {
+        content.extend(file.to_string_lossy().as_bytes());
+        content.push(0);
+        content.extend(fs::read(&file).unwrap_or_default());
+        content.push(0);
+    }
+    Ok(fingerprint(&content))
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(());
+    };
+    for entry in entries {
+        let path = entry
+            .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?
+            .path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// ページが読んだパスの今の状態 (ファイルは内容、ディレクトリは中の名前の一覧のハッシュ)
+fn read_fingerprint(project_dir: &Path, path: &str) -> String {
+    let full = project_dir.join(path);
+    if let Ok(content) = fs::read(&full) {
+        return fingerprint(&content);
+    }
+    match fs::read_dir(&full) {
+        Ok(entries) => {
+            let mut names: Vec<String> = entries
+                .flatten()
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            format!("dir:{}", fingerprint(names.join("\n").as_bytes()))
+        }
+        Err(_) => "missing".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn render(dir: &Path, incremental: bool) -> SiteReport {
+        // fs.read_file の相対パスはカレントディレクトリから読むので、テストでは絶対パスで読む
+        let home = dir
+            .join("content/home.txt")
+            .to_string_lossy()
+            .replace('\\', "/");
+        let source = format!("server Site\n\tget \"/\"\n\t\treturn fs.read_file(\"{}\")\n\tget \"/about\"\n\t\treturn \"about\"\n\tget \"/feed.xml\"\n\t\treturn \"<feed/>\"\n\tget \"/posts/*\"\n\t\treturn \"post\"\n", home);
+        let program = Parser::new(Lexer::new(&source).tokenize()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&program).unwrap();
+        build(&mut interpreter, dir, incremental).unwrap()
+    }
+
+    #[test]
+    fn test_incremental_static_build() {
+        let dir = std::env::temp_dir().join(format!("n7tya-ssg-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("content")).unwrap();
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("content/home.txt"), "hello").unwrap();
+
+        let first = render(&dir, false);
+        assert_eq!(first.rendered, vec!["/", "/about", "/feed.xml"]);
+        assert_eq!(
+            fs::read_to_string(dir.join("dist/index.html")).unwrap(),
+            "hello"
+        );
+        assert!(dir.join("dist/about/index.html").exists());
+        assert!(dir.join("dist/feed.xml").exists());
+
+        let unchanged = render(&dir, true);
+        assert_eq!((unchanged.rendered.len(), unchanged.unchanged), (0, 3));
+
+        fs::write(dir.join("content/home.txt"), "changed").unwrap();
+        let changed = render(&dir, true);
+        assert_eq!(
+            (changed.rendered, changed.unchanged),
+            (vec!["/".to_string()], 2)
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("dist/index.html")).unwrap(),
+            "changed"
+        );
+
+        // ソースが変わればすべて書き直す
+        fs::write(dir.join("src/main.n7t"), "# changed\n").unwrap();
+        assert_eq!(render(&dir, true).rendered.len(), 3);
+
+        assert!(output_file("/../x").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_removes_pages_no_route_writes() {
+        let dir = std::env::temp_dir().join(format!("n7tya-ssg-removed-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let build_site = |source: &str| {
+            let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+            let mut interpreter = Interpreter::new();
+            interpreter.run(&program).unwrap();
+            build(&mut interpreter, &dir, false).unwrap()
+        };
+
+        let source = "server Site\n\tget \"/\"\n\t\treturn \"home\"\n\tget \"/docs/intro\"\n\t\treturn \"intro\"\n\tget \"/about\"\n\t\treturn \"about\"\n";
+        assert_eq!(build_site(source).rendered.len(), 3);
+        assert!(dir.join("dist/docs/intro/index.html").exists());
+
+        // /docs/intro をなくし、/about は書き出せなくする
+        let source =
+            "server Site\n\tget \"/\"\n\t\treturn \"home\"\n\tget \"/about\"\n\t\treturn 1 / 0\n";
+        let report = build_site(source);
+        assert_eq!(report.removed, vec!["/about", "/docs/intro"]);
+        assert_eq!(
+            report.errors,
+            vec!["/about: GET /about returned 500 Internal Server Error: Error: Division by zero"]
+        );
+        assert!(!dir.join("dist/about").exists());
+        assert!(!dir.join("dist/docs").exists());
+        assert!(dir.join("dist/index.html").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
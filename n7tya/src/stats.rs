@@ -0,0 +1,183 @@
+//! stats モジュール (数値のリストの簡単な統計)
+//!
+//! mean / median / stdev / percentile は Float を返す。mode は最も多い値をそのまま返し、
+//! 同じ数なら先に出てきた値にする。histogram は最小値から最大値までを等しい幅に分けて数える。
+
+use crate::interpreter::{values_equal, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// stats.* の関数 (name は "mean" など、"stats." を除いた名前)
+pub fn call(name: &str, args: Vec<Value>) -> Result<Value, String> {
+    match (name, args.as_slice()) {
+        ("mean", [list]) => {
+            let values = numbers(name, list)?;
+            Ok(Value::Float(mean(&values)))
+        }
+        ("median", [list]) => Ok(Value::Float(percentile(&numbers(name, list)?, 50.0))),
+        ("mode", [list]) => mode(list),
+        ("stdev", [list]) => {
+            let values = numbers(name, list)?;
+            if values.len() < 2 {
+                return Err("stats.stdev() requires at least 2 values".to_string());
+            }
+            let mean = mean(&values);
+            let squares: f64 = values.iter().map(|v| (v - mean).powi(2)).sum();
+            Ok(Value::Float((squares / (values.len() - 1) as f64).sqrt()))
+        }
+        ("percentile", [list, p]) => {
+            let values = numbers(name, list)?;
+            let p = match p {
+                Value::Int(n) => *n as f64,
+                Value::Float(f) => *f,
+                _ => f64::NAN,
+            };
+            if !(0.0..=100.0).contains(&p) {
+                return Err("stats.percentile() expects a percentile between 0 and 100".to_string());
+            }
+            Ok(Value::Float(percentile(&values, p)))
+        }
+        ("histogram", [list, Value::Int(bins)]) if *bins > 0 => {
+            histogram(&numbers(name, list)?, *bins as usize)
+        }
+        ("histogram", _) => {
+            Err("stats.histogram() expects a list of numbers and a positive bin count".to_string())
+        }
+        ("percentile", _) => {
+            Err("stats.percentile() expects a list of numbers and a percentile".to_string())
+        }
+        _ => Err(format!("stats.{}() takes exactly 1 argument", name)),
+    }
+}
+
+/// 空でない数値のリストを f64 にする
+fn numbers(name: &str, list: &Value) -> Result<Vec<f64>, String> {
+    let error = || format!("stats.{}() expects a non-empty list of numbers", name);
+    let Value::List(items) = list else {
+        return Err(error());
+    };
+    let values = items
+        .borrow()
+        .iter()
+        .map(|item| match item {
+            Value::Int(n) => Ok(*n as f64),
+            Value::Float(f) if !f.is_nan() => Ok(*f),
+            _ => Err(error()),
+        })
+        .collect::<Result<Vec<f64>, String>>()?;
+    if values.is_empty() {
+        return Err(error());
+    }
+    Ok(values)
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// p パーセンタイル (順位の間は線形に補間する)
+fn percentile(values: &[f64], p: f64) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let rank = p / 100.0 * (sorted.len() - 1) as f64;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+/// 最も多く出てくる値 (数値でなくてもよい)
+fn mode(list: &Value) -> Result<Value, String> {
+    let Value::List(items) = list else {
+        return Err("stats.mode() expects a non-empty list".to_string());
+    };
+    let mut counts: Vec<(Value, usize)> = Vec::new();
+    for item in items.borrow().iter() {
+        match counts
+            .iter_mut()
+            .find(|(value, _)| values_equal(value, item))
+        {
+            Some((_, count)) => *count += 1,
+            None => counts.push((item.clone(), 1)),
+        }
+    }
+    // max_by_key は同じ数なら後の値を返すので、逆順にして先に出てきた値を選ぶ
+    counts
+        .into_iter()
+        .rev()
+        .max_by_key(|(_, count)| *count)
+        .map(|(value, _)| value)
+        .ok_or_else(|| "stats.mode() expects a non-empty list".to_string())
+}
+
+/// bins 個の区間 {"start", "end", "count"} のリスト (最後の区間は最大値を含む)
+fn histogram(values: &[f64], bins: usize) -> Result<Value, String> {
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    // すべて同じ値なら、その値を中心に幅 1 の範囲を分ける
+    let (start, end) = if min == max {
+        (min - 0.5, max + 0.5)
+    } else {
+        (min, max)
+    };
+    let width = (end - start) / bins as f64;
+    let mut counts = vec![0i64; bins];
+    for value in values {
+        let index = ((value - start) / width) as usize;
+        counts[index.min(bins - 1)] += 1;
+    }
+    let rows = counts
+        .into_iter()
+        .enumerate()
+        .map(|(i, count)| {
+            let row = HashMap::from([
+                ("start".to_string(), Value::Float(start + width * i as f64)),
+                (
+                    "end".to_string(),
+                    Value::Float(start + width * (i + 1) as f64),
+                ),
+                ("count".to_string(), Value::Int(count)),
+            ]);
+            Value::Dict(Rc::new(RefCell::new(row)))
+        })
+        .collect();
+    Ok(Value::List(Rc::new(RefCell::new(rows))))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::run;
+
+    #[test]
+    fn test_summaries() {
+        let source = "let xs = [2, 4, 4, 4, 5, 5, 7, 9]\n[stats.mean(xs), stats.median(xs), stats.mode(xs), stats.percentile(xs, 25), stats.median([3, 1, 2]), stats.mode([\"b\", \"a\", \"a\", \"b\"])]\n";
+        assert_eq!(run(source).unwrap(), "[5, 4.5, 4, 4, 2, b]");
+        let stdev = run("stats.stdev([2, 4, 4, 4, 5, 5, 7, 9])\n").unwrap();
+        assert!(stdev.starts_with("2.138"), "{}", stdev);
+    }
+
+    #[test]
+    fn test_histogram() {
+        let source = "from stats import histogram\ndef count row\n    return row[\"count\"]\nlet rows = histogram([1, 2, 2, 3, 9, 10], 3)\n[list(map(count, rows)), rows[0][\"start\"], rows[2][\"end\"], list(map(count, histogram([5, 5], 2)))]\n";
+        assert_eq!(run(source).unwrap(), "[[4, 0, 2], 1, 10, [0, 2]]");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            run("stats.mean([])\n").unwrap_err(),
+            "stats.mean() expects a non-empty list of numbers"
+        );
+        assert_eq!(
+            run("stats.stdev([1])\n").unwrap_err(),
+            "stats.stdev() requires at least 2 values"
+        );
+        assert_eq!(
+            run("stats.percentile([1, 2], 150)\n").unwrap_err(),
+            "stats.percentile() expects a percentile between 0 and 100"
+        );
+        assert_eq!(
+            run("stats.histogram([1, 2], 0)\n").unwrap_err(),
+            "stats.histogram() expects a list of numbers and a positive bin count"
+        );
+    }
+}
@@ -0,0 +1,235 @@
+//! エディタ向け構文定義の生成 (n7tya gen-syntax)
+//!
+//! キーワードと演算子は lexer.rs の `#[token("...")]` から読み取るので、
+//! トークンを追加すれば生成される構文定義にも自動で反映される。
+//! 構文規則そのものは手で書いた grammar.ebnf にあり、`--target ebnf` でそのまま出力する。
+
+use serde_json::json;
+
+/// lexer.rs のソース (トークン定義の読み取り用)
+const LEXER_SOURCE: &str = include_str!("lexer.rs");
+
+/// パーサーが受け付ける文法 (W3C 形式の EBNF)
+pub const GRAMMAR: &str = include_str!("grammar.ebnf");
+
+/// 値として扱うキーワード
+const CONSTANTS: &[&str] = &["true", "false", "none"];
+/// インスタンスを指すキーワード
+const LANGUAGE_VARIABLES: &[&str] = &["self", "super"];
+
+/// 生成先のエディタ形式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyntaxTarget {
+    /// TextMate 文法 (VS Code など)
+    TmLanguage,
+    /// tree-sitter の grammar.js
+    TreeSitter,
+    /// 文法の EBNF
+    Ebnf,
+}
+
+impl SyntaxTarget {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "tmlanguage" => Ok(Self::TmLanguage),
+            "tree-sitter" => Ok(Self::TreeSitter),
+            "ebnf" => Ok(Self::Ebnf),
+            _ => Err(format!(
+                "Unknown syntax target '{}' (expected tmlanguage, tree-sitter or ebnf)",
+                name
+            )),
+        }
+    }
+}
+
+/// 構文定義を生成する
+pub fn generate(target: SyntaxTarget) -> String {
+    match target {
+        SyntaxTarget::TmLanguage => tm_language(),
+        SyntaxTarget::TreeSitter => tree_sitter(),
+        SyntaxTarget::Ebnf => GRAMMAR.to_string(),
+    }
+}
+
+/// Token enum に `#[token("...")]` で定義された文字列 (定義順)
+fn token_literals() -> Vec<&'static str> {
+    LEXER_SOURCE
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("#[token(\"")?.strip_suffix("\")]"))
+        // 改行のようなエスケープを含むものは構文定義に載せない
+        .filter(|literal| !literal.contains('\\'))
+        .collect()
+}
+
+/// 識別子の形をしたトークン
+fn keywords() -> Vec<&'static str> {
+    token_literals()
+        .into_iter()
+        .filter(|t| t.chars().all(|c| c.is_ascii_alphabetic()))
+        .collect()
+}
+
+/// 記号のトークン (長いものから並べる)
+fn operators() -> Vec<&'static str> {
+    let mut operators: Vec<&str> = token_literals()
+        .into_iter()
+        .filter(|t| !t.chars().any(|c| c.is_ascii_alphanumeric()))
+        .collect();
+    operators.sort_by_key(|op| std::cmp::Reverse(op.len()));
+    operators
+}
+
+/// 制御構文などのキーワード (定数と self/super を除き、文脈キーワードを含む)
+fn control_keywords() -> Vec<&'static str> {
+    keywords()
+        .into_iter()
+        .filter(|k| !CONSTANTS.contains(k) && !LANGUAGE_VARIABLES.contains(k))
+        .chain(crate::parser::SOFT_KEYWORDS.iter().copied())
+        .collect()
+}
+
+fn escape_regex(literal: &str) -> String {
+    literal
+        .chars()
+        .map(|c| {
+            if "\\.+*?()|[]{}^$/".contains(c) {
+                format!("\\{}", c)
+            } else {
+                c.to_string()
+            }
+        })
+        .collect()
+}
+
+fn word_pattern(words: &[&str]) -> String {
+    format!("\\b({})\\b", words.join("|"))
+}
+
+/// TextMate 文法 (JSON)
+fn tm_language() -> String {
+    let operators: Vec<String> = operators().iter().map(|op| escape_regex(op)).collect();
+    let grammar = json!({
+        "name": "n7tya",
+        "scopeName": "source.n7tya",
+        "fileTypes": ["n7t"],
+        "patterns": [
+            { "name": "comment.line.number-sign.n7tya", "match": "#.*$" },
+            {
+                "name": "string.quoted.double.n7tya",
+                "begin": "\"",
+                "end": "\"",
+                "patterns": [{ "name": "constant.character.escape.n7tya", "match": "\\\\." }]
+            },
+            {
+                "name": "string.quoted.single.n7tya",
+                "begin": "'",
+                "end": "'|$",
+                "patterns": [{ "name": "constant.character.escape.n7tya", "match": "\\\\." }]
+            },
+            { "name": "string.quoted.other.multiline.n7tya", "begin": "`", "end": "`" },
+            { "name": "constant.numeric.n7tya", "match": "\\b[0-9]+(\\.[0-9]+)?\\b" },
+            {
+                "match": "\\b(def|class|struct|interface|component|server)\\s+([A-Za-z_][A-Za-z0-9_]*)",
+                "captures": {
+                    "1": { "name": "keyword.control.n7tya" },
+                    "2": { "name": "entity.name.function.n7tya" }
+                }
+            },
+            { "name": "constant.language.n7tya", "match": word_pattern(CONSTANTS) },
+            { "name": "variable.language.n7tya", "match": word_pattern(LANGUAGE_VARIABLES) },
+            { "name": "keyword.control.n7tya", "match": word_pattern(&control_keywords()) },
+            { "name": "entity.name.type.n7tya", "match": "\\b[A-Z][A-Za-z0-9_]*\\b" },
+            { "name": "keyword.operator.n7tya", "match": operators.join("|") }
+        ]
+    });
+    let mut text = serde_json::to_string_pretty(&grammar).unwrap_or_default();
+    text.push('\n');
+    text
+}
+
+/// tree-sitter の grammar.js
+/// (インデント構造は扱わず、ハイライト用にトークンを並べただけの文法)
+fn tree_sitter() -> String {
+    let quote = |words: &[&str]| {
+        words
+            .iter()
+            .map(|w| format!("'{}'", w.replace('\\', "\\\\").replace('\'', "\\'")))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let mut out = String::new();
+    out.push_str("// n7tya gen-syntax --target tree-sitter で生成\n");
+    out.push_str("module.exports = grammar({\n");
+    out.push_str("  name: 'n7tya',\n");
+    out.push_str("  extras: $ => [/\\s/],\n");
+    out.push_str("  word: $ => $.identifier,\n");
+    out.push_str("  rules: {\n");
+    out.push_str("    source_file: $ => repeat($._token),\n");
+    out.push_str("    _token: $ => choice($.comment, $.string, $.number, $.constant, $.variable, $.keyword, $.type, $.identifier, $.operator),\n");
+    out.push_str("    comment: $ => /#[^\\n]*/,\n");
+    out.push_str(
+        "    string: $ => choice(/\"([^\"\\\\]|\\\\.)*\"/, /'([^'\\\\\\n]|\\\\.)*'/, /`[^`]*`/),\n",
+    );
+    out.push_str("    number: $ => /[0-9]+(\\.[0-9]+)?/,\n");
+    out.push_str(&format!(
+        "    constant: $ => choice({}),\n",
+        quote(CONSTANTS)
+    ));
+    out.push_str(&format!(
+        "    variable: $ => choice({}),\n",
+        quote(LANGUAGE_VARIABLES)
+    ));
+    out.push_str(&format!(
+        "    keyword: $ => choice({}),\n",
+        quote(&control_keywords())
+    ));
+    out.push_str("    type: $ => /[A-Z][A-Za-z0-9_]*/,\n");
+    out.push_str("    identifier: $ => /[a-z_][A-Za-z0-9_]*/,\n");
+    out.push_str(&format!(
+        "    operator: $ => choice({}),\n",
+        quote(&operators())
+    ));
+    out.push_str("  },\n");
+    out.push_str("});\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{Lexer, Token};
+
+    #[test]
+    fn test_token_literals_match_lexer() {
+        // 読み取った文字列は全て識別子以外の単一トークンとして字句解析される
+        for literal in token_literals() {
+            let tokens: Vec<Token> = Lexer::new(literal)
+                .tokenize()
+                .into_iter()
+                .map(|t| t.token)
+                .filter(|t| !matches!(t, Token::Newline))
+                .collect();
+            assert_eq!(tokens.len(), 1, "{}", literal);
+            assert!(!matches!(tokens[0], Token::Identifier(_)), "{}", literal);
+        }
+        assert!(keywords().contains(&"super"));
+        assert!(operators().contains(&"->"));
+
+        let grammar: serde_json::Value =
+            serde_json::from_str(&generate(SyntaxTarget::TmLanguage)).unwrap();
+        assert_eq!(grammar["scopeName"], "source.n7tya");
+        assert!(generate(SyntaxTarget::TreeSitter).contains("'component'"));
+    }
+
+    #[test]
+    fn test_grammar_mentions_every_token() {
+        // トークンを増やしたら grammar.ebnf にも書く (使わないものは reserved に入れる)
+        let words = token_literals()
+            .into_iter()
+            .chain(crate::parser::SOFT_KEYWORDS.iter().copied());
+        for word in words {
+            assert!(GRAMMAR.contains(&format!("\"{}\"", word)), "{}", word);
+        }
+    }
+}
@@ -0,0 +1,448 @@
+//! OpenTelemetry のトレース (n7tya.toml の [telemetry])
+//!
+//! ```toml
+//! [telemetry]
+//! endpoint = "http://localhost:4318"   # OTLP/HTTP の送信先 (書いたときだけトレースを送る)
+//! service_name = "shop"                # 省略時は [package] の name
+//! headers = { "x-api-key" = "..." }    # 送信時に加えるヘッダー
+//! ```
+//!
+//! リクエストの処理、標準ライブラリの関数 (`fs.read_file` など) の呼び出し、
+//! `sqlite.execute` / `sqlite.query` をスパンにし、`{endpoint}/v1/traces` へ OTLP の JSON で送る。
+//! リクエストに `traceparent` ヘッダーがあれば、そのトレースの続きにする。
+//! 送信は別スレッドでまとめて行うので、送信先が遅くてもリクエストは待たない。
+
+use crate::interpreter::Value;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 一度に送るスパンの数の上限
+const BATCH_SIZE: usize = 256;
+/// スパンが溜まらなくても送る間隔
+const EXPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// [telemetry] の設定
+#[derive(Debug, Clone, PartialEq)]
+pub struct TelemetryOptions {
+    pub endpoint: String,
+    pub service_name: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl TelemetryOptions {
+    /// n7tya.toml の内容から [telemetry] を読む (endpoint がなければ None)
+    pub fn from_toml(content: &str) -> Result<Option<Self>, String> {
+        let table: toml::Table = content
+            .parse()
+            .map_err(|e| format!("Invalid n7tya.toml: {}", e))?;
+        let telemetry = match table.get("telemetry") {
+            Some(toml::Value::Table(telemetry)) => telemetry,
+            Some(_) => return Err("[telemetry] must be a table".to_string()),
+            None => return Ok(None),
+        };
+        let text = |key: &str| match telemetry.get(key) {
+            Some(toml::Value::String(s)) => Ok(Some(s.clone())),
+            Some(_) => Err(format!("[telemetry] {} must be a string", key)),
+            None => Ok(None),
+        };
+        let Some(endpoint) = text("endpoint")? else {
+            return Ok(None);
+        };
+        let package = table
+            .get("package")
+            .and_then(|package| package.get("name"))
+            .and_then(toml::Value::as_str);
+        let service_name = text("service_name")?
+            .or(package.map(str::to_string))
+            .unwrap_or_else(|| "n7tya".to_string());
+        let headers = match telemetry.get("headers") {
+            Some(toml::Value::Table(headers)) => headers
+                .iter()
+                .map(|(name, value)| match value {
+                    toml::Value::String(value) => Ok((name.clone(), value.clone())),
+                    _ => Err(format!("[telemetry] header '{}' must be a string", name)),
+                })
+                .collect::<Result<_, _>>()?,
+            Some(_) => return Err("[telemetry] headers must be a table".to_string()),
+            None => Vec::new(),
+        };
+        Ok(Some(Self {
+            endpoint,
+            service_name,
+            headers,
+        }))
+    }
+
+    /// トレースを送る URL (endpoint が /v1/traces で終わっていればそのまま)
+    fn traces_url(&self) -> String {
+        let endpoint = self.endpoint.trim_end_matches('/');
+        if endpoint.ends_with("/v1/traces") {
+            endpoint.to_string()
+        } else {
+            format!("{}/v1/traces", endpoint)
+        }
+    }
+}
+
+/// スパンの種類 (OTLP の SpanKind の値)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpanKind {
+    Internal = 1,
+    Server = 2,
+    Client = 3,
+}
+
+/// トレースの中でスパンを指す ID
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpanContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+}
+
+impl SpanContext {
+    /// W3C の traceparent ヘッダー ("00-{trace_id}-{span_id}-{flags}")
+    pub fn from_traceparent(header: &str) -> Option<Self> {
+        let mut parts = header.trim().split('-');
+        let (version, trace_id, span_id, _flags) =
+            (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        if version.len() != 2 || trace_id.len() != 32 || span_id.len() != 16 {
+            return None;
+        }
+        let context = Self {
+            trace_id: u128::from_str_radix(trace_id, 16).ok()?,
+            span_id: u64::from_str_radix(span_id, 16).ok()?,
+        };
+        (context.trace_id != 0 && context.span_id != 0).then_some(context)
+    }
+
+    /// リクエストのヘッダーの traceparent (ヘッダー名は小文字)
+    pub(crate) fn from_headers(headers: &HashMap<String, Value>) -> Option<Self> {
+        match headers.get("traceparent") {
+            Some(Value::Str(header)) => Self::from_traceparent(header),
+            _ => None,
+        }
+    }
+}
+
+/// 処理中のスパン (Telemetry::end で送る)
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub context: SpanContext,
+    parent: Option<u64>,
+    name: String,
+    kind: SpanKind,
+    start: u128,
+    end: u128,
+    attributes: Vec<(&'static str, serde_json::Value)>,
+    error: Option<String>,
+}
+
+impl Span {
+    pub fn set_str(&mut self, key: &'static str, value: impl Into<String>) {
+        self.attributes
+            .push((key, json!({ "stringValue": value.into() })));
+    }
+
+    pub fn set_int(&mut self, key: &'static str, value: i64) {
+        // OTLP の JSON では 64 ビット整数を文字列で書く
+        self.attributes
+            .push((key, json!({ "intValue": value.to_string() })));
+    }
+
+    /// スパンの処理が失敗したことにする
+    pub fn fail(&mut self, message: impl Into<String>) {
+        self.error = Some(message.into());
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let attributes: Vec<_> = self
+            .attributes
+            .iter()
+            .map(|(key, value)| json!({ "key": key, "value": value }))
+            .collect();
+        let status = match &self.error {
+            Some(message) => json!({ "code": 2, "message": message }),
+            None => json!({}),
+        };
+        let mut span = json!({
+            "traceId": format!("{:032x}", self.context.trace_id),
+            "spanId": format!("{:016x}", self.context.span_id),
+            "name": self.name,
+            "kind": self.kind as u8,
+            "startTimeUnixNano": self.start.to_string(),
+            "endTimeUnixNano": self.end.to_string(),
+            "attributes": attributes,
+            "status": status,
+        });
+        if let Some(parent) = self.parent {
+            span["parentSpanId"] = json!(format!("{:016x}", parent));
+        }
+        span
+    }
+}
+
+/// スパンを作って送る
+pub struct Telemetry {
+    options: TelemetryOptions,
+    /// 処理中のスパン (最後のものが次に作るスパンの親)
+    active: Vec<SpanContext>,
+    sender: Option<mpsc::Sender<Span>>,
+    exporter: Option<JoinHandle<()>>,
+}
+
+impl Telemetry {
+    pub fn new(options: TelemetryOptions) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let exporter = {
+            let options = options.clone();
+            std::thread::spawn(move || export(&options, receiver))
+        };
+        Self {
+            options,
+            active: Vec::new(),
+            sender: Some(sender),
+            exporter: Some(exporter),
+        }
+    }
+
+    pub fn options(&self) -> &TelemetryOptions {
+        &self.options
+    }
+
+    /// スパンを始める。リクエストのスパンの親は remote (traceparent で受け取ったもの)、
+    /// それ以外は処理中のスパン (待っている間に別のリクエストを処理しても、そのトレースには入れない)
+    pub fn start(&mut self, name: &str, kind: SpanKind, remote: Option<SpanContext>) -> Span {
+        let parent = match kind {
+            SpanKind::Server => remote,
+            _ => remote.or(self.active.last().copied()),
+        };
+        let context = SpanContext {
+            trace_id: parent.map_or_else(
+                || (random_id() as u128) << 64 | random_id() as u128,
+                |p| p.trace_id,
+            ),
+            span_id: random_id(),
+        };
+        self.active.push(context);
+        Span {
+            context,
+            parent: parent.map(|p| p.span_id),
+            name: name.to_string(),
+            kind,
+            start: now(),
+            end: 0,
+            attributes: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// スパンを終えて送る
+    pub fn end(&mut self, mut span: Span) {
+        span.end = now();
+        if let Some(index) = self.active.iter().rposition(|c| *c == span.context) {
+            self.active.truncate(index);
+        }
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(span);
+        }
+    }
+
+    /// 標準ライブラリの関数の呼び出しのスパン (HTTP と SQL は送る先の情報も付ける)
+    pub(crate) fn start_builtin(&mut self, name: &str, args: &[Value]) -> Span {
+        match (name, args) {
+            ("sqlite.execute" | "sqlite.query", [_, Value::Str(sql), ..]) => {
+                let mut span = self.start(name, SpanKind::Client, None);
+                span.set_str("db.system.name", "sqlite");
+                span.set_str("db.query.text", sql.to_string());
+                span
+            }
+            (_, [Value::Str(url), ..])
+                if crate::http::is_request(name) || name == "http.download" =>
+            {
+                let method = match name {
+                    "http.download" => "GET".to_string(),
+                    _ => name.trim_start_matches("http.").to_uppercase(),
+                };
+                let mut span = self.start(name, SpanKind::Client, None);
+                span.set_str("http.request.method", method);
+                span.set_str("url.full", url.to_string());
+                span
+            }
+            _ => self.start(name, SpanKind::Internal, None),
+        }
+    }
+}
+
+impl Drop for Telemetry {
+    /// 送っていないスパンを送り終えるまで待つ
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(exporter) = self.exporter.take() {
+            let _ = exporter.join();
+        }
+    }
+}
+
+/// スパンを受け取り、BATCH_SIZE 個か EXPORT_INTERVAL ごとにまとめて送る
+fn export(options: &TelemetryOptions, receiver: mpsc::Receiver<Span>) {
+    let mut batch = Vec::new();
+    loop {
+        let closed = match receiver.recv_timeout(EXPORT_INTERVAL) {
+            Ok(span) => {
+                batch.push(span);
+                if batch.len() < BATCH_SIZE {
+                    continue;
+                }
+                false
+            }
+            Err(RecvTimeoutError::Timeout) => false,
+            Err(RecvTimeoutError::Disconnected) => true,
+        };
+        if !batch.is_empty() {
+            if let Err(e) = send(options, &std::mem::take(&mut batch)) {
+                println!("Failed to export traces: {}", e);
+            }
+        }
+        if closed {
+            return;
+        }
+    }
+}
+
+fn send(options: &TelemetryOptions, spans: &[Span]) -> Result<(), String> {
+    let mut request = ureq::post(&options.traces_url()).set("Content-Type", "application/json");
+    for (name, value) in &options.headers {
+        request = request.set(name, value);
+    }
+    request
+        .send_string(&request_body(options, spans).to_string())
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// OTLP の ExportTraceServiceRequest (JSON)
+fn request_body(options: &TelemetryOptions, spans: &[Span]) -> serde_json::Value {
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    { "key": "service.name", "value": { "stringValue": options.service_name } },
+                ],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "n7tya", "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans.iter().map(Span::to_json).collect::<Vec<_>>(),
+            }],
+        }],
+    })
+}
+
+fn now() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos())
+}
+
+/// 0 でない乱数の ID
+fn random_id() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+    loop {
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u128(now());
+        let id = hasher.finish();
+        if id != 0 {
+            return id;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    /// 1回の POST の本文を受け取る OTLP の受け口
+    fn collector() -> (String, std::thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let mut stream = reader.into_inner();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            String::from_utf8(body).unwrap()
+        });
+        (endpoint, handle)
+    }
+
+    #[test]
+    fn test_export_spans() {
+        let options = TelemetryOptions::from_toml(
+            "[package]\nname = \"shop\"\n[telemetry]\nendpoint = \"http://localhost:4318/\"\n",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(options.service_name, "shop");
+        assert_eq!(options.traces_url(), "http://localhost:4318/v1/traces");
+        assert_eq!(TelemetryOptions::from_toml("[server]\n").unwrap(), None);
+
+        let remote = SpanContext::from_traceparent(
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        )
+        .unwrap();
+        assert_eq!(remote.span_id, 0x00f067aa0ba902b7);
+        assert_eq!(SpanContext::from_traceparent("00-0-0-01"), None);
+
+        let (endpoint, received) = collector();
+        let mut telemetry = Telemetry::new(TelemetryOptions {
+            endpoint,
+            ..options
+        });
+        let mut request = telemetry.start("GET /users/*", SpanKind::Server, Some(remote));
+        let sql = Value::Str("SELECT 1".into());
+        let mut query = telemetry.start_builtin("sqlite.query", &[Value::Int(1), sql]);
+        query.fail("no such table");
+        assert_eq!(query.parent, Some(request.context.span_id));
+        telemetry.end(query);
+        request.set_int("http.response.status_code", 200);
+        telemetry.end(request);
+        drop(telemetry);
+
+        let body: serde_json::Value = serde_json::from_str(&received.join().unwrap()).unwrap();
+        let resource = &body["resourceSpans"][0];
+        assert_eq!(
+            resource["resource"]["attributes"][0]["value"]["stringValue"],
+            "shop"
+        );
+        let spans = &resource["scopeSpans"][0]["spans"];
+        assert_eq!(spans[0]["name"], "sqlite.query");
+        assert_eq!(spans[0]["kind"], 3);
+        assert_eq!(spans[0]["status"]["code"], 2);
+        assert_eq!(
+            spans[0]["attributes"][1]["value"]["stringValue"],
+            "SELECT 1"
+        );
+        assert_eq!(spans[1]["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(spans[1]["parentSpanId"], "00f067aa0ba902b7");
+        assert_eq!(spans[1]["attributes"][0]["value"]["intValue"], "200");
+    }
+}
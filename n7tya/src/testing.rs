@@ -0,0 +1,13 @@
+//! テスト用の共通ヘルパー
+
+use crate::interpreter::Interpreter;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+
+/// ソースを実行し、最後の値を表示用の文字列にして返す
+pub(crate) fn run(source: &str) -> Result<String, String> {
+    let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+    Interpreter::new()
+        .run(&program)
+        .map(|value| value.display())
+}
@@ -0,0 +1,133 @@
+//! toml モジュール (TOML の読み書き)
+//!
+//! `toml.parse(text)` は表を辞書にする (日時は "1979-05-27T07:32:00Z" のような文字列のまま)。
+//! `toml.parse(fs.read_file("n7tya.toml"))` でプロジェクトの設定も読める。
+//! `toml.stringify(value)` は辞書か構造体を書き出す。TOML には空の値がないので none のキーは書かない。
+
+use crate::interpreter::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// toml.parse(text) / toml.stringify(value) (toml.try_parse は builtins で Result にする)
+pub fn call(name: &str, args: Vec<Value>) -> Result<Value, String> {
+    match (name, args.as_slice()) {
+        ("parse", [Value::Str(text)]) => {
+            let table: ::toml::Table = text
+                .parse()
+                .map_err(|e: ::toml::de::Error| format!("TOML parse error: {}", e.message()))?;
+            Ok(to_value(::toml::Value::Table(table)))
+        }
+        ("stringify", [value @ (Value::Dict(_) | Value::Struct(..))]) => {
+            let table = match from_json(crate::builtins::value_to_json(value))? {
+                Some(::toml::Value::Table(table)) => table,
+                _ => ::toml::Table::new(),
+            };
+            ::toml::to_string(&table)
+                .map(|text| Value::Str(text.into()))
+                .map_err(|e| format!("TOML stringify error: {}", e))
+        }
+        ("parse", _) => Err("toml.parse() expects a string".to_string()),
+        ("stringify", [other]) => Err(format!(
+            "toml.stringify() expects a dict or a struct, got {}",
+            other.type_name()
+        )),
+        ("stringify", _) => Err("toml.stringify() takes exactly 1 argument".to_string()),
+        _ => Err(format!("Module 'toml' has no member '{}'", name)),
+    }
+}
+
+fn to_value(toml: ::toml::Value) -> Value {
+    match toml {
+        ::toml::Value::String(s) => Value::Str(s.into()),
+        ::toml::Value::Integer(n) => Value::Int(n),
+        ::toml::Value::Float(f) => Value::Float(f),
+        ::toml::Value::Boolean(b) => Value::Bool(b),
+        ::toml::Value::Datetime(datetime) => Value::Str(datetime.to_string().into()),
+        ::toml::Value::Array(items) => Value::List(Rc::new(RefCell::new(
+            items.into_iter().map(to_value).collect(),
+        ))),
+        ::toml::Value::Table(table) => {
+            let map: HashMap<String, Value> = table
+                .into_iter()
+                .map(|(key, value)| (key, to_value(value)))
+                .collect();
+            Value::Dict(Rc::new(RefCell::new(map)))
+        }
+    }
+}
+
+/// JSON にした値を TOML にする (null は None で、表では書かずにリストではエラー)
+fn from_json(json: serde_json::Value) -> Result<Option<::toml::Value>, String> {
+    Ok(Some(match json {
+        serde_json::Value::Null => return Ok(None),
+        serde_json::Value::Bool(b) => ::toml::Value::Boolean(b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => ::toml::Value::Integer(i),
+            None => ::toml::Value::Float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_json::Value::String(s) => ::toml::Value::String(s),
+        serde_json::Value::Array(items) => {
+            let mut array = Vec::new();
+            for item in items {
+                match from_json(item)? {
+                    Some(item) => array.push(item),
+                    None => return Err("toml.stringify(): lists cannot contain none".to_string()),
+                }
+            }
+            ::toml::Value::Array(array)
+        }
+        serde_json::Value::Object(object) => {
+            let mut table = ::toml::Table::new();
+            for (key, value) in object {
+                if let Some(value) = from_json(value)? {
+                    table.insert(key, value);
+                }
+            }
+            ::toml::Value::Table(table)
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::run;
+
+    #[test]
+    fn test_parse() {
+        let source = "let config = toml.parse(\"name = \\\"app\\\"\\n[server]\\nport = 8080\\nhosts = [\\\"a\\\", \\\"b\\\"]\\nstarted = 1979-05-27T07:32:00Z\\n\")\n[config[\"name\"], config[\"server\"][\"port\"] + 1, config[\"server\"][\"hosts\"], config[\"server\"][\"started\"]]\n";
+        assert_eq!(
+            run(source).unwrap(),
+            "[app, 8081, [a, b], 1979-05-27T07:32:00Z]"
+        );
+    }
+
+    #[test]
+    fn test_stringify() {
+        let source = "struct Server\n\tport: Int\n\thost: Str?\ntoml.stringify({\"name\": \"app\", \"debug\": false, \"server\": Server { port: 80, host: none }})\n";
+        assert_eq!(
+            run(source).unwrap(),
+            "debug = false\nname = \"app\"\n\n[server]\nport = 80\n"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(run("toml.parse(\"name = \")\n")
+            .unwrap_err()
+            .starts_with("TOML parse error: "));
+        assert_eq!(
+            run("toml.parse(\"= 1\")\n").unwrap_err(),
+            "TOML parse error: invalid key"
+        );
+        assert_eq!(run("toml.try_parse(\"= 1\").is_err()\n").unwrap(), "true");
+        assert_eq!(
+            run("toml.stringify([1, 2])\n").unwrap_err(),
+            "toml.stringify() expects a dict or a struct, got List"
+        );
+        assert_eq!(
+            run("toml.stringify({\"a\": [1, none]})\n").unwrap_err(),
+            "toml.stringify(): lists cannot contain none"
+        );
+    }
+}
@@ -3,8 +3,10 @@
 //! ASTを走査し、型の整合性を検証する
 
 use crate::ast::*;
+use crate::resolve::{ReferenceKind, ScopeRules};
 use miette::Result;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
 
 /// 型表現（ASTのTypeとは別に、推論結果を表す）
 #[derive(Debug, Clone, PartialEq)]
@@ -20,18 +22,119 @@ pub enum TypeInfo {
         ret: Box<TypeInfo>,
     },
     Class(String),
-    Unknown, // 型推論が未確定
+    Module(String),       // 標準ライブラリのモジュール (import http as web の web も)
+    Union(Vec<TypeInfo>), // Int | none など (Optional は None を含む合併型で表す)
+    Unknown,              // 型推論が未確定
     Error,   // 型エラー
 }
 
+impl TypeInfo {
+    /// 型注釈として書ける確定した型か
+    pub fn is_concrete(&self) -> bool {
+        match self {
+            TypeInfo::Int | TypeInfo::Float | TypeInfo::Bool | TypeInfo::Str => true,
+            TypeInfo::Class(_) => true,
+            TypeInfo::List(inner) => inner.is_concrete(),
+            _ => false,
+        }
+    }
+
+    /// 合併型を作る (入れ子を平らにして重複を除き、1つだけならその型にする)
+    pub fn union(members: Vec<TypeInfo>) -> TypeInfo {
+        let mut flat: Vec<TypeInfo> = Vec::new();
+        for member in members {
+            let inner = match member {
+                TypeInfo::Union(inner) => inner,
+                other => vec![other],
+            };
+            for ty in inner {
+                if !flat.contains(&ty) {
+                    flat.push(ty);
+                }
+            }
+        }
+        if flat.len() == 1 {
+            flat.pop().unwrap_or(TypeInfo::Unknown)
+        } else {
+            TypeInfo::Union(flat)
+        }
+    }
+
+    /// none になりうる合併型か
+    pub fn is_optional(&self) -> bool {
+        matches!(self, TypeInfo::Union(members) if members.contains(&TypeInfo::None))
+    }
+
+    /// 合併型から none を除いた型
+    pub fn without_none(&self) -> TypeInfo {
+        match self {
+            TypeInfo::Union(members) => TypeInfo::union(
+                members.iter().filter(|t| **t != TypeInfo::None).cloned().collect(),
+            ),
+            other => other.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for TypeInfo {
+    /// ソースコードでの書き方で表示する (エラーメッセージ用)
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TypeInfo::Int => write!(f, "Int"),
+            TypeInfo::Float => write!(f, "Float"),
+            TypeInfo::Bool => write!(f, "Bool"),
+            TypeInfo::Str => write!(f, "Str"),
+            TypeInfo::None => write!(f, "none"),
+            TypeInfo::List(inner) if **inner == TypeInfo::Unknown => write!(f, "List"),
+            TypeInfo::List(inner) => write!(f, "List<{}>", inner),
+            TypeInfo::Fn { params, ret } => {
+                let names: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+                write!(f, "Fn[{}] -> {}", names.join(", "), ret)
+            }
+            TypeInfo::Class(name) | TypeInfo::Module(name) => write!(f, "{}", name),
+            // Int | none は Int? と書く
+            TypeInfo::Union(members) if self.is_optional() && members.len() == 2 => {
+                write!(f, "{}?", self.without_none())
+            }
+            TypeInfo::Union(members) => {
+                let names: Vec<String> = members.iter().map(|m| m.to_string()).collect();
+                write!(f, "{}", names.join(" | "))
+            }
+            TypeInfo::Unknown => write!(f, "Unknown"),
+            TypeInfo::Error => write!(f, "Error"),
+        }
+    }
+}
+
 /// 型環境（スコープごとの変数・関数の型情報）
 #[derive(Debug, Clone)]
 pub struct TypeEnv {
+    builtins: &'static HashMap<String, TypeInfo>, // 組み込み関数とモジュール (グローバルスコープの外側)
     scopes: Vec<HashMap<String, TypeInfo>>,
+    narrowed: Vec<HashMap<String, TypeInfo>>, // none チェックで絞り込んだ型 (scopes と同じ深さ)
+    defined: HashSet<String>, // スコープを抜けた後も含め、一度でも定義された名前 (組み込みを除く)
+}
+
+impl Default for TypeEnv {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TypeEnv {
     pub fn new() -> Self {
+        // 組み込みの型はプロセスで一度だけ作り、型チェッカーどうしで共有する
+        static BUILTINS: OnceLock<HashMap<String, TypeInfo>> = OnceLock::new();
+        Self {
+            builtins: BUILTINS.get_or_init(Self::builtins),
+            scopes: vec![HashMap::new()],
+            narrowed: vec![HashMap::new()],
+            defined: HashSet::new(),
+        }
+    }
+
+    /// 組み込み関数と標準ライブラリのモジュールの型
+    fn builtins() -> HashMap<String, TypeInfo> {
         let mut global = HashMap::new();
 
         // 汎用関数型 (任意の型を受け付ける)
@@ -67,19 +170,57 @@ impl TypeEnv {
 
         // コレクション
         global.insert("len".to_string(), any_to_int.clone());
-        global.insert("range".to_string(), any_to_list.clone());
-        global.insert("sum".to_string(), any_to_int.clone());
+        // Decimal のリストなら Decimal
+        global.insert("sum".to_string(), any_fn.clone());
         global.insert("sorted".to_string(), any_to_list.clone());
         global.insert("reversed".to_string(), any_to_list.clone());
-        global.insert("enumerate".to_string(), any_to_list.clone());
-        global.insert("zip".to_string(), any_to_list.clone());
+        global.insert("list".to_string(), any_to_list.clone());
+        global.insert("set".to_string(), any_fn.clone());
+        // 遅延イテレータを返す (添字や len は使えない)
+        global.insert("range".to_string(), any_fn.clone());
+        global.insert("enumerate".to_string(), any_fn.clone());
+        global.insert("zip".to_string(), any_fn.clone());
+        global.insert("zip_longest".to_string(), any_fn.clone());
+        global.insert("map".to_string(), any_fn.clone());
+        global.insert("filter".to_string(), any_fn.clone());
+        global.insert("reduce".to_string(), any_fn.clone());
+
+        // 並行実行
+        global.insert("await_all".to_string(), any_to_list.clone());
+        global.insert("race".to_string(), any_fn.clone());
+
+        // コンポーネントからページ / メール用の HTML を生成
+        global.insert("render_page".to_string(), any_to_str.clone());
+        global.insert("render_email".to_string(), any_to_str.clone());
+        global.insert("decode_form".to_string(), any_fn.clone());
+        global.insert("redirect".to_string(), any_fn.clone());
+        global.insert("csrf_token".to_string(), any_to_str.clone());
 
         // 型変換
         global.insert("str".to_string(), any_to_str.clone());
         global.insert("int".to_string(), any_to_int.clone());
         global.insert("float".to_string(), any_to_float.clone());
+        global.insert(
+            "decimal".to_string(),
+            TypeInfo::Fn {
+                params: vec![TypeInfo::Unknown],
+                ret: Box::new(TypeInfo::Class("Decimal".to_string())),
+            },
+        );
         global.insert("type".to_string(), any_to_str.clone());
         global.insert("bool".to_string(), any_to_bool.clone());
+        global.insert("hex".to_string(), any_to_str.clone());
+        global.insert("bin".to_string(), any_to_str.clone());
+        global.insert("oct".to_string(), any_to_str.clone());
+        global.insert("bit_length".to_string(), any_to_int.clone());
+        global.insert("popcount".to_string(), any_to_int.clone());
+        global.insert("format".to_string(), any_to_str.clone());
+
+        // Result / Option (中身の型は追跡しない)
+        global.insert("Ok".to_string(), any_fn.clone());
+        global.insert("Err".to_string(), any_fn.clone());
+        global.insert("Some".to_string(), any_fn.clone());
+        global.insert("None".to_string(), TypeInfo::None);
 
         // 数値
         global.insert("abs".to_string(), any_to_int.clone());
@@ -88,6 +229,7 @@ impl TypeEnv {
 
         // fs モジュール
         global.insert("fs.read_file".to_string(), any_to_str.clone());
+        global.insert("fs.try_read_file".to_string(), any_fn.clone());
         global.insert("fs.write_file".to_string(), any_fn.clone());
         global.insert("fs.exists".to_string(), any_to_bool.clone());
         global.insert("fs.remove".to_string(), any_fn.clone());
@@ -98,48 +240,197 @@ impl TypeEnv {
             params: vec![TypeInfo::Str],
             ret: Box::new(TypeInfo::Unknown),
         });
+        global.insert("json.try_parse".to_string(), any_fn.clone());
         global.insert("json.stringify".to_string(), any_to_str.clone());
+        global.insert("json.get".to_string(), any_fn.clone());
+        global.insert("json.set".to_string(), any_fn.clone());
+
+        // yaml / toml モジュール (json と同じ)
+        for module in ["yaml", "toml"] {
+            global.insert(
+                format!("{}.parse", module),
+                TypeInfo::Fn {
+                    params: vec![TypeInfo::Str],
+                    ret: Box::new(TypeInfo::Unknown),
+                },
+            );
+            global.insert(format!("{}.try_parse", module), any_fn.clone());
+            global.insert(format!("{}.stringify", module), any_to_str.clone());
+        }
+
+        // csv モジュール (行は辞書かリスト)
+        global.insert("csv.read".to_string(), any_to_list.clone());
+        global.insert("csv.parse".to_string(), any_to_list.clone());
+        global.insert("csv.write".to_string(), any_fn.clone());
+        global.insert("csv.stringify".to_string(), any_to_str.clone());
 
         // http モジュール
-        global.insert("http.get".to_string(), any_to_str.clone());
-        global.insert("http.post".to_string(), any_to_str.clone());
+        // status, ok, headers, body, url, json() の辞書
+        for method in crate::http::METHODS {
+            global.insert(format!("http.{}", method), any_fn.clone());
+        }
+        global.insert("http.download".to_string(), any_fn.clone()); // body の代わりに path, size
+
+        // url モジュール
+        global.insert("url.parse".to_string(), any_fn.clone()); // scheme, host, port, path, query, fragment の辞書
+        global.insert("url.encode".to_string(), any_to_str.clone());
+        global.insert("url.decode".to_string(), any_to_str.clone());
+        global.insert("url.build_query".to_string(), any_to_str.clone());
 
         // base64 モジュール
         global.insert("base64.encode".to_string(), any_to_str.clone());
         global.insert("base64.decode".to_string(), any_to_str.clone());
 
         // sqlite モジュール
-        global.insert("sqlite.open".to_string(), any_to_int.clone());
+        global.insert(
+            "sqlite.open".to_string(),
+            TypeInfo::Fn {
+                params: vec![TypeInfo::Str],
+                ret: Box::new(TypeInfo::Class("SqliteConnection".to_string())),
+            },
+        );
         global.insert("sqlite.execute".to_string(), any_to_int.clone());
-        global.insert("sqlite.query".to_string(), any_fn.clone()); // List<Dict>だが動的なのでUnknownにする
+        global.insert("sqlite.query".to_string(), any_to_list.clone()); // 行は列名をキーにした辞書
         global.insert("sqlite.close".to_string(), any_fn.clone());
 
-        Self {
-            scopes: vec![global],
+        // env / process モジュール
+        global.insert("env.get".to_string(), any_fn.clone());
+        global.insert("process.run".to_string(), any_fn.clone());
+        global.insert("os.env".to_string(), any_fn.clone());
+        global.insert("os.set_env".to_string(), any_fn.clone());
+        global.insert("os.args".to_string(), any_to_list.clone());
+        global.insert("os.cwd".to_string(), any_to_str.clone());
+        global.insert("os.platform".to_string(), any_to_str.clone());
+        global.insert("os.exit".to_string(), any_fn.clone());
+
+        // flash モジュール
+        global.insert("flash.set".to_string(), any_fn.clone());
+        global.insert("flash.take".to_string(), any_to_list.clone());
+
+        // health モジュール
+        global.insert("health.check".to_string(), any_fn.clone());
+
+        // config モジュール
+        global.insert("config.get".to_string(), any_fn.clone());
+
+        // cache モジュール (保存した値の型は追跡しない)
+        global.insert("cache.get_or".to_string(), any_fn.clone());
+        global.insert("cache.get".to_string(), any_fn.clone());
+        global.insert("cache.set".to_string(), any_fn.clone());
+        global.insert("cache.delete".to_string(), any_to_bool.clone());
+        global.insert("cache.clear".to_string(), any_fn.clone());
+        // retry と circuit モジュール (関数の戻り値をそのまま返す)
+        global.insert("retry".to_string(), any_fn.clone());
+        global.insert("assert_valid".to_string(), any_fn.clone());
+        // 値とテキストの差分
+        global.insert("diff".to_string(), any_to_list.clone());
+        global.insert("patch".to_string(), any_fn.clone());
+        global.insert("assert_eq".to_string(), any_fn.clone());
+        global.insert("text.diff".to_string(), any_to_str.clone());
+        global.insert("circuit.call".to_string(), any_fn.clone());
+        global.insert("circuit.status".to_string(), any_to_str.clone());
+        global.insert("circuit.reset".to_string(), any_fn.clone());
+        // events モジュール
+        global.insert("events.on".to_string(), any_fn.clone());
+        global.insert("events.off".to_string(), any_fn.clone());
+        global.insert("events.emit".to_string(), any_fn.clone());
+
+        // time モジュール (時刻は 1970 年からの秒数)
+        for name in ["now", "monotonic", "parse", "date"] {
+            global.insert(format!("time.{}", name), any_to_float.clone());
+        }
+        for unit in ["seconds", "minutes", "hours", "days", "weeks"] {
+            global.insert(format!("time.{}", unit), any_to_float.clone());
+        }
+        global.insert("time.sleep".to_string(), any_fn.clone());
+        global.insert("time.format".to_string(), any_to_str.clone());
+        global.insert("time.parts".to_string(), any_fn.clone());
+
+        // stats モジュール (mode は要素の値をそのまま返す)
+        for name in ["mean", "median", "stdev", "percentile"] {
+            global.insert(format!("stats.{}", name), any_to_float.clone());
         }
+        global.insert("stats.mode".to_string(), any_fn.clone());
+        global.insert("stats.histogram".to_string(), any_to_list.clone());
+
+        // re モジュール (match / search は一致の辞書か none)
+        global.insert("re.match".to_string(), any_fn.clone());
+        global.insert("re.search".to_string(), any_fn.clone());
+        global.insert("re.find_all".to_string(), any_to_list.clone());
+        global.insert("re.split".to_string(), any_to_list.clone());
+        global.insert("re.replace".to_string(), any_to_str.clone());
+        global.insert("re.escape".to_string(), any_to_str.clone());
+
+        // linalg モジュール (ベクトルと行列はリスト)
+        for name in ["dot", "norm", "det"] {
+            global.insert(format!("linalg.{}", name), any_to_float.clone());
+        }
+        for name in ["identity", "zeros", "cross", "add", "sub", "scale"] {
+            global.insert(format!("linalg.{}", name), any_to_list.clone());
+        }
+        for name in ["transpose", "matmul", "inverse"] {
+            global.insert(format!("linalg.{}", name), any_to_list.clone());
+        }
+
+        // モジュール自体も値として使える (関数の型は上の "module.name" で引く)
+        for (module, _) in crate::builtins::STDLIB_MODULES {
+            global.insert(module.to_string(), TypeInfo::Module(module.to_string()));
+        }
+        global
     }
 
     pub fn push_scope(&mut self) {
         self.scopes.push(HashMap::new());
+        self.narrowed.push(HashMap::new());
     }
 
     pub fn pop_scope(&mut self) {
         self.scopes.pop();
+        self.narrowed.pop();
     }
 
     pub fn define(&mut self, name: &str, ty: TypeInfo) {
+        self.defined.insert(name.to_string());
         if let Some(scope) = self.scopes.last_mut() {
             scope.insert(name.to_string(), ty);
         }
+        if let Some(narrowed) = self.narrowed.last_mut() {
+            narrowed.remove(name);
+        }
     }
 
+    /// 絞り込みを考慮した現在の型
     pub fn lookup(&self, name: &str) -> Option<TypeInfo> {
-        for scope in self.scopes.iter().rev() {
-            if let Some(ty) = scope.get(name) {
+        for (scope, narrowed) in self.scopes.iter().zip(&self.narrowed).rev() {
+            if let Some(ty) = narrowed.get(name).or_else(|| scope.get(name)) {
                 return Some(ty.clone());
             }
         }
-        None
+        self.builtins.get(name).cloned()
+    }
+
+    /// 宣言された型 (絞り込みを無視する)
+    pub fn lookup_declared(&self, name: &str) -> Option<TypeInfo> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .or_else(|| self.builtins.get(name))
+            .cloned()
+    }
+
+    /// 現在のスコープの間だけ name の型を ty とみなす
+    pub fn narrow(&mut self, name: &str, ty: TypeInfo) {
+        if let Some(narrowed) = self.narrowed.last_mut() {
+            narrowed.insert(name.to_string(), ty);
+        }
+    }
+
+    /// name の絞り込みを全てのスコープで取り消す
+    pub fn forget_narrowing(&mut self, name: &str) {
+        for narrowed in &mut self.narrowed {
+            narrowed.remove(name);
+        }
     }
 }
 
@@ -147,6 +438,25 @@ impl TypeEnv {
 pub struct TypeChecker {
     env: TypeEnv,
     errors: Vec<String>,
+    warnings: Vec<String>, // 実行はできるが紛らわしい書き方 (名前の隠し方)
+    rules: ScopeRules,
+    structs: HashMap<String, Vec<(String, TypeInfo)>>, // 構造体名 -> フィールド型
+    classes: HashMap<String, Vec<(String, TypeInfo)>>, // クラス名 -> 宣言済みフィールド型 (継承分を含む)
+    parents: HashMap<String, String>,                  // クラス名 -> 親クラス名
+    class_fields: HashMap<String, Vec<String>>,        // クラス名 -> フィールド名 (定義順)
+    methods: HashMap<String, Vec<(String, TypeInfo)>>, // クラス名 -> メソッドの型 (継承分を含む)
+    interfaces: HashMap<String, Vec<(String, TypeInfo)>>, // インターフェース名 -> 要求するメソッドの型
+    implements: HashMap<String, Vec<String>>,          // クラス名 -> 実装するインターフェース
+    undefined: Vec<String>,                            // 未定義として報告した名前
+    unresolved: HashSet<String>,                       // 名前解決で定義が見つからなかった名前
+    returns: Vec<Vec<TypeInfo>>,                       // チェック中の関数ごとの return の型
+    inferred_returns: HashMap<String, TypeInfo>,       // 戻り値型の注釈がない関数の推論結果
+}
+
+impl Default for TypeChecker {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TypeChecker {
@@ -154,11 +464,112 @@ impl TypeChecker {
         Self {
             env: TypeEnv::new(),
             errors: Vec::new(),
+            warnings: Vec::new(),
+            rules: ScopeRules::default(),
+            structs: HashMap::new(),
+            classes: HashMap::new(),
+            parents: HashMap::new(),
+            class_fields: HashMap::new(),
+            methods: HashMap::new(),
+            interfaces: HashMap::new(),
+            implements: HashMap::new(),
+            undefined: Vec::new(),
+            unresolved: HashSet::new(),
+            returns: Vec::new(),
+            inferred_returns: HashMap::new(),
+        }
+    }
+
+    /// 代入と名前の隠し方の規則 (n7tya.toml の [scope])
+    pub fn set_scope_rules(&mut self, rules: ScopeRules) {
+        self.rules = rules;
+    }
+
+    /// 型エラーにはしない警告 (check で見つけたもの)
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// 未定義として報告した変数名 (報告順、重複なし)
+    pub fn undefined_names(&self) -> &[String] {
+        &self.undefined
+    }
+
+    /// 名前解決で定義が見つからず、組み込みや定義済みのグローバルでもない名前か
+    /// (関数の中の同名の束縛があっても、その外の参照は未定義として扱う)
+    pub fn is_unresolved(&self, name: &str) -> bool {
+        self.unresolved.contains(name) || self.undefined.iter().any(|n| n == name)
+    }
+
+    /// content/ のコレクションを `content.posts` (構造体 `content.posts` のリスト) として使えるようにする
+    pub fn set_content(&mut self, content: &crate::content::Collections) {
+        let module = crate::content::CONTENT_DIR;
+        self.env
+            .define(module, TypeInfo::Module(module.to_string()));
+        for (name, collection) in content.iter() {
+            let struct_name = format!("{}.{}", module, name);
+            let ty = TypeInfo::List(Box::new(TypeInfo::Class(struct_name.clone())));
+            self.env.define(&struct_name, ty);
+            self.structs.insert(struct_name, collection.fields.clone());
         }
     }
 
+    /// 埋め込む側が定義したトップレベルの変数 (Engine::set)
+    pub fn define_global(&mut self, name: &str, ty: TypeInfo) {
+        self.env.define(name, ty);
+    }
+
+    /// プログラム中で一度でも定義された名前 (組み込みを含む)
+    pub fn defined_names(&self) -> impl Iterator<Item = &String> {
+        self.env.defined.iter().chain(self.env.builtins.keys())
+    }
+
+    /// 戻り値型の注釈がなく、全ての return が同じ具体的な型を返す関数
+    pub fn inferred_return_types(&self) -> &HashMap<String, TypeInfo> {
+        &self.inferred_returns
+    }
+
     pub fn check(&mut self, program: &Program) -> Result<Vec<String>> {
-        for item in &program.items {
+        // 未定義かどうかはインタプリタと同じスコープで名前解決した結果で決める
+        // (if の中で定義した変数はその後でも使える)。前の eval や Engine::set で定義した名前は除く
+        let table = crate::resolve::resolve(program);
+        self.errors.extend(table.errors.iter().cloned());
+        self.unresolved.clear();
+        for reference in table.undefined() {
+            if self.env.lookup(&reference.name).is_some() {
+                continue;
+            }
+            match reference.kind {
+                ReferenceKind::Nonlocal => self.errors.push(format!(
+                    "No binding for nonlocal '{}' in an enclosing scope",
+                    reference.name
+                )),
+                // let を書かない代入は、許していれば変数を作る (その後の参照は定義済みとして扱う)
+                ReferenceKind::Write if self.rules.implicit_declarations => {}
+                ReferenceKind::Write => {
+                    self.errors.push(format!(
+                        "Cannot assign to undefined variable '{0}' (declare it with let {0} = ...)",
+                        reference.name
+                    ));
+                    if !self.undefined.contains(&reference.name) {
+                        self.undefined.push(reference.name.clone());
+                    }
+                }
+                _ => {
+                    self.unresolved.insert(reference.name.clone());
+                }
+            }
+        }
+        if self.rules.warn_shadowing {
+            self.warnings.extend(table.warnings.iter().cloned());
+        }
+
+        // import はほかのトップレベルの文より先に実行されるので、先に名前を定義しておく
+        let (imports, rest): (Vec<&Item>, Vec<&Item>) = program
+            .items
+            .iter()
+            .partition(|item| matches!(item, Item::Import(_)));
+        for item in imports.into_iter().chain(rest) {
             self.check_item(item);
         }
         Ok(self.errors.clone())
@@ -168,8 +579,13 @@ impl TypeChecker {
         match item {
             Item::FunctionDef(f) => self.check_function_def(f),
             Item::ClassDef(c) => self.check_class_def(c),
+            Item::StructDef(s) => self.check_struct_def(s),
+            Item::InterfaceDef(i) => self.check_interface_def(i),
             Item::ComponentDef(c) => self.check_component_def(c),
             Item::ServerDef(s) => self.check_server_def(s),
+            Item::Import(imp) if Self::is_stdlib_module(&imp.module) => {
+                self.check_stdlib_import(imp)
+            }
             Item::Import(imp) => {
                 if let Some(alias) = &imp.alias {
                     self.env.define(alias, TypeInfo::Unknown);
@@ -191,6 +607,55 @@ impl TypeChecker {
         }
     }
 
+    fn is_stdlib_module(name: &str) -> bool {
+        crate::builtins::STDLIB_MODULES
+            .iter()
+            .any(|(module, _)| *module == name)
+    }
+
+    /// import http as web / from json import parse (関数の型は json.parse などで引く)
+    fn check_stdlib_import(&mut self, imp: &ImportStmt) {
+        let functions = crate::builtins::STDLIB_MODULES
+            .iter()
+            .find(|(module, _)| *module == imp.module)
+            .map_or(&[][..], |(_, functions)| *functions);
+
+        if let Some(alias) = &imp.alias {
+            self.env.define(alias, TypeInfo::Module(imp.module.clone()));
+        }
+        for name in &imp.names {
+            if functions.contains(&name.as_str()) {
+                let ty = self
+                    .env
+                    .lookup(&format!("{}.{}", imp.module, name))
+                    .unwrap_or(TypeInfo::Unknown);
+                self.env.define(name, ty);
+            } else {
+                self.errors
+                    .push(format!("'{}' not found in module '{}'", name, imp.module));
+                self.env.define(name, TypeInfo::Error);
+            }
+        }
+    }
+
+    /// モジュールの関数 (fs.read_file / import http as web の web.get) の型
+    fn module_member(&mut self, m: &MemberExpr) -> Option<TypeInfo> {
+        let Expression::Identifier(name) = &m.object else {
+            return None;
+        };
+        let Some(TypeInfo::Module(module)) = self.env.lookup(name) else {
+            return None;
+        };
+        match self.env.lookup(&format!("{}.{}", module, m.member)) {
+            Some(ty) => Some(ty),
+            None => {
+                self.errors
+                    .push(format!("Module '{}' has no member '{}'", module, m.member));
+                Some(TypeInfo::Error)
+            }
+        }
+    }
+
     fn check_function_def(&mut self, f: &FunctionDef) {
         // 関数の型を環境に登録
         let param_types: Vec<TypeInfo> = f
@@ -216,9 +681,20 @@ impl TypeChecker {
             self.env.define(&param.name, ty.clone());
         }
 
+        self.returns.push(Vec::new());
         for stmt in &f.body {
             self.check_statement(stmt);
         }
+        let returns = self.returns.pop().unwrap_or_default();
+
+        // 最後の文が return で、全ての return が同じ具体的な型なら戻り値型を推論できる
+        if f.return_type.is_none() && matches!(f.body.last(), Some(Statement::Return(_))) {
+            if let Some(first) = returns.first() {
+                if first.is_concrete() && returns.iter().all(|t| t == first) {
+                    self.inferred_returns.insert(f.name.clone(), first.clone());
+                }
+            }
+        }
 
         self.env.pop_scope();
     }
@@ -229,32 +705,216 @@ impl TypeChecker {
         self.env.push_scope();
         self.env.define("self", TypeInfo::Class(c.name.clone()));
 
+        // 親クラスのフィールドを引き継ぐ
+        let mut fields = Vec::new();
+        let mut methods = Vec::new();
+        if let Some(parent) = &c.parent {
+            match self.classes.get(parent) {
+                Some(parent_fields) => {
+                    fields = parent_fields.clone();
+                    methods = self.methods.get(parent).cloned().unwrap_or_default();
+                    self.parents.insert(c.name.clone(), parent.clone());
+                }
+                None => self.errors.push(format!(
+                    "Unknown parent class '{}' for class '{}'",
+                    parent, c.name
+                )),
+            }
+            self.env.define("super", TypeInfo::Unknown);
+        }
+
+        // メソッド内の self.field や ClassName.CONST を解決できるよう、先にフィールド型を登録する
+        let mut field_names = c
+            .parent
+            .as_ref()
+            .and_then(|parent| self.class_fields.get(parent).cloned())
+            .unwrap_or_default();
+        for item in &c.body {
+            let (name, ty) = match item {
+                ClassBodyItem::Field(f) => {
+                    field_names.retain(|field| field != &f.name);
+                    field_names.push(f.name.clone());
+                    (&f.name, self.ast_type_to_type_info(Some(&f.type_annotation)))
+                }
+                ClassBodyItem::Const(decl) => (&decl.name, self.check_class_const(c, decl)),
+                _ => continue,
+            };
+            fields.retain(|(field, _)| field != name);
+            fields.push((name.clone(), ty));
+        }
+        self.classes.insert(c.name.clone(), fields);
+        self.class_fields.insert(c.name.clone(), field_names);
+
+        for item in &c.body {
+            if let ClassBodyItem::Method(m) = item {
+                methods.retain(|(name, _)| name != &m.name);
+                methods.push((m.name.clone(), self.signature_type(&m.params, &m.return_type)));
+            }
+        }
+        self.methods.insert(c.name.clone(), methods);
+        self.check_implements(c);
+
         for item in &c.body {
             match item {
                 ClassBodyItem::Field(f) => {
                     let ty = self.ast_type_to_type_info(Some(&f.type_annotation));
+                    if let Some(default) = &f.default {
+                        let default_ty = self.infer_expression(default);
+                        if !self.types_compatible(&ty, &default_ty) {
+                            self.errors.push(format!(
+                                "Type mismatch for default of field '{}' in {}: expected {}, got {}",
+                                f.name, c.name, ty, default_ty
+                            ));
+                        }
+                    }
                     self.env.define(&f.name, ty);
                 }
-                ClassBodyItem::Method(m) => {
+                ClassBodyItem::Method(m) | ClassBodyItem::StaticMethod(m) => {
                     self.check_function_def(m);
                 }
+                ClassBodyItem::Const(_) | ClassBodyItem::Trivia(_) => {}
             }
         }
 
         self.env.pop_scope();
     }
 
+    /// implements で宣言したインターフェースのメソッドが揃っているか検査する
+    fn check_implements(&mut self, c: &ClassDef) {
+        for interface in &c.interfaces {
+            let Some(required) = self.interfaces.get(interface).cloned() else {
+                self.errors.push(format!(
+                    "Unknown interface '{}' for class '{}'",
+                    interface, c.name
+                ));
+                continue;
+            };
+            for (name, expected) in &required {
+                let actual = self.methods[&c.name]
+                    .iter()
+                    .find(|(method, _)| method == name)
+                    .map(|(_, ty)| ty.clone());
+                match actual {
+                    None => self.errors.push(format!(
+                        "Class '{}' does not implement '{}' required by interface '{}'",
+                        c.name, name, interface
+                    )),
+                    Some(actual) if !self.signatures_conform(expected, &actual) => {
+                        self.errors.push(format!(
+                            "Method '{}' of class '{}' does not match interface '{}': expected {}, got {}",
+                            name, c.name, interface, expected, actual
+                        ))
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        self.implements.insert(c.name.clone(), c.interfaces.clone());
+    }
+
+    /// 実装側のメソッドがインターフェースの宣言どおりに呼べるか
+    /// (型注釈のない引数・戻り値はどの型とも一致するとみなす)
+    fn signatures_conform(&self, expected: &TypeInfo, actual: &TypeInfo) -> bool {
+        let (
+            TypeInfo::Fn { params, ret },
+            TypeInfo::Fn {
+                params: actual_params,
+                ret: actual_ret,
+            },
+        ) = (expected, actual)
+        else {
+            return false;
+        };
+        params.len() == actual_params.len()
+            && params
+                .iter()
+                .zip(actual_params)
+                .all(|(p, a)| self.types_compatible(a, p))
+            && self.types_compatible(ret, actual_ret)
+    }
+
+    fn check_interface_def(&mut self, i: &InterfaceDef) {
+        let mut methods: Vec<(String, TypeInfo)> = Vec::new();
+        for m in &i.methods {
+            if methods.iter().any(|(name, _)| name == &m.name) {
+                self.errors.push(format!(
+                    "Duplicate method '{}' in interface {}",
+                    m.name, i.name
+                ));
+                continue;
+            }
+            methods.push((m.name.clone(), self.signature_type(&m.params, &m.return_type)));
+        }
+        self.interfaces.insert(i.name.clone(), methods);
+    }
+
+    fn signature_type(&self, params: &[Param], return_type: &Option<Type>) -> TypeInfo {
+        TypeInfo::Fn {
+            params: params
+                .iter()
+                .map(|p| self.ast_type_to_type_info(p.type_annotation.as_ref()))
+                .collect(),
+            ret: Box::new(self.ast_type_to_type_info(return_type.as_ref())),
+        }
+    }
+
+    /// クラス定数の値を検査して型を返す
+    fn check_class_const(&mut self, c: &ClassDef, decl: &ConstDecl) -> TypeInfo {
+        let value_ty = self.infer_expression(&decl.value);
+        let Some(annotation) = &decl.type_annotation else {
+            return value_ty;
+        };
+        let ty = self.ast_type_to_type_info(Some(annotation));
+        if !self.types_compatible(&ty, &value_ty) {
+            self.errors.push(format!(
+                "Type mismatch for constant '{}' in {}: expected {}, got {}",
+                decl.name, c.name, ty, value_ty
+            ));
+        }
+        ty
+    }
+
+    fn check_struct_def(&mut self, s: &StructDef) {
+        let mut fields: Vec<(String, TypeInfo)> = Vec::new();
+        for f in &s.fields {
+            if fields.iter().any(|(name, _)| name == &f.name) {
+                self.errors.push(format!(
+                    "Duplicate field '{}' in struct {}",
+                    f.name, s.name
+                ));
+                continue;
+            }
+            let ty = self.ast_type_to_type_info(Some(&f.type_annotation));
+            fields.push((f.name.clone(), ty));
+        }
+        self.structs.insert(s.name.clone(), fields);
+        // 構造体名は bind={User} / decode_form(User, ...) に値として渡せる
+        self.env.define(&s.name, TypeInfo::Unknown);
+    }
+
     fn check_component_def(&mut self, c: &ComponentDef) {
         self.env.define(&c.name, TypeInfo::Class(c.name.clone()));
 
         self.env.push_scope();
         self.env.define("self", TypeInfo::Class(c.name.clone()));
+        // island のメソッドは api.get_users() でサーバーのルートを呼べる
+        // (ルートの有無と引数の数は build でクライアント JS を作るときに確かめる)
+        if c.island {
+            self.env.define("api", TypeInfo::Unknown);
+        }
 
         for item in &c.body {
             match item {
                 ComponentBodyItem::State(s) => {
                     let ty = self.infer_expression(&s.value);
                     self.env.define(&s.name, ty);
+                    // localStorage はクライアント JS からしか使えない
+                    if s.persist == Some(Persist::Local) && !c.island {
+                        self.errors.push(format!(
+                            "State '{}' uses local persistence but {} is not an island component",
+                            s.name, c.name
+                        ));
+                    }
                 }
                 ComponentBodyItem::Method(m) => {
                     self.check_function_def(m);
@@ -264,6 +924,7 @@ impl TypeChecker {
                         self.check_statement(stmt);
                     }
                 }
+                ComponentBodyItem::Trivia(_) => {}
             }
         }
 
@@ -274,13 +935,56 @@ impl TypeChecker {
         self.env.define(&s.name, TypeInfo::Class(s.name.clone()));
 
         self.env.push_scope();
+        // ルートの本体には request が渡される
+        self.env.define("request", TypeInfo::Unknown);
 
         for item in &s.body {
             match item {
-                ServerBodyItem::Route(r) => {
-                    for stmt in &r.body {
-                        self.check_statement(stmt);
-                    }
+                ServerBodyItem::Route(r) => self.check_route(r),
+                ServerBodyItem::Trivia(_) => {}
+            }
+        }
+
+        self.env.pop_scope();
+    }
+
+    /// ルート本体をチェックし、型付きのボディと戻り値を検証する
+    fn check_route(&mut self, r: &RouteDef) {
+        let route = format!("{} {}", r.method.to_ascii_uppercase(), r.path);
+        self.env.push_scope();
+
+        if let Some(param) = &r.body_param {
+            // ボディは JSON オブジェクトから作るので、クラスではなく構造体に限る
+            if let Some(Type::Custom(name)) = &param.type_annotation {
+                if !self.structs.contains_key(name) {
+                    self.errors.push(format!(
+                        "Request body of route '{}' must be a struct, got '{}'",
+                        route, name
+                    ));
+                }
+            }
+            let ty = self.ast_type_to_type_info(param.type_annotation.as_ref());
+            self.env.define(&param.name, ty);
+        }
+
+        self.returns.push(Vec::new());
+        for stmt in &r.body {
+            self.check_statement(stmt);
+        }
+        let returns = self.returns.pop().unwrap_or_default();
+
+        if let Some(ret) = &r.return_type {
+            let expected = self.ast_type_to_type_info(Some(ret));
+            if returns.is_empty() {
+                self.errors
+                    .push(format!("Route '{}' must return {}", route, ret));
+            }
+            for actual in &returns {
+                if !self.types_compatible(&expected, actual) {
+                    self.errors.push(format!(
+                        "Type mismatch in return of route '{}': expected {}, got {}",
+                        route, expected, actual
+                    ));
                 }
             }
         }
@@ -291,54 +995,94 @@ impl TypeChecker {
     fn check_statement(&mut self, stmt: &Statement) {
         match stmt {
             Statement::Let(decl) => {
-                let ty = self.infer_expression(&decl.value);
+                let ty = self.check_declaration(&decl.name, &decl.value, &decl.type_annotation);
                 self.env.define(&decl.name, ty);
             }
             Statement::Const(decl) => {
-                let ty = self.infer_expression(&decl.value);
+                let ty = self.check_declaration(&decl.name, &decl.value, &decl.type_annotation);
                 self.env.define(&decl.name, ty);
             }
             Statement::Assignment(a) => {
-                let target_ty = self.infer_expression(&a.target);
+                // 変数への代入は絞り込み前の宣言された型と比べる
+                let declared = match &a.target {
+                    Expression::Identifier(name) => self.env.lookup_declared(name),
+                    _ => None,
+                };
+                let target_ty = match &declared {
+                    Some(ty) => ty.clone(),
+                    None => self.infer_expression(&a.target),
+                };
                 let value_ty = self.infer_expression(&a.value);
                 if !self.types_compatible(&target_ty, &value_ty) {
                     self.errors.push(format!(
-                        "Type mismatch in assignment: expected {:?}, got {:?}",
+                        "Type mismatch in assignment: expected {}, got {}",
                         target_ty, value_ty
                     ));
                 }
+                // none になりうる値を代入したら、それまでの none チェックは無効
+                if let Expression::Identifier(name) = &a.target {
+                    if value_ty == TypeInfo::None || value_ty.is_optional() {
+                        self.env.forget_narrowing(name);
+                    }
+                    if self.rules.implicit_declarations && self.env.lookup(name).is_none() {
+                        self.env.define(name, value_ty);
+                    }
+                }
             }
+            Statement::FunctionDef(f) => self.check_function_def(f),
+            // 外側に変数があるかは check で名前解決したときに確かめている
+            Statement::Nonlocal(_) | Statement::Copy(_) => {}
             Statement::Return(expr) => {
-                if let Some(e) = expr {
-                    let _ = self.infer_expression(e);
+                let ty = match expr {
+                    Some(e) => self.infer_expression(e),
+                    None => TypeInfo::None,
+                };
+                if let Some(returns) = self.returns.last_mut() {
+                    returns.push(ty);
                 }
             }
             Statement::If(if_stmt) => {
                 let cond_ty = self.infer_expression(&if_stmt.condition);
                 if cond_ty != TypeInfo::Bool && cond_ty != TypeInfo::Unknown {
                     self.errors
-                        .push(format!("If condition must be Bool, got {:?}", cond_ty));
+                        .push(format!("If condition must be Bool, got {}", cond_ty));
                 }
+                let none_check = none_check(&if_stmt.condition);
                 self.env.push_scope();
+                for name in checked_not_none(&if_stmt.condition) {
+                    self.narrow_not_none(name);
+                }
                 for s in &if_stmt.then_block {
                     self.check_statement(s);
                 }
                 self.env.pop_scope();
                 if let Some(else_block) = &if_stmt.else_block {
                     self.env.push_scope();
+                    if let Some((name, false)) = none_check {
+                        self.narrow_not_none(name);
+                    }
                     for s in else_block {
                         self.check_statement(s);
                     }
                     self.env.pop_scope();
                 }
+                // if x == none で return などして抜けるなら、以降の x は none ではない
+                if let Some((name, false)) = none_check {
+                    if if_stmt.else_block.is_none() && block_exits(&if_stmt.then_block) {
+                        self.narrow_not_none(name);
+                    }
+                }
             }
             Statement::While(w) => {
                 let cond_ty = self.infer_expression(&w.condition);
                 if cond_ty != TypeInfo::Bool && cond_ty != TypeInfo::Unknown {
                     self.errors
-                        .push(format!("While condition must be Bool, got {:?}", cond_ty));
+                        .push(format!("While condition must be Bool, got {}", cond_ty));
                 }
                 self.env.push_scope();
+                for name in checked_not_none(&w.condition) {
+                    self.narrow_not_none(name);
+                }
                 for s in &w.body {
                     self.check_statement(s);
                 }
@@ -346,8 +1090,15 @@ impl TypeChecker {
             }
             Statement::For(f) => {
                 let iter_ty = self.infer_expression(&f.iterator);
+                let iter_ty = self.require_not_none(&f.iterator, iter_ty);
+                // リストは要素、文字列は1文字の文字列 (辞書と集合は要素の型を持たない)
                 let elem_ty = match iter_ty {
                     TypeInfo::List(inner) => *inner,
+                    TypeInfo::Str => TypeInfo::Str,
+                    TypeInfo::Int | TypeInfo::Float | TypeInfo::Bool => {
+                        self.errors.push(format!("Cannot iterate over {}", iter_ty));
+                        TypeInfo::Unknown
+                    }
                     _ => TypeInfo::Unknown,
                 };
                 self.env.push_scope();
@@ -358,16 +1109,17 @@ impl TypeChecker {
                 self.env.pop_scope();
             }
             Statement::Match(m) => {
-                let _ = self.infer_expression(&m.value);
+                let value_ty = self.infer_expression(&m.value);
                 for case in &m.cases {
                     self.env.push_scope();
+                    self.bind_pattern(&case.pattern, value_ty.clone());
                     for s in &case.body {
                         self.check_statement(s);
                     }
                     self.env.pop_scope();
                 }
             }
-            Statement::Break | Statement::Continue => {}
+            Statement::Break(_) | Statement::Continue(_) | Statement::Trivia(_) => {}
             Statement::Expression(e) => {
                 let _ = self.infer_expression(e);
             }
@@ -383,57 +1135,238 @@ impl TypeChecker {
         }
     }
 
+    /// let / const の値を検査し、変数の型を返す (型注釈があればその型)
+    fn check_declaration(
+        &mut self,
+        name: &str,
+        value: &Expression,
+        annotation: &Option<Type>,
+    ) -> TypeInfo {
+        let value_ty = self.infer_expression(value);
+        let Some(annotation) = annotation else {
+            return value_ty;
+        };
+        let ty = self.ast_type_to_type_info(Some(annotation));
+        if !self.types_compatible(&ty, &value_ty) {
+            self.errors.push(format!(
+                "Type mismatch in declaration of '{}': expected {}, got {}",
+                name, ty, value_ty
+            ));
+        }
+        ty
+    }
+
+    /// none になりうる値をそのまま使っていればエラーにし、none を除いた型を返す
+    fn require_not_none(&mut self, expr: &Expression, ty: TypeInfo) -> TypeInfo {
+        if !ty.is_optional() {
+            return ty;
+        }
+        self.errors.push(format!(
+            "'{}' may be none; check it against none before using it",
+            describe(expr)
+        ));
+        ty.without_none()
+    }
+
+    /// 現在のスコープの間、変数を none ではない型とみなす
+    fn narrow_not_none(&mut self, name: &str) {
+        if let Some(ty) = self.env.lookup(name).filter(TypeInfo::is_optional) {
+            self.env.narrow(name, ty.without_none());
+        }
+    }
+
+    /// パターン変数を現在のスコープに定義する
+    fn bind_pattern(&mut self, pattern: &Pattern, ty: TypeInfo) {
+        match pattern {
+            Pattern::Identifier(name) => self.env.define(name, ty),
+            Pattern::List(patterns) => {
+                let elem_ty = match &ty {
+                    TypeInfo::List(inner) => (**inner).clone(),
+                    _ => TypeInfo::Unknown,
+                };
+                for p in patterns {
+                    match p {
+                        Pattern::Rest(name) => {
+                            self.env.define(name, TypeInfo::List(Box::new(elem_ty.clone())))
+                        }
+                        _ => self.bind_pattern(p, elem_ty.clone()),
+                    }
+                }
+            }
+            Pattern::Dict(entries) => {
+                for (_, p) in entries {
+                    self.bind_pattern(p, TypeInfo::Unknown);
+                }
+            }
+            Pattern::Variant(_, inner) => self.bind_pattern(inner, TypeInfo::Unknown),
+            Pattern::Constructor(name, patterns) => {
+                let fields = self.pattern_fields(name);
+                if let Some(fields) = fields.as_ref().filter(|f| f.len() != patterns.len()) {
+                    self.errors.push(format!(
+                        "'{}' has {} fields, but the pattern has {}",
+                        name,
+                        fields.len(),
+                        patterns.len()
+                    ));
+                }
+                for (i, p) in patterns.iter().enumerate() {
+                    let ty = match fields.as_ref().and_then(|f| f.get(i)) {
+                        Some((_, ty)) => ty.clone(),
+                        None => TypeInfo::Unknown,
+                    };
+                    self.bind_pattern(p, ty);
+                }
+            }
+            Pattern::Fields(name, patterns) => {
+                let fields = self.pattern_fields(name);
+                for (field, p) in patterns {
+                    let ty = match fields.as_ref().map(|f| f.iter().find(|(f, _)| f == field)) {
+                        Some(Some((_, ty))) => ty.clone(),
+                        Some(None) => {
+                            let kind = if self.structs.contains_key(name) {
+                                "Struct"
+                            } else {
+                                "Class"
+                            };
+                            self.errors
+                                .push(format!("{} '{}' has no field '{}'", kind, name, field));
+                            TypeInfo::Unknown
+                        }
+                        None => TypeInfo::Unknown,
+                    };
+                    self.bind_pattern(p, ty);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Point(x, 0) / User {name: n} の型のフィールドと型 (定義順)。
+    /// 定義を知らない型なら None (import した型は調べず、未定義の名前ならエラー)
+    fn pattern_fields(&mut self, name: &str) -> Option<Vec<(String, TypeInfo)>> {
+        if let Some(fields) = self.structs.get(name) {
+            return Some(fields.clone());
+        }
+        if let (Some(names), Some(types)) = (self.class_fields.get(name), self.classes.get(name)) {
+            let fields = names
+                .iter()
+                .filter_map(|field| types.iter().find(|(f, _)| f == field).cloned())
+                .collect();
+            return Some(fields);
+        }
+        if self.env.lookup(name).is_none() {
+            self.errors
+                .push(format!("Unknown type in pattern: {}", name));
+        }
+        None
+    }
+
     fn infer_expression(&mut self, expr: &Expression) -> TypeInfo {
         match expr {
             Expression::Literal(lit) => self.infer_literal(lit),
             Expression::Identifier(name) => self.env.lookup(name).unwrap_or_else(|| {
+                // 定義はあるがブロックの外などで型を追えていない名前
+                if !self.unresolved.contains(name) {
+                    return TypeInfo::Unknown;
+                }
                 self.errors.push(format!("Undefined variable: {}", name));
+                if !self.undefined.contains(name) {
+                    self.undefined.push(name.clone());
+                }
                 TypeInfo::Error
             }),
             Expression::BinaryOp(bin) => {
                 let left = self.infer_expression(&bin.left);
+                // x != none and x > 0 の右辺では x は none ではない
+                let narrowed = matches!(bin.op, BinaryOp::And);
+                if narrowed {
+                    self.env.push_scope();
+                    for name in checked_not_none(&bin.left) {
+                        self.narrow_not_none(name);
+                    }
+                }
                 let right = self.infer_expression(&bin.right);
+                if narrowed {
+                    self.env.pop_scope();
+                }
+                let (left, right) = if matches!(
+                    bin.op,
+                    BinaryOp::Eq | BinaryOp::Ne | BinaryOp::And | BinaryOp::Or | BinaryOp::In
+                ) {
+                    (left, right)
+                } else {
+                    (
+                        self.require_not_none(&bin.left, left),
+                        self.require_not_none(&bin.right, right),
+                    )
+                };
                 self.infer_binary_op(&bin.op, &left, &right)
             }
             Expression::UnaryOp(unary) => {
                 let operand = self.infer_expression(&unary.operand);
                 match unary.op {
-                    UnaryOp::Neg => operand,
+                    UnaryOp::Neg => self.require_not_none(&unary.operand, operand),
                     UnaryOp::Not => TypeInfo::Bool,
                 }
             }
             Expression::Call(call) => {
+                // 引数の中の未定義変数なども報告する
+                for arg in &call.args {
+                    self.infer_expression(arg);
+                }
+
                 // モジュール関数チェック (fs.read_file など)
                 if let Expression::MemberAccess(m) = &call.func {
-                    if let Expression::Identifier(module_name) = &m.object {
-                        let full_name = format!("{}.{}", module_name, m.member);
-                        if let Some(ty) = self.env.lookup(&full_name) {
-                            return match ty {
-                                TypeInfo::Fn { ret, .. } => *ret,
-                                _ => TypeInfo::Unknown,
-                            };
-                        }
+                    if let Some(ty) = self.module_member(m) {
+                        return match ty {
+                            TypeInfo::Fn { ret, .. } => *ret,
+                            _ => TypeInfo::Unknown,
+                        };
                     }
                 }
                 
                 let func_ty = self.infer_expression(&call.func);
+                let func_ty = self.require_not_none(&call.func, func_ty);
                 match func_ty {
                     TypeInfo::Fn { ret, .. } => *ret,
                     TypeInfo::Class(name) => TypeInfo::Class(name),
                     TypeInfo::Unknown => TypeInfo::Unknown,
                     _ => {
                         self.errors
-                            .push(format!("Attempt to call non-function: {:?}", func_ty));
+                            .push(format!("Attempt to call non-function: {}", func_ty));
                         TypeInfo::Error
                     }
                 }
             }
             Expression::MemberAccess(m) => {
-                let _ = self.infer_expression(&m.object);
+                if let Some(ty) = self.module_member(m) {
+                    return ty;
+                }
+                let obj_ty = self.infer_expression(&m.object);
+                let obj_ty = self.require_not_none(&m.object, obj_ty);
+                if let TypeInfo::Class(name) = &obj_ty {
+                    if let Some(fields) = self.structs.get(name) {
+                        if let Some((_, ty)) = fields.iter().find(|(f, _)| f == &m.member) {
+                            return ty.clone();
+                        }
+                        self.errors
+                            .push(format!("Struct '{}' has no field '{}'", name, m.member));
+                        return TypeInfo::Error;
+                    }
+                    // クラスはメソッドや動的に追加された属性もあるので、未知の名前はエラーにしない
+                    if let Some((_, ty)) = self
+                        .classes
+                        .get(name)
+                        .and_then(|fields| fields.iter().find(|(f, _)| f == &m.member))
+                    {
+                        return ty.clone();
+                    }
+                }
                 TypeInfo::Unknown
             }
             Expression::Index(idx) => {
                 let obj_ty = self.infer_expression(&idx.object);
+                let obj_ty = self.require_not_none(&idx.object, obj_ty);
                 let _ = self.infer_expression(&idx.index);
                 match obj_ty {
                     TypeInfo::List(inner) => *inner,
@@ -442,14 +1375,59 @@ impl TypeChecker {
             }
             Expression::Lambda(_) => TypeInfo::Unknown,
             Expression::Await(inner) => self.infer_expression(inner),
+            // Int? に ? を付けると none の場合は関数から返るので Int になる
+            Expression::Try(inner) => self.infer_expression(inner).without_none(),
             Expression::JsxElement(_) => TypeInfo::Unknown,
+            Expression::StructLiteral(lit) => self.infer_struct_literal(lit),
+        }
+    }
+
+    fn infer_struct_literal(&mut self, lit: &StructLiteral) -> TypeInfo {
+        let Some(fields) = self.structs.get(&lit.name).cloned() else {
+            // from utils import Point のように他のモジュールから取り込んだ構造体は検査しない
+            if self.env.lookup(&lit.name).is_some() {
+                for (_, expr) in &lit.fields {
+                    self.infer_expression(expr);
+                }
+                return TypeInfo::Unknown;
+            }
+            self.errors.push(format!("Unknown struct: {}", lit.name));
+            return TypeInfo::Error;
+        };
+
+        for (name, expr) in &lit.fields {
+            let value_ty = self.infer_expression(expr);
+            match fields.iter().find(|(f, _)| f == name) {
+                Some((_, field_ty)) => {
+                    if !self.types_compatible(field_ty, &value_ty) {
+                        self.errors.push(format!(
+                            "Type mismatch for field '{}' of {}: expected {}, got {}",
+                            name, lit.name, field_ty, value_ty
+                        ));
+                    }
+                }
+                None => self
+                    .errors
+                    .push(format!("Struct '{}' has no field '{}'", lit.name, name)),
+            }
         }
+
+        for (name, _) in &fields {
+            if !lit.fields.iter().any(|(f, _)| f == name) {
+                self.errors
+                    .push(format!("Missing field '{}' in {}", name, lit.name));
+            }
+        }
+
+        TypeInfo::Class(lit.name.clone())
     }
 
     fn infer_literal(&self, lit: &Literal) -> TypeInfo {
         match lit {
             Literal::Int(_) => TypeInfo::Int,
             Literal::Float(_) => TypeInfo::Float,
+            Literal::Duration(_) => TypeInfo::Class("Duration".to_string()),
+            Literal::Size(_) => TypeInfo::Class("Size".to_string()),
             Literal::Str(_) => TypeInfo::Str,
             Literal::Bool(_) => TypeInfo::Bool,
             Literal::None => TypeInfo::None,
@@ -462,19 +1440,30 @@ impl TypeChecker {
     fn infer_binary_op(&mut self, op: &BinaryOp, left: &TypeInfo, right: &TypeInfo) -> TypeInfo {
         match op {
             BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => {
-                if *left == TypeInfo::Str && *right == TypeInfo::Str && matches!(op, BinaryOp::Add)
-                {
-                    return TypeInfo::Str;
-                }
-                if (*left == TypeInfo::Int || *left == TypeInfo::Unknown)
-                    && (*right == TypeInfo::Int || *right == TypeInfo::Unknown)
-                {
-                    return TypeInfo::Int;
+                // 合併型はどの型だったとしても計算できる必要がある
+                let members = |ty: &TypeInfo| match ty {
+                    TypeInfo::Union(members) => members.clone(),
+                    other => vec![other.clone()],
+                };
+                let mut results = Vec::new();
+                for l in members(left) {
+                    for r in members(right) {
+                        match arithmetic_type(op, &l, &r) {
+                            Some(ty) => results.push(ty),
+                            None => {
+                                self.errors.push(format!(
+                                    "Unsupported operand types for {}: {} and {}",
+                                    op, left, right
+                                ));
+                                return TypeInfo::Error;
+                            }
+                        }
+                    }
                 }
-                if *left == TypeInfo::Float || *right == TypeInfo::Float {
-                    return TypeInfo::Float;
+                if results.contains(&TypeInfo::Unknown) {
+                    return TypeInfo::Unknown;
                 }
-                TypeInfo::Unknown
+                TypeInfo::union(results)
             }
             BinaryOp::Eq
             | BinaryOp::Ne
@@ -491,9 +1480,40 @@ impl TypeChecker {
         if *expected == TypeInfo::Unknown || *actual == TypeInfo::Unknown {
             return true;
         }
+        // 合併型の値は、どの型だったとしても受け入れられる必要がある
+        if let TypeInfo::Union(members) = actual {
+            return members.iter().all(|m| self.types_compatible(expected, m));
+        }
+        if let TypeInfo::Union(members) = expected {
+            return members.iter().any(|m| self.types_compatible(m, actual));
+        }
+        if let (TypeInfo::List(expected), TypeInfo::List(actual)) = (expected, actual) {
+            return self.types_compatible(expected, actual);
+        }
+        // サブクラスのインスタンスは親クラスとして扱える
+        if let (TypeInfo::Class(expected), TypeInfo::Class(actual)) = (expected, actual) {
+            return self.is_subclass(actual, expected);
+        }
         expected == actual
     }
 
+    /// class が ancestor 自身かその子孫か (ancestor が祖先の実装するインターフェースでもよい)
+    fn is_subclass(&self, class: &str, ancestor: &str) -> bool {
+        let mut current = Some(class);
+        while let Some(name) = current {
+            if name == ancestor
+                || self
+                    .implements
+                    .get(name)
+                    .is_some_and(|interfaces| interfaces.iter().any(|i| i == ancestor))
+            {
+                return true;
+            }
+            current = self.parents.get(name).map(String::as_str);
+        }
+        false
+    }
+
     fn ast_type_to_type_info(&self, ty: Option<&Type>) -> TypeInfo {
         match ty {
             Some(Type::Int) => TypeInfo::Int,
@@ -507,7 +1527,425 @@ impl TypeChecker {
             Some(Type::Set(_)) => TypeInfo::Unknown,
             Some(Type::Fn(_, _)) => TypeInfo::Unknown,
             Some(Type::Custom(name)) => TypeInfo::Class(name.clone()),
+            Some(Type::None) => TypeInfo::None,
+            Some(Type::Optional(inner)) => {
+                TypeInfo::union(vec![self.ast_type_to_type_info(Some(inner)), TypeInfo::None])
+            }
+            // ハンドラーが返すのは中身の型の値
+            Some(Type::Json(inner)) => self.ast_type_to_type_info(Some(inner)),
+            Some(Type::Union(members)) => TypeInfo::union(
+                members
+                    .iter()
+                    .map(|m| self.ast_type_to_type_info(Some(m)))
+                    .collect(),
+            ),
             None => TypeInfo::Unknown,
         }
     }
 }
+
+/// 単位つきリテラルの型 (Duration / Size)
+/// 算術演算の結果の型 (計算できない組み合わせなら None、分からなければ Unknown)
+fn arithmetic_type(op: &BinaryOp, left: &TypeInfo, right: &TypeInfo) -> Option<TypeInfo> {
+    if *left == TypeInfo::Str && *right == TypeInfo::Str && matches!(op, BinaryOp::Add) {
+        return Some(TypeInfo::Str);
+    }
+    match (op, left, right) {
+        // "-" * 40 / [0] * n / list_a + list_b
+        (BinaryOp::Mul, TypeInfo::Str, TypeInfo::Int)
+        | (BinaryOp::Mul, TypeInfo::Int, TypeInfo::Str) => return Some(TypeInfo::Str),
+        (BinaryOp::Mul, TypeInfo::List(_), TypeInfo::Int) => return Some(left.clone()),
+        (BinaryOp::Mul, TypeInfo::Int, TypeInfo::List(_)) => return Some(right.clone()),
+        (BinaryOp::Add, TypeInfo::List(a), TypeInfo::List(b)) => {
+            let item = if **a == TypeInfo::Unknown { b } else { a };
+            return Some(TypeInfo::List(item.clone()));
+        }
+        // Decimal と Int の計算は Decimal
+        (_, TypeInfo::Class(d), TypeInfo::Int | TypeInfo::Class(_))
+        | (_, TypeInfo::Int, TypeInfo::Class(d))
+            if d == "Decimal" =>
+        {
+            return Some(TypeInfo::Class(d.clone()))
+        }
+        // 1m / 30s は比の Float、5s + 500ms や 10mb * 2 は同じ種類
+        (BinaryOp::Div, TypeInfo::Class(a), TypeInfo::Class(b)) if is_unit(a) && a == b => {
+            return Some(TypeInfo::Float)
+        }
+        (_, TypeInfo::Class(unit), _) | (BinaryOp::Mul, _, TypeInfo::Class(unit))
+            if is_unit(unit) =>
+        {
+            return Some(TypeInfo::Class(unit.clone()))
+        }
+        _ => {}
+    }
+    let number = |ty: &TypeInfo| matches!(ty, TypeInfo::Int | TypeInfo::Float);
+    if number(left) && number(right) {
+        if *left == TypeInfo::Int && *right == TypeInfo::Int {
+            return Some(TypeInfo::Int);
+        }
+        return Some(TypeInfo::Float);
+    }
+    // 組み込みの値どうしで上のどれにも当てはまらなければ実行時にも計算できない
+    let builtin = |ty: &TypeInfo| {
+        matches!(
+            ty,
+            TypeInfo::Int
+                | TypeInfo::Float
+                | TypeInfo::Bool
+                | TypeInfo::Str
+                | TypeInfo::None
+                | TypeInfo::List(_)
+        )
+    };
+    if builtin(left) && builtin(right) {
+        return None;
+    }
+    // 片方が分からなければ Decimal などかもしれないので決めない
+    Some(TypeInfo::Unknown)
+}
+
+fn is_unit(name: &str) -> bool {
+    matches!(name, "Duration" | "Size")
+}
+
+/// `x != none` / `x == none` の形なら (変数名, != かどうか) を返す
+fn none_check(condition: &Expression) -> Option<(&str, bool)> {
+    let Expression::BinaryOp(bin) = condition else {
+        return None;
+    };
+    let not_none = match bin.op {
+        BinaryOp::Ne => true,
+        BinaryOp::Eq => false,
+        _ => return None,
+    };
+    match (&bin.left, &bin.right) {
+        (Expression::Identifier(name), Expression::Literal(Literal::None))
+        | (Expression::Literal(Literal::None), Expression::Identifier(name)) => {
+            Some((name.as_str(), not_none))
+        }
+        _ => None,
+    }
+}
+
+/// 条件が真なら none ではないと分かる変数 (and でつないだ x != none)
+fn checked_not_none(condition: &Expression) -> Vec<&str> {
+    match condition {
+        Expression::BinaryOp(bin) if matches!(bin.op, BinaryOp::And) => {
+            let mut names = checked_not_none(&bin.left);
+            names.extend(checked_not_none(&bin.right));
+            names
+        }
+        _ => match none_check(condition) {
+            Some((name, true)) => vec![name],
+            _ => Vec::new(),
+        },
+    }
+}
+
+/// ブロックの最後の文が return / break / continue か
+fn block_exits(block: &[Statement]) -> bool {
+    matches!(
+        block.iter().rev().find(|s| !matches!(s, Statement::Trivia(_))),
+        Some(Statement::Return(_) | Statement::Break(_) | Statement::Continue(_))
+    )
+}
+
+/// エラーメッセージ用に式を短く表す
+fn describe(expr: &Expression) -> String {
+    match expr {
+        Expression::Identifier(name) => name.clone(),
+        Expression::MemberAccess(m) => format!("{}.{}", describe(&m.object), m.member),
+        Expression::Call(call) => format!("{}()", describe(&call.func)),
+        _ => "value".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn check(source: &str) -> Vec<String> {
+        let mut lexer = Lexer::new(source);
+        let tokens = lexer.tokenize();
+        let mut parser = Parser::new(tokens);
+        let program = parser.parse().unwrap();
+        TypeChecker::new().check(&program).unwrap()
+    }
+
+    #[test]
+    fn test_struct_field_types() {
+        let errors = check("struct Point\n\tx: Int\nlet p = Point { x: \"a\" }\nlet y = p.y\n");
+        assert_eq!(
+            errors,
+            vec![
+                "Type mismatch for field 'x' of Point: expected Int, got Str",
+                "Struct 'Point' has no field 'y'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_instance_pattern_fields() {
+        let source = "struct Point\n\tx: Int\n\ty: Int\nclass User\n\tname: Str\nlet p = Point { x: 1, y: 2 }\nmatch p\n\tcase Point(x, y)\n\t\tlet z: Str = x\n\tcase Point(x)\n\t\tprint(x)\n\tcase Point {z}\n\t\tprint(z)\n\tcase User {age: a}\n\t\tprint(a)\n\tcase Line(a, b)\n\t\tprint(a)\n";
+        assert_eq!(
+            check(source),
+            vec![
+                "Type mismatch in declaration of 'z': expected Str, got Int",
+                "'Point' has 2 fields, but the pattern has 1",
+                "Struct 'Point' has no field 'z'",
+                "Class 'User' has no field 'age'",
+                "Unknown type in pattern: Line",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_local_persist_needs_island() {
+        let source = "component Note\n\tstate local text = \"\"\n\trender\n\t\t<p>{text}</p>\nisland component Draft\n\tstate local text = \"\"\n\trender\n\t\t<p>{text}</p>\n";
+        assert_eq!(
+            check(source),
+            vec!["State 'text' uses local persistence but Note is not an island component"]
+        );
+    }
+
+    #[test]
+    fn test_class_field_types() {
+        let source = "class Counter\n\tcount: Int = \"zero\"\n\tdef reset\n\t\tself.count = \"none\"\n";
+        assert_eq!(
+            check(source),
+            vec![
+                "Type mismatch for default of field 'count' in Counter: expected Int, got Str",
+                "Type mismatch in assignment: expected Int, got Str",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_class_inheritance() {
+        let source = "class Animal\n\tname: Str\nclass Dog Animal\n\tdef rename\n\t\tself.name = 1\nclass Home\n\tpet: Animal = Dog()\n\tdog: Dog = Animal()\n";
+        assert_eq!(
+            check(source),
+            vec![
+                "Type mismatch in assignment: expected Str, got Int",
+                "Type mismatch for default of field 'dog' in Home: expected Dog, got Animal",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_class_constants() {
+        let source = "class Config\n\tconst RETRIES: Int = \"three\"\n\tconst NAME = \"app\"\nlet n: Int = 0\nn = Config.NAME\n";
+        assert_eq!(
+            check(source),
+            vec![
+                "Type mismatch for constant 'RETRIES' in Config: expected Int, got Str",
+                "Type mismatch in assignment: expected Int, got Str",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_interfaces() {
+        let source = "interface Shape
+\tdef area -> Int
+\tdef scale factor: Int
+class Base implements Shape
+\tdef area -> Int
+\t\treturn 1
+\tdef scale factor
+\t\treturn factor
+class Square Base
+class Circle implements Shape, Named
+\tdef area -> Str
+\t\treturn \"big\"
+class Home
+\tshape: Shape = Square()
+\tother: Shape = Home()
+";
+        assert_eq!(
+            check(source),
+            vec![
+                "Method 'area' of class 'Circle' does not match interface 'Shape': expected Fn[] -> Int, got Fn[] -> Str",
+                "Class 'Circle' does not implement 'scale' required by interface 'Shape'",
+                "Unknown interface 'Named' for class 'Circle'",
+                "Type mismatch for default of field 'other' in Home: expected Shape, got Home",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_typed_routes() {
+        let source = "struct User
+\tname: Str
+\tage: Int
+class Session
+server App
+\tpost \"/users\" (user: User) -> Json<User>
+\t\treturn User { name: user.name, age: user.age + 1 }
+\tpost \"/bad\" (user: User) -> Json<User>
+\t\treturn user.name
+\tpost \"/session\" (session: Session) -> Str
+\t\treturn \"ok\"
+\tget \"/empty\" -> Json<User>
+\t\tprintln \"no return\"
+";
+        assert_eq!(
+            check(source),
+            vec![
+                "Type mismatch in return of route 'POST /bad': expected User, got Str",
+                "Request body of route 'POST /session' must be a struct, got 'Session'",
+                "Route 'GET /empty' must return Json<User>",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stdlib_module_imports() {
+        let source = "import http as web
+from json import parse, dump
+let body: Str = web.get(\"https://example.com\")
+let data = parse(body)
+let n: Int = json.stringify(data)
+fs.read(\"a.txt\")
+";
+        assert_eq!(
+            check(source),
+            vec![
+                "'dump' not found in module 'json'",
+                "Type mismatch in declaration of 'n': expected Int, got Str",
+                "Module 'fs' has no member 'read'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_optional_types() {
+        let source = "def find key: Str -> Int?
+\treturn none
+let a: Int? = find(\"a\")
+let b: Int | Str | none = 1
+let c: Int = none
+let n = a + 1
+if a != none
+\tn = a + 1
+if a != none and a > 0
+\tn = a
+def first xs: List<Int>? -> Int
+\tif xs == none
+\t\treturn 0
+\treturn xs[0]
+a = 2
+a = none
+n = -a
+";
+        assert_eq!(
+            check(source),
+            vec![
+                "Type mismatch in declaration of 'c': expected Int, got none",
+                "'a' may be none; check it against none before using it",
+                "'a' may be none; check it against none before using it",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_union_arithmetic() {
+        let source = "let a: Int | Str = 1
+let b: Int | Float = 2
+let c: Int? = none
+let d: Int | Float = b * 2
+let e = a + \"x\"
+let f = 1 + \"x\"
+let xs: List<Int> = [1]
+let g = xs - xs
+let h: Str = b + 1
+if c != none
+\tlet i: Str = c
+let j: Int | Str = a
+";
+        assert_eq!(
+            check(source),
+            vec![
+                "Unsupported operand types for +: Int | Str and Str",
+                "Unsupported operand types for +: Int and Str",
+                "Unsupported operand types for -: List<Int> and List<Int>",
+                "Type mismatch in declaration of 'h': expected Str, got Int | Float",
+                "Type mismatch in declaration of 'i': expected Str, got Int",
+            ]
+        );
+        assert_eq!(
+            check("let a: Int? = none\nlet b: Str = a\n"),
+            vec!["Type mismatch in declaration of 'b': expected Str, got Int?"]
+        );
+    }
+
+    #[test]
+    fn test_repetition_types() {
+        let source = "let line: Str = \"-\" * 40
+let zeros: List<Int> = [0] * 3
+let both: List<Int> = zeros + [1]
+let n: Int = \"ab\" * 2
+";
+        assert_eq!(
+            check(source),
+            vec!["Type mismatch in declaration of 'n': expected Int, got Str"]
+        );
+    }
+
+    #[test]
+    fn test_assignment_needs_declaration() {
+        let source = "totl = 1\nlet n: Int = totl + 1\ndef f\n\tcount = \"a\"\n\treturn count\n";
+        // 代入した後の参照は未定義として重ねて報告しない
+        assert_eq!(
+            check(source),
+            vec![
+                "Cannot assign to undefined variable 'totl' (declare it with let totl = ...)",
+                "Cannot assign to undefined variable 'count' (declare it with let count = ...)",
+            ]
+        );
+
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let mut checker = TypeChecker::new();
+        checker.set_scope_rules(ScopeRules {
+            implicit_declarations: true,
+            warn_shadowing: true,
+        });
+        assert!(checker.check(&program).unwrap().is_empty());
+        // 代入で作った変数の型は代入した値の型
+        let program = Parser::new(Lexer::new("x = 1\nlet s: Str = x\n").tokenize())
+            .parse()
+            .unwrap();
+        assert_eq!(
+            checker.check(&program).unwrap(),
+            vec!["Type mismatch in declaration of 's': expected Str, got Int"]
+        );
+    }
+
+    #[test]
+    fn test_shadowing_warnings() {
+        let source = "def counter
+\tlet count = 0
+\tdef inc
+\t\tlet count = count + 1
+\t\treturn count
+\treturn inc
+";
+        let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+        let mut checker = TypeChecker::new();
+        assert!(checker.check(&program).unwrap().is_empty());
+        assert_eq!(
+            checker.warnings(),
+            ["'count' in 'inc' shadows the one in 'counter' (use nonlocal count to assign to it)"]
+        );
+
+        let mut checker = TypeChecker::new();
+        checker.set_scope_rules(ScopeRules {
+            implicit_declarations: false,
+            warn_shadowing: false,
+        });
+        checker.check(&program).unwrap();
+        assert!(checker.warnings().is_empty());
+    }
+}
@@ -0,0 +1,286 @@
+//! 時間と大きさのリテラル (`5s` `200ms` `10mb`)
+//!
+//! 数字の直後に単位を書くと Duration / Size の値になる。同じ種類どうしで足し引きと比較ができ、
+//! 数値を掛けたり割ったりできる。cache の ttl や retry の間隔などの秒数を受け取る関数は Duration も受け取る。
+//! n7tya.toml の timeout や --timeout / --max-memory にも同じ書き方を使える。
+//! 大きさの単位は 1024 倍ずつ (1kb = 1024 バイト)。どちらも負の値にはならない。
+
+use crate::ast::BinaryOp;
+use crate::interpreter::Value;
+use std::cmp::Ordering;
+use std::time::Duration;
+
+/// 時間の単位と1単位のナノ秒 (表示では上から順に割り切れる単位を使う)
+const DURATION_UNITS: [(&str, u64); 5] = [
+    ("d", 86_400_000_000_000),
+    ("h", 3_600_000_000_000),
+    ("m", 60_000_000_000),
+    ("s", 1_000_000_000),
+    ("ms", 1_000_000),
+];
+
+/// 大きさの単位と1単位のバイト数
+const SIZE_UNITS: [(&str, u64); 5] = [
+    ("tb", 1 << 40),
+    ("gb", 1 << 30),
+    ("mb", 1 << 20),
+    ("kb", 1 << 10),
+    ("b", 1),
+];
+
+/// "1.5s" "200ms" "2h" (大きすぎる値は None)
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let nanos = parse_with_units(text, &DURATION_UNITS)?;
+    let secs = nanos / 1_000_000_000;
+    Some(Duration::new(secs, (nanos % 1_000_000_000) as u32))
+}
+
+/// "10mb" "1.5kb" "512b" をバイト数にする
+pub fn parse_size(text: &str) -> Option<u64> {
+    parse_with_units(text, &SIZE_UNITS)
+}
+
+/// 数字 (小数も可) と単位に分け、単位の値を掛ける (小数部は丸める)
+fn parse_with_units(text: &str, units: &[(&str, u64)]) -> Option<u64> {
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let (_, scale) = units.iter().find(|(name, _)| *name == unit)?;
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if whole.is_empty() || (number.contains('.') && fraction.is_empty()) {
+        return None;
+    }
+    let whole = whole.parse::<u64>().ok()?.checked_mul(*scale)?;
+    if fraction.is_empty() {
+        return Some(whole);
+    }
+    let fraction = format!("0.{}", fraction).parse::<f64>().ok()?;
+    whole.checked_add((fraction * *scale as f64).round() as u64)
+}
+
+/// 割り切れる最も大きい単位で表す (1.5s は "1500ms"、割り切れなければ秒の小数)
+pub fn format_duration(duration: Duration) -> String {
+    let nanos = duration.as_nanos();
+    match DURATION_UNITS
+        .iter()
+        .find(|(_, scale)| nanos.is_multiple_of(*scale as u128))
+    {
+        Some(_) if nanos == 0 => "0s".to_string(),
+        Some((unit, scale)) => format!("{}{}", nanos / *scale as u128, unit),
+        None => format!("{}s", duration.as_secs_f64()),
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    let (unit, scale) = SIZE_UNITS
+        .iter()
+        .find(|(_, scale)| bytes > 0 && bytes.is_multiple_of(*scale))
+        .unwrap_or(&("b", 1));
+    format!("{}{}", bytes / scale, unit)
+}
+
+/// Int / Float / Duration の秒数 (ttl や間隔を受け取る関数で使う)
+pub fn seconds(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(n) => Some(*n as f64),
+        Value::Float(f) => Some(*f),
+        Value::Duration(d) => Some(d.as_secs_f64()),
+        _ => None,
+    }
+}
+
+/// Duration / Size を含む算術演算と比較
+pub fn binary_op(op: &BinaryOp, left: &Value, right: &Value) -> Result<Value, String> {
+    // 数値は掛ける側と、右側で割る数にだけ使える
+    let scalable = |number: &Value| {
+        is_number(number)
+            && (matches!(op, BinaryOp::Mul) || matches!(op, BinaryOp::Div) && is_number(right))
+    };
+    match (left, right) {
+        (Value::Duration(a), Value::Duration(b)) => {
+            let (a, b) = (a.as_nanos(), b.as_nanos());
+            same_kind(op, a, b, "Duration", |n| Value::Duration(from_nanos(n)))
+        }
+        (Value::Size(a), Value::Size(b)) => same_kind(op, *a as u128, *b as u128, "Size", |n| {
+            Value::Size(n as u64)
+        }),
+        (Value::Duration(d), number) | (number, Value::Duration(d)) if scalable(number) => {
+            let nanos = scale(op, d.as_nanos(), number, "Duration")?;
+            Ok(Value::Duration(from_nanos(nanos)))
+        }
+        (Value::Size(bytes), number) | (number, Value::Size(bytes)) if scalable(number) => {
+            let bytes = scale(op, *bytes as u128, number, "Size")?;
+            Ok(Value::Size(bytes as u64))
+        }
+        _ => Err(format!(
+            "Unsupported operation: {} {} {}",
+            left.display(),
+            op,
+            right.display()
+        )),
+    }
+}
+
+/// 同じ種類どうしの演算 (足し算・引き算・余りは同じ種類、割り算は比の Float)
+fn same_kind(
+    op: &BinaryOp,
+    a: u128,
+    b: u128,
+    kind: &str,
+    make: impl Fn(u128) -> Value,
+) -> Result<Value, String> {
+    let limit = if kind == "Size" {
+        u64::MAX as u128
+    } else {
+        u64::MAX as u128 * 1_000_000_000
+    };
+    Ok(match op {
+        BinaryOp::Add => match a.checked_add(b).filter(|n| *n <= limit) {
+            Some(n) => make(n),
+            None => return Err(format!("{} overflow", kind)),
+        },
+        BinaryOp::Sub => match a.checked_sub(b) {
+            Some(n) => make(n),
+            None => return Err(format!("{} cannot be negative", kind)),
+        },
+        BinaryOp::Div | BinaryOp::Mod if b == 0 => {
+            let message = match op {
+                BinaryOp::Div => "Division by zero",
+                _ => "Modulo by zero",
+            };
+            return Err(message.to_string());
+        }
+        BinaryOp::Div => Value::Float(a as f64 / b as f64),
+        BinaryOp::Mod => make(a % b),
+        BinaryOp::Lt => Value::Bool(a < b),
+        BinaryOp::Gt => Value::Bool(a > b),
+        BinaryOp::Le => Value::Bool(a <= b),
+        BinaryOp::Ge => Value::Bool(a >= b),
+        _ => return Err(format!("Unsupported operation: {} {} {}", kind, op, kind)),
+    })
+}
+
+/// 数値を掛けるか数値で割る (結果は丸める)
+fn scale(op: &BinaryOp, amount: u128, number: &Value, kind: &str) -> Result<u128, String> {
+    let factor = seconds(number).unwrap_or(f64::NAN);
+    let result = match op {
+        BinaryOp::Div if factor == 0.0 => return Err("Division by zero".to_string()),
+        BinaryOp::Div => amount as f64 / factor,
+        _ => amount as f64 * factor,
+    };
+    let limit = match kind {
+        "Size" => u64::MAX as f64,
+        _ => u64::MAX as f64 * 1e9,
+    };
+    if result.is_nan() || result < 0.0 {
+        return Err(format!("{} cannot be negative", kind));
+    }
+    if result >= limit {
+        return Err(format!("{} overflow", kind));
+    }
+    Ok(result.round() as u128)
+}
+
+fn is_number(value: &Value) -> bool {
+    matches!(value, Value::Int(_) | Value::Float(_))
+}
+
+fn from_nanos(nanos: u128) -> Duration {
+    Duration::new(
+        (nanos / 1_000_000_000) as u64,
+        (nanos % 1_000_000_000) as u32,
+    )
+}
+
+/// 並べ替えや min / max での比較
+pub fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Duration(a), Value::Duration(b)) => Some(a.cmp(b)),
+        (Value::Size(a), Value::Size(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+/// d.seconds() / d.milliseconds() / s.bytes()
+pub fn method(value: &Value, method: &str, args: &[Value]) -> Result<Value, String> {
+    if !args.is_empty() {
+        return Err(format!("{}() takes no arguments", method));
+    }
+    match (value, method) {
+        (Value::Duration(d), "seconds") => Ok(Value::Float(d.as_secs_f64())),
+        (Value::Duration(d), "milliseconds") => {
+            Ok(Value::Int(i64::try_from(d.as_millis()).unwrap_or(i64::MAX)))
+        }
+        (Value::Size(bytes), "bytes") => Ok(Value::Int(i64::try_from(*bytes).unwrap_or(i64::MAX))),
+        _ => Err(format!(
+            "'{}' has no method '{}'",
+            value.type_name(),
+            method
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::run;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_duration("200ms"), Some(Duration::from_millis(200)));
+        assert_eq!(parse_duration("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("5"), None);
+        assert_eq!(parse_duration("5.s"), None);
+        assert_eq!(parse_size("10mb"), Some(10 * 1024 * 1024));
+        assert_eq!(parse_size("1.5kb"), Some(1536));
+        assert_eq!(parse_size("99999999999tb"), None);
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format_duration(Duration::from_secs(90)), "90s");
+        assert_eq!(format_duration(Duration::from_secs(5400)), "90m");
+        assert_eq!(format_duration(Duration::from_millis(1500)), "1500ms");
+        assert_eq!(format_duration(Duration::from_micros(1500)), "0.0015s");
+        assert_eq!(format_duration(Duration::ZERO), "0s");
+        assert_eq!(format_size(1536), "1536b");
+        assert_eq!(format_size(2048), "2kb");
+        assert_eq!(format_size(0), "0b");
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(
+            run("[1m + 30s, 2s - 500ms, 200ms * 3, 2 * 1h, 1s / 4, 1m / 30s, 1d > 23h, 5s == 5000ms]\n")
+                .unwrap(),
+            "[90s, 1500ms, 600ms, 2h, 250ms, 2, true, true]"
+        );
+        assert_eq!(
+            run("[1mb + 512kb, 1gb / 1mb, 10mb > 1gb, sorted([1gb, 1kb, 1mb]), 1.5s.seconds(), 2kb.bytes()]\n")
+                .unwrap(),
+            "[1536kb, 1024, false, [1kb, 1mb, 1gb], 1.5, 2048]"
+        );
+        assert_eq!(run("[type(5s), type(1kb)]\n").unwrap(), "[Duration, Size]");
+    }
+
+    #[test]
+    fn test_arithmetic_errors() {
+        assert_eq!(run("1s - 2s\n").unwrap_err(), "Duration cannot be negative");
+        assert_eq!(run("1s * -1\n").unwrap_err(), "Duration cannot be negative");
+        assert_eq!(run("1kb / 0\n").unwrap_err(), "Division by zero");
+        assert_eq!(
+            run("1s + 1\n").unwrap_err(),
+            "Unsupported operation: 1s + 1"
+        );
+        assert_eq!(
+            run("1s + 1kb\n").unwrap_err(),
+            "Unsupported operation: 1s + 1kb"
+        );
+        assert_eq!(
+            run("1s * 2s\n").unwrap_err(),
+            "Unsupported operation: Duration * Duration"
+        );
+    }
+}
@@ -0,0 +1,174 @@
+//! url モジュール (URL の分解と組み立て)
+//!
+//! `url.parse(s)` は URL を scheme / host / port / path / query / fragment の辞書にする。
+//! `/users?id=1` のようにスキームとホストのない URL も読み、そのときの scheme / host / port は none。
+//! query はキーごとに値を入れた辞書で、同じキーが何度も現れると値のリストになる。
+//! `url.encode` / `url.decode` は URL の一部に入れる文字列のパーセントエンコーディング、
+//! `url.build_query(dict)` はキーの名前順に "a=1&b=x%20y" を作る (リストの値はキーを繰り返す)。
+
+use crate::interpreter::{sorted_entries, Value};
+use ::url::{form_urlencoded, ParseError, Url};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// エンコードしない文字 (RFC 3986 の unreserved: 英数字と - . _ ~)
+const COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// url.parse(s) / url.encode(s) / url.decode(s) / url.build_query(dict)
+pub fn call(name: &str, args: Vec<Value>) -> Result<Value, String> {
+    match (name, args.as_slice()) {
+        ("parse", [Value::Str(s)]) => parse(s),
+        ("parse", _) => Err("url.parse() expects a URL string".to_string()),
+        ("encode", [Value::Str(s)]) => Ok(Value::Str(encode(s).into())),
+        ("encode", _) => Err("url.encode() expects a string".to_string()),
+        ("decode", [Value::Str(s)]) => percent_decode_str(s)
+            .decode_utf8()
+            .map(|decoded| Value::Str(decoded.into()))
+            .map_err(|_| format!("url.decode(): '{}' does not decode to valid UTF-8", s)),
+        ("decode", _) => Err("url.decode() expects a string".to_string()),
+        ("build_query", [Value::Dict(entries)]) => {
+            Ok(Value::Str(build_query(&entries.borrow())?.into()))
+        }
+        ("build_query", _) => Err("url.build_query() expects a dict".to_string()),
+        _ => Err(format!("Module 'url' has no member '{}'", name)),
+    }
+}
+
+/// URL の一部に入れられるようにパーセントエンコードする (空白は %20)
+pub fn encode(s: &str) -> String {
+    utf8_percent_encode(s, COMPONENT).to_string()
+}
+
+/// クエリ文字列を辞書にする (+ は空白、同じキーが2回以上あれば値のリスト)
+pub fn query_dict(query: &str) -> Value {
+    let mut dict: HashMap<String, Value> = HashMap::new();
+    for (key, value) in form_urlencoded::parse(query.as_bytes()).into_owned() {
+        let value = Value::Str(value.into());
+        match dict.get_mut(&key) {
+            Some(Value::List(items)) => items.borrow_mut().push(value),
+            Some(first) => {
+                let items = vec![first.clone(), value];
+                *first = Value::List(Rc::new(RefCell::new(items)));
+            }
+            None => {
+                dict.insert(key, value);
+            }
+        }
+    }
+    Value::Dict(Rc::new(RefCell::new(dict)))
+}
+
+/// 辞書をクエリ文字列にする (none の値は書かない)
+pub fn build_query(entries: &HashMap<String, Value>) -> Result<String, String> {
+    let mut pairs = Vec::new();
+    for (key, value) in sorted_entries(entries) {
+        let values = match value {
+            Value::List(items) => items.borrow().clone(),
+            value => vec![value.clone()],
+        };
+        for value in values {
+            let text = match value {
+                Value::None => continue,
+                Value::Str(s) => s.to_string(),
+                Value::Int(_) | Value::Float(_) | Value::Bool(_) | Value::Decimal(_) => {
+                    value.display()
+                }
+                other => {
+                    return Err(format!(
+                        "url.build_query(): the value of '{}' is a {}, not a string or a number",
+                        key,
+                        other.type_name()
+                    ))
+                }
+            };
+            pairs.push(format!("{}={}", encode(key), encode(&text)));
+        }
+    }
+    Ok(pairs.join("&"))
+}
+
+fn parse(s: &str) -> Result<Value, String> {
+    let invalid = |e: ParseError| format!("Invalid URL '{}': {}", s, e);
+    // スキームのない URL は仮のホストを基準にして読み、scheme / host / port を none にする
+    let (url, absolute) = match Url::parse(s) {
+        Ok(url) => (url, true),
+        Err(ParseError::RelativeUrlWithoutBase) => {
+            let base = Url::parse("http://localhost/").map_err(invalid)?;
+            (base.join(s).map_err(invalid)?, false)
+        }
+        Err(e) => return Err(invalid(e)),
+    };
+    let text = |s: Option<&str>| s.map_or(Value::None, |s| Value::Str(s.into()));
+    let mut dict = HashMap::new();
+    let (scheme, host, port) = if absolute {
+        (
+            Value::Str(url.scheme().into()),
+            text(url.host_str()),
+            url.port_or_known_default()
+                .map_or(Value::None, |port| Value::Int(port as i64)),
+        )
+    } else {
+        (Value::None, Value::None, Value::None)
+    };
+    dict.insert("scheme".to_string(), scheme);
+    dict.insert("host".to_string(), host);
+    dict.insert("port".to_string(), port);
+    dict.insert("path".to_string(), Value::Str(url.path().into()));
+    dict.insert("query".to_string(), query_dict(url.query().unwrap_or("")));
+    dict.insert("fragment".to_string(), text(url.fragment()));
+    Ok(Value::Dict(Rc::new(RefCell::new(dict))))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::run;
+
+    #[test]
+    fn test_parse() {
+        let source = "let u = url.parse(\"https://example.com:8443/a%20b/c?q=x+y&tag=1&tag=2&e=%E3%81%82#top\")
+[u[\"scheme\"], u[\"host\"], u[\"port\"], u[\"path\"], u[\"query\"][\"q\"], u[\"query\"][\"tag\"], u[\"query\"][\"e\"], u[\"fragment\"]]
+";
+        assert_eq!(
+            run(source).unwrap(),
+            "[https, example.com, 8443, /a%20b/c, x y, [1, 2], あ, top]"
+        );
+        let source = "let u = url.parse(\"/users?id=7\")\n[u[\"scheme\"], u[\"host\"], u[\"port\"], u[\"path\"], u[\"query\"], url.parse(\"http://a.test\")[\"port\"]]\n";
+        assert_eq!(
+            run(source).unwrap(),
+            "[none, none, none, /users, {id: 7}, 80]"
+        );
+        assert_eq!(
+            run("url.parse(\"http://exa mple.com\")\n").unwrap_err(),
+            "Invalid URL 'http://exa mple.com': invalid international domain name"
+        );
+    }
+
+    #[test]
+    fn test_encode_and_decode() {
+        let source = "[url.encode(\"a b&c=d/é~\"), url.decode(\"a%20b%26c+%E3%81%82\"), url.decode(url.encode(\"?x=1#\"))]\n";
+        assert_eq!(
+            run(source).unwrap(),
+            "[a%20b%26c%3Dd%2F%C3%A9~, a b&c+あ, ?x=1#]"
+        );
+        assert_eq!(
+            run("url.decode(\"%FF\")\n").unwrap_err(),
+            "url.decode(): '%FF' does not decode to valid UTF-8"
+        );
+    }
+
+    #[test]
+    fn test_build_query() {
+        let source = "url.build_query({\"q\": \"rust lang\", \"page\": 2, \"tag\": [\"a\", \"b&c\"], \"skip\": none})\n";
+        assert_eq!(run(source).unwrap(), "page=2&q=rust%20lang&tag=a&tag=b%26c");
+        assert_eq!(
+            run("url.build_query({\"a\": {}})\n").unwrap_err(),
+            "url.build_query(): the value of 'a' is a Dict, not a string or a number"
+        );
+    }
+}
@@ -0,0 +1,365 @@
+//! フィールドの検証 (`email: Str @format("email")`、`age: Int @min(0)`)
+//!
+//! 構造体とクラスのフィールドの型のあとに書いた検証は、リクエストのボディやフォームを
+//! 構造体に変換するとき (Interpreter::decode_json) と `assert_valid(value)` で確かめる。
+//! 値が none のフィールド (Optional) は検証しない。書ける検証は CHECKS のとおり。
+
+use crate::ast::{FieldCheck, FieldDef, Literal};
+use crate::interpreter::{sorted_entries, values_equal, Interpreter, Value};
+use std::collections::HashSet;
+
+/// 書ける検証と、その説明 (引数の誤りのエラーに使う)
+const CHECKS: &[(&str, &str)] = &[
+    ("min", "a number"),
+    ("max", "a number"),
+    ("min_length", "a non-negative integer"),
+    ("max_length", "a non-negative integer"),
+    ("format", "\"email\", \"url\", \"uuid\" or \"date\""),
+    ("pattern", "a regular expression"),
+    ("one_of", "one or more literals"),
+];
+
+/// パース時に検証の名前と引数を確かめる
+pub fn check_definition(check: &FieldCheck) -> Result<(), String> {
+    let Some((_, expected)) = CHECKS.iter().find(|(name, _)| *name == check.name) else {
+        let names: Vec<&str> = CHECKS.iter().map(|(name, _)| *name).collect();
+        return Err(format!(
+            "Unknown field check '@{}' (expected one of {})",
+            check.name,
+            names.join(", ")
+        ));
+    };
+    let valid = match (check.name.as_str(), check.args.as_slice()) {
+        ("min" | "max", [Literal::Int(_) | Literal::Float(_)]) => true,
+        ("min_length" | "max_length", [Literal::Int(n)]) => *n >= 0,
+        ("format", [Literal::Str(kind)]) => matches!(&**kind, "email" | "url" | "uuid" | "date"),
+        ("pattern", [Literal::Str(pattern)]) => crate::re::compile(pattern).is_ok(),
+        ("one_of", args) => !args.is_empty(),
+        _ => false,
+    };
+    if !valid {
+        return Err(format!("@{} expects {}", check.name, expected));
+    }
+    Ok(())
+}
+
+/// フィールドの値がすべての検証を満たすか (path はエラーに出すフィールドの位置)
+pub fn check_field(field: &FieldDef, value: &Value, path: &str) -> Result<(), String> {
+    if matches!(value, Value::None) {
+        return Ok(());
+    }
+    for check in &field.checks {
+        check_value(check, value, path)?;
+    }
+    Ok(())
+}
+
+fn check_value(check: &FieldCheck, value: &Value, path: &str) -> Result<(), String> {
+    let arg = check.args.first();
+    let (valid, requirement) = match (check.name.as_str(), value) {
+        ("min", Value::Int(_) | Value::Float(_) | Value::Decimal(_)) => (
+            number(value) >= literal_number(arg),
+            format!("at least {}", literal_value(arg).display()),
+        ),
+        ("max", Value::Int(_) | Value::Float(_) | Value::Decimal(_)) => (
+            number(value) <= literal_number(arg),
+            format!("at most {}", literal_value(arg).display()),
+        ),
+        ("min_length" | "max_length", Value::Str(_) | Value::List(_)) => {
+            let (length, unit) = match value {
+                Value::Str(s) => (s.chars().count(), "characters"),
+                Value::List(items) => (items.borrow().len(), "items"),
+                _ => unreachable!(),
+            };
+            let limit = literal_number(arg) as usize;
+            if check.name == "min_length" {
+                (length >= limit, format!("at least {} {} long", limit, unit))
+            } else {
+                (length <= limit, format!("at most {} {} long", limit, unit))
+            }
+        }
+        ("format", Value::Str(s)) => {
+            let kind = literal_value(arg).display();
+            let valid = match kind.as_str() {
+                "email" => is_email(s),
+                "url" => is_url(s),
+                "uuid" => is_uuid(s),
+                _ => is_date(s),
+            };
+            (valid, format!("a valid {}", kind))
+        }
+        ("pattern", Value::Str(s)) => {
+            let pattern = literal_value(arg).display();
+            let regex = crate::re::compile(&format!(r"\A(?:{})\z", pattern))?;
+            (regex.is_match(s), format!("match /{}/", pattern))
+        }
+        ("one_of", value) => {
+            let options: Vec<Value> = check.args.iter().map(|a| literal_value(Some(a))).collect();
+            let shown: Vec<String> = options.iter().map(Value::display).collect();
+            (
+                options.iter().any(|option| values_equal(option, value)),
+                format!("one of {}", shown.join(", ")),
+            )
+        }
+        (name, value) => {
+            return Err(format!(
+                "'{}' has @{} which cannot check a {}",
+                path,
+                name,
+                value.type_name()
+            ))
+        }
+    };
+    if valid {
+        Ok(())
+    } else {
+        let verb = if check.name == "pattern" {
+            "must"
+        } else {
+            "must be"
+        };
+        Err(format!("'{}' {} {}", path, verb, requirement))
+    }
+}
+
+/// assert_valid(value): 構造体とクラスのインスタンスのフィールドを中までたどって検証し、値をそのまま返す
+pub fn assert_valid(interpreter: &Interpreter, args: &[Value]) -> Result<Value, String> {
+    let [value] = args else {
+        return Err("assert_valid() takes exactly 1 argument".to_string());
+    };
+    let path = match value {
+        Value::Struct(name, _) | Value::Class(name, _) => name.clone(),
+        _ => "value".to_string(),
+    };
+    validate(interpreter, value, &path, &mut HashSet::new())?;
+    Ok(value.clone())
+}
+
+/// seen はたどったリストや辞書など (循環していても止まるように)
+fn validate(
+    interpreter: &Interpreter,
+    value: &Value,
+    path: &str,
+    seen: &mut HashSet<usize>,
+) -> Result<(), String> {
+    let children: Vec<(String, Value)> = match value {
+        Value::Struct(name, fields) => {
+            if !seen.insert(fields.as_ptr() as usize) {
+                return Ok(());
+            }
+            if let Some(def) = interpreter.struct_def(name) {
+                for (field, value) in fields.borrow().iter() {
+                    if let Some(def) = def.fields.iter().find(|def| &def.name == field) {
+                        check_field(def, value, &format!("{}.{}", path, field))?;
+                    }
+                }
+            }
+            fields.borrow().clone()
+        }
+        Value::Class(name, fields) => {
+            if !seen.insert(fields.as_ptr() as usize) {
+                return Ok(());
+            }
+            for def in interpreter.class_fields(name) {
+                if let Some(value) = fields.borrow().get(&def.name) {
+                    check_field(&def, value, &format!("{}.{}", path, def.name))?;
+                }
+            }
+            sorted_entries(&fields.borrow())
+                .into_iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect()
+        }
+        Value::List(items) => {
+            if !seen.insert(items.as_ptr() as usize) {
+                return Ok(());
+            }
+            let items = items.borrow();
+            for (i, item) in items.iter().enumerate() {
+                validate(interpreter, item, &format!("{}[{}]", path, i), seen)?;
+            }
+            return Ok(());
+        }
+        Value::Dict(entries) => {
+            if !seen.insert(entries.as_ptr() as usize) {
+                return Ok(());
+            }
+            sorted_entries(&entries.borrow())
+                .into_iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect()
+        }
+        Value::Ok(inner) | Value::Some(inner) => {
+            return validate(interpreter, inner, path, seen);
+        }
+        _ => return Ok(()),
+    };
+    for (key, child) in &children {
+        validate(interpreter, child, &format!("{}.{}", path, key), seen)?;
+    }
+    Ok(())
+}
+
+fn number(value: &Value) -> f64 {
+    match value {
+        Value::Int(n) => *n as f64,
+        Value::Float(f) => *f,
+        Value::Decimal(d) => crate::decimal::to_float(*d),
+        _ => f64::NAN,
+    }
+}
+
+fn literal_value(literal: Option<&Literal>) -> Value {
+    match literal {
+        Some(Literal::Int(n)) => Value::Int(*n),
+        Some(Literal::Float(f)) => Value::Float(*f),
+        Some(Literal::Str(s)) => Value::Str(s.as_str().into()),
+        Some(Literal::Bool(b)) => Value::Bool(*b),
+        _ => Value::None,
+    }
+}
+
+fn literal_number(literal: Option<&Literal>) -> f64 {
+    number(&literal_value(literal))
+}
+
+/// name@example.com の形 (空白なし、ドメインにドットがある)
+fn is_email(s: &str) -> bool {
+    let Some((local, domain)) = s.split_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && !domain.contains('@')
+        && !s.chars().any(char::is_whitespace)
+        && domain.contains('.')
+        && domain.split('.').all(|part| !part.is_empty())
+}
+
+/// http:// か https:// で始まり、ホストがある
+fn is_url(s: &str) -> bool {
+    let rest = s
+        .strip_prefix("https://")
+        .or_else(|| s.strip_prefix("http://"));
+    rest.is_some_and(|rest| {
+        let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+        !host.is_empty() && !s.chars().any(char::is_whitespace)
+    })
+}
+
+/// 8-4-4-4-12 桁の16進数
+fn is_uuid(s: &str) -> bool {
+    let parts: Vec<&str> = s.split('-').collect();
+    parts.len() == 5
+        && parts
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .all(|(part, len)| part.len() == len && part.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// YYYY-MM-DD の実在する日付
+fn is_date(s: &str) -> bool {
+    s.len() == 10
+        && crate::datetime::call(
+            "parse",
+            vec![Value::Str(s.into()), Value::Str("%Y-%m-%d".into())],
+        )
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::testing::run;
+
+    const STRUCTS: &str = "struct Address\n\tzip: Str @pattern(\"\\\\d{3}-\\\\d{4}\")\nstruct User\n\temail: Str @format(\"email\")\n\tage: Int @min(0) @max(150)\n\tname: Str @min_length(1) @max_length(20)\n\trole: Str @one_of(\"admin\", \"member\")\n\tnick: Str? @min_length(2)\n\taddress: Address\n";
+    const VALID_USER: &str = "assert_valid(User { email: \"ann@example.com\", age: 30, name: \"ann\", role: \"admin\", nick: none, address: Address { zip: \"123-4567\" } }).age\n";
+
+    #[test]
+    fn test_formats() {
+        assert!(is_email("ann@example.com"));
+        assert!(!is_email("ann@example") && !is_email("a b@example.com") && !is_email("@x.com"));
+        assert!(is_url("https://example.com/a?b") && !is_url("ftp://example.com"));
+        assert!(!is_url("https://"));
+        assert!(is_uuid("123e4567-e89b-12d3-a456-426614174000"));
+        assert!(!is_uuid("123e4567-e89b-12d3-a456"));
+        assert!(is_date("2024-02-29") && !is_date("2023-02-29") && !is_date("2024-2-1"));
+    }
+
+    #[test]
+    fn test_assert_valid() {
+        let source = format!("{}{}", STRUCTS, VALID_USER);
+        assert_eq!(run(&source).unwrap(), "30");
+    }
+
+    #[test]
+    fn test_assert_valid_errors() {
+        let source = format!("{}{}", STRUCTS, VALID_USER);
+        let cases = [
+            (
+                "\"ann@example.com\"",
+                "\"ann\"",
+                "'User.email' must be a valid email",
+            ),
+            ("age: 30", "age: -1", "'User.age' must be at least 0"),
+            (
+                "\"ann\", role",
+                "\"\", role",
+                "'User.name' must be at least 1 characters long",
+            ),
+            (
+                "role: \"admin\"",
+                "role: \"guest\"",
+                "'User.role' must be one of admin, member",
+            ),
+            (
+                "nick: none",
+                "nick: \"a\"",
+                "'User.nick' must be at least 2 characters long",
+            ),
+            (
+                "\"123-4567\"",
+                "\"1234567\"",
+                "'User.address.zip' must match /\\d{3}-\\d{4}/",
+            ),
+        ];
+        for (from, to, error) in cases {
+            assert_eq!(run(&source.replace(from, to)).unwrap_err(), error, "{}", to);
+        }
+    }
+
+    #[test]
+    fn test_assert_valid_checks_class_fields() {
+        let source = "class Account\n\tbalance: Int @min(0) = 0\nlet a = Account()\na.balance = -5\nassert_valid([a])\n";
+        assert_eq!(
+            run(source).unwrap_err(),
+            "'value[0].balance' must be at least 0"
+        );
+    }
+
+    #[test]
+    fn test_invalid_checks_are_parse_errors() {
+        for (source, message) in [
+            (
+                "struct A\n\tx: Int @between(1, 2)\n",
+                "Unknown field check '@between' (expected one of min, max, min_length, max_length, format, pattern, one_of)",
+            ),
+            ("struct A\n\tx: Int @min(\"1\")\n", "@min expects a number"),
+            (
+                "struct A\n\tx: Str @format(\"phone\")\n",
+                "@format expects \"email\", \"url\", \"uuid\" or \"date\"",
+            ),
+            (
+                "struct A\n\tx: Str @pattern(\"(\")\n",
+                "@pattern expects a regular expression",
+            ),
+            ("struct A\n\tx: Int @min(y)\n", "@min expects literal arguments"),
+        ] {
+            let error = Parser::new(Lexer::new(source).tokenize())
+                .parse()
+                .unwrap_err();
+            assert_eq!(error.to_string(), message, "{}", source);
+        }
+    }
+}
@@ -0,0 +1,643 @@
+//! AST をたどる Visitor / MutVisitor
+//!
+//! 解析や書き換えのパスは、必要なノードの visit_* だけを実装すればよい。
+//! 実装しなかった visit_* は同じ名前の walk_* を呼んで子ノードへ進むので、
+//! 実装した visit_* の中で walk_* を呼ぶと子ノードもたどり、呼ばなければそこで止まる。
+//!
+//! たどる順番は書いた順 (関数は引数の型 → 戻り値の型 → 本体、代入は左辺 → 右辺)。
+//! コメントや空行 (Trivia) の文も visit_statement に渡す。
+
+use crate::ast::*;
+
+/// AST を読むだけのパス (解析・集計など)
+pub trait Visitor {
+    fn visit_program(&mut self, program: &Program) {
+        walk_program(self, program)
+    }
+    fn visit_item(&mut self, item: &Item) {
+        walk_item(self, item)
+    }
+    fn visit_function(&mut self, function: &FunctionDef) {
+        walk_function(self, function)
+    }
+    fn visit_block(&mut self, block: &[Statement]) {
+        walk_block(self, block)
+    }
+    fn visit_statement(&mut self, statement: &Statement) {
+        walk_statement(self, statement)
+    }
+    fn visit_expression(&mut self, expression: &Expression) {
+        walk_expression(self, expression)
+    }
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        walk_pattern(self, pattern)
+    }
+    fn visit_jsx_element(&mut self, element: &JsxElement) {
+        walk_jsx_element(self, element)
+    }
+    fn visit_type(&mut self, ty: &Type) {
+        walk_type(self, ty)
+    }
+}
+
+pub fn walk_program<V: Visitor + ?Sized>(visitor: &mut V, program: &Program) {
+    for item in &program.items {
+        visitor.visit_item(item);
+    }
+}
+
+pub fn walk_item<V: Visitor + ?Sized>(visitor: &mut V, item: &Item) {
+    match item {
+        Item::FunctionDef(function) => visitor.visit_function(function),
+        Item::ClassDef(class) => {
+            for member in &class.body {
+                match member {
+                    ClassBodyItem::Field(field) => walk_field(visitor, field),
+                    ClassBodyItem::Method(method) | ClassBodyItem::StaticMethod(method) => {
+                        visitor.visit_function(method)
+                    }
+                    ClassBodyItem::Const(decl) => {
+                        walk_declaration(visitor, &decl.type_annotation, &decl.value)
+                    }
+                    ClassBodyItem::Trivia(_) => {}
+                }
+            }
+        }
+        Item::StructDef(def) => {
+            for field in &def.fields {
+                walk_field(visitor, field);
+            }
+        }
+        Item::InterfaceDef(def) => {
+            for method in &def.methods {
+                walk_signature(visitor, &method.params, &method.return_type);
+            }
+        }
+        Item::ComponentDef(component) => {
+            for member in &component.body {
+                match member {
+                    ComponentBodyItem::State(state) => visitor.visit_expression(&state.value),
+                    ComponentBodyItem::Method(method) => visitor.visit_function(method),
+                    ComponentBodyItem::Render(render) => visitor.visit_block(&render.body),
+                    ComponentBodyItem::Trivia(_) => {}
+                }
+            }
+        }
+        Item::ServerDef(server) => {
+            for member in &server.body {
+                if let ServerBodyItem::Route(route) = member {
+                    let params = route.body_param.as_slice();
+                    walk_signature(visitor, params, &route.return_type);
+                    visitor.visit_block(&route.body);
+                }
+            }
+        }
+        Item::Import(_) => {}
+        Item::Statement(statement) => visitor.visit_statement(statement),
+    }
+}
+
+pub fn walk_function<V: Visitor + ?Sized>(visitor: &mut V, function: &FunctionDef) {
+    walk_signature(visitor, &function.params, &function.return_type);
+    visitor.visit_block(&function.body);
+}
+
+pub fn walk_block<V: Visitor + ?Sized>(visitor: &mut V, block: &[Statement]) {
+    for statement in block {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_statement<V: Visitor + ?Sized>(visitor: &mut V, statement: &Statement) {
+    match statement {
+        Statement::Let(decl) => walk_declaration(visitor, &decl.type_annotation, &decl.value),
+        Statement::Const(decl) => walk_declaration(visitor, &decl.type_annotation, &decl.value),
+        Statement::State(decl) => visitor.visit_expression(&decl.value),
+        Statement::Return(Some(expression)) | Statement::Expression(expression) => {
+            visitor.visit_expression(expression)
+        }
+        Statement::Assignment(assignment) => {
+            visitor.visit_expression(&assignment.target);
+            visitor.visit_expression(&assignment.value);
+        }
+        Statement::If(stmt) => {
+            visitor.visit_expression(&stmt.condition);
+            visitor.visit_block(&stmt.then_block);
+            if let Some(else_block) = &stmt.else_block {
+                visitor.visit_block(else_block);
+            }
+        }
+        Statement::For(stmt) => {
+            visitor.visit_expression(&stmt.iterator);
+            visitor.visit_block(&stmt.body);
+        }
+        Statement::While(stmt) => {
+            visitor.visit_expression(&stmt.condition);
+            visitor.visit_block(&stmt.body);
+        }
+        Statement::Match(stmt) => {
+            visitor.visit_expression(&stmt.value);
+            for case in &stmt.cases {
+                visitor.visit_pattern(&case.pattern);
+                visitor.visit_block(&case.body);
+            }
+        }
+        Statement::FunctionDef(function) => visitor.visit_function(function),
+        Statement::Render(render) => visitor.visit_block(&render.body),
+        Statement::Return(None)
+        | Statement::Break(_)
+        | Statement::Continue(_)
+        | Statement::Nonlocal(_)
+        | Statement::Copy(_)
+        | Statement::Trivia(_) => {}
+    }
+}
+
+pub fn walk_expression<V: Visitor + ?Sized>(visitor: &mut V, expression: &Expression) {
+    match expression {
+        Expression::Literal(Literal::List(items) | Literal::Set(items)) => {
+            for item in items {
+                visitor.visit_expression(item);
+            }
+        }
+        Expression::Literal(Literal::Dict(entries)) => {
+            for (key, value) in entries {
+                visitor.visit_expression(key);
+                visitor.visit_expression(value);
+            }
+        }
+        Expression::Literal(_) | Expression::Identifier(_) => {}
+        Expression::BinaryOp(binary) => {
+            visitor.visit_expression(&binary.left);
+            visitor.visit_expression(&binary.right);
+        }
+        Expression::UnaryOp(unary) => visitor.visit_expression(&unary.operand),
+        Expression::Call(call) => {
+            visitor.visit_expression(&call.func);
+            for arg in &call.args {
+                visitor.visit_expression(arg);
+            }
+        }
+        Expression::MemberAccess(member) => visitor.visit_expression(&member.object),
+        Expression::Index(index) => {
+            visitor.visit_expression(&index.object);
+            visitor.visit_expression(&index.index);
+        }
+        Expression::Lambda(lambda) => visitor.visit_expression(&lambda.body),
+        Expression::Await(inner) | Expression::Try(inner) => visitor.visit_expression(inner),
+        Expression::JsxElement(element) => visitor.visit_jsx_element(element),
+        Expression::StructLiteral(literal) => {
+            for (_, value) in &literal.fields {
+                visitor.visit_expression(value);
+            }
+        }
+    }
+}
+
+pub fn walk_pattern<V: Visitor + ?Sized>(visitor: &mut V, pattern: &Pattern) {
+    match pattern {
+        Pattern::List(elements) | Pattern::Constructor(_, elements) => {
+            for element in elements {
+                visitor.visit_pattern(element);
+            }
+        }
+        Pattern::Dict(entries) | Pattern::Fields(_, entries) => {
+            for (_, element) in entries {
+                visitor.visit_pattern(element);
+            }
+        }
+        Pattern::Variant(_, inner) => visitor.visit_pattern(inner),
+        Pattern::Literal(_)
+        | Pattern::Identifier(_)
+        | Pattern::Wildcard
+        | Pattern::Range(..)
+        | Pattern::Rest(_) => {}
+    }
+}
+
+pub fn walk_jsx_element<V: Visitor + ?Sized>(visitor: &mut V, element: &JsxElement) {
+    for attribute in &element.attributes {
+        if let Some(value) = &attribute.value {
+            visitor.visit_expression(value);
+        }
+    }
+    for child in &element.children {
+        match child {
+            JsxChild::Element(child) => visitor.visit_jsx_element(child),
+            JsxChild::Expression(expression) => visitor.visit_expression(expression),
+            JsxChild::Text(_) => {}
+        }
+    }
+}
+
+pub fn walk_type<V: Visitor + ?Sized>(visitor: &mut V, ty: &Type) {
+    match ty {
+        Type::List(inner) | Type::Set(inner) | Type::Optional(inner) | Type::Json(inner) => {
+            visitor.visit_type(inner)
+        }
+        Type::Dict(key, value) => {
+            visitor.visit_type(key);
+            visitor.visit_type(value);
+        }
+        Type::Fn(params, ret) => {
+            for param in params {
+                visitor.visit_type(param);
+            }
+            visitor.visit_type(ret);
+        }
+        Type::Union(members) => {
+            for member in members {
+                visitor.visit_type(member);
+            }
+        }
+        Type::Int | Type::Float | Type::Bool | Type::Str | Type::Custom(_) | Type::None => {}
+    }
+}
+
+fn walk_signature<V: Visitor + ?Sized>(visitor: &mut V, params: &[Param], ret: &Option<Type>) {
+    for ty in params.iter().filter_map(|p| p.type_annotation.as_ref()) {
+        visitor.visit_type(ty);
+    }
+    if let Some(ty) = ret {
+        visitor.visit_type(ty);
+    }
+}
+
+fn walk_declaration<V: Visitor + ?Sized>(visitor: &mut V, ty: &Option<Type>, value: &Expression) {
+    if let Some(ty) = ty {
+        visitor.visit_type(ty);
+    }
+    visitor.visit_expression(value);
+}
+
+fn walk_field<V: Visitor + ?Sized>(visitor: &mut V, field: &FieldDef) {
+    visitor.visit_type(&field.type_annotation);
+    if let Some(default) = &field.default {
+        visitor.visit_expression(default);
+    }
+}
+
+/// AST を書き換えるパス (最適化・脱糖など)
+///
+/// visit_block は文のリストそのものを受け取るので、文を足したり消したりもできる。
+pub trait MutVisitor {
+    fn visit_program(&mut self, program: &mut Program) {
+        walk_program_mut(self, program)
+    }
+    fn visit_item(&mut self, item: &mut Item) {
+        walk_item_mut(self, item)
+    }
+    fn visit_function(&mut self, function: &mut FunctionDef) {
+        walk_function_mut(self, function)
+    }
+    fn visit_block(&mut self, block: &mut Vec<Statement>) {
+        walk_block_mut(self, block)
+    }
+    fn visit_statement(&mut self, statement: &mut Statement) {
+        walk_statement_mut(self, statement)
+    }
+    fn visit_expression(&mut self, expression: &mut Expression) {
+        walk_expression_mut(self, expression)
+    }
+    fn visit_pattern(&mut self, pattern: &mut Pattern) {
+        walk_pattern_mut(self, pattern)
+    }
+    fn visit_jsx_element(&mut self, element: &mut JsxElement) {
+        walk_jsx_element_mut(self, element)
+    }
+    fn visit_type(&mut self, ty: &mut Type) {
+        walk_type_mut(self, ty)
+    }
+}
+
+pub fn walk_program_mut<V: MutVisitor + ?Sized>(visitor: &mut V, program: &mut Program) {
+    for item in &mut program.items {
+        visitor.visit_item(item);
+    }
+}
+
+pub fn walk_item_mut<V: MutVisitor + ?Sized>(visitor: &mut V, item: &mut Item) {
+    match item {
+        Item::FunctionDef(function) => visitor.visit_function(function),
+        Item::ClassDef(class) => {
+            for member in &mut class.body {
+                match member {
+                    ClassBodyItem::Field(field) => walk_field_mut(visitor, field),
+                    ClassBodyItem::Method(method) | ClassBodyItem::StaticMethod(method) => {
+                        visitor.visit_function(method)
+                    }
+                    ClassBodyItem::Const(decl) => {
+                        walk_declaration_mut(visitor, &mut decl.type_annotation, &mut decl.value)
+                    }
+                    ClassBodyItem::Trivia(_) => {}
+                }
+            }
+        }
+        Item::StructDef(def) => {
+            for field in &mut def.fields {
+                walk_field_mut(visitor, field);
+            }
+        }
+        Item::InterfaceDef(def) => {
+            for method in &mut def.methods {
+                walk_signature_mut(visitor, &mut method.params, &mut method.return_type);
+            }
+        }
+        Item::ComponentDef(component) => {
+            for member in &mut component.body {
+                match member {
+                    ComponentBodyItem::State(state) => visitor.visit_expression(&mut state.value),
+                    ComponentBodyItem::Method(method) => visitor.visit_function(method),
+                    ComponentBodyItem::Render(render) => visitor.visit_block(&mut render.body),
+                    ComponentBodyItem::Trivia(_) => {}
+                }
+            }
+        }
+        Item::ServerDef(server) => {
+            for member in &mut server.body {
+                if let ServerBodyItem::Route(route) = member {
+                    let params = route.body_param.as_mut_slice();
+                    walk_signature_mut(visitor, params, &mut route.return_type);
+                    visitor.visit_block(&mut route.body);
+                }
+            }
+        }
+        Item::Import(_) => {}
+        Item::Statement(statement) => visitor.visit_statement(statement),
+    }
+}
+
+pub fn walk_function_mut<V: MutVisitor + ?Sized>(visitor: &mut V, function: &mut FunctionDef) {
+    walk_signature_mut(visitor, &mut function.params, &mut function.return_type);
+    visitor.visit_block(&mut function.body);
+}
+
+pub fn walk_block_mut<V: MutVisitor + ?Sized>(visitor: &mut V, block: &mut Vec<Statement>) {
+    for statement in block {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_statement_mut<V: MutVisitor + ?Sized>(visitor: &mut V, statement: &mut Statement) {
+    match statement {
+        Statement::Let(decl) => {
+            walk_declaration_mut(visitor, &mut decl.type_annotation, &mut decl.value)
+        }
+        Statement::Const(decl) => {
+            walk_declaration_mut(visitor, &mut decl.type_annotation, &mut decl.value)
+        }
+        Statement::State(decl) => visitor.visit_expression(&mut decl.value),
+        Statement::Return(Some(expression)) | Statement::Expression(expression) => {
+            visitor.visit_expression(expression)
+        }
+        Statement::Assignment(assignment) => {
+            visitor.visit_expression(&mut assignment.target);
+            visitor.visit_expression(&mut assignment.value);
+        }
+        Statement::If(stmt) => {
+            visitor.visit_expression(&mut stmt.condition);
+            visitor.visit_block(&mut stmt.then_block);
+            if let Some(else_block) = &mut stmt.else_block {
+                visitor.visit_block(else_block);
+            }
+        }
+        Statement::For(stmt) => {
+            visitor.visit_expression(&mut stmt.iterator);
+            visitor.visit_block(&mut stmt.body);
+        }
+        Statement::While(stmt) => {
+            visitor.visit_expression(&mut stmt.condition);
+            visitor.visit_block(&mut stmt.body);
+        }
+        Statement::Match(stmt) => {
+            visitor.visit_expression(&mut stmt.value);
+            for case in &mut stmt.cases {
+                visitor.visit_pattern(&mut case.pattern);
+                visitor.visit_block(&mut case.body);
+            }
+        }
+        Statement::FunctionDef(function) => visitor.visit_function(function),
+        Statement::Render(render) => visitor.visit_block(&mut render.body),
+        Statement::Return(None)
+        | Statement::Break(_)
+        | Statement::Continue(_)
+        | Statement::Nonlocal(_)
+        | Statement::Copy(_)
+        | Statement::Trivia(_) => {}
+    }
+}
+
+pub fn walk_expression_mut<V: MutVisitor + ?Sized>(visitor: &mut V, expression: &mut Expression) {
+    match expression {
+        Expression::Literal(Literal::List(items) | Literal::Set(items)) => {
+            for item in items {
+                visitor.visit_expression(item);
+            }
+        }
+        Expression::Literal(Literal::Dict(entries)) => {
+            for (key, value) in entries {
+                visitor.visit_expression(key);
+                visitor.visit_expression(value);
+            }
+        }
+        Expression::Literal(_) | Expression::Identifier(_) => {}
+        Expression::BinaryOp(binary) => {
+            visitor.visit_expression(&mut binary.left);
+            visitor.visit_expression(&mut binary.right);
+        }
+        Expression::UnaryOp(unary) => visitor.visit_expression(&mut unary.operand),
+        Expression::Call(call) => {
+            visitor.visit_expression(&mut call.func);
+            for arg in &mut call.args {
+                visitor.visit_expression(arg);
+            }
+        }
+        Expression::MemberAccess(member) => visitor.visit_expression(&mut member.object),
+        Expression::Index(index) => {
+            visitor.visit_expression(&mut index.object);
+            visitor.visit_expression(&mut index.index);
+        }
+        Expression::Lambda(lambda) => visitor.visit_expression(&mut lambda.body),
+        Expression::Await(inner) | Expression::Try(inner) => visitor.visit_expression(inner),
+        Expression::JsxElement(element) => visitor.visit_jsx_element(element),
+        Expression::StructLiteral(literal) => {
+            for (_, value) in &mut literal.fields {
+                visitor.visit_expression(value);
+            }
+        }
+    }
+}
+
+pub fn walk_pattern_mut<V: MutVisitor + ?Sized>(visitor: &mut V, pattern: &mut Pattern) {
+    match pattern {
+        Pattern::List(elements) | Pattern::Constructor(_, elements) => {
+            for element in elements {
+                visitor.visit_pattern(element);
+            }
+        }
+        Pattern::Dict(entries) | Pattern::Fields(_, entries) => {
+            for (_, element) in entries {
+                visitor.visit_pattern(element);
+            }
+        }
+        Pattern::Variant(_, inner) => visitor.visit_pattern(inner),
+        Pattern::Literal(_)
+        | Pattern::Identifier(_)
+        | Pattern::Wildcard
+        | Pattern::Range(..)
+        | Pattern::Rest(_) => {}
+    }
+}
+
+pub fn walk_jsx_element_mut<V: MutVisitor + ?Sized>(visitor: &mut V, element: &mut JsxElement) {
+    for attribute in &mut element.attributes {
+        if let Some(value) = &mut attribute.value {
+            visitor.visit_expression(value);
+        }
+    }
+    for child in &mut element.children {
+        match child {
+            JsxChild::Element(child) => visitor.visit_jsx_element(child),
+            JsxChild::Expression(expression) => visitor.visit_expression(expression),
+            JsxChild::Text(_) => {}
+        }
+    }
+}
+
+pub fn walk_type_mut<V: MutVisitor + ?Sized>(visitor: &mut V, ty: &mut Type) {
+    match ty {
+        Type::List(inner) | Type::Set(inner) | Type::Optional(inner) | Type::Json(inner) => {
+            visitor.visit_type(inner)
+        }
+        Type::Dict(key, value) => {
+            visitor.visit_type(key);
+            visitor.visit_type(value);
+        }
+        Type::Fn(params, ret) => {
+            for param in params {
+                visitor.visit_type(param);
+            }
+            visitor.visit_type(ret);
+        }
+        Type::Union(members) => {
+            for member in members {
+                visitor.visit_type(member);
+            }
+        }
+        Type::Int | Type::Float | Type::Bool | Type::Str | Type::Custom(_) | Type::None => {}
+    }
+}
+
+fn walk_signature_mut<V: MutVisitor + ?Sized>(
+    visitor: &mut V,
+    params: &mut [Param],
+    ret: &mut Option<Type>,
+) {
+    for ty in params.iter_mut().filter_map(|p| p.type_annotation.as_mut()) {
+        visitor.visit_type(ty);
+    }
+    if let Some(ty) = ret {
+        visitor.visit_type(ty);
+    }
+}
+
+fn walk_declaration_mut<V: MutVisitor + ?Sized>(
+    visitor: &mut V,
+    ty: &mut Option<Type>,
+    value: &mut Expression,
+) {
+    if let Some(ty) = ty {
+        visitor.visit_type(ty);
+    }
+    visitor.visit_expression(value);
+}
+
+fn walk_field_mut<V: MutVisitor + ?Sized>(visitor: &mut V, field: &mut FieldDef) {
+    visitor.visit_type(&mut field.type_annotation);
+    if let Some(default) = &mut field.default {
+        visitor.visit_expression(default);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn parse(source: &str) -> Program {
+        Parser::new(Lexer::new(source).tokenize()).parse().unwrap()
+    }
+
+    /// 識別子の名前を出てきた順に集める
+    #[derive(Default)]
+    struct Names(Vec<String>);
+
+    impl Visitor for Names {
+        fn visit_expression(&mut self, expression: &Expression) {
+            if let Expression::Identifier(name) = expression {
+                self.0.push(name.clone());
+            }
+            walk_expression(self, expression);
+        }
+    }
+
+    #[test]
+    fn test_visitor_reaches_every_expression() {
+        let source = "class A
+    x: Int = a
+    def m
+        return b
+component C
+    state s = c
+    render
+        <p title={d}>{e}</p>
+server S
+    GET \"/\"
+        return f
+while g
+    match h
+        case [i]
+            j[k] = l.m
+";
+        let mut names = Names::default();
+        names.visit_program(&parse(source));
+        let expected = ["a", "b", "c", "d", "e", "f", "g", "h", "j", "k", "l"];
+        assert_eq!(names.0, expected);
+    }
+
+    /// 値のない return を消し、識別子 x を y にする
+    struct Rewrite;
+
+    impl MutVisitor for Rewrite {
+        fn visit_block(&mut self, block: &mut Vec<Statement>) {
+            block.retain(|s| !matches!(s, Statement::Return(None)));
+            walk_block_mut(self, block);
+        }
+        fn visit_expression(&mut self, expression: &mut Expression) {
+            if let Expression::Identifier(name) = expression {
+                if name == "x" {
+                    *name = "y".to_string();
+                }
+            }
+            walk_expression_mut(self, expression);
+        }
+    }
+
+    #[test]
+    fn test_mut_visitor_rewrites() {
+        let mut program =
+            parse("def f\n    if x\n        return\n    return [x, {\"k\": x + 1}]\n");
+        Rewrite.visit_program(&mut program);
+        let mut names = Names::default();
+        names.visit_program(&program);
+        assert_eq!(names.0, ["y", "y", "y"]);
+        let Item::FunctionDef(f) = &program.items[0] else {
+            panic!("expected function");
+        };
+        let Statement::If(stmt) = &f.body[0] else {
+            panic!("expected if");
+        };
+        assert!(stmt.then_block.is_empty());
+    }
+}
@@ -0,0 +1,92 @@
+//! yaml モジュール (YAML の読み書き)
+//!
+//! json モジュールと同じく、`yaml.parse(text)` はマッピングを辞書に、シーケンスをリストにする。
+//! 文字列でないキー (`1: a`) は str() の表示をキーにし、タグ (`!foo`) は無視して中身を使う。
+//! `yaml.stringify(value)` は json.stringify と同じ値を書き出す (辞書のキーは名前順)。
+
+use crate::interpreter::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// yaml.parse(text) / yaml.stringify(value) (yaml.try_parse は builtins で Result にする)
+pub fn call(name: &str, args: Vec<Value>) -> Result<Value, String> {
+    match (name, args.as_slice()) {
+        ("parse", [Value::Str(text)]) => {
+            let yaml: serde_yaml_ng::Value =
+                serde_yaml_ng::from_str(text).map_err(|e| format!("YAML parse error: {}", e))?;
+            Ok(to_value(yaml))
+        }
+        ("stringify", [value]) => {
+            let json = crate::builtins::value_to_json(value);
+            serde_yaml_ng::to_string(&json)
+                .map(|text| Value::Str(text.into()))
+                .map_err(|e| format!("YAML stringify error: {}", e))
+        }
+        ("parse", _) => Err("yaml.parse() expects a string".to_string()),
+        ("stringify", _) => Err("yaml.stringify() takes exactly 1 argument".to_string()),
+        _ => Err(format!("Module 'yaml' has no member '{}'", name)),
+    }
+}
+
+fn to_value(yaml: serde_yaml_ng::Value) -> Value {
+    match yaml {
+        serde_yaml_ng::Value::Null => Value::None,
+        serde_yaml_ng::Value::Bool(b) => Value::Bool(b),
+        serde_yaml_ng::Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::Int(i),
+            None => Value::Float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_yaml_ng::Value::String(s) => Value::Str(s.into()),
+        serde_yaml_ng::Value::Sequence(items) => Value::List(Rc::new(RefCell::new(
+            items.into_iter().map(to_value).collect(),
+        ))),
+        serde_yaml_ng::Value::Mapping(mapping) => {
+            let mut map = HashMap::new();
+            for (key, value) in mapping {
+                let key = match key {
+                    serde_yaml_ng::Value::String(s) => s,
+                    other => to_value(other).display(),
+                };
+                map.insert(key, to_value(value));
+            }
+            Value::Dict(Rc::new(RefCell::new(map)))
+        }
+        serde_yaml_ng::Value::Tagged(tagged) => to_value(tagged.value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::run;
+
+    #[test]
+    fn test_parse() {
+        let source = "let data = yaml.parse(\"name: app\\nport: 8080\\nratio: 0.5\\ntags:\\n  - web\\n  - api\\n1: one\\ndebug: ~\\n\")\n[data[\"name\"], data[\"port\"] + 1, data[\"ratio\"], data[\"tags\"], data[\"1\"], data[\"debug\"]]\n";
+        assert_eq!(
+            run(source).unwrap(),
+            "[app, 8081, 0.5, [web, api], one, none]"
+        );
+        assert_eq!(run("yaml.parse(\"\")\n").unwrap(), "none");
+    }
+
+    #[test]
+    fn test_stringify() {
+        let source = "yaml.stringify({\"name\": \"app\", \"ports\": [80, 443], \"tls\": none})\n";
+        assert_eq!(
+            run(source).unwrap(),
+            "name: app\nports:\n- 80\n- 443\ntls: null\n"
+        );
+        let source = "let text = yaml.stringify({\"a\": {\"b\": [1, \"x: y\"]}})\nyaml.parse(text)[\"a\"][\"b\"]\n";
+        assert_eq!(run(source).unwrap(), "[1, x: y]");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            run("yaml.parse(\"a: [1\")\n").unwrap_err(),
+            "YAML parse error: did not find expected ',' or ']' at line 2 column 1, while parsing a flow sequence at line 1 column 4"
+        );
+        assert_eq!(run("yaml.try_parse(\"a: [1\").is_err()\n").unwrap(), "true");
+    }
+}
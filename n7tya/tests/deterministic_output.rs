@@ -0,0 +1,38 @@
+//! 同じプログラムの出力が実行ごとに変わらないことを確かめる (スナップショットテストやキャッシュのキーに使うため)
+//!
+//! tests/fixtures/*.n7t を毎回新しいインタプリタで何度か実行し、最後の式の値が
+//! 同じ名前の .out とバイト単位で一致するかを比べる。
+
+use n7tya::interpreter::Interpreter;
+use n7tya::lexer::Lexer;
+use n7tya::parser::Parser;
+
+const FIXTURES: &[(&str, &str, &str)] = &[
+    (
+        "data",
+        include_str!("fixtures/data.n7t"),
+        include_str!("fixtures/data.out"),
+    ),
+    (
+        "html",
+        include_str!("fixtures/html.n7t"),
+        include_str!("fixtures/html.out"),
+    ),
+];
+
+/// HashMap の順番はインタプリタごとに変わるので、何度か作り直して実行する
+const RUNS: usize = 20;
+
+fn run(source: &str) -> String {
+    let program = Parser::new(Lexer::new(source).tokenize()).parse().unwrap();
+    Interpreter::new().run(&program).unwrap().display()
+}
+
+#[test]
+fn test_output_is_byte_identical() {
+    for (name, source, expected) in FIXTURES {
+        for _ in 0..RUNS {
+            assert_eq!(run(source), expected.trim_end_matches('\n'), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! 文法のコーパスを構文解析した AST がスナップショットと一致することを確かめる
+//!
+//! tests/grammar/*.n7t はドキュメントに載せている構文を集めたもの (文法は src/grammar.ebnf)。
+//! 優先順位・JSX・インデントなどのパーサーの変更で受け付ける言語が変わると、
+//! 同じ名前の .ast との差分で失敗する。意図した変更なら
+//! `UPDATE_GRAMMAR_SNAPSHOTS=1 cargo test --test grammar` で .ast を書き直して差分を確認する。
+
+use n7tya::lexer::Lexer;
+use n7tya::parser::Parser;
+use n7tya::resolve::Resolver;
+use std::fs;
+use std::path::PathBuf;
+
+fn corpus_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/grammar")
+}
+
+/// トップレベルの項目ごとに AST を書き出す
+fn snapshot(source: &str) -> Result<String, String> {
+    let program = Parser::new(Lexer::new(source).tokenize())
+        .parse()
+        .map_err(|e| e.to_string())?;
+    let items: Vec<String> = program
+        .items
+        .iter()
+        .map(|item| format!("{:#?}\n", item))
+        .collect();
+    Ok(items.join("\n"))
+}
+
+#[test]
+fn test_corpus_matches_snapshots() {
+    let update = std::env::var_os("UPDATE_GRAMMAR_SNAPSHOTS").is_some();
+    let mut sources: Vec<PathBuf> = fs::read_dir(corpus_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "n7t"))
+        .collect();
+    sources.sort();
+    assert!(!sources.is_empty());
+
+    let mut failures = Vec::new();
+    for path in sources {
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        // Windows のチェックアウトで CRLF になっていても同じ AST になるようにする
+        let source = fs::read_to_string(&path).unwrap().replace("\r\n", "\n");
+        let actual = match snapshot(&source) {
+            Ok(actual) => actual,
+            Err(e) => {
+                failures.push(format!("{}: parse error: {}", name, e));
+                continue;
+            }
+        };
+        let expected_path = path.with_extension("ast");
+        if update {
+            fs::write(&expected_path, &actual).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&expected_path)
+            .unwrap_or_default()
+            .replace("\r\n", "\n");
+        if actual != expected {
+            // 最初に違う行を示す (全体は UPDATE_GRAMMAR_SNAPSHOTS で書き出して git diff で見る)
+            let line = actual
+                .lines()
+                .zip(expected.lines())
+                .position(|(a, e)| a != e)
+                .unwrap_or_else(|| actual.lines().count().min(expected.lines().count()));
+            failures.push(format!(
+                "{}: AST differs from {} at line {}\n  actual:   {}\n  expected: {}",
+                name,
+                expected_path.file_name().unwrap().to_string_lossy(),
+                line + 1,
+                actual.lines().nth(line).unwrap_or("<end>"),
+                expected.lines().nth(line).unwrap_or("<end>")
+            ));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+/// 名前解決が付けた位置が、全ての定義と参照で同じ名前のトークンを指すことを確かめる
+///
+/// 位置はトークン列を書いた順に探して付けるので、AST のたどり方がパーサーとずれると別の名前を指す。
+#[test]
+fn test_corpus_resolves_positions() {
+    let mut sources: Vec<PathBuf> = fs::read_dir(corpus_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "n7t"))
+        .collect();
+    sources.sort();
+
+    let mut failures = Vec::new();
+    for path in sources {
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        let source = fs::read_to_string(&path).unwrap().replace("\r\n", "\n");
+        let tokens = Lexer::new(&source).tokenize();
+        let program = Parser::new(tokens.clone()).parse().unwrap();
+        let table = Resolver::new()
+            .with_tokens(&source, &tokens)
+            .resolve(&program);
+
+        let symbols = table.symbols.iter().map(|s| (&s.name, &s.position));
+        let references = table.references.iter().map(|r| (&r.name, &r.position));
+        for (expected, position) in symbols.chain(references) {
+            let Some(position) = position else {
+                // self や request のような暗黙の名前
+                if !["self", "super", "api", "request"].contains(&expected.as_str()) {
+                    failures.push(format!("{}: no position for '{}'", name, expected));
+                }
+                continue;
+            };
+            let actual = &source[position.span.clone()];
+            if actual != expected {
+                failures.push(format!(
+                    "{}:{}:{}: expected '{}', found '{}'",
+                    name, position.line, position.column, expected, actual
+                ));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}